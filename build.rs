@@ -32,4 +32,8 @@ fn main() {
 
     let output = std::env::var("OUT_DIR").unwrap();
     ::std::fs::write(format!("{}/sha", output), sha.as_bytes()).unwrap();
+
+    // Try builds are downloaded for the target Crater is built for
+    let target = std::env::var("TARGET").unwrap();
+    println!("cargo:rustc-env=CRATER_HOST_TARGET={}", target);
 }
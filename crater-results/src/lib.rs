@@ -0,0 +1,10 @@
+//! Types of the results recorded by crater and of the reports it generates.
+//!
+//! Crater uses these types to store the results of experiments and to serialize `results.json`,
+//! so tools analyzing its output can deserialize the same data without re-implementing them.
+
+#[macro_use]
+mod macros;
+pub mod report;
+pub mod results;
+pub mod version;
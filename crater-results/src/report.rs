@@ -0,0 +1,464 @@
+use crate::results::{
+    Diagnostic, DiagnosticCounts, DiagnosticLevel, DocItem, FailedTarget, TestResult,
+};
+use crate::version::{RustVersion, ToolchainVersion};
+use failure::bail;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Contents of the `results.json` file of a report.
+#[derive(Serialize, Deserialize)]
+pub struct TestResults {
+    pub crates: Vec<CrateResult>,
+    #[serde(default)]
+    pub toolchain_versions: [Option<ToolchainVersion>; 2],
+    /// Versions of the C compiler reported by the builds of each toolchain, if it chose one.
+    #[serde(default)]
+    pub c_compilers: [Vec<String>; 2],
+    /// Version of Crater the report was generated with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generator: Option<String>,
+    /// Number of crates that weren't tested, keyed by the reason they were skipped for.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub skipped_crates: BTreeMap<String, u32>,
+}
+
+impl TestResults {
+    /// Number of crates regressed and fixed for each target, in the order the targets appear in
+    /// the results. Crates known to be broken aren't counted, and crates built for the host are
+    /// left out.
+    pub fn targets_summary(&self) -> Vec<TargetSummary> {
+        let mut summary = Vec::new();
+        TargetSummary::count(&mut summary, &self.crates);
+        summary
+    }
+}
+
+/// Number of crates regressed and fixed when built for one of the targets of an experiment.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TargetSummary {
+    pub target: String,
+    pub regressed: u32,
+    pub fixed: u32,
+}
+
+impl TargetSummary {
+    /// Add the crates regressed and fixed among `crates` to an existing summary, to build the
+    /// summary of results that aren't all loaded at the same time.
+    pub fn count(summary: &mut Vec<TargetSummary>, crates: &[CrateResult]) {
+        for krate in crates {
+            let target = match krate.target {
+                Some(ref target) => target,
+                None => continue,
+            };
+            let idx = match summary.iter().position(|s| &s.target == target) {
+                Some(idx) => idx,
+                None => {
+                    summary.push(TargetSummary {
+                        target: target.clone(),
+                        regressed: 0,
+                        fixed: 0,
+                    });
+                    summary.len() - 1
+                }
+            };
+
+            if krate.known_issue.is_some() {
+                continue;
+            }
+            match krate.res {
+                Comparison::Regressed => summary[idx].regressed += 1,
+                Comparison::Fixed => summary[idx].fixed += 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CrateResult {
+    pub name: String,
+    pub url: String,
+    /// Target triple the crate was built for, if it's not the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Why the crate is known to be broken regardless of the toolchains, if it is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub known_issue: Option<String>,
+    pub res: Comparison,
+    pub runs: [Option<BuildTestResult>; 2],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rust_version: Option<RustVersion>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<MemberComparison>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics_delta: Option<DiagnosticsDelta>,
+    /// Whether the runs failed in the same way, but their logs are different once normalized.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub log_changed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub docs_delta: Option<DocsDelta>,
+    /// First toolchain the crate regressed with, in experiments also running the toolchains
+    /// released between the two compared ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_bad: Option<String>,
+}
+
+/// Change in the number of errors and warnings emitted by a crate between the two toolchains.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiagnosticsDelta {
+    pub errors: i64,
+    pub warnings: i64,
+}
+
+impl DiagnosticsDelta {
+    pub fn new(runs: &[Option<BuildTestResult>; 2]) -> Option<Self> {
+        let counts = |run: &Option<BuildTestResult>| run.as_ref()?.diagnostic_counts;
+        let (before, after) = (counts(&runs[0])?, counts(&runs[1])?);
+        if before == after {
+            return None;
+        }
+        Some(DiagnosticsDelta {
+            errors: i64::from(after.errors) - i64::from(before.errors),
+            warnings: i64::from(after.warnings) - i64::from(before.warnings),
+        })
+    }
+}
+
+/// Paths of the public items of the documentation added, removed or changed between the two
+/// toolchains.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DocsDelta {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl DocsDelta {
+    pub fn new(before: &[DocItem], after: &[DocItem]) -> Option<Self> {
+        // Items can't be compared when either of the toolchains failed to document the crate
+        if before.is_empty() || after.is_empty() {
+            return None;
+        }
+
+        let key = |item: &DocItem| (item.path.clone(), item.kind.clone());
+        let before = before
+            .iter()
+            .map(|item| (key(item), &item.hash))
+            .collect::<BTreeMap<_, _>>();
+        let after = after
+            .iter()
+            .map(|item| (key(item), &item.hash))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut delta = DocsDelta {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+        for (key, hash) in &after {
+            match before.get(key) {
+                None => delta.added.push(key.0.clone()),
+                Some(old) if old != hash => delta.changed.push(key.0.clone()),
+                Some(_) => {}
+            }
+        }
+        for key in before.keys() {
+            if !after.contains_key(key) {
+                delta.removed.push(key.0.clone());
+            }
+        }
+
+        if delta.added.is_empty() && delta.removed.is_empty() && delta.changed.is_empty() {
+            None
+        } else {
+            Some(delta)
+        }
+    }
+}
+
+/// Comparison of a single member of a workspace, nested under the result of the whole crate.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MemberComparison {
+    pub name: String,
+    pub res: Comparison,
+    pub runs: [Option<TestResult>; 2],
+}
+
+string_enum!(pub enum Comparison {
+    Regressed => "regressed",
+    Fixed => "fixed",
+    Skipped => "skipped",
+    Unknown => "unknown",
+    Error => "error",
+    Broken => "broken",
+    SameBuildFail => "build-fail",
+    SameTestFail => "test-fail",
+    SameTestSkipped => "test-skipped",
+    SameTestPass => "test-pass",
+    SpuriousRegressed => "spurious-regressed",
+    SpuriousFixed => "spurious-fixed",
+});
+
+impl Comparison {
+    pub fn show_in_summary(self) -> bool {
+        match self {
+            Comparison::Regressed
+            | Comparison::Fixed
+            | Comparison::Unknown
+            | Comparison::Error
+            | Comparison::SpuriousRegressed
+            | Comparison::SpuriousFixed => true,
+            Comparison::Skipped
+            | Comparison::Broken
+            | Comparison::SameBuildFail
+            | Comparison::SameTestFail
+            | Comparison::SameTestSkipped
+            | Comparison::SameTestPass => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BuildTestResult {
+    pub res: TestResult,
+    pub log: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_targets: Vec<FailedTarget>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_error: Option<PrimaryError>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostic_counts: Option<DiagnosticCounts>,
+    /// Whether a script reproducing the build is available next to the log.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub reproduce: bool,
+    /// Whether a snapshot of the build directory is available next to the log.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub snapshot: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// First error emitted by the compiler in a failed build, to tell the failures apart at a glance.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PrimaryError {
+    pub package: String,
+    pub code: Option<String>,
+}
+
+impl PrimaryError {
+    pub fn find(diagnostics: &[Diagnostic]) -> Option<Self> {
+        diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.level != DiagnosticLevel::Warning)
+            .map(|diagnostic| PrimaryError {
+                package: diagnostic.package.clone(),
+                code: diagnostic.code.clone(),
+            })
+    }
+}
+
+/// Compare the results of a crate with the two toolchains of an experiment.
+pub fn compare(res1: TestResult, res2: TestResult) -> Comparison {
+    use crate::results::TestResult::*;
+
+    match (res1, res2) {
+        (BuildFail(_), BuildFail(_)) => Comparison::SameBuildFail,
+        (TestFail(_), TestFail(_)) => Comparison::SameTestFail,
+        (TestSkipped, TestSkipped) => Comparison::SameTestSkipped,
+        (TestPass, TestPass) => Comparison::SameTestPass,
+
+        (BuildFail(reason1), TestFail(reason2))
+            if reason1.is_spurious() || reason2.is_spurious() =>
+        {
+            Comparison::SpuriousFixed
+        }
+        (BuildFail(reason), TestSkipped)
+        | (BuildFail(reason), TestPass)
+        | (TestFail(reason), TestPass)
+            if reason.is_spurious() =>
+        {
+            Comparison::SpuriousFixed
+        }
+        (BuildFail(_), TestFail(_))
+        | (BuildFail(_), TestSkipped)
+        | (BuildFail(_), TestPass)
+        | (TestFail(_), TestPass) => Comparison::Fixed,
+
+        (TestFail(reason1), BuildFail(reason2))
+            if reason1.is_spurious() || reason2.is_spurious() =>
+        {
+            Comparison::SpuriousRegressed
+        }
+        (TestPass, TestFail(reason))
+        | (TestPass, BuildFail(reason))
+        | (TestSkipped, BuildFail(reason))
+        | (TestFail(_), BuildFail(reason))
+            if reason.is_spurious() =>
+        {
+            Comparison::SpuriousRegressed
+        }
+        (TestPass, TestFail(_))
+        | (TestPass, BuildFail(_))
+        | (TestSkipped, BuildFail(_))
+        | (TestFail(_), BuildFail(_)) => Comparison::Regressed,
+
+        (Skipped(_), _) | (_, Skipped(_)) => Comparison::Skipped,
+        (Error, _) | (_, Error) => Comparison::Error,
+        (BrokenCrate(_), _) | (_, BrokenCrate(_)) => Comparison::Broken,
+        (TestFail(_), TestSkipped)
+        | (TestPass, TestSkipped)
+        | (TestSkipped, TestFail(_))
+        | (TestSkipped, TestPass) => {
+            panic!("can't compare {} and {}", res1, res2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, Comparison, CrateResult, DocsDelta, TargetSummary, TestResults};
+    use crate::results::{DocItem, FailureReason, TestResult};
+
+    #[test]
+    fn test_compare() {
+        macro_rules! test_compare {
+            ($($res1:expr, $res2:expr => $comparison:expr,)*) => {
+                $(assert_eq!(compare($res1, $res2), $comparison);)*
+            };
+        }
+
+        test_compare! {
+            TestResult::TestPass, TestResult::TestPass => Comparison::SameTestPass,
+            TestResult::TestPass, TestResult::BuildFail(FailureReason::CompilerError)
+                => Comparison::Regressed,
+            TestResult::TestPass, TestResult::TestFail(FailureReason::OOM)
+                => Comparison::SpuriousRegressed,
+            TestResult::BuildFail(FailureReason::Unknown), TestResult::TestPass
+                => Comparison::Fixed,
+            TestResult::Error, TestResult::TestPass => Comparison::Error,
+        }
+    }
+
+    #[test]
+    fn test_docs_delta() {
+        let item = |path: &str, hash: &str| DocItem {
+            path: path.into(),
+            kind: "function".into(),
+            hash: hash.into(),
+        };
+        let before = vec![
+            item("foo::a", "1"),
+            item("foo::b", "2"),
+            item("foo::c", "3"),
+        ];
+        let after = vec![
+            item("foo::a", "1"),
+            item("foo::b", "4"),
+            item("foo::d", "5"),
+        ];
+
+        assert_eq!(
+            DocsDelta::new(&before, &after),
+            Some(DocsDelta {
+                added: vec!["foo::d".into()],
+                removed: vec!["foo::c".into()],
+                changed: vec!["foo::b".into()],
+            })
+        );
+        assert_eq!(DocsDelta::new(&before, &before), None);
+        assert_eq!(DocsDelta::new(&before, &[]), None);
+    }
+
+    #[test]
+    fn test_parse_results() {
+        let results: TestResults = serde_json::from_str(
+            r#"{
+                "crates": [{
+                    "name": "lazy_static-0.2.11",
+                    "url": "https://crates.io/crates/lazy_static/0.2.11",
+                    "res": "regressed",
+                    "runs": [
+                        {"res": "test-pass", "log": "stable/reg/lazy_static-0.2.11"},
+                        {
+                            "res": "build-fail:compiler-error",
+                            "log": "beta/reg/lazy_static-0.2.11",
+                            "primary_error": {"package": "lazy_static", "code": "E0308"}
+                        }
+                    ]
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(results.crates.len(), 1);
+        let krate = &results.crates[0];
+        assert_eq!(krate.res, Comparison::Regressed);
+        let run = krate.runs[1].as_ref().unwrap();
+        assert_eq!(run.res, TestResult::BuildFail(FailureReason::CompilerError));
+        assert_eq!(
+            run.primary_error.as_ref().unwrap().code,
+            Some("E0308".to_string())
+        );
+        assert!(results.toolchain_versions[0].is_none());
+    }
+
+    #[test]
+    fn test_targets_summary() {
+        let krate =
+            |target: Option<&str>, res: Comparison, known_issue: Option<&str>| CrateResult {
+                name: "foo".into(),
+                url: "https://crates.io/crates/foo".into(),
+                target: target.map(|t| t.to_string()),
+                known_issue: known_issue.map(|k| k.to_string()),
+                res,
+                runs: [None, None],
+                rust_version: None,
+                members: Vec::new(),
+                diagnostics_delta: None,
+                log_changed: false,
+                docs_delta: None,
+                first_bad: None,
+            };
+        let wasm = Some("wasm32-unknown-unknown");
+        let aarch64 = Some("aarch64-unknown-linux-gnu");
+        let results = TestResults {
+            crates: vec![
+                krate(wasm, Comparison::Regressed, None),
+                krate(aarch64, Comparison::Fixed, None),
+                krate(wasm, Comparison::Regressed, None),
+                krate(wasm, Comparison::Regressed, Some("broken")),
+                krate(aarch64, Comparison::SameTestPass, None),
+                krate(None, Comparison::Regressed, None),
+            ],
+            toolchain_versions: [None, None],
+            c_compilers: [Vec::new(), Vec::new()],
+            generator: None,
+            skipped_crates: Default::default(),
+        };
+
+        assert_eq!(
+            results.targets_summary(),
+            vec![
+                TargetSummary {
+                    target: "wasm32-unknown-unknown".into(),
+                    regressed: 2,
+                    fixed: 0,
+                },
+                TargetSummary {
+                    target: "aarch64-unknown-linux-gnu".into(),
+                    regressed: 0,
+                    fixed: 1,
+                },
+            ]
+        );
+
+        // Counting the crates in multiple steps gives the same summary
+        let mut summary = Vec::new();
+        TargetSummary::count(&mut summary, &results.crates[..3]);
+        TargetSummary::count(&mut summary, &results.crates[3..]);
+        assert_eq!(summary, results.targets_summary());
+    }
+}
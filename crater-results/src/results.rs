@@ -0,0 +1,292 @@
+use failure::{bail, Fail, Fallible};
+use serde_derive::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+macro_rules! test_result_enum {
+    (pub enum $name:ident {
+        with_reason { $($with_reason_name:ident($reason:ident) => $with_reason_repr:expr,)* }
+        without_reason { $($reasonless_name:ident => $reasonless_repr:expr,)* }
+    }) => {
+        #[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+        pub enum $name {
+            $($with_reason_name($reason),)*
+            $($reasonless_name,)*
+        }
+
+        impl FromStr for $name {
+            type Err = ::failure::Error;
+
+            fn from_str(input: &str) -> Fallible<Self> {
+                let parts: Vec<&str> = input.split(':').collect();
+
+                if parts.len() == 1 {
+                    match parts[0] {
+                        $($with_reason_repr => Ok($name::$with_reason_name($reason::Unknown)),)*
+                        $($reasonless_repr => Ok($name::$reasonless_name),)*
+                        other => Err(TestResultParseError::UnknownResult(other.into()).into()),
+                    }
+                } else if parts.len() == 2 {
+                    match parts[0] {
+                        $($reasonless_repr => Err(TestResultParseError::UnexpectedFailureReason.into()),)*
+                        $($with_reason_repr => Ok($name::$with_reason_name(parts[1].parse()?)),)*
+                        other => Err(TestResultParseError::UnknownResult(other.into()).into()),
+                    }
+                } else {
+                    Err(TestResultParseError::TooManySegments.into())
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self {
+                    $($name::$with_reason_name(reason) => write!(f, "{}:{}", $with_reason_repr, reason),)*
+                    $($name::$reasonless_name => write!(f, "{}", $reasonless_repr),)*
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum TestResultParseError {
+    #[fail(display = "unknown test result: {}", _0)]
+    UnknownResult(String),
+    #[fail(display = "unexpected failure reason")]
+    UnexpectedFailureReason,
+    #[fail(display = "too many segments")]
+    TooManySegments,
+}
+
+string_enum!(pub enum FailureReason {
+    Unknown => "unknown",
+    OOM => "oom",
+    Timeout => "timeout",
+    ICE => "ice",
+    CompilerError => "compiler-error",
+    DependsOn => "depends-on",
+    BuildScript => "build-script",
+    DocTest => "doctest",
+    UndefinedBehavior => "undefined-behavior",
+    FixFailed => "fix-failed",
+});
+
+impl FailureReason {
+    /// Whether the failure was caused by the environment the crate was built in rather than by
+    /// the crate or the compiler, making it likely to go away when the crate is built again.
+    pub fn is_spurious(self) -> bool {
+        match self {
+            FailureReason::Unknown
+            | FailureReason::ICE
+            | FailureReason::CompilerError
+            | FailureReason::DependsOn
+            | FailureReason::BuildScript
+            | FailureReason::DocTest
+            | FailureReason::UndefinedBehavior
+            | FailureReason::FixFailed => false,
+            FailureReason::OOM | FailureReason::Timeout => true,
+        }
+    }
+}
+
+string_enum!(pub enum BrokenReason {
+    Unknown => "unknown",
+    CargoToml => "cargo-toml",
+    Yanked => "yanked",
+    MissingGitRepository => "missing-git-repository",
+});
+
+string_enum!(pub enum SkipReason {
+    Unknown => "unknown",
+    IncompatibleTarget => "incompatible-target",
+    WorkspaceBuildFailed => "workspace-build-failed",
+});
+
+test_result_enum!(pub enum TestResult {
+    with_reason {
+        BrokenCrate(BrokenReason) => "broken",
+        BuildFail(FailureReason) => "build-fail",
+        TestFail(FailureReason) => "test-fail",
+        Skipped(SkipReason) => "skipped",
+    }
+    without_reason {
+        TestSkipped => "test-skipped",
+        TestPass => "test-pass",
+        Error => "error",
+    }
+});
+
+impl_serde_from_parse!(TestResult, expecting = "a test result");
+
+/// Result of a single package of a workspace, recorded alongside the result of the whole crate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemberResult {
+    pub name: String,
+    pub result: TestResult,
+}
+
+string_enum!(pub enum TargetKind {
+    Lib => "lib",
+    Bin => "bin",
+    Test => "test",
+    Example => "example",
+    Bench => "bench",
+    BuildScript => "custom-build",
+});
+
+/// Cargo target that failed to build, like the library or a specific example of a package.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedTarget {
+    pub package: String,
+    pub kind: TargetKind,
+    pub name: String,
+}
+
+string_enum!(pub enum DiagnosticLevel {
+    Error => "error",
+    Warning => "warning",
+    InternalCompilerError => "ice",
+});
+
+/// Diagnostic emitted by the compiler while building a package, as reported in Cargo's JSON
+/// messages. Only the location of the primary span is kept.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Diagnostic {
+    /// Name of the package being built, which is not necessarily part of the crate being tested.
+    pub package: String,
+    pub target_kind: Option<TargetKind>,
+    pub target_name: Option<String>,
+    pub level: DiagnosticLevel,
+    /// Error code (like `E0308`) or lint name (like `unused_imports`) of the diagnostic.
+    pub code: Option<String>,
+    pub message: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+/// Number of diagnostics emitted by the compiler while building the packages of a crate,
+/// ignoring the ones of its dependencies. Unlike the recorded diagnostics these are not capped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagnosticCounts {
+    /// Errors, including internal compiler errors.
+    pub errors: u32,
+    pub warnings: u32,
+}
+
+/// Time spent by Cargo compiling a single unit (a target of a package in a compile mode), as
+/// reported by `--timings=json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UnitTiming {
+    /// Name of the package, which is not necessarily part of the crate being tested.
+    pub package: String,
+    pub version: String,
+    pub target_kind: Option<TargetKind>,
+    pub target_name: String,
+    /// Compile mode of the unit, like `build`, `check`, `test` or `run-custom-build`.
+    pub mode: String,
+    /// Seconds spent compiling the unit.
+    pub duration: f64,
+}
+
+/// Public item of the documentation of a crate, as generated by rustdoc's JSON output.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DocItem {
+    /// Path of the item, like `foo::bar::Baz`.
+    pub path: String,
+    /// Kind of the item, like `struct` or `function`.
+    pub kind: String,
+    /// Hash of the JSON of the item, including its fields, variants, methods and trait
+    /// implementations, with the parts changing between runs removed.
+    pub hash: String,
+}
+
+/// Cargo command executed in the build sandbox while testing a crate, recorded to allow
+/// reproducing the build outside of Crater.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Invocation {
+    /// Name of the toolchain in rustup, like `nightly-2020-01-01` or the hash of a CI build.
+    pub toolchain: String,
+    /// Name of the toolchain providing Cargo, if it's not the same one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cargo_toolchain: Option<String>,
+    /// Arguments passed to Cargo.
+    pub args: Vec<String>,
+    /// Environment variables set by Crater, not including the ones pointing inside the sandbox.
+    pub env: Vec<(String, String)>,
+    /// Memory available to the sandbox, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_limit: Option<u64>,
+    /// Number of CPUs the sandbox was allowed to use.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_limit: Option<f32>,
+    /// CPUs the sandbox was pinned to, like `0-3`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpuset: Option<String>,
+}
+
+/// Resources used to test a crate on a toolchain, to estimate the cost of experiments.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResourceUsage {
+    /// Seconds spent building and testing the crate.
+    pub duration: f64,
+    /// Number of CPUs the builds were allowed to use.
+    pub cpus: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    #[test]
+    fn test_test_result_parsing() {
+        use super::{
+            FailureReason::*,
+            SkipReason,
+            TestResult::{self, *},
+        };
+
+        macro_rules! test_from_str {
+            ($($str:expr => $rust:expr,)*) => {
+                $(
+                    // Test parsing from string to rust
+                    assert_eq!(TestResult::from_str($str).unwrap(), $rust);
+
+                    // Test dumping from rust to string
+                    assert_eq!(&$rust.to_string(), $str);
+
+                    // Test dumping from rust to string to rust
+                    assert_eq!(TestResult::from_str($rust.to_string().as_ref()).unwrap(), $rust);
+                )*
+            };
+        }
+
+        test_from_str! {
+            "build-fail:unknown" => BuildFail(Unknown),
+            "build-fail:oom" => BuildFail(OOM),
+            "build-fail:depends-on" => BuildFail(DependsOn),
+            "test-fail:doctest" => TestFail(DocTest),
+            "test-fail:undefined-behavior" => TestFail(UndefinedBehavior),
+            "test-fail:fix-failed" => TestFail(FixFailed),
+            "test-fail:timeout" => TestFail(Timeout),
+            "test-pass" => TestPass,
+            "error" => Error,
+            "skipped:incompatible-target" => Skipped(SkipReason::IncompatibleTarget),
+            "skipped:workspace-build-failed" => Skipped(SkipReason::WorkspaceBuildFailed),
+        }
+
+        // Backward compatibility
+        assert_eq!(
+            TestResult::from_str("build-fail").unwrap(),
+            BuildFail(Unknown)
+        );
+
+        assert!(TestResult::from_str("error:oom").is_err());
+        assert!(TestResult::from_str("build-fail:pleasedonotaddthis").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use failure::{bail, err_msg, Fallible};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Minimum Rust version supported by a crate, as declared in the `rust-version` manifest key.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RustVersion {
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl FromStr for RustVersion {
+    type Err = ::failure::Error;
+
+    fn from_str(input: &str) -> Fallible<Self> {
+        let mut parts = input.split('.').map(|part| part.parse::<u64>());
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor)), patch, None) => Ok(RustVersion {
+                major,
+                minor,
+                patch: match patch {
+                    Some(patch) => patch?,
+                    None => 0,
+                },
+            }),
+            _ => bail!("invalid Rust version: {}", input),
+        }
+    }
+}
+
+impl fmt::Display for RustVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl_serde_from_parse!(RustVersion, expecting = "a Rust version");
+
+/// Exact version of an installed toolchain, as reported by `rustc -vV`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ToolchainVersion {
+    pub release: String,
+    pub commit_hash: Option<String>,
+    pub commit_date: Option<String>,
+    pub host: Option<String>,
+    pub llvm_version: Option<String>,
+}
+
+impl ToolchainVersion {
+    /// Parse the output of `rustc -vV`.
+    pub fn parse(output: &str) -> Fallible<Self> {
+        let mut fields = HashMap::new();
+        for line in output.lines() {
+            if let Some(idx) = line.find(": ") {
+                let value = line[idx + 2..].trim();
+                // Toolchains built without git information report "unknown" commits
+                if value != "unknown" {
+                    fields.insert(&line[..idx], value.to_string());
+                }
+            }
+        }
+
+        Ok(ToolchainVersion {
+            release: fields
+                .remove("release")
+                .ok_or_else(|| err_msg("missing release in the rustc version"))?,
+            commit_hash: fields.remove("commit-hash"),
+            commit_date: fields.remove("commit-date"),
+            host: fields.remove("host"),
+            llvm_version: fields.remove("LLVM version"),
+        })
+    }
+}
@@ -0,0 +1,86 @@
+use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::audit::{self, AuditAction};
+use crate::db::QueryUtils;
+use crate::experiments::{Experiment, Status};
+use crate::prelude::*;
+use serde_json::json;
+
+pub struct ArchiveExperiment {
+    pub name: String,
+}
+
+impl Action for ArchiveExperiment {
+    fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
+        let ex = match Experiment::get(&ctx.db, &self.name)? {
+            Some(ex) => ex,
+            None => return Err(ExperimentError::NotFound(self.name).into()),
+        };
+
+        match ex.status {
+            Status::Completed
+            | Status::Cancelled
+            | Status::Failed
+            | Status::ToolchainInstallFailed => {}
+            _ => return Err(ExperimentError::CanOnlyArchiveFinishedExperiments.into()),
+        }
+
+        ctx.db.transaction(|t| {
+            t.execute(
+                "UPDATE experiments SET status = ?1 WHERE name = ?2;",
+                &[&Status::Archived.to_str(), &self.name.as_str()],
+            )?;
+
+            audit::record(
+                t,
+                &ctx.actor,
+                AuditAction::ArchiveExperiment,
+                Some(&self.name),
+                &json!({ "previous-status": ex.status }),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArchiveExperiment;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment, ExperimentError};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::{Experiment, Status};
+
+    #[test]
+    fn test_archive_experiment() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+
+        // Unfinished experiments can't be archived
+        let err = ArchiveExperiment {
+            name: "dummy".to_string(),
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::CanOnlyArchiveFinishedExperiments)
+        );
+
+        let mut ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        ex.set_status(&db, Status::Completed).unwrap();
+
+        ArchiveExperiment {
+            name: "dummy".to_string(),
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        // Archived experiments are kept, but hidden from the queue
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.status, Status::Archived);
+        assert!(Experiment::unfinished(&db).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,434 @@
+use crate::actions::{experiments::ExperimentError, Action, ActionsCtx, CreateExperiment};
+use crate::crates::{lists, Crate};
+use crate::db::QueryUtils;
+use crate::experiments::{
+    qualified_name, BuildTargets, CapLints, CrateSelect, Experiment, GitHubIssue, Mode, Tags,
+    Targets, TestFlags,
+};
+use crate::prelude::*;
+use crate::report::{Comparison, TestResults};
+use crate::results::{DatabaseDB, ReadResults};
+use crate::toolchain::{self, Toolchain};
+use chrono::{Duration, NaiveDate};
+
+/// Maximum number of nightlies run between the bounds by each step of the bisection.
+const MAX_INTERMEDIATE_NIGHTLIES: i64 = 6;
+
+/// Find the first nightly a crate regressed with, by queueing experiments running the crate with
+/// a sample of the nightlies in the range. Once the report of a step is generated, the next step
+/// narrows the range down to the two nightlies surrounding the regression, until they're
+/// consecutive.
+///
+/// The crate is stored in the `bisections` table along with each step, so the experiments
+/// cloned from a step aren't bisections themselves.
+pub struct BisectRegression {
+    pub name: String,
+    pub namespace: String,
+    /// Crate that regressed, the only one tested by the steps of the bisection.
+    pub krate: Crate,
+    /// Date of the last nightly known to work.
+    pub start: NaiveDate,
+    /// Date of the first nightly known to be broken.
+    pub end: NaiveDate,
+    pub mode: Mode,
+    pub test_flags: TestFlags,
+    pub priority: i32,
+    pub github_issue: Option<GitHubIssue>,
+}
+
+impl Action for BisectRegression {
+    fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
+        let server = toolchain::dist_server(&ctx.config);
+        self.create(ctx, |date| toolchain::nightly_published(server, date))
+    }
+}
+
+impl BisectRegression {
+    fn create<F: FnMut(NaiveDate) -> Fallible<bool>>(
+        self,
+        ctx: &ActionsCtx,
+        mut published: F,
+    ) -> Fallible<()> {
+        if self.end <= self.start {
+            return Err(ExperimentError::InvalidBisectionRange.into());
+        }
+        for &date in &[self.start, self.end] {
+            if !published(date)? {
+                return Err(ExperimentError::UnpublishedNightly(date).into());
+            }
+        }
+
+        let mut toolchains = vec![nightly(self.start)?];
+        for date in sample_nightlies(self.start, self.end, &mut published)? {
+            toolchains.push(nightly(date)?);
+        }
+        toolchains.push(nightly(self.end)?);
+
+        // The crate is selected through a list named after the step
+        let name = qualified_name(&self.namespace, &self.name);
+        lists::store_crate_list(&ctx.db, &self.namespace, &self.name, &[self.krate.clone()])?;
+
+        CreateExperiment {
+            name: self.name,
+            namespace: self.namespace,
+            toolchains,
+            mode: self.mode,
+            crates: CrateSelect::List(name.clone()),
+            cap_lints: CapLints::Forbid,
+            priority: self.priority,
+            github_issue: self.github_issue,
+            // The crates were explicitly chosen, so they're tested even if they're blacklisted
+            ignore_blacklist: true,
+            assign: None,
+            requirement: None,
+            edition: None,
+            max_rust_version: None,
+            build_targets: BuildTargets::Default,
+            test_flags: self.test_flags,
+            dist_server: None,
+            rustc_wrapper: None,
+            timings: false,
+            targets: Targets::default(),
+            tags: Tags::default(),
+        }
+        .apply(ctx)?;
+
+        ctx.db.execute(
+            "INSERT INTO bisections (experiment, crate) VALUES (?1, ?2);",
+            &[&name, &::serde_json::to_string(&self.krate)?],
+        )?;
+        Ok(())
+    }
+}
+
+/// Outcome of a step of a bisection.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BisectionStep {
+    /// None of the crates regressed between the bounds of the step.
+    NotReproduced,
+    /// The regression appeared in the `bad` nightly, released the day after the `good` one.
+    Found {
+        good: Toolchain,
+        bad: Toolchain,
+        /// URL of the rustc commits merged between the two nightlies, if their commits are known.
+        commits: Option<String>,
+    },
+    /// The range was narrowed, and the experiment with this name was queued to bisect it.
+    Continued(String),
+}
+
+/// Queue the next step of a bisection once the report of the previous one is generated,
+/// returning `None` for experiments that aren't part of a bisection.
+pub fn advance_bisection(
+    ctx: &ActionsCtx,
+    ex: &Experiment,
+    results: &TestResults,
+) -> Fallible<Option<BisectionStep>> {
+    let server = toolchain::dist_server(&ctx.config);
+    advance(ctx, ex, results, |date| {
+        toolchain::nightly_published(server, date)
+    })
+}
+
+fn advance<F: FnMut(NaiveDate) -> Fallible<bool>>(
+    ctx: &ActionsCtx,
+    ex: &Experiment,
+    results: &TestResults,
+    published: F,
+) -> Fallible<Option<BisectionStep>> {
+    let krate = ctx.db.get_row(
+        "SELECT crate FROM bisections WHERE experiment = ?1;",
+        &[&ex.name],
+        |row| row.get::<_, String>("crate"),
+    )?;
+    let krate: Crate = match krate {
+        Some(krate) => ::serde_json::from_str(&krate)?,
+        None => return Ok(None),
+    };
+
+    let regressed = match results
+        .crates
+        .iter()
+        .find(|krate| krate.res == Comparison::Regressed)
+    {
+        Some(krate) => krate,
+        None => return Ok(Some(BisectionStep::NotReproduced)),
+    };
+
+    // Without intermediate toolchains the first bad nightly is the end of the range
+    let ordered = ex.ordered_toolchains().remove(0).1;
+    let bad_name = regressed
+        .first_bad
+        .clone()
+        .unwrap_or_else(|| ex.end_toolchain().to_string());
+    let bad_idx = ordered
+        .iter()
+        .position(|tc| tc.to_string() == bad_name)
+        .filter(|&idx| idx > 0)
+        .ok_or_else(|| err_msg(format!("unknown first bad toolchain: {}", bad_name)))?;
+    let (good, bad) = (&ordered[bad_idx - 1], &ordered[bad_idx]);
+    let (good_date, bad_date) = match (nightly_date(good), nightly_date(bad)) {
+        (Some(good_date), Some(bad_date)) => (good_date, bad_date),
+        _ => bail!(
+            "the bisection experiment {} isn't run on nightlies",
+            ex.name
+        ),
+    };
+
+    if bad_date - good_date <= Duration::days(1) {
+        let db = DatabaseDB::new(&ctx.db);
+        let commit = |tc: &Toolchain| -> Fallible<Option<String>> {
+            Ok(db
+                .load_toolchain_version(ex, tc)?
+                .and_then(|version| version.commit_hash))
+        };
+        let commits = match (commit(good)?, commit(bad)?) {
+            (Some(good), Some(bad)) => Some(format!(
+                "https://github.com/rust-lang/rust/compare/{}...{}",
+                good, bad
+            )),
+            _ => None,
+        };
+        return Ok(Some(BisectionStep::Found {
+            good: good.clone(),
+            bad: bad.clone(),
+            commits,
+        }));
+    }
+
+    let (namespace, name) = match ex.name.find(':') {
+        Some(idx) => (&ex.name[..idx], &ex.name[idx + 1..]),
+        None => (crate::experiments::DEFAULT_NAMESPACE, ex.name.as_str()),
+    };
+    let next_name = next_step_name(name);
+    BisectRegression {
+        name: next_name.clone(),
+        namespace: namespace.to_string(),
+        krate,
+        start: good_date,
+        end: bad_date,
+        mode: ex.mode,
+        test_flags: ex.test_flags.clone(),
+        priority: ex.priority,
+        github_issue: ex.github_issue.clone(),
+    }
+    .create(ctx, published)?;
+
+    Ok(Some(BisectionStep::Continued(qualified_name(
+        namespace, &next_name,
+    ))))
+}
+
+fn nightly(date: NaiveDate) -> Fallible<Toolchain> {
+    Ok(format!("nightly-{}", date.format("%Y-%m-%d")).parse()?)
+}
+
+fn nightly_date(toolchain: &Toolchain) -> Option<NaiveDate> {
+    let name = toolchain.to_string();
+    if !name.starts_with("nightly-") {
+        return None;
+    }
+    NaiveDate::parse_from_str(&name["nightly-".len()..], "%Y-%m-%d").ok()
+}
+
+/// Dates of the nightlies run between the two bounds, evenly spread across the range. The days
+/// without a nightly published on the dist server are replaced by the next published one.
+fn sample_nightlies<F: FnMut(NaiveDate) -> Fallible<bool>>(
+    start: NaiveDate,
+    end: NaiveDate,
+    mut published: F,
+) -> Fallible<Vec<NaiveDate>> {
+    let days = (end - start).num_days();
+    let targets: Vec<i64> = if days - 1 <= MAX_INTERMEDIATE_NIGHTLIES {
+        (1..days).collect()
+    } else {
+        (1..=MAX_INTERMEDIATE_NIGHTLIES)
+            .map(|i| days * i / (MAX_INTERMEDIATE_NIGHTLIES + 1))
+            .collect()
+    };
+
+    let mut sampled = Vec::new();
+    // First day not checked yet, to avoid sampling the same nightly twice
+    let mut next = 1;
+    for target in targets {
+        for day in target.max(next)..days {
+            next = day + 1;
+            let date = start + Duration::days(day);
+            if published(date)? {
+                sampled.push(date);
+                break;
+            }
+        }
+    }
+    Ok(sampled)
+}
+
+/// Name of the experiment of the next step, numbering the steps after the first one.
+fn next_step_name(name: &str) -> String {
+    if let Some(idx) = name.rfind("-step") {
+        if let Ok(step) = name[idx + "-step".len()..].parse::<u32>() {
+            return format!("{}-step{}", &name[..idx], step + 1);
+        }
+    }
+    format!("{}-step2", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{advance, next_step_name, sample_nightlies, BisectRegression, BisectionStep};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment, ExperimentError};
+    use crate::config::Config;
+    use crate::crates::Crate;
+    use crate::db::Database;
+    use crate::experiments::{CrateSelect, Experiment, Mode, Status, TestFlags, DEFAULT_NAMESPACE};
+    use crate::report::{Comparison, CrateResult, TestResults};
+    use crate::results::{DatabaseDB, EncodingType, FailureReason, TestResult};
+    use chrono::NaiveDate;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2024, 1, day)
+    }
+
+    fn regressed(first_bad: Option<&str>) -> TestResults {
+        TestResults {
+            crates: vec![CrateResult {
+                name: "foo".into(),
+                url: "https://crates.io/crates/foo".into(),
+                target: None,
+                known_issue: None,
+                res: Comparison::Regressed,
+                runs: [None, None],
+                rust_version: None,
+                members: Vec::new(),
+                diagnostics_delta: None,
+                log_changed: false,
+                docs_delta: None,
+                first_bad: first_bad.map(|tc| tc.to_string()),
+            }],
+            toolchain_versions: [None, None],
+            c_compilers: [Vec::new(), Vec::new()],
+            generator: None,
+            skipped_crates: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_sample_nightlies() {
+        let all = |_| Ok(true);
+        assert!(sample_nightlies(date(1), date(2), all).unwrap().is_empty());
+        assert_eq!(
+            sample_nightlies(date(1), date(4), all).unwrap(),
+            vec![date(2), date(3)]
+        );
+        assert_eq!(
+            sample_nightlies(date(1), date(30), all).unwrap(),
+            vec![date(5), date(9), date(13), date(17), date(21), date(25)]
+        );
+
+        // The missing nightlies are replaced by the next published ones, without duplicates
+        let missing = |day: NaiveDate| Ok(day < date(5) || day > date(10));
+        assert_eq!(
+            sample_nightlies(date(1), date(30), missing).unwrap(),
+            vec![date(11), date(12), date(13), date(17), date(21), date(25)]
+        );
+        let none = |day: NaiveDate| Ok(day == date(1) || day == date(4));
+        assert!(sample_nightlies(date(1), date(4), none).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_next_step_name() {
+        assert_eq!(next_step_name("bisect-foo"), "bisect-foo-step2");
+        assert_eq!(next_step_name("bisect-foo-step2"), "bisect-foo-step3");
+        assert_eq!(next_step_name("foo-stepper"), "foo-stepper-step2");
+    }
+
+    #[test]
+    fn test_bisection() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        let all = |_| Ok(true);
+
+        let krate = Crate::Local("build-pass".into());
+        let bisect = |start, end| BisectRegression {
+            name: "bisect-foo".into(),
+            namespace: DEFAULT_NAMESPACE.into(),
+            krate: krate.clone(),
+            start,
+            end,
+            mode: Mode::BuildAndTest,
+            test_flags: TestFlags::default(),
+            priority: 0,
+            github_issue: None,
+        };
+        let err = bisect(date(2), date(1)).create(&ctx, all).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::InvalidBisectionRange)
+        );
+        let err = bisect(date(1), date(30))
+            .create(&ctx, |day| Ok(day != date(30)))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::UnpublishedNightly(date(30)))
+        );
+
+        bisect(date(1), date(30)).create(&ctx, all).unwrap();
+        let mut ex = Experiment::get(&db, "bisect-foo").unwrap().unwrap();
+        assert_eq!(ex.toolchains[0].to_string(), "nightly-2024-01-01");
+        assert_eq!(ex.toolchains.len(), 8);
+        assert_eq!(ex.get_crates(&db).unwrap(), vec![krate.clone()]);
+
+        let results = DatabaseDB::new(&db);
+        for (tc, res) in [ex.start_toolchain(), ex.end_toolchain()].iter().zip(&[
+            TestResult::TestPass,
+            TestResult::BuildFail(FailureReason::Unknown),
+        ]) {
+            results
+                .store_result(&ex, &krate, tc, res.clone(), b"", EncodingType::Plain)
+                .unwrap();
+        }
+        ex.set_status(&db, Status::Completed).unwrap();
+
+        // The next step runs the nightlies between the last good one and the first bad one
+        let step = advance(&ctx, &ex, &regressed(Some("nightly-2024-01-13")), all).unwrap();
+        assert_eq!(
+            step,
+            Some(BisectionStep::Continued("bisect-foo-step2".into()))
+        );
+        let ex = Experiment::get(&db, "bisect-foo-step2").unwrap().unwrap();
+        assert_eq!(ex.toolchains[0].to_string(), "nightly-2024-01-09");
+        assert_eq!(ex.end_toolchain().to_string(), "nightly-2024-01-13");
+        assert_eq!(ex.toolchains.len(), 5);
+        assert_eq!(ex.get_crates(&db).unwrap(), vec![krate]);
+
+        let step = advance(&ctx, &ex, &regressed(Some("nightly-2024-01-11")), all).unwrap();
+        assert_eq!(
+            step,
+            Some(BisectionStep::Found {
+                good: "nightly-2024-01-10".parse().unwrap(),
+                bad: "nightly-2024-01-11".parse().unwrap(),
+                commits: None,
+            })
+        );
+
+        let mut not_reproduced = regressed(None);
+        not_reproduced.crates[0].res = Comparison::SameTestPass;
+        let step = advance(&ctx, &ex, &not_reproduced, all).unwrap();
+        assert_eq!(step, Some(BisectionStep::NotReproduced));
+
+        // Experiments outside of a bisection are ignored, even with the same tags and crates
+        CreateExperiment {
+            crates: CrateSelect::SameAs("bisect-foo-step2".into()),
+            tags: ex.tags.clone(),
+            ..CreateExperiment::dummy("not-bisect")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let other = Experiment::get(&db, "not-bisect").unwrap().unwrap();
+        let step = advance(&ctx, &other, &regressed(None), all).unwrap();
+        assert_eq!(step, None);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::audit::{self, AuditAction};
+use crate::experiments::{Experiment, Status};
+use crate::prelude::*;
+use serde_json::json;
+
+/// Stop a queued or running experiment, keeping the results recorded so far. Agents running the
+/// experiment notice it the next time they check, and don't start any new task for it.
+pub struct CancelExperiment {
+    pub name: String,
+}
+
+impl Action for CancelExperiment {
+    fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
+        let mut ex = match Experiment::get(&ctx.db, &self.name)? {
+            Some(ex) => ex,
+            None => return Err(ExperimentError::NotFound(self.name).into()),
+        };
+
+        let previous = ex.status;
+        match previous {
+            Status::Queued | Status::Running => {}
+            _ => return Err(ExperimentError::CanOnlyCancelUnfinishedExperiments.into()),
+        }
+
+        ex.set_status(&ctx.db, Status::Cancelled)?;
+        audit::record(
+            &ctx.db,
+            &ctx.actor,
+            AuditAction::CancelExperiment,
+            Some(&self.name),
+            &json!({ "previous-status": previous }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CancelExperiment;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment, ExperimentError};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::{Experiment, Status};
+
+    #[test]
+    fn test_cancel_experiment() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+
+        let mut ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        ex.set_status(&db, Status::Running).unwrap();
+
+        CancelExperiment {
+            name: "dummy".to_string(),
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        // The partial results are kept, but the experiment is out of the queue
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.status, Status::Cancelled);
+        assert!(ex.completed_at.is_some());
+        assert!(Experiment::unfinished(&db).unwrap().is_empty());
+
+        let err = CancelExperiment {
+            name: "dummy".to_string(),
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::CanOnlyCancelUnfinishedExperiments)
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::actions::{experiments::ExperimentError, Action, ActionsCtx, CreateExperiment};
+use crate::audit::{self, AuditAction};
+use crate::crates::{Edition, RustVersion};
+use crate::experiments::{
+    qualified_name, Assignee, BuildTargets, CapLints, CrateSelect, Experiment, GitHubIssue, Mode,
+    RustcWrapper, Targets, TestFlags,
+};
+use crate::prelude::*;
+use crate::toolchain::Toolchain;
+use serde_json::json;
+
+/// Create a new experiment with the configuration of an existing one, changing only the provided
+/// fields. Unless other crates are selected, the new experiment tests the same crates as the
+/// existing one.
+pub struct CloneExperiment {
+    pub name: String,
+    /// Name of the new experiment, inside the namespace of the cloned one.
+    pub new_name: String,
+    pub github_issue: Option<GitHubIssue>,
+    pub toolchains: [Option<Toolchain>; 2],
+    pub crates: Option<CrateSelect>,
+    pub mode: Option<Mode>,
+    pub cap_lints: Option<CapLints>,
+    pub priority: Option<i32>,
+    pub ignore_blacklist: Option<bool>,
+    pub assign: Option<Assignee>,
+    pub requirement: Option<String>,
+    pub edition: Option<Edition>,
+    pub max_rust_version: Option<RustVersion>,
+    pub build_targets: Option<BuildTargets>,
+    pub test_flags: Option<TestFlags>,
+    pub dist_server: Option<String>,
+    pub rustc_wrapper: Option<RustcWrapper>,
+    pub timings: Option<bool>,
+    pub targets: Option<Targets>,
+}
+
+impl CloneExperiment {
+    #[cfg(test)]
+    pub fn dummy(name: &str, new_name: &str) -> Self {
+        CloneExperiment {
+            name: name.to_string(),
+            new_name: new_name.to_string(),
+            github_issue: None,
+            toolchains: [None, None],
+            crates: None,
+            mode: None,
+            cap_lints: None,
+            priority: None,
+            ignore_blacklist: None,
+            assign: None,
+            requirement: None,
+            edition: None,
+            max_rust_version: None,
+            build_targets: None,
+            test_flags: None,
+            dist_server: None,
+            rustc_wrapper: None,
+            timings: None,
+            targets: None,
+        }
+    }
+}
+
+impl Action for CloneExperiment {
+    fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
+        let source = match Experiment::get(&ctx.db, &self.name)? {
+            Some(ex) => ex,
+            None => return Err(ExperimentError::NotFound(self.name).into()),
+        };
+
+        // The intermediate toolchains are only meaningful between the toolchains of the source
+        let toolchains = match self.toolchains {
+            [None, None] => source.toolchains,
+            [start, end] => vec![
+                start.unwrap_or_else(|| source.start_toolchain().clone()),
+                end.unwrap_or_else(|| source.end_toolchain().clone()),
+            ],
+        };
+        let name = qualified_name(&source.namespace, &self.new_name);
+
+        CreateExperiment {
+            name: self.new_name,
+            namespace: source.namespace,
+            toolchains,
+            mode: self.mode.unwrap_or(source.mode),
+            crates: match self.crates {
+                Some(crates) => crates,
+                None => CrateSelect::SameAs(self.name.clone()),
+            },
+            cap_lints: self.cap_lints.unwrap_or(source.cap_lints),
+            priority: self.priority.unwrap_or(source.priority),
+            github_issue: self.github_issue,
+            ignore_blacklist: self.ignore_blacklist.unwrap_or(source.ignore_blacklist),
+            // The experiment is assigned again when it's its turn to run
+            assign: self.assign,
+            requirement: self.requirement.or(source.requirement),
+            edition: self.edition.or(source.edition),
+            max_rust_version: self.max_rust_version.or(source.max_rust_version),
+            build_targets: self.build_targets.unwrap_or(source.build_targets),
+            test_flags: self.test_flags.unwrap_or(source.test_flags),
+            dist_server: self.dist_server.or(source.dist_server),
+            rustc_wrapper: self.rustc_wrapper.or(source.rustc_wrapper),
+            timings: self.timings.unwrap_or(source.timings),
+            targets: self.targets.unwrap_or(source.targets),
+            tags: source.tags,
+        }
+        .apply(ctx)?;
+
+        audit::record(
+            &ctx.db,
+            &ctx.actor,
+            AuditAction::CloneExperiment,
+            Some(&name),
+            &json!({ "source": self.name }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CloneExperiment;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment, ExperimentError};
+    use crate::audit::{self, AuditAction};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::{CapLints, Experiment, Mode, Status};
+    use crate::toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+
+    #[test]
+    fn test_clone_experiment() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let mut create = CreateExperiment::dummy("source");
+        create.mode = Mode::CheckOnly;
+        create.priority = 5;
+        create.timings = true;
+        create.tags = "release:1.78".parse().unwrap();
+        create.apply(&ctx).unwrap();
+        let mut source = Experiment::get(&db, "source").unwrap().unwrap();
+        source.set_status(&db, Status::Completed).unwrap();
+
+        let mut clone = CloneExperiment::dummy("source", "clone");
+        clone.toolchains[1] = Some(MAIN_TOOLCHAIN.clone());
+        clone.toolchains[0] = Some(TEST_TOOLCHAIN.clone());
+        clone.cap_lints = Some(CapLints::Warn);
+        clone.apply(&ctx).unwrap();
+
+        // Only the provided fields are changed
+        let ex = Experiment::get(&db, "clone").unwrap().unwrap();
+        assert_eq!(ex.status, Status::Queued);
+        assert_eq!(
+            ex.toolchains,
+            [TEST_TOOLCHAIN.clone(), MAIN_TOOLCHAIN.clone()]
+        );
+        assert_eq!(ex.cap_lints, CapLints::Warn);
+        assert_eq!(ex.mode, Mode::CheckOnly);
+        assert_eq!(ex.priority, 5);
+        assert!(ex.timings);
+        assert_eq!(ex.tags, source.tags);
+
+        // The same crates are tested
+        let mut crates = ex.get_crates(&db).unwrap();
+        let mut source_crates = source.get_crates(&db).unwrap();
+        crates.sort();
+        source_crates.sort();
+        assert_eq!(crates, source_crates);
+
+        let entries = audit::entries(&db, None, None, 1).unwrap();
+        assert_eq!(entries[0].action, AuditAction::CloneExperiment);
+        assert_eq!(entries[0].experiment.as_ref().unwrap(), "clone");
+        assert_eq!(entries[0].payload["source"], "source");
+
+        // The name of the clone can't be taken
+        let err = CloneExperiment::dummy("source", "clone")
+            .apply(&ctx)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::AlreadyExists("clone".into()))
+        );
+    }
+
+    #[test]
+    fn test_clone_missing_experiment() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        let err = CloneExperiment::dummy("source", "clone")
+            .apply(&ctx)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::NotFound("source".into()))
+        );
+    }
+}
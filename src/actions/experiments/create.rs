@@ -1,13 +1,30 @@
-use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::actions::{
+    experiments::{index_snapshot, requirement_for_targets, skip_reasons, ExperimentError},
+    Action, ActionsCtx,
+};
+use crate::audit::{self, AuditAction};
+use crate::crates::{metadata, Edition, RustVersion};
 use crate::db::QueryUtils;
-use crate::experiments::{Assignee, CapLints, CrateSelect, Experiment, GitHubIssue, Mode, Status};
+use crate::experiments::{
+    is_valid_namespace, qualified_name, Assignee, BuildTargets, CapLints, CrateSelect, Experiment,
+    GitHubIssue, Mode, RustcWrapper, Status, Tags, Targets, TestFlags, DEFAULT_NAMESPACE,
+};
 use crate::prelude::*;
 use crate::toolchain::Toolchain;
 use chrono::Utc;
+use rand::{thread_rng, Rng};
+use serde_json::json;
+use std::collections::HashSet;
+
+/// Number of crates inserted in each transaction while creating an experiment.
+const CRATES_PER_TRANSACTION: usize = 1000;
 
 pub struct CreateExperiment {
     pub name: String,
-    pub toolchains: [Toolchain; 2],
+    pub namespace: String,
+    /// Toolchains the crates are run with, in release order: the results of the first and the last
+    /// one are compared.
+    pub toolchains: Vec<Toolchain>,
     pub mode: Mode,
     pub crates: CrateSelect,
     pub cap_lints: CapLints,
@@ -16,6 +33,15 @@ pub struct CreateExperiment {
     pub ignore_blacklist: bool,
     pub assign: Option<Assignee>,
     pub requirement: Option<String>,
+    pub edition: Option<Edition>,
+    pub max_rust_version: Option<RustVersion>,
+    pub build_targets: BuildTargets,
+    pub test_flags: TestFlags,
+    pub dist_server: Option<String>,
+    pub rustc_wrapper: Option<RustcWrapper>,
+    pub timings: bool,
+    pub targets: Targets,
+    pub tags: Tags,
 }
 
 impl CreateExperiment {
@@ -25,7 +51,8 @@ impl CreateExperiment {
 
         CreateExperiment {
             name: name.to_string(),
-            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            toolchains: vec![MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
             mode: Mode::BuildAndTest,
             crates: CrateSelect::Local,
             cap_lints: CapLints::Forbid,
@@ -34,56 +61,292 @@ impl CreateExperiment {
             ignore_blacklist: false,
             assign: None,
             requirement: None,
+            edition: None,
+            max_rust_version: None,
+            build_targets: BuildTargets::Default,
+            test_flags: TestFlags::default(),
+            dist_server: None,
+            rustc_wrapper: None,
+            timings: false,
+            targets: Targets::default(),
+            tags: Tags::default(),
+        }
+    }
+}
+
+/// What creating an experiment would result in, without creating it.
+pub struct ExperimentPreview {
+    pub requirement: Option<String>,
+    /// Number of crates selected, after applying the manifest-based filters.
+    pub crates: usize,
+    /// Number of selected crates that would be skipped.
+    pub skipped: usize,
+}
+
+impl CreateExperiment {
+    /// Check the experiment could be created and count the crates it would test, without
+    /// creating anything. The manifests of the crates are only fetched when filtering them.
+    pub fn preview(&self, ctx: &ActionsCtx) -> Fallible<ExperimentPreview> {
+        let requirement = self.validate(ctx)?;
+        let name = qualified_name(&self.namespace, &self.name);
+        if Experiment::exists(&ctx.db, &name)? {
+            return Err(ExperimentError::AlreadyExists(name).into());
+        }
+
+        let mut crates =
+            crate::crates::lists::get_crates(self.crates.clone(), &ctx.db, &ctx.config, None)?;
+        if self.edition.is_some() || self.max_rust_version.is_some() {
+            metadata::update(&ctx.db, &crates)?;
+            crates = metadata::apply_filters(
+                &ctx.db,
+                crates,
+                self.edition,
+                self.max_rust_version.as_ref(),
+            )?;
+        }
+        let skipped = skip_reasons(&ctx.config, &crates, self.ignore_blacklist)?.len();
+
+        Ok(ExperimentPreview {
+            requirement,
+            crates: crates.len(),
+            skipped,
+        })
+    }
+
+    /// Check the configuration of the experiment, returning the requirement of its agents.
+    fn validate(&self, ctx: &ActionsCtx) -> Fallible<Option<String>> {
+        if let Some(maintenance) = crate::maintenance::status(&ctx.db)? {
+            return Err(ExperimentError::Maintenance(maintenance.message).into());
+        }
+
+        if !is_valid_namespace(&self.namespace) {
+            return Err(ExperimentError::InvalidNamespace(self.namespace.clone()).into());
+        }
+
+        // The namespace separator is reserved, to avoid clashes between namespaces
+        if self.name.contains(':') {
+            return Err(ExperimentError::InvalidName(self.name.clone()).into());
+        }
+
+        if self.toolchains.len() < 2 {
+            return Err(ExperimentError::MissingToolchains.into());
+        }
+
+        // Ensure no experiment with duplicate toolchains is created
+        for (i, tc) in self.toolchains.iter().enumerate() {
+            tc.validate_new()?;
+            if self.toolchains[i + 1..].contains(tc) {
+                return Err(ExperimentError::DuplicateToolchains.into());
+            }
         }
+
+        Ok(requirement_for_targets(
+            self.requirement.clone(),
+            &self.targets,
+        )?)
+    }
+
+    /// Check whether an experiment whose creation was interrupted was created with this
+    /// configuration.
+    fn same_configuration(&self, ex: &Experiment, requirement: &Option<String>) -> bool {
+        ex.namespace == self.namespace
+            && ex.toolchains == self.toolchains
+            && ex.mode == self.mode
+            && ex.crate_select.as_ref() == Some(&self.crates)
+            && ex.cap_lints == self.cap_lints
+            && ex.priority == self.priority
+            && ex.github_issue.as_ref().map(|i| &i.api_url)
+                == self.github_issue.as_ref().map(|i| &i.api_url)
+            && ex.ignore_blacklist == self.ignore_blacklist
+            && ex.assigned_to.as_ref().map(|a| a.to_string())
+                == self.assign.as_ref().map(|a| a.to_string())
+            && ex.requirement == *requirement
+            && ex.edition == self.edition
+            && ex.max_rust_version == self.max_rust_version
+            && ex.build_targets == self.build_targets
+            && ex.test_flags == self.test_flags
+            && ex.dist_server == self.dist_server
+            && ex.rustc_wrapper == self.rustc_wrapper
+            && ex.timings == self.timings
+            && ex.targets == self.targets
+            && ex.tags == self.tags
     }
 }
 
 impl Action for CreateExperiment {
     fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
-        // Ensure no duplicate experiments are created
-        if Experiment::exists(&ctx.db, &self.name)? {
-            return Err(ExperimentError::AlreadyExists(self.name.clone()).into());
+        let requirement = self.validate(ctx)?;
+        let name = qualified_name(&self.namespace, &self.name);
+
+        // Ensure no duplicate experiments are created, unless a previous attempt at creating
+        // this one with the same configuration was interrupted
+        let interrupted = match Experiment::get(&ctx.db, &name)? {
+            Some(ex)
+                if ex.status == Status::Creating && self.same_configuration(&ex, &requirement) =>
+            {
+                Some(ex)
+            }
+            Some(_) => return Err(ExperimentError::AlreadyExists(name).into()),
+            None => None,
+        };
+        // Resuming the creation must select the same random sample of crates
+        let crates_seed = interrupted
+            .as_ref()
+            .and_then(|ex| ex.crates_seed)
+            .unwrap_or_else(|| thread_rng().gen());
+
+        let crates = crate::crates::lists::get_crates(
+            self.crates.clone(),
+            &ctx.db,
+            &ctx.config,
+            Some(crates_seed),
+        )?;
+        let index_snapshot = index_snapshot(&ctx.db, &self.crates, &crates)?;
+        let crates_metadata = metadata::analyze(&ctx.db, &crates)?;
+        let crates = metadata::apply_filters(
+            &ctx.db,
+            crates,
+            self.edition,
+            self.max_rust_version.as_ref(),
+        )?;
+        let skip_reasons = skip_reasons(&ctx.config, &crates, self.ignore_blacklist)?;
+
+        // The crates already inserted must still be selected, otherwise the lists changed since
+        // the interrupted attempt and resuming it would mix both versions of the lists
+        if let Some(ex) = &interrupted {
+            let selected = crates.iter().collect::<HashSet<_>>();
+            if ex.index_snapshot != index_snapshot
+                || ex
+                    .get_crates(&ctx.db)?
+                    .iter()
+                    .any(|krate| !selected.contains(krate))
+            {
+                return Err(ExperimentError::AlreadyExists(name).into());
+            }
         }
 
-        // Ensure no experiment with duplicate toolchains is created
-        if self.toolchains[0] == self.toolchains[1] {
-            return Err(ExperimentError::DuplicateToolchains.into());
+        let toolchains = self
+            .toolchains
+            .iter()
+            .map(|tc| tc.to_string())
+            .collect::<Vec<_>>();
+        // The compared toolchains have their own columns
+        let intermediate_toolchains = &toolchains[1..toolchains.len() - 1];
+
+        let audit_payload = json!({
+            "namespace": self.namespace,
+            "toolchains": toolchains,
+            "mode": self.mode,
+            "crates": self.crates,
+            "cap-lints": self.cap_lints,
+            "priority": self.priority,
+            "github-issue": self.github_issue.as_ref().map(|i| i.html_url.as_str()),
+            "ignore-blacklist": self.ignore_blacklist,
+            "assign": self.assign.as_ref().map(|a| a.to_string()),
+            "requirement": requirement,
+            "edition": self.edition,
+            "max-rust-version": self.max_rust_version,
+            "build-targets": self.build_targets,
+            "test-flags": self.test_flags,
+            "dist-server": self.dist_server,
+            "rustc-wrapper": self.rustc_wrapper,
+            "timings": self.timings,
+            "targets": self.targets,
+            "tags": self.tags,
+        });
+
+        // The experiment is only queued once all of its crates are inserted, and creating it again
+        // after a crash completes the missing parts
+        if interrupted.is_some() {
+            info!("resuming the creation of experiment {}", name);
+        } else {
+            ctx.db.transaction(|transaction| {
+                transaction.execute(
+                    "INSERT INTO experiments \
+                     (name, mode, cap_lints, toolchain_start, toolchain_end, priority, \
+                     created_at, status, github_issue, github_issue_url, github_issue_number, \
+                     ignore_blacklist, assigned_to, requirement, namespace, edition, \
+                     max_rust_version, build_targets, test_flags, dist_server, rustc_wrapper, \
+                     timings, targets, tags, index_snapshot, crates_seed, \
+                     intermediate_toolchains, crate_select) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, \
+                     ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28);",
+                    &[
+                        &name,
+                        &self.mode.to_str(),
+                        &self.cap_lints.to_str(),
+                        &toolchains[0],
+                        &toolchains[toolchains.len() - 1],
+                        &self.priority,
+                        &Utc::now(),
+                        &Status::Creating.to_str(),
+                        &self.github_issue.as_ref().map(|i| i.api_url.as_str()),
+                        &self.github_issue.as_ref().map(|i| i.html_url.as_str()),
+                        &self.github_issue.as_ref().map(|i| i.number),
+                        &self.ignore_blacklist,
+                        &self.assign.map(|a| a.to_string()),
+                        &requirement,
+                        &self.namespace,
+                        &self.edition.map(|e| e.to_str()),
+                        &self.max_rust_version.as_ref().map(|v| v.to_string()),
+                        &self.build_targets.to_str(),
+                        &self.test_flags.to_string(),
+                        &self.dist_server,
+                        &self.rustc_wrapper.as_ref().map(|w| w.to_string()),
+                        &self.timings,
+                        &self.targets.to_string(),
+                        &self.tags.to_string(),
+                        &index_snapshot
+                            .as_ref()
+                            .map(::serde_json::to_string)
+                            .transpose()?,
+                        &crates_seed,
+                        &::serde_json::to_string(intermediate_toolchains)?,
+                        &self.crates.to_string(),
+                    ],
+                )?;
+                Ok(())
+            })?;
         }
 
-        let crates = crate::crates::lists::get_crates(self.crates, &ctx.db, &ctx.config)?;
+        // Tens of thousands of crates can be inserted, so they're split across transactions
+        for chunk in crates.chunks(CRATES_PER_TRANSACTION) {
+            ctx.db.transaction(|transaction| {
+                for krate in chunk {
+                    let skip_reason = skip_reasons.get(krate);
+                    transaction.execute(
+                        "INSERT OR IGNORE INTO experiment_crates \
+                         (experiment, crate, skipped, skip_reason, metadata) \
+                         VALUES (?1, ?2, ?3, ?4, ?5);",
+                        &[
+                            &name,
+                            &::serde_json::to_string(&krate)?,
+                            &skip_reason.is_some(),
+                            &skip_reason.map(|reason| reason.to_str()),
+                            &crates_metadata
+                                .get(krate)
+                                .map(::serde_json::to_string)
+                                .transpose()?,
+                        ],
+                    )?;
+                }
+                Ok(())
+            })?;
+        }
 
         ctx.db.transaction(|transaction| {
             transaction.execute(
-                "INSERT INTO experiments \
-                 (name, mode, cap_lints, toolchain_start, toolchain_end, priority, created_at, \
-                 status, github_issue, github_issue_url, github_issue_number, ignore_blacklist, \
-                 assigned_to, requirement) \
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14);",
-                &[
-                    &self.name,
-                    &self.mode.to_str(),
-                    &self.cap_lints.to_str(),
-                    &self.toolchains[0].to_string(),
-                    &self.toolchains[1].to_string(),
-                    &self.priority,
-                    &Utc::now(),
-                    &Status::Queued.to_str(),
-                    &self.github_issue.as_ref().map(|i| i.api_url.as_str()),
-                    &self.github_issue.as_ref().map(|i| i.html_url.as_str()),
-                    &self.github_issue.as_ref().map(|i| i.number),
-                    &self.ignore_blacklist,
-                    &self.assign.map(|a| a.to_string()),
-                    &self.requirement,
-                ],
+                "UPDATE experiments SET status = ?1 WHERE name = ?2;",
+                &[&Status::Queued.to_str(), &name],
             )?;
 
-            for krate in &crates {
-                let skipped = !self.ignore_blacklist && ctx.config.should_skip(krate);
-                transaction.execute(
-                    "INSERT INTO experiment_crates (experiment, crate, skipped) VALUES (?1, ?2, ?3);",
-                    &[&self.name, &::serde_json::to_string(&krate)?, &skipped],
-                )?;
-            }
+            audit::record(
+                transaction,
+                &ctx.actor,
+                AuditAction::CreateExperiment,
+                Some(&name),
+                &audit_payload,
+            )?;
 
             Ok(())
         })?;
@@ -97,10 +360,11 @@ mod tests {
     use super::CreateExperiment;
     use crate::actions::{Action, ActionsCtx, ExperimentError};
     use crate::config::{Config, CrateConfig};
-    use crate::crates::Crate;
+    use crate::crates::{Crate, Edition, RegistryCrate};
     use crate::db::{Database, QueryUtils};
     use crate::experiments::{
-        Assignee, CapLints, CrateSelect, Experiment, GitHubIssue, Mode, Status,
+        Assignee, BuildTargets, CapLints, CrateSelect, CrateSkipReason, Experiment, GitHubIssue,
+        Mode, Status, Tags, Targets, TestFlags, DEFAULT_NAMESPACE,
     };
     use crate::toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
 
@@ -117,7 +381,8 @@ mod tests {
 
         CreateExperiment {
             name: "foo".to_string(),
-            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            toolchains: vec![MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
             mode: Mode::BuildAndTest,
             crates: CrateSelect::Local,
             cap_lints: CapLints::Forbid,
@@ -130,6 +395,15 @@ mod tests {
             ignore_blacklist: true,
             assign: None,
             requirement: Some("linux".to_string()),
+            edition: None,
+            max_rust_version: None,
+            build_targets: BuildTargets::Examples,
+            test_flags: "--no-fail-fast".parse().unwrap(),
+            dist_server: Some("https://mirror.example.com/rust".to_string()),
+            rustc_wrapper: Some("time-rustc".parse().unwrap()),
+            timings: true,
+            targets: "wasm32-unknown-unknown".parse().unwrap(),
+            tags: "release:1.78,team:libs".parse().unwrap(),
         }
         .apply(&ctx)
         .unwrap();
@@ -143,9 +417,19 @@ mod tests {
         assert_eq!(ex.mode, Mode::BuildAndTest);
         assert_eq!(
             ex.get_crates(&ctx.db).unwrap(),
-            crate::crates::lists::get_crates(CrateSelect::Local, &db, &config).unwrap()
+            crate::crates::lists::get_crates(CrateSelect::Local, &db, &config, None).unwrap()
         );
         assert_eq!(ex.cap_lints, CapLints::Forbid);
+        assert_eq!(ex.build_targets, BuildTargets::Examples);
+        assert_eq!(ex.test_flags.args(), &["--no-fail-fast"]);
+        assert_eq!(
+            ex.dist_server.as_ref().map(|s| s.as_str()),
+            Some("https://mirror.example.com/rust")
+        );
+        assert_eq!(ex.rustc_wrapper, Some("time-rustc".parse().unwrap()));
+        assert!(ex.timings);
+        assert_eq!(ex.targets.triples(), &["wasm32-unknown-unknown"]);
+        assert_eq!(ex.tags.names(), &["release:1.78", "team:libs"]);
         assert_eq!(ex.github_issue.as_ref().unwrap().api_url.as_str(), api_url);
         assert_eq!(
             ex.github_issue.as_ref().unwrap().html_url.as_str(),
@@ -157,6 +441,14 @@ mod tests {
         assert!(ex.assigned_to.is_none());
         assert!(ex.ignore_blacklist);
         assert_eq!(ex.requirement, Some("linux".to_string()));
+
+        // The manifests of the crates were analyzed before the experiment was created
+        let metadata = ex.get_crates_metadata(&ctx.db).unwrap();
+        assert_eq!(
+            metadata[&Crate::Local("build-pass".into())].edition,
+            Some(Edition::Edition2015)
+        );
+        assert!(!metadata.contains_key(&Crate::Local("broken-cargotoml".into())));
     }
 
     #[test]
@@ -195,8 +487,8 @@ mod tests {
             crates
                 .iter()
                 .find(|c| {
-                    if let Crate::Local(name) = c {
-                        name == krate
+                    if let Crate::Local(local) = c {
+                        local.name == krate
                     } else {
                         panic!("there should be no non-local crates")
                     }
@@ -213,6 +505,7 @@ mod tests {
                 skip_tests: false,
                 quiet: false,
                 broken: false,
+                known_broken: None,
             },
         );
         let ctx = ActionsCtx::new(&db, &config);
@@ -226,6 +519,13 @@ mod tests {
         .apply(&ctx)
         .unwrap();
         assert!(is_skipped(&db, "foo", "build-pass"));
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        let reasons = ex.get_skip_reasons(&db).unwrap();
+        assert_eq!(reasons.len(), 1);
+        assert_eq!(
+            reasons.get(&Crate::Local("build-pass".into())),
+            Some(&CrateSkipReason::Blacklisted)
+        );
 
         CreateExperiment {
             ignore_blacklist: true,
@@ -234,6 +534,8 @@ mod tests {
         .apply(&ctx)
         .unwrap();
         assert!(!is_skipped(&db, "bar", "build-pass"));
+        let ex = Experiment::get(&db, "bar").unwrap().unwrap();
+        assert!(ex.get_skip_reasons(&db).unwrap().is_empty());
     }
 
     #[test]
@@ -247,7 +549,8 @@ mod tests {
         // Ensure an experiment with duplicate toolchains can't be created
         let err = CreateExperiment {
             name: "foo".to_string(),
-            toolchains: [MAIN_TOOLCHAIN.clone(), MAIN_TOOLCHAIN.clone()],
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            toolchains: vec![MAIN_TOOLCHAIN.clone(), MAIN_TOOLCHAIN.clone()],
             mode: Mode::BuildAndTest,
             crates: CrateSelect::Local,
             cap_lints: CapLints::Forbid,
@@ -256,6 +559,15 @@ mod tests {
             ignore_blacklist: false,
             assign: None,
             requirement: None,
+            edition: None,
+            max_rust_version: None,
+            build_targets: BuildTargets::Default,
+            test_flags: TestFlags::default(),
+            dist_server: None,
+            rustc_wrapper: None,
+            timings: false,
+            targets: Targets::default(),
+            tags: Tags::default(),
         }
         .apply(&ctx)
         .unwrap_err();
@@ -264,6 +576,65 @@ mod tests {
             err.downcast_ref(),
             Some(&ExperimentError::DuplicateToolchains)
         );
+
+        // The intermediate toolchains must be different from the compared ones too
+        let err = CreateExperiment {
+            toolchains: vec![
+                MAIN_TOOLCHAIN.clone(),
+                TEST_TOOLCHAIN.clone(),
+                TEST_TOOLCHAIN.clone(),
+            ],
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::DuplicateToolchains)
+        );
+
+        // At least two toolchains must be compared
+        let err = CreateExperiment {
+            toolchains: vec![MAIN_TOOLCHAIN.clone()],
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::MissingToolchains)
+        );
+
+        let intermediate: Toolchain = "nightly-1970-01-01".parse().unwrap();
+        let toolchains = vec![MAIN_TOOLCHAIN.clone(), intermediate, TEST_TOOLCHAIN.clone()];
+        CreateExperiment {
+            toolchains: toolchains.clone(),
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        assert_eq!(ex.toolchains, toolchains);
+        assert_eq!(ex.end_toolchain(), &TEST_TOOLCHAIN);
+    }
+
+    #[test]
+    fn test_short_commit_hash() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        // The CI artifacts can only be downloaded with the full commit hash
+        let err = CreateExperiment {
+            toolchains: vec![MAIN_TOOLCHAIN.clone(), "try#deadbeef".parse().unwrap()],
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert!(err.to_string().contains("deadbeef"));
+        assert!(Experiment::get(&db, "foo").unwrap().is_none());
     }
 
     #[test]
@@ -277,7 +648,8 @@ mod tests {
         // The first experiment can be created successfully
         CreateExperiment {
             name: "foo".to_string(),
-            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            toolchains: vec![MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
             mode: Mode::BuildAndTest,
             crates: CrateSelect::Local,
             cap_lints: CapLints::Forbid,
@@ -286,6 +658,15 @@ mod tests {
             ignore_blacklist: false,
             assign: None,
             requirement: None,
+            edition: None,
+            max_rust_version: None,
+            build_targets: BuildTargets::Default,
+            test_flags: TestFlags::default(),
+            dist_server: None,
+            rustc_wrapper: None,
+            timings: false,
+            targets: Targets::default(),
+            tags: Tags::default(),
         }
         .apply(&ctx)
         .unwrap();
@@ -293,7 +674,8 @@ mod tests {
         // While the second one fails
         let err = CreateExperiment {
             name: "foo".to_string(),
-            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            toolchains: vec![MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
             mode: Mode::BuildAndTest,
             crates: CrateSelect::Local,
             cap_lints: CapLints::Forbid,
@@ -302,6 +684,15 @@ mod tests {
             ignore_blacklist: false,
             assign: None,
             requirement: None,
+            edition: None,
+            max_rust_version: None,
+            build_targets: BuildTargets::Default,
+            test_flags: TestFlags::default(),
+            dist_server: None,
+            rustc_wrapper: None,
+            timings: false,
+            targets: Targets::default(),
+            tags: Tags::default(),
         }
         .apply(&ctx)
         .unwrap_err();
@@ -311,4 +702,231 @@ mod tests {
             Some(&ExperimentError::AlreadyExists("foo".into()))
         );
     }
+
+    #[test]
+    fn test_resume_interrupted_creation() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        let crates = ex.get_crates(&db).unwrap();
+        assert!(ex.crates_seed.is_some());
+
+        // Simulate a crash while the crates were being inserted
+        db.execute(
+            "UPDATE experiments SET status = 'creating' WHERE name = 'foo';",
+            &[],
+        )
+        .unwrap();
+        db.execute(
+            "DELETE FROM experiment_crates WHERE rowid IN \
+             (SELECT rowid FROM experiment_crates WHERE experiment = 'foo' LIMIT 2);",
+            &[],
+        )
+        .unwrap();
+
+        // Creating a different experiment with the same name is still rejected
+        let different = vec![
+            CreateExperiment {
+                mode: Mode::CheckOnly,
+                ..CreateExperiment::dummy("foo")
+            },
+            CreateExperiment {
+                crates: CrateSelect::Demo,
+                ..CreateExperiment::dummy("foo")
+            },
+            CreateExperiment {
+                priority: 10,
+                ..CreateExperiment::dummy("foo")
+            },
+            CreateExperiment {
+                cap_lints: CapLints::Warn,
+                ..CreateExperiment::dummy("foo")
+            },
+            CreateExperiment {
+                test_flags: "-- --test-threads=1".parse().unwrap(),
+                ..CreateExperiment::dummy("foo")
+            },
+        ];
+        for create in different {
+            let err = create.apply(&ctx).unwrap_err();
+            assert_eq!(
+                err.downcast_ref(),
+                Some(&ExperimentError::AlreadyExists("foo".into()))
+            );
+        }
+
+        // So is resuming it when the crates already inserted aren't selected anymore
+        let removed = ::serde_json::to_string(&Crate::Registry(RegistryCrate {
+            name: "removed".into(),
+            version: "1.0.0".into(),
+        }))
+        .unwrap();
+        db.execute(
+            "INSERT INTO experiment_crates (experiment, crate, skipped) VALUES ('foo', ?1, 0);",
+            &[&removed],
+        )
+        .unwrap();
+        let err = CreateExperiment::dummy("foo").apply(&ctx).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::AlreadyExists("foo".into()))
+        );
+        db.execute(
+            "DELETE FROM experiment_crates WHERE crate = ?1;",
+            &[&removed],
+        )
+        .unwrap();
+
+        // Creating it again completes the creation
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        let resumed_ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        assert_eq!(resumed_ex.status, Status::Queued);
+        assert_eq!(resumed_ex.crates_seed, ex.crates_seed);
+        let mut resumed = resumed_ex.get_crates(&db).unwrap();
+        let mut expected = crates;
+        resumed.sort();
+        expected.sort();
+        assert_eq!(resumed, expected);
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        // The same name can be used in different namespaces
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        CreateExperiment {
+            namespace: "infra".to_string(),
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        assert_eq!(ex.namespace.as_str(), DEFAULT_NAMESPACE);
+        let ex = Experiment::get(&db, "infra:foo").unwrap().unwrap();
+        assert_eq!(ex.namespace.as_str(), "infra");
+        assert!(!ex.get_crates(&db).unwrap().is_empty());
+
+        // But not twice in the same one
+        let err = CreateExperiment {
+            namespace: "infra".to_string(),
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::AlreadyExists("infra:foo".into()))
+        );
+
+        // Reject invalid namespaces and names
+        let err = CreateExperiment {
+            namespace: "Invalid Namespace".to_string(),
+            ..CreateExperiment::dummy("bar")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::InvalidNamespace(
+                "Invalid Namespace".into()
+            ))
+        );
+
+        let err = CreateExperiment::dummy("infra:bar")
+            .apply(&ctx)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::InvalidName("infra:bar".into()))
+        );
+    }
+
+    #[test]
+    fn test_requirement_inferred_from_targets() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment {
+            targets: "x86_64-pc-windows-msvc".parse().unwrap(),
+            ..CreateExperiment::dummy("foo")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        assert_eq!(ex.requirement, Some("windows".to_string()));
+
+        // Explicit requirements conflicting with the targets are rejected
+        let err = CreateExperiment {
+            requirement: Some("linux".to_string()),
+            targets: "x86_64-pc-windows-msvc".parse().unwrap(),
+            ..CreateExperiment::dummy("bar")
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::RequirementMismatch(
+                "linux".into(),
+                "windows".into()
+            ))
+        );
+        assert!(Experiment::get(&db, "bar").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_preview() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let preview = CreateExperiment {
+            targets: "x86_64-pc-windows-msvc".parse().unwrap(),
+            ..CreateExperiment::dummy("foo")
+        }
+        .preview(&ctx)
+        .unwrap();
+        assert_eq!(
+            preview.crates,
+            crate::crates::lists::get_crates(CrateSelect::Local, &db, &config, None)
+                .unwrap()
+                .len()
+        );
+        assert_eq!(preview.skipped, 0);
+        assert_eq!(preview.requirement, Some("windows".to_string()));
+        assert!(Experiment::get(&db, "foo").unwrap().is_none());
+
+        // The same validation as when creating the experiment is performed
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        let err = CreateExperiment::dummy("foo").preview(&ctx).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::AlreadyExists("foo".into()))
+        );
+        let err = CreateExperiment {
+            toolchains: vec![MAIN_TOOLCHAIN.clone(), MAIN_TOOLCHAIN.clone()],
+            ..CreateExperiment::dummy("bar")
+        }
+        .preview(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::DuplicateToolchains)
+        );
+    }
 }
@@ -1,7 +1,9 @@
 use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::audit::{self, AuditAction};
 use crate::db::QueryUtils;
 use crate::experiments::Experiment;
 use crate::prelude::*;
+use serde_json::json;
 
 pub struct DeleteExperiment {
     pub name: String,
@@ -13,12 +15,19 @@ impl Action for DeleteExperiment {
             return Err(ExperimentError::NotFound(self.name).into());
         }
 
-        // This will also delete all the data related to this experiment, thanks to the foreign
-        // keys in the SQLite database
-        ctx.db
-            .execute("DELETE FROM experiments WHERE name = ?1;", &[&self.name])?;
+        ctx.db.transaction(|t| {
+            // This will also delete all the data related to this experiment, thanks to the foreign
+            // keys in the SQLite database
+            t.execute("DELETE FROM experiments WHERE name = ?1;", &[&self.name])?;
 
-        Ok(())
+            audit::record(
+                t,
+                &ctx.actor,
+                AuditAction::DeleteExperiment,
+                Some(&self.name),
+                &json!({}),
+            )
+        })
     }
 }
 
@@ -27,8 +36,9 @@ mod tests {
     use super::DeleteExperiment;
     use crate::actions::{Action, ActionsCtx, CreateExperiment, ExperimentError};
     use crate::config::Config;
-    use crate::db::Database;
+    use crate::db::{Database, QueryUtils};
     use crate::experiments::Experiment;
+    use crate::results::{DatabaseDB, EncodingType, FailureReason, TestResult};
 
     #[test]
     fn test_delete_missing_experiment() {
@@ -60,6 +70,19 @@ mod tests {
         CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
         assert!(Experiment::exists(&db, "dummy").unwrap());
 
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let krate = ex.get_crates(&db).unwrap().pop().unwrap();
+        DatabaseDB::new(&db)
+            .store_result(
+                &ex,
+                &krate,
+                &ex.toolchains[0],
+                TestResult::BuildFail(FailureReason::Unknown),
+                b"log",
+                EncodingType::Plain,
+            )
+            .unwrap();
+
         // Delete it and make sure it doesn't exist anymore
         DeleteExperiment {
             name: "dummy".to_string(),
@@ -67,5 +90,19 @@ mod tests {
         .apply(&ctx)
         .unwrap();
         assert!(!Experiment::exists(&db, "dummy").unwrap());
+
+        // The rows referencing the experiment and the logs of its results are deleted with it
+        let count = |table: &str| -> i64 {
+            db.get_row(
+                &format!("SELECT COUNT(*) FROM {} WHERE experiment = ?1;", table),
+                &["dummy"],
+                |r| r.get(0),
+            )
+            .unwrap()
+            .unwrap()
+        };
+        assert_eq!(count("experiment_crates"), 0);
+        assert_eq!(count("results"), 0);
+        assert!(!db.exists("SELECT rowid FROM logs;", &[]).unwrap());
     }
 }
@@ -1,8 +1,18 @@
-use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::actions::{
+    experiments::{index_snapshot, requirement_for_targets, skip_reasons, ExperimentError},
+    Action, ActionsCtx,
+};
+use crate::audit::{self, AuditAction};
+use crate::crates::{metadata, Edition, RustVersion, TARGET_OS};
 use crate::db::QueryUtils;
-use crate::experiments::{Assignee, CapLints, CrateSelect, Experiment, Mode, Status};
+use crate::experiments::{
+    Assignee, BuildTargets, CapLints, CrateSelect, Experiment, Mode, RustcWrapper, Status, Targets,
+    TestFlags,
+};
 use crate::prelude::*;
 use crate::toolchain::Toolchain;
+use rand::{thread_rng, Rng};
+use serde_json::json;
 
 pub struct EditExperiment {
     pub name: String,
@@ -14,6 +24,14 @@ pub struct EditExperiment {
     pub ignore_blacklist: Option<bool>,
     pub assign: Option<Assignee>,
     pub requirement: Option<String>,
+    pub edition: Option<Edition>,
+    pub max_rust_version: Option<RustVersion>,
+    pub build_targets: Option<BuildTargets>,
+    pub test_flags: Option<TestFlags>,
+    pub dist_server: Option<String>,
+    pub rustc_wrapper: Option<RustcWrapper>,
+    pub timings: Option<bool>,
+    pub targets: Option<Targets>,
 }
 
 impl EditExperiment {
@@ -29,6 +47,14 @@ impl EditExperiment {
             ignore_blacklist: None,
             assign: None,
             requirement: None,
+            edition: None,
+            max_rust_version: None,
+            build_targets: None,
+            test_flags: None,
+            dist_server: None,
+            rustc_wrapper: None,
+            timings: None,
+            targets: None,
         }
     }
 }
@@ -45,20 +71,71 @@ impl Action for EditExperiment {
             return Err(ExperimentError::CanOnlyEditQueuedExperiments.into());
         }
 
+        // Fields that are not changed are recorded as null in the audit log
+        let audit_payload = json!({
+            "toolchains": [
+                self.toolchains[0].as_ref().map(|tc| tc.to_string()),
+                self.toolchains[1].as_ref().map(|tc| tc.to_string()),
+            ],
+            "crates": self.crates,
+            "mode": self.mode,
+            "cap-lints": self.cap_lints,
+            "priority": self.priority,
+            "ignore-blacklist": self.ignore_blacklist,
+            "assign": self.assign.as_ref().map(|a| a.to_string()),
+            "requirement": self.requirement,
+            "edition": self.edition,
+            "max-rust-version": self.max_rust_version,
+            "build-targets": self.build_targets,
+            "test-flags": self.test_flags,
+            "dist-server": self.dist_server,
+            "rustc-wrapper": self.rustc_wrapper,
+            "timings": self.timings,
+            "targets": self.targets,
+        });
+
+        // The manifests of the new crates are analyzed before the transaction starts, as fetching
+        // them can take a while
+        let selected_crates = match self.crates.take() {
+            Some(select) => {
+                // Experiments created before the seed was stored get a new one
+                let seed = ex.crates_seed.unwrap_or_else(|| thread_rng().gen());
+                let crates = crate::crates::lists::get_crates(
+                    select.clone(),
+                    &ctx.db,
+                    &ctx.config,
+                    Some(seed),
+                )?;
+                metadata::update(&ctx.db, &crates)?;
+                let snapshot = index_snapshot(&ctx.db, &select, &crates)?;
+                Some((select, crates, snapshot, seed))
+            }
+            None => None,
+        };
+
+        let targets_changed = self.targets.is_some();
         ctx.db.transaction(|t| {
-            // Try to update both toolchains
+            // Try to update both compared toolchains, the first and the last one
+            let last = ex.toolchains.len() - 1;
             for (i, col) in ["toolchain_start", "toolchain_end"].iter().enumerate() {
                 if let Some(tc) = self.toolchains[i].take() {
-                    ex.toolchains[i] = tc;
+                    tc.validate_new()?;
+                    let idx = if i == 0 { 0 } else { last };
 
                     // Ensure no duplicate toolchain is inserted
-                    if ex.toolchains[0] == ex.toolchains[1] {
+                    let duplicate = ex
+                        .toolchains
+                        .iter()
+                        .enumerate()
+                        .any(|(other, existing)| other != idx && *existing == tc);
+                    if duplicate {
                         return Err(ExperimentError::DuplicateToolchains.into());
                     }
+                    ex.toolchains[idx] = tc;
 
                     let changes = t.execute(
                         &format!("UPDATE experiments SET {} = ?1 WHERE name = ?2;", col),
-                        &[&ex.toolchains[i].to_string(), &self.name],
+                        &[&ex.toolchains[idx].to_string(), &self.name],
                     )?;
                     assert_eq!(changes, 1);
                 }
@@ -75,13 +152,59 @@ impl Action for EditExperiment {
                 ex.ignore_blacklist = ignore_blacklist;
             }
 
+            // Try to update the edition filter
+            // The list of crates will be recalculated afterwards
+            if let Some(edition) = self.edition {
+                let changes = t.execute(
+                    "UPDATE experiments SET edition = ?1 WHERE name = ?2;",
+                    &[&edition.to_str(), &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.edition = Some(edition);
+            }
+
+            // Try to update the maximum Rust version filter
+            // The list of crates will be recalculated afterwards
+            if let Some(ref version) = self.max_rust_version {
+                let changes = t.execute(
+                    "UPDATE experiments SET max_rust_version = ?1 WHERE name = ?2;",
+                    &[&version.to_string(), &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.max_rust_version = Some(version.clone());
+            }
+
             // Try to update the list of crates
             // This is also done if ignore_blacklist is changed to recalculate the skipped crates
-            let new_crates = if let Some(crates) = self.crates {
-                Some(crate::crates::lists::get_crates(
+            let new_crates = if let Some((select, crates, snapshot, seed)) = selected_crates {
+                let changes = t.execute(
+                    "UPDATE experiments SET index_snapshot = ?1, crates_seed = ?2, \
+                     crate_select = ?3 WHERE name = ?4;",
+                    &[
+                        &snapshot.as_ref().map(::serde_json::to_string).transpose()?,
+                        &seed,
+                        &select.to_string(),
+                        &self.name,
+                    ],
+                )?;
+                assert_eq!(changes, 1);
+                ex.index_snapshot = snapshot;
+                ex.crates_seed = Some(seed);
+                ex.crate_select = Some(select);
+
+                Some(metadata::apply_filters(
+                    &ctx.db,
                     crates,
+                    ex.edition,
+                    ex.max_rust_version.as_ref(),
+                )?)
+            } else if self.edition.is_some() || self.max_rust_version.is_some() {
+                // The crate selection isn't stored, so only the current list can be filtered
+                Some(metadata::apply_filters(
                     &ctx.db,
-                    &ctx.config,
+                    ex.get_crates(&ctx.db)?,
+                    self.edition,
+                    self.max_rust_version.as_ref(),
                 )?)
             } else if self.ignore_blacklist.is_some() {
                 Some(ex.get_crates(&ctx.db)?)
@@ -89,6 +212,9 @@ impl Action for EditExperiment {
                 None
             };
             if let Some(crates_vec) = new_crates {
+                let crates_metadata = metadata::load(&ctx.db, &crates_vec)?;
+                let skip_reasons = skip_reasons(&ctx.config, &crates_vec, ex.ignore_blacklist)?;
+
                 // Recreate the list of crates without checking if it was the same
                 // This is done to allow reloading the list of crates in an existing experiment
                 t.execute(
@@ -96,13 +222,20 @@ impl Action for EditExperiment {
                     &[&self.name],
                 )?;
                 for krate in &crates_vec {
+                    let skip_reason = skip_reasons.get(krate);
                     t.execute(
-                        "INSERT INTO experiment_crates (experiment, crate, skipped) \
-                         VALUES (?1, ?2, ?3);",
+                        "INSERT INTO experiment_crates \
+                         (experiment, crate, skipped, skip_reason, metadata) \
+                         VALUES (?1, ?2, ?3, ?4, ?5);",
                         &[
                             &self.name,
                             &::serde_json::to_string(&krate)?,
-                            &(!ex.ignore_blacklist && ctx.config.should_skip(krate)),
+                            &skip_reason.is_some(),
+                            &skip_reason.map(|reason| reason.to_str()),
+                            &crates_metadata
+                                .get(krate)
+                                .map(::serde_json::to_string)
+                                .transpose()?,
                         ],
                     )?;
                 }
@@ -128,6 +261,66 @@ impl Action for EditExperiment {
                 ex.cap_lints = cap_lints;
             }
 
+            // Try to update the extra targets to build
+            if let Some(build_targets) = self.build_targets {
+                let changes = t.execute(
+                    "UPDATE experiments SET build_targets = ?1 WHERE name = ?2;",
+                    &[&build_targets.to_str(), &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.build_targets = build_targets;
+            }
+
+            // Try to update the flags passed to cargo test
+            if let Some(test_flags) = self.test_flags {
+                let changes = t.execute(
+                    "UPDATE experiments SET test_flags = ?1 WHERE name = ?2;",
+                    &[&test_flags.to_string(), &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.test_flags = test_flags;
+            }
+
+            // Try to update the server the toolchains are downloaded from
+            if let Some(dist_server) = self.dist_server {
+                let changes = t.execute(
+                    "UPDATE experiments SET dist_server = ?1 WHERE name = ?2;",
+                    &[&dist_server, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.dist_server = Some(dist_server);
+            }
+
+            // Try to update the wrapper of the rustc invocations
+            if let Some(rustc_wrapper) = self.rustc_wrapper {
+                let changes = t.execute(
+                    "UPDATE experiments SET rustc_wrapper = ?1 WHERE name = ?2;",
+                    &[&rustc_wrapper.to_string(), &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.rustc_wrapper = Some(rustc_wrapper);
+            }
+
+            // Try to update whether the build timings are collected
+            if let Some(timings) = self.timings {
+                let changes = t.execute(
+                    "UPDATE experiments SET timings = ?1 WHERE name = ?2;",
+                    &[&timings, &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.timings = timings;
+            }
+
+            // Try to update the targets the crates are built for
+            if let Some(targets) = self.targets.take() {
+                let changes = t.execute(
+                    "UPDATE experiments SET targets = ?1 WHERE name = ?2;",
+                    &[&targets.to_string(), &self.name],
+                )?;
+                assert_eq!(changes, 1);
+                ex.targets = targets;
+            }
+
             // Try to update the priority
             if let Some(priority) = self.priority {
                 let changes = t.execute(
@@ -148,16 +341,33 @@ impl Action for EditExperiment {
                 ex.assigned_to = Some(assign);
             }
 
-            // Try to update the requirement
-            if let Some(requirement) = self.requirement {
+            // Try to update the requirement, keeping it in sync with the targets
+            if self.requirement.is_some() || targets_changed {
+                let requirement = match self.requirement.take() {
+                    Some(requirement) => Some(requirement),
+                    // The operating system required by the previous targets is inferred again
+                    None => ex
+                        .requirement
+                        .take()
+                        .filter(|requirement| !TARGET_OS.contains(&requirement.as_str())),
+                };
+                let requirement = requirement_for_targets(requirement, &ex.targets)?;
                 let changes = t.execute(
                     "UPDATE experiments SET requirement = ?1 WHERE name = ?2;",
-                    &[&requirement.to_string(), &self.name],
+                    &[&requirement, &self.name],
                 )?;
                 assert_eq!(changes, 1);
-                ex.requirement = Some(requirement);
+                ex.requirement = requirement;
             }
 
+            audit::record(
+                t,
+                &ctx.actor,
+                AuditAction::EditExperiment,
+                Some(&self.name),
+                &audit_payload,
+            )?;
+
             Ok(())
         })?;
         Ok(())
@@ -171,8 +381,11 @@ mod tests {
     use crate::config::{Config, CrateConfig};
     use crate::crates::Crate;
     use crate::db::{Database, QueryUtils};
-    use crate::experiments::{Assignee, CapLints, CrateSelect, Experiment, Mode, Status};
-    use crate::toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use crate::experiments::{
+        Assignee, BuildTargets, CapLints, CrateSelect, Experiment, Mode, Status, Tags, Targets,
+        TestFlags, DEFAULT_NAMESPACE,
+    };
+    use crate::toolchain::{Toolchain, MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
 
     #[test]
     fn test_edit_with_no_changes() {
@@ -197,7 +410,8 @@ mod tests {
         // Create an experiment with the data we're going to change
         CreateExperiment {
             name: "foo".to_string(),
-            toolchains: ["stable".parse().unwrap(), "beta".parse().unwrap()],
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            toolchains: vec!["stable".parse().unwrap(), "beta".parse().unwrap()],
             mode: Mode::BuildAndTest,
             crates: CrateSelect::SmallRandom,
             cap_lints: CapLints::Forbid,
@@ -206,9 +420,20 @@ mod tests {
             ignore_blacklist: false,
             assign: None,
             requirement: None,
+            edition: None,
+            max_rust_version: None,
+            build_targets: BuildTargets::Default,
+            test_flags: TestFlags::default(),
+            dist_server: None,
+            rustc_wrapper: None,
+            timings: false,
+            targets: Targets::default(),
+            tags: Tags::default(),
         }
         .apply(&ctx)
         .unwrap();
+        let seed = Experiment::get(&db, "foo").unwrap().unwrap().crates_seed;
+        assert!(seed.is_some());
 
         // Change everything!
         EditExperiment {
@@ -224,6 +449,14 @@ mod tests {
             ignore_blacklist: Some(true),
             assign: Some(Assignee::CLI),
             requirement: Some("windows".to_string()),
+            edition: None,
+            max_rust_version: None,
+            build_targets: Some(BuildTargets::All),
+            test_flags: Some("-- --test-threads=1".parse().unwrap()),
+            dist_server: Some("https://mirror.example.com/rust".to_string()),
+            rustc_wrapper: Some("time-rustc".parse().unwrap()),
+            timings: Some(true),
+            targets: Some("aarch64-pc-windows-msvc".parse().unwrap()),
         }
         .apply(&ctx)
         .unwrap();
@@ -235,14 +468,26 @@ mod tests {
         assert_eq!(ex.toolchains[1], "nightly-1970-01-02".parse().unwrap());
         assert_eq!(ex.mode, Mode::CheckOnly);
         assert_eq!(ex.cap_lints, CapLints::Warn);
+        assert_eq!(ex.build_targets, BuildTargets::All);
+        assert_eq!(ex.test_flags.args(), &["--", "--test-threads=1"]);
+        assert_eq!(
+            ex.dist_server,
+            Some("https://mirror.example.com/rust".to_string())
+        );
+        assert_eq!(ex.rustc_wrapper, Some("time-rustc".parse().unwrap()));
+        assert!(ex.timings);
+        assert_eq!(ex.targets.triples(), &["aarch64-pc-windows-msvc"]);
         assert_eq!(ex.priority, 10);
         assert_eq!(ex.ignore_blacklist, true);
         assert_eq!(ex.assigned_to, Some(Assignee::CLI));
         assert_eq!(ex.requirement, Some("windows".to_string()));
+        // The seed of the random samples is kept when the crates are selected again
+        assert_eq!(ex.crates_seed, seed);
+        assert_eq!(ex.crate_select, Some(CrateSelect::Local));
 
         assert_eq!(
             ex.get_crates(&ctx.db).unwrap(),
-            crate::crates::lists::get_crates(CrateSelect::Local, &db, &config).unwrap()
+            crate::crates::lists::get_crates(CrateSelect::Local, &db, &config, None).unwrap()
         );
     }
 
@@ -262,8 +507,8 @@ mod tests {
             crates
                 .iter()
                 .find(|c| {
-                    if let Crate::Local(name) = c {
-                        name == krate
+                    if let Crate::Local(local) = c {
+                        local.name == krate
                     } else {
                         panic!("there should be no non-local crates")
                     }
@@ -280,6 +525,7 @@ mod tests {
                 skip_tests: false,
                 quiet: false,
                 broken: false,
+                known_broken: None,
             },
         );
         let ctx = ActionsCtx::new(&db, &config);
@@ -320,18 +566,36 @@ mod tests {
         crate::crates::lists::setup_test_lists(&db, &config).unwrap();
 
         // First create an experiment
+        let intermediate: Toolchain = "nightly-1970-01-01".parse().unwrap();
         let mut dummy = CreateExperiment::dummy("foo");
-        dummy.toolchains = [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()];
+        dummy.toolchains = vec![
+            MAIN_TOOLCHAIN.clone(),
+            intermediate.clone(),
+            TEST_TOOLCHAIN.clone(),
+        ];
         dummy.apply(&ctx).unwrap();
 
-        // Then try to switch the second toolchain to MAIN_TOOLCHAIN
-        let mut edit = EditExperiment::dummy("foo");
-        edit.toolchains[1] = Some(MAIN_TOOLCHAIN.clone());
+        // Then try to switch the second toolchain to MAIN_TOOLCHAIN or the intermediate one
+        for tc in &[MAIN_TOOLCHAIN.clone(), intermediate.clone()] {
+            let mut edit = EditExperiment::dummy("foo");
+            edit.toolchains[1] = Some(tc.clone());
+
+            let err = edit.apply(&ctx).unwrap_err();
+            assert_eq!(
+                err.downcast_ref(),
+                Some(&ExperimentError::DuplicateToolchains)
+            );
+        }
 
-        let err = edit.apply(&ctx).unwrap_err();
+        // The end toolchain is the last one, after the intermediate toolchains
+        let end: Toolchain = "nightly-1970-01-02".parse().unwrap();
+        let mut edit = EditExperiment::dummy("foo");
+        edit.toolchains[1] = Some(end.clone());
+        edit.apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
         assert_eq!(
-            err.downcast_ref(),
-            Some(&ExperimentError::DuplicateToolchains)
+            ex.toolchains,
+            vec![MAIN_TOOLCHAIN.clone(), intermediate, end]
         );
     }
 
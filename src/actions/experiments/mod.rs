@@ -1,10 +1,31 @@
+mod archive;
+mod bisect;
+mod cancel;
+mod clone;
 mod create;
 mod delete;
 mod edit;
+mod regenerate_report;
+mod rename;
+mod tag;
 
-pub use self::create::CreateExperiment;
+pub use self::archive::ArchiveExperiment;
+pub use self::bisect::{advance_bisection, BisectRegression, BisectionStep};
+pub use self::cancel::CancelExperiment;
+pub use self::clone::CloneExperiment;
+pub use self::create::{CreateExperiment, ExperimentPreview};
 pub use self::delete::DeleteExperiment;
 pub use self::edit::EditExperiment;
+pub use self::regenerate_report::RegenerateReport;
+pub use self::rename::RenameExperiment;
+pub use self::tag::TagExperiment;
+
+use crate::config::Config;
+use crate::crates::{lists::unavailable_crates, Crate, IndexSnapshot, TARGET_OS};
+use crate::db::Database;
+use crate::experiments::{CrateSelect, CrateSkipReason, Experiment, Targets};
+use crate::prelude::*;
+use std::collections::HashMap;
 
 #[derive(Debug, failure::Fail)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
@@ -13,8 +34,197 @@ pub enum ExperimentError {
     NotFound(String),
     #[fail(display = "experiment '{}' already exists", _0)]
     AlreadyExists(String),
+    #[fail(display = "invalid namespace name: {}", _0)]
+    InvalidNamespace(String),
+    #[fail(display = "experiment names can't contain `:`: {}", _0)]
+    InvalidName(String),
+    #[fail(
+        display = "new experiments can't be created during maintenance: {}",
+        _0
+    )]
+    Maintenance(String),
     #[fail(display = "duplicate toolchains provided")]
     DuplicateToolchains,
+    #[fail(display = "at least two toolchains must be compared")]
+    MissingToolchains,
+    #[fail(display = "the first bad nightly must be released after the last good one")]
+    InvalidBisectionRange,
+    #[fail(display = "nightly-{} wasn't published", _0)]
+    UnpublishedNightly(chrono::NaiveDate),
+    #[fail(
+        display = "the targets can only be built by {} agents, not by {} ones",
+        _1, _0
+    )]
+    RequirementMismatch(String, String),
+    #[fail(display = "the targets can't be built by agents running the same operating system")]
+    TargetsOnMultipleOs,
     #[fail(display = "it's only possible to edit queued experiments")]
     CanOnlyEditQueuedExperiments,
+    #[fail(display = "it's only possible to archive completed, cancelled or failed experiments")]
+    CanOnlyArchiveFinishedExperiments,
+    #[fail(display = "it's only possible to cancel queued or running experiments")]
+    CanOnlyCancelUnfinishedExperiments,
+    #[fail(
+        display = "it's not possible to rename running or archived experiments, or while their \
+                   report is being generated"
+    )]
+    CanOnlyRenameIdleExperiments,
+    #[fail(display = "it's only possible to regenerate the report of completed experiments")]
+    CanOnlyRegenerateFinishedReports,
+}
+
+/// Get the requirement of an experiment built for the targets, as agents can only build for some
+/// targets from their own operating system. The operating system is required when `requirement`
+/// isn't set explicitly.
+fn requirement_for_targets(
+    requirement: Option<String>,
+    targets: &Targets,
+) -> Result<Option<String>, ExperimentError> {
+    match (targets.required_os().as_slice(), requirement) {
+        (&[], requirement) => Ok(requirement),
+        (&[os], None) => Ok(Some(os.to_string())),
+        (&[os], Some(requirement)) => {
+            if requirement != *os && TARGET_OS.contains(&requirement.as_str()) {
+                Err(ExperimentError::RequirementMismatch(
+                    requirement,
+                    os.to_string(),
+                ))
+            } else {
+                Ok(Some(requirement))
+            }
+        }
+        _ => Err(ExperimentError::TargetsOnMultipleOs),
+    }
+}
+
+/// Get why the crates of an experiment shouldn't be tested, for the ones that shouldn't.
+fn skip_reasons(
+    config: &Config,
+    crates: &[Crate],
+    ignore_blacklist: bool,
+) -> Fallible<HashMap<Crate, CrateSkipReason>> {
+    let mut reasons = unavailable_crates(crates)?;
+    if !ignore_blacklist {
+        for krate in crates.iter().filter(|krate| config.should_skip(krate)) {
+            reasons
+                .entry(krate.clone())
+                .or_insert(CrateSkipReason::Blacklisted);
+        }
+    }
+    Ok(reasons)
+}
+
+/// Get the snapshot of the crates.io index the registry crates of an experiment come from.
+fn index_snapshot(
+    db: &Database,
+    select: &CrateSelect,
+    crates: &[Crate],
+) -> Fallible<Option<IndexSnapshot>> {
+    if !crates.iter().any(|krate| match krate {
+        Crate::Registry(_) => true,
+        _ => false,
+    }) {
+        return Ok(None);
+    }
+
+    // The regressed or copied crates come from another experiment, not from the current list
+    match select {
+        CrateSelect::Regressed(source) | CrateSelect::SameAs(source) => {
+            if let Some(source) = Experiment::get(db, source)? {
+                return Ok(source.index_snapshot);
+            }
+        }
+        _ => {}
+    }
+    IndexSnapshot::load(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{index_snapshot, requirement_for_targets, ExperimentError};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::crates::{Crate, IndexSnapshot, RegistryCrate};
+    use crate::db::Database;
+    use crate::experiments::CrateSelect;
+    use chrono::Utc;
+
+    #[test]
+    fn test_requirement_for_targets() {
+        let check = |requirement: Option<&str>, targets: &str| {
+            requirement_for_targets(requirement.map(String::from), &targets.parse().unwrap())
+        };
+
+        // Targets buildable from any agent don't change the requirement
+        assert_eq!(check(None, ""), Ok(None));
+        assert_eq!(
+            check(Some("linux"), "wasm32-unknown-unknown"),
+            Ok(Some("linux".into()))
+        );
+
+        assert_eq!(
+            check(None, "x86_64-pc-windows-msvc,i686-pc-windows-msvc"),
+            Ok(Some("windows".into()))
+        );
+        assert_eq!(
+            check(None, "aarch64-apple-darwin,wasm32-unknown-unknown"),
+            Ok(Some("macos".into()))
+        );
+        assert_eq!(
+            check(Some("big-disk"), "x86_64-pc-windows-msvc"),
+            Ok(Some("big-disk".into()))
+        );
+        assert_eq!(
+            check(Some("linux"), "x86_64-pc-windows-msvc"),
+            Err(ExperimentError::RequirementMismatch(
+                "linux".into(),
+                "windows".into()
+            ))
+        );
+        assert_eq!(
+            check(None, "x86_64-unknown-linux-gnu,x86_64-pc-windows-msvc"),
+            Err(ExperimentError::TargetsOnMultipleOs)
+        );
+    }
+
+    #[test]
+    fn test_index_snapshot() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let registry = vec![Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0.0".into(),
+        })];
+        let local = vec![Crate::Local("beta-regression".into())];
+
+        // Nothing is known before the registry list is generated
+        assert_eq!(
+            index_snapshot(&db, &CrateSelect::Full, &registry).unwrap(),
+            None
+        );
+
+        let snapshot = IndexSnapshot {
+            commit: "0123456789abcdef0123456789abcdef01234567".into(),
+            date: Utc::now(),
+        };
+        snapshot.store(&db).unwrap();
+        assert_eq!(
+            index_snapshot(&db, &CrateSelect::Full, &registry).unwrap(),
+            Some(snapshot)
+        );
+        assert_eq!(
+            index_snapshot(&db, &CrateSelect::Local, &local).unwrap(),
+            None
+        );
+
+        // The regressed crates come from the parent experiment, which has no registry crates
+        CreateExperiment::dummy("parent").apply(&ctx).unwrap();
+        assert_eq!(
+            index_snapshot(&db, &CrateSelect::Regressed("parent".into()), &registry).unwrap(),
+            None
+        );
+    }
 }
@@ -0,0 +1,83 @@
+use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::audit::{self, AuditAction};
+use crate::db::QueryUtils;
+use crate::experiments::{Experiment, Status};
+use crate::prelude::*;
+use serde_json::json;
+
+/// Queue the report of a finished experiment to be generated again from the stored results,
+/// picking up the changes to the report generator since it was published.
+pub struct RegenerateReport {
+    pub name: String,
+}
+
+impl Action for RegenerateReport {
+    fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
+        let ex = match Experiment::get(&ctx.db, &self.name)? {
+            Some(ex) => ex,
+            None => return Err(ExperimentError::NotFound(self.name).into()),
+        };
+
+        match ex.status {
+            Status::Completed | Status::ReportFailed => {}
+            _ => return Err(ExperimentError::CanOnlyRegenerateFinishedReports.into()),
+        }
+
+        ctx.db.transaction(|t| {
+            t.execute(
+                "UPDATE experiments SET status = ?1 WHERE name = ?2;",
+                &[&Status::NeedsReport.to_str(), &self.name.as_str()],
+            )?;
+
+            audit::record(
+                t,
+                &ctx.actor,
+                AuditAction::RegenerateReport,
+                Some(&self.name),
+                &json!({ "previous-status": ex.status }),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegenerateReport;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment, ExperimentError};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::{Experiment, Status};
+
+    #[test]
+    fn test_regenerate_report() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+
+        // Experiments that didn't run yet have no results to generate the report from
+        let err = RegenerateReport {
+            name: "dummy".to_string(),
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::CanOnlyRegenerateFinishedReports)
+        );
+
+        let mut ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        ex.set_status(&db, Status::Completed).unwrap();
+
+        RegenerateReport {
+            name: "dummy".to_string(),
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.status, Status::NeedsReport);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::audit::{self, AuditAction};
+use crate::db::QueryUtils;
+use crate::experiments::{qualified_name, Experiment, Status};
+use crate::prelude::*;
+use serde_json::json;
+
+/// Tables referencing the experiments by name, all updated together when renaming one.
+static EXPERIMENT_TABLES: &[&str] = &[
+    "experiment_crates",
+    "results",
+    "shas",
+    "member_results",
+    "failed_targets",
+    "toolchain_versions",
+    "diagnostics",
+    "diagnostic_counts",
+    "unit_timings",
+    "doc_items",
+    "invocations",
+    "resource_usage",
+    "snapshots",
+    "c_compilers",
+    "lockfiles",
+    "saved_names",
+    "bisections",
+    "audit_log",
+];
+
+pub struct RenameExperiment {
+    pub name: String,
+    /// New name of the experiment, inside the same namespace.
+    pub new_name: String,
+}
+
+impl Action for RenameExperiment {
+    fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
+        let ex = match Experiment::get(&ctx.db, &self.name)? {
+            Some(ex) => ex,
+            None => return Err(ExperimentError::NotFound(self.name).into()),
+        };
+
+        // Agents and the reports generator refer to the experiment by name while working on it
+        match ex.status {
+            Status::Running | Status::GeneratingReport | Status::Archived => {
+                return Err(ExperimentError::CanOnlyRenameIdleExperiments.into());
+            }
+            _ => {}
+        }
+
+        // The namespace separator is reserved, to avoid clashes between namespaces
+        if self.new_name.contains(':') {
+            return Err(ExperimentError::InvalidName(self.new_name.clone()).into());
+        }
+        let new_name = qualified_name(&ex.namespace, &self.new_name);
+        if Experiment::exists(&ctx.db, &new_name)? {
+            return Err(ExperimentError::AlreadyExists(new_name).into());
+        }
+
+        // Reports are uploaded under the name of the experiment, so a published report has to be
+        // generated again to be available at the new path
+        let regenerate_report = ex.status == Status::Completed && ex.report_url.is_some();
+
+        ctx.db.transaction(|t| {
+            // The foreign keys are only checked at the end of the transaction, after both the
+            // experiment and the rows referencing it are renamed
+            t.execute("PRAGMA defer_foreign_keys = ON;", &[])?;
+
+            t.execute(
+                "UPDATE experiments SET name = ?1 WHERE name = ?2;",
+                &[&new_name, &self.name],
+            )?;
+            for table in EXPERIMENT_TABLES {
+                t.execute(
+                    &format!(
+                        "UPDATE {} SET experiment = ?1 WHERE experiment = ?2;",
+                        table
+                    ),
+                    &[&new_name, &self.name],
+                )?;
+            }
+
+            if regenerate_report {
+                t.execute(
+                    "UPDATE experiments SET status = ?1, report_url = NULL WHERE name = ?2;",
+                    &[&Status::NeedsReport.to_str(), &new_name.as_str()],
+                )?;
+            }
+
+            audit::record(
+                t,
+                &ctx.actor,
+                AuditAction::RenameExperiment,
+                Some(&new_name),
+                &json!({ "previous-name": self.name }),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RenameExperiment;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment, ExperimentError};
+    use crate::config::Config;
+    use crate::db::{Database, QueryUtils};
+    use crate::experiments::{Experiment, Status};
+    use crate::results::{DatabaseDB, EncodingType, FailureReason, TestResult, WriteResults};
+
+    #[test]
+    fn test_rename_experiment() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        CreateExperiment::dummy("other").apply(&ctx).unwrap();
+
+        let mut ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let krate = ex.get_crates(&db).unwrap().pop().unwrap();
+        let results = DatabaseDB::new(&db);
+        results
+            .store_result(
+                &ex,
+                &krate,
+                &ex.toolchains[0],
+                TestResult::BuildFail(FailureReason::Unknown),
+                b"log",
+                EncodingType::Plain,
+            )
+            .unwrap();
+        ex.set_status(&db, Status::Completed).unwrap();
+        ex.set_report_url(&db, "https://example.com/dummy/index.html")
+            .unwrap();
+
+        // The new name can't be taken by another experiment
+        let err = RenameExperiment {
+            name: "dummy".into(),
+            new_name: "other".into(),
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::AlreadyExists("other".into()))
+        );
+
+        RenameExperiment {
+            name: "dummy".into(),
+            new_name: "renamed".into(),
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        assert!(!Experiment::exists(&db, "dummy").unwrap());
+        let ex = Experiment::get(&db, "renamed").unwrap().unwrap();
+        assert_eq!(ex.status, Status::NeedsReport);
+        assert!(ex.report_url.is_none());
+
+        // The crates and the results follow the experiment
+        assert!(ex.get_crates(&db).unwrap().contains(&krate));
+        assert_eq!(
+            results.get_result(&ex, &ex.toolchains[0], &krate).unwrap(),
+            Some(TestResult::BuildFail(FailureReason::Unknown))
+        );
+        let leftover: i64 = db
+            .get_row(
+                "SELECT COUNT(*) FROM experiment_crates WHERE experiment = ?1;",
+                &["dummy"],
+                |r| r.get(0),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(leftover, 0);
+    }
+
+    #[test]
+    fn test_rename_running_experiment() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let mut ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        ex.set_status(&db, Status::Running).unwrap();
+
+        let err = RenameExperiment {
+            name: "dummy".into(),
+            new_name: "renamed".into(),
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::CanOnlyRenameIdleExperiments)
+        );
+        assert!(Experiment::exists(&db, "dummy").unwrap());
+    }
+
+    #[test]
+    fn test_rename_missing_experiment() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        let err = RenameExperiment {
+            name: "dummy".into(),
+            new_name: "renamed".into(),
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::NotFound("dummy".into()))
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use crate::actions::{experiments::ExperimentError, Action, ActionsCtx};
+use crate::audit::{self, AuditAction};
+use crate::db::QueryUtils;
+use crate::experiments::{Experiment, Tags};
+use crate::prelude::*;
+use serde_json::json;
+
+/// Replace the tags of an experiment. Tags don't affect how the experiment runs, so they can be
+/// changed whatever its status.
+pub struct TagExperiment {
+    pub name: String,
+    pub tags: Tags,
+}
+
+impl Action for TagExperiment {
+    fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
+        let ex = match Experiment::get(&ctx.db, &self.name)? {
+            Some(ex) => ex,
+            None => return Err(ExperimentError::NotFound(self.name).into()),
+        };
+
+        ctx.db.transaction(|t| {
+            t.execute(
+                "UPDATE experiments SET tags = ?1 WHERE name = ?2;",
+                &[&self.tags.to_string(), &self.name],
+            )?;
+
+            audit::record(
+                t,
+                &ctx.actor,
+                AuditAction::TagExperiment,
+                Some(&self.name),
+                &json!({ "previous-tags": ex.tags, "tags": self.tags }),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TagExperiment;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment, ExperimentError};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::{Experiment, Status};
+
+    #[test]
+    fn test_tag_experiment() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment {
+            tags: "team:libs".parse().unwrap(),
+            ..CreateExperiment::dummy("dummy")
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        // Completed experiments can be tagged too
+        let mut ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        ex.set_status(&db, Status::Completed).unwrap();
+
+        TagExperiment {
+            name: "dummy".into(),
+            tags: "release:1.78,team:compiler".parse().unwrap(),
+        }
+        .apply(&ctx)
+        .unwrap();
+
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.tags.names(), &["release:1.78", "team:compiler"]);
+
+        let err = TagExperiment {
+            name: "missing".into(),
+            tags: "release:1.78".parse().unwrap(),
+        }
+        .apply(&ctx)
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&ExperimentError::NotFound("missing".into()))
+        );
+    }
+}
@@ -1,3 +1,5 @@
 mod update;
+mod upload;
 
 pub use self::update::UpdateLists;
+pub use self::upload::UploadCrateList;
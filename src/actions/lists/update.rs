@@ -1,11 +1,16 @@
 use crate::actions::{Action, ActionsCtx};
 use crate::crates::lists::{GitHubList, List, LocalList, RegistryList};
+use crate::crates::metadata;
 use crate::prelude::*;
 
 pub struct UpdateLists {
     pub github: bool,
     pub registry: bool,
     pub local: bool,
+    /// Commit of the crates.io index the registry list is generated from, instead of the latest.
+    pub registry_snapshot: Option<String>,
+    /// Also load the manifest metadata of the crates in the updated lists.
+    pub metadata: bool,
 }
 
 impl Default for UpdateLists {
@@ -14,6 +19,8 @@ impl Default for UpdateLists {
             github: true,
             registry: true,
             local: true,
+            registry_snapshot: None,
+            metadata: false,
         }
     }
 }
@@ -27,7 +34,19 @@ impl Action for UpdateLists {
 
         if self.registry {
             info!("updating crates.io crates list");
-            RegistryList.update(&ctx.db)?;
+            let list = match self.registry_snapshot {
+                Some(ref commit) => RegistryList::at_snapshot(commit),
+                None => RegistryList::default(),
+            };
+            list.update(&ctx.db)?;
+
+            // Record the snapshot, to allow generating the same list again in the future
+            let snapshot = list.index_snapshot()?;
+            info!(
+                "crates.io list generated from index commit {}",
+                snapshot.commit
+            );
+            snapshot.store(&ctx.db)?;
         }
 
         if self.local {
@@ -35,6 +54,21 @@ impl Action for UpdateLists {
             LocalList::default().update(&ctx.db)?;
         }
 
+        if self.metadata {
+            info!("loading the manifest metadata of the crates");
+            let mut crates = Vec::new();
+            if self.github {
+                crates.append(&mut GitHubList::get(&ctx.db)?);
+            }
+            if self.registry {
+                crates.append(&mut RegistryList::get(&ctx.db)?);
+            }
+            if self.local {
+                crates.append(&mut LocalList::get(&ctx.db)?);
+            }
+            metadata::update(&ctx.db, &crates)?;
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,21 @@
+use crate::actions::{Action, ActionsCtx};
+use crate::crates::lists;
+use crate::experiments::DEFAULT_NAMESPACE;
+use crate::prelude::*;
+
+/// Store an explicit list of crates, which experiments can then select with `list:<name>`.
+pub struct UploadCrateList {
+    pub name: String,
+    /// One crate for each line, either a GitHub repository URL, a registry crate name or a
+    /// `<name>-<version>` pair.
+    pub list: String,
+}
+
+impl Action for UploadCrateList {
+    fn apply(self, ctx: &ActionsCtx) -> Fallible<()> {
+        let crates = lists::parse_crate_list(&ctx.db, &self.list)?;
+        lists::store_crate_list(&ctx.db, DEFAULT_NAMESPACE, &self.name, &crates)?;
+        info!("stored {} crates in the list {}", crates.len(), self.name);
+        Ok(())
+    }
+}
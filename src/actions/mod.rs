@@ -4,6 +4,7 @@ mod lists;
 pub use self::experiments::*;
 pub use self::lists::*;
 
+use crate::audit::Actor;
 use crate::config::Config;
 use crate::db::Database;
 use crate::prelude::*;
@@ -15,10 +16,21 @@ pub trait Action {
 pub struct ActionsCtx<'ctx> {
     db: &'ctx Database,
     config: &'ctx Config,
+    actor: Actor,
 }
 
 impl<'ctx> ActionsCtx<'ctx> {
     pub fn new(db: &'ctx Database, config: &'ctx Config) -> Self {
-        ActionsCtx { db, config }
+        ActionsCtx {
+            db,
+            config,
+            actor: Actor::CLI,
+        }
+    }
+
+    /// Set who is applying the actions, to record it in the audit log.
+    pub fn with_actor(mut self, actor: Actor) -> Self {
+        self.actor = actor;
+        self
     }
 }
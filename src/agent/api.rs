@@ -1,16 +1,27 @@
-use crate::agent::Capabilities;
+use crate::agent::{AgentWorkers, Capabilities};
 use crate::crates::{Crate, GitHubRepo};
 use crate::experiments::Experiment;
 use crate::prelude::*;
-use crate::results::TestResult;
-use crate::server::api_types::{AgentConfig, ApiResponse, CraterToken};
-use crate::toolchain::Toolchain;
+use crate::results::{
+    Diagnostic, DiagnosticCounts, DocItem, FailedTarget, Invocation, MemberResult, ResourceUsage,
+    TestResult, UnitTiming,
+};
+use crate::server::api_types::{
+    AgentConfig, ApiResponse, CraterToken, NextExperiment, AGENT_VERSION_HEADER,
+};
+use crate::toolchain::{Toolchain, ToolchainInstallError, ToolchainVersion};
 use crate::utils;
 use base64;
-use http::{header::AUTHORIZATION, Method, StatusCode};
-use reqwest::RequestBuilder;
+use http::{
+    header::{AUTHORIZATION, USER_AGENT},
+    Method, StatusCode,
+};
+use reqwest::{Client, Identity, RequestBuilder};
 use serde::de::DeserializeOwned;
 use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 #[derive(Debug, Fail)]
 pub enum AgentApiError {
@@ -24,6 +35,13 @@ pub enum AgentApiError {
     InvalidAuthorizationToken,
     #[fail(display = "internal server error: {}", _0)]
     InternalServerError(String),
+    #[fail(display = "bad request: {}", _0)]
+    BadRequest(String),
+    #[fail(
+        display = "this agent is outdated: the server requires at least version {}",
+        _0
+    )]
+    OutdatedAgent(String),
 }
 
 trait ResponseExt {
@@ -55,35 +73,85 @@ impl ResponseExt for ::reqwest::Response {
             ApiResponse::InternalError { error } => {
                 Err(AgentApiError::InternalServerError(error).into())
             }
+            ApiResponse::BadRequest { error } => Err(AgentApiError::BadRequest(error).into()),
             ApiResponse::Unauthorized => Err(AgentApiError::InvalidAuthorizationToken.into()),
             ApiResponse::NotFound => Err(AgentApiError::InvalidEndpoint.into()),
+            ApiResponse::UpgradeRequired { minimum_version } => {
+                Err(AgentApiError::OutdatedAgent(minimum_version).into())
+            }
         }
     }
 }
 
-const RETRY_AFTER: u64 = 5;
+pub(super) const RETRY_AFTER: u64 = 5;
+
+/// How the agent authenticates with the Crater server.
+#[derive(Clone)]
+pub enum Credentials {
+    Token(String),
+    /// PKCS#12 archive containing the client certificate and its private key. The agent name is
+    /// derived by the server from the CN of the certificate.
+    ClientCertificate { path: PathBuf, password: String },
+}
 
 pub struct AgentApi {
     url: String,
-    token: String,
+    credentials: Credentials,
+    cert_client: Mutex<Option<(SystemTime, Client)>>,
 }
 
 impl AgentApi {
-    pub fn new(url: &str, token: &str) -> Self {
+    pub fn new(url: &str, credentials: &Credentials) -> Self {
         AgentApi {
             url: url.to_string(),
-            token: token.to_string(),
+            credentials: credentials.clone(),
+            cert_client: Mutex::new(None),
         }
     }
 
-    fn build_request(&self, method: Method, url: &str) -> RequestBuilder {
-        utils::http::prepare_sync(method, &format!("{}/agent-api/{}", self.url, url)).header(
-            AUTHORIZATION,
-            (CraterToken {
-                token: self.token.clone(),
-            })
-            .to_string(),
-        )
+    fn build_request(&self, method: Method, url: &str) -> Fallible<RequestBuilder> {
+        let url = format!("{}/agent-api/{}", self.url, url);
+        let builder = match self.credentials {
+            Credentials::Token(ref token) => utils::http::prepare_sync(method, &url).header(
+                AUTHORIZATION,
+                (CraterToken {
+                    token: token.clone(),
+                })
+                .to_string(),
+            ),
+            Credentials::ClientCertificate {
+                ref path,
+                ref password,
+            } => self
+                .cert_client(path, password)?
+                .request(method, &url)
+                .header(USER_AGENT, crate::USER_AGENT.clone()),
+        };
+        Ok(builder.header(AGENT_VERSION_HEADER, crate::CRATER_VERSION))
+    }
+
+    /// Return the HTTP client presenting the client certificate, loading the certificate again
+    /// if the file changed since the last request. This allows rotating the certificate without
+    /// restarting the agent.
+    fn cert_client(&self, path: &Path, password: &str) -> Fallible<Client> {
+        let modified = std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .with_context(|_| format!("failed to read {}", path.display()))?;
+
+        let mut cache = self.cert_client.lock().unwrap();
+        if let Some((loaded, ref client)) = *cache {
+            if loaded == modified {
+                return Ok(client.clone());
+            }
+        }
+
+        info!("loading the client certificate from {}", path.display());
+        let identity = Identity::from_pkcs12_der(&std::fs::read(path)?, password)
+            .with_context(|_| format!("failed to load the certificate {}", path.display()))?;
+        let client = utils::http::client_builder().identity(identity).build()?;
+
+        *cache = Some((modified, client.clone()));
+        Ok(client)
     }
 
     fn retry<T, F: Fn(&Self) -> Fallible<T>>(&self, f: F) -> Fallible<T> {
@@ -123,25 +191,19 @@ impl AgentApi {
 
     pub fn config(&self, caps: &Capabilities) -> Fallible<AgentConfig> {
         self.retry(|this| {
-            this.build_request(Method::POST, "config")
+            this.build_request(Method::POST, "config")?
                 .json(&json!(caps))
                 .send()?
                 .to_api_response()
         })
     }
 
-    pub fn next_experiment(&self) -> Fallible<(Experiment, Vec<Crate>)> {
-        self.retry(|this| loop {
-            let resp: Option<_> = this
-                .build_request(Method::GET, "next-experiment")
+    pub fn next_experiment(&self, workers: &AgentWorkers) -> Fallible<Option<NextExperiment>> {
+        self.retry(|this| {
+            this.build_request(Method::POST, "next-experiment")?
+                .json(workers)
                 .send()?
-                .to_api_response()?;
-
-            if let Some((experiment, crates)) = resp {
-                return Ok((experiment, crates));
-            }
-
-            ::std::thread::sleep(::std::time::Duration::from_secs(RETRY_AFTER));
+                .to_api_response()
         })
     }
 
@@ -152,11 +214,23 @@ impl AgentApi {
         toolchain: &Toolchain,
         log: &[u8],
         result: TestResult,
+        members: &[MemberResult],
+        failed_targets: &[FailedTarget],
+        diagnostics: &[Diagnostic],
+        diagnostic_counts: Option<DiagnosticCounts>,
+        unit_timings: &[UnitTiming],
+        doc_items: &[DocItem],
+        invocations: &[Invocation],
+        resource_usage: Option<ResourceUsage>,
+        snapshot: Option<&[u8]>,
+        c_compiler: Option<&str>,
+        lockfile: Option<&str>,
         shas: &[(GitHubRepo, String)],
+        toolchain_versions: &[(Toolchain, ToolchainVersion)],
     ) -> Fallible<()> {
         self.retry(|this| {
             let _: bool = this
-                .build_request(Method::POST, "record-progress")
+                .build_request(Method::POST, "record-progress")?
                 .json(&json!({
                     "experiment-name": ex.name,
                     "results": [
@@ -165,9 +239,21 @@ impl AgentApi {
                             "toolchain": toolchain,
                             "result": result,
                             "log": base64::encode(log),
+                            "members": members,
+                            "failed-targets": failed_targets,
+                            "diagnostics": diagnostics,
+                            "diagnostic-counts": diagnostic_counts,
+                            "unit-timings": unit_timings,
+                            "doc-items": doc_items,
+                            "invocations": invocations,
+                            "resource-usage": resource_usage,
+                            "snapshot": snapshot.map(base64::encode),
+                            "c-compiler": c_compiler,
+                            "lockfile": lockfile,
                         },
                     ],
                     "shas": shas,
+                    "toolchain-versions": toolchain_versions,
                 }))
                 .send()?
                 .to_api_response()?;
@@ -178,7 +264,7 @@ impl AgentApi {
     pub fn heartbeat(&self) -> Fallible<()> {
         self.retry(|this| {
             let _: bool = this
-                .build_request(Method::POST, "heartbeat")
+                .build_request(Method::POST, "heartbeat")?
                 .send()?
                 .to_api_response()?;
             Ok(())
@@ -188,7 +274,7 @@ impl AgentApi {
     pub fn report_error(&self, ex: &Experiment, error: String) -> Fallible<()> {
         self.retry(|this| {
             let _: bool = this
-                .build_request(Method::POST, "error")
+                .build_request(Method::POST, "error")?
                 .json(&json!({
                     "experiment-name": ex.name,
                     "error": error
@@ -198,4 +284,49 @@ impl AgentApi {
             Ok(())
         })
     }
+
+    pub fn report_worker_crash(&self, ex: &Experiment, worker: &str, error: &str) -> Fallible<()> {
+        self.retry(|this| {
+            let _: bool = this
+                .build_request(Method::POST, "worker-crash")?
+                .json(&json!({
+                    "experiment-name": ex.name,
+                    "worker": worker,
+                    "error": error,
+                }))
+                .send()?
+                .to_api_response()?;
+            Ok(())
+        })
+    }
+
+    /// Check whether the experiment was cancelled, in which case the agent should stop running it.
+    pub fn is_cancelled(&self, ex: &Experiment) -> Fallible<bool> {
+        self.retry(|this| {
+            this.build_request(Method::POST, "experiment-cancelled")?
+                .json(&json!({ "experiment-name": ex.name }))
+                .send()?
+                .to_api_response()
+        })
+    }
+
+    pub fn report_toolchain_install_error(
+        &self,
+        ex: &Experiment,
+        error: &ToolchainInstallError,
+    ) -> Fallible<()> {
+        self.retry(|this| {
+            let _: bool = this
+                .build_request(Method::POST, "error")?
+                .json(&json!({
+                    "experiment-name": ex.name,
+                    "error": error.error,
+                    "toolchain": error.toolchain,
+                    "log": error.log,
+                }))
+                .send()?
+                .to_api_response()?;
+            Ok(())
+        })
+    }
 }
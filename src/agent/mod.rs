@@ -1,22 +1,35 @@
 mod api;
 mod results;
 
-use crate::agent::api::AgentApi;
+pub use crate::agent::api::Credentials;
+
+use crate::agent::api::{AgentApi, AgentApiError, RETRY_AFTER};
 use crate::agent::results::ResultsUploader;
 use crate::config::Config;
-use crate::crates::Crate;
+use crate::crates::TARGET_OS;
 use crate::db::{Database, QueryUtils};
-use crate::experiments::Experiment;
+use crate::experiments::{Experiment, Targets};
 use crate::prelude::*;
+use crate::runner::Workers;
+use crate::server::api_types::NextExperiment;
+use crate::toolchain::ToolchainInstallError;
 use crate::utils;
+use crossbeam_utils::thread::scope;
 use failure::Error;
 use rustwide::Workspace;
 use std::collections::BTreeSet;
 use std::iter::FromIterator;
 use std::ops;
+use std::sync::{mpsc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+/// Prefix of the capabilities advertising the targets an agent can build for.
+const TARGET_CAPABILITY_PREFIX: &str = "target:";
+
+/// Number of seconds between the heartbeats the agents send to the server.
+pub const HEARTBEAT_INTERVAL: u64 = 60;
+
 #[derive(Default, Serialize, Deserialize)]
 pub struct Capabilities {
     #[serde(default)]
@@ -52,6 +65,44 @@ impl Capabilities {
 
         Ok(caps.into_iter().collect())
     }
+
+    /// Operating system the agent runs on, if it's declared among its capabilities.
+    pub fn target_os(&self) -> Option<&str> {
+        self.capabilities
+            .iter()
+            .map(|cap| cap.as_str())
+            .find(|cap| TARGET_OS.contains(cap))
+    }
+
+    /// Add the capabilities advertising that the agent can build for the target triples.
+    pub fn add_targets<'a>(&mut self, triples: impl IntoIterator<Item = &'a String>) {
+        for triple in triples {
+            self.capabilities
+                .insert(format!("{}{}", TARGET_CAPABILITY_PREFIX, triple));
+        }
+    }
+
+    /// Whether the agent can build for all the targets. Agents not advertising any target are
+    /// assumed to be able to build for every target their operating system supports.
+    pub fn supports_targets(&self, targets: &Targets) -> bool {
+        let advertised = self
+            .capabilities
+            .iter()
+            .filter_map(|cap| {
+                if cap.starts_with(TARGET_CAPABILITY_PREFIX) {
+                    Some(&cap[TARGET_CAPABILITY_PREFIX.len()..])
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        advertised.is_empty()
+            || targets
+                .triples()
+                .iter()
+                .all(|triple| advertised.contains(&triple.as_str()))
+    }
 }
 
 impl FromIterator<String> for Capabilities {
@@ -64,16 +115,90 @@ impl FromIterator<String> for Capabilities {
     }
 }
 
+/// Workers of an agent, sent to the server when asking for a new experiment to run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AgentWorkers {
+    pub total: usize,
+    /// Workers not running any experiment.
+    pub idle: usize,
+    /// Names of the experiments the agent is running with the other workers.
+    pub running: Vec<String>,
+}
+
+/// Experiment every worker of the agent is running, if any.
+struct WorkerSlots {
+    slots: Mutex<Vec<Option<String>>>,
+}
+
+impl WorkerSlots {
+    fn new(count: usize) -> Self {
+        WorkerSlots {
+            slots: Mutex::new(vec![None; count]),
+        }
+    }
+
+    fn status(&self) -> AgentWorkers {
+        let slots = self.slots.lock().unwrap();
+        let mut running = slots.iter().filter_map(|ex| ex.clone()).collect::<Vec<_>>();
+        running.sort();
+        running.dedup();
+        AgentWorkers {
+            total: slots.len(),
+            idle: slots.iter().filter(|ex| ex.is_none()).count(),
+            running,
+        }
+    }
+
+    /// Reserve up to `count` idle workers to run the experiment.
+    fn claim(&self, ex: &str, count: usize) -> ClaimedWorkers {
+        let mut slots = self.slots.lock().unwrap();
+        let indexes = slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.is_none())
+            .map(|(index, _)| index)
+            .take(count)
+            .collect::<Vec<_>>();
+        for &index in &indexes {
+            slots[index] = Some(ex.to_string());
+        }
+        ClaimedWorkers {
+            slots: self,
+            indexes,
+        }
+    }
+
+    fn release(&self, indexes: &[usize]) {
+        let mut slots = self.slots.lock().unwrap();
+        for &index in indexes {
+            slots[index] = None;
+        }
+    }
+}
+
+/// Workers reserved for an experiment, released once it finishes even if its thread panicked.
+struct ClaimedWorkers<'a> {
+    slots: &'a WorkerSlots,
+    indexes: Vec<usize>,
+}
+
+impl Drop for ClaimedWorkers<'_> {
+    fn drop(&mut self) {
+        self.slots.release(&self.indexes);
+    }
+}
+
 struct Agent {
     api: AgentApi,
     config: Config,
 }
 
 impl Agent {
-    fn new(url: &str, token: &str, caps: &Capabilities) -> Fallible<Self> {
+    fn new(url: &str, credentials: &Credentials, caps: &Capabilities) -> Fallible<Self> {
         info!("connecting to crater server {}...", url);
 
-        let api = AgentApi::new(url, token);
+        let api = AgentApi::new(url, credentials);
         let config = api.config(caps)?;
 
         info!("connected to the crater server!");
@@ -85,59 +210,131 @@ impl Agent {
         })
     }
 
-    fn experiment(&self) -> Fallible<(Experiment, Vec<Crate>)> {
-        info!("asking the server for a new experiment...");
-        Ok(self.api.next_experiment()?)
+    fn experiment(&self, workers: &AgentWorkers) -> Fallible<Option<NextExperiment>> {
+        self.api.next_experiment(workers)
     }
 }
 
-fn run_heartbeat(url: &str, token: &str) {
-    let api = AgentApi::new(url, token);
+fn run_heartbeat(url: &str, credentials: &Credentials) {
+    let api = AgentApi::new(url, credentials);
 
     thread::spawn(move || loop {
         if let Err(e) = api.heartbeat().with_context(|_| "failed to send heartbeat") {
             utils::report_failure(&e);
         }
-        thread::sleep(Duration::from_secs(60));
+        thread::sleep(Duration::from_secs(HEARTBEAT_INTERVAL));
     });
 }
 
 fn run_experiment(
     agent: &Agent,
     workspace: &Workspace,
-    db: &ResultsUploader,
-    threads_count: usize,
-) -> Result<(), (Option<Experiment>, Error)> {
-    let (ex, crates) = agent.experiment().map_err(|e| (None, e))?;
-    crate::runner::run_ex(&ex, workspace, &crates, db, threads_count, &agent.config)
-        .map_err(|err| (Some(ex), err))?;
-    Ok(())
+    next: &NextExperiment,
+    workers: &Workers,
+) -> Fallible<()> {
+    // Every experiment has its own uploader, as the results are sent along with the data
+    // recorded while running the experiment
+    let db = ResultsUploader::new(&agent.api);
+    crate::runner::run_ex(
+        &next.experiment,
+        workspace,
+        &next.crates,
+        &db,
+        workers,
+        &agent.config,
+    )
+}
+
+fn report_experiment_error(agent: &Agent, ex: &Experiment, err: &Error) {
+    utils::report_failure(err);
+    let res = if let Some(install_err) = err.downcast_ref::<ToolchainInstallError>() {
+        agent.api.report_toolchain_install_error(ex, install_err)
+    } else {
+        agent
+            .api
+            .report_error(ex, format!("{}", err.find_root_cause()))
+    };
+    if let Err(e) = res.with_context(|_| "error encountered") {
+        utils::report_failure(&e);
+    }
 }
 
 pub fn run(
     url: &str,
-    token: &str,
+    credentials: &Credentials,
     threads_count: usize,
     caps: &Capabilities,
     workspace: &Workspace,
 ) -> Fallible<()> {
-    let agent = Agent::new(url, token, caps)?;
-    let db = results::ResultsUploader::new(&agent.api);
-
-    run_heartbeat(url, token);
-
-    loop {
-        if let Err((ex, err)) = run_experiment(&agent, workspace, &db, threads_count) {
-            utils::report_failure(&err);
-            if let Some(ex) = ex {
-                if let Err(e) = agent
-                    .api
-                    .report_error(&ex, format!("{}", err.find_root_cause()))
-                    .with_context(|_| "error encountered")
-                {
-                    utils::report_failure(&e);
-                }
+    let agent = Agent::new(url, credentials, caps)?;
+    let slots = WorkerSlots::new(threads_count);
+
+    run_heartbeat(url, credentials);
+
+    // Large agents can run multiple experiments at the same time, each one in its own thread with
+    // the part of the workers the server assigned to it
+    let (finished_send, finished_recv) = mpsc::channel();
+    let wait = || {
+        let _ = finished_recv.recv_timeout(Duration::from_secs(RETRY_AFTER));
+    };
+    scope(|scope| {
+        let mut waiting = false;
+        loop {
+            let workers = slots.status();
+            if workers.idle == 0 {
+                wait();
+                continue;
+            }
+
+            if !waiting {
+                info!(
+                    "asking the server for a new experiment for {} idle workers...",
+                    workers.idle
+                );
             }
+            let next = match agent.experiment(&workers) {
+                Ok(Some(next)) => next,
+                Ok(None) => {
+                    waiting = true;
+                    wait();
+                    continue;
+                }
+                Err(err) => {
+                    // There is no point in retrying if the server refuses to give work to this
+                    // agent, but the experiments already running are finished first
+                    if let Some(AgentApiError::OutdatedAgent(_)) = err.downcast_ref() {
+                        return Err(err);
+                    }
+                    utils::report_failure(&err);
+                    wait();
+                    continue;
+                }
+            };
+            waiting = false;
+
+            let claimed = slots.claim(&next.experiment.name, next.workers);
+            let partition = match Workers::partition(claimed.indexes.clone(), workers.total) {
+                Ok(partition) => partition,
+                Err(err) => {
+                    report_experiment_error(&agent, &next.experiment, &err);
+                    continue;
+                }
+            };
+            info!(
+                "running experiment {} with {} of the {} workers",
+                next.experiment.name,
+                claimed.indexes.len(),
+                workers.total,
+            );
+            let finished_send = finished_send.clone();
+            let agent = &agent;
+            scope.spawn(move || {
+                if let Err(err) = run_experiment(agent, workspace, &next, &partition) {
+                    report_experiment_error(agent, &next.experiment, &err);
+                }
+                drop(claimed);
+                let _ = finished_send.send(());
+            });
         }
-    }
+    })
 }
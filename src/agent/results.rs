@@ -3,9 +3,13 @@ use crate::config::Config;
 use crate::crates::{Crate, GitHubRepo};
 use crate::experiments::Experiment;
 use crate::prelude::*;
-use crate::results::{EncodingType, TestResult, WriteResults};
-use crate::toolchain::Toolchain;
+use crate::results::{
+    Diagnostic, DiagnosticCounts, DocItem, EncodingType, FailedTarget, Invocation, MemberResult,
+    ResourceUsage, TestResult, UnitTiming, WriteResults,
+};
+use crate::toolchain::{Toolchain, ToolchainVersion};
 use rustwide::logging::{self, LogStorage};
+use std::collections::HashMap;
 use std::ops::DerefMut;
 use std::sync::{Arc, Mutex};
 
@@ -13,6 +17,18 @@ use std::sync::{Arc, Mutex};
 pub struct ResultsUploader<'a> {
     api: &'a AgentApi,
     shas: Arc<Mutex<Vec<(GitHubRepo, String)>>>,
+    members: Arc<Mutex<HashMap<(Crate, Toolchain), Vec<MemberResult>>>>,
+    failed_targets: Arc<Mutex<HashMap<(Crate, Toolchain), Vec<FailedTarget>>>>,
+    diagnostics: Arc<Mutex<HashMap<(Crate, Toolchain), Vec<Diagnostic>>>>,
+    diagnostic_counts: Arc<Mutex<HashMap<(Crate, Toolchain), DiagnosticCounts>>>,
+    unit_timings: Arc<Mutex<HashMap<(Crate, Toolchain), Vec<UnitTiming>>>>,
+    doc_items: Arc<Mutex<HashMap<(Crate, Toolchain), Vec<DocItem>>>>,
+    invocations: Arc<Mutex<HashMap<(Crate, Toolchain), Vec<Invocation>>>>,
+    resource_usage: Arc<Mutex<HashMap<(Crate, Toolchain), ResourceUsage>>>,
+    snapshots: Arc<Mutex<HashMap<(Crate, Toolchain), Vec<u8>>>>,
+    c_compilers: Arc<Mutex<HashMap<(Crate, Toolchain), String>>>,
+    lockfiles: Arc<Mutex<HashMap<(Crate, Toolchain), String>>>,
+    toolchain_versions: Arc<Mutex<Vec<(Toolchain, ToolchainVersion)>>>,
 }
 
 impl<'a> ResultsUploader<'a> {
@@ -20,6 +36,18 @@ impl<'a> ResultsUploader<'a> {
         ResultsUploader {
             api,
             shas: Arc::new(Mutex::new(Vec::new())),
+            members: Arc::new(Mutex::new(HashMap::new())),
+            failed_targets: Arc::new(Mutex::new(HashMap::new())),
+            diagnostics: Arc::new(Mutex::new(HashMap::new())),
+            diagnostic_counts: Arc::new(Mutex::new(HashMap::new())),
+            unit_timings: Arc::new(Mutex::new(HashMap::new())),
+            doc_items: Arc::new(Mutex::new(HashMap::new())),
+            invocations: Arc::new(Mutex::new(HashMap::new())),
+            resource_usage: Arc::new(Mutex::new(HashMap::new())),
+            snapshots: Arc::new(Mutex::new(HashMap::new())),
+            c_compilers: Arc::new(Mutex::new(HashMap::new())),
+            lockfiles: Arc::new(Mutex::new(HashMap::new())),
+            toolchain_versions: Arc::new(Mutex::new(Vec::new())),
         }
     }
 }
@@ -61,11 +89,271 @@ impl<'a> WriteResults for ResultsUploader<'a> {
         let output = storage.to_string();
 
         let shas = ::std::mem::replace(self.shas.lock().unwrap().deref_mut(), Vec::new());
+        let toolchain_versions = ::std::mem::replace(
+            self.toolchain_versions.lock().unwrap().deref_mut(),
+            Vec::new(),
+        );
+        let members = self
+            .members
+            .lock()
+            .unwrap()
+            .remove(&(krate.clone(), toolchain.clone()))
+            .unwrap_or_default();
+        let failed_targets = self
+            .failed_targets
+            .lock()
+            .unwrap()
+            .remove(&(krate.clone(), toolchain.clone()))
+            .unwrap_or_default();
+        let diagnostics = self
+            .diagnostics
+            .lock()
+            .unwrap()
+            .remove(&(krate.clone(), toolchain.clone()))
+            .unwrap_or_default();
+        let diagnostic_counts = self
+            .diagnostic_counts
+            .lock()
+            .unwrap()
+            .remove(&(krate.clone(), toolchain.clone()));
+        let unit_timings = self
+            .unit_timings
+            .lock()
+            .unwrap()
+            .remove(&(krate.clone(), toolchain.clone()))
+            .unwrap_or_default();
+        let doc_items = self
+            .doc_items
+            .lock()
+            .unwrap()
+            .remove(&(krate.clone(), toolchain.clone()))
+            .unwrap_or_default();
+        let invocations = self
+            .invocations
+            .lock()
+            .unwrap()
+            .remove(&(krate.clone(), toolchain.clone()))
+            .unwrap_or_default();
+        let resource_usage = self
+            .resource_usage
+            .lock()
+            .unwrap()
+            .remove(&(krate.clone(), toolchain.clone()));
+        let snapshot = self
+            .snapshots
+            .lock()
+            .unwrap()
+            .remove(&(krate.clone(), toolchain.clone()));
+        let c_compiler = self
+            .c_compilers
+            .lock()
+            .unwrap()
+            .remove(&(krate.clone(), toolchain.clone()));
+        let lockfile = self
+            .lockfiles
+            .lock()
+            .unwrap()
+            .remove(&(krate.clone(), toolchain.clone()));
 
         info!("sending results to the crater server...");
-        self.api
-            .record_progress(ex, krate, toolchain, output.as_bytes(), result, &shas)?;
+        self.api.record_progress(
+            ex,
+            krate,
+            toolchain,
+            output.as_bytes(),
+            result,
+            &members,
+            &failed_targets,
+            &diagnostics,
+            diagnostic_counts,
+            &unit_timings,
+            &doc_items,
+            &invocations,
+            resource_usage,
+            snapshot.as_ref().map(|snapshot| snapshot.as_slice()),
+            c_compiler.as_ref().map(|version| version.as_str()),
+            lockfile.as_ref().map(|lockfile| lockfile.as_str()),
+            &shas,
+            &toolchain_versions,
+        )?;
 
         Ok(result)
     }
+
+    fn record_member_results(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        members: &[MemberResult],
+    ) -> Fallible<()> {
+        // The results of the members are sent along with the result of the whole crate
+        self.members
+            .lock()
+            .unwrap()
+            .insert((krate.clone(), toolchain.clone()), members.to_vec());
+        Ok(())
+    }
+
+    fn record_failed_targets(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        targets: &[FailedTarget],
+    ) -> Fallible<()> {
+        self.failed_targets
+            .lock()
+            .unwrap()
+            .insert((krate.clone(), toolchain.clone()), targets.to_vec());
+        Ok(())
+    }
+
+    fn record_diagnostics(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        diagnostics: &[Diagnostic],
+    ) -> Fallible<()> {
+        self.diagnostics
+            .lock()
+            .unwrap()
+            .insert((krate.clone(), toolchain.clone()), diagnostics.to_vec());
+        Ok(())
+    }
+
+    fn record_diagnostic_counts(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        counts: DiagnosticCounts,
+    ) -> Fallible<()> {
+        self.diagnostic_counts
+            .lock()
+            .unwrap()
+            .insert((krate.clone(), toolchain.clone()), counts);
+        Ok(())
+    }
+
+    fn record_unit_timings(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        timings: &[UnitTiming],
+    ) -> Fallible<()> {
+        self.unit_timings
+            .lock()
+            .unwrap()
+            .insert((krate.clone(), toolchain.clone()), timings.to_vec());
+        Ok(())
+    }
+
+    fn record_doc_items(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        items: &[DocItem],
+    ) -> Fallible<()> {
+        self.doc_items
+            .lock()
+            .unwrap()
+            .insert((krate.clone(), toolchain.clone()), items.to_vec());
+        Ok(())
+    }
+
+    fn record_invocations(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        invocations: &[Invocation],
+    ) -> Fallible<()> {
+        self.invocations
+            .lock()
+            .unwrap()
+            .insert((krate.clone(), toolchain.clone()), invocations.to_vec());
+        Ok(())
+    }
+
+    fn record_resource_usage(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        usage: ResourceUsage,
+    ) -> Fallible<()> {
+        self.resource_usage
+            .lock()
+            .unwrap()
+            .insert((krate.clone(), toolchain.clone()), usage);
+        Ok(())
+    }
+
+    fn record_snapshot(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        snapshot: &[u8],
+    ) -> Fallible<()> {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .insert((krate.clone(), toolchain.clone()), snapshot.to_vec());
+        Ok(())
+    }
+
+    fn record_c_compiler(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        version: &str,
+    ) -> Fallible<()> {
+        self.c_compilers
+            .lock()
+            .unwrap()
+            .insert((krate.clone(), toolchain.clone()), version.to_string());
+        Ok(())
+    }
+
+    fn record_lockfile(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        lockfile: &str,
+    ) -> Fallible<()> {
+        self.lockfiles
+            .lock()
+            .unwrap()
+            .insert((krate.clone(), toolchain.clone()), lockfile.to_string());
+        Ok(())
+    }
+
+    fn record_toolchain_version(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        version: &ToolchainVersion,
+    ) -> Fallible<()> {
+        // The versions are sent along with the next result, like the SHAs
+        self.toolchain_versions
+            .lock()
+            .unwrap()
+            .push((toolchain.clone(), version.clone()));
+        Ok(())
+    }
+
+    fn record_worker_crash(&self, ex: &Experiment, worker: &str, error: &str) -> Fallible<()> {
+        self.api.report_worker_crash(ex, worker, error)
+    }
+
+    fn is_cancelled(&self, ex: &Experiment) -> Fallible<bool> {
+        self.api.is_cancelled(ex)
+    }
 }
@@ -61,6 +61,8 @@ load_files! {
         "ui/layout.html",
 
         "ui/agents.html",
+        "ui/audit-log.html",
+        "ui/jobs.html",
 
         "ui/queue.html",
         "ui/experiment.html",
@@ -71,6 +73,8 @@ load_files! {
         "report/layout.html",
         "report/downloads.html",
         "report/results.html",
+        "report/crates.html",
+        "report/deleted.html",
     ],
     assets: [
         "ui.css" => mime::TEXT_CSS,
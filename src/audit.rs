@@ -0,0 +1,223 @@
+use crate::db::{Database, QueryUtils};
+use crate::experiments::DEFAULT_NAMESPACE;
+use crate::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+string_enum!(pub enum AuditAction {
+    CreateExperiment => "create-experiment",
+    CloneExperiment => "clone-experiment",
+    EditExperiment => "edit-experiment",
+    DeleteExperiment => "delete-experiment",
+    ArchiveExperiment => "archive-experiment",
+    CancelExperiment => "cancel-experiment",
+    RenameExperiment => "rename-experiment",
+    TagExperiment => "tag-experiment",
+    RetryExperiment => "retry-experiment",
+    RequeueExperiment => "requeue-experiment",
+    RetryReport => "retry-report",
+    RegenerateReport => "regenerate-report",
+    DeleteResults => "delete-results",
+    PruneLogs => "prune-logs",
+    PinExperiment => "pin-experiment",
+    UnpinExperiment => "unpin-experiment",
+    ExperimentFailed => "experiment-failed",
+    WorkerCrashed => "worker-crashed",
+    ClaimsReleased => "claims-released",
+    AgentAdded => "agent-added",
+    AgentRemoved => "agent-removed",
+    EnableMaintenance => "enable-maintenance",
+    DisableMaintenance => "disable-maintenance",
+});
+
+/// Who performed an action recorded in the audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Actor {
+    CLI,
+    Server,
+    GitHub(String),
+    Agent(String),
+    /// Owner of a token of the public API.
+    Api(String),
+}
+
+impl fmt::Display for Actor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Actor::CLI => write!(f, "cli"),
+            Actor::Server => write!(f, "server"),
+            Actor::GitHub(ref user) => write!(f, "github:{}", user),
+            Actor::Agent(ref name) => write!(f, "agent:{}", name),
+            Actor::Api(ref name) => write!(f, "api:{}", name),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub actor: String,
+    pub action: AuditAction,
+    pub experiment: Option<String>,
+    pub payload: serde_json::Value,
+}
+
+/// Record an action in the audit log.
+pub fn record<DB: QueryUtils, P: Serialize>(
+    db: &DB,
+    actor: &Actor,
+    action: AuditAction,
+    experiment: Option<&str>,
+    payload: &P,
+) -> Fallible<()> {
+    db.execute(
+        "INSERT INTO audit_log (created_at, actor, action, experiment, payload) \
+         VALUES (?1, ?2, ?3, ?4, ?5);",
+        &[
+            &Utc::now(),
+            &actor.to_string(),
+            &action.to_str(),
+            &experiment,
+            &serde_json::to_string(payload)?,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Load the most recent entries of the audit log, optionally only the ones older than the entry
+/// with the `before` ID.
+///
+/// If a namespace is provided only the entries about its experiments are returned, along with
+/// the ones not about a single experiment for the default namespace.
+pub fn entries(
+    db: &Database,
+    namespace: Option<&str>,
+    before: Option<i64>,
+    limit: usize,
+) -> Fallible<Vec<AuditEntry>> {
+    // Experiments outside the default namespace are stored as `namespace:name`
+    db.query(
+        "SELECT * FROM audit_log WHERE id < ?1 AND (\
+             ?3 IS NULL \
+             OR (?3 = ?4 AND (experiment IS NULL OR instr(experiment, ':') = 0)) \
+             OR substr(experiment, 1, length(?3) + 1) = ?3 || ':'\
+         ) ORDER BY id DESC LIMIT ?2;",
+        &[
+            &before.unwrap_or(i64::max_value()),
+            &(limit as i64),
+            &namespace,
+            &DEFAULT_NAMESPACE,
+        ],
+        |row| -> Fallible<AuditEntry> {
+            let action: String = row.get("action");
+            let payload: String = row.get("payload");
+            Ok(AuditEntry {
+                id: row.get("id"),
+                created_at: row.get("created_at"),
+                actor: row.get("actor"),
+                action: action.parse()?,
+                experiment: row.get("experiment"),
+                payload: serde_json::from_str(&payload)?,
+            })
+        },
+    )?
+    .into_iter()
+    .collect()
+}
+
+/// Load who created each experiment, keyed by the name of the experiment.
+pub fn requesters(db: &Database) -> Fallible<HashMap<String, String>> {
+    // Names of deleted experiments can be reused, so the most recent creation wins
+    let rows = db.query(
+        "SELECT experiment, actor FROM audit_log \
+         WHERE action = ?1 AND experiment IS NOT NULL ORDER BY id;",
+        &[&AuditAction::CreateExperiment.to_str()],
+        |row| -> (String, String) { (row.get("experiment"), row.get("actor")) },
+    )?;
+    Ok(rows.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entries, record, requesters, Actor, AuditAction};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment, DeleteExperiment};
+    use crate::config::Config;
+    use crate::db::Database;
+
+    #[test]
+    fn test_actions_are_recorded() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let ctx = ActionsCtx::new(&db, &config).with_actor(Actor::GitHub("ghost".into()));
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        DeleteExperiment {
+            name: "foo".to_string(),
+        }
+        .apply(&ActionsCtx::new(&db, &config))
+        .unwrap();
+
+        let log = entries(&db, None, None, 10).unwrap();
+        assert_eq!(log.len(), 2);
+
+        // The most recent entries are returned first
+        assert_eq!(log[0].action, AuditAction::DeleteExperiment);
+        assert_eq!(log[0].actor.as_str(), "cli");
+        assert_eq!(log[0].experiment.as_ref().unwrap().as_str(), "foo");
+        assert_eq!(log[1].action, AuditAction::CreateExperiment);
+        assert_eq!(log[1].actor.as_str(), "github:ghost");
+        assert_eq!(log[1].payload["mode"], "build-and-test");
+
+        // Pagination works
+        let older = entries(&db, None, Some(log[0].id), 10).unwrap();
+        assert_eq!(older.len(), 1);
+        assert_eq!(older[0].id, log[1].id);
+
+        // The most recent creation of an experiment is the one considered
+        CreateExperiment::dummy("foo")
+            .apply(&ActionsCtx::new(&db, &config))
+            .unwrap();
+        let requesters = requesters(&db).unwrap();
+        assert_eq!(requesters.len(), 1);
+        assert_eq!(requesters["foo"], "cli");
+    }
+
+    #[test]
+    fn test_namespace_entries() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let ctx = ActionsCtx::new(&db, &config);
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        CreateExperiment {
+            namespace: "infra".into(),
+            ..CreateExperiment::dummy("bar")
+        }
+        .apply(&ctx)
+        .unwrap();
+        record(&db, &Actor::CLI, AuditAction::EnableMaintenance, None, &()).unwrap();
+
+        let experiments = |namespace| {
+            entries(&db, namespace, None, 10)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.experiment)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            experiments(None),
+            vec![None, Some("infra:bar".into()), Some("foo".into())]
+        );
+        assert_eq!(experiments(Some("default")), vec![None, Some("foo".into())]);
+        assert_eq!(experiments(Some("infra")), vec![Some("infra:bar".into())]);
+        assert!(experiments(Some("infr")).is_empty());
+    }
+}
@@ -9,24 +9,41 @@
 //! application state employs ownership techniques to ensure that
 //! parallel access is consistent and race-free.
 
+use chrono::{Duration as ChronoDuration, NaiveDate, Utc};
 use crater::actions::{self, Action, ActionsCtx};
 use crater::agent::{self, Capabilities};
+use crater::audit::{self, Actor, AuditAction};
+use crater::client::ApiClient;
 use crater::config::Config;
-use crater::crates::Crate;
+use crater::crates::{parse_crate, Crate, Edition, RustVersion};
 use crater::db::Database;
-use crater::experiments::{Assignee, CapLints, CrateSelect, Experiment, Mode, Status};
-use crater::report;
+use crater::experiments::{
+    Assignee, BuildTargets, CapLints, CrateSelect, Experiment, Mode, RustcWrapper, Status, Tags,
+    Targets, TestFlags, DEFAULT_NAMESPACE,
+};
+use crater::maintenance;
+use crater::report::{self, Comparison, ReportRemoval};
 use crater::results::{DatabaseDB, DeleteResults};
+use crater::retention;
 use crater::runner;
-use crater::server;
+use crater::server::{
+    self,
+    api_types::{ExperimentsQuery, QueueExperiment, ResultsQuery},
+    webhook_events,
+};
 use crater::toolchain::Toolchain;
-use failure::{bail, Error, Fallible};
+use failure::{bail, Error, Fallible, ResultExt};
+use log::info;
 use rustwide::{cmd::SandboxImage, Workspace, WorkspaceBuilder};
+use serde_json::json;
 use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 use structopt::clap::AppSettings;
+use tokio::runtime::Runtime;
 
 // An experiment name
 #[derive(Debug, Clone)]
@@ -67,6 +84,8 @@ fn default_capabilities_for_target() -> Capabilities {
         &["windows"]
     } else if cfg!(target_os = "linux") {
         &["linux"]
+    } else if cfg!(target_os = "macos") {
+        &["macos"]
     } else {
         &[]
     };
@@ -96,6 +115,18 @@ pub enum Crater {
     CreateLists {
         #[structopt(name = "lists")]
         lists: Vec<String>,
+        #[structopt(
+            name = "metadata",
+            long = "metadata",
+            help = "Also load the manifest metadata of the crates, instead of when experiments are created."
+        )]
+        metadata: bool,
+        #[structopt(
+            name = "registry-snapshot",
+            long = "registry-snapshot",
+            help = "Generate the registry list from this commit of the crates.io index."
+        )]
+        registry_snapshot: Option<String>,
     },
 
     #[structopt(name = "define-ex", about = "define an experiment")]
@@ -106,6 +137,14 @@ pub enum Crater {
         tc1: Toolchain,
         #[structopt(name = "tc-2")]
         tc2: Toolchain,
+        #[structopt(
+            name = "intermediate-toolchain",
+            long = "intermediate-toolchain",
+            help = "Toolchain released between the two compared ones, to find the first one a \
+                    regression appeared in. Can be repeated, in release order.",
+            raw(number_of_values = "1")
+        )]
+        intermediate_toolchains: Vec<Toolchain>,
         #[structopt(
             name = "mode",
             long = "mode",
@@ -118,12 +157,26 @@ pub enum Crater {
         #[structopt(
             name = "crate-select",
             long = "crate-select",
-            raw(
-                default_value = "CrateSelect::Demo.to_str()",
-                possible_values = "CrateSelect::possible_values()"
-            )
+            default_value = "demo",
+            help = "Crates to test: full, demo, small-random, top-<count> (like top-100) to \
+                    test the most downloaded crates, random-<count> to test a reproducible \
+                    random sample, local, dummy, local:<path-or-git-url> to \
+                    test a single crate, regressed:<experiment> to test the regressions of \
+                    another experiment again, same-as:<experiment> to test the same crates as \
+                    another experiment, rdeps:<crate>[:<depth>] to test the crates \
+                    depending on a crate, list:<name> to test an uploaded list of crates, or \
+                    category:<slug> and keyword:<keyword> to test the crates.io crates in a \
+                    category or with a keyword."
         )]
         crates: CrateSelect,
+        #[structopt(
+            name = "crate-list-file",
+            long = "crate-list-file",
+            parse(from_os_str),
+            help = "Test the crates listed in this file instead of --crate-select, one for each \
+                    line: crates.io crates (optionally followed by -<version>) or GitHub URLs."
+        )]
+        crate_list_file: Option<PathBuf>,
         #[structopt(
             name = "level",
             long = "cap-lints",
@@ -141,6 +194,118 @@ pub enum Crater {
         assign: Option<Assignee>,
         #[structopt(name = "requirement", long = "requirement")]
         requirement: Option<String>,
+        #[structopt(
+            name = "namespace",
+            long = "namespace",
+            raw(default_value = "DEFAULT_NAMESPACE")
+        )]
+        namespace: String,
+        #[structopt(
+            name = "edition",
+            long = "edition",
+            help = "Only test the crates using this edition.",
+            raw(possible_values = "Edition::possible_values()")
+        )]
+        edition: Option<Edition>,
+        #[structopt(name = "max-rust-version", long = "max-rust-version")]
+        max_rust_version: Option<RustVersion>,
+        #[structopt(
+            name = "build-targets",
+            long = "build-targets",
+            help = "Also build the examples, the benchmarks or all the targets of the crates.",
+            raw(
+                default_value = "BuildTargets::Default.to_str()",
+                possible_values = "BuildTargets::possible_values()"
+            )
+        )]
+        build_targets: BuildTargets,
+        #[structopt(
+            name = "test-flags",
+            long = "test-flags",
+            help = "Extra flags passed to cargo test, for example \"-- --test-threads=1\"."
+        )]
+        test_flags: Option<TestFlags>,
+        #[structopt(
+            name = "dist-server",
+            long = "dist-server",
+            help = "Server the toolchains are downloaded from, instead of the configured one."
+        )]
+        dist_server: Option<String>,
+        #[structopt(
+            name = "rustc-wrapper",
+            long = "rustc-wrapper",
+            help = "Executable in the agents' rustc-wrappers directory wrapping every rustc call."
+        )]
+        rustc_wrapper: Option<RustcWrapper>,
+        #[structopt(
+            name = "timings",
+            long = "timings",
+            help = "Record how long compiling every crate took, with cargo's --timings=json."
+        )]
+        timings: bool,
+        #[structopt(
+            name = "targets",
+            long = "targets",
+            help = "Comma-separated target triples the crates are built for, instead of the host."
+        )]
+        targets: Option<Targets>,
+        #[structopt(
+            name = "tags",
+            long = "tags",
+            help = "Comma-separated tags attached to the experiment, like release:1.78."
+        )]
+        tags: Option<Tags>,
+    },
+
+    #[structopt(
+        name = "bisect",
+        about = "find the first nightly a crate regressed with"
+    )]
+    Bisect {
+        #[structopt(name = "experiment", long = "ex")]
+        ex: Ex,
+        #[structopt(
+            name = "crate",
+            long = "crate",
+            help = "Crate to bisect: a crates.io crate (optionally followed by -<version>) or a \
+                    GitHub URL."
+        )]
+        krate: String,
+        #[structopt(
+            name = "start",
+            long = "start",
+            help = "Date of the last nightly the crate worked with, like 2024-01-01."
+        )]
+        start: NaiveDate,
+        #[structopt(
+            name = "end",
+            long = "end",
+            help = "Date of the first nightly the crate is known to be broken with."
+        )]
+        end: NaiveDate,
+        #[structopt(
+            name = "mode",
+            long = "mode",
+            raw(
+                default_value = "Mode::BuildAndTest.to_str()",
+                possible_values = "Mode::possible_values()"
+            )
+        )]
+        mode: Mode,
+        #[structopt(
+            name = "test-flags",
+            long = "test-flags",
+            help = "Extra flags passed to cargo test, for example \"-- --test-threads=1\"."
+        )]
+        test_flags: Option<TestFlags>,
+        #[structopt(name = "priority", long = "priority", short = "p", default_value = "0")]
+        priority: i32,
+        #[structopt(
+            name = "namespace",
+            long = "namespace",
+            raw(default_value = "DEFAULT_NAMESPACE")
+        )]
+        namespace: String,
     },
 
     #[structopt(name = "edit", about = "edit an experiment configuration")]
@@ -157,10 +322,115 @@ pub enum Crater {
             raw(possible_values = "Mode::possible_values()")
         )]
         mode: Option<Mode>,
+        #[structopt(name = "crates", long = "crates")]
+        crates: Option<CrateSelect>,
+        #[structopt(
+            name = "cap-lints",
+            long = "cap-lints",
+            raw(possible_values = "CapLints::possible_values()")
+        )]
+        cap_lints: Option<CapLints>,
+        #[structopt(name = "priority", long = "priority", short = "p")]
+        priority: Option<i32>,
+        #[structopt(
+            name = "ignore-blacklist",
+            long = "ignore-blacklist",
+            conflicts_with = "no-ignore-blacklist"
+        )]
+        ignore_blacklist: bool,
+        #[structopt(
+            name = "no-ignore-blacklist",
+            long = "no-ignore-blacklist",
+            conflicts_with = "ignore-blacklist"
+        )]
+        no_ignore_blacklist: bool,
+        #[structopt(name = "assign", long = "assign")]
+        assign: Option<Assignee>,
+        #[structopt(name = "requirement", long = "requirement")]
+        requirement: Option<String>,
+        #[structopt(
+            name = "edition",
+            long = "edition",
+            raw(possible_values = "Edition::possible_values()")
+        )]
+        edition: Option<Edition>,
+        #[structopt(name = "max-rust-version", long = "max-rust-version")]
+        max_rust_version: Option<RustVersion>,
+        #[structopt(
+            name = "build-targets",
+            long = "build-targets",
+            raw(possible_values = "BuildTargets::possible_values()")
+        )]
+        build_targets: Option<BuildTargets>,
+        #[structopt(name = "test-flags", long = "test-flags")]
+        test_flags: Option<TestFlags>,
+        #[structopt(name = "dist-server", long = "dist-server")]
+        dist_server: Option<String>,
+        #[structopt(name = "rustc-wrapper", long = "rustc-wrapper")]
+        rustc_wrapper: Option<RustcWrapper>,
+        #[structopt(name = "timings", long = "timings", conflicts_with = "no-timings")]
+        timings: bool,
+        #[structopt(name = "no-timings", long = "no-timings", conflicts_with = "timings")]
+        no_timings: bool,
+        #[structopt(name = "targets", long = "targets")]
+        targets: Option<Targets>,
+    },
+
+    #[structopt(name = "delete-ex", about = "delete shared data for experiment")]
+    DeleteEx {
+        #[structopt(long = "ex", default_value = "default")]
+        ex: Ex,
+        #[structopt(
+            name = "report",
+            long = "report",
+            help = "Delete the published report, or replace it with a page saying the experiment \
+                    was deleted.",
+            raw(possible_values = "ReportRemoval::possible_values()")
+        )]
+        report: Option<ReportRemoval>,
+        #[structopt(
+            name = "report-dest",
+            long = "report-dest",
+            help = "S3 URI or directory the report was published to (default: the reports bucket \
+                    of tokens.toml)."
+        )]
+        report_dest: Option<String>,
+    },
+
+    #[structopt(
+        name = "rename-ex",
+        about = "rename an experiment, along with its results and its report"
+    )]
+    RenameEx {
+        #[structopt(long = "ex")]
+        ex: Ex,
+        #[structopt(name = "new-name", long = "new-name")]
+        new_name: String,
+    },
+
+    #[structopt(
+        name = "clone-ex",
+        about = "define an experiment with the configuration and the crates of another one"
+    )]
+    CloneEx {
+        #[structopt(name = "source")]
+        source: Ex,
+        #[structopt(name = "new-name")]
+        new_name: String,
+        #[structopt(name = "toolchain-start", long = "start")]
+        tc1: Option<Toolchain>,
+        #[structopt(name = "toolchain-end", long = "end")]
+        tc2: Option<Toolchain>,
+        #[structopt(
+            name = "mode",
+            long = "mode",
+            raw(possible_values = "Mode::possible_values()")
+        )]
+        mode: Option<Mode>,
         #[structopt(
             name = "crates",
             long = "crates",
-            raw(possible_values = "CrateSelect::possible_values()")
+            help = "Crates to test instead of the ones of the cloned experiment."
         )]
         crates: Option<CrateSelect>,
         #[structopt(
@@ -187,12 +457,95 @@ pub enum Crater {
         assign: Option<Assignee>,
         #[structopt(name = "requirement", long = "requirement")]
         requirement: Option<String>,
+        #[structopt(
+            name = "edition",
+            long = "edition",
+            raw(possible_values = "Edition::possible_values()")
+        )]
+        edition: Option<Edition>,
+        #[structopt(name = "max-rust-version", long = "max-rust-version")]
+        max_rust_version: Option<RustVersion>,
+        #[structopt(
+            name = "build-targets",
+            long = "build-targets",
+            raw(possible_values = "BuildTargets::possible_values()")
+        )]
+        build_targets: Option<BuildTargets>,
+        #[structopt(name = "test-flags", long = "test-flags")]
+        test_flags: Option<TestFlags>,
+        #[structopt(name = "dist-server", long = "dist-server")]
+        dist_server: Option<String>,
+        #[structopt(name = "rustc-wrapper", long = "rustc-wrapper")]
+        rustc_wrapper: Option<RustcWrapper>,
+        #[structopt(name = "timings", long = "timings", conflicts_with = "no-timings")]
+        timings: bool,
+        #[structopt(name = "no-timings", long = "no-timings", conflicts_with = "timings")]
+        no_timings: bool,
+        #[structopt(name = "targets", long = "targets")]
+        targets: Option<Targets>,
     },
 
-    #[structopt(name = "delete-ex", about = "delete shared data for experiment")]
-    DeleteEx {
-        #[structopt(long = "ex", default_value = "default")]
+    #[structopt(
+        name = "tag-ex",
+        about = "replace the tags of an experiment, whatever its status"
+    )]
+    TagEx {
+        #[structopt(long = "ex")]
         ex: Ex,
+        #[structopt(
+            name = "tags",
+            long = "tags",
+            help = "Comma-separated tags, or an empty string to remove them."
+        )]
+        tags: Tags,
+    },
+
+    #[structopt(
+        name = "cancel-ex",
+        about = "stop a queued or running experiment, keeping its partial results"
+    )]
+    CancelEx {
+        #[structopt(name = "experiment", long = "ex", default_value = "default")]
+        ex: Ex,
+    },
+
+    #[structopt(
+        name = "archive-ex",
+        about = "archive completed or failed experiments, hiding them from the listings"
+    )]
+    ArchiveEx {
+        #[structopt(name = "experiment", long = "ex")]
+        ex: Vec<Ex>,
+        #[structopt(
+            name = "older-than",
+            long = "older-than",
+            help = "Archive all the experiments that finished more than this many days ago."
+        )]
+        older_than: Option<i64>,
+    },
+
+    #[structopt(
+        name = "pin-ex",
+        about = "prevent the retention policy from deleting an experiment"
+    )]
+    PinEx {
+        #[structopt(name = "experiment", long = "ex", default_value = "default")]
+        ex: Ex,
+        #[structopt(name = "unpin", long = "unpin")]
+        unpin: bool,
+    },
+
+    #[structopt(
+        name = "enforce-retention",
+        about = "remove the old data according to the retention policy"
+    )]
+    EnforceRetention {
+        #[structopt(
+            name = "dry-run",
+            long = "dry-run",
+            help = "Only show what would be removed."
+        )]
+        dry_run: bool,
     },
 
     #[structopt(
@@ -217,6 +570,30 @@ pub enum Crater {
         krate: Crate,
     },
 
+    #[structopt(
+        name = "enable-maintenance",
+        about = "stop assigning experiments to agents and creating new ones"
+    )]
+    EnableMaintenance {
+        #[structopt(name = "message", long = "message")]
+        message: Option<String>,
+    },
+
+    #[structopt(name = "disable-maintenance", about = "resume the normal operation")]
+    DisableMaintenance,
+
+    #[structopt(
+        name = "replay-webhooks",
+        about = "queue GitHub webhook events to be processed again by the server"
+    )]
+    ReplayWebhooks {
+        #[structopt(
+            name = "id",
+            help = "IDs of the events to replay (all the failed events by default)."
+        )]
+        ids: Vec<i64>,
+    },
+
     #[structopt(name = "run-graph", about = "run a parallelized experiment")]
     RunGraph {
         #[structopt(name = "experiment", long = "ex", default_value = "default")]
@@ -254,6 +631,31 @@ pub enum Crater {
         force: bool,
     },
 
+    #[structopt(
+        name = "regenerate-report",
+        about = "queue the report of a completed experiment to be generated again by the server"
+    )]
+    RegenerateReport {
+        #[structopt(name = "experiment", long = "ex", default_value = "default")]
+        ex: Ex,
+    },
+
+    #[structopt(
+        name = "export-results",
+        about = "export the results of an experiment as newline-delimited JSON"
+    )]
+    ExportResults {
+        #[structopt(name = "experiment", long = "ex", default_value = "default")]
+        ex: Ex,
+        #[structopt(
+            name = "dest",
+            long = "dest",
+            parse(from_os_str),
+            help = "File to write the results to (the standard output by default)."
+        )]
+        dest: Option<PathBuf>,
+    },
+
     #[structopt(name = "server")]
     Server,
 
@@ -262,7 +664,20 @@ pub enum Crater {
         #[structopt(name = "url")]
         url: String,
         #[structopt(name = "token")]
-        token: String,
+        token: Option<String>,
+        #[structopt(
+            name = "client-cert",
+            long = "client-cert",
+            help = "Authenticate with a client certificate (PKCS#12) instead of a token.",
+            parse(from_os_str)
+        )]
+        client_cert: Option<PathBuf>,
+        #[structopt(
+            name = "client-cert-password",
+            long = "client-cert-password",
+            default_value = ""
+        )]
+        client_cert_password: String,
         #[structopt(name = "threads", short = "t", long = "threads", default_value = "1")]
         threads: usize,
         #[structopt(name = "docker-env", long = "docker-env")]
@@ -285,6 +700,61 @@ pub enum Crater {
             help = "Disables the default capabilities for this platform."
         )]
         no_default_capabilities: bool,
+        #[structopt(
+            name = "targets",
+            help = "Target triples this agent can build for.",
+            long_help = "Target triples this agent can build for.\n\n \
+                         Experiments built for other targets won't be assigned to this agent. \
+                         When no target is listed the agent builds for any target supported by \
+                         its operating system.",
+            long,
+            raw(use_delimiter = "true")
+        )]
+        targets: Vec<String>,
+    },
+
+    #[structopt(
+        name = "remote-experiments",
+        about = "list the experiments of a Crater server"
+    )]
+    RemoteExperiments {
+        #[structopt(name = "url")]
+        url: String,
+        #[structopt(name = "token")]
+        token: String,
+    },
+
+    #[structopt(
+        name = "remote-results",
+        about = "fetch the results of an experiment from a Crater server"
+    )]
+    RemoteResults {
+        #[structopt(name = "url")]
+        url: String,
+        #[structopt(name = "token")]
+        token: String,
+        #[structopt(name = "experiment", long = "ex", default_value = "default")]
+        ex: Ex,
+        #[structopt(name = "classification", long = "classification")]
+        classification: Option<Comparison>,
+    },
+
+    #[structopt(
+        name = "remote-queue",
+        about = "queue an experiment on a Crater server"
+    )]
+    RemoteQueue {
+        #[structopt(name = "url")]
+        url: String,
+        #[structopt(name = "token")]
+        token: String,
+        #[structopt(name = "experiment", long = "ex")]
+        ex: Ex,
+        #[structopt(
+            name = "args",
+            help = "Arguments of the experiment, with the same syntax as the `run` command of the bot."
+        )]
+        args: Vec<String>,
     },
 
     #[structopt(
@@ -311,7 +781,11 @@ pub enum Crater {
 impl Crater {
     pub fn run(&self) -> Fallible<()> {
         match *self {
-            Crater::CreateLists { ref lists } => {
+            Crater::CreateLists {
+                ref lists,
+                metadata,
+                ref registry_snapshot,
+            } => {
                 let mut lists: HashSet<_> = lists.iter().map(|s| s.as_str()).collect();
 
                 let config = Config::load()?;
@@ -319,12 +793,18 @@ impl Crater {
                 let ctx = ActionsCtx::new(&db, &config);
 
                 let action = if lists.is_empty() {
-                    actions::UpdateLists::default()
+                    actions::UpdateLists {
+                        metadata,
+                        registry_snapshot: registry_snapshot.clone(),
+                        ..actions::UpdateLists::default()
+                    }
                 } else {
                     actions::UpdateLists {
                         github: lists.remove("github"),
                         registry: lists.remove("registry"),
                         local: lists.remove("local"),
+                        registry_snapshot: registry_snapshot.clone(),
+                        metadata,
                     }
                 };
 
@@ -344,29 +824,96 @@ impl Crater {
                 ref ex,
                 ref tc1,
                 ref tc2,
+                ref intermediate_toolchains,
                 ref mode,
                 ref crates,
+                ref crate_list_file,
                 ref cap_lints,
                 ref priority,
                 ref ignore_blacklist,
                 ref assign,
                 ref requirement,
+                ref namespace,
+                ref edition,
+                ref max_rust_version,
+                ref build_targets,
+                ref test_flags,
+                ref dist_server,
+                ref rustc_wrapper,
+                timings,
+                ref targets,
+                ref tags,
             } => {
                 let config = Config::load()?;
                 let db = Database::open()?;
                 let ctx = ActionsCtx::new(&db, &config);
 
+                // The crates of the file are stored as a list named after the experiment
+                let crates = if let Some(path) = crate_list_file {
+                    let list = std::fs::read_to_string(path)
+                        .with_context(|_| format!("failed to read {}", path.display()))?;
+                    actions::UploadCrateList {
+                        name: ex.0.clone(),
+                        list,
+                    }
+                    .apply(&ctx)?;
+                    CrateSelect::List(ex.0.clone())
+                } else {
+                    crates.clone()
+                };
+
+                let mut toolchains = vec![tc1.clone()];
+                toolchains.extend(intermediate_toolchains.iter().cloned());
+                toolchains.push(tc2.clone());
+
                 actions::CreateExperiment {
                     name: ex.0.clone(),
-                    toolchains: [tc1.clone(), tc2.clone()],
+                    namespace: namespace.clone(),
+                    toolchains,
                     mode: *mode,
-                    crates: *crates,
+                    crates,
                     cap_lints: *cap_lints,
                     priority: *priority,
                     github_issue: None,
                     ignore_blacklist: *ignore_blacklist,
                     assign: assign.clone(),
                     requirement: requirement.clone(),
+                    edition: *edition,
+                    max_rust_version: max_rust_version.clone(),
+                    build_targets: *build_targets,
+                    test_flags: test_flags.clone().unwrap_or_default(),
+                    dist_server: dist_server.clone(),
+                    rustc_wrapper: rustc_wrapper.clone(),
+                    timings,
+                    targets: targets.clone().unwrap_or_default(),
+                    tags: tags.clone().unwrap_or_default(),
+                }
+                .apply(&ctx)?;
+            }
+            Crater::Bisect {
+                ref ex,
+                ref krate,
+                start,
+                end,
+                mode,
+                ref test_flags,
+                priority,
+                ref namespace,
+            } => {
+                let config = Config::load()?;
+                let db = Database::open()?;
+                let ctx = ActionsCtx::new(&db, &config);
+
+                actions::BisectRegression {
+                    name: ex.0.clone(),
+                    namespace: namespace.clone(),
+                    krate: parse_crate(&db, krate)?,
+                    start,
+                    end,
+                    mode,
+                    test_flags: test_flags.clone().unwrap_or_default(),
+                    priority,
+                    github_issue: None,
                 }
                 .apply(&ctx)?;
             }
@@ -382,6 +929,15 @@ impl Crater {
                 ref no_ignore_blacklist,
                 ref assign,
                 ref requirement,
+                ref edition,
+                ref max_rust_version,
+                ref build_targets,
+                ref test_flags,
+                ref dist_server,
+                ref rustc_wrapper,
+                timings,
+                no_timings,
+                ref targets,
             } => {
                 let config = Config::load()?;
                 let db = Database::open()?;
@@ -394,27 +950,255 @@ impl Crater {
                 } else {
                     None
                 };
+                let timings = if timings {
+                    Some(true)
+                } else if no_timings {
+                    Some(false)
+                } else {
+                    None
+                };
 
                 actions::EditExperiment {
                     name: name.clone(),
                     toolchains: [tc1.clone(), tc2.clone()],
                     mode: *mode,
-                    crates: *crates,
+                    crates: crates.clone(),
                     cap_lints: *cap_lints,
                     priority: *priority,
                     ignore_blacklist,
                     assign: assign.clone(),
                     requirement: requirement.clone(),
+                    edition: *edition,
+                    max_rust_version: max_rust_version.clone(),
+                    build_targets: *build_targets,
+                    test_flags: test_flags.clone(),
+                    dist_server: dist_server.clone(),
+                    rustc_wrapper: rustc_wrapper.clone(),
+                    timings,
+                    targets: targets.clone(),
                 }
                 .apply(&ctx)?;
             }
-            Crater::DeleteEx { ref ex } => {
+            Crater::DeleteEx {
+                ref ex,
+                report,
+                ref report_dest,
+            } => {
                 let config = Config::load()?;
                 let db = Database::open()?;
                 let ctx = ActionsCtx::new(&db, &config);
 
+                // The report is removed first, so the deletion can be retried if that fails
+                if let Some(removal) = report {
+                    if !Experiment::exists(&db, &ex.0)? {
+                        bail!("missing experiment: {}", ex.0);
+                    }
+                    match report_dest {
+                        Some(dest) if dest.starts_with("s3://") => {
+                            let prefix: report::S3Prefix = dest.parse()?;
+                            let client = report::get_client_for_bucket(&prefix.bucket)?;
+                            let writer = report::S3Writer::create(client, prefix)?;
+                            report::remove(&ex.0, removal, &writer)?;
+                        }
+                        Some(dest) => {
+                            let writer = report::FileWriter::create(dest.into())?;
+                            report::remove(&ex.0, removal, &writer)?;
+                        }
+                        None => {
+                            let bucket = server::tokens::Tokens::load()?.reports_bucket;
+                            let prefix = format!("s3://{}/{}", bucket.bucket, ex.0).parse()?;
+                            let writer =
+                                report::S3Writer::create(Box::new(bucket.s3_client()?), prefix)?;
+                            report::remove(&ex.0, removal, &writer)?;
+                        }
+                    }
+                }
+
                 actions::DeleteExperiment { name: ex.0.clone() }.apply(&ctx)?;
             }
+            Crater::RegenerateReport { ref ex } => {
+                let config = Config::load()?;
+                let db = Database::open()?;
+                let ctx = ActionsCtx::new(&db, &config);
+
+                actions::RegenerateReport { name: ex.0.clone() }.apply(&ctx)?;
+            }
+            Crater::ExportResults { ref ex, ref dest } => {
+                let config = Config::load()?;
+                let db = Database::open()?;
+
+                if let Some(experiment) = Experiment::get(&db, &ex.0)? {
+                    let result_db = DatabaseDB::new(&db);
+                    let crates = experiment.get_crates(&db)?;
+                    let stdout = io::stdout();
+                    let mut dest: Box<dyn Write> = match dest {
+                        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                        None => Box::new(BufWriter::new(stdout.lock())),
+                    };
+                    report::export_ndjson(&result_db, &config, &experiment, &crates, &mut dest)?;
+                } else {
+                    bail!("missing experiment: {}", ex.0);
+                }
+            }
+            Crater::RenameEx {
+                ref ex,
+                ref new_name,
+            } => {
+                let config = Config::load()?;
+                let db = Database::open()?;
+                let ctx = ActionsCtx::new(&db, &config);
+
+                actions::RenameExperiment {
+                    name: ex.0.clone(),
+                    new_name: new_name.clone(),
+                }
+                .apply(&ctx)?;
+            }
+            Crater::CloneEx {
+                ref source,
+                ref new_name,
+                ref tc1,
+                ref tc2,
+                ref mode,
+                ref crates,
+                ref cap_lints,
+                ref priority,
+                ignore_blacklist,
+                no_ignore_blacklist,
+                ref assign,
+                ref requirement,
+                ref edition,
+                ref max_rust_version,
+                ref build_targets,
+                ref test_flags,
+                ref dist_server,
+                ref rustc_wrapper,
+                timings,
+                no_timings,
+                ref targets,
+            } => {
+                let config = Config::load()?;
+                let db = Database::open()?;
+                let ctx = ActionsCtx::new(&db, &config);
+
+                let ignore_blacklist = if ignore_blacklist {
+                    Some(true)
+                } else if no_ignore_blacklist {
+                    Some(false)
+                } else {
+                    None
+                };
+                let timings = if timings {
+                    Some(true)
+                } else if no_timings {
+                    Some(false)
+                } else {
+                    None
+                };
+
+                actions::CloneExperiment {
+                    name: source.0.clone(),
+                    new_name: new_name.clone(),
+                    github_issue: None,
+                    toolchains: [tc1.clone(), tc2.clone()],
+                    crates: crates.clone(),
+                    mode: *mode,
+                    cap_lints: *cap_lints,
+                    priority: *priority,
+                    ignore_blacklist,
+                    assign: assign.clone(),
+                    requirement: requirement.clone(),
+                    edition: *edition,
+                    max_rust_version: max_rust_version.clone(),
+                    build_targets: *build_targets,
+                    test_flags: test_flags.clone(),
+                    dist_server: dist_server.clone(),
+                    rustc_wrapper: rustc_wrapper.clone(),
+                    timings,
+                    targets: targets.clone(),
+                }
+                .apply(&ctx)?;
+            }
+            Crater::TagEx { ref ex, ref tags } => {
+                let config = Config::load()?;
+                let db = Database::open()?;
+                let ctx = ActionsCtx::new(&db, &config);
+
+                actions::TagExperiment {
+                    name: ex.0.clone(),
+                    tags: tags.clone(),
+                }
+                .apply(&ctx)?;
+            }
+            Crater::CancelEx { ref ex } => {
+                let config = Config::load()?;
+                let db = Database::open()?;
+                let ctx = ActionsCtx::new(&db, &config);
+
+                actions::CancelExperiment { name: ex.0.clone() }.apply(&ctx)?;
+            }
+            Crater::ArchiveEx { ref ex, older_than } => {
+                let config = Config::load()?;
+                let db = Database::open()?;
+                let ctx = ActionsCtx::new(&db, &config);
+
+                let mut names = ex.iter().map(|ex| ex.0.clone()).collect::<Vec<_>>();
+                if let Some(days) = older_than {
+                    let threshold = Utc::now() - ChronoDuration::days(days);
+                    names.extend(
+                        Experiment::all(&db)?
+                            .into_iter()
+                            .filter(|ex| match ex.status {
+                                Status::Completed
+                                | Status::Cancelled
+                                | Status::Failed
+                                | Status::ToolchainInstallFailed => {
+                                    ex.completed_at.unwrap_or(ex.created_at) < threshold
+                                }
+                                _ => false,
+                            })
+                            .map(|ex| ex.name),
+                    );
+                }
+                if names.is_empty() {
+                    bail!("either --ex or --older-than must be provided");
+                }
+
+                for name in names {
+                    actions::ArchiveExperiment { name: name.clone() }.apply(&ctx)?;
+                    info!("archived experiment {}", name);
+                }
+            }
+            Crater::PinEx { ref ex, unpin } => {
+                let db = Database::open()?;
+
+                if let Some(mut experiment) = Experiment::get(&db, &ex.0)? {
+                    experiment.set_pinned(&db, !unpin)?;
+                    audit::record(
+                        &db,
+                        &Actor::CLI,
+                        if unpin {
+                            AuditAction::UnpinExperiment
+                        } else {
+                            AuditAction::PinExperiment
+                        },
+                        Some(&experiment.name),
+                        &json!({}),
+                    )?;
+                } else {
+                    bail!("missing experiment {}", ex.0);
+                }
+            }
+            Crater::EnforceRetention { dry_run } => {
+                let mut config = Config::load()?;
+                let db = Database::open()?;
+
+                config.server.retention.dry_run |= dry_run;
+                let plan = retention::run(&db, &config, &Actor::CLI)?;
+                if plan.is_empty() {
+                    info!("no data to remove");
+                }
+            }
             Crater::DeleteAllResults { ref ex } => {
                 let db = Database::open()?;
                 let result_db = DatabaseDB::new(&db);
@@ -422,6 +1206,13 @@ impl Crater {
                 if let Some(mut experiment) = Experiment::get(&db, &ex.0)? {
                     result_db.delete_all_results(&experiment)?;
                     experiment.set_status(&db, Status::Queued)?;
+                    audit::record(
+                        &db,
+                        &Actor::CLI,
+                        AuditAction::DeleteResults,
+                        Some(&experiment.name),
+                        &json!({}),
+                    )?;
                 } else {
                     bail!("missing experiment {}", ex.0);
                 }
@@ -444,10 +1235,45 @@ impl Crater {
                     }
 
                     experiment.set_status(&db, Status::Queued)?;
+                    audit::record(
+                        &db,
+                        &Actor::CLI,
+                        AuditAction::DeleteResults,
+                        Some(&experiment.name),
+                        &json!({
+                            "toolchain": tc.as_ref().map(|tc| tc.to_string()),
+                            "crate": krate,
+                        }),
+                    )?;
                 } else {
                     bail!("missing experiment {}", ex.0);
                 }
             }
+            Crater::EnableMaintenance { ref message } => {
+                let db = Database::open()?;
+                maintenance::enable(&db, &Actor::CLI, message.as_ref().map(|m| m.as_str()))?;
+            }
+            Crater::DisableMaintenance => {
+                let db = Database::open()?;
+                maintenance::disable(&db, &Actor::CLI)?;
+            }
+            Crater::ReplayWebhooks { ref ids } => {
+                let db = Database::open()?;
+                let events = webhook_events::replay(&db, ids)?;
+                for event in &events {
+                    let outcome = match event.last_error {
+                        Some(ref error) => error.as_str(),
+                        None => "processed",
+                    };
+                    info!(
+                        "queued webhook event {} ({}, received at {}): {}",
+                        event.id, event.event, event.received_at, outcome
+                    );
+                }
+                if events.is_empty() {
+                    info!("no webhook events to replay");
+                }
+            }
             Crater::RunGraph {
                 ref ex,
                 threads,
@@ -481,7 +1307,7 @@ impl Crater {
                         )?,
                         &experiment.get_uncompleted_crates(&db)?,
                         &result_db,
-                        threads,
+                        &runner::Workers::all(threads),
                         &config,
                     )?;
                     experiment.set_status(&db, Status::NeedsReport)?;
@@ -578,11 +1404,14 @@ impl Crater {
             Crater::Agent {
                 ref url,
                 ref token,
+                ref client_cert,
+                ref client_cert_password,
                 threads,
                 ref docker_env,
                 fast_workspace_init,
                 ref capabilities,
                 no_default_capabilities,
+                ref targets,
             } => {
                 let mut caps = if no_default_capabilities {
                     Capabilities::default()
@@ -590,16 +1419,67 @@ impl Crater {
                     default_capabilities_for_target()
                 };
                 caps.extend(capabilities.clone().into_iter());
+                caps.add_targets(targets);
+
+                let credentials = match (token, client_cert) {
+                    (Some(token), None) => agent::Credentials::Token(token.clone()),
+                    (None, Some(path)) => agent::Credentials::ClientCertificate {
+                        path: path.clone(),
+                        password: client_cert_password.clone(),
+                    },
+                    (Some(_), Some(_)) => {
+                        bail!("a token and a client certificate can't be both provided")
+                    }
+                    (None, None) => {
+                        bail!("either a token or a client certificate must be provided")
+                    }
+                };
 
                 agent::run(
                     url,
-                    token,
+                    &credentials,
                     threads,
                     &caps,
                     &self
                         .workspace(docker_env.as_ref().map(|s| s.as_str()), fast_workspace_init)?,
                 )?;
             }
+            Crater::RemoteExperiments { ref url, ref token } => {
+                let client = ApiClient::new(url, token);
+                let query = ExperimentsQuery::default();
+                for ex in Runtime::new()?.block_on(client.experiments(&query))? {
+                    println!("{} {} {}", ex.name, ex.status, ex.toolchains.join(" "));
+                }
+            }
+            Crater::RemoteResults {
+                ref url,
+                ref token,
+                ref ex,
+                classification,
+            } => {
+                let client = ApiClient::new(url, token);
+                let query = ResultsQuery {
+                    classification,
+                    ..ResultsQuery::default()
+                };
+                for krate in Runtime::new()?.block_on(client.results(&ex.0, &query))? {
+                    println!("{} {}", krate.name, krate.classification);
+                }
+            }
+            Crater::RemoteQueue {
+                ref url,
+                ref token,
+                ref ex,
+                ref args,
+            } => {
+                let client = ApiClient::new(url, token);
+                let experiment = QueueExperiment {
+                    name: ex.0.clone(),
+                    args: args.join(" "),
+                };
+                Runtime::new()?.block_on(client.queue_experiment(&experiment))?;
+                println!("queued experiment {}", ex.0);
+            }
             Crater::DumpTasksGraph { ref dest, ref ex } => {
                 let config = Config::load()?;
                 let db = Database::open()?;
@@ -0,0 +1,253 @@
+//! Client of the HTTP APIs of the Crater server: the public API, documented in
+//! `docs/public-http-api.md`, and the agent API, documented in `docs/agent-http-api.md`.
+//!
+//! All the requests are asynchronous, returning futures that need to be run on a Tokio runtime.
+
+use crate::agent::{AgentWorkers, Capabilities};
+use crate::audit::AuditEntry;
+use crate::experiments::Progress;
+use crate::prelude::*;
+use crate::server::api_types::{
+    AgentConfig, AgentSummary, ApiResponse, AuditLogQuery, CrateResults, CraterToken,
+    ExperimentSummary, ExperimentsQuery, NextExperiment, QueueExperiment, ResultsPage,
+    ResultsQuery, ToolchainDetails, AGENT_VERSION_HEADER,
+};
+use futures::{future, Future};
+use http::{
+    header::{AUTHORIZATION, USER_AGENT},
+    Method, StatusCode,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use reqwest::r#async::{Client, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+/// Characters escaped in the experiment names put in the paths of the requests.
+const PATH_SEGMENT_ENCODE_SET: AsciiSet = CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Path of an endpoint of the public API about a single experiment.
+fn experiment_path(ex: &str, endpoint: &str) -> String {
+    format!(
+        "experiments/{}/{}",
+        utf8_percent_encode(ex, &PATH_SEGMENT_ENCODE_SET),
+        endpoint
+    )
+}
+
+/// Future returned by the requests of the client.
+pub type ApiFuture<T> = Box<dyn Future<Item = T, Error = failure::Error> + Send>;
+
+#[derive(Debug, Fail)]
+pub enum ClientError {
+    #[fail(display = "experiment or endpoint not found")]
+    NotFound,
+    #[fail(display = "Crater server unavailable")]
+    ServerUnavailable,
+    #[fail(display = "invalid authorization token")]
+    InvalidAuthorizationToken,
+    #[fail(display = "internal server error: {}", _0)]
+    InternalServerError(String),
+    #[fail(display = "bad request: {}", _0)]
+    BadRequest(String),
+    #[fail(
+        display = "this client is outdated: the server requires at least version {}",
+        _0
+    )]
+    OutdatedClient(String),
+}
+
+#[derive(Clone)]
+pub struct ApiClient {
+    url: String,
+    token: String,
+    client: Client,
+}
+
+impl ApiClient {
+    /// Create a client of the server at `url`, authenticating with `token`. Read-only tokens can
+    /// only fetch data from the public API, queueing experiments requires a token from the
+    /// `queue` section of the server tokens, and the agent API requires an agent token.
+    pub fn new(url: &str, token: &str) -> Self {
+        ApiClient {
+            url: url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            client: Client::new(),
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.client
+            .request(method, &format!("{}/{}", self.url, path))
+            .header(
+                AUTHORIZATION,
+                (CraterToken {
+                    token: self.token.clone(),
+                })
+                .to_string(),
+            )
+            .header(USER_AGENT, crate::USER_AGENT.clone())
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.request(Method::GET, &format!("api/{}", path))
+    }
+
+    fn agent(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, &format!("agent-api/{}", path))
+            .header(AGENT_VERSION_HEADER, crate::CRATER_VERSION)
+    }
+
+    fn send<T: DeserializeOwned + Send + 'static>(request: RequestBuilder) -> ApiFuture<T> {
+        Box::new(
+            request
+                .send()
+                .from_err::<failure::Error>()
+                .and_then(|mut resp| -> ApiFuture<T> {
+                    match resp.status() {
+                        StatusCode::BAD_GATEWAY
+                        | StatusCode::SERVICE_UNAVAILABLE
+                        | StatusCode::GATEWAY_TIMEOUT => {
+                            let err = ClientError::ServerUnavailable.into();
+                            return Box::new(future::err::<T, failure::Error>(err));
+                        }
+                        _ => {}
+                    }
+
+                    Box::new(
+                        resp.json::<ApiResponse<T>>()
+                            .from_err::<failure::Error>()
+                            .and_then(|result| match result {
+                                ApiResponse::Success { result } => Ok(result),
+                                ApiResponse::InternalError { error } => {
+                                    Err(ClientError::InternalServerError(error).into())
+                                }
+                                ApiResponse::BadRequest { error } => {
+                                    Err(ClientError::BadRequest(error).into())
+                                }
+                                ApiResponse::Unauthorized => {
+                                    Err(ClientError::InvalidAuthorizationToken.into())
+                                }
+                                ApiResponse::NotFound => Err(ClientError::NotFound.into()),
+                                ApiResponse::UpgradeRequired { minimum_version } => {
+                                    Err(ClientError::OutdatedClient(minimum_version).into())
+                                }
+                            }),
+                    )
+                }),
+        )
+    }
+
+    /// List the experiments known by the server matching the query.
+    pub fn experiments(&self, query: &ExperimentsQuery) -> ApiFuture<Vec<ExperimentSummary>> {
+        Self::send(self.get("experiments").query(query))
+    }
+
+    /// Fetch a single page of results of an experiment.
+    pub fn results_page(&self, ex: &str, query: &ResultsQuery) -> ApiFuture<ResultsPage> {
+        Self::send(self.get(&experiment_path(ex, "results")).query(query))
+    }
+
+    /// Fetch all the results of an experiment matching the query, following the pagination.
+    pub fn results(&self, ex: &str, query: &ResultsQuery) -> ApiFuture<Vec<CrateResults>> {
+        let client = self.clone();
+        let ex = ex.to_string();
+        let query = query.clone();
+
+        Box::new(future::loop_fn(
+            (client, query, Vec::new()),
+            move |(client, mut query, mut results)| {
+                client.results_page(&ex, &query).map(move |page| {
+                    results.extend(page.results);
+                    match page.next_cursor {
+                        Some(cursor) => {
+                            query.cursor = Some(cursor);
+                            future::Loop::Continue((client, query, results))
+                        }
+                        None => future::Loop::Break(results),
+                    }
+                })
+            },
+        ))
+    }
+
+    /// Fetch the exact versions of the toolchains of an experiment.
+    pub fn toolchains(&self, ex: &str) -> ApiFuture<Vec<ToolchainDetails>> {
+        Self::send(self.get(&experiment_path(ex, "toolchains")))
+    }
+
+    /// Fetch the progress of an experiment, split by toolchain.
+    pub fn progress(&self, ex: &str) -> ApiFuture<Progress> {
+        Self::send(self.get(&experiment_path(ex, "progress")))
+    }
+
+    /// Fetch the most recent entries of the audit log.
+    pub fn audit_log(&self, query: &AuditLogQuery) -> ApiFuture<Vec<AuditEntry>> {
+        Self::send(self.get("audit-log").query(query))
+    }
+
+    /// List the agents known by the server, along with whether they're still reachable.
+    pub fn agents(&self) -> ApiFuture<Vec<AgentSummary>> {
+        Self::send(self.get("agents"))
+    }
+
+    /// Queue a new experiment, configured with the same arguments as the `run` command of the
+    /// bot.
+    pub fn queue_experiment(&self, experiment: &QueueExperiment) -> ApiFuture<()> {
+        Self::send(
+            self.request(Method::POST, "api/experiments")
+                .json(experiment),
+        )
+    }
+
+    /// Register the capabilities of the agent, fetching the configuration it should run with.
+    pub fn agent_config(&self, caps: &Capabilities) -> ApiFuture<AgentConfig> {
+        Self::send(self.agent("config").json(caps))
+    }
+
+    /// Fetch the next experiment the agent should run with its idle workers, if any.
+    pub fn next_experiment(&self, workers: &AgentWorkers) -> ApiFuture<Option<NextExperiment>> {
+        Self::send(self.agent("next-experiment").json(workers))
+    }
+
+    /// Tell the server the agent is still alive.
+    pub fn heartbeat(&self) -> ApiFuture<bool> {
+        Self::send(self.agent("heartbeat"))
+    }
+
+    /// Check whether an experiment the agent is running was cancelled.
+    pub fn experiment_cancelled(&self, ex: &str) -> ApiFuture<bool> {
+        Self::send(
+            self.agent("experiment-cancelled")
+                .json(&json!({ "experiment-name": ex })),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::experiment_path;
+
+    #[test]
+    fn test_experiment_path() {
+        assert_eq!(
+            experiment_path("pr-12345", "results"),
+            "experiments/pr-12345/results"
+        );
+        assert_eq!(
+            experiment_path("infra:foo bar/baz?#", "progress"),
+            "experiments/infra:foo%20bar%2Fbaz%3F%23/progress"
+        );
+    }
+}
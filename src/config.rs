@@ -1,6 +1,10 @@
 use crate::crates::Crate;
+use crate::experiments::Mode;
 use crate::prelude::*;
+use crate::results::NormalizationRule;
+use crate::utils::cron::CronSchedule;
 use crate::utils::size::Size;
+use chrono::Duration;
 use log::LevelFilter;
 use regex::Regex;
 use rustwide::logging::LogStorage;
@@ -33,17 +37,195 @@ pub struct CrateConfig {
     pub quiet: bool,
     #[serde(default = "default_false")]
     pub broken: bool,
+    /// Why the crate is known to be broken regardless of the toolchains being compared. Its
+    /// results are listed separately in the reports, and don't count as regressions or fixes.
+    #[serde(default)]
+    pub known_broken: Option<String>,
 }
 
 fn default_false() -> bool {
     false
 }
 
+fn default_build_timeout() -> u64 {
+    15 * 60
+}
+
+fn default_chunk_lease_minutes() -> u32 {
+    30
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct ServerConfig {
     pub bot_acl: BotACL,
     pub labels: ServerLabels,
+    /// Agents older than this version are not allowed to run experiments.
+    #[serde(default)]
+    pub agent_min_version: Option<String>,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub try_experiments: Option<TryExperimentsConfig>,
+    #[serde(default)]
+    pub priority_aging: PriorityAgingConfig,
+    #[serde(default)]
+    pub partitioning: PartitioningConfig,
+    #[serde(default)]
+    pub chunks: ChunksConfig,
+    /// Queue again the experiments of the agents which missed this many heartbeats in a row.
+    /// Experiments stay assigned to unreachable agents if this is not set.
+    #[serde(default)]
+    pub requeue_after_missed_heartbeats: Option<u32>,
+    /// Queue again the running experiments no agent sent results of for this many minutes, as
+    /// their agents are likely stuck.
+    #[serde(default)]
+    pub requeue_stalled_after_minutes: Option<u32>,
+    #[serde(default)]
+    pub schedules: Vec<ScheduleConfig>,
+    /// Authenticate agents with the CN of their client certificate, forwarded by the reverse
+    /// proxy in the header configured in the tokens. Anyone able to set that header can act as
+    /// any agent, so this must only be enabled behind a proxy overwriting it.
+    #[serde(default)]
+    pub trust_client_cert_header: bool,
+}
+
+/// Experiments created automatically when a try build of a pull request completes.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TryExperimentsConfig {
+    /// Only the pull requests with this label get an experiment.
+    pub label: String,
+    /// Arguments of the experiment, with the same syntax as the `run` command of the bot.
+    pub template: String,
+}
+
+/// Experiments created automatically on a recurring schedule, for example to monitor the
+/// ecosystem with every new nightly.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ScheduleConfig {
+    /// Name of the schedule, followed by the creation date in the names of its experiments.
+    pub name: String,
+    /// When the experiments are created.
+    pub cron: CronSchedule,
+    /// Arguments of the experiments, with the same syntax as the `run` command of the bot.
+    /// `{nightly}` and `{previous-nightly}` are replaced with the latest nightly published on the
+    /// dist server and the one published before it, and `{date}` with the date.
+    pub template: String,
+}
+
+/// How long the data of finished experiments is kept around.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RetentionConfig {
+    /// Remove the build logs of experiments finished more than this many days ago.
+    #[serde(default)]
+    pub logs_days: Option<u32>,
+    /// Delete the (unpinned) experiments finished more than this many days ago.
+    #[serde(default)]
+    pub experiments_days: Option<u32>,
+    /// Only report what would be removed, without deleting anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// How the priority of queued experiments grows while they wait, to prevent a steady stream of
+/// high priority experiments from starving the other ones.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PriorityAgingConfig {
+    /// Raise the priority of queued experiments by one every this many hours. The priority never
+    /// changes if this is not set.
+    #[serde(default)]
+    pub hours_per_level: Option<u32>,
+    /// Maximum amount the priority of an experiment can be raised by.
+    #[serde(default)]
+    pub max_increase: Option<i32>,
+}
+
+impl PriorityAgingConfig {
+    /// Priority of an experiment with the base `priority`, after waiting in the queue for `waited`.
+    pub fn effective_priority(&self, priority: i32, waited: Duration) -> i32 {
+        let increase = match self.hours_per_level {
+            Some(hours) if hours > 0 => waited.num_hours().max(0) / i64::from(hours),
+            _ => 0,
+        };
+        let increase = match self.max_increase {
+            Some(max) => increase.min(i64::from(max.max(0))),
+            None => increase,
+        };
+        priority.saturating_add(increase.min(i64::from(i32::max_value())) as i32)
+    }
+}
+
+/// How the workers of large agents are split between multiple experiments, allowing small
+/// experiments to run alongside the full runs instead of waiting for them to finish.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PartitioningConfig {
+    /// Experiments with up to this many crates are small. Agents run a single experiment at a
+    /// time with all their workers if this is not set.
+    #[serde(default)]
+    pub small_experiment_crates: Option<u32>,
+    /// Workers of an agent running each small experiment. The other experiments never use these
+    /// workers, leaving them available for the small experiments.
+    #[serde(default)]
+    pub small_experiment_workers: usize,
+}
+
+impl PartitioningConfig {
+    /// Number of workers an experiment with `crates` crates should be run with, on an agent with
+    /// `total` workers of which `idle` are not running any experiment. `None` is returned if the
+    /// experiment can't be run by the agent until some of its workers finish.
+    pub fn workers(&self, crates: u32, total: usize, idle: usize) -> Option<usize> {
+        let small = self.small_experiment_workers;
+        match self.small_experiment_crates {
+            // Agents too small to be split run a single experiment like without partitioning
+            Some(max_crates) if small > 0 && small < total => {
+                if crates <= max_crates {
+                    Some(idle.min(small)).filter(|&workers| workers > 0)
+                } else if idle >= total - small {
+                    Some(total - small)
+                } else {
+                    None
+                }
+            }
+            _ if idle == total => Some(total),
+            _ => None,
+        }
+    }
+}
+
+/// How the crates of an experiment are split between the agents, allowing multiple agents to run
+/// the same experiment at the same time.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ChunksConfig {
+    /// Number of crates an agent claims at a time. Agents claim all the remaining crates of the
+    /// experiment if this is not set.
+    #[serde(default)]
+    pub crates: Option<u32>,
+    /// The crates claimed by an agent can be claimed by other agents when the agent didn't send a
+    /// heartbeat for this many minutes.
+    #[serde(default = "default_chunk_lease_minutes")]
+    pub lease_minutes: u32,
+}
+
+impl Default for ChunksConfig {
+    fn default() -> Self {
+        ChunksConfig {
+            crates: None,
+            lease_minutes: default_chunk_lease_minutes(),
+        }
+    }
+}
+
+impl ChunksConfig {
+    /// How long the crates stay claimed by an agent without hearing from it.
+    pub fn lease(&self) -> Duration {
+        Duration::minutes(i64::from(self.lease_minutes))
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -76,6 +258,76 @@ pub struct SandboxConfig {
     pub memory_limit: Size,
     pub build_log_max_size: Size,
     pub build_log_max_lines: usize,
+    #[serde(default)]
+    pub cpu_limit: Option<f32>,
+    /// Pin the builds of each worker to its own subset of the agent's CPUs.
+    #[serde(default = "default_false")]
+    pub pin_cpus: bool,
+    /// Maximum duration of every Cargo command executed in the sandbox, in seconds.
+    #[serde(default = "default_build_timeout")]
+    pub build_timeout: u64,
+}
+
+/// Where the toolchains tested by the experiments are installed from.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ToolchainsConfig {
+    /// Server rustup downloads the toolchains from, instead of static.rust-lang.org.
+    #[serde(default)]
+    pub dist_server: Option<String>,
+    /// Disk space the installed toolchains can use before the least recently used ones are removed.
+    #[serde(default)]
+    pub cache_size: Option<Size>,
+}
+
+/// How the build logs are compared, ignoring the parts changing between runs of the same build.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LogsConfig {
+    /// Normalization rules applied to the logs of the experiments in each mode, instead of the
+    /// default ones.
+    #[serde(default)]
+    pub normalize: HashMap<Mode, Vec<NormalizationRule>>,
+}
+
+impl LogsConfig {
+    pub fn normalization_rules(&self, mode: Mode) -> &[NormalizationRule] {
+        self.normalize
+            .get(&mode)
+            .map(|rules| rules.as_slice())
+            .unwrap_or(NormalizationRule::DEFAULT)
+    }
+}
+
+/// How the crates are fixed by the experiments in the `fix` mode.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FixConfig {
+    /// Arguments passed to `cargo fix`, selecting which fixes are applied.
+    #[serde(default = "default_fix_args")]
+    pub args: Vec<String>,
+}
+
+fn default_fix_args() -> Vec<String> {
+    vec!["--edition".into()]
+}
+
+impl Default for FixConfig {
+    fn default() -> Self {
+        FixConfig {
+            args: default_fix_args(),
+        }
+    }
+}
+
+/// Snapshots of the build directory captured when a crate fails, to diagnose the failures the
+/// logs alone can't explain.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SnapshotsConfig {
+    /// Maximum size of the files included in each snapshot, which are only captured if it's set.
+    #[serde(default)]
+    pub max_size: Option<Size>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -87,6 +339,14 @@ pub struct Config {
     pub local_crates: HashMap<String, CrateConfig>,
     pub server: ServerConfig,
     pub sandbox: SandboxConfig,
+    #[serde(default)]
+    pub toolchains: ToolchainsConfig,
+    #[serde(default)]
+    pub logs: LogsConfig,
+    #[serde(default)]
+    pub fix: FixConfig,
+    #[serde(default)]
+    pub snapshots: SnapshotsConfig,
 }
 
 impl Config {
@@ -104,11 +364,7 @@ impl Config {
     }
 
     fn crate_config(&self, c: &Crate) -> Option<&CrateConfig> {
-        match *c {
-            Crate::Registry(ref details) => self.crates.get(&details.name),
-            Crate::GitHub(ref repo) => self.github_repos.get(&repo.slug()),
-            Crate::Local(ref name) => self.local_crates.get(name),
-        }
+        c.source().config(self)
     }
 
     pub fn should_skip(&self, c: &Crate) -> bool {
@@ -127,6 +383,12 @@ impl Config {
         self.crate_config(c).map(|c| c.broken).unwrap_or(false)
     }
 
+    pub fn known_issue(&self, c: &Crate) -> Option<&str> {
+        self.crate_config(c)
+            .and_then(|c| c.known_broken.as_ref())
+            .map(|reason| reason.as_str())
+    }
+
     pub fn demo_crates(&self) -> &DemoCrates {
         &self.demo_crates
     }
@@ -146,7 +408,7 @@ impl Config {
         let mut has_errors = Self::check_for_dup_keys(&buffer).is_err();
         let cfg: Self = ::toml::from_str(&buffer)?;
         let db = crate::db::Database::open()?;
-        let crates = crate::crates::lists::get_crates(CrateSelect::Full, &db, &cfg)?;
+        let crates = crate::crates::lists::get_crates(CrateSelect::Full, &db, &cfg, None)?;
         has_errors |= cfg.check_for_missing_crates(&crates).is_err();
         has_errors |= cfg.check_for_missing_repos(&crates).is_err();
         if has_errors {
@@ -246,6 +508,9 @@ impl Default for Config {
                 memory_limit: Size::Gigabytes(2),
                 build_log_max_size: Size::Megabytes(1),
                 build_log_max_lines: 1000,
+                cpu_limit: None,
+                pin_cpus: false,
+                build_timeout: default_build_timeout(),
             },
             server: ServerConfig {
                 bot_acl: BotACL {
@@ -257,7 +522,21 @@ impl Default for Config {
                     experiment_queued: "".into(),
                     experiment_completed: "".into(),
                 },
+                agent_min_version: None,
+                retention: RetentionConfig::default(),
+                try_experiments: None,
+                priority_aging: PriorityAgingConfig::default(),
+                partitioning: PartitioningConfig::default(),
+                chunks: ChunksConfig::default(),
+                requeue_after_missed_heartbeats: None,
+                requeue_stalled_after_minutes: None,
+                schedules: Vec::new(),
+                trust_client_cert_header: false,
             },
+            toolchains: ToolchainsConfig::default(),
+            logs: LogsConfig::default(),
+            fix: FixConfig::default(),
+            snapshots: SnapshotsConfig::default(),
         }
     }
 }
@@ -273,8 +552,11 @@ impl From<&'_ Config> for LogStorage {
 
 #[cfg(test)]
 mod tests {
-    use super::Config;
+    use super::{Config, PartitioningConfig, PriorityAgingConfig};
     use crate::crates::{Crate, GitHubRepo, RegistryCrate};
+    use crate::experiments::Mode;
+    use crate::results::NormalizationRule;
+    use chrono::Duration;
 
     #[test]
     fn test_config() {
@@ -297,6 +579,7 @@ mod tests {
             "build-log-max-lines = 1000\n",
             "[crates]\n",
             "lazy_static = { skip = true }\n",
+            "esprit = { known-broken = \"broken Cargo.toml\" }\n",
             "[github-repos]\n",
             "\"rust-lang/rust\" = { quiet = true }\n", // :(
             "[local-crates]\n"
@@ -313,6 +596,20 @@ mod tests {
             version: "42".into(),
         })));
 
+        assert_eq!(
+            list.known_issue(&Crate::Registry(RegistryCrate {
+                name: "esprit".into(),
+                version: "42".into(),
+            })),
+            Some("broken Cargo.toml")
+        );
+        assert!(list
+            .known_issue(&Crate::Registry(RegistryCrate {
+                name: "lazy_static".into(),
+                version: "42".into(),
+            }))
+            .is_none());
+
         assert!(list.is_quiet(&Crate::GitHub(GitHubRepo {
             org: "rust-lang".into(),
             name: "rust".into(),
@@ -321,5 +618,91 @@ mod tests {
             org: "rust-lang".into(),
             name: "cargo".into(),
         })));
+
+        assert!(list.toolchains.dist_server.is_none());
+        assert!(list.toolchains.cache_size.is_none());
+    }
+
+    #[test]
+    fn test_logs_config() {
+        let config = concat!(
+            "[server.bot-acl]\n",
+            "rust-teams = false\n",
+            "github = []\n",
+            "[server.labels]\n",
+            "remove = \"\"\n",
+            "experiment-queued = \"\"\n",
+            "experiment-completed = \"\"\n",
+            "[demo-crates]\n",
+            "crates = []\n",
+            "github-repos = []\n",
+            "local-crates = []\n",
+            "[sandbox]\n",
+            "memory-limit = \"2G\"\n",
+            "build-log-max-size = \"2M\"\n",
+            "build-log-max-lines = 1000\n",
+            "[logs.normalize]\n",
+            "check-only = [\"timestamps\", \"hashes\"]\n",
+            "[crates]\n",
+            "[github-repos]\n",
+            "[local-crates]\n"
+        );
+
+        let config: Config = ::toml::from_str(&config).unwrap();
+        assert_eq!(
+            config.logs.normalization_rules(Mode::CheckOnly),
+            &[NormalizationRule::Timestamps, NormalizationRule::Hashes]
+        );
+        assert_eq!(
+            config.logs.normalization_rules(Mode::BuildAndTest),
+            NormalizationRule::DEFAULT
+        );
+    }
+
+    #[test]
+    fn test_priority_aging() {
+        let aging = PriorityAgingConfig::default();
+        assert_eq!(aging.effective_priority(0, Duration::days(30)), 0);
+
+        let aging = PriorityAgingConfig {
+            hours_per_level: Some(6),
+            max_increase: None,
+        };
+        assert_eq!(aging.effective_priority(0, Duration::hours(5)), 0);
+        assert_eq!(aging.effective_priority(0, Duration::hours(6)), 1);
+        assert_eq!(aging.effective_priority(2, Duration::days(2)), 10);
+        assert_eq!(aging.effective_priority(2, Duration::hours(-12)), 2);
+
+        let aging = PriorityAgingConfig {
+            hours_per_level: Some(6),
+            max_increase: Some(5),
+        };
+        assert_eq!(aging.effective_priority(2, Duration::days(2)), 7);
+    }
+
+    #[test]
+    fn test_partitioning() {
+        // Without partitioning experiments need all the workers
+        let partitioning = PartitioningConfig::default();
+        assert_eq!(partitioning.workers(10, 32, 32), Some(32));
+        assert_eq!(partitioning.workers(10, 32, 24), None);
+
+        let partitioning = PartitioningConfig {
+            small_experiment_crates: Some(1000),
+            small_experiment_workers: 8,
+        };
+        // Full runs leave the workers of the small experiments idle
+        assert_eq!(partitioning.workers(50_000, 32, 32), Some(24));
+        assert_eq!(partitioning.workers(50_000, 32, 8), None);
+        assert_eq!(partitioning.workers(50_000, 32, 24), Some(24));
+        // Small experiments run alongside them
+        assert_eq!(partitioning.workers(100, 32, 8), Some(8));
+        assert_eq!(partitioning.workers(100, 32, 32), Some(8));
+        assert_eq!(partitioning.workers(100, 32, 5), Some(5));
+        assert_eq!(partitioning.workers(100, 32, 0), None);
+        // Agents with few workers aren't split
+        assert_eq!(partitioning.workers(100, 8, 8), Some(8));
+        assert_eq!(partitioning.workers(50_000, 8, 8), Some(8));
+        assert_eq!(partitioning.workers(100, 8, 4), None);
     }
 }
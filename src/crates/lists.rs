@@ -1,19 +1,35 @@
 use crate::config::Config;
 use crate::crates::sources::github::GitHubRepo;
-use crate::crates::{Crate, RegistryCrate};
+use crate::crates::{is_git_url, Crate, RegistryCrate, GITHUB_URL_PREFIX};
 use crate::db::{Database, QueryUtils};
-use crate::experiments::CrateSelect;
+use crate::experiments::{qualified_name, CrateSelect, Experiment, Status};
 use crate::prelude::*;
+use crate::report::Comparison;
+use crate::results::{DatabaseDB, ReadResults};
 use chrono::Utc;
-use rand::{thread_rng, Rng};
-use std::collections::HashSet;
+use rand::{prng::XorShiftRng, thread_rng, Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
 
 pub(crate) use crate::crates::sources::{
-    github::GitHubList, local::LocalList, registry::RegistryList,
+    github::GitHubList,
+    local::LocalList,
+    registry::{crates_io_search, reverse_dependencies, unavailable_crates, RegistryList},
 };
 
 const SMALL_RANDOM_COUNT: usize = 20;
 
+#[derive(Debug, Fail)]
+pub(crate) enum CrateListError {
+    #[fail(display = "invalid name of the list of crates: {}", _0)]
+    InvalidName(String),
+    #[fail(display = "invalid git repository in the list: {}", _0)]
+    InvalidRepo(String),
+    #[fail(display = "unknown crate in the list: {}", _0)]
+    UnknownCrate(String),
+    #[fail(display = "the list doesn't contain any crate")]
+    Empty,
+}
+
 pub(crate) trait List {
     const NAME: &'static str;
 
@@ -62,10 +78,13 @@ pub(crate) trait List {
     }
 }
 
+/// Get the crates of a selection. `seed` is used to draw random samples of crates, which are
+/// different every time if it's not provided.
 pub(crate) fn get_crates(
     select: CrateSelect,
     db: &Database,
     config: &Config,
+    seed: Option<u32>,
 ) -> Fallible<Vec<Crate>> {
     let mut crates = Vec::new();
 
@@ -96,7 +115,8 @@ pub(crate) fn get_crates(
                 let add = match krate {
                     Crate::Registry(RegistryCrate { ref name, .. }) => demo_registry.remove(name),
                     Crate::GitHub(ref repo) => demo_github.remove(&repo.slug()),
-                    Crate::Local(ref name) => demo_local.remove(name),
+                    Crate::Local(ref krate) => demo_local.remove(&krate.name),
+                    _ => false,
                 };
 
                 if add {
@@ -119,24 +139,219 @@ pub(crate) fn get_crates(
             crates.append(&mut RegistryList::get(db)?);
             crates.append(&mut GitHubList::get(db)?);
 
-            let mut rng = thread_rng();
-            rng.shuffle(&mut crates);
-            crates.truncate(SMALL_RANDOM_COUNT);
+            crates = random_sample(crates, SMALL_RANDOM_COUNT, None);
+        }
+        CrateSelect::Random(count) => {
+            crates.append(&mut RegistryList::get(db)?);
+            crates.append(&mut GitHubList::get(db)?);
+            crates = random_sample(crates, count as usize, seed);
         }
-        CrateSelect::Top100 => {
+        CrateSelect::Top(count) => {
+            // The registry list is sorted by the number of downloads
             crates.append(&mut RegistryList::get(db)?);
-            crates.truncate(100);
+            crates.truncate(count);
         }
         CrateSelect::Local => {
             crates.append(&mut LocalList::get(db)?);
         }
         CrateSelect::Dummy => crates.push(Crate::GitHub(GitHubRepo::dummy())),
+        CrateSelect::LocalSource(source) => crates.push(Crate::from_local_source(&source)?),
+        CrateSelect::Regressed(parent) => {
+            crates.append(&mut regressed_crates(db, config, &parent)?)
+        }
+        CrateSelect::SameAs(source) => match Experiment::get(db, &source)? {
+            Some(ex) => crates.append(&mut ex.get_crates(db)?),
+            None => bail!("experiment {} doesn't exist", source),
+        },
+        CrateSelect::ReverseDeps(root, depth) => {
+            let names = reverse_dependencies(&root, depth)?;
+            crates.append(&mut registry_crates_named(db, &names)?);
+        }
+        CrateSelect::List(name) => crates.append(&mut uploaded_list(db, &name)?),
+        CrateSelect::Category(category) => {
+            let names = crates_io_search("category", &category)?;
+            crates.append(&mut registry_crates_named(db, &names)?);
+        }
+        CrateSelect::Keyword(keyword) => {
+            let names = crates_io_search("keyword", &keyword)?;
+            crates.append(&mut registry_crates_named(db, &names)?);
+        }
     }
 
+    // The same crate could be present in multiple lists: building it more than once would only
+    // waste time, as the results are stored per crate. The same package fetched from different
+    // sources (like a registry crate and its git repository) isn't a duplicate though, as their
+    // contents can differ: the repository could contain unpublished changes.
     crates.sort();
+    crates.dedup();
+    Ok(crates)
+}
+
+/// Crates of the registry list with one of the provided names, at the version in the list.
+fn registry_crates_named(db: &Database, names: &HashSet<String>) -> Fallible<Vec<Crate>> {
+    Ok(RegistryList::get(db)?
+        .into_iter()
+        .filter(|krate| match krate {
+            Crate::Registry(RegistryCrate { ref name, .. }) => names.contains(name),
+            _ => false,
+        })
+        .collect())
+}
+
+/// Pick `count` random crates from the list. The same crates are picked every time with the same
+/// seed, as long as the list contains the same crates.
+fn random_sample(mut crates: Vec<Crate>, count: usize, seed: Option<u32>) -> Vec<Crate> {
+    if let Some(seed) = seed {
+        // The order of the lists isn't stable, as they're sorted by popularity
+        crates.sort();
+        let mut bytes = [0; 16];
+        bytes[..4].copy_from_slice(&seed.to_le_bytes());
+        XorShiftRng::from_seed(bytes).shuffle(&mut crates);
+    } else {
+        thread_rng().shuffle(&mut crates);
+    }
+    crates.truncate(count);
+    crates
+}
+
+/// Crates that regressed on at least one of the targets of a finished experiment.
+fn regressed_crates(db: &Database, config: &Config, name: &str) -> Fallible<Vec<Crate>> {
+    let ex = match Experiment::get(db, name)? {
+        Some(ex) => ex,
+        None => bail!("experiment {} doesn't exist", name),
+    };
+    match ex.status {
+        Status::NeedsReport
+        | Status::GeneratingReport
+        | Status::ReportFailed
+        | Status::Completed => {}
+        _ => bail!(
+            "the regressions of experiment {} are only known once it's finished",
+            name
+        ),
+    }
+
+    let results = DatabaseDB::new(db);
+    let target_toolchains = ex.target_toolchains();
+    let mut regressed = Vec::new();
+    for krate in ex.get_crates(db)? {
+        for (_, toolchains) in &target_toolchains {
+            let res1 = results.load_test_result(&ex, &toolchains[0], &krate)?;
+            let res2 = results.load_test_result(&ex, &toolchains[1], &krate)?;
+            if crate::report::compare(config, &krate, res1, res2) == Comparison::Regressed {
+                regressed.push(krate);
+                break;
+            }
+        }
+    }
+
+    if regressed.is_empty() {
+        bail!("experiment {} has no regressions", name);
+    }
+    Ok(regressed)
+}
+
+/// Parse an explicit list of crates, with one crate on each line: either the URL of a git
+/// repository (optionally pinned with `?branch=<name>` or `?rev=<commit>`), the name of a registry
+/// crate (tested at the version in the registry list) or a registry crate followed by its version
+/// (`<name>-<version>`). Empty lines and lines starting with `#` are ignored.
+pub(crate) fn parse_crate_list(db: &Database, list: &str) -> Fallible<Vec<Crate>> {
+    let registry = RegistryList::get(db)?
+        .into_iter()
+        .filter_map(|krate| match krate {
+            Crate::Registry(krate) => Some((krate.name.clone(), krate)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut crates = Vec::new();
+    for line in list.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let krate = if line.starts_with(GITHUB_URL_PREFIX) && !line.contains('?') {
+            let path = line[GITHUB_URL_PREFIX.len()..].trim_end_matches('/');
+            match path.split('/').collect::<Vec<_>>().as_slice() {
+                [org, name] if !org.is_empty() && !name.is_empty() => Crate::GitHub(GitHubRepo {
+                    org: org.to_string(),
+                    name: name.to_string(),
+                }),
+                _ => return Err(CrateListError::InvalidRepo(line.into()).into()),
+            }
+        } else if is_git_url(line) {
+            match line.parse() {
+                Ok(krate) => Crate::Git(krate),
+                Err(_) => return Err(CrateListError::InvalidRepo(line.into()).into()),
+            }
+        } else if let Some(krate) = registry.get(line) {
+            Crate::Registry(krate.clone())
+        } else {
+            match line.parse() {
+                Ok(Crate::Registry(ref krate)) if registry.contains_key(&krate.name) => {
+                    Crate::Registry(krate.clone())
+                }
+                _ => return Err(CrateListError::UnknownCrate(line.into()).into()),
+            }
+        };
+        crates.push(krate);
+    }
+
+    if crates.is_empty() {
+        return Err(CrateListError::Empty.into());
+    }
     Ok(crates)
 }
 
+/// Parse a single crate, written in any of the forms accepted by the explicit lists of crates.
+pub fn parse_crate(db: &Database, krate: &str) -> Fallible<Crate> {
+    let mut crates = parse_crate_list(db, krate)?;
+    if crates.len() != 1 {
+        bail!("expected a single crate, found {}", crates.len());
+    }
+    Ok(crates.remove(0))
+}
+
+/// Store an explicit list of crates, replacing the existing list with the same name in the
+/// namespace. The list can then be selected with `list:<name>`, or `list:<namespace>:<name>`
+/// outside of the default namespace.
+pub(crate) fn store_crate_list(
+    db: &Database,
+    namespace: &str,
+    name: &str,
+    crates: &[Crate],
+) -> Fallible<()> {
+    // The name is part of the crate selection in the bot commands and of the API URLs
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if name.is_empty() || !valid {
+        return Err(CrateListError::InvalidName(name.into()).into());
+    }
+
+    db.execute(
+        "INSERT OR REPLACE INTO crate_lists (name, crates, uploaded_at) VALUES (?1, ?2, ?3);",
+        &[
+            &qualified_name(namespace, name),
+            &::serde_json::to_string(crates)?,
+            &Utc::now(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn uploaded_list(db: &Database, name: &str) -> Fallible<Vec<Crate>> {
+    let crates = db.get_row(
+        "SELECT crates FROM crate_lists WHERE name = ?1;",
+        &[&name],
+        |r| r.get::<_, String>("crates"),
+    )?;
+    match crates {
+        Some(crates) => Ok(::serde_json::from_str(&crates)?),
+        None => bail!("list of crates {} doesn't exist", name),
+    }
+}
+
 #[cfg(test)]
 pub(crate) fn setup_test_lists(db: &Database, config: &Config) -> Fallible<()> {
     use crate::actions::{Action, ActionsCtx, UpdateLists};
@@ -145,6 +360,181 @@ pub(crate) fn setup_test_lists(db: &Database, config: &Config) -> Fallible<()> {
         github: false,
         registry: false,
         local: true,
+        registry_snapshot: None,
+        metadata: true,
     }
     .apply(&ActionsCtx::new(db, config))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_crates, parse_crate_list, random_sample, setup_test_lists, store_crate_list, List,
+        RegistryList,
+    };
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::crates::{Crate, GitHubRepo, RegistryCrate};
+    use crate::db::{Database, QueryUtils};
+    use crate::experiments::{CrateSelect, Experiment, Status, DEFAULT_NAMESPACE};
+    use crate::results::{DatabaseDB, EncodingType, FailureReason, TestResult, WriteResults};
+
+    #[test]
+    fn test_regressed_crates() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("parent").apply(&ctx).unwrap();
+        let mut ex = Experiment::get(&db, "parent").unwrap().unwrap();
+        let select = CrateSelect::Regressed("parent".into());
+
+        // The regressions are only known once the experiment is finished
+        assert!(get_crates(select.clone(), &db, &config, None).is_err());
+
+        // Only the first crate regressed
+        let crates = ex.get_crates(&db).unwrap();
+        let results = DatabaseDB::new(&db);
+        for (i, krate) in crates.iter().enumerate() {
+            let after = if i == 0 {
+                TestResult::BuildFail(FailureReason::Unknown)
+            } else {
+                TestResult::TestPass
+            };
+            for (toolchain, res) in ex.toolchains.iter().zip(&[TestResult::TestPass, after]) {
+                results
+                    .store_result(&ex, krate, toolchain, *res, b"log", EncodingType::Plain)
+                    .unwrap();
+            }
+        }
+        ex.set_status(&db, Status::Completed).unwrap();
+
+        assert_eq!(
+            get_crates(select, &db, &config, None).unwrap(),
+            vec![crates[0].clone()]
+        );
+    }
+
+    #[test]
+    fn test_random_sample() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        setup_test_lists(&db, &config).unwrap();
+
+        let crates = get_crates(CrateSelect::Local, &db, &config, None).unwrap();
+        let sample = random_sample(crates.clone(), 3, Some(42));
+        assert_eq!(sample.len(), 3);
+        assert!(sample.iter().all(|krate| crates.contains(krate)));
+
+        // The same crates are picked with the same seed, regardless of the order of the list
+        let mut reversed = crates.clone();
+        reversed.reverse();
+        assert_eq!(random_sample(reversed, 3, Some(42)), sample);
+
+        assert_eq!(
+            random_sample(crates.clone(), 1000, None).len(),
+            crates.len()
+        );
+    }
+
+    #[test]
+    fn test_crate_list() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+
+        let lazy_static = RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.4.0".into(),
+        };
+        db.execute(
+            "INSERT INTO crates (crate, list, loaded_at) VALUES (?1, ?2, ?3);",
+            &[
+                &serde_json::to_string(&Crate::Registry(lazy_static.clone())).unwrap(),
+                &RegistryList::NAME,
+                &chrono::Utc::now(),
+            ],
+        )
+        .unwrap();
+
+        let crates = parse_crate_list(
+            &db,
+            "# regressed in pr-1\n\
+             lazy_static\n\
+             \n\
+             lazy_static-1.0.0\n\
+             https://github.com/brson/hello-rs/\n\
+             https://gitlab.com/foo/bar.git?branch=main\n",
+        )
+        .unwrap();
+        assert_eq!(
+            crates,
+            vec![
+                Crate::Registry(lazy_static),
+                Crate::Registry(RegistryCrate {
+                    name: "lazy_static".into(),
+                    version: "1.0.0".into(),
+                }),
+                Crate::GitHub(GitHubRepo {
+                    org: "brson".into(),
+                    name: "hello-rs".into(),
+                }),
+                Crate::Git(
+                    "https://gitlab.com/foo/bar.git?branch=main"
+                        .parse()
+                        .unwrap()
+                ),
+            ]
+        );
+
+        // Crates missing from the registry are rejected, as well as empty lists
+        assert!(parse_crate_list(&db, "lazy_static\nfoo\n").is_err());
+        assert!(parse_crate_list(&db, "foo-1.0.0").is_err());
+        assert!(parse_crate_list(&db, "https://github.com/brson").is_err());
+        assert!(parse_crate_list(&db, "https://gitlab.com/foo/bar.git?tag=1.0").is_err());
+        assert!(parse_crate_list(&db, "# nothing\n\n").is_err());
+
+        let select = CrateSelect::List("regressed".into());
+        assert!(get_crates(select.clone(), &db, &config, None).is_err());
+        assert!(store_crate_list(&db, DEFAULT_NAMESPACE, "foo bar", &crates).is_err());
+        assert!(store_crate_list(&db, DEFAULT_NAMESPACE, "infra:foo", &crates).is_err());
+        store_crate_list(&db, DEFAULT_NAMESPACE, "regressed", &crates).unwrap();
+        let mut sorted = crates.clone();
+        sorted.sort();
+        assert_eq!(get_crates(select, &db, &config, None).unwrap(), sorted);
+
+        // Lists of other namespaces don't replace the ones of the default namespace
+        store_crate_list(&db, "infra", "regressed", &crates[..1]).unwrap();
+        let select = CrateSelect::List("infra:regressed".into());
+        assert_eq!(
+            get_crates(select, &db, &config, None).unwrap(),
+            &crates[..1]
+        );
+        let select = CrateSelect::List("regressed".into());
+        assert_eq!(get_crates(select, &db, &config, None).unwrap(), sorted);
+    }
+
+    #[test]
+    fn test_duplicate_crates() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        setup_test_lists(&db, &config).unwrap();
+
+        let registry = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0.0".into(),
+        });
+        let github = Crate::GitHub(GitHubRepo {
+            org: "rust-lang-nursery".into(),
+            name: "lazy_static".into(),
+        });
+        let crates = vec![registry.clone(), github.clone(), registry.clone()];
+        store_crate_list(&db, DEFAULT_NAMESPACE, "duplicates", &crates).unwrap();
+
+        // The exact duplicates are removed, while the repository of a registry crate is kept
+        let select = CrateSelect::List("duplicates".into());
+        let mut expected = vec![registry, github];
+        expected.sort();
+        assert_eq!(get_crates(select, &db, &config, None).unwrap(), expected);
+    }
+}
@@ -0,0 +1,611 @@
+use crate::crates::Crate;
+use crate::db::{Database, QueryUtils};
+use crate::prelude::*;
+pub use crater_results::version::RustVersion;
+use rusqlite::Row;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+string_enum!(pub enum Edition {
+    Edition2015 => "2015",
+    Edition2018 => "2018",
+});
+
+#[derive(Deserialize)]
+struct Manifest {
+    package: Option<Package>,
+    #[serde(default)]
+    features: BTreeMap<String, ::toml::Value>,
+    workspace: Option<Workspace>,
+}
+
+#[derive(Deserialize)]
+struct Workspace {
+    #[serde(default)]
+    members: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Package {
+    edition: Option<Edition>,
+    rust_version: Option<String>,
+    #[serde(default)]
+    categories: Vec<String>,
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    metadata: PackageMetadata,
+}
+
+#[derive(Deserialize, Default)]
+struct PackageMetadata {
+    docs: Option<DocsMetadata>,
+}
+
+#[derive(Deserialize)]
+struct DocsMetadata {
+    rs: Option<DocsRsMetadata>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DocsRsMetadata {
+    #[serde(default)]
+    targets: Vec<String>,
+    default_target: Option<String>,
+}
+
+/// Operating systems agents can declare among their capabilities, named after `target_os`.
+pub const TARGET_OS: &[&str] = &[
+    "linux",
+    "windows",
+    "macos",
+    "android",
+    "ios",
+    "freebsd",
+    "dragonfly",
+    "netbsd",
+    "openbsd",
+    "solaris",
+];
+
+const UNIX_OS: &[&str] = &[
+    "linux",
+    "macos",
+    "android",
+    "ios",
+    "freebsd",
+    "dragonfly",
+    "netbsd",
+    "openbsd",
+    "solaris",
+];
+
+/// Operating systems implied by a crates.io category or keyword.
+fn platform_os(name: &str) -> Option<&'static [&'static str]> {
+    Some(match name {
+        "os::windows-apis" | "windows" => &["windows"],
+        "os::macos-apis" | "macos" => &["macos"],
+        "os::linux-apis" | "linux" => &["linux"],
+        "os::android-apis" | "android" => &["android"],
+        "os::freebsd-apis" | "freebsd" => &["freebsd"],
+        "os::unix-apis" | "unix" => UNIX_OS,
+        _ => return None,
+    })
+}
+
+/// Operating system of a target triple, or `None` if it's not a known one.
+fn triple_os(triple: &str) -> Option<&'static str> {
+    // Android triples also contain "linux", so it needs to be checked first
+    const COMPONENTS: &[(&str, &str)] = &[
+        ("windows", "windows"),
+        ("darwin", "macos"),
+        ("ios", "ios"),
+        ("android", "android"),
+        ("linux", "linux"),
+        ("freebsd", "freebsd"),
+        ("dragonfly", "dragonfly"),
+        ("netbsd", "netbsd"),
+        ("openbsd", "openbsd"),
+        ("solaris", "solaris"),
+    ];
+
+    COMPONENTS.iter().find_map(|&(component, os)| {
+        if triple.split('-').any(|part| part.starts_with(component)) {
+            Some(os)
+        } else {
+            None
+        }
+    })
+}
+
+/// Get the operating systems a package is restricted to, or `None` if it doesn't declare any
+/// restriction.
+///
+/// The restrictions come from the targets built by docs.rs and from the `os::*-apis` categories.
+/// Platform keywords are less reliable, so they can only add operating systems to an existing
+/// restriction, never introduce one.
+fn supported_os(package: &Package) -> Option<BTreeSet<String>> {
+    let mut supported = BTreeSet::new();
+
+    if let Some(docs_rs) = package.metadata.docs.as_ref().and_then(|d| d.rs.as_ref()) {
+        let targets = if docs_rs.targets.is_empty() {
+            docs_rs.default_target.iter().collect::<Vec<_>>()
+        } else {
+            docs_rs.targets.iter().collect()
+        };
+        for target in targets {
+            // Targets without an operating system (like wasm32-unknown-unknown) can't be matched
+            // against any agent, so the crate is considered portable
+            supported.insert(triple_os(target)?.to_string());
+        }
+    }
+
+    for category in &package.categories {
+        if let Some(os) = platform_os(category) {
+            supported.extend(os.iter().map(|os| os.to_string()));
+        }
+    }
+
+    if supported.is_empty() {
+        return None;
+    }
+
+    for keyword in &package.keywords {
+        if let Some(os) = platform_os(keyword) {
+            supported.extend(os.iter().map(|os| os.to_string()));
+        }
+    }
+
+    Some(supported)
+}
+
+/// Metadata extracted from the manifest of a crate, before running the experiments.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CrateMetadata {
+    /// Edition of the package, or `None` if the manifest is a virtual workspace.
+    pub edition: Option<Edition>,
+    pub rust_version: Option<RustVersion>,
+    /// Operating systems the package is restricted to, or `None` if it's portable.
+    pub target_os: Option<BTreeSet<String>>,
+    pub features: Vec<String>,
+    /// Members of the workspace defined in the manifest, as written (globs are not expanded).
+    pub workspace_members: Vec<String>,
+}
+
+fn parse_manifest(manifest: &str) -> Fallible<CrateMetadata> {
+    let manifest: Manifest = ::toml::from_str(manifest)?;
+    let package = manifest.package.as_ref();
+    Ok(CrateMetadata {
+        edition: package.map(|p| p.edition.unwrap_or(Edition::Edition2015)),
+        // An invalid `rust-version` is ignored by Cargo too
+        rust_version: package
+            .and_then(|p| p.rust_version.as_ref())
+            .and_then(|v| v.parse().ok()),
+        target_os: package.and_then(supported_os),
+        features: manifest.features.keys().cloned().collect(),
+        workspace_members: manifest.workspace.map(|w| w.members).unwrap_or_default(),
+    })
+}
+
+struct CrateMetadataRecord {
+    krate: String,
+    edition: Option<String>,
+    rust_version: Option<String>,
+    target_os: Option<String>,
+    features: Option<String>,
+    workspace_members: Option<String>,
+}
+
+impl CrateMetadataRecord {
+    fn from_row(row: &Row) -> Self {
+        CrateMetadataRecord {
+            krate: row.get("crate"),
+            edition: row.get("edition"),
+            rust_version: row.get("rust_version"),
+            target_os: row.get("target_os"),
+            features: row.get("features"),
+            workspace_members: row.get("workspace_members"),
+        }
+    }
+
+    fn into_metadata(self) -> Fallible<CrateMetadata> {
+        Ok(CrateMetadata {
+            edition: self.edition.map(|e| e.parse()).transpose()?,
+            rust_version: self.rust_version.map(|v| v.parse()).transpose()?,
+            target_os: self
+                .target_os
+                .map(|os| os.split(',').map(|os| os.to_string()).collect()),
+            features: match self.features {
+                Some(features) => ::serde_json::from_str(&features)?,
+                None => Vec::new(),
+            },
+            workspace_members: match self.workspace_members {
+                Some(members) => ::serde_json::from_str(&members)?,
+                None => Vec::new(),
+            },
+        })
+    }
+}
+
+/// Load the manifest metadata of the crates that weren't analyzed yet.
+///
+/// Crates whose manifest can't be fetched or parsed are skipped, and will be retried the next
+/// time the metadata is updated.
+pub(crate) fn update(db: &Database, crates: &[Crate]) -> Fallible<()> {
+    let known = db
+        .query("SELECT crate FROM crate_metadata;", &[], |r| {
+            r.get::<_, String>("crate")
+        })?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let mut loaded = 0;
+    for krate in crates {
+        let serialized = ::serde_json::to_string(krate)?;
+        if known.contains(&serialized) {
+            continue;
+        }
+
+        let metadata = match krate
+            .source()
+            .fetch_manifest()
+            .and_then(|manifest| parse_manifest(&manifest))
+        {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                warn!("failed to load the manifest of {}: {}", krate, err);
+                continue;
+            }
+        };
+
+        db.execute(
+            "INSERT INTO crate_metadata \
+             (crate, edition, rust_version, target_os, features, workspace_members) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+            &[
+                &serialized,
+                &metadata.edition.map(|e| e.to_str()),
+                &metadata.rust_version.as_ref().map(|v| v.to_string()),
+                &metadata
+                    .target_os
+                    .as_ref()
+                    .map(|os| os.iter().cloned().collect::<Vec<_>>().join(",")),
+                &::serde_json::to_string(&metadata.features)?,
+                &::serde_json::to_string(&metadata.workspace_members)?,
+            ],
+        )?;
+        loaded += 1;
+    }
+
+    info!("loaded the manifest metadata of {} crates", loaded);
+    Ok(())
+}
+
+/// Get the metadata of the crates that were already analyzed.
+pub(crate) fn load(db: &Database, crates: &[Crate]) -> Fallible<HashMap<Crate, CrateMetadata>> {
+    let mut known = db
+        .query("SELECT * FROM crate_metadata;", &[], |r| {
+            CrateMetadataRecord::from_row(r)
+        })?
+        .into_iter()
+        .map(|record| (record.krate.clone(), record))
+        .collect::<HashMap<_, _>>();
+
+    let mut result = HashMap::new();
+    for krate in crates {
+        if let Some(record) = known.remove(&::serde_json::to_string(krate)?) {
+            result.insert(krate.clone(), record.into_metadata()?);
+        }
+    }
+    Ok(result)
+}
+
+/// Pre-analysis phase of an experiment: fetch the manifests of the crates that weren't analyzed
+/// yet, and return the metadata of all the crates that could be analyzed.
+pub(crate) fn analyze(db: &Database, crates: &[Crate]) -> Fallible<HashMap<Crate, CrateMetadata>> {
+    update(db, crates)?;
+    load(db, crates)
+}
+
+/// Get the minimum Rust version declared by a crate, if its metadata was loaded.
+pub(crate) fn rust_version(db: &Database, krate: &Crate) -> Fallible<Option<RustVersion>> {
+    let version: Option<Option<String>> = db
+        .query(
+            "SELECT rust_version FROM crate_metadata WHERE crate = ?1;",
+            &[&::serde_json::to_string(krate)?],
+            |r| r.get("rust_version"),
+        )?
+        .pop();
+    Ok(match version {
+        Some(Some(version)) => Some(version.parse()?),
+        _ => None,
+    })
+}
+
+fn retain_matching(crates: Vec<Crate>, matching: &HashSet<String>) -> Fallible<Vec<Crate>> {
+    let mut filtered = Vec::new();
+    for krate in crates {
+        if matching.contains(&::serde_json::to_string(&krate)?) {
+            filtered.push(krate);
+        }
+    }
+    Ok(filtered)
+}
+
+/// Only keep the crates declaring the provided edition in their manifest. Crates whose metadata
+/// wasn't loaded are excluded.
+pub(crate) fn filter_by_edition(
+    db: &Database,
+    crates: Vec<Crate>,
+    edition: Edition,
+) -> Fallible<Vec<Crate>> {
+    let matching = db
+        .query(
+            "SELECT crate FROM crate_metadata WHERE edition = ?1;",
+            &[&edition.to_str()],
+            |r| r.get::<_, String>("crate"),
+        )?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    retain_matching(crates, &matching)
+}
+
+/// Only keep the crates declaring a minimum Rust version lower or equal than `max`. Crates not
+/// declaring any minimum version, or whose metadata wasn't loaded, are excluded.
+pub(crate) fn filter_by_rust_version(
+    db: &Database,
+    crates: Vec<Crate>,
+    max: &RustVersion,
+) -> Fallible<Vec<Crate>> {
+    let mut matching = HashSet::new();
+    let rows = db.query(
+        "SELECT crate, rust_version FROM crate_metadata WHERE rust_version IS NOT NULL;",
+        &[],
+        |r| {
+            (
+                r.get::<_, String>("crate"),
+                r.get::<_, String>("rust_version"),
+            )
+        },
+    )?;
+    for (krate, version) in rows {
+        if version.parse::<RustVersion>()? <= *max {
+            matching.insert(krate);
+        }
+    }
+    retain_matching(crates, &matching)
+}
+
+/// Find out which crates can't be built on the `os` operating system, according to the platforms
+/// declared in their manifest. Crates whose metadata wasn't loaded are considered portable.
+pub(crate) fn incompatible_crates(
+    db: &Database,
+    crates: &[Crate],
+    os: &str,
+) -> Fallible<Vec<Crate>> {
+    let incompatible = db
+        .query(
+            "SELECT crate, target_os FROM crate_metadata WHERE target_os IS NOT NULL;",
+            &[],
+            |r| (r.get::<_, String>("crate"), r.get::<_, String>("target_os")),
+        )?
+        .into_iter()
+        .filter(|(_, supported)| !supported.split(',').any(|supported| supported == os))
+        .map(|(krate, _)| krate)
+        .collect::<HashSet<_>>();
+    retain_matching(crates.to_vec(), &incompatible)
+}
+
+/// Apply the optional manifest-based filters of an experiment to a list of crates.
+pub(crate) fn apply_filters(
+    db: &Database,
+    mut crates: Vec<Crate>,
+    edition: Option<Edition>,
+    max_rust_version: Option<&RustVersion>,
+) -> Fallible<Vec<Crate>> {
+    if let Some(edition) = edition {
+        crates = filter_by_edition(db, crates, edition)?;
+    }
+    if let Some(max) = max_rust_version {
+        crates = filter_by_rust_version(db, crates, max)?;
+    }
+    Ok(crates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        filter_by_edition, filter_by_rust_version, incompatible_crates, parse_manifest,
+        CrateMetadata, Edition, RustVersion,
+    };
+    use crate::config::Config;
+    use crate::crates::{lists::get_crates, Crate};
+    use crate::db::{Database, QueryUtils};
+    use crate::experiments::CrateSelect;
+
+    fn local_names(crates: Vec<Crate>) -> Vec<String> {
+        let mut names = crates
+            .into_iter()
+            .map(|krate| match krate {
+                Crate::Local(krate) => krate.name,
+                other => panic!("unexpected crate: {}", other),
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        assert_eq!(
+            parse_manifest(
+                "[package]\nname = \"foo\"\nedition = \"2018\"\n\
+                 [features]\ndefault = [\"std\"]\nstd = []"
+            )
+            .unwrap(),
+            CrateMetadata {
+                edition: Some(Edition::Edition2018),
+                rust_version: None,
+                target_os: None,
+                features: vec!["default".into(), "std".into()],
+                workspace_members: vec![],
+            }
+        );
+        assert_eq!(
+            parse_manifest(
+                "[package]\nname = \"foo\"\nrust-version = \"1.31\"\n\
+                 [workspace]\nmembers = [\"foo-derive\"]"
+            )
+            .unwrap(),
+            CrateMetadata {
+                edition: Some(Edition::Edition2015),
+                rust_version: Some("1.31.0".parse().unwrap()),
+                target_os: None,
+                features: vec![],
+                workspace_members: vec!["foo-derive".into()],
+            }
+        );
+        assert_eq!(
+            parse_manifest("[workspace]\nmembers = [\"foo\", \"bar\"]").unwrap(),
+            CrateMetadata {
+                edition: None,
+                rust_version: None,
+                target_os: None,
+                features: vec![],
+                workspace_members: vec!["foo".into(), "bar".into()],
+            }
+        );
+        assert!(parse_manifest("[package]\nedition = \"1234\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_target_os() {
+        let target_os = |manifest: &str| {
+            parse_manifest(&format!("[package]\nname = \"foo\"\n{}", manifest))
+                .unwrap()
+                .target_os
+                .map(|os| os.into_iter().collect::<Vec<_>>())
+        };
+
+        assert_eq!(target_os(""), None);
+        assert_eq!(target_os("keywords = [\"windows\"]"), None);
+        assert_eq!(
+            target_os("categories = [\"os::windows-apis\"]"),
+            Some(vec!["windows".into()])
+        );
+        assert_eq!(
+            target_os("categories = [\"os::macos-apis\"]\nkeywords = [\"linux\", \"ffi\"]"),
+            Some(vec!["linux".into(), "macos".into()])
+        );
+        assert_eq!(
+            target_os(
+                "[package.metadata.docs.rs]\n\
+                 targets = [\"x86_64-pc-windows-msvc\", \"i686-pc-windows-gnu\"]"
+            ),
+            Some(vec!["windows".into()])
+        );
+        assert_eq!(
+            target_os("[package.metadata.docs.rs]\ndefault-target = \"aarch64-linux-android\""),
+            Some(vec!["android".into()])
+        );
+        assert_eq!(
+            target_os(
+                "categories = [\"os::windows-apis\"]\n\
+                 [package.metadata.docs.rs]\n\
+                 targets = [\"wasm32-unknown-unknown\"]"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rust_version_parsing() {
+        let v = |s: &str| s.parse::<RustVersion>().unwrap();
+
+        assert_eq!(v("1.60"), v("1.60.0"));
+        assert_eq!(v("1.60").to_string().as_str(), "1.60.0");
+        assert!(v("1.9") < v("1.10"));
+        assert!(v("1.60.1") > v("1.60"));
+        for invalid in &["", "1", "1.x", "1.2.3.4", "stable"] {
+            assert!(invalid.parse::<RustVersion>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_filter_by_edition() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let crates = get_crates(CrateSelect::Local, &db, &config, None).unwrap();
+        assert_eq!(
+            local_names(filter_by_edition(&db, crates, Edition::Edition2018).unwrap()),
+            vec!["clippy-warn", "outdated-lockfile", "yanked-deps"]
+        );
+    }
+
+    #[test]
+    fn test_filter_by_rust_version() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        for (name, version) in &[("build-pass", "1.31"), ("test-fail", "1.60.0")] {
+            db.execute(
+                "UPDATE crate_metadata SET rust_version = ?1 WHERE crate = ?2;",
+                &[
+                    version,
+                    &::serde_json::to_string(&Crate::Local((*name).into())).unwrap(),
+                ],
+            )
+            .unwrap();
+        }
+
+        let crates = get_crates(CrateSelect::Local, &db, &config, None).unwrap();
+        let max = "1.59".parse().unwrap();
+        assert_eq!(
+            local_names(filter_by_rust_version(&db, crates.clone(), &max).unwrap()),
+            vec!["build-pass"]
+        );
+        let max = "1.60".parse().unwrap();
+        assert_eq!(
+            local_names(filter_by_rust_version(&db, crates, &max).unwrap()),
+            vec!["build-pass", "test-fail"]
+        );
+    }
+
+    #[test]
+    fn test_incompatible_crates() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        for (name, target_os) in &[("build-pass", "windows"), ("test-fail", "linux,macos")] {
+            db.execute(
+                "UPDATE crate_metadata SET target_os = ?1 WHERE crate = ?2;",
+                &[
+                    target_os,
+                    &::serde_json::to_string(&Crate::Local((*name).into())).unwrap(),
+                ],
+            )
+            .unwrap();
+        }
+
+        let crates = get_crates(CrateSelect::Local, &db, &config, None).unwrap();
+        assert_eq!(
+            local_names(incompatible_crates(&db, &crates, "linux").unwrap()),
+            vec!["build-pass"]
+        );
+        assert_eq!(
+            local_names(incompatible_crates(&db, &crates, "windows").unwrap()),
+            vec!["test-fail"]
+        );
+        assert_eq!(
+            local_names(incompatible_crates(&db, &crates, "macos").unwrap()),
+            vec!["build-pass"]
+        );
+    }
+}
@@ -1,53 +1,94 @@
 pub(crate) mod lists;
+pub(crate) mod metadata;
 mod sources;
 
-use crate::dirs::LOCAL_CRATES_DIR;
 use crate::prelude::*;
 use rustwide::Crate as RustwideCrate;
+use std::collections::HashMap;
 use std::fmt;
+use std::path::Path;
 use std::str::FromStr;
 
+pub use crate::crates::lists::parse_crate;
+pub use crate::crates::metadata::{CrateMetadata, Edition, RustVersion, TARGET_OS};
+use crate::crates::sources::git::GitCrate;
 pub(crate) use crate::crates::sources::github::GitHubRepo;
+use crate::crates::sources::local::{LocalCrate, PathCrate};
+pub use crate::crates::sources::registry::IndexSnapshot;
 pub(crate) use crate::crates::sources::registry::RegistryCrate;
+use crate::crates::sources::CrateSource;
 
+/// Crate tested by an experiment. The behavior of every variant is implemented by the
+/// `CrateSource` it wraps, returned by `Crate::source`.
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Clone)]
 pub enum Crate {
     Registry(RegistryCrate),
     GitHub(GitHubRepo),
-    Local(String),
+    Local(LocalCrate),
+    Path(PathCrate),
+    Git(GitCrate),
+}
+
+/// Prefixes of the sources that are git repositories rather than paths.
+const GIT_URL_PREFIXES: &[&str] = &["https://", "http://", "git://", "ssh://", "git@"];
+const GITHUB_URL_PREFIX: &str = "https://github.com/";
+
+fn is_git_url(source: &str) -> bool {
+    GIT_URL_PREFIXES.iter().any(|p| source.starts_with(p))
 }
 
 impl Crate {
-    pub(crate) fn id(&self) -> String {
-        match *self {
-            Crate::Registry(ref details) => format!("reg/{}/{}", details.name, details.version),
-            Crate::GitHub(ref repo) => format!("gh/{}/{}", repo.org, repo.name),
-            Crate::Local(ref name) => format!("local/{}", name),
+    /// Get the crate in a path on disk or in a git repository, as selected with
+    /// `local:<path-or-git-url>`.
+    pub(crate) fn from_local_source(source: &str) -> Fallible<Self> {
+        if is_git_url(source) {
+            return Ok(Crate::Git(source.parse()?));
+        }
+
+        let path = Path::new(source);
+        if !path.join("Cargo.toml").is_file() {
+            bail!("missing Cargo.toml in {}", path.display());
+        }
+        let path = path
+            .canonicalize()
+            .with_context(|_| format!("failed to resolve {}", path.display()))?;
+        match path.to_str() {
+            Some(path) => Ok(Crate::Path(path.into())),
+            None => bail!("non-UTF-8 paths are not supported: {}", path.display()),
         }
     }
 
-    pub(crate) fn to_rustwide(&self) -> RustwideCrate {
+    pub(crate) fn source(&self) -> &dyn CrateSource {
         match self {
-            Self::Registry(krate) => RustwideCrate::crates_io(&krate.name, &krate.version),
-            Self::GitHub(repo) => {
-                RustwideCrate::git(&format!("https://github.com/{}/{}", repo.org, repo.name))
-            }
-            Self::Local(name) => RustwideCrate::local(&LOCAL_CRATES_DIR.join(name)),
+            Crate::Registry(krate) => krate,
+            Crate::GitHub(repo) => repo,
+            Crate::Local(krate) => krate,
+            Crate::Path(krate) => krate,
+            Crate::Git(krate) => krate,
         }
     }
+
+    pub(crate) fn id(&self) -> String {
+        let source = self.source();
+        format!("{}/{}", source.kind(), source.id())
+    }
+
+    /// Commit of the crate tested by the experiment, if its source records it.
+    pub(crate) fn commit<'a>(&self, shas: &'a HashMap<GitHubRepo, String>) -> Option<&'a str> {
+        match self {
+            Crate::GitHub(repo) => shas.get(repo).map(|sha| sha.as_str()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn to_rustwide(&self) -> RustwideCrate {
+        self.source().to_rustwide()
+    }
 }
 
 impl fmt::Display for Crate {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match *self {
-                Crate::Registry(ref krate) => format!("{}-{}", krate.name, krate.version),
-                Crate::GitHub(ref repo) => repo.slug(),
-                Crate::Local(ref name) => format!("{} (local)", name),
-            }
-        )
+        write!(f, "{}", self.source())
     }
 }
 
@@ -55,8 +96,11 @@ impl FromStr for Crate {
     type Err = ::failure::Error;
 
     fn from_str(s: &str) -> Fallible<Self> {
-        if s.starts_with("https://github.com/") {
+        // GitHub repositories pinned to a branch or commit are tested as any other git repository
+        if s.starts_with(GITHUB_URL_PREFIX) && !s.contains('?') {
             Ok(Crate::GitHub(s.parse()?))
+        } else if is_git_url(s) {
+            Ok(Crate::Git(s.parse()?))
         } else if let Some(dash_idx) = s.rfind('-') {
             let name = &s[..dash_idx];
             let version = &s[dash_idx + 1..];
@@ -69,3 +113,69 @@ impl FromStr for Crate {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Crate, GitHubRepo, RegistryCrate};
+    use crate::dirs::LOCAL_CRATES_DIR;
+
+    #[test]
+    fn test_from_local_source() {
+        let url = "https://example.com/foo/bar.git";
+        let git = Crate::from_local_source(url).unwrap();
+        assert_eq!(git, Crate::Git(url.into()));
+        assert_eq!(git.id().as_str(), "git/bar");
+        let pinned =
+            Crate::from_local_source("https://example.com/foo/bar.git?rev=0123abc").unwrap();
+        assert_eq!(pinned.id().as_str(), "git/bar@0123abc");
+        assert!(Crate::from_local_source("https://example.com/foo/bar.git?tag=1.0").is_err());
+
+        let path = LOCAL_CRATES_DIR.join("build-pass");
+        let krate = Crate::from_local_source(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            krate,
+            Crate::Path(path.canonicalize().unwrap().to_str().unwrap().into())
+        );
+        assert_eq!(krate.id().as_str(), "path/build-pass");
+
+        // Directories without a manifest are rejected
+        assert!(Crate::from_local_source(LOCAL_CRATES_DIR.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_serialization() {
+        // Crates are stored serialized in the database, so the format must not change
+        for (krate, serialized) in &[
+            (
+                Crate::Registry(RegistryCrate {
+                    name: "lazy_static".into(),
+                    version: "1.0.0".into(),
+                }),
+                r#"{"Registry":{"name":"lazy_static","version":"1.0.0"}}"#,
+            ),
+            (
+                Crate::GitHub(GitHubRepo {
+                    org: "brson".into(),
+                    name: "hello-rs".into(),
+                }),
+                r#"{"GitHub":{"org":"brson","name":"hello-rs"}}"#,
+            ),
+            (
+                Crate::Local("build-pass".into()),
+                r#"{"Local":"build-pass"}"#,
+            ),
+            (Crate::Path("/src/foo".into()), r#"{"Path":"/src/foo"}"#),
+            (
+                Crate::Git("https://example.com/foo.git".into()),
+                r#"{"Git":"https://example.com/foo.git"}"#,
+            ),
+            (
+                Crate::Git("https://example.com/foo.git?branch=main".parse().unwrap()),
+                r#"{"Git":"https://example.com/foo.git?branch=main"}"#,
+            ),
+        ] {
+            assert_eq!(serde_json::to_string(krate).unwrap(), *serialized);
+            assert_eq!(serde_json::from_str::<Crate>(serialized).unwrap(), *krate);
+        }
+    }
+}
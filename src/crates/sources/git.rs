@@ -0,0 +1,272 @@
+use crate::crates::sources::{local::local_source_name, run_git, CrateSource};
+use crate::dirs::WORK_DIR;
+use crate::prelude::*;
+use crate::utils::{hex::to_hex, string::shell_quote};
+use rustwide::Crate as RustwideCrate;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Branch or commit a git crate is pinned to, instead of the default branch of the repository.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone)]
+pub enum GitReference {
+    Branch(String),
+    Rev(String),
+}
+
+impl GitReference {
+    /// Name of the branch or commit, as accepted by `git checkout`.
+    fn name(&self) -> &str {
+        match self {
+            GitReference::Branch(name) | GitReference::Rev(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for GitReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GitReference::Branch(branch) => write!(f, "branch={}", branch),
+            GitReference::Rev(rev) => write!(f, "rev={}", rev),
+        }
+    }
+}
+
+impl FromStr for GitReference {
+    type Err = ::failure::Error;
+
+    fn from_str(input: &str) -> Fallible<Self> {
+        let mut parts = input.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("branch"), Some(branch))
+                if !branch.is_empty()
+                    && !branch.starts_with('-')
+                    && !branch.contains(char::is_whitespace) =>
+            {
+                Ok(GitReference::Branch(branch.into()))
+            }
+            (Some("rev"), Some(rev))
+                if (7..=40).contains(&rev.len()) && rev.chars().all(|c| c.is_ascii_hexdigit()) =>
+            {
+                Ok(GitReference::Rev(rev.to_lowercase()))
+            }
+            _ => bail!("invalid git reference: {}", input),
+        }
+    }
+}
+
+/// Crate in an arbitrary git repository, optionally pinned to a branch or commit with the same
+/// syntax Cargo uses in its source IDs, like `https://gitlab.com/foo/bar.git?branch=main`.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone)]
+pub struct GitCrate {
+    pub url: String,
+    pub reference: Option<GitReference>,
+}
+
+impl GitCrate {
+    /// Directory the pinned repository is checked out in, as rustwide can only clone the default
+    /// branch of git repositories.
+    fn checkout_dir(&self) -> PathBuf {
+        let mut hasher = Sha256::default();
+        hasher.input(self.to_string().as_bytes());
+        WORK_DIR
+            .join("git-checkouts")
+            .join(&to_hex(hasher.result().as_slice())[..16])
+    }
+}
+
+impl<'a> From<&'a str> for GitCrate {
+    fn from(url: &'a str) -> Self {
+        GitCrate {
+            url: url.into(),
+            reference: None,
+        }
+    }
+}
+
+impl fmt::Display for GitCrate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.url)?;
+        if let Some(reference) = &self.reference {
+            write!(f, "?{}", reference)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for GitCrate {
+    type Err = ::failure::Error;
+
+    fn from_str(input: &str) -> Fallible<Self> {
+        let (url, reference) = match input.rfind('?') {
+            Some(idx) => (&input[..idx], Some(input[idx + 1..].parse()?)),
+            None => (input, None),
+        };
+        if url.is_empty() || url.contains(char::is_whitespace) {
+            bail!("invalid git URL: {}", input);
+        }
+        Ok(GitCrate {
+            url: url.into(),
+            reference,
+        })
+    }
+}
+
+impl_serde_from_parse!(GitCrate, expecting = "a git URL");
+
+impl CrateSource for GitCrate {
+    fn kind(&self) -> &'static str {
+        "git"
+    }
+
+    fn id(&self) -> String {
+        let name = local_source_name(&self.url);
+        match &self.reference {
+            Some(reference) => format!("{}@{}", name, reference.name()),
+            None => name.to_string(),
+        }
+    }
+
+    // Branch names can contain slashes, which would nest the directories of the report
+    fn path_name(&self) -> String {
+        self.id().replace('/', ".")
+    }
+
+    fn name(&self) -> String {
+        self.to_string()
+    }
+
+    fn url(&self, _commit: Option<&str>) -> String {
+        self.url.clone()
+    }
+
+    // The commit being built is only known for the crates pinned to one
+    fn fetch_commands(&self, _commit: Option<&str>) -> Vec<String> {
+        let mut commands = vec![format!("git clone {} .", shell_quote(&self.url))];
+        if let Some(reference) = &self.reference {
+            commands.push(format!("git checkout {}", shell_quote(reference.name())));
+        }
+        commands
+    }
+
+    fn fetch(&self) -> Fallible<()> {
+        let reference = match &self.reference {
+            Some(reference) => reference,
+            None => return Ok(()),
+        };
+
+        let dir = self.checkout_dir();
+        if dir.join(".git").is_dir() {
+            run_git(&dir, &["fetch", "--quiet", "origin"])?;
+        } else {
+            fs::create_dir_all(&dir)?;
+            run_git(&dir, &["clone", "--quiet", &self.url, "."])?;
+        }
+
+        // Branches are checked out from the remote, in case they changed since the last fetch
+        let target = match reference {
+            GitReference::Branch(branch) => format!("origin/{}", branch),
+            GitReference::Rev(rev) => rev.clone(),
+        };
+        run_git(
+            &dir,
+            &["checkout", "--quiet", "--force", "--detach", &target],
+        )
+        .with_context(|_| format!("failed to check out {} of {}", reference, self.url))?;
+        Ok(())
+    }
+
+    fn fetch_manifest(&self) -> Fallible<String> {
+        bail!(
+            "can't fetch the manifest of {} without cloning it",
+            self.url
+        );
+    }
+
+    fn to_rustwide(&self) -> RustwideCrate {
+        if self.reference.is_some() {
+            RustwideCrate::local(&self.checkout_dir())
+        } else {
+            RustwideCrate::git(&self.url)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GitCrate, GitReference};
+    use crate::crates::sources::CrateSource;
+
+    #[test]
+    fn test_parse() {
+        macro_rules! test_parse {
+            ($input:expr, $url:expr, $reference:expr) => {
+                let krate: GitCrate = $input.parse().unwrap();
+                assert_eq!(
+                    krate,
+                    GitCrate {
+                        url: $url.into(),
+                        reference: $reference,
+                    }
+                );
+                assert_eq!(krate.to_string(), $input);
+            };
+        }
+
+        test_parse!(
+            "https://gitlab.com/foo/bar.git",
+            "https://gitlab.com/foo/bar.git",
+            None
+        );
+        test_parse!(
+            "https://git.sr.ht/~foo/bar?branch=feature/baz",
+            "https://git.sr.ht/~foo/bar",
+            Some(GitReference::Branch("feature/baz".into()))
+        );
+        test_parse!(
+            "git@example.com:foo/bar.git?rev=0123abc",
+            "git@example.com:foo/bar.git",
+            Some(GitReference::Rev("0123abc".into()))
+        );
+
+        assert!("https://gitlab.com/foo/bar?tag=1.0"
+            .parse::<GitCrate>()
+            .is_err());
+        assert!("https://gitlab.com/foo/bar?branch="
+            .parse::<GitCrate>()
+            .is_err());
+        assert!("https://gitlab.com/foo/bar?rev=main"
+            .parse::<GitCrate>()
+            .is_err());
+        assert!("?branch=main".parse::<GitCrate>().is_err());
+    }
+
+    #[test]
+    fn test_source() {
+        let krate: GitCrate = "https://gitlab.com/foo/bar.git?branch=main"
+            .parse()
+            .unwrap();
+        assert_eq!(krate.id(), "bar@main");
+        assert_eq!(krate.url(None), "https://gitlab.com/foo/bar.git");
+        assert_eq!(
+            krate.fetch_commands(None),
+            vec![
+                "git clone https://gitlab.com/foo/bar.git .".to_string(),
+                "git checkout main".to_string(),
+            ]
+        );
+
+        let krate: GitCrate = "https://gitlab.com/foo/bar.git?branch=feature/baz"
+            .parse()
+            .unwrap();
+        assert_eq!(krate.id(), "bar@feature/baz");
+        assert_eq!(krate.path_name(), "bar@feature.baz");
+
+        // Unpinned crates keep the same ID as before pinning was supported
+        let krate = GitCrate::from("https://gitlab.com/foo/bar.git");
+        assert_eq!(krate.id(), "bar");
+        assert_eq!(krate.fetch_commands(None).len(), 1);
+    }
+}
@@ -1,6 +1,11 @@
+use crate::config::{Config, CrateConfig};
+use crate::crates::sources::CrateSource;
 use crate::crates::{lists::List, Crate};
 use crate::prelude::*;
+use crate::utils::{http, string::shell_quote};
+use rustwide::Crate as RustwideCrate;
 use std::borrow::Cow;
+use std::fmt;
 use std::str::FromStr;
 
 static CACHED_LIST: &str =
@@ -33,7 +38,7 @@ impl List for GitHubList {
     fn fetch(&self) -> Fallible<Vec<Crate>> {
         info!("loading cached GitHub list from {}", self.source);
 
-        let mut resp = crate::utils::http::get_sync(&self.source)
+        let mut resp = http::get_sync(&self.source)
             .with_context(|_| format!("failed to fetch GitHub crates list from {}", self.source))?;
         let mut reader = ::csv::Reader::from_reader(&mut resp);
 
@@ -84,6 +89,74 @@ impl GitHubRepo {
     }
 }
 
+impl fmt::Display for GitHubRepo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.org, self.name)
+    }
+}
+
+impl CrateSource for GitHubRepo {
+    fn kind(&self) -> &'static str {
+        "gh"
+    }
+
+    fn id(&self) -> String {
+        self.slug()
+    }
+
+    fn path_name(&self) -> String {
+        format!("{}.{}", self.org, self.name)
+    }
+
+    fn name(&self) -> String {
+        self.slug()
+    }
+
+    fn report_name(&self, commit: Option<&str>) -> String {
+        if let Some(sha) = commit {
+            format!("{}.{}.{}", self.org, self.name, sha)
+        } else {
+            format!("{}.{}", self.org, self.name)
+        }
+    }
+
+    fn url(&self, commit: Option<&str>) -> String {
+        if let Some(sha) = commit {
+            format!("https://github.com/{}/{}/tree/{}", self.org, self.name, sha)
+        } else {
+            format!("https://github.com/{}/{}", self.org, self.name)
+        }
+    }
+
+    fn fetch_commands(&self, commit: Option<&str>) -> Vec<String> {
+        let mut commands = vec![format!(
+            "git clone https://github.com/{}/{} .",
+            shell_quote(&self.org),
+            shell_quote(&self.name)
+        )];
+        if let Some(sha) = commit {
+            commands.push(format!("git checkout {}", shell_quote(sha)));
+        }
+        commands
+    }
+
+    fn fetch_manifest(&self) -> Fallible<String> {
+        let url = format!(
+            "https://raw.githubusercontent.com/{}/{}/HEAD/Cargo.toml",
+            self.org, self.name
+        );
+        Ok(http::get_sync(&url)?.text()?)
+    }
+
+    fn to_rustwide(&self) -> RustwideCrate {
+        RustwideCrate::git(&format!("https://github.com/{}/{}", self.org, self.name))
+    }
+
+    fn config<'a>(&self, config: &'a Config) -> Option<&'a CrateConfig> {
+        config.github_repos.get(&self.slug())
+    }
+}
+
 impl FromStr for GitHubRepo {
     type Err = ::failure::Error;
 
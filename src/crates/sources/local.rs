@@ -1,7 +1,13 @@
+use crate::config::{Config, CrateConfig};
+use crate::crates::sources::CrateSource;
 use crate::crates::{lists::List, Crate};
 use crate::dirs::LOCAL_CRATES_DIR;
 use crate::prelude::*;
-use std::path::PathBuf;
+use crate::utils::string::shell_quote;
+use rustwide::Crate as RustwideCrate;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 pub(crate) struct LocalList {
     source: PathBuf,
@@ -24,7 +30,7 @@ impl List for LocalList {
         }
 
         let mut list = Vec::new();
-        for entry in ::std::fs::read_dir(&self.source)? {
+        for entry in fs::read_dir(&self.source)? {
             let entry = entry?;
 
             if entry.path().join("Cargo.toml").is_file() {
@@ -39,10 +45,141 @@ impl List for LocalList {
                     })?
                     .to_string();
 
-                list.push(Crate::Local(name));
+                list.push(Crate::Local(LocalCrate { name }));
             }
         }
 
         Ok(list)
     }
 }
+
+/// Short name of a crate selected from a local source, used in the IDs and in the report.
+pub(in crate::crates) fn local_source_name(source: &str) -> &str {
+    let name = source
+        .trim_end_matches('/')
+        .rsplit(|c| c == '/' || c == ':')
+        .next()
+        .unwrap_or(source);
+    if name.ends_with(".git") {
+        &name[..name.len() - 4]
+    } else {
+        name
+    }
+}
+
+/// Crate bundled with Crater in the `local-crates` directory.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Clone)]
+#[serde(transparent)]
+pub struct LocalCrate {
+    pub name: String,
+}
+
+impl<'a> From<&'a str> for LocalCrate {
+    fn from(name: &'a str) -> Self {
+        LocalCrate { name: name.into() }
+    }
+}
+
+impl fmt::Display for LocalCrate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (local)", self.name)
+    }
+}
+
+impl CrateSource for LocalCrate {
+    fn kind(&self) -> &'static str {
+        "local"
+    }
+
+    fn id(&self) -> String {
+        self.name.clone()
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn url(&self, _commit: Option<&str>) -> String {
+        format!(
+            "{}/tree/master/local-crates/{}",
+            crate::CRATER_REPO_URL,
+            self.name
+        )
+    }
+
+    fn fetch_commands(&self, _commit: Option<&str>) -> Vec<String> {
+        vec![
+            format!("git clone --depth 1 {} crater", crate::CRATER_REPO_URL),
+            format!(
+                "cp -r crater/local-crates/{}/. . && rm -rf crater",
+                shell_quote(&self.name)
+            ),
+        ]
+    }
+
+    fn fetch_manifest(&self) -> Fallible<String> {
+        Ok(fs::read_to_string(
+            LOCAL_CRATES_DIR.join(&self.name).join("Cargo.toml"),
+        )?)
+    }
+
+    fn to_rustwide(&self) -> RustwideCrate {
+        RustwideCrate::local(&LOCAL_CRATES_DIR.join(&self.name))
+    }
+
+    fn config<'a>(&self, config: &'a Config) -> Option<&'a CrateConfig> {
+        config.local_crates.get(&self.name)
+    }
+}
+
+/// Crate in a directory outside of the bundled local crates.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Clone)]
+#[serde(transparent)]
+pub struct PathCrate {
+    /// Absolute path of the directory.
+    pub path: String,
+}
+
+impl<'a> From<&'a str> for PathCrate {
+    fn from(path: &'a str) -> Self {
+        PathCrate { path: path.into() }
+    }
+}
+
+impl fmt::Display for PathCrate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.path)
+    }
+}
+
+impl CrateSource for PathCrate {
+    fn kind(&self) -> &'static str {
+        "path"
+    }
+
+    fn id(&self) -> String {
+        local_source_name(&self.path).to_string()
+    }
+
+    fn name(&self) -> String {
+        self.path.clone()
+    }
+
+    fn url(&self, _commit: Option<&str>) -> String {
+        format!("file://{}", self.path)
+    }
+
+    fn fetch_commands(&self, _commit: Option<&str>) -> Vec<String> {
+        vec![format!("cp -r {}/. .", shell_quote(&self.path))]
+    }
+
+    fn fetch_manifest(&self) -> Fallible<String> {
+        Ok(fs::read_to_string(
+            Path::new(&self.path).join("Cargo.toml"),
+        )?)
+    }
+
+    fn to_rustwide(&self) -> RustwideCrate {
+        RustwideCrate::local(Path::new(&self.path))
+    }
+}
@@ -1,3 +1,79 @@
+pub(in crate::crates) mod git;
 pub(in crate::crates) mod github;
 pub(in crate::crates) mod local;
 pub(in crate::crates) mod registry;
+
+use crate::config::{Config, CrateConfig};
+use crate::prelude::*;
+use rustwide::Crate as RustwideCrate;
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+/// Source the crates of an experiment are fetched from. Every variant of `Crate` wraps a type
+/// implementing this trait, so adding a new source doesn't require changes outside of this module.
+///
+/// The `commit` received by some of the methods is the commit tested by the experiment, for the
+/// sources recording it while the crate is prepared.
+pub(crate) trait CrateSource: fmt::Display {
+    /// Short name of the source, used as the first component of the IDs and of the report paths.
+    fn kind(&self) -> &'static str;
+
+    /// Identifier of the crate, unique among the crates of the same source.
+    fn id(&self) -> String;
+
+    /// Name of the directory of the crate in the report, unique among the crates of the same
+    /// source. It's sanitized by the caller.
+    fn path_name(&self) -> String {
+        self.id()
+    }
+
+    /// Name of the crate returned by the public API.
+    fn name(&self) -> String;
+
+    /// Name of the crate shown in the report.
+    fn report_name(&self, _commit: Option<&str>) -> String {
+        self.to_string()
+    }
+
+    /// URL of the source code of the crate.
+    fn url(&self, commit: Option<&str>) -> String;
+
+    /// Shell commands downloading the source code of the crate in the current directory.
+    fn fetch_commands(&self, commit: Option<&str>) -> Vec<String>;
+
+    /// Download the source code rustwide can't fetch by itself, before the crate is prepared.
+    fn fetch(&self) -> Fallible<()> {
+        Ok(())
+    }
+
+    /// Fetch the content of the `Cargo.toml` of the crate.
+    fn fetch_manifest(&self) -> Fallible<String>;
+
+    fn to_rustwide(&self) -> RustwideCrate;
+
+    /// Configuration of the crate in `config.toml`, if the source can be configured.
+    fn config<'a>(&self, _config: &'a Config) -> Option<&'a CrateConfig> {
+        None
+    }
+}
+
+/// Run git in `dir`, returning its standard output.
+fn run_git(dir: &Path, args: &[&str]) -> Fallible<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        // Fail instead of waiting for credentials when a repository isn't public
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .with_context(|_| format!("failed to run git in {}", dir.display()))?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
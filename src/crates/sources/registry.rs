@@ -1,11 +1,150 @@
+use crate::config::{Config, CrateConfig};
+use crate::crates::sources::{run_git, CrateSource};
 use crate::crates::{lists::List, Crate};
+use crate::db::{Database, QueryUtils};
 use crate::dirs::WORK_DIR;
+use crate::experiments::CrateSkipReason;
 use crate::prelude::*;
-use crates_index::Index;
-use std::collections::HashMap;
+use crate::utils::{http, string::shell_quote};
+use chrono::{DateTime, TimeZone, Utc};
+use crates_index::{DependencyKind, Index};
+use flate2::read::GzDecoder;
+use rustwide::Crate as RustwideCrate;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::fs::{self};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tar::Archive;
 
-pub(crate) struct RegistryList;
+const CRATES_IO_API: &str = "https://crates.io/api/v1/crates";
+const CRATES_IO_PAGE_SIZE: usize = 100;
+/// Delay between two requests to the crates.io API, as required by its crawler policy.
+const CRATES_IO_API_DELAY: Duration = Duration::from_secs(1);
+
+fn index_path() -> PathBuf {
+    WORK_DIR.join("crates.io-index")
+}
+
+#[derive(Deserialize)]
+struct CratesPage {
+    crates: Vec<CratesPageCrate>,
+}
+
+#[derive(Deserialize)]
+struct CratesPageCrate {
+    name: String,
+}
+
+/// Commit of the crates.io index a list of registry crates was generated from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IndexSnapshot {
+    pub commit: String,
+    pub date: DateTime<Utc>,
+}
+
+impl IndexSnapshot {
+    /// Get the snapshot the registry list in the database was generated from, if it was recorded.
+    pub(crate) fn load(db: &Database) -> Fallible<Option<Self>> {
+        let raw = db.get_row(
+            "SELECT snapshot FROM list_snapshots WHERE list = ?1;",
+            &[&RegistryList::NAME],
+            |r| r.get::<_, String>("snapshot"),
+        )?;
+        Ok(raw.map(|raw| serde_json::from_str(&raw)).transpose()?)
+    }
+
+    pub(crate) fn store(&self, db: &Database) -> Fallible<()> {
+        db.execute(
+            "INSERT OR REPLACE INTO list_snapshots (list, snapshot) VALUES (?1, ?2);",
+            &[&RegistryList::NAME, &serde_json::to_string(self)?],
+        )?;
+        Ok(())
+    }
+
+    /// Path of the local clone of the index at this snapshot, used as the registry of the builds.
+    pub(crate) fn registry_path(&self) -> PathBuf {
+        WORK_DIR.join("index-snapshots").join(&self.commit)
+    }
+
+    /// Create the local clone of the index at this snapshot, if it doesn't exist yet. Cargo
+    /// fetches the `master` branch of registries, which points to the snapshot in the clone.
+    pub(crate) fn prepare_registry(&self) -> Fallible<()> {
+        let dest = self.registry_path();
+        if dest.exists() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&*WORK_DIR)?;
+        Index::new(index_path()).retrieve_or_update().to_failure()?;
+        // The index is squashed periodically, so old commits might not be available anymore
+        run_git(
+            &index_path(),
+            &["cat-file", "-e", &format!("{}^{{commit}}", self.commit)],
+        )
+        .with_context(|_| format!("the crates.io index doesn't contain commit {}", self.commit))?;
+
+        // The clone is moved in place only once it's complete, as multiple experiments could be
+        // pinned to the same snapshot
+        let parent = dest.parent().unwrap();
+        fs::create_dir_all(parent)?;
+        let tmp = tempfile::tempdir_in(parent)?;
+        let tmp_path = tmp.path().to_string_lossy().into_owned();
+        run_git(
+            &index_path(),
+            &["clone", "--quiet", "--bare", "--shared", ".", &tmp_path],
+        )?;
+        run_git(
+            tmp.path(),
+            &["update-ref", "refs/heads/master", &self.commit],
+        )?;
+        run_git(tmp.path(), &["symbolic-ref", "HEAD", "refs/heads/master"])?;
+        if let Err(err) = fs::rename(tmp.path(), &dest) {
+            if !dest.exists() {
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn parse(raw: &str) -> Fallible<Self> {
+        let mut parts = raw.trim().split(' ');
+        match (parts.next(), parts.next().map(|ts| ts.parse::<i64>())) {
+            (Some(commit), Some(Ok(timestamp))) if !commit.is_empty() => Ok(IndexSnapshot {
+                commit: commit.to_string(),
+                date: Utc.timestamp(timestamp, 0),
+            }),
+            _ => bail!("invalid snapshot of the crates.io index: {}", raw),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct RegistryList {
+    /// Commit of the crates.io index the list is generated from, instead of the latest one.
+    snapshot: Option<String>,
+}
+
+impl RegistryList {
+    pub(crate) fn at_snapshot(commit: &str) -> Self {
+        RegistryList {
+            snapshot: Some(commit.to_string()),
+        }
+    }
+
+    /// Get the snapshot of the crates.io index this list is generated from. The index has to be
+    /// fetched already.
+    pub(crate) fn index_snapshot(&self) -> Fallible<IndexSnapshot> {
+        let rev = self.snapshot.as_ref().map(|c| c.as_str()).unwrap_or("HEAD");
+        // The index is squashed periodically, so old commits might not be available anymore
+        let raw = run_git(&index_path(), &["log", "-1", "--format=%H %ct", rev])
+            .with_context(|_| format!("the crates.io index doesn't contain commit {}", rev))?;
+        IndexSnapshot::parse(&raw)
+    }
+}
 
 impl List for RegistryList {
     const NAME: &'static str = "registry";
@@ -15,9 +154,34 @@ impl List for RegistryList {
         let mut counts = HashMap::new();
 
         fs::create_dir_all(&*WORK_DIR)?;
-        let index = Index::new(WORK_DIR.join("crates.io-index"));
+        let index = Index::new(index_path());
         index.retrieve_or_update().to_failure()?;
 
+        // Old snapshots are checked out in a separate clone, to avoid touching the local copy of
+        // the index used by the rest of Crater
+        let checkout = if self.snapshot.is_some() {
+            let snapshot = self.index_snapshot()?;
+            info!(
+                "loading the crates.io index at commit {} ({})",
+                snapshot.commit, snapshot.date
+            );
+
+            let dir = tempfile::tempdir()?;
+            let dest = dir.path().to_string_lossy().into_owned();
+            run_git(
+                &index_path(),
+                &["clone", "--quiet", "--shared", "--no-checkout", ".", &dest],
+            )?;
+            run_git(dir.path(), &["checkout", "--quiet", &snapshot.commit])?;
+            Some(dir)
+        } else {
+            None
+        };
+        let index = match checkout {
+            Some(ref dir) => Index::new(dir.path()),
+            None => index,
+        };
+
         for krate in index.crates() {
             // The versions() method returns the list of published versions starting from the
             // first one, so its output is reversed to check the latest first
@@ -55,8 +219,290 @@ impl List for RegistryList {
     }
 }
 
+/// Find the registry crates that can't be tested anymore, as their version was yanked or they were
+/// removed from crates.io. The local copy of the index is used, which is updated along with the
+/// lists.
+pub(crate) fn unavailable_crates(crates: &[Crate]) -> Fallible<HashMap<Crate, CrateSkipReason>> {
+    let mut wanted = HashMap::new();
+    for krate in crates {
+        if let Crate::Registry(ref details) = krate {
+            wanted
+                .entry(details.name.as_str())
+                .or_insert_with(Vec::new)
+                .push(krate);
+        }
+    }
+
+    let mut unavailable = HashMap::new();
+    let path = index_path();
+    if wanted.is_empty() {
+        return Ok(unavailable);
+    } else if !path.exists() {
+        warn!("the crates.io index wasn't fetched yet, skipping the check of unavailable crates");
+        return Ok(unavailable);
+    }
+
+    for index_crate in Index::new(path).crates() {
+        let crates = match wanted.remove(index_crate.name()) {
+            Some(crates) => crates,
+            None => continue,
+        };
+        for krate in crates {
+            if let Crate::Registry(ref details) = krate {
+                let version = index_crate
+                    .versions()
+                    .iter()
+                    .find(|version| version.version() == details.version);
+                match version {
+                    Some(version) if version.is_yanked() => {
+                        unavailable.insert(krate.clone(), CrateSkipReason::Yanked);
+                    }
+                    Some(_) => {}
+                    None => {
+                        unavailable.insert(krate.clone(), CrateSkipReason::MissingFromIndex);
+                    }
+                }
+            }
+        }
+    }
+
+    // Crates still wanted after going through the whole index were removed from it
+    for krate in wanted.into_iter().flat_map(|(_, crates)| crates) {
+        unavailable.insert(krate.clone(), CrateSkipReason::MissingFromIndex);
+    }
+
+    Ok(unavailable)
+}
+
+/// Crates depending on each crate, along with whether the dependency is also built by their own
+/// dependents (which is not the case for dev-dependencies).
+type Dependents = HashMap<String, Vec<(String, bool)>>;
+
+/// Find the names of the crates depending on `root`, either directly or through at most `depth`
+/// other crates (any number of them if `depth` is `None`). Only the latest version of every crate
+/// is considered, and the local copy of the index is used, which is updated along with the lists.
+pub(crate) fn reverse_dependencies(root: &str, depth: Option<usize>) -> Fallible<HashSet<String>> {
+    let path = index_path();
+    if !path.exists() {
+        bail!("the crates.io index wasn't fetched yet, run `crater create-lists` first");
+    }
+
+    let mut dependents = Dependents::new();
+    let mut found = false;
+    for krate in Index::new(path).crates() {
+        found |= krate.name() == root;
+        let latest = krate.versions().iter().rev().find(|v| !v.is_yanked());
+        for dependency in latest.iter().flat_map(|version| version.dependencies()) {
+            let transitive = match dependency.kind() {
+                Some(DependencyKind::Dev) => false,
+                _ => true,
+            };
+            dependents
+                .entry(dependency.name().to_string())
+                .or_insert_with(Vec::new)
+                .push((krate.name().to_string(), transitive));
+        }
+    }
+    if !found {
+        bail!("crate {} is not in the crates.io index", root);
+    }
+
+    Ok(collect_dependents(&dependents, root, depth))
+}
+
+/// Find the names of the crates matching a filter of the crates.io API, like `category` or
+/// `keyword`. Categories also match the crates in their subcategories.
+pub(crate) fn crates_io_search(filter: &str, value: &str) -> Fallible<HashSet<String>> {
+    let mut names = HashSet::new();
+    for page in 1.. {
+        if page > 1 {
+            thread::sleep(CRATES_IO_API_DELAY);
+        }
+        let url = url::Url::parse_with_params(
+            CRATES_IO_API,
+            &[
+                (filter, value.to_string()),
+                ("per_page", CRATES_IO_PAGE_SIZE.to_string()),
+                ("page", page.to_string()),
+            ],
+        )?;
+        let response: CratesPage = http::get_sync(url.as_str())?
+            .json()
+            .with_context(|_| format!("invalid response from the crates.io API: {}", url))?;
+
+        let last = response.crates.len() < CRATES_IO_PAGE_SIZE;
+        names.extend(response.crates.into_iter().map(|krate| krate.name));
+        if last {
+            break;
+        }
+    }
+
+    if names.is_empty() {
+        bail!("no crates.io crates match the {} {}", filter, value);
+    }
+    info!(
+        "found {} crates.io crates with {} {}",
+        names.len(),
+        filter,
+        value
+    );
+    Ok(names)
+}
+
+fn collect_dependents(
+    dependents: &Dependents,
+    root: &str,
+    depth: Option<usize>,
+) -> HashSet<String> {
+    let mut result = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    visited.insert(root.to_string());
+    queue.push_back((root, 0));
+
+    while let Some((name, distance)) = queue.pop_front() {
+        if depth.map(|depth| distance >= depth).unwrap_or(false) {
+            continue;
+        }
+        for (dependent, transitive) in dependents.get(name).into_iter().flatten() {
+            result.insert(dependent.clone());
+            // The crates depending on a dev-dependency don't build it, so they're not dependents
+            // of the crates it depends on
+            if *transitive && visited.insert(dependent.clone()) {
+                queue.push_back((dependent.as_str(), distance + 1));
+            }
+        }
+    }
+
+    result.remove(root);
+    result
+}
+
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Clone)]
 pub struct RegistryCrate {
     pub name: String,
     pub version: String,
 }
+
+impl fmt::Display for RegistryCrate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.name, self.version)
+    }
+}
+
+impl CrateSource for RegistryCrate {
+    fn kind(&self) -> &'static str {
+        "reg"
+    }
+
+    fn id(&self) -> String {
+        format!("{}/{}", self.name, self.version)
+    }
+
+    fn path_name(&self) -> String {
+        self.to_string()
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn url(&self, _commit: Option<&str>) -> String {
+        format!("https://crates.io/crates/{}/{}", self.name, self.version)
+    }
+
+    fn fetch_commands(&self, _commit: Option<&str>) -> Vec<String> {
+        vec![format!(
+            "curl -sSfL https://static.crates.io/crates/{0}/{0}-{1}.crate \
+             | tar -xz --strip-components=1",
+            shell_quote(&self.name),
+            shell_quote(&self.version)
+        )]
+    }
+
+    fn fetch_manifest(&self) -> Fallible<String> {
+        let url = format!(
+            "https://static.crates.io/crates/{name}/{name}-{version}.crate",
+            name = self.name,
+            version = self.version
+        );
+        let path = format!("{}-{}/Cargo.toml", self.name, self.version);
+
+        let mut archive = Archive::new(GzDecoder::new(http::get_sync(&url)?));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.path()? == Path::new(&path) {
+                let mut content = String::new();
+                entry.read_to_string(&mut content)?;
+                return Ok(content);
+            }
+        }
+        bail!("missing Cargo.toml in {}", url);
+    }
+
+    fn to_rustwide(&self) -> RustwideCrate {
+        RustwideCrate::crates_io(&self.name, &self.version)
+    }
+
+    fn config<'a>(&self, config: &'a Config) -> Option<&'a CrateConfig> {
+        config.crates.get(&self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect_dependents, Dependents, IndexSnapshot};
+    use crate::db::Database;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_index_snapshot() {
+        let db = Database::temp().unwrap();
+        assert_eq!(IndexSnapshot::load(&db).unwrap(), None);
+
+        let snapshot =
+            IndexSnapshot::parse("0123456789abcdef0123456789abcdef01234567 1546300800\n").unwrap();
+        assert_eq!(
+            snapshot,
+            IndexSnapshot {
+                commit: "0123456789abcdef0123456789abcdef01234567".into(),
+                date: Utc.ymd(2019, 1, 1).and_hms(0, 0, 0),
+            }
+        );
+        assert!(IndexSnapshot::parse("").is_err());
+        assert!(IndexSnapshot::parse("0123456789abcdef").is_err());
+
+        snapshot.store(&db).unwrap();
+        assert_eq!(IndexSnapshot::load(&db).unwrap(), Some(snapshot));
+    }
+
+    #[test]
+    fn test_collect_dependents() {
+        let mut dependents = Dependents::new();
+        let mut depend = |krate: &str, dependency: &str, transitive: bool| {
+            dependents
+                .entry(dependency.to_string())
+                .or_insert_with(Vec::new)
+                .push((krate.to_string(), transitive));
+        };
+        depend("b", "a", true);
+        depend("c", "b", true);
+        depend("d", "c", true);
+        depend("e", "a", false);
+        depend("f", "e", true);
+        // Cycles through dev-dependencies are allowed by Cargo
+        depend("a", "d", false);
+
+        let collect = |depth| {
+            let mut names = collect_dependents(&dependents, "a", depth)
+                .into_iter()
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        assert_eq!(collect(None), vec!["b", "c", "d", "e"]);
+        assert_eq!(collect(Some(1)), vec!["b", "e"]);
+        assert_eq!(collect(Some(2)), vec!["b", "c", "e"]);
+        assert!(collect_dependents(&dependents, "f", None).is_empty());
+    }
+}
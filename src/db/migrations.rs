@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use crate::results::{lockfile_hash, EncodedLog, EncodingType};
 use rand::{self, distributions::Alphanumeric, Rng};
 use rusqlite::{Connection, Transaction};
 use serde_json;
@@ -304,6 +305,747 @@ fn migrations() -> Vec<(&'static str, MigrationKind)> {
         ),
     ));
 
+    migrations.push((
+        "deduplicate_experiment_crates",
+        MigrationKind::SQL(
+            "
+            DELETE FROM experiment_crates WHERE rowid NOT IN (
+                SELECT MIN(rowid) FROM experiment_crates GROUP BY experiment, crate
+            );
+
+            CREATE UNIQUE INDEX experiment_crates__experiment_crate
+            ON experiment_crates (experiment, crate);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "deduplicate_logs",
+        MigrationKind::Code(Box::new(|t| {
+            t.execute_batch(
+                "
+                CREATE TABLE logs (
+                    hash TEXT PRIMARY KEY,
+                    encoding TEXT NOT NULL,
+                    content BLOB NOT NULL,
+                    refs INTEGER NOT NULL DEFAULT 0
+                );
+
+                CREATE TABLE results_new (
+                    experiment TEXT NOT NULL,
+                    crate TEXT NOT NULL,
+                    toolchain TEXT NOT NULL,
+                    result TEXT NOT NULL,
+                    log TEXT NOT NULL,
+
+                    PRIMARY KEY (experiment, crate, toolchain),
+                    FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE,
+                    FOREIGN KEY (log) REFERENCES logs(hash)
+                );
+                ",
+            )?;
+
+            {
+                let mut select = t.prepare(
+                    "SELECT experiment, crate, toolchain, result, log, encoding FROM results;",
+                )?;
+                let mut rows = select.query(no_args())?;
+                while let Some(row) = rows.next() {
+                    let row = row?;
+                    let content: Vec<u8> = row.get("log");
+                    let encoding: String = row.get("encoding");
+                    let log = if encoding == EncodingType::Gzip.to_str() {
+                        EncodedLog::Gzip(content)
+                    } else {
+                        EncodedLog::Plain(content)
+                    };
+                    let hash = log.content_hash();
+
+                    t.execute(
+                        "INSERT OR IGNORE INTO logs (hash, encoding, content) VALUES (?1, ?2, ?3);",
+                        &[&hash, &encoding, &log.as_slice()],
+                    )?;
+                    t.execute(
+                        "INSERT INTO results_new (experiment, crate, toolchain, result, log) \
+                         VALUES (?1, ?2, ?3, ?4, ?5);",
+                        &[
+                            &row.get::<_, String>("experiment"),
+                            &row.get::<_, String>("crate"),
+                            &row.get::<_, String>("toolchain"),
+                            &row.get::<_, String>("result"),
+                            &hash,
+                        ],
+                    )?;
+                }
+            }
+
+            t.execute_batch(
+                "
+                DROP TABLE results;
+                ALTER TABLE results_new RENAME TO results;
+
+                CREATE INDEX results__experiment ON results (experiment);
+
+                UPDATE logs SET refs = (SELECT COUNT(*) FROM results WHERE results.log = logs.hash);
+
+                CREATE TRIGGER results__logs_insert AFTER INSERT ON results
+                BEGIN
+                    UPDATE logs SET refs = refs + 1 WHERE hash = NEW.log;
+                END;
+
+                CREATE TRIGGER results__logs_delete AFTER DELETE ON results
+                BEGIN
+                    UPDATE logs SET refs = refs - 1 WHERE hash = OLD.log;
+                    DELETE FROM logs WHERE hash = OLD.log AND refs <= 0;
+                END;
+                ",
+            )?;
+
+            Ok(())
+        })),
+    ));
+
+    migrations.push((
+        "add_namespaces",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default';
+            ALTER TABLE agents ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default';
+
+            CREATE INDEX experiments__namespace ON experiments (namespace);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_audit_log",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                created_at DATETIME NOT NULL,
+                actor TEXT NOT NULL,
+                action TEXT NOT NULL,
+                experiment TEXT,
+                payload TEXT NOT NULL
+            );
+
+            CREATE INDEX audit_log__experiment ON audit_log (experiment);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "store_agents_version",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE agents ADD COLUMN version TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_maintenance",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE maintenance (
+                message TEXT NOT NULL,
+                since DATETIME NOT NULL
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_pinned",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "results_logs_update_trigger",
+        MigrationKind::SQL(
+            "
+            CREATE TRIGGER results__logs_update AFTER UPDATE OF log ON results
+            BEGIN
+                UPDATE logs SET refs = refs + 1 WHERE hash = NEW.log;
+                UPDATE logs SET refs = refs - 1 WHERE hash = OLD.log;
+                DELETE FROM logs WHERE hash = OLD.log AND refs <= 0;
+            END;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_crate_metadata",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE crate_metadata (
+                crate TEXT PRIMARY KEY,
+                edition TEXT
+            );
+
+            ALTER TABLE experiments ADD COLUMN edition TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_crate_metadata_rust_version",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE crate_metadata ADD COLUMN rust_version TEXT;
+            ALTER TABLE experiments ADD COLUMN max_rust_version TEXT;
+
+            -- Analyze the manifests again to load the new metadata
+            DELETE FROM crate_metadata;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_crate_metadata_target_os",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE crate_metadata ADD COLUMN target_os TEXT;
+
+            -- Analyze the manifests again to load the new metadata
+            DELETE FROM crate_metadata;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiment_crates_metadata",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE crate_metadata ADD COLUMN features TEXT;
+            ALTER TABLE crate_metadata ADD COLUMN workspace_members TEXT;
+            ALTER TABLE experiment_crates ADD COLUMN metadata TEXT;
+
+            -- Analyze the manifests again to load the new metadata
+            DELETE FROM crate_metadata;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_member_results",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE member_results (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                member TEXT NOT NULL,
+                result TEXT NOT NULL,
+
+                PRIMARY KEY (experiment, crate, toolchain, member) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_failed_targets",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE failed_targets (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                package TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                name TEXT NOT NULL,
+
+                PRIMARY KEY (experiment, crate, toolchain, package, kind, name) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_build_targets",
+        MigrationKind::SQL(
+            "ALTER TABLE experiments ADD COLUMN build_targets TEXT NOT NULL DEFAULT 'default';",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_test_flags",
+        MigrationKind::SQL(
+            "ALTER TABLE experiments ADD COLUMN test_flags TEXT NOT NULL DEFAULT '';",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_dist_server",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN dist_server TEXT;"),
+    ));
+
+    migrations.push((
+        "add_experiments_rustc_wrapper",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN rustc_wrapper TEXT;"),
+    ));
+
+    migrations.push((
+        "create_toolchain_versions",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE toolchain_versions (
+                experiment TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                release TEXT NOT NULL,
+                commit_hash TEXT,
+                commit_date TEXT,
+                host TEXT,
+                llvm_version TEXT,
+
+                PRIMARY KEY (experiment, toolchain) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_diagnostics",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE diagnostics (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                package TEXT NOT NULL,
+                target_kind TEXT,
+                target_name TEXT,
+                level TEXT NOT NULL,
+                code TEXT,
+                message TEXT NOT NULL,
+                file TEXT,
+                line INTEGER,
+                column INTEGER,
+
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            CREATE INDEX diagnostics__experiment_crate_toolchain
+                ON diagnostics (experiment, crate, toolchain);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_diagnostic_counts",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE diagnostic_counts (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                errors INTEGER NOT NULL,
+                warnings INTEGER NOT NULL,
+
+                PRIMARY KEY (experiment, crate, toolchain) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_timings",
+        MigrationKind::SQL(
+            "ALTER TABLE experiments ADD COLUMN timings INTEGER NOT NULL DEFAULT 0;",
+        ),
+    ));
+
+    migrations.push((
+        "create_unit_timings",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE unit_timings (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                package TEXT NOT NULL,
+                version TEXT NOT NULL,
+                target_kind TEXT,
+                target_name TEXT NOT NULL,
+                mode TEXT NOT NULL,
+                duration REAL NOT NULL,
+
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            CREATE INDEX unit_timings__experiment_crate_toolchain
+                ON unit_timings (experiment, crate, toolchain);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_targets",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN targets TEXT NOT NULL DEFAULT '';"),
+    ));
+
+    migrations.push((
+        "delete_orphaned_experiment_rows",
+        MigrationKind::SQL(
+            "
+            -- Rows written while the foreign keys weren't enforced can reference experiments
+            -- that were already deleted, and would never be cleaned up otherwise
+            DELETE FROM experiment_crates WHERE experiment NOT IN (SELECT name FROM experiments);
+            DELETE FROM results WHERE experiment NOT IN (SELECT name FROM experiments);
+            DELETE FROM shas WHERE experiment NOT IN (SELECT name FROM experiments);
+            DELETE FROM member_results WHERE experiment NOT IN (SELECT name FROM experiments);
+            DELETE FROM failed_targets WHERE experiment NOT IN (SELECT name FROM experiments);
+            DELETE FROM toolchain_versions WHERE experiment NOT IN (SELECT name FROM experiments);
+            DELETE FROM diagnostics WHERE experiment NOT IN (SELECT name FROM experiments);
+            DELETE FROM diagnostic_counts WHERE experiment NOT IN (SELECT name FROM experiments);
+            DELETE FROM unit_timings WHERE experiment NOT IN (SELECT name FROM experiments);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_invocations",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE invocations (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                rustup_toolchain TEXT NOT NULL,
+                cargo_toolchain TEXT,
+                args TEXT NOT NULL,
+                env TEXT NOT NULL,
+                memory_limit INTEGER,
+                cpu_limit REAL,
+
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            CREATE INDEX invocations__experiment_crate_toolchain
+                ON invocations (experiment, crate, toolchain);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_tags",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN tags TEXT NOT NULL DEFAULT '';"),
+    ));
+
+    migrations.push((
+        "add_experiment_crates_skip_reason",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiment_crates ADD COLUMN skip_reason TEXT;
+            UPDATE experiment_crates SET skip_reason = 'blacklisted' WHERE skipped = 1;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_index_snapshots",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE list_snapshots (
+                list TEXT PRIMARY KEY NOT NULL,
+                snapshot TEXT NOT NULL
+            );
+            ALTER TABLE experiments ADD COLUMN index_snapshot TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_doc_items",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE doc_items (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                path TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                hash TEXT NOT NULL,
+
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            CREATE INDEX doc_items__experiment_crate_toolchain
+                ON doc_items (experiment, crate, toolchain);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_webhook_events",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE webhook_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                event TEXT NOT NULL,
+                host TEXT NOT NULL,
+                payload BLOB NOT NULL,
+                received_at DATETIME NOT NULL,
+                status TEXT NOT NULL,
+                attempts INTEGER NOT NULL,
+                last_error TEXT
+            );
+            CREATE INDEX webhook_events__status ON webhook_events (status);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_resource_usage",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE resource_usage (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                duration REAL NOT NULL,
+                cpus REAL NOT NULL,
+
+                PRIMARY KEY (experiment, crate, toolchain) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_crates_seed",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN crates_seed INTEGER;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_crate_lists",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE crate_lists (
+                name TEXT PRIMARY KEY,
+                crates TEXT NOT NULL,
+                uploaded_at DATETIME NOT NULL
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_snapshots",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE snapshots (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                content BLOB NOT NULL,
+
+                PRIMARY KEY (experiment, crate, toolchain) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_c_compilers",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE c_compilers (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                version TEXT NOT NULL,
+
+                PRIMARY KEY (experiment, crate, toolchain) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_jobs",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE jobs (
+                name TEXT PRIMARY KEY,
+                status TEXT NOT NULL,
+                runs INTEGER NOT NULL,
+                failures INTEGER NOT NULL,
+                last_started_at DATETIME,
+                last_finished_at DATETIME,
+                next_run_at DATETIME,
+                last_error TEXT
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_lockfiles",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE lockfiles (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                lockfile TEXT NOT NULL,
+
+                PRIMARY KEY (experiment, crate, toolchain) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiment_intermediate_toolchains",
+        MigrationKind::SQL(
+            "ALTER TABLE experiments ADD COLUMN intermediate_toolchains TEXT NOT NULL DEFAULT '[]';",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiment_crates_claims",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiment_crates ADD COLUMN claimed_by TEXT;
+            ALTER TABLE experiment_crates ADD COLUMN claimed_at DATETIME;
+
+            CREATE INDEX experiment_crates__claimed_by ON experiment_crates (claimed_by);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_last_progress_at",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN last_progress_at DATETIME;"),
+    ));
+
+    migrations.push((
+        "create_schedules",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE schedules (
+                name TEXT PRIMARY KEY,
+                last_run DATETIME NOT NULL
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_crate_select",
+        MigrationKind::SQL("ALTER TABLE experiments ADD COLUMN crate_select TEXT;"),
+    ));
+
+    migrations.push((
+        "add_invocations_cpuset",
+        MigrationKind::SQL("ALTER TABLE invocations ADD COLUMN cpuset TEXT;"),
+    ));
+
+    migrations.push((
+        "create_bisections",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE bisections (
+                experiment TEXT PRIMARY KEY NOT NULL,
+                crate TEXT NOT NULL,
+
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "deduplicate_lockfiles",
+        MigrationKind::Code(Box::new(|t| {
+            t.execute_batch(
+                "
+                CREATE TABLE lockfile_contents (
+                    hash TEXT PRIMARY KEY,
+                    content TEXT NOT NULL,
+                    refs INTEGER NOT NULL DEFAULT 0
+                );
+
+                CREATE TABLE lockfiles_new (
+                    experiment TEXT NOT NULL,
+                    crate TEXT NOT NULL,
+                    toolchain TEXT NOT NULL,
+                    lockfile TEXT NOT NULL,
+
+                    PRIMARY KEY (experiment, crate, toolchain),
+                    FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE,
+                    FOREIGN KEY (lockfile) REFERENCES lockfile_contents(hash)
+                );
+                ",
+            )?;
+
+            {
+                let mut select =
+                    t.prepare("SELECT experiment, crate, toolchain, lockfile FROM lockfiles;")?;
+                let mut rows = select.query(no_args())?;
+                while let Some(row) = rows.next() {
+                    let row = row?;
+                    let content: String = row.get("lockfile");
+                    let hash = lockfile_hash(&content);
+
+                    t.execute(
+                        "INSERT OR IGNORE INTO lockfile_contents (hash, content) VALUES (?1, ?2);",
+                        &[&hash, &content],
+                    )?;
+                    t.execute(
+                        "INSERT INTO lockfiles_new (experiment, crate, toolchain, lockfile) \
+                         VALUES (?1, ?2, ?3, ?4);",
+                        &[
+                            &row.get::<_, String>("experiment"),
+                            &row.get::<_, String>("crate"),
+                            &row.get::<_, String>("toolchain"),
+                            &hash,
+                        ],
+                    )?;
+                }
+            }
+
+            t.execute_batch(
+                "
+                DROP TABLE lockfiles;
+                ALTER TABLE lockfiles_new RENAME TO lockfiles;
+
+                UPDATE lockfile_contents SET refs = (
+                    SELECT COUNT(*) FROM lockfiles WHERE lockfiles.lockfile = lockfile_contents.hash
+                );
+
+                CREATE TRIGGER lockfiles__contents_insert AFTER INSERT ON lockfiles
+                BEGIN
+                    UPDATE lockfile_contents SET refs = refs + 1 WHERE hash = NEW.lockfile;
+                END;
+
+                CREATE TRIGGER lockfiles__contents_delete AFTER DELETE ON lockfiles
+                BEGIN
+                    UPDATE lockfile_contents SET refs = refs - 1 WHERE hash = OLD.lockfile;
+                    DELETE FROM lockfile_contents WHERE hash = OLD.lockfile AND refs <= 0;
+                END;
+                ",
+            )?;
+
+            Ok(())
+        })),
+    ));
+
     migrations
 }
 
@@ -10,4 +10,5 @@ lazy_static! {
             .into()
     };
     pub static ref LOCAL_CRATES_DIR: PathBuf = "local-crates".into();
+    pub static ref RUSTC_WRAPPERS_DIR: PathBuf = WORK_DIR.join("rustc-wrappers");
 }
@@ -1,21 +1,61 @@
-use crate::crates::Crate;
+use crate::agent::{AgentWorkers, Capabilities};
+use crate::audit::{self, Actor, AuditAction};
+use crate::config::{ChunksConfig, Config, PriorityAgingConfig};
+use crate::crates::{Crate, CrateMetadata, Edition, IndexSnapshot, RustVersion};
 use crate::db::{Database, QueryUtils};
 use crate::prelude::*;
+use crate::report::Comparison;
 use crate::toolchain::Toolchain;
-use chrono::{DateTime, Utc};
+use crate::utils::string::split_quoted;
+use chrono::{DateTime, Duration, Utc};
 use rusqlite::Row;
-use serde_json;
+use serde_json::{self, json};
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::fmt;
+use std::path::PathBuf;
 use std::str::FromStr;
 
+/// Namespace experiments and agents belong to when no other namespace is configured.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+fn default_namespace() -> String {
+    DEFAULT_NAMESPACE.to_string()
+}
+
+/// Check whether a namespace name is valid, allowing only lowercase ASCII letters, digits, `-`
+/// and `_`.
+pub fn is_valid_namespace(namespace: &str) -> bool {
+    !namespace.is_empty()
+        && namespace
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+/// Return the name an experiment is stored with.
+///
+/// Experiment names are only unique inside their namespace, so experiments outside the default
+/// namespace are prefixed with the namespace name (for example `infra:pr-1234`).
+pub fn qualified_name(namespace: &str, name: &str) -> String {
+    if namespace == DEFAULT_NAMESPACE {
+        name.to_string()
+    } else {
+        format!("{}:{}", namespace, name)
+    }
+}
+
 string_enum!(pub enum Status {
+    Creating => "creating",
     Queued => "queued",
     Running => "running",
     NeedsReport => "needs-report",
     Failed => "failed",
+    ToolchainInstallFailed => "toolchain-install-failed",
     GeneratingReport => "generating-report",
     ReportFailed => "report-failed",
     Completed => "completed",
+    Cancelled => "cancelled",
+    Archived => "archived",
 });
 
 string_enum!(pub enum Mode {
@@ -24,17 +64,171 @@ string_enum!(pub enum Mode {
     CheckOnly => "check-only",
     Clippy => "clippy",
     Rustdoc => "rustdoc",
+    RustdocJson => "rustdoc-json",
     UnstableFeatures => "unstable-features",
+    BuildBenches => "build-benches",
+    Miri => "miri",
+    Fix => "fix",
+    DocTest => "doctest",
 });
 
-string_enum!(pub enum CrateSelect {
-    Full => "full",
-    Demo => "demo",
-    SmallRandom => "small-random",
-    Top100 => "top-100",
-    Local => "local",
-    Dummy => "dummy",
-});
+const TOP_PREFIX: &str = "top-";
+const RANDOM_PREFIX: &str = "random-";
+const LOCAL_SOURCE_PREFIX: &str = "local:";
+const REGRESSED_PREFIX: &str = "regressed:";
+const SAME_AS_PREFIX: &str = "same-as:";
+const REVERSE_DEPS_PREFIX: &str = "rdeps:";
+const LIST_PREFIX: &str = "list:";
+const CATEGORY_PREFIX: &str = "category:";
+const KEYWORD_PREFIX: &str = "keyword:";
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum CrateSelect {
+    Full,
+    Demo,
+    SmallRandom,
+    /// The most downloaded crates of the registry, selected with `top-<count>`.
+    Top(usize),
+    /// A random sample of the crates of the full list, selected with `random-<count>`. The
+    /// sample is drawn with the seed stored in the experiment, to select the same crates again
+    /// when the experiment is edited.
+    Random(u32),
+    Local,
+    Dummy,
+    /// A single crate, either in a directory or in a git repository, selected with
+    /// `local:<path-or-git-url>`.
+    LocalSource(String),
+    /// The crates that regressed in a finished experiment, selected with
+    /// `regressed:<experiment>`.
+    Regressed(String),
+    /// The same crates as another experiment, selected with `same-as:<experiment>`.
+    SameAs(String),
+    /// The registry crates depending on a crate, selected with `rdeps:<crate>`, either directly
+    /// or through at most the provided number of other crates (`rdeps:<crate>:<depth>`).
+    ReverseDeps(String, Option<usize>),
+    /// An explicit list of crates uploaded to the server or loaded from a file, selected with
+    /// `list:<name>`.
+    List(String),
+    /// The registry crates in a crates.io category (or one of its subcategories), selected with
+    /// `category:<slug>`.
+    Category(String),
+    /// The registry crates tagged with a crates.io keyword, selected with `keyword:<keyword>`.
+    Keyword(String),
+}
+
+/// Check whether a crates.io category slug or keyword can be used in a crate selection.
+fn is_valid_crates_io_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '+' || c == ':')
+}
+
+impl FromStr for CrateSelect {
+    type Err = ::failure::Error;
+
+    fn from_str(input: &str) -> Fallible<Self> {
+        Ok(match input {
+            "full" => CrateSelect::Full,
+            "demo" => CrateSelect::Demo,
+            "small-random" => CrateSelect::SmallRandom,
+            "local" => CrateSelect::Local,
+            "dummy" => CrateSelect::Dummy,
+            top if top.starts_with(TOP_PREFIX) => match top[TOP_PREFIX.len()..].parse() {
+                Ok(count) if count > 0 => CrateSelect::Top(count),
+                _ => bail!("invalid number of crates in the crate selection: {}", input),
+            },
+            random if random.starts_with(RANDOM_PREFIX) => {
+                match random[RANDOM_PREFIX.len()..].parse() {
+                    Ok(count) if count > 0 => CrateSelect::Random(count),
+                    _ => bail!("invalid number of crates in the crate selection: {}", input),
+                }
+            }
+            source if source.starts_with(LOCAL_SOURCE_PREFIX) => {
+                let source = &source[LOCAL_SOURCE_PREFIX.len()..];
+                if source.is_empty() {
+                    bail!("missing path or git URL in the crate selection: {}", input);
+                }
+                CrateSelect::LocalSource(source.to_string())
+            }
+            select if select.starts_with(REGRESSED_PREFIX) => {
+                let parent = &select[REGRESSED_PREFIX.len()..];
+                if parent.is_empty() {
+                    bail!("missing experiment name in the crate selection: {}", input);
+                }
+                CrateSelect::Regressed(parent.to_string())
+            }
+            select if select.starts_with(SAME_AS_PREFIX) => {
+                let source = &select[SAME_AS_PREFIX.len()..];
+                if source.is_empty() {
+                    bail!("missing experiment name in the crate selection: {}", input);
+                }
+                CrateSelect::SameAs(source.to_string())
+            }
+            select if select.starts_with(REVERSE_DEPS_PREFIX) => {
+                let mut parts = select[REVERSE_DEPS_PREFIX.len()..].splitn(2, ':');
+                let krate = parts.next().unwrap_or("");
+                if krate.is_empty() {
+                    bail!("missing crate name in the crate selection: {}", input);
+                }
+                let depth = match parts.next().map(|depth| depth.parse()) {
+                    None => None,
+                    Some(Ok(depth)) if depth > 0 => Some(depth),
+                    Some(_) => bail!("invalid depth in the crate selection: {}", input),
+                };
+                CrateSelect::ReverseDeps(krate.to_string(), depth)
+            }
+            select if select.starts_with(LIST_PREFIX) => {
+                let name = &select[LIST_PREFIX.len()..];
+                if name.is_empty() {
+                    bail!("missing list name in the crate selection: {}", input);
+                }
+                CrateSelect::List(name.to_string())
+            }
+            select if select.starts_with(CATEGORY_PREFIX) => {
+                let category = &select[CATEGORY_PREFIX.len()..];
+                if !is_valid_crates_io_tag(category) {
+                    bail!("invalid category in the crate selection: {}", input);
+                }
+                CrateSelect::Category(category.to_string())
+            }
+            select if select.starts_with(KEYWORD_PREFIX) => {
+                let keyword = &select[KEYWORD_PREFIX.len()..];
+                if !is_valid_crates_io_tag(keyword) {
+                    bail!("invalid keyword in the crate selection: {}", input);
+                }
+                CrateSelect::Keyword(keyword.to_string())
+            }
+            other => bail!("invalid CrateSelect: {}", other),
+        })
+    }
+}
+
+impl fmt::Display for CrateSelect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CrateSelect::Full => write!(f, "full"),
+            CrateSelect::Demo => write!(f, "demo"),
+            CrateSelect::SmallRandom => write!(f, "small-random"),
+            CrateSelect::Top(count) => write!(f, "{}{}", TOP_PREFIX, count),
+            CrateSelect::Random(count) => write!(f, "{}{}", RANDOM_PREFIX, count),
+            CrateSelect::Local => write!(f, "local"),
+            CrateSelect::Dummy => write!(f, "dummy"),
+            CrateSelect::LocalSource(source) => write!(f, "{}{}", LOCAL_SOURCE_PREFIX, source),
+            CrateSelect::Regressed(parent) => write!(f, "{}{}", REGRESSED_PREFIX, parent),
+            CrateSelect::SameAs(source) => write!(f, "{}{}", SAME_AS_PREFIX, source),
+            CrateSelect::ReverseDeps(krate, None) => write!(f, "{}{}", REVERSE_DEPS_PREFIX, krate),
+            CrateSelect::ReverseDeps(krate, Some(depth)) => {
+                write!(f, "{}{}:{}", REVERSE_DEPS_PREFIX, krate, depth)
+            }
+            CrateSelect::List(name) => write!(f, "{}{}", LIST_PREFIX, name),
+            CrateSelect::Category(category) => write!(f, "{}{}", CATEGORY_PREFIX, category),
+            CrateSelect::Keyword(keyword) => write!(f, "{}{}", KEYWORD_PREFIX, keyword),
+        }
+    }
+}
+
+impl_serde_from_parse!(CrateSelect, expecting = "a crate selection");
 
 string_enum!(pub enum CapLints {
     Allow => "allow",
@@ -43,6 +237,283 @@ string_enum!(pub enum CapLints {
     Forbid => "forbid",
 });
 
+string_enum!(pub enum BuildTargets {
+    Default => "default",
+    Examples => "examples",
+    Benches => "benches",
+    All => "all-targets",
+});
+
+impl BuildTargets {
+    /// Extra arguments passed to `cargo build` to compile these targets.
+    pub(crate) fn cargo_args(self) -> &'static [&'static str] {
+        match self {
+            BuildTargets::Default => &[],
+            BuildTargets::Examples => &["--examples"],
+            BuildTargets::Benches => &["--benches"],
+            BuildTargets::All => &["--all-targets"],
+        }
+    }
+}
+
+impl Default for BuildTargets {
+    fn default() -> Self {
+        BuildTargets::Default
+    }
+}
+
+/// Jobs completed with one of the toolchains of an experiment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ToolchainProgress {
+    pub toolchain: String,
+    pub completed_jobs: u32,
+    pub total_jobs: u32,
+}
+
+/// Detailed progress of an experiment, as returned by `Experiment::detailed_progress`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Progress {
+    pub completed_jobs: u32,
+    pub total_jobs: u32,
+    pub toolchains: Vec<ToolchainProgress>,
+    /// Number of crates in each category, counting only the crates with results on both
+    /// toolchains (once per target).
+    pub comparisons: HashMap<Comparison, u32>,
+}
+
+/// Estimated resources used by an experiment, as returned by `Experiment::cost`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExperimentCost {
+    /// Number of jobs whose resource usage was recorded by the agents.
+    pub jobs: u32,
+    /// Hours spent running the jobs, as if the agents ran one job at a time.
+    pub agent_hours: f64,
+    /// Hours spent running the jobs, multiplied by the CPUs available to each of them.
+    pub cpu_hours: f64,
+    /// Size of the logs stored for the experiment. Logs shared with other experiments are
+    /// counted in all of them.
+    pub stored_bytes: u64,
+}
+
+// Why a crate of an experiment isn't tested, recorded in the list of crates of the experiment.
+string_enum!(pub enum CrateSkipReason {
+    Blacklisted => "blacklisted",
+    IncompatibleTarget => "incompatible-target",
+    MissingFromIndex => "missing-from-index",
+    Yanked => "yanked",
+});
+
+/// Extra flags passed to `cargo test`, like `--no-fail-fast` or `-- --test-threads=1`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestFlags(Vec<String>);
+
+impl TestFlags {
+    pub fn args(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl FromStr for TestFlags {
+    type Err = ::failure::Error;
+
+    fn from_str(input: &str) -> Fallible<Self> {
+        Ok(TestFlags(
+            split_quoted(input)?
+                .into_iter()
+                .filter(|arg| !arg.is_empty())
+                .collect(),
+        ))
+    }
+}
+
+impl fmt::Display for TestFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, arg) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(f, " ")?;
+            }
+            for chr in arg.chars() {
+                if chr == ' ' || chr == '\t' || chr == '"' || chr == '\\' {
+                    write!(f, "\\")?;
+                }
+                write!(f, "{}", chr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl_serde_from_parse!(TestFlags, expecting = "a list of cargo test flags");
+
+/// Path where the agent's directory of rustc wrappers is mounted inside the build sandbox.
+pub(crate) const SANDBOX_RUSTC_WRAPPERS_DIR: &str = "/opt/crater/rustc-wrappers";
+
+/// Name of an executable in the agent's rustc wrappers directory, set as `RUSTC_WRAPPER` during
+/// the builds to wrap every rustc invocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RustcWrapper(String);
+
+impl RustcWrapper {
+    /// Path of the wrapper on the agent.
+    pub(crate) fn host_path(&self) -> PathBuf {
+        crate::dirs::RUSTC_WRAPPERS_DIR.join(&self.0)
+    }
+
+    /// Path of the wrapper inside the build sandbox.
+    pub(crate) fn sandbox_path(&self) -> String {
+        format!("{}/{}", SANDBOX_RUSTC_WRAPPERS_DIR, self.0)
+    }
+}
+
+#[derive(Debug, Fail)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+#[fail(display = "invalid rustc wrapper (it must be a file name): {}", _0)]
+pub struct InvalidRustcWrapper(String);
+
+impl FromStr for RustcWrapper {
+    type Err = InvalidRustcWrapper;
+
+    fn from_str(input: &str) -> Result<Self, InvalidRustcWrapper> {
+        // Only the executables in the wrappers directory can be used
+        if input.is_empty()
+            || input == "."
+            || input == ".."
+            || input.contains('/')
+            || input.contains('\\')
+        {
+            return Err(InvalidRustcWrapper(input.to_string()));
+        }
+        Ok(RustcWrapper(input.to_string()))
+    }
+}
+
+impl fmt::Display for RustcWrapper {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl_serde_from_parse!(RustcWrapper, expecting = "the file name of a rustc wrapper");
+
+/// Target triples the crates are built for, like `wasm32-unknown-unknown` or
+/// `aarch64-unknown-linux-gnu`. Every crate is run with both toolchains for each of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Targets(Vec<String>);
+
+/// Operating system of the agents able to build for a target triple, if it can't be built from
+/// any agent. Crates for Windows and Apple targets are linked with their platform's tools.
+fn target_host_os(triple: &str) -> Option<&'static str> {
+    if triple.contains("-windows") {
+        Some("windows")
+    } else if triple.contains("-apple-") {
+        Some("macos")
+    } else if triple.contains("-linux") {
+        Some("linux")
+    } else {
+        None
+    }
+}
+
+impl Targets {
+    pub fn triples(&self) -> &[String] {
+        &self.0
+    }
+
+    /// Operating systems the agents have to run on to build for the targets.
+    pub fn required_os(&self) -> Vec<&'static str> {
+        let mut required = Vec::new();
+        for os in self.0.iter().filter_map(|triple| target_host_os(triple)) {
+            if !required.contains(&os) {
+                required.push(os);
+            }
+        }
+        required
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for Targets {
+    type Err = ::failure::Error;
+
+    fn from_str(input: &str) -> Fallible<Self> {
+        let mut targets = Vec::new();
+        for target in input.split(',').map(|target| target.trim()) {
+            if target.is_empty() {
+                continue;
+            }
+            // The triple is part of the name of the toolchains the results are stored with
+            if target.contains('+') || target.contains(char::is_whitespace) {
+                bail!("invalid target: {}", target);
+            }
+            if !targets.iter().any(|t| t == target) {
+                targets.push(target.to_string());
+            }
+        }
+        Ok(Targets(targets))
+    }
+}
+
+impl fmt::Display for Targets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.join(","))
+    }
+}
+
+impl_serde_from_parse!(Targets, expecting = "a list of target triples");
+
+/// Arbitrary labels attached to an experiment, like `release:1.78` or `team:libs`, used to find
+/// and group related experiments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tags(Vec<String>);
+
+impl Tags {
+    pub fn names(&self) -> &[String] {
+        &self.0
+    }
+
+    pub fn contains(&self, tag: &str) -> bool {
+        self.0.iter().any(|t| t == tag)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for Tags {
+    type Err = ::failure::Error;
+
+    fn from_str(input: &str) -> Fallible<Self> {
+        let mut tags = Vec::new();
+        for tag in input.split(',').map(|tag| tag.trim()) {
+            if tag.is_empty() {
+                continue;
+            }
+            if tag.contains(char::is_whitespace) {
+                bail!("invalid tag: {}", tag);
+            }
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+        Ok(Tags(tags))
+    }
+}
+
+impl fmt::Display for Tags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.join(","))
+    }
+}
+
+impl_serde_from_parse!(Tags, expecting = "a list of tags");
+
 #[cfg_attr(test, derive(Debug, PartialEq, Eq))]
 #[derive(Clone, Serialize, Deserialize)]
 pub enum Assignee {
@@ -112,7 +583,12 @@ pub struct GitHubIssue {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Experiment {
     pub name: String,
-    pub toolchains: [Toolchain; 2],
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    /// Toolchains every crate is run with, in release order. The results are compared between the
+    /// first and the last one, while the toolchains in between are only run to find the first
+    /// toolchain a regression appeared in.
+    pub toolchains: Vec<Toolchain>,
     pub mode: Mode,
     pub cap_lints: CapLints,
     pub priority: i32,
@@ -125,6 +601,45 @@ pub struct Experiment {
     pub report_url: Option<String>,
     pub ignore_blacklist: bool,
     pub requirement: Option<String>,
+    /// Pinned experiments are never deleted by the retention policy.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Only the crates declaring this edition in their manifest are tested.
+    #[serde(default)]
+    pub edition: Option<Edition>,
+    /// Only the crates declaring a minimum Rust version up to this one are tested.
+    #[serde(default)]
+    pub max_rust_version: Option<RustVersion>,
+    /// Targets built in addition to the default ones, like the examples or the benchmarks.
+    #[serde(default)]
+    pub build_targets: BuildTargets,
+    /// Extra flags passed to `cargo test`.
+    #[serde(default)]
+    pub test_flags: TestFlags,
+    /// Server the toolchains are downloaded from, overriding the one in `config.toml`.
+    #[serde(default)]
+    pub dist_server: Option<String>,
+    /// Wrapper of every rustc invocation, used to instrument the builds.
+    #[serde(default)]
+    pub rustc_wrapper: Option<RustcWrapper>,
+    /// Whether the compile time of every crate is recorded, with Cargo's `--timings=json`.
+    #[serde(default)]
+    pub timings: bool,
+    /// Targets the crates are built for instead of the host one.
+    #[serde(default)]
+    pub targets: Targets,
+    #[serde(default)]
+    pub tags: Tags,
+    /// Commit of the crates.io index the registry crates were selected from.
+    #[serde(default)]
+    pub index_snapshot: Option<IndexSnapshot>,
+    /// Seed of the random samples of crates, generated when the experiment is created.
+    #[serde(default)]
+    pub crates_seed: Option<u32>,
+    /// Selection the crates of the experiment were generated from, missing in the experiments
+    /// created before it was stored.
+    #[serde(default)]
+    pub crate_select: Option<CrateSelect>,
 }
 
 impl Experiment {
@@ -134,8 +649,13 @@ impl Experiment {
 
     pub fn unfinished(db: &Database) -> Fallible<Vec<Experiment>> {
         let records = db.query(
-            "SELECT * FROM experiments WHERE status != ?1 ORDER BY priority DESC, created_at;",
-            &[&Status::Completed.to_str()],
+            "SELECT * FROM experiments WHERE status NOT IN (?1, ?2, ?3) \
+             ORDER BY priority DESC, created_at;",
+            &[
+                &Status::Completed.to_str(),
+                &Status::Cancelled.to_str(),
+                &Status::Archived.to_str(),
+            ],
             |r| ExperimentDBRecord::from_row(r),
         )?;
         records
@@ -144,19 +664,61 @@ impl Experiment {
             .collect::<Fallible<_>>()
     }
 
-    pub fn run_by(db: &Database, assignee: &Assignee) -> Fallible<Option<Experiment>> {
-        let record = db.get_row(
-            "SELECT * FROM experiments \
-             WHERE status = ?1 AND assigned_to = ?2;",
-            &[Status::Running.to_str(), &assignee.to_string()],
+    pub fn all(db: &Database) -> Fallible<Vec<Experiment>> {
+        let records = db.query(
+            "SELECT * FROM experiments ORDER BY created_at;",
+            &[],
             |r| ExperimentDBRecord::from_row(r),
         )?;
+        records
+            .into_iter()
+            .map(|record| record.into_experiment())
+            .collect::<Fallible<_>>()
+    }
 
-        if let Some(record) = record {
-            Ok(Some(record.into_experiment()?))
-        } else {
-            Ok(None)
+    pub fn run_by(db: &Database, assignee: &Assignee) -> Fallible<Option<Experiment>> {
+        Ok(Experiment::all_run_by(db, assignee)?.into_iter().next())
+    }
+
+    /// Experiments running on the agent, which can run multiple experiments at the same time if
+    /// its workers are partitioned. Agents are only running the experiments they claimed some
+    /// uncompleted crates of, as the other crates can be run by other agents.
+    pub fn all_run_by(db: &Database, assignee: &Assignee) -> Fallible<Vec<Experiment>> {
+        let records = match assignee {
+            Assignee::Agent(agent) => db.query(
+                "SELECT * FROM experiments \
+                 WHERE status = ?1 AND name IN \
+                 (SELECT experiment FROM experiment_crates WHERE claimed_by = ?2) \
+                 ORDER BY started_at;",
+                &[&Status::Running.to_str(), agent],
+                |r| ExperimentDBRecord::from_row(r),
+            )?,
+            Assignee::CLI => db.query(
+                "SELECT * FROM experiments \
+                 WHERE status = ?1 AND assigned_to = ?2 \
+                 ORDER BY started_at;",
+                &[&Status::Running.to_str(), &assignee.to_string()],
+                |r| ExperimentDBRecord::from_row(r),
+            )?,
+        };
+
+        let mut experiments = Vec::new();
+        for record in records {
+            let experiment = record.into_experiment()?;
+            if let Assignee::Agent(agent) = assignee {
+                if experiment.get_claimed_crates(db, agent)?.is_empty() {
+                    continue;
+                }
+            }
+            experiments.push(experiment);
         }
+        Ok(experiments)
+    }
+
+    /// Whether the agent is still running the experiment, as it claimed some of its uncompleted
+    /// crates. Agents stop running the experiments queued again while they were running them.
+    pub fn is_run_by(&self, db: &Database, agent: &str) -> Fallible<bool> {
+        Ok(self.status == Status::Running && !self.get_claimed_crates(db, agent)?.is_empty())
     }
 
     pub fn first_by_status(db: &Database, status: Status) -> Fallible<Option<Experiment>> {
@@ -175,34 +737,101 @@ impl Experiment {
         }
     }
 
-    pub fn next(db: &Database, assignee: &Assignee) -> Fallible<Option<(bool, Experiment)>> {
+    pub fn next(
+        db: &Database,
+        config: &Config,
+        assignee: &Assignee,
+    ) -> Fallible<Option<(bool, Experiment)>> {
         // Avoid assigning two experiments to the same agent
         if let Some(experiment) = Experiment::run_by(db, assignee)? {
             return Ok(Some((false, experiment)));
         }
 
+        let next = Experiment::assign_next(db, config, assignee, |_| Ok(Some(())))?;
+        Ok(next.map(|(new, experiment, ())| (new, experiment)))
+    }
+
+    /// Experiment the agent was running before it restarted, which isn't among the experiments
+    /// it's currently running.
+    pub fn interrupted(db: &Database, agent: &AgentWorkers, name: &str) -> Fallible<Option<Self>> {
+        Ok(
+            Experiment::all_run_by(db, &Assignee::Agent(name.to_string()))?
+                .into_iter()
+                .find(|ex| !agent.running.contains(&ex.name)),
+        )
+    }
+
+    /// Assign a new experiment to an agent running other experiments with part of its workers,
+    /// returning whether it just started along with the number of workers the agent should run
+    /// it with.
+    pub fn next_partitioned(
+        db: &Database,
+        config: &Config,
+        agent: &AgentWorkers,
+        name: &str,
+    ) -> Fallible<Option<(bool, Experiment, usize)>> {
+        Experiment::assign_next(db, config, &Assignee::Agent(name.to_string()), |ex| {
+            // More crates of an experiment are only claimed once the agent finished its chunk
+            if agent.running.contains(&ex.name) {
+                return Ok(None);
+            }
+            ex.partition_workers(db, config, agent)
+        })
+    }
+
+    /// Number of workers of the agent the experiment should be run with, if it can be run by the
+    /// agent right now.
+    pub fn partition_workers(
+        &self,
+        db: &Database,
+        config: &Config,
+        agent: &AgentWorkers,
+    ) -> Fallible<Option<usize>> {
+        Ok(config.server.partitioning.workers(
+            self.tested_crates_count(db)?,
+            agent.total,
+            agent.idle,
+        ))
+    }
+
+    /// Assign to the agent the most important experiment `fits` returns something for, returning
+    /// whether it just started along with that value. Agents get either a queued experiment or a
+    /// chunk of the crates of a running experiment nobody claimed yet.
+    fn assign_next<T, F>(
+        db: &Database,
+        config: &Config,
+        assignee: &Assignee,
+        mut fits: F,
+    ) -> Fallible<Option<(bool, Experiment, T)>>
+    where
+        F: FnMut(&Experiment) -> Fallible<Option<T>>,
+    {
         let assigned_to = assignee.to_string();
 
         // Get an experiment whose requirements are met by this agent, preferring (in order of
         // importance):
         //    - experiments that were explicitly assigned to us.
-        //    - experiments with a higher priority.
+        //    - experiments with a higher priority, which grows while they wait in the queue.
+        //    - experiments already running on other agents.
         //    - older experiments.
         let (query, params) = match assignee {
             Assignee::Agent(agent_name) => {
                 const AGENT_QUERY: &str = r#"
                     SELECT *
                     FROM   experiments ex
-                    WHERE  ex.status = "queued"
-                           AND ( ex.assigned_to IS NULL OR ex.assigned_to = ?2 )
+                    WHERE  ( ( ex.status = "queued"
+                               AND ( ex.assigned_to IS NULL OR ex.assigned_to = ?2 ) )
+                             OR ex.status = "running" )
+                           AND ex.namespace = (SELECT namespace
+                                               FROM   agents
+                                               WHERE  name = ?1)
                            AND ( ex.requirement IS NULL
                                   OR ex.requirement IN (SELECT capability
                                                         FROM   agent_capabilities
                                                         WHERE  agent_name = ?1) )
                     ORDER  BY ex.assigned_to IS NULL,
                               ex.priority DESC,
-                              ex.created_at
-                    LIMIT  1;
+                              ex.created_at;
                 "#;
 
                 (AGENT_QUERY, vec![agent_name, &assigned_to])
@@ -218,27 +847,258 @@ impl Experiment {
                                AND ( ex.assigned_to IS NULL OR ex.assigned_to = ?2 )
                     ORDER BY   ex.assigned_to IS NULL,
                                ex.priority DESC,
-                               ex.created_at
-                    LIMIT 1;
+                               ex.created_at;
                 "#;
 
                 (CLI_QUERY, vec![&assigned_to])
             }
         };
 
-        let next = db.get_row(query, params.as_slice(), |r| {
+        let records = db.query(query, params.as_slice(), |r| {
             ExperimentDBRecord::from_row(r)
         })?;
-        if let Some(record) = next {
-            let mut experiment = record.into_experiment()?;
-            experiment.set_status(&db, Status::Running)?;
-            experiment.set_assigned_to(&db, Some(assignee))?;
-            return Ok(Some((true, experiment)));
+        let mut candidates = records
+            .into_iter()
+            .map(|record| record.into_experiment())
+            .collect::<Fallible<Vec<_>>>()?;
+
+        // Agents advertising the targets they can build for only get experiments built for them
+        if let Assignee::Agent(agent_name) = assignee {
+            let capabilities = Capabilities::for_agent(db, agent_name)?;
+            candidates.retain(|ex| capabilities.supports_targets(&ex.targets));
+
+            // Running experiments can only be joined while some of their crates are unclaimed
+            Experiment::release_expired_claims(db, &config.server.chunks)?;
+            let mut joinable = Vec::with_capacity(candidates.len());
+            for ex in candidates {
+                if ex.status == Status::Queued || ex.has_unclaimed_crates(db)? {
+                    joinable.push(ex);
+                }
+            }
+            candidates = joinable;
+        }
+
+        let aging = &config.server.priority_aging;
+        candidates.sort_by_key(|ex| {
+            (
+                ex.status != Status::Queued || ex.assigned_to.is_none(),
+                Reverse(ex.effective_priority(aging)),
+                ex.status != Status::Running,
+                ex.created_at,
+            )
+        });
+        for mut experiment in candidates {
+            if let Some(fit) = fits(&experiment)? {
+                let new = experiment.status == Status::Queued;
+                if new {
+                    experiment.set_status(&db, Status::Running)?;
+                    experiment.set_assigned_to(&db, Some(assignee))?;
+                }
+                if let Assignee::Agent(agent_name) = assignee {
+                    let claimed = experiment.claim_crates(db, agent_name, &config.server.chunks)?;
+                    // Other agents could have claimed the remaining crates in the meantime
+                    if claimed == 0 && !new {
+                        continue;
+                    }
+                }
+                return Ok(Some((new, experiment, fit)));
+            }
         }
 
         Ok(None)
     }
 
+    /// Claim for the agent a chunk of the uncompleted crates not claimed by other agents (or
+    /// whose lease expired), returning how many crates were claimed.
+    pub fn claim_crates(
+        &self,
+        db: &Database,
+        agent: &str,
+        chunks: &ChunksConfig,
+    ) -> Fallible<usize> {
+        // The expired claims are released first, so they're recorded in the audit log
+        Experiment::release_expired_claims(db, chunks)?;
+
+        // SQLite treats negative limits as no limit at all
+        let limit = chunks.crates.map(i64::from).unwrap_or(-1);
+        db.execute(
+            "UPDATE experiment_crates SET claimed_by = ?1, claimed_at = ?2 WHERE rowid IN \
+             (SELECT rowid FROM experiment_crates WHERE experiment = ?3 AND skipped = 0 \
+             AND claimed_by IS NULL \
+             AND (SELECT COUNT(*) FROM results WHERE results.experiment = ?3 \
+             AND results.crate = experiment_crates.crate) < ?4 \
+             ORDER BY rowid LIMIT ?5);",
+            &[
+                &agent,
+                &Utc::now(),
+                &self.name,
+                &(self.run_toolchains().len() as i64),
+                &limit,
+            ],
+        )
+    }
+
+    /// Whether some uncompleted crates of the experiment are not claimed by any agent. The
+    /// expired claims must be released before.
+    fn has_unclaimed_crates(&self, db: &Database) -> Fallible<bool> {
+        db.exists(
+            "SELECT rowid FROM experiment_crates WHERE experiment = ?1 AND skipped = 0 \
+             AND claimed_by IS NULL \
+             AND (SELECT COUNT(*) FROM results WHERE results.experiment = ?1 \
+             AND results.crate = experiment_crates.crate) < ?2 \
+             LIMIT 1;",
+            &[&self.name, &(self.run_toolchains().len() as i64)],
+        )
+    }
+
+    /// Extend the lease of the crates claimed by the agent in the running experiments, as the
+    /// agent is still alive.
+    pub fn renew_claims(db: &Database, agent: &str) -> Fallible<()> {
+        db.execute(
+            "UPDATE experiment_crates SET claimed_at = ?1 WHERE claimed_by = ?2 \
+             AND experiment IN (SELECT name FROM experiments WHERE status = ?3);",
+            &[&Utc::now(), &agent, &Status::Running.to_str()],
+        )?;
+        Ok(())
+    }
+
+    /// Release the crates of the running experiments claimed by agents whose lease expired, so
+    /// the experiments don't stall if the agents never come back. Returns how many crates were
+    /// released.
+    ///
+    /// This is the only place checking the expiry of the leases: the agents claiming crates
+    /// release the expired claims first, and the server also releases them periodically.
+    pub fn release_expired_claims(db: &Database, chunks: &ChunksConfig) -> Fallible<usize> {
+        let expired = Utc::now() - chunks.lease();
+        db.transaction(|trans| {
+            let claims = trans.query(
+                "SELECT experiment, claimed_by, COUNT(*) AS crates FROM experiment_crates \
+                 WHERE claimed_by IS NOT NULL AND claimed_at < ?1 \
+                 AND experiment IN (SELECT name FROM experiments WHERE status = ?2) \
+                 GROUP BY experiment, claimed_by;",
+                &[&expired, &Status::Running.to_str()],
+                |row| {
+                    let experiment: String = row.get("experiment");
+                    let agent: String = row.get("claimed_by");
+                    let crates: i64 = row.get("crates");
+                    (experiment, agent, crates)
+                },
+            )?;
+
+            let mut released = 0;
+            for (experiment, agent, crates) in &claims {
+                released += trans.execute(
+                    "UPDATE experiment_crates SET claimed_by = NULL, claimed_at = NULL \
+                     WHERE experiment = ?1 AND claimed_by = ?2 AND claimed_at < ?3;",
+                    &[experiment, agent, &expired],
+                )?;
+                audit::record(
+                    trans,
+                    &Actor::Server,
+                    AuditAction::ClaimsReleased,
+                    Some(experiment),
+                    &json!({ "agent": agent, "crates": crates }),
+                )?;
+                info!(
+                    "released {} crates of {} claimed by the unreachable agent {}",
+                    crates, experiment, agent
+                );
+            }
+
+            Ok(released)
+        })
+    }
+
+    /// Queue again the running experiments assigned to an agent which stopped sending
+    /// heartbeats, so other agents can run them. The experiments other agents claimed crates of
+    /// are left running, as those agents will claim the remaining crates. Returns the names of
+    /// the experiments queued again.
+    pub fn requeue_abandoned(db: &Database, agent: &str) -> Fallible<Vec<String>> {
+        let assignee = Assignee::Agent(agent.to_string()).to_string();
+        db.transaction(|trans| {
+            let names = trans.query(
+                "SELECT name FROM experiments WHERE status = ?1 AND assigned_to = ?2 \
+                 AND NOT EXISTS (SELECT rowid FROM experiment_crates \
+                 WHERE experiment = experiments.name AND claimed_by <> ?3);",
+                &[&Status::Running.to_str(), &assignee, &agent],
+                |row| -> String { row.get("name") },
+            )?;
+
+            for name in &names {
+                Experiment::requeue(trans, name, agent, "unreachable")?;
+                warn!(
+                    "queued {} again, as the agent {} running it is unreachable",
+                    name, agent
+                );
+            }
+
+            Ok(names)
+        })
+    }
+
+    /// Queue again the running experiments no agent sent results of in the last `after`, as the
+    /// agents running them are likely stuck. Returns the names of the experiments queued again.
+    pub fn requeue_stalled(db: &Database, after: Duration) -> Fallible<Vec<String>> {
+        db.transaction(|trans| {
+            let stalled = trans.query(
+                "SELECT name, assigned_to FROM experiments WHERE status = ?1 \
+                 AND assigned_to LIKE 'agent:%' AND last_progress_at < ?2;",
+                &[&Status::Running.to_str(), &(Utc::now() - after)],
+                |row| -> (String, String) { (row.get("name"), row.get("assigned_to")) },
+            )?;
+
+            let mut requeued = Vec::new();
+            for (name, assignee) in stalled {
+                let agent = match assignee.parse()? {
+                    Assignee::Agent(agent) => agent,
+                    Assignee::CLI => continue,
+                };
+                Experiment::requeue(trans, &name, &agent, "stalled")?;
+                warn!(
+                    "queued {} again, as the agent {} didn't send results in the last {} minutes",
+                    name,
+                    agent,
+                    after.num_minutes()
+                );
+                requeued.push(name);
+            }
+
+            Ok(requeued)
+        })
+    }
+
+    /// Queue the experiment again, releasing the crates claimed by the agent it's taken away
+    /// from. The claims of other agents are left alone, and released only once their lease
+    /// expires.
+    fn requeue<DB: QueryUtils>(db: &DB, name: &str, agent: &str, reason: &str) -> Fallible<()> {
+        db.execute(
+            "UPDATE experiments SET status = ?1, assigned_to = NULL WHERE name = ?2;",
+            &[&Status::Queued.to_str(), &name],
+        )?;
+        db.execute(
+            "UPDATE experiment_crates SET claimed_by = NULL, claimed_at = NULL \
+             WHERE experiment = ?1 AND claimed_by = ?2;",
+            &[&name, &agent],
+        )?;
+        audit::record(
+            db,
+            &Actor::Server,
+            AuditAction::RequeueExperiment,
+            Some(name),
+            &json!({ "agent": agent, "reason": reason }),
+        )
+    }
+
+    /// Record that some results of the experiment were just received, for the watchdog queueing
+    /// again the stalled experiments.
+    pub fn record_progress_time(&self, db: &Database) -> Fallible<()> {
+        db.execute(
+            "UPDATE experiments SET last_progress_at = ?1 WHERE name = ?2;",
+            &[&Utc::now(), &self.name.as_str()],
+        )?;
+        Ok(())
+    }
+
     pub fn get(db: &Database, name: &str) -> Fallible<Option<Experiment>> {
         let record = db.get_row(
             "SELECT * FROM experiments WHERE name = ?1;",
@@ -253,6 +1113,15 @@ impl Experiment {
         }
     }
 
+    /// Priority the experiment is scheduled with, raised by the aging policy while it's queued.
+    pub fn effective_priority(&self, aging: &PriorityAgingConfig) -> i32 {
+        if self.status == Status::Queued {
+            aging.effective_priority(self.priority, Utc::now() - self.created_at)
+        } else {
+            self.priority
+        }
+    }
+
     pub fn set_status(&mut self, db: &Database, status: Status) -> Fallible<()> {
         db.execute(
             "UPDATE experiments SET status = ?1 WHERE name = ?2;",
@@ -261,6 +1130,14 @@ impl Experiment {
 
         let now = Utc::now();
 
+        // Stalled experiments are detected from the time they (re)started running
+        if status == Status::Running {
+            db.execute(
+                "UPDATE experiments SET last_progress_at = ?1 WHERE name = ?2;",
+                &[&now, &self.name.as_str()],
+            )?;
+        }
+
         // Check if the new status is "running" and there is no starting date
         if status == Status::Running && self.started_at.is_none() {
             db.execute(
@@ -272,6 +1149,7 @@ impl Experiment {
         } else if self.status == Status::Running
             && self.completed_at.is_none()
             && status != Status::Failed
+            && status != Status::ToolchainInstallFailed
         {
             db.execute(
                 "UPDATE experiments SET completed_at = ?1 WHERE name = ?2;",
@@ -306,25 +1184,171 @@ impl Experiment {
         Ok(())
     }
 
-    pub fn raw_progress(&self, db: &Database) -> Fallible<(u32, u32)> {
-        let results_len: u32 = db
-            .get_row(
-                "SELECT COUNT(*) AS count FROM results WHERE experiment = ?1;",
-                &[&self.name.as_str()],
-                |r| r.get("count"),
-            )?
-            .unwrap();
+    pub fn set_pinned(&mut self, db: &Database, pinned: bool) -> Fallible<()> {
+        db.execute(
+            "UPDATE experiments SET pinned = ?1 WHERE name = ?2;",
+            &[&pinned, &self.name.as_str()],
+        )?;
+        self.pinned = pinned;
+        Ok(())
+    }
 
-        let crates_len: u32 = db
-            .get_row(
-                "SELECT COUNT(*) AS count FROM experiment_crates \
-                 WHERE experiment = ?1 AND skipped = 0;",
+    /// Toolchain the results are compared against.
+    pub fn start_toolchain(&self) -> &Toolchain {
+        &self.toolchains[0]
+    }
+
+    /// Toolchain whose results are compared with the start toolchain.
+    pub fn end_toolchain(&self) -> &Toolchain {
+        &self.toolchains[self.toolchains.len() - 1]
+    }
+
+    /// Pairs of toolchains compared by the experiment, one for each target the crates are built
+    /// for (`None` being the host).
+    pub fn target_toolchains(&self) -> Vec<(Option<&str>, [Toolchain; 2])> {
+        self.ordered_toolchains()
+            .into_iter()
+            .map(|(target, mut toolchains)| {
+                let end = toolchains.pop().unwrap();
+                (target, [toolchains.swap_remove(0), end])
+            })
+            .collect()
+    }
+
+    /// All the toolchains of the experiment in order, from the start toolchain to the end one,
+    /// for each target the crates are built for (`None` being the host).
+    pub fn ordered_toolchains(&self) -> Vec<(Option<&str>, Vec<Toolchain>)> {
+        if self.targets.is_empty() {
+            return vec![(None, self.toolchains.clone())];
+        }
+        self.targets
+            .triples()
+            .iter()
+            .map(|target| {
+                let toolchains = self
+                    .toolchains
+                    .iter()
+                    .map(|tc| tc.with_target(target))
+                    .collect();
+                (Some(target.as_str()), toolchains)
+            })
+            .collect()
+    }
+
+    /// Toolchains every crate of the experiment is run with.
+    pub fn run_toolchains(&self) -> Vec<Toolchain> {
+        self.ordered_toolchains()
+            .into_iter()
+            .flat_map(|(_, toolchains)| toolchains)
+            .collect()
+    }
+
+    /// Number of crates of the experiment that are run with every toolchain.
+    fn tested_crates_count(&self, db: &Database) -> Fallible<u32> {
+        Ok(db
+            .get_row(
+                "SELECT COUNT(*) AS count FROM experiment_crates \
+                 WHERE experiment = ?1 AND skipped = 0;",
+                &[&self.name.as_str()],
+                |r| r.get("count"),
+            )?
+            .unwrap())
+    }
+
+    pub fn raw_progress(&self, db: &Database) -> Fallible<(u32, u32)> {
+        let results_len: u32 = db
+            .get_row(
+                "SELECT COUNT(*) AS count FROM results WHERE experiment = ?1;",
                 &[&self.name.as_str()],
                 |r| r.get("count"),
             )?
             .unwrap();
 
-        Ok((results_len, crates_len * 2))
+        let crates_len = self.tested_crates_count(db)?;
+        Ok((results_len, crates_len * self.run_toolchains().len() as u32))
+    }
+
+    /// Progress of the experiment split by toolchain, along with the comparison of the crates
+    /// already completed on both sides.
+    pub fn detailed_progress(&self, db: &Database) -> Fallible<Progress> {
+        let crates_len = self.tested_crates_count(db)?;
+        let completed = db
+            .query(
+                "SELECT toolchain, COUNT(*) AS count FROM results \
+                 WHERE experiment = ?1 GROUP BY toolchain;",
+                &[&self.name.as_str()],
+                |r| -> (String, u32) { (r.get("toolchain"), r.get("count")) },
+            )?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        let mut progress = Progress {
+            completed_jobs: 0,
+            total_jobs: 0,
+            toolchains: Vec::new(),
+            comparisons: HashMap::new(),
+        };
+        for tc in self.run_toolchains() {
+            let name = tc.to_string();
+            let completed_jobs = completed.get(&name).cloned().unwrap_or(0);
+            progress.completed_jobs += completed_jobs;
+            progress.total_jobs += crates_len;
+            progress.toolchains.push(ToolchainProgress {
+                toolchain: name,
+                completed_jobs,
+                total_jobs: crates_len,
+            });
+        }
+
+        for (_, toolchains) in self.target_toolchains() {
+            let results = db.query(
+                "SELECT r1.result AS start_result, r2.result AS end_result \
+                 FROM results r1 INNER JOIN results r2 \
+                 ON r2.experiment = r1.experiment AND r2.crate = r1.crate \
+                 WHERE r1.experiment = ?1 AND r1.toolchain = ?2 AND r2.toolchain = ?3;",
+                &[
+                    &self.name,
+                    &toolchains[0].to_string(),
+                    &toolchains[1].to_string(),
+                ],
+                |r| -> (String, String) { (r.get("start_result"), r.get("end_result")) },
+            )?;
+            for (start, end) in results {
+                let comparison = crater_results::report::compare(start.parse()?, end.parse()?);
+                *progress.comparisons.entry(comparison).or_insert(0) += 1;
+            }
+        }
+
+        Ok(progress)
+    }
+
+    /// Estimate the resources used by the experiment so far, from the duration of the jobs
+    /// reported by the agents and the size of the stored logs.
+    pub fn cost(&self, db: &Database) -> Fallible<ExperimentCost> {
+        let (jobs, duration, cpu_time) = db
+            .get_row(
+                "SELECT COUNT(*) AS jobs, TOTAL(duration) AS duration, \
+                 TOTAL(duration * cpus) AS cpu_time \
+                 FROM resource_usage WHERE experiment = ?1;",
+                &[&self.name],
+                |r| -> (u32, f64, f64) { (r.get("jobs"), r.get("duration"), r.get("cpu_time")) },
+            )?
+            .unwrap_or((0, 0.0, 0.0));
+        let stored_bytes = db
+            .get_row(
+                "SELECT TOTAL(LENGTH(content)) AS size FROM logs \
+                 WHERE hash IN (SELECT log FROM results WHERE experiment = ?1);",
+                &[&self.name],
+                |r| -> f64 { r.get("size") },
+            )?
+            .unwrap_or(0.0);
+
+        Ok(ExperimentCost {
+            jobs,
+            agent_hours: duration / 3600.0,
+            cpu_hours: cpu_time / 3600.0,
+            stored_bytes: stored_bytes as u64,
+        })
     }
 
     pub fn progress(&self, db: &Database) -> Fallible<u8> {
@@ -337,6 +1361,22 @@ impl Experiment {
         }
     }
 
+    /// Time left before a running experiment completes, assuming the remaining jobs take as long
+    /// as the ones completed so far did on average.
+    pub fn estimated_remaining(
+        &self,
+        completed_jobs: u32,
+        total_jobs: u32,
+    ) -> Option<chrono::Duration> {
+        let started_at = self.started_at?;
+        if completed_jobs == 0 || self.completed_at.is_some() {
+            return None;
+        }
+
+        let job_duration = Utc::now().signed_duration_since(started_at) / completed_jobs as i32;
+        Some(job_duration * (total_jobs as i32 - completed_jobs as i32))
+    }
+
     pub fn get_crates(&self, db: &Database) -> Fallible<Vec<Crate>> {
         db.query(
             "SELECT crate FROM experiment_crates WHERE experiment = ?1;",
@@ -350,11 +1390,64 @@ impl Experiment {
         .collect::<Fallible<Vec<Crate>>>()
     }
 
-    pub fn get_uncompleted_crates(&self, db: &Database) -> Fallible<Vec<Crate>> {
+    /// Get why the crates of the experiment that aren't tested were skipped.
+    pub fn get_skip_reasons(&self, db: &Database) -> Fallible<HashMap<Crate, CrateSkipReason>> {
         db.query(
-            "SELECT crate FROM experiment_crates WHERE experiment = ?1
-            AND (SELECT COUNT(*) AS count FROM results WHERE results.experiment = ?1 AND results.crate = experiment_crates.crate) < 2;",
+            "SELECT crate, skip_reason FROM experiment_crates \
+             WHERE experiment = ?1 AND skip_reason IS NOT NULL;",
             &[&self.name],
+            |r| {
+                let krate: String = r.get("crate");
+                let reason: String = r.get("skip_reason");
+                Ok((serde_json::from_str(&krate)?, reason.parse()?))
+            },
+        )?
+        .into_iter()
+        .collect::<Fallible<HashMap<Crate, CrateSkipReason>>>()
+    }
+
+    /// Get the manifest metadata of the crates of the experiment, as analyzed when the list of
+    /// crates was created. Crates whose manifest couldn't be analyzed are missing.
+    pub fn get_crates_metadata(&self, db: &Database) -> Fallible<HashMap<Crate, CrateMetadata>> {
+        db.query(
+            "SELECT crate, metadata FROM experiment_crates \
+             WHERE experiment = ?1 AND metadata IS NOT NULL;",
+            &[&self.name],
+            |r| {
+                let krate: String = r.get("crate");
+                let metadata: String = r.get("metadata");
+                Ok((
+                    serde_json::from_str(&krate)?,
+                    serde_json::from_str(&metadata)?,
+                ))
+            },
+        )?
+        .into_iter()
+        .collect::<Fallible<HashMap<Crate, CrateMetadata>>>()
+    }
+
+    /// Uncompleted crates of the experiment the agent claimed and should run.
+    pub fn get_claimed_crates(&self, db: &Database, agent: &str) -> Fallible<Vec<Crate>> {
+        db.query(
+            "SELECT crate FROM experiment_crates WHERE experiment = ?1 AND skipped = 0 \
+             AND claimed_by = ?3 \
+             AND (SELECT COUNT(*) AS count FROM results WHERE results.experiment = ?1 \
+             AND results.crate = experiment_crates.crate) < ?2;",
+            &[&self.name, &(self.run_toolchains().len() as i64), &agent],
+            |r| {
+                let value: String = r.get("crate");
+                Ok(serde_json::from_str(&value)?)
+            },
+        )?
+        .into_iter()
+        .collect::<Fallible<Vec<Crate>>>()
+    }
+
+    pub fn get_uncompleted_crates(&self, db: &Database) -> Fallible<Vec<Crate>> {
+        db.query(
+            "SELECT crate FROM experiment_crates WHERE experiment = ?1 AND skipped = 0
+            AND (SELECT COUNT(*) AS count FROM results WHERE results.experiment = ?1 AND results.crate = experiment_crates.crate) < ?2;",
+            &[&self.name, &(self.run_toolchains().len() as i64)],
             |r| {
                 let value: String = r.get("crate");
                 Ok(serde_json::from_str(&value)?)
@@ -367,10 +1460,12 @@ impl Experiment {
 
 struct ExperimentDBRecord {
     name: String,
+    namespace: String,
     mode: String,
     cap_lints: String,
     toolchain_start: String,
     toolchain_end: String,
+    intermediate_toolchains: String,
     priority: i32,
     created_at: DateTime<Utc>,
     started_at: Option<DateTime<Utc>>,
@@ -383,16 +1478,31 @@ struct ExperimentDBRecord {
     report_url: Option<String>,
     ignore_blacklist: bool,
     requirement: Option<String>,
+    pinned: bool,
+    edition: Option<String>,
+    max_rust_version: Option<String>,
+    build_targets: String,
+    test_flags: String,
+    dist_server: Option<String>,
+    rustc_wrapper: Option<String>,
+    timings: bool,
+    targets: String,
+    tags: String,
+    index_snapshot: Option<String>,
+    crates_seed: Option<u32>,
+    crate_select: Option<String>,
 }
 
 impl ExperimentDBRecord {
     fn from_row(row: &Row) -> Self {
         ExperimentDBRecord {
             name: row.get("name"),
+            namespace: row.get("namespace"),
             mode: row.get("mode"),
             cap_lints: row.get("cap_lints"),
             toolchain_start: row.get("toolchain_start"),
             toolchain_end: row.get("toolchain_end"),
+            intermediate_toolchains: row.get("intermediate_toolchains"),
             priority: row.get("priority"),
             created_at: row.get("created_at"),
             started_at: row.get("started_at"),
@@ -405,13 +1515,34 @@ impl ExperimentDBRecord {
             report_url: row.get("report_url"),
             ignore_blacklist: row.get("ignore_blacklist"),
             requirement: row.get("requirement"),
+            pinned: row.get("pinned"),
+            edition: row.get("edition"),
+            max_rust_version: row.get("max_rust_version"),
+            build_targets: row.get("build_targets"),
+            test_flags: row.get("test_flags"),
+            dist_server: row.get("dist_server"),
+            rustc_wrapper: row.get("rustc_wrapper"),
+            timings: row.get("timings"),
+            targets: row.get("targets"),
+            tags: row.get("tags"),
+            index_snapshot: row.get("index_snapshot"),
+            crates_seed: row.get("crates_seed"),
+            crate_select: row.get("crate_select"),
         }
     }
 
     fn into_experiment(self) -> Fallible<Experiment> {
         Ok(Experiment {
             name: self.name,
-            toolchains: [self.toolchain_start.parse()?, self.toolchain_end.parse()?],
+            namespace: self.namespace,
+            toolchains: {
+                let mut toolchains = vec![self.toolchain_start.parse()?];
+                for tc in serde_json::from_str::<Vec<String>>(&self.intermediate_toolchains)? {
+                    toolchains.push(tc.parse()?);
+                }
+                toolchains.push(self.toolchain_end.parse()?);
+                toolchains
+            },
             cap_lints: self.cap_lints.parse()?,
             mode: self.mode.parse()?,
             priority: self.priority,
@@ -440,19 +1571,56 @@ impl ExperimentDBRecord {
             report_url: self.report_url,
             ignore_blacklist: self.ignore_blacklist,
             requirement: self.requirement,
+            pinned: self.pinned,
+            edition: if let Some(edition) = self.edition {
+                Some(edition.parse()?)
+            } else {
+                None
+            },
+            max_rust_version: if let Some(version) = self.max_rust_version {
+                Some(version.parse()?)
+            } else {
+                None
+            },
+            build_targets: self.build_targets.parse()?,
+            test_flags: self.test_flags.parse()?,
+            dist_server: self.dist_server,
+            rustc_wrapper: if let Some(wrapper) = self.rustc_wrapper {
+                Some(wrapper.parse()?)
+            } else {
+                None
+            },
+            timings: self.timings,
+            targets: self.targets.parse()?,
+            tags: self.tags.parse()?,
+            index_snapshot: self
+                .index_snapshot
+                .map(|raw| serde_json::from_str(&raw))
+                .transpose()?,
+            crates_seed: self.crates_seed,
+            crate_select: if let Some(select) = self.crate_select {
+                Some(select.parse()?)
+            } else {
+                None
+            },
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Assignee, AssigneeParseError, Experiment, Status};
+    use super::{
+        Assignee, AssigneeParseError, CrateSelect, Experiment, RustcWrapper, Status, Tags, Targets,
+        TestFlags,
+    };
     use crate::actions::{Action, ActionsCtx, CreateExperiment};
-    use crate::agent::Capabilities;
+    use crate::agent::{AgentWorkers, Capabilities};
+    use crate::audit::{self, AuditAction};
     use crate::config::Config;
-    use crate::db::Database;
+    use crate::db::{Database, QueryUtils};
     use crate::server::agents::Agents;
-    use crate::server::tokens::Tokens;
+    use crate::server::tokens::{NamespaceTokens, Tokens};
+    use chrono::{Duration, Utc};
     use std::str::FromStr;
 
     #[test]
@@ -483,6 +1651,168 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_crate_select_parsing() {
+        for select in &[
+            "full",
+            "top-100",
+            "top-5000",
+            "random-2000",
+            "local",
+            "local:/src/foo",
+            "local:https://example.com/foo.git",
+            "regressed:pr-1",
+            "same-as:pr-1",
+            "rdeps:serde",
+            "rdeps:serde:2",
+            "list:regressed-1.78",
+            "category:embedded",
+            "category:os::unix-apis",
+            "keyword:async",
+        ] {
+            assert_eq!(
+                CrateSelect::from_str(select).unwrap().to_string().as_str(),
+                *select
+            );
+        }
+        assert_eq!(
+            CrateSelect::from_str("local:../foo").unwrap(),
+            CrateSelect::LocalSource("../foo".into())
+        );
+
+        assert_eq!(
+            CrateSelect::from_str("regressed:team:foo").unwrap(),
+            CrateSelect::Regressed("team:foo".into())
+        );
+
+        assert_eq!(
+            CrateSelect::from_str("top-1000").unwrap(),
+            CrateSelect::Top(1000)
+        );
+
+        assert_eq!(
+            CrateSelect::from_str("rdeps:serde:1").unwrap(),
+            CrateSelect::ReverseDeps("serde".into(), Some(1))
+        );
+
+        assert!(CrateSelect::from_str("top-").is_err());
+        assert!(CrateSelect::from_str("top-0").is_err());
+        assert!(CrateSelect::from_str("top-foo").is_err());
+        assert!(CrateSelect::from_str("random-0").is_err());
+        assert!(CrateSelect::from_str("random-").is_err());
+        assert!(CrateSelect::from_str("local:").is_err());
+        assert!(CrateSelect::from_str("regressed:").is_err());
+        assert!(CrateSelect::from_str("same-as:").is_err());
+        assert!(CrateSelect::from_str("rdeps:").is_err());
+        assert!(CrateSelect::from_str("rdeps:serde:0").is_err());
+        assert!(CrateSelect::from_str("rdeps:serde:").is_err());
+        assert!(CrateSelect::from_str("list:").is_err());
+        assert!(CrateSelect::from_str("category:").is_err());
+        assert!(CrateSelect::from_str("keyword:").is_err());
+        assert!(CrateSelect::from_str("keyword:foo&page=2").is_err());
+        assert!(CrateSelect::from_str("foo").is_err());
+    }
+
+    #[test]
+    fn test_test_flags_parsing() {
+        let flags = TestFlags::from_str("--no-fail-fast  -- --test-threads=1").unwrap();
+        assert_eq!(flags.args(), &["--no-fail-fast", "--", "--test-threads=1"]);
+        assert_eq!(flags.to_string(), "--no-fail-fast -- --test-threads=1");
+
+        let flags = TestFlags::from_str(r#"-- --skip "slow test""#).unwrap();
+        assert_eq!(flags.args(), &["--", "--skip", "slow test"]);
+        assert_eq!(TestFlags::from_str(&flags.to_string()).unwrap(), flags);
+
+        assert!(TestFlags::from_str("").unwrap().args().is_empty());
+        assert!(TestFlags::from_str("--skip \"foo").is_err());
+    }
+
+    #[test]
+    fn test_rustc_wrapper_parsing() {
+        let wrapper = RustcWrapper::from_str("time-rustc").unwrap();
+        assert_eq!(wrapper.to_string(), "time-rustc");
+        assert_eq!(
+            wrapper.sandbox_path(),
+            "/opt/crater/rustc-wrappers/time-rustc"
+        );
+
+        for invalid in &["", ".", "..", "../../bin/sh", "/usr/bin/sccache"] {
+            assert!(RustcWrapper::from_str(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_tags() {
+        let tags = Tags::from_str("release:1.78, team:libs,release:1.78,").unwrap();
+        assert_eq!(tags.names(), &["release:1.78", "team:libs"]);
+        assert_eq!(tags.to_string(), "release:1.78,team:libs");
+        assert!(tags.contains("team:libs"));
+        assert!(!tags.contains("team"));
+        assert!(Tags::from_str("").unwrap().is_empty());
+        assert!(Tags::from_str("team: libs").is_err());
+    }
+
+    #[test]
+    fn test_targets() {
+        let targets =
+            Targets::from_str("wasm32-unknown-unknown, aarch64-unknown-linux-gnu,").unwrap();
+        assert_eq!(
+            targets.triples(),
+            &["wasm32-unknown-unknown", "aarch64-unknown-linux-gnu"]
+        );
+        assert_eq!(
+            targets.to_string(),
+            "wasm32-unknown-unknown,aarch64-unknown-linux-gnu"
+        );
+        assert!(Targets::from_str("").unwrap().is_empty());
+        assert!(Targets::from_str("wasm32-unknown-unknown+foo").is_err());
+
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let mut ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        // Without targets the crates are only built for the host
+        assert_eq!(
+            ex.target_toolchains(),
+            vec![(None, [ex.toolchains[0].clone(), ex.toolchains[1].clone()])]
+        );
+        assert_eq!(ex.run_toolchains(), ex.toolchains);
+
+        ex.targets = targets;
+        let toolchains = ex.run_toolchains();
+        assert_eq!(toolchains.len(), 4);
+        assert_eq!(
+            toolchains[0].to_string(),
+            format!("{}+target=wasm32-unknown-unknown", ex.toolchains[0])
+        );
+        assert_eq!(
+            toolchains[3].to_string(),
+            format!("{}+target=aarch64-unknown-linux-gnu", ex.toolchains[1])
+        );
+        assert_eq!(
+            ex.target_toolchains()[1].0,
+            Some("aarch64-unknown-linux-gnu")
+        );
+
+        // The intermediate toolchains are run between the compared ones
+        ex.toolchains
+            .insert(1, "nightly-2019-01-01".parse().unwrap());
+        let toolchains = ex.run_toolchains();
+        assert_eq!(toolchains.len(), 6);
+        assert_eq!(
+            toolchains[1].to_string(),
+            "nightly-2019-01-01+target=wasm32-unknown-unknown"
+        );
+        assert_eq!(
+            toolchains[2].to_string(),
+            format!("{}+target=wasm32-unknown-unknown", ex.end_toolchain())
+        );
+        assert_eq!(ex.target_toolchains()[0].1[1], toolchains[2]);
+    }
+
     #[test]
     fn test_assigning_experiment() {
         let db = Database::temp().unwrap();
@@ -512,26 +1842,26 @@ mod tests {
         create_important.apply(&ctx).unwrap();
 
         // Test the important experiment is correctly assigned
-        let (new, ex) = Experiment::next(&db, &agent1).unwrap().unwrap();
+        let (new, ex) = Experiment::next(&db, &config, &agent1).unwrap().unwrap();
         assert!(new);
         assert_eq!(ex.name.as_str(), "important");
         assert_eq!(ex.status, Status::Running);
         assert_eq!(ex.assigned_to.unwrap(), agent1);
 
         // Test the same experiment is returned to the agent
-        let (new, ex) = Experiment::next(&db, &agent1).unwrap().unwrap();
+        let (new, ex) = Experiment::next(&db, &config, &agent1).unwrap().unwrap();
         assert!(!new);
         assert_eq!(ex.name.as_str(), "important");
 
         // Test the less important experiment is assigned to the next agent
-        let (new, ex) = Experiment::next(&db, &agent2).unwrap().unwrap();
+        let (new, ex) = Experiment::next(&db, &config, &agent2).unwrap().unwrap();
         assert!(new);
         assert_eq!(ex.name.as_str(), "test");
         assert_eq!(ex.status, Status::Running);
         assert_eq!(ex.assigned_to.unwrap(), agent2);
 
         // Test no other experiment is available for the other agents
-        assert!(Experiment::next(&db, &agent3).unwrap().is_none());
+        assert!(Experiment::next(&db, &config, &agent3).unwrap().is_none());
     }
 
     #[test]
@@ -569,27 +1899,112 @@ mod tests {
 
         // Test that an experiment will not be assigned to an agent without the required
         // capabilities.
-        assert!(Experiment::next(&db, &agent1).unwrap().is_none());
+        assert!(Experiment::next(&db, &config, &agent1).unwrap().is_none());
 
         // Test that an experiment with no capabilities can be assigned to any agent.
         CreateExperiment::dummy("no-requirements")
             .apply(&ctx)
             .unwrap();
 
-        let (new, ex) = Experiment::next(&db, &agent1).unwrap().unwrap();
+        let (new, ex) = Experiment::next(&db, &config, &agent1).unwrap().unwrap();
         assert!(new);
         assert_eq!(ex.name.as_str(), "no-requirements");
         assert_eq!(ex.status, Status::Running);
         assert_eq!(ex.assigned_to.unwrap(), agent1);
 
         // Test that an experiment will be assigned to an agent with the required capabilities.
-        let (new, ex) = Experiment::next(&db, &agent2).unwrap().unwrap();
+        let (new, ex) = Experiment::next(&db, &config, &agent2).unwrap().unwrap();
         assert!(new);
         assert_eq!(ex.name.as_str(), "windows");
         assert_eq!(ex.status, Status::Running);
         assert_eq!(ex.assigned_to.unwrap(), agent2);
     }
 
+    #[test]
+    fn test_assigning_experiment_with_targets() {
+        let db = Database::temp().unwrap();
+        let config = Config::load().unwrap();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+
+        let agent1 = Assignee::Agent("agent-1".to_string());
+        let agent2 = Assignee::Agent("agent-2".to_string());
+
+        // Populate the `agents` table
+        let agents = Agents::new(db.clone(), &tokens).unwrap();
+        let mut wasm_only = Capabilities::new(&["linux"]);
+        wasm_only.add_targets(&["wasm32-unknown-unknown".to_string()]);
+        agents.add_capabilities("agent-1", &wasm_only).unwrap();
+        agents
+            .add_capabilities("agent-2", &Capabilities::new(&["linux"]))
+            .unwrap();
+
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        let mut aarch64 = CreateExperiment::dummy("aarch64");
+        aarch64.targets = "aarch64-unknown-linux-gnu".parse().unwrap();
+        aarch64.apply(&ctx).unwrap();
+
+        // Test that an experiment isn't assigned to agents advertising other targets
+        assert!(Experiment::next(&db, &config, &agent1).unwrap().is_none());
+
+        // Test that agents not advertising any target can build for every target
+        let (_, ex) = Experiment::next(&db, &config, &agent2).unwrap().unwrap();
+        assert_eq!(ex.name.as_str(), "aarch64");
+
+        // Test that experiments without targets are assigned to any agent
+        CreateExperiment::dummy("host").apply(&ctx).unwrap();
+        let (_, ex) = Experiment::next(&db, &config, &agent1).unwrap().unwrap();
+        assert_eq!(ex.name.as_str(), "host");
+    }
+
+    #[test]
+    fn test_assigning_experiment_with_namespaces() {
+        let db = Database::temp().unwrap();
+        let config = Config::load().unwrap();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        let mut infra = NamespaceTokens::default();
+        infra.agents.insert("token2".into(), "agent-2".into());
+        tokens.namespaces.insert("infra".into(), infra);
+
+        let agent1 = Assignee::Agent("agent-1".to_string());
+        let agent2 = Assignee::Agent("agent-2".to_string());
+
+        // Populate the `agents` table
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        let mut create_infra = CreateExperiment::dummy("infra-only");
+        create_infra.namespace = "infra".to_string();
+        create_infra.priority = 10;
+        create_infra.apply(&ctx).unwrap();
+
+        // Agents in the default namespace can't run experiments of other namespaces
+        assert!(Experiment::next(&db, &config, &agent1).unwrap().is_none());
+
+        let (new, ex) = Experiment::next(&db, &config, &agent2).unwrap().unwrap();
+        assert!(new);
+        assert_eq!(ex.name.as_str(), "infra:infra-only");
+        assert_eq!(ex.namespace.as_str(), "infra");
+
+        // And agents of other namespaces can't run experiments in the default namespace
+        CreateExperiment::dummy("default-only").apply(&ctx).unwrap();
+        let (new, ex) = Experiment::next(&db, &config, &agent1).unwrap().unwrap();
+        assert!(new);
+        assert_eq!(ex.name.as_str(), "default-only");
+    }
+
     #[test]
     fn test_assigning_experiment_with_preassigned_agent() {
         let db = Database::temp().unwrap();
@@ -620,18 +2035,286 @@ mod tests {
 
         // Try to get an experiment for agent 1, it should pick 'assigned' even if 'important' has
         // an higher priority.
-        let (new, ex) = Experiment::next(&db, &agent1).unwrap().unwrap();
+        let (new, ex) = Experiment::next(&db, &config, &agent1).unwrap().unwrap();
         assert!(new);
         assert_eq!(ex.assigned_to.unwrap(), agent1);
         assert_eq!(ex.name.as_str(), "assigned");
 
         // Then the 'important' experiment will be picked by agent 2
-        let (new, ex) = Experiment::next(&db, &agent2).unwrap().unwrap();
+        let (new, ex) = Experiment::next(&db, &config, &agent2).unwrap().unwrap();
         assert!(new);
         assert_eq!(ex.assigned_to.unwrap(), agent2);
         assert_eq!(ex.name.as_str(), "important");
     }
 
+    #[test]
+    fn test_assigning_experiment_with_priority_aging() {
+        use chrono::{Duration, Utc};
+
+        let db = Database::temp().unwrap();
+        let mut config = Config::load().unwrap();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+
+        let agent1 = Assignee::Agent("agent-1".to_string());
+        let agent2 = Assignee::Agent("agent-2".to_string());
+
+        // Populate the `agents` table
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        config.server.priority_aging.hours_per_level = Some(2);
+        let ctx = ActionsCtx::new(&db, &config);
+
+        CreateExperiment::dummy("community").apply(&ctx).unwrap();
+        let mut create_important = CreateExperiment::dummy("important");
+        create_important.priority = 10;
+        create_important.apply(&ctx).unwrap();
+
+        // The community experiment waited for a day, raising its priority to 12
+        db.execute(
+            "UPDATE experiments SET created_at = ?1 WHERE name = ?2;",
+            &[&(Utc::now() - Duration::days(1)), &"community"],
+        )
+        .unwrap();
+        let community = Experiment::get(&db, "community").unwrap().unwrap();
+        assert_eq!(community.priority, 0);
+        assert_eq!(
+            community.effective_priority(&config.server.priority_aging),
+            12
+        );
+
+        let (new, ex) = Experiment::next(&db, &config, &agent1).unwrap().unwrap();
+        assert!(new);
+        assert_eq!(ex.name.as_str(), "community");
+
+        let (new, ex) = Experiment::next(&db, &config, &agent2).unwrap().unwrap();
+        assert!(new);
+        assert_eq!(ex.name.as_str(), "important");
+    }
+
+    #[test]
+    fn test_assigning_experiment_with_partitioned_workers() {
+        let db = Database::temp().unwrap();
+        let mut config = Config::load().unwrap();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+
+        // Populate the `agents` table
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        config.server.partitioning.small_experiment_crates = Some(1000);
+        config.server.partitioning.small_experiment_workers = 8;
+        let ctx = ActionsCtx::new(&db, &config);
+
+        CreateExperiment::dummy("first").apply(&ctx).unwrap();
+        CreateExperiment::dummy("second").apply(&ctx).unwrap();
+        CreateExperiment::dummy("large").apply(&ctx).unwrap();
+        let workers = |idle, running: &[&str]| AgentWorkers {
+            total: 32,
+            idle,
+            running: running.iter().map(|name| name.to_string()).collect(),
+        };
+
+        // Small experiments run alongside each other
+        let (_, ex, count) =
+            Experiment::next_partitioned(&db, &config, &workers(32, &[]), "agent-1")
+                .unwrap()
+                .unwrap();
+        assert_eq!(ex.name.as_str(), "first");
+        assert_eq!(count, 8);
+        let (_, ex, count) =
+            Experiment::next_partitioned(&db, &config, &workers(24, &["first"]), "agent-1")
+                .unwrap()
+                .unwrap();
+        assert_eq!(ex.name.as_str(), "second");
+        assert_eq!(count, 8);
+
+        // The experiments the agent isn't running anymore are resumed
+        let running = workers(16, &["first", "second"]);
+        assert!(Experiment::interrupted(&db, &running, "agent-1")
+            .unwrap()
+            .is_none());
+        let ex = Experiment::interrupted(&db, &workers(24, &["first"]), "agent-1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(ex.name.as_str(), "second");
+
+        // Large experiments need all the workers except the ones kept for the small experiments
+        config.server.partitioning.small_experiment_crates = Some(0);
+        assert!(
+            Experiment::next_partitioned(&db, &config, &running, "agent-1")
+                .unwrap()
+                .is_none()
+        );
+        let (_, ex, count) =
+            Experiment::next_partitioned(&db, &config, &workers(24, &[]), "agent-1")
+                .unwrap()
+                .unwrap();
+        assert_eq!(ex.name.as_str(), "large");
+        assert_eq!(count, 24);
+    }
+
+    #[test]
+    fn test_assigning_experiment_chunks() {
+        let db = Database::temp().unwrap();
+        let mut config = Config::load().unwrap();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+
+        let agent1 = Assignee::Agent("agent-1".to_string());
+        let agent2 = Assignee::Agent("agent-2".to_string());
+
+        // Populate the `agents` table
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        config.server.chunks.crates = Some(2);
+        let ctx = ActionsCtx::new(&db, &config);
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+
+        // Every agent claims its own chunk of the crates
+        let (new, ex) = Experiment::next(&db, &config, &agent1).unwrap().unwrap();
+        assert!(new);
+        let chunk1 = ex.get_claimed_crates(&db, "agent-1").unwrap();
+        assert_eq!(chunk1.len(), 2);
+
+        let (new, ex) = Experiment::next(&db, &config, &agent2).unwrap().unwrap();
+        assert!(!new);
+        assert_eq!(ex.name.as_str(), "dummy");
+        assert_eq!(ex.assigned_to.as_ref(), Some(&agent1));
+        let chunk2 = ex.get_claimed_crates(&db, "agent-2").unwrap();
+        assert_eq!(chunk2.len(), 2);
+        assert!(chunk2.iter().all(|krate| !chunk1.contains(krate)));
+
+        // The agents get their own chunk again until they complete it
+        let (new, ex) = Experiment::next(&db, &config, &agent1).unwrap().unwrap();
+        assert!(!new);
+        assert_eq!(ex.get_claimed_crates(&db, "agent-1").unwrap(), chunk1);
+
+        // The crates of the agents not heard from in a while are claimed by the other agents
+        db.execute(
+            "UPDATE experiment_crates SET claimed_at = ?1 WHERE claimed_by = 'agent-1';",
+            &[&(Utc::now() - Duration::hours(1))],
+        )
+        .unwrap();
+        Experiment::renew_claims(&db, "agent-2").unwrap();
+        let claimed = ex.claim_crates(&db, "agent-2", &config.server.chunks);
+        assert_eq!(claimed.unwrap(), 2);
+        assert!(Experiment::run_by(&db, &agent1).unwrap().is_none());
+        let claimed = ex.get_claimed_crates(&db, "agent-2").unwrap();
+        assert!(chunk1.iter().all(|krate| claimed.contains(krate)));
+    }
+
+    #[test]
+    fn test_releasing_expired_claims() {
+        let db = Database::temp().unwrap();
+        let mut config = Config::load().unwrap();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        config.server.chunks.crates = Some(2);
+        let ctx = ActionsCtx::new(&db, &config);
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+
+        let agent1 = Assignee::Agent("agent-1".to_string());
+        let agent2 = Assignee::Agent("agent-2".to_string());
+        let (_, ex) = Experiment::next(&db, &config, &agent1).unwrap().unwrap();
+        let chunk1 = ex.get_claimed_crates(&db, "agent-1").unwrap();
+        Experiment::next(&db, &config, &agent2).unwrap().unwrap();
+
+        // Claims are kept while the lease is valid
+        let released = Experiment::release_expired_claims(&db, &config.server.chunks);
+        assert_eq!(released.unwrap(), 0);
+
+        // Only the crates of the agents not heard from in a while are released
+        db.execute(
+            "UPDATE experiment_crates SET claimed_at = ?1 WHERE claimed_by = 'agent-1';",
+            &[&(Utc::now() - Duration::hours(1))],
+        )
+        .unwrap();
+        let released = Experiment::release_expired_claims(&db, &config.server.chunks);
+        assert_eq!(released.unwrap(), 2);
+        assert!(ex.get_claimed_crates(&db, "agent-1").unwrap().is_empty());
+        assert_eq!(ex.get_claimed_crates(&db, "agent-2").unwrap().len(), 2);
+
+        let entries = audit::entries(&db, None, None, 10).unwrap();
+        assert_eq!(entries[0].action, AuditAction::ClaimsReleased);
+        assert_eq!(entries[0].payload["agent"], "agent-1");
+
+        // The released crates are available to the other agents
+        let claimed = ex.claim_crates(&db, "agent-2", &config.server.chunks);
+        assert_eq!(claimed.unwrap(), 2);
+        let claimed = ex.get_claimed_crates(&db, "agent-2").unwrap();
+        assert!(chunk1.iter().all(|krate| claimed.contains(krate)));
+    }
+
+    #[test]
+    fn test_requeue_stalled() {
+        let db = Database::temp().unwrap();
+        let mut config = Config::default();
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token".into(), "agent".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        config.server.chunks.crates = Some(2);
+        let ctx = ActionsCtx::new(&db, &config);
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let agent = Assignee::Agent("agent".to_string());
+        let (_, ex) = Experiment::next(&db, &config, &agent).unwrap().unwrap();
+        assert!(ex.is_run_by(&db, "agent").unwrap());
+        // Another agent is running a chunk of the experiment too
+        ex.claim_crates(&db, "agent-2", &config.server.chunks)
+            .unwrap();
+
+        // Experiments which recently made progress keep running
+        let requeued = Experiment::requeue_stalled(&db, Duration::hours(1)).unwrap();
+        assert!(requeued.is_empty());
+
+        db.execute(
+            "UPDATE experiments SET last_progress_at = ?1;",
+            &[&(Utc::now() - Duration::hours(2))],
+        )
+        .unwrap();
+        let requeued = Experiment::requeue_stalled(&db, Duration::hours(1)).unwrap();
+        assert_eq!(requeued, vec!["dummy"]);
+
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.status, Status::Queued);
+        assert!(ex.assigned_to.is_none());
+        let entries = audit::entries(&db, None, None, 1).unwrap();
+        assert_eq!(entries[0].action, AuditAction::RequeueExperiment);
+        assert_eq!(entries[0].payload["reason"], "stalled");
+
+        // Only the claims of the stalled agent are released, and it stops running the experiment
+        assert!(ex.get_claimed_crates(&db, "agent").unwrap().is_empty());
+        assert_eq!(ex.get_claimed_crates(&db, "agent-2").unwrap().len(), 2);
+        assert!(!ex.is_run_by(&db, "agent").unwrap());
+
+        // The clock starts again when the experiment is assigned to an agent
+        Experiment::next(&db, &config, &agent).unwrap().unwrap();
+        let requeued = Experiment::requeue_stalled(&db, Duration::hours(1)).unwrap();
+        assert!(requeued.is_empty());
+    }
+
     #[test]
     fn test_completed_crates() {
         use crate::prelude::*;
@@ -701,4 +2384,113 @@ mod tests {
         let uncompleted_crates = ex.get_uncompleted_crates(&db).unwrap();
         assert_eq!(uncompleted_crates.len(), crates.len() - 1);
     }
+
+    #[test]
+    fn test_detailed_progress() {
+        use crate::report::Comparison;
+        use crate::results::{DatabaseDB, EncodingType, FailureReason, TestResult, WriteResults};
+
+        rustwide::logging::init();
+
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let crates = ex.get_uncompleted_crates(&db).unwrap();
+
+        let progress = ex.detailed_progress(&db).unwrap();
+        assert_eq!(progress.completed_jobs, 0);
+        assert_eq!(progress.total_jobs, crates.len() as u32 * 2);
+        assert!(progress.comparisons.is_empty());
+
+        // The first crate regressed, while the second one only completed on the first toolchain
+        let results = DatabaseDB::new(&db);
+        for &(krate, tc, res) in &[
+            (&crates[0], &ex.toolchains[0], TestResult::TestPass),
+            (
+                &crates[0],
+                &ex.toolchains[1],
+                TestResult::BuildFail(FailureReason::Unknown),
+            ),
+            (&crates[1], &ex.toolchains[0], TestResult::TestPass),
+        ] {
+            results
+                .record_result(&ex, tc, krate, None, &config, EncodingType::Plain, || {
+                    Ok(res)
+                })
+                .unwrap();
+        }
+
+        let progress = ex.detailed_progress(&db).unwrap();
+        assert_eq!(progress.completed_jobs, 3);
+        assert_eq!(progress.total_jobs, crates.len() as u32 * 2);
+        assert_eq!(progress.toolchains.len(), 2);
+        assert_eq!(
+            progress.toolchains[0].toolchain,
+            ex.toolchains[0].to_string()
+        );
+        assert_eq!(progress.toolchains[0].completed_jobs, 2);
+        assert_eq!(progress.toolchains[1].completed_jobs, 1);
+        assert_eq!(progress.comparisons.len(), 1);
+        assert_eq!(progress.comparisons[&Comparison::Regressed], 1);
+        assert_eq!(ex.raw_progress(&db).unwrap(), (3, progress.total_jobs));
+    }
+
+    #[test]
+    fn test_cost() {
+        use crate::results::{DatabaseDB, EncodingType, ResourceUsage, TestResult, WriteResults};
+
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let krate = ex.get_uncompleted_crates(&db).unwrap().remove(0);
+        assert_eq!(ex.cost(&db).unwrap(), ExperimentCost::default());
+
+        let results = DatabaseDB::new(&db);
+        for &(tc, log, duration) in &[
+            (&ex.toolchains[0], &b"first"[..], 1800.0),
+            (&ex.toolchains[1], &b"second"[..], 5400.0),
+        ] {
+            results
+                .store_result(
+                    &ex,
+                    &krate,
+                    tc,
+                    TestResult::TestPass,
+                    log,
+                    EncodingType::Plain,
+                )
+                .unwrap();
+            results
+                .record_resource_usage(
+                    &ex,
+                    tc,
+                    &krate,
+                    ResourceUsage {
+                        duration,
+                        cpus: 2.0,
+                    },
+                )
+                .unwrap();
+        }
+
+        assert_eq!(
+            ex.cost(&db).unwrap(),
+            ExperimentCost {
+                jobs: 2,
+                agent_hours: 2.0,
+                cpu_hours: 4.0,
+                stored_bytes: 11,
+            }
+        );
+    }
 }
@@ -9,21 +9,26 @@
 pub mod actions;
 pub mod agent;
 mod assets;
+pub mod audit;
 #[macro_use]
 pub mod utils;
+pub mod client;
 pub mod config;
 pub mod crates;
 pub mod db;
 pub mod dirs;
 pub mod experiments;
+pub mod maintenance;
 mod prelude;
 pub mod report;
 pub mod results;
+pub mod retention;
 pub mod runner;
 pub mod server;
 pub mod toolchain;
 
 pub(crate) static GIT_REVISION: Option<&str> = include!(concat!(env!("OUT_DIR"), "/sha"));
+pub(crate) static CRATER_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub(crate) static CRATER_REPO_URL: &str = "https://github.com/rust-lang/crater";
 
 lazy_static::lazy_static! {
@@ -0,0 +1,83 @@
+use crate::audit::{self, Actor, AuditAction};
+use crate::db::{Database, QueryUtils};
+use crate::prelude::*;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+
+/// Message shown when maintenance mode is enabled without a custom one.
+pub const DEFAULT_MESSAGE: &str = "Crater is undergoing maintenance.";
+
+/// Details about the maintenance mode, while it's enabled.
+///
+/// During maintenance agents are not given any experiment to run (but can still upload the results
+/// of the crates they're building) and new experiments can't be created.
+#[derive(Clone, Serialize)]
+pub struct Maintenance {
+    pub message: String,
+    pub since: DateTime<Utc>,
+}
+
+/// Return the details of the maintenance mode, or `None` if it's not enabled.
+pub fn status(db: &Database) -> Fallible<Option<Maintenance>> {
+    Ok(db
+        .query(
+            "SELECT message, since FROM maintenance LIMIT 1;",
+            &[],
+            |row| Maintenance {
+                message: row.get("message"),
+                since: row.get("since"),
+            },
+        )?
+        .pop())
+}
+
+pub fn enable(db: &Database, actor: &Actor, message: Option<&str>) -> Fallible<()> {
+    let message = message.unwrap_or(DEFAULT_MESSAGE);
+    db.transaction(|t| {
+        t.execute("DELETE FROM maintenance;", &[])?;
+        t.execute(
+            "INSERT INTO maintenance (message, since) VALUES (?1, ?2);",
+            &[&message, &Utc::now()],
+        )?;
+        audit::record(
+            t,
+            actor,
+            AuditAction::EnableMaintenance,
+            None,
+            &json!({ "message": message }),
+        )
+    })
+}
+
+pub fn disable(db: &Database, actor: &Actor) -> Fallible<()> {
+    db.transaction(|t| {
+        t.execute("DELETE FROM maintenance;", &[])?;
+        audit::record(t, actor, AuditAction::DisableMaintenance, None, &json!({}))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disable, enable, status, DEFAULT_MESSAGE};
+    use crate::audit::Actor;
+    use crate::db::Database;
+
+    #[test]
+    fn test_maintenance_toggle() {
+        let db = Database::temp().unwrap();
+        assert!(status(&db).unwrap().is_none());
+
+        enable(&db, &Actor::CLI, None).unwrap();
+        assert_eq!(status(&db).unwrap().unwrap().message, DEFAULT_MESSAGE);
+
+        // Enabling it again replaces the message
+        enable(&db, &Actor::CLI, Some("moving to a new host")).unwrap();
+        assert_eq!(
+            status(&db).unwrap().unwrap().message,
+            "moving to a new host"
+        );
+
+        disable(&db, &Actor::CLI).unwrap();
+        assert!(status(&db).unwrap().is_none());
+    }
+}
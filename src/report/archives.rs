@@ -3,8 +3,9 @@ use crate::crates::Crate;
 use crate::experiments::Experiment;
 use crate::prelude::*;
 use crate::report::{compare, ReportWriter};
-use crate::results::{EncodedLog, EncodingType, ReadResults};
+use crate::results::{EncodedLog, ReadResults};
 use flate2::{write::GzEncoder, Compression};
+use mime::Mime;
 use std::collections::HashMap;
 use tar::{Builder as TarBuilder, Header as TarHeader};
 
@@ -21,65 +22,75 @@ pub fn write_logs_archives<DB: ReadResults, W: ReportWriter>(
     dest: &W,
     config: &Config,
 ) -> Fallible<Vec<Archive>> {
+    let mime: Mime = "application/gzip".parse().unwrap();
     let mut archives = Vec::new();
-    let mut all = TarBuilder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    // The archives are streamed to the destination while they're generated, as keeping the whole
+    // archives in memory would require a lot of it for big experiments
+    let mut all = TarBuilder::new(GzEncoder::new(
+        dest.stream("logs-archives/all.tar.gz", &mime)?,
+        Compression::default(),
+    ));
     let mut by_comparison = HashMap::new();
+    let target_toolchains = ex.target_toolchains();
 
     for krate in crates {
         if config.should_skip(krate) {
             continue;
         }
 
-        let res1 = db.load_test_result(ex, &ex.toolchains[0], krate)?;
-        let res2 = db.load_test_result(ex, &ex.toolchains[1], krate)?;
-        let comparison = compare(config, krate, res1, res2);
-
-        for tc in &ex.toolchains {
-            let log = db
-                .load_log(ex, tc, krate)
-                .and_then(|c| c.ok_or_else(|| err_msg("missing logs")))
-                .with_context(|_| format!("failed to read log of {} on {}", krate, tc));
-
-            let log_bytes: EncodedLog = match log {
-                Ok(l) => l,
-                Err(e) => {
-                    crate::utils::report_failure(&e);
-                    continue;
+        for (_, toolchains) in &target_toolchains {
+            let res1 = db.load_test_result(ex, &toolchains[0], krate)?;
+            let res2 = db.load_test_result(ex, &toolchains[1], krate)?;
+            let comparison = compare(config, krate, res1, res2);
+
+            for tc in toolchains {
+                let log = db
+                    .load_log(ex, tc, krate)
+                    .and_then(|c| c.ok_or_else(|| err_msg("missing logs")))
+                    .with_context(|_| format!("failed to read log of {} on {}", krate, tc));
+
+                let log_bytes: EncodedLog = match log {
+                    Ok(l) => l,
+                    Err(e) => {
+                        crate::utils::report_failure(&e);
+                        continue;
+                    }
+                };
+
+                let log_bytes = log_bytes.to_plain()?;
+                let log_bytes = log_bytes.as_slice();
+
+                let path = format!(
+                    "{}/{}/{}.txt",
+                    comparison,
+                    krate.id(),
+                    tc.to_path_component(),
+                );
+
+                let mut header = TarHeader::new_gnu();
+                header.set_size(log_bytes.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+
+                all.append_data(&mut header, &path, log_bytes)?;
+                if !by_comparison.contains_key(&comparison) {
+                    let stream =
+                        dest.stream(format!("logs-archives/{}.tar.gz", comparison), &mime)?;
+                    by_comparison.insert(
+                        comparison,
+                        TarBuilder::new(GzEncoder::new(stream, Compression::default())),
+                    );
                 }
-            };
-
-            let log_bytes = log_bytes.to_plain()?;
-            let log_bytes = log_bytes.as_slice();
-
-            let path = format!(
-                "{}/{}/{}.txt",
-                comparison,
-                krate.id(),
-                tc.to_path_component(),
-            );
-
-            let mut header = TarHeader::new_gnu();
-            header.set_size(log_bytes.len() as u64);
-            header.set_mode(0o644);
-            header.set_cksum();
-
-            all.append_data(&mut header, &path, log_bytes)?;
-            by_comparison
-                .entry(comparison)
-                .or_insert_with(|| {
-                    TarBuilder::new(GzEncoder::new(Vec::new(), Compression::default()))
-                })
-                .append_data(&mut header, &path, log_bytes)?;
+                by_comparison.get_mut(&comparison).unwrap().append_data(
+                    &mut header,
+                    &path,
+                    log_bytes,
+                )?;
+            }
         }
     }
 
-    let data = all.into_inner()?.finish()?;
-    dest.write_bytes(
-        "logs-archives/all.tar.gz",
-        data,
-        &"application/gzip".parse().unwrap(),
-        EncodingType::Plain,
-    )?;
+    all.into_inner()?.finish()?.finish()?;
 
     archives.push(Archive {
         name: "All the crates".to_string(),
@@ -87,13 +98,7 @@ pub fn write_logs_archives<DB: ReadResults, W: ReportWriter>(
     });
 
     for (comparison, archive) in by_comparison.drain() {
-        let data = archive.into_inner()?.finish()?;
-        dest.write_bytes(
-            &format!("logs-archives/{}.tar.gz", comparison),
-            data,
-            &"application/gzip".parse().unwrap(),
-            EncodingType::Plain,
-        )?;
+        archive.into_inner()?.finish()?.finish()?;
 
         archives.push(Archive {
             name: format!("{} crates", comparison),
@@ -0,0 +1,156 @@
+use crate::config::Config;
+use crate::crates::Crate;
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::report::{compare, crate_to_path_fragment, Comparison, SanitizationContext};
+use crate::results::{ReadResults, TestResult};
+use serde_json;
+use std::io::Write;
+
+/// Result of a crate on a single toolchain, as exported by `export_ndjson`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExportedResult {
+    #[serde(rename = "crate")]
+    pub krate: Crate,
+    pub name: String,
+    pub toolchain: String,
+    /// Target the crate was built for, or `None` for the host.
+    pub target: Option<String>,
+    pub result: Option<TestResult>,
+    pub classification: Comparison,
+    /// Path of the log in the report, relative to its `index.html`.
+    pub log: String,
+    /// Full URL of the log, once the report is published.
+    pub log_url: Option<String>,
+    /// Version of the C compiler the crate was built with, if the toolchain chose one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub c_compiler: Option<String>,
+}
+
+/// Write the results of the crates as newline-delimited JSON, with one line for each crate and
+/// toolchain. The results are written while they're loaded, to export big experiments without
+/// keeping all of them in memory.
+pub fn export_ndjson<DB: ReadResults, W: Write>(
+    db: &DB,
+    config: &Config,
+    ex: &Experiment,
+    crates: &[Crate],
+    dest: &mut W,
+) -> Fallible<()> {
+    let skip_reasons = db.load_skip_reasons(ex)?;
+    let report_url = ex.report_url.as_ref().map(|url| {
+        url.trim_end_matches("index.html")
+            .trim_end_matches('/')
+            .to_string()
+    });
+    let target_toolchains = ex.target_toolchains();
+
+    for krate in crates {
+        for (target, toolchains) in &target_toolchains {
+            let res1 = db.load_test_result(ex, &toolchains[0], krate)?;
+            let res2 = db.load_test_result(ex, &toolchains[1], krate)?;
+            let classification = match (res1, res2) {
+                // Crates skipped when the experiment was created are never run
+                (None, None) if skip_reasons.contains_key(krate) => Comparison::Skipped,
+                _ => compare(config, krate, res1, res2),
+            };
+
+            for (tc, result) in toolchains.iter().zip(&[res1, res2]) {
+                let log = format!(
+                    "{}/log.txt",
+                    crate_to_path_fragment(tc, krate, SanitizationContext::Url)
+                        .to_string_lossy()
+                        .replace(r"\", "/"),
+                );
+                let record = ExportedResult {
+                    krate: krate.clone(),
+                    name: krate.to_string(),
+                    toolchain: tc.to_string(),
+                    target: target.map(|target| target.to_string()),
+                    result: *result,
+                    classification,
+                    log_url: report_url.as_ref().map(|url| format!("{}/{}", url, log)),
+                    log,
+                    c_compiler: db.load_c_compiler(ex, tc, krate)?,
+                };
+                serde_json::to_writer(&mut *dest, &record)?;
+                dest.write_all(b"\n")?;
+            }
+        }
+    }
+
+    dest.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_ndjson, ExportedResult};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::Experiment;
+    use crate::report::Comparison;
+    use crate::results::{DatabaseDB, EncodingType, FailureReason, TestResult, WriteResults};
+
+    #[test]
+    fn test_export_ndjson() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let krate = ex.get_crates(&db).unwrap().remove(0);
+        let results = DatabaseDB::new(&db);
+        for (tc, result) in ex.toolchains.iter().zip(&[
+            TestResult::TestPass,
+            TestResult::BuildFail(FailureReason::Unknown),
+        ]) {
+            results
+                .store_result(&ex, &krate, tc, *result, b"", EncodingType::Plain)
+                .unwrap();
+        }
+        results
+            .record_c_compiler(
+                &ex,
+                &ex.toolchains[1],
+                &krate,
+                "gcc-9 (Debian 9.3.0-22) 9.3.0",
+            )
+            .unwrap();
+
+        let mut dest = Vec::new();
+        export_ndjson(&results, &config, &ex, &[krate.clone()], &mut dest).unwrap();
+        let records = String::from_utf8(dest)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<ExportedResult>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(records.len(), 2);
+        for (record, tc) in records.iter().zip(&ex.toolchains) {
+            assert_eq!(record.krate, krate);
+            assert_eq!(record.toolchain, tc.to_string());
+            assert_eq!(record.classification, Comparison::Regressed);
+            assert!(record.log.starts_with(&format!("{}/", tc)));
+            assert!(record.log.ends_with("/log.txt"));
+            assert!(record.log_url.is_none());
+        }
+        assert_eq!(records[0].result, Some(TestResult::TestPass));
+        assert_eq!(
+            records[1].result,
+            Some(TestResult::BuildFail(FailureReason::Unknown))
+        );
+        assert!(records[0].c_compiler.is_none());
+        assert_eq!(
+            records[1]
+                .c_compiler
+                .as_ref()
+                .map(|version| version.as_str()),
+            Some("gcc-9 (Debian 9.3.0-22) 9.3.0")
+        );
+    }
+}
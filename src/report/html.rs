@@ -1,11 +1,18 @@
 use crate::assets;
-use crate::experiments::Experiment;
+use crate::experiments::{CrateSkipReason, Experiment, Mode};
 use crate::prelude::*;
-use crate::report::{archives::Archive, Comparison, CrateResult, ReportWriter, TestResults};
-use crate::results::{BrokenReason, EncodingType, FailureReason, TestResult};
+use crate::report::{
+    archives::Archive, Comparison, CrateResult, ReportSummary, ReportWriter, TargetSummary,
+    TestResults,
+};
+use crate::results::{BrokenReason, EncodingType, FailureReason, SkipReason, TestResult};
+use crate::toolchain::ToolchainVersion;
 use mime;
 use minifier;
-use std::collections::HashMap;
+use std::collections::{btree_map::Entry, BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use tempfile;
 
 #[derive(Serialize)]
 enum Color {
@@ -44,6 +51,7 @@ impl ResultColor for TestResult {
             TestResult::TestFail(_) => Color::Single("#65461e"),
             TestResult::TestSkipped | TestResult::TestPass => Color::Single("#62a156"),
             TestResult::Error => Color::Single("#d77026"),
+            TestResult::Skipped(_) => Color::Striped("#494b4a", "#555555"),
         }
     }
 }
@@ -58,6 +66,13 @@ impl ResultName for FailureReason {
             FailureReason::Unknown => "failed".into(),
             FailureReason::Timeout => "timed out".into(),
             FailureReason::OOM => "OOM".into(),
+            FailureReason::ICE => "ICE".into(),
+            FailureReason::CompilerError => "failed (compiler error)".into(),
+            FailureReason::DependsOn => "failed (dependency)".into(),
+            FailureReason::BuildScript => "failed (build script)".into(),
+            FailureReason::DocTest => "failed (doctests)".into(),
+            FailureReason::UndefinedBehavior => "failed (undefined behavior)".into(),
+            FailureReason::FixFailed => "failed (fix not applied)".into(),
         }
     }
 }
@@ -73,6 +88,27 @@ impl ResultName for BrokenReason {
     }
 }
 
+impl ResultName for SkipReason {
+    fn name(&self) -> String {
+        match self {
+            SkipReason::Unknown => "skipped".into(),
+            SkipReason::IncompatibleTarget => "incompatible target".into(),
+            SkipReason::WorkspaceBuildFailed => "workspace build failed".into(),
+        }
+    }
+}
+
+impl ResultName for CrateSkipReason {
+    fn name(&self) -> String {
+        match self {
+            CrateSkipReason::Blacklisted => "blacklisted".into(),
+            CrateSkipReason::IncompatibleTarget => "incompatible target".into(),
+            CrateSkipReason::MissingFromIndex => "missing from the index".into(),
+            CrateSkipReason::Yanked => "yanked".into(),
+        }
+    }
+}
+
 impl ResultName for TestResult {
     fn name(&self) -> String {
         match self {
@@ -82,10 +118,24 @@ impl ResultName for TestResult {
             TestResult::TestSkipped => "test skipped".into(),
             TestResult::TestPass => "test passed".into(),
             TestResult::Error => "error".into(),
+            TestResult::Skipped(reason) => reason.name(),
         }
     }
 }
 
+/// Name of the result in the legend of the report, describing what the result means in the mode
+/// of the experiment when it differs from the usual one.
+fn result_name(ex: &Experiment, res: TestResult) -> String {
+    match (ex.mode, res) {
+        (Mode::Fix, TestResult::TestPass) => "fixed cleanly".into(),
+        (Mode::Fix, TestResult::TestFail(FailureReason::FixFailed)) => "fix failed to apply".into(),
+        (Mode::Fix, TestResult::TestFail(reason)) if !reason.is_spurious() => {
+            format!("fixed code {}", reason.name())
+        }
+        _ => res.name(),
+    }
+}
+
 #[derive(Serialize)]
 struct NavbarItem {
     label: &'static str,
@@ -122,74 +172,196 @@ impl CurrentPage {
     }
 }
 
+/// Category of the crates listed in the `known-broken` section of the configuration, shown
+/// separately from the comparisons.
+const KNOWN_ISSUES: &str = "known-issues";
+
+fn category(result: &CrateResult) -> &'static str {
+    if result.known_issue.is_some() {
+        KNOWN_ISSUES
+    } else {
+        result.res.to_str()
+    }
+}
+
+/// Position of the target the crate was built for, as the crates of experiments built for
+/// multiple targets are grouped by target.
+fn target_position(ex: &Experiment, result: &CrateResult) -> Option<usize> {
+    let target = result.target.as_ref()?;
+    ex.targets
+        .triples()
+        .iter()
+        .position(|triple| triple == target)
+}
+
+/// Colors and names of the results shown in a page of the report.
+#[derive(Default)]
+struct Legend {
+    comparison_colors: HashMap<Comparison, Color>,
+    result_colors: HashMap<TestResult, Color>,
+    result_names: HashMap<TestResult, String>,
+}
+
+impl Legend {
+    fn add(&mut self, ex: &Experiment, result: &CrateResult) {
+        self.comparison_colors
+            .entry(result.res)
+            .or_insert_with(|| result.res.color());
+        let runs = result
+            .runs
+            .iter()
+            .filter_map(|run| run.as_ref().map(|run| run.res));
+        let members = result
+            .members
+            .iter()
+            .flat_map(|member| member.runs.iter().filter_map(|run| *run));
+        for member in &result.members {
+            self.comparison_colors
+                .entry(member.res)
+                .or_insert_with(|| member.res.color());
+        }
+        for res in runs.chain(members) {
+            self.result_colors.entry(res).or_insert_with(|| res.color());
+            self.result_names
+                .entry(res)
+                .or_insert_with(|| result_name(ex, res));
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CratesContext<'a> {
+    crates: &'a [&'a CrateResult],
+    result_names: &'a HashMap<TestResult, String>,
+}
+
+fn render_crates(crates: &[&CrateResult], legend: &Legend) -> Fallible<String> {
+    let context = CratesContext {
+        crates,
+        result_names: &legend.result_names,
+    };
+    assets::render_template("report/crates.html", &context)
+}
+
+/// Crates of the full report, rendered a page at a time into a temporary file for each category
+/// and target, as the results of all the crates of big experiments take a lot of memory.
+pub struct FullReport<'a> {
+    ex: &'a Experiment,
+    legend: Legend,
+    counts: HashMap<&'static str, usize>,
+    crates: BTreeMap<(&'static str, Option<usize>), File>,
+}
+
+impl<'a> FullReport<'a> {
+    pub fn new(ex: &'a Experiment) -> Self {
+        FullReport {
+            ex,
+            legend: Legend::default(),
+            counts: HashMap::new(),
+            crates: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, page: &[CrateResult]) -> Fallible<()> {
+        let mut groups = BTreeMap::new();
+        for result in page {
+            self.legend.add(self.ex, result);
+            let category = category(result);
+            *self.counts.entry(category).or_insert(0) += 1;
+            groups
+                .entry((category, target_position(self.ex, result)))
+                .or_insert_with(Vec::new)
+                .push(result);
+        }
+
+        for (key, crates) in groups {
+            let html = render_crates(&crates, &self.legend)?;
+            let file = match self.crates.entry(key) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(tempfile::tempfile()?),
+            };
+            file.write_all(html.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Crates of a category, already rendered as the results of all the crates of big experiments
+/// take a lot of memory.
+#[derive(Serialize)]
+struct Category {
+    count: usize,
+    crates: String,
+}
+
+#[derive(Serialize)]
+struct SkippedCrates {
+    reason: String,
+    count: u32,
+}
+
 #[derive(Serialize)]
 struct ResultsContext<'a> {
     ex: &'a Experiment,
+    toolchain_versions: &'a [Option<ToolchainVersion>; 2],
+    c_compilers: &'a [Vec<String>; 2],
     nav: Vec<NavbarItem>,
-    categories: HashMap<Comparison, Vec<CrateResult>>,
+    categories: HashMap<&'static str, Category>,
     full: bool,
     crates_count: usize,
+    skipped_crates: Vec<SkippedCrates>,
+    targets: Vec<TargetSummary>,
+    generator: Option<&'a str>,
 
-    comparison_colors: HashMap<Comparison, Color>,
-    result_colors: HashMap<TestResult, Color>,
-    result_names: HashMap<TestResult, String>,
+    comparison_colors: &'a HashMap<Comparison, Color>,
+    result_colors: &'a HashMap<TestResult, Color>,
 }
 
 #[derive(Serialize)]
 struct DownloadsContext<'a> {
     ex: &'a Experiment,
+    toolchain_versions: &'a [Option<ToolchainVersion>; 2],
+    c_compilers: &'a [Vec<String>; 2],
     nav: Vec<NavbarItem>,
     crates_count: usize,
+    generator: Option<&'a str>,
 
     available_archives: Vec<Archive>,
 }
 
+#[derive(Serialize)]
+struct DeletedContext<'a> {
+    name: &'a str,
+}
+
 fn write_report<W: ReportWriter>(
     ex: &Experiment,
     crates_count: usize,
-    res: &TestResults,
+    summary: &ReportSummary,
+    legend: &Legend,
+    categories: HashMap<&'static str, Category>,
     full: bool,
     to: &str,
     dest: &W,
 ) -> Fallible<()> {
-    let mut comparison_colors = HashMap::new();
-    let mut result_colors = HashMap::new();
-    let mut result_names = HashMap::new();
-
-    let mut categories = HashMap::new();
-    for result in &res.crates {
-        // Skip some categories if this is not the full report
-        if !full && !result.res.show_in_summary() {
-            continue;
-        }
-
-        // Add the colors and names used in this run
-        comparison_colors
-            .entry(result.res)
-            .or_insert_with(|| result.res.color());
-        if let Some(ref run) = result.runs[0] {
-            result_colors
-                .entry(run.res)
-                .or_insert_with(|| run.res.color());
-            result_names
-                .entry(run.res)
-                .or_insert_with(|| run.res.name());
-        }
-        if let Some(ref run) = result.runs[1] {
-            result_colors
-                .entry(run.res)
-                .or_insert_with(|| run.res.color());
-            result_names
-                .entry(run.res)
-                .or_insert_with(|| run.res.name());
-        }
-
-        let category = categories.entry(result.res).or_insert_with(Vec::new);
-        category.push(result.clone());
-    }
+    let res = &summary.results;
+    let skipped_crates = res
+        .skipped_crates
+        .iter()
+        .map(|(reason, &count)| SkippedCrates {
+            // Reasons unknown to this version of Crater are shown as they're recorded
+            reason: reason
+                .parse::<CrateSkipReason>()
+                .map(|reason| reason.name())
+                .unwrap_or_else(|_| reason.clone()),
+            count,
+        })
+        .collect();
 
     let context = ResultsContext {
         ex,
+        toolchain_versions: &res.toolchain_versions,
+        c_compilers: &res.c_compilers,
         nav: if full {
             CurrentPage::Full
         } else {
@@ -199,9 +371,11 @@ fn write_report<W: ReportWriter>(
         categories,
         full,
         crates_count,
-        comparison_colors,
-        result_colors,
-        result_names,
+        skipped_crates,
+        targets: summary.targets.clone(),
+        generator: res.generator.as_ref().map(|g| g.as_str()),
+        comparison_colors: &legend.comparison_colors,
+        result_colors: &legend.result_colors,
     };
 
     info!("generating {}", to);
@@ -213,14 +387,18 @@ fn write_report<W: ReportWriter>(
 
 fn write_downloads<W: ReportWriter>(
     ex: &Experiment,
+    res: &TestResults,
     crates_count: usize,
     available_archives: Vec<Archive>,
     dest: &W,
 ) -> Fallible<()> {
     let context = DownloadsContext {
         ex,
+        toolchain_versions: &res.toolchain_versions,
+        c_compilers: &res.c_compilers,
         nav: CurrentPage::Downloads.navbar(),
         crates_count,
+        generator: res.generator.as_ref().map(|g| g.as_str()),
         available_archives,
     };
 
@@ -234,15 +412,76 @@ fn write_downloads<W: ReportWriter>(
 pub fn write_html_report<W: ReportWriter>(
     ex: &Experiment,
     crates_count: usize,
-    res: &TestResults,
+    summary: &ReportSummary,
+    full: FullReport,
     available_archives: Vec<Archive>,
     dest: &W,
 ) -> Fallible<()> {
     let js_in = assets::load("report.js")?;
     let css_in = assets::load("report.css")?;
-    write_report(ex, crates_count, res, false, "index.html", dest)?;
-    write_report(ex, crates_count, res, true, "full.html", dest)?;
-    write_downloads(ex, crates_count, available_archives, dest)?;
+
+    // Only the crates shown in the summary are kept in memory
+    let mut legend = Legend::default();
+    let mut categories = HashMap::new();
+    for result in &summary.results.crates {
+        legend.add(ex, result);
+        categories
+            .entry(category(result))
+            .or_insert_with(Vec::new)
+            .push(result);
+    }
+    for crates in categories.values_mut() {
+        crates.sort_by_key(|krate| target_position(ex, krate));
+    }
+    let mut rendered = HashMap::new();
+    for (name, crates) in categories {
+        rendered.insert(
+            name,
+            Category {
+                count: crates.len(),
+                crates: render_crates(&crates, &legend)?,
+            },
+        );
+    }
+    write_report(
+        ex,
+        crates_count,
+        summary,
+        &legend,
+        rendered,
+        false,
+        "index.html",
+        dest,
+    )?;
+
+    // The crates of each target were rendered separately, and are joined in the order of the
+    // targets
+    let FullReport {
+        legend,
+        counts,
+        crates,
+        ..
+    } = full;
+    let mut rendered = HashMap::new();
+    for ((name, _), mut file) in crates {
+        let category = rendered.entry(name).or_insert_with(|| Category {
+            count: counts[name],
+            crates: String::new(),
+        });
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_string(&mut category.crates)?;
+    }
+    write_report(
+        ex,
+        crates_count,
+        summary,
+        &legend,
+        rendered,
+        true,
+        "full.html",
+        dest,
+    )?;
+    write_downloads(ex, &summary.results, crates_count, available_archives, dest)?;
 
     info!("copying static assets");
     dest.write_bytes(
@@ -260,3 +499,13 @@ pub fn write_html_report<W: ReportWriter>(
 
     Ok(())
 }
+
+/// Write the page replacing the report of a deleted experiment, at the paths of all the pages
+/// of the report.
+pub fn write_deleted_report<W: ReportWriter>(name: &str, dest: &W) -> Fallible<()> {
+    let html = assets::render_template("report/deleted.html", &DeletedContext { name })?;
+    for page in &["index.html", "full.html", "downloads.html"] {
+        dest.write_string(page, html.clone().into(), &mime::TEXT_HTML)?;
+    }
+    Ok(())
+}
@@ -1,27 +1,39 @@
 use crate::config::Config;
 use crate::crates::{Crate, GitHubRepo};
-use crate::experiments::Experiment;
+use crate::experiments::{Experiment, Mode};
 use crate::prelude::*;
-use crate::results::{EncodedLog, EncodingType, ReadResults, TestResult};
-use crate::toolchain::Toolchain;
+use crate::results::{normalize, EncodedLog, EncodingType, MemberResult, ReadResults, TestResult};
+use crate::toolchain::{Toolchain, ToolchainVersion};
 use crate::utils;
+use crater_results::report::{BuildTestResult, DiagnosticsDelta, DocsDelta, PrimaryError};
+pub use crater_results::report::{
+    Comparison, CrateResult, MemberComparison, TargetSummary, TestResults,
+};
+use crossbeam_utils::thread::scope;
 use mime::{self, Mime};
 use percent_encoding::{utf8_percent_encode, AsciiSet};
+use serde::ser::{Error as _, Serialize, SerializeSeq, Serializer};
 use serde_json;
 use std::borrow::Cow;
-#[cfg(test)]
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::AsRef;
 use std::fmt::{self, Display};
 use std::fs::{self, File};
-use std::io::{self, Read};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(test)]
+use std::sync::Mutex;
 
 mod archives;
+mod export;
 mod html;
+mod repro;
 mod s3;
+mod timings;
 
+pub use self::export::{export_ndjson, ExportedResult};
 pub use self::s3::{get_client_for_bucket, S3Prefix, S3Writer};
 
 pub(crate) const REPORT_ENCODE_SET: AsciiSet = percent_encoding::CONTROLS
@@ -36,62 +48,9 @@ pub(crate) const REPORT_ENCODE_SET: AsciiSet = percent_encoding::CONTROLS
     .add(b'}')
     .add(b'+');
 
-#[derive(Serialize, Deserialize)]
-pub struct TestResults {
-    pub crates: Vec<CrateResult>,
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-pub struct CrateResult {
-    name: String,
-    url: String,
-    pub res: Comparison,
-    runs: [Option<BuildTestResult>; 2],
-}
-
-string_enum!(pub enum Comparison {
-    Regressed => "regressed",
-    Fixed => "fixed",
-    Skipped => "skipped",
-    Unknown => "unknown",
-    Error => "error",
-    Broken => "broken",
-    SameBuildFail => "build-fail",
-    SameTestFail => "test-fail",
-    SameTestSkipped => "test-skipped",
-    SameTestPass => "test-pass",
-    SpuriousRegressed => "spurious-regressed",
-    SpuriousFixed => "spurious-fixed",
-});
-
-impl Comparison {
-    pub fn show_in_summary(self) -> bool {
-        match self {
-            Comparison::Regressed
-            | Comparison::Fixed
-            | Comparison::Unknown
-            | Comparison::Error
-            | Comparison::SpuriousRegressed
-            | Comparison::SpuriousFixed => true,
-            Comparison::Skipped
-            | Comparison::Broken
-            | Comparison::SameBuildFail
-            | Comparison::SameTestFail
-            | Comparison::SameTestSkipped
-            | Comparison::SameTestPass => false,
-        }
-    }
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-struct BuildTestResult {
-    res: TestResult,
-    log: String,
-}
-
 /// The type of sanitization required for a string.
 #[derive(Debug, Clone, Copy)]
-enum SanitizationContext {
+pub(crate) enum SanitizationContext {
     Url,
     Path,
 }
@@ -108,7 +67,7 @@ impl SanitizationContext {
     }
 }
 
-fn crate_to_path_fragment(
+pub(crate) fn crate_to_path_fragment(
     toolchain: &Toolchain,
     krate: &Crate,
     dest: SanitizationContext,
@@ -116,81 +75,313 @@ fn crate_to_path_fragment(
     let mut path = PathBuf::new();
     path.push(dest.sanitize(&toolchain.to_string()).into_owned());
 
-    match *krate {
-        Crate::Registry(ref details) => {
-            path.push("reg");
-
-            let name = format!("{}-{}", details.name, details.version);
-            path.push(dest.sanitize(&name).into_owned());
-        }
-        Crate::GitHub(ref repo) => {
-            path.push("gh");
-
-            let name = format!("{}.{}", repo.org, repo.name);
-            path.push(dest.sanitize(&name).into_owned());
-        }
-        Crate::Local(ref name) => {
-            path.push("local");
-            path.push(name);
-        }
-    }
+    let source = krate.source();
+    path.push(source.kind());
+    path.push(dest.sanitize(&source.path_name()).into_owned());
 
     path
 }
 
-pub fn generate_report<DB: ReadResults>(
+/// Number of crates whose results are loaded at the same time while generating a report.
+const REPORT_PAGE_SIZE: usize = 1000;
+
+/// Load the results of the experiment, passing them to `page` a few crates at a time as the
+/// results of all the crates of big experiments take a lot of memory. The returned results
+/// contain the metadata of the report, without any crate.
+pub fn generate_report<DB, F>(
     db: &DB,
     config: &Config,
     ex: &Experiment,
     crates: &[Crate],
-) -> Fallible<TestResults> {
+    mut page: F,
+) -> Fallible<TestResults>
+where
+    DB: ReadResults + Sync,
+    F: FnMut(Vec<CrateResult>) -> Fallible<()>,
+{
     let shas = db.load_all_shas(ex)?;
-    let mut crates = crates.to_vec();
+    let skip_reasons = db.load_skip_reasons(ex)?;
+    let mut skipped_crates = BTreeMap::new();
+    for reason in skip_reasons.values() {
+        *skipped_crates.entry(reason.to_string()).or_insert(0) += 1;
+    }
+
+    // Only sort references to the crates, to avoid keeping a second copy of the list in memory
+    let mut crates = crates.iter().collect::<Vec<_>>();
     //crate ids are unique so unstable sort is equivalent to stable sort but is generally faster
-    crates.sort_unstable_by(|a, b| a.id().cmp(&b.id()));
-    let res = crates
-        .iter()
-        .map(|krate| {
-            // Any errors here will turn into unknown results
-            let crate_results = ex.toolchains.iter().map(|tc| -> Fallible<BuildTestResult> {
-                let res = db
-                    .load_test_result(ex, tc, &krate)?
-                    .ok_or_else(|| err_msg("no result"))?;
-
-                Ok(BuildTestResult {
-                    res,
-                    log: crate_to_path_fragment(tc, &krate, SanitizationContext::Url)
-                        .to_str()
-                        .unwrap()
-                        .replace(r"\", "/"), // Normalize paths in reports generated on Windows
-                })
-            });
-            // Convert errors to Nones
-            let mut crate_results = crate_results.map(|r| r.ok()).collect::<Vec<_>>();
-            let crate2 = crate_results.pop().unwrap();
-            let crate1 = crate_results.pop().unwrap();
-            let comp = compare(
-                config,
-                &krate,
-                crate1.as_ref().map(|b| b.res),
-                crate2.as_ref().map(|b| b.res),
-            );
+    crates.sort_unstable_by_key(|krate| krate.id());
+    let target_toolchains = ex.target_toolchains();
+    let ordered_toolchains = ex.ordered_toolchains();
+    for crates in crates.chunks(REPORT_PAGE_SIZE) {
+        let res = parallel_map(crates, |&krate| {
+            let mut results = Vec::with_capacity(target_toolchains.len());
+            for (idx, (target, toolchains)) in target_toolchains.iter().enumerate() {
+                // Any errors here will turn into unknown results
+                let crate_results = toolchains.iter().map(|tc| -> Fallible<BuildTestResult> {
+                    let res = db
+                        .load_test_result(ex, tc, &krate)?
+                        .ok_or_else(|| err_msg("no result"))?;
+                    let primary_error = if let TestResult::BuildFail(_) = res {
+                        PrimaryError::find(&db.load_diagnostics(ex, tc, &krate)?)
+                    } else {
+                        None
+                    };
+
+                    // Snapshots are only captured when the build fails
+                    let snapshot = match res {
+                        TestResult::BuildFail(_) | TestResult::TestFail(_) => {
+                            db.load_snapshot(ex, tc, &krate)?.is_some()
+                        }
+                        _ => false,
+                    };
+
+                    Ok(BuildTestResult {
+                        res,
+                        log: crate_to_path_fragment(tc, &krate, SanitizationContext::Url)
+                            .to_str()
+                            .unwrap()
+                            .replace(r"\", "/"), // Normalize paths in reports generated on Windows
+                        failed_targets: db.load_failed_targets(ex, tc, &krate)?,
+                        primary_error,
+                        diagnostic_counts: db.load_diagnostic_counts(ex, tc, &krate)?,
+                        reproduce: false,
+                        snapshot,
+                    })
+                });
+                // Convert errors to Nones
+                let mut crate_results = crate_results.map(|r| r.ok()).collect::<Vec<_>>();
+                let crate2 = crate_results.pop().unwrap();
+                let crate1 = crate_results.pop().unwrap();
+                let comp = match (&crate1, &crate2) {
+                    // Crates skipped when the experiment was created are never run
+                    (None, None) if skip_reasons.contains_key(krate) => Comparison::Skipped,
+                    _ => compare(
+                        config,
+                        &krate,
+                        crate1.as_ref().map(|b| b.res),
+                        crate2.as_ref().map(|b| b.res),
+                    ),
+                };
+
+                let mut runs = [crate1, crate2];
+                if let Some(ref mut run) = runs[1] {
+                    let invocations = db.load_invocations(ex, &toolchains[1], &krate)?;
+                    run.reproduce = repro::is_reproducible(comp, &invocations);
+                }
+                let diagnostics_delta = DiagnosticsDelta::new(&runs);
+                let docs_delta = if ex.mode == Mode::RustdocJson {
+                    DocsDelta::new(
+                        &db.load_doc_items(ex, &toolchains[0], &krate)?,
+                        &db.load_doc_items(ex, &toolchains[1], &krate)?,
+                    )
+                } else {
+                    None
+                };
+                let log_changed = match comp {
+                    Comparison::SameBuildFail | Comparison::SameTestFail => {
+                        logs_changed(db, config, ex, toolchains, &krate)?
+                    }
+                    _ => false,
+                };
+                let first_bad = if comp == Comparison::Regressed {
+                    first_bad(db, config, ex, &ordered_toolchains[idx].1, &krate)?
+                } else {
+                    None
+                };
+
+                results.push(CrateResult {
+                    name: crate_to_name(&krate, &shas)?,
+                    url: crate_to_url(&krate, &shas)?,
+                    target: target.map(|target| target.to_string()),
+                    known_issue: config.known_issue(&krate).map(|reason| reason.to_string()),
+                    res: comp,
+                    runs,
+                    rust_version: db.load_rust_version(&krate)?,
+                    members: compare_members(db, config, ex, toolchains, &krate)?,
+                    diagnostics_delta,
+                    log_changed,
+                    docs_delta,
+                    first_bad,
+                });
+            }
+            Ok(results)
+        })?;
+        page(res.into_iter().flatten().collect())?;
+    }
 
-            Ok(CrateResult {
-                name: crate_to_name(&krate, &shas)?,
-                url: crate_to_url(&krate, &shas)?,
-                res: comp,
-                runs: [crate1, crate2],
-            })
+    // The versions reported by the builds for every target are merged in the report header
+    let mut c_compilers = [Vec::new(), Vec::new()];
+    for (_, toolchains) in &target_toolchains {
+        for (versions, tc) in c_compilers.iter_mut().zip(toolchains) {
+            for version in db.load_c_compilers(ex, tc)? {
+                if !versions.contains(&version) {
+                    versions.push(version);
+                }
+            }
+        }
+    }
+
+    Ok(TestResults {
+        crates: Vec::new(),
+        toolchain_versions: [
+            db.load_toolchain_version(ex, ex.start_toolchain())?,
+            db.load_toolchain_version(ex, ex.end_toolchain())?,
+        ],
+        c_compilers,
+        generator: Some(format!(
+            "crater {} ({})",
+            crate::CRATER_VERSION,
+            crate::GIT_REVISION.unwrap_or("unknown")
+        )),
+        skipped_crates,
+    })
+}
+
+/// Whether the logs of the two runs are different once normalized, to find the crates failing in
+/// a different way than before.
+fn logs_changed<DB: ReadResults>(
+    db: &DB,
+    config: &Config,
+    ex: &Experiment,
+    toolchains: &[Toolchain; 2],
+    krate: &Crate,
+) -> Fallible<bool> {
+    let rules = config.logs.normalization_rules(ex.mode);
+    let mut fingerprints = Vec::with_capacity(toolchains.len());
+    for tc in toolchains {
+        let log = match db.load_log(ex, tc, krate)? {
+            Some(log) => log.to_plain()?,
+            // Missing logs can't be compared
+            None => return Ok(false),
+        };
+        // The name of the toolchain is always different between the two logs
+        let log = String::from_utf8_lossy(&log).replace(&tc.to_string(), "[toolchain]");
+        fingerprints.push(normalize::fingerprint(log.as_bytes(), rules));
+    }
+    Ok(fingerprints[0] != fingerprints[1])
+}
+
+/// First toolchain the crate regressed with compared to the start toolchain, when the experiment
+/// also runs the toolchains released in between.
+fn first_bad<DB: ReadResults>(
+    db: &DB,
+    config: &Config,
+    ex: &Experiment,
+    ordered_toolchains: &[Toolchain],
+    krate: &Crate,
+) -> Fallible<Option<String>> {
+    if ordered_toolchains.len() <= 2 {
+        return Ok(None);
+    }
+
+    let start = db.load_test_result(ex, &ordered_toolchains[0], krate)?;
+    for tc in &ordered_toolchains[1..] {
+        let res = db.load_test_result(ex, tc, krate)?;
+        if compare(config, krate, start, res) == Comparison::Regressed {
+            return Ok(Some(tc.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+fn compare_members<DB: ReadResults>(
+    db: &DB,
+    config: &Config,
+    ex: &Experiment,
+    toolchains: &[Toolchain; 2],
+    krate: &Crate,
+) -> Fallible<Vec<MemberComparison>> {
+    let before = db.load_member_results(ex, &toolchains[0], krate)?;
+    let after = db.load_member_results(ex, &toolchains[1], krate)?;
+
+    // Members can be added or removed between the two runs when testing a git repository
+    let mut names = before.iter().map(|m| &m.name).collect::<Vec<_>>();
+    for member in &after {
+        if !names.contains(&&member.name) {
+            names.push(&member.name);
+        }
+    }
+
+    let find = |results: &[MemberResult], name: &str| {
+        results
+            .iter()
+            .find(|member| member.name == name)
+            .map(|member| member.result)
+    };
+    Ok(names
+        .into_iter()
+        .map(|name| {
+            let runs = [find(&before, name), find(&after, name)];
+            // Members whose build was interrupted by another one used to be recorded as having no
+            // tests, which can't be compared with the tests of the other toolchain
+            let res = match (runs[0], runs[1]) {
+                (Some(TestResult::TestSkipped), Some(TestResult::TestPass))
+                | (Some(TestResult::TestSkipped), Some(TestResult::TestFail(_)))
+                | (Some(TestResult::TestPass), Some(TestResult::TestSkipped))
+                | (Some(TestResult::TestFail(_)), Some(TestResult::TestSkipped)) => {
+                    Comparison::Skipped
+                }
+                _ => compare(config, krate, runs[0], runs[1]),
+            };
+            MemberComparison {
+                name: name.clone(),
+                res,
+                runs,
+            }
         })
-        .collect::<Fallible<Vec<_>>>()?;
+        .collect())
+}
+
+/// Number of threads used to generate and upload the report.
+const REPORT_THREADS: usize = 16;
+
+/// Apply `f` to all the items using a bounded pool of threads, returning the results in the same
+/// order as the items. The first error encountered is returned, if any.
+fn parallel_map<T, R, F>(items: &[T], f: F) -> Fallible<Vec<R>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> Fallible<R> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let threads = std::cmp::min(REPORT_THREADS, items.len());
+
+    let mut results = scope(|scope| {
+        let handles = (0..threads)
+            .map(|_| {
+                scope.spawn(|| -> Fallible<Vec<(usize, R)>> {
+                    let mut results = Vec::new();
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::SeqCst);
+                        if idx >= items.len() {
+                            return Ok(results);
+                        }
+                        results.push((idx, f(&items[idx])?));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = Vec::with_capacity(items.len());
+        for handle in handles {
+            match handle.join() {
+                Ok(res) => results.append(&mut res?),
+                Err(panic) => {
+                    utils::report_panic(&panic);
+                    bail!("a report generation thread panicked");
+                }
+            }
+        }
+        Ok(results)
+    })?;
 
-    Ok(TestResults { crates: res })
+    results.sort_unstable_by_key(|&(idx, _)| idx);
+    Ok(results.into_iter().map(|(_, res)| res).collect())
 }
 
 const PROGRESS_FRACTION: usize = 10; // write progress every ~1/N crates
 
-fn write_logs<DB: ReadResults, W: ReportWriter>(
+fn write_logs<DB: ReadResults + Sync, W: ReportWriter + Sync>(
     db: &DB,
     ex: &Experiment,
     crates: &[Crate],
@@ -199,16 +390,19 @@ fn write_logs<DB: ReadResults, W: ReportWriter>(
 ) -> Fallible<()> {
     let num_crates = crates.len();
     let progress_every = (num_crates / PROGRESS_FRACTION) + 1;
-    for (i, krate) in crates.iter().enumerate() {
+    let written = AtomicUsize::new(0);
+    let toolchains = ex.run_toolchains();
+    parallel_map(crates, |krate| -> Fallible<()> {
+        let i = written.fetch_add(1, Ordering::SeqCst);
         if i % progress_every == 0 {
             info!("wrote logs for {}/{} crates", i, num_crates)
         }
 
         if config.should_skip(krate) {
-            continue;
+            return Ok(());
         }
 
-        for tc in &ex.toolchains {
+        for tc in &toolchains {
             let log_path =
                 crate_to_path_fragment(tc, krate, SanitizationContext::Path).join("log.txt");
             let content = db
@@ -231,137 +425,178 @@ fn write_logs<DB: ReadResults, W: ReportWriter>(
                     dest.write_bytes(log_path, data, &mime::TEXT_PLAIN_UTF_8, EncodingType::Gzip)
                 }
             }?;
+
+            if let Some(snapshot) = db.load_snapshot(ex, tc, krate)? {
+                let snapshot_path = crate_to_path_fragment(tc, krate, SanitizationContext::Path)
+                    .join("snapshot.tar.gz");
+                let mime: Mime = "application/gzip".parse().unwrap();
+                // The snapshot is already compressed, and it's downloaded as is
+                dest.write_bytes(snapshot_path, snapshot, &mime, EncodingType::Plain)?;
+            }
         }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+string_enum!(pub enum ReportRemoval {
+    Delete => "delete",
+    Tombstone => "tombstone",
+});
+
+/// Remove the published report of a deleted experiment, leaving behind a page explaining the
+/// experiment was deleted if a tombstone is requested.
+pub fn remove<W: ReportWriter + Display>(
+    name: &str,
+    removal: ReportRemoval,
+    dest: &W,
+) -> Fallible<()> {
+    info!("removing the report of {} from {}", name, dest);
+    dest.clear()?;
+    if removal == ReportRemoval::Tombstone {
+        html::write_deleted_report(name, dest)?;
     }
     Ok(())
 }
 
-pub fn gen<DB: ReadResults, W: ReportWriter + Display>(
+/// What's kept in memory of a report once it's written. Only the crates shown in the summary of
+/// the report are kept, as the results of all the crates of big experiments take a lot of memory.
+pub struct ReportSummary {
+    /// Results of the report, containing only the crates shown in its summary.
+    pub results: TestResults,
+    /// Number of crate results in the full report.
+    pub total: usize,
+    /// Number of crates regressed and fixed for each target.
+    pub targets: Vec<TargetSummary>,
+}
+
+/// Crates of the report spooled to a temporary file while it's generated, read back a crate at a
+/// time when they're serialized.
+struct SpooledCrates(RefCell<BufReader<File>>);
+
+impl SpooledCrates {
+    fn new(spooled: BufWriter<File>) -> Fallible<Self> {
+        let mut file = spooled.into_inner()?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(SpooledCrates(RefCell::new(BufReader::new(file))))
+    }
+}
+
+impl Serialize for SpooledCrates {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut file = self.0.borrow_mut();
+        let mut seq = serializer.serialize_seq(None)?;
+        for krate in serde_json::Deserializer::from_reader(&mut *file).into_iter::<CrateResult>() {
+            seq.serialize_element(&krate.map_err(S::Error::custom)?)?;
+        }
+        seq.end()
+    }
+}
+
+/// Contents of the `results.json` file, matching the serialization of `TestResults` without
+/// keeping all the crates in memory.
+#[derive(Serialize)]
+struct ResultsJson<'a> {
+    crates: SpooledCrates,
+    toolchain_versions: &'a [Option<ToolchainVersion>; 2],
+    c_compilers: &'a [Vec<String>; 2],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generator: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    skipped_crates: &'a BTreeMap<String, u32>,
+}
+
+pub fn gen<DB: ReadResults + Sync, W: ReportWriter + Display + Sync>(
     db: &DB,
     ex: &Experiment,
     crates: &[Crate],
     dest: &W,
     config: &Config,
-) -> Fallible<TestResults> {
-    let res = generate_report(db, config, ex, crates)?;
-
+) -> Fallible<ReportSummary> {
     info!("writing results to {}", dest);
-    info!("writing metadata");
-    dest.write_string(
-        "results.json",
-        serde_json::to_string(&res)?.into(),
-        &mime::APPLICATION_JSON,
+    // The crates are spooled to disk a page at a time, as the JSON representation of a big
+    // experiment can take a lot of memory
+    let mut spooled = BufWriter::new(tempfile::tempfile()?);
+    let mut full_report = html::FullReport::new(ex);
+    let mut summary_crates = Vec::new();
+    let mut total = 0;
+    let mut targets = Vec::new();
+    let mut res = generate_report(db, config, ex, crates, |page| {
+        for krate in &page {
+            serde_json::to_writer(&mut spooled, krate)?;
+            total += 1;
+        }
+        full_report.add(&page)?;
+        TargetSummary::count(&mut targets, &page);
+        summary_crates.extend(page.into_iter().filter(|krate| krate.res.show_in_summary()));
+        Ok(())
+    })?;
+    let mut results_json = dest.stream("results.json", &mime::APPLICATION_JSON)?;
+    serde_json::to_writer(
+        &mut results_json,
+        &ResultsJson {
+            crates: SpooledCrates::new(spooled)?,
+            toolchain_versions: &res.toolchain_versions,
+            c_compilers: &res.c_compilers,
+            generator: res.generator.as_ref().map(|g| g.as_str()),
+            skipped_crates: &res.skipped_crates,
+        },
     )?;
+    results_json.finish()?;
+    res.crates = summary_crates;
+    let summary = ReportSummary {
+        results: res,
+        total,
+        targets,
+    };
+
+    info!("writing metadata");
     dest.write_string(
         "config.json",
         serde_json::to_string(&ex)?.into(),
         &mime::APPLICATION_JSON,
     )?;
 
+    if ex.timings {
+        info!("writing timings");
+        timings::write_timings(db, ex, crates, dest)?;
+    }
+
     info!("writing archives");
     let available_archives = archives::write_logs_archives(db, ex, crates, dest, config)?;
     info!("writing html files");
-    html::write_html_report(ex, crates.len(), &res, available_archives, dest)?;
+    html::write_html_report(
+        ex,
+        crates.len(),
+        &summary,
+        full_report,
+        available_archives,
+        dest,
+    )?;
     info!("writing logs");
     write_logs(db, ex, crates, dest, config)?;
+    info!("writing reproduction scripts");
+    repro::write_reproductions(db, ex, crates, dest)?;
 
-    Ok(res)
+    Ok(summary)
 }
 
 fn crate_to_name(c: &Crate, shas: &HashMap<GitHubRepo, String>) -> Fallible<String> {
-    Ok(match *c {
-        Crate::Registry(ref details) => format!("{}-{}", details.name, details.version),
-        Crate::GitHub(ref repo) => {
-            if let Some(sha) = shas.get(repo) {
-                format!("{}.{}.{}", repo.org, repo.name, sha)
-            } else {
-                format!("{}.{}", repo.org, repo.name)
-            }
-        }
-        Crate::Local(ref name) => format!("{} (local)", name),
-    })
+    Ok(c.source().report_name(c.commit(shas)))
 }
 
 fn crate_to_url(c: &Crate, shas: &HashMap<GitHubRepo, String>) -> Fallible<String> {
-    Ok(match *c {
-        Crate::Registry(ref details) => format!(
-            "https://crates.io/crates/{}/{}",
-            details.name, details.version
-        ),
-        Crate::GitHub(ref repo) => {
-            if let Some(sha) = shas.get(repo) {
-                format!("https://github.com/{}/{}/tree/{}", repo.org, repo.name, sha)
-            } else {
-                format!("https://github.com/{}/{}", repo.org, repo.name)
-            }
-        }
-        Crate::Local(ref name) => format!(
-            "{}/tree/master/local-crates/{}",
-            crate::CRATER_REPO_URL,
-            name
-        ),
-    })
+    Ok(c.source().url(c.commit(shas)))
 }
 
-fn compare(
+pub(crate) fn compare(
     config: &Config,
     krate: &Crate,
     r1: Option<TestResult>,
     r2: Option<TestResult>,
 ) -> Comparison {
-    use crate::results::TestResult::*;
-
     match (r1, r2) {
-        (Some(res1), Some(res2)) => match (res1, res2) {
-            (BuildFail(_), BuildFail(_)) => Comparison::SameBuildFail,
-            (TestFail(_), TestFail(_)) => Comparison::SameTestFail,
-            (TestSkipped, TestSkipped) => Comparison::SameTestSkipped,
-            (TestPass, TestPass) => Comparison::SameTestPass,
-
-            (BuildFail(reason1), TestFail(reason2))
-                if reason1.is_spurious() || reason2.is_spurious() =>
-            {
-                Comparison::SpuriousFixed
-            }
-            (BuildFail(reason), TestSkipped)
-            | (BuildFail(reason), TestPass)
-            | (TestFail(reason), TestPass)
-                if reason.is_spurious() =>
-            {
-                Comparison::SpuriousFixed
-            }
-            (BuildFail(_), TestFail(_))
-            | (BuildFail(_), TestSkipped)
-            | (BuildFail(_), TestPass)
-            | (TestFail(_), TestPass) => Comparison::Fixed,
-
-            (TestFail(reason1), BuildFail(reason2))
-                if reason1.is_spurious() || reason2.is_spurious() =>
-            {
-                Comparison::SpuriousRegressed
-            }
-            (TestPass, TestFail(reason))
-            | (TestPass, BuildFail(reason))
-            | (TestSkipped, BuildFail(reason))
-            | (TestFail(_), BuildFail(reason))
-                if reason.is_spurious() =>
-            {
-                Comparison::SpuriousRegressed
-            }
-            (TestPass, TestFail(_))
-            | (TestPass, BuildFail(_))
-            | (TestSkipped, BuildFail(_))
-            | (TestFail(_), BuildFail(_)) => Comparison::Regressed,
-
-            (Error, _) | (_, Error) => Comparison::Error,
-            (BrokenCrate(_), _) | (_, BrokenCrate(_)) => Comparison::Broken,
-            (TestFail(_), TestSkipped)
-            | (TestPass, TestSkipped)
-            | (TestSkipped, TestFail(_))
-            | (TestSkipped, TestPass) => {
-                panic!("can't compare {} and {}", res1, res2);
-            }
-        },
+        (Some(res1), Some(res2)) => crater_results::report::compare(res1, res2),
         _ if config.should_skip(krate) => Comparison::Skipped,
         _ => Comparison::Unknown,
     }
@@ -377,18 +612,41 @@ pub trait ReportWriter {
     ) -> Fallible<()>;
     fn write_string<P: AsRef<Path>>(&self, path: P, s: Cow<str>, mime: &Mime) -> Fallible<()>;
     fn copy<P: AsRef<Path>, R: Read>(&self, r: &mut R, path: P, mime: &Mime) -> Fallible<()>;
+    /// Start writing a file whose content is produced incrementally, without having to keep it
+    /// all in memory. The file is only guaranteed to be written after the stream is finished.
+    fn stream<P: AsRef<Path>>(&self, path: P, mime: &Mime) -> Fallible<Box<dyn ReportStream + '_>>;
+    /// Remove all the files previously written in the destination.
+    fn clear(&self) -> Fallible<()>;
+}
+
+/// File being written by a `ReportWriter` while it's being generated.
+pub trait ReportStream: Write {
+    fn finish(self: Box<Self>) -> Fallible<()>;
 }
 
-pub struct FileWriter(PathBuf);
+/// File marking the directories containing a report written by `FileWriter`, which are the only
+/// ones it's allowed to clear.
+const REPORT_MARKER: &str = ".crater-report";
+
+pub struct FileWriter {
+    dest: PathBuf,
+    marked: AtomicBool,
+}
 
 impl FileWriter {
     pub fn create(dest: PathBuf) -> Fallible<FileWriter> {
         fs::create_dir_all(&dest)?;
-        Ok(FileWriter(dest))
+        Ok(FileWriter {
+            dest,
+            marked: AtomicBool::new(false),
+        })
     }
     fn create_prefix(&self, path: &Path) -> Fallible<()> {
+        if !self.marked.swap(true, Ordering::SeqCst) {
+            fs::write(self.dest.join(REPORT_MARKER), b"")?;
+        }
         if let Some(parent) = path.parent() {
-            fs::create_dir_all(self.0.join(parent))?;
+            fs::create_dir_all(self.dest.join(parent))?;
         }
         Ok(())
     }
@@ -403,40 +661,73 @@ impl ReportWriter for FileWriter {
         _: EncodingType,
     ) -> Fallible<()> {
         self.create_prefix(path.as_ref())?;
-        fs::write(&self.0.join(path.as_ref()), &b)?;
+        fs::write(&self.dest.join(path.as_ref()), &b)?;
         Ok(())
     }
 
     fn write_string<P: AsRef<Path>>(&self, path: P, s: Cow<str>, _: &Mime) -> Fallible<()> {
         self.create_prefix(path.as_ref())?;
-        fs::write(&self.0.join(path.as_ref()), s.as_ref().as_bytes())?;
+        fs::write(&self.dest.join(path.as_ref()), s.as_ref().as_bytes())?;
         Ok(())
     }
 
     fn copy<P: AsRef<Path>, R: Read>(&self, r: &mut R, path: P, _: &Mime) -> Fallible<()> {
         self.create_prefix(path.as_ref())?;
-        io::copy(r, &mut File::create(self.0.join(path.as_ref()))?)?;
+        io::copy(r, &mut File::create(self.dest.join(path.as_ref()))?)?;
+        Ok(())
+    }
+
+    fn stream<P: AsRef<Path>>(&self, path: P, _: &Mime) -> Fallible<Box<dyn ReportStream + '_>> {
+        self.create_prefix(path.as_ref())?;
+        Ok(Box::new(BufWriter::new(File::create(
+            self.dest.join(path.as_ref()),
+        )?)))
+    }
+
+    fn clear(&self) -> Fallible<()> {
+        // The destination is picked by the user, and could contain anything: it's only removed if
+        // a report was written in it before
+        if self.dest.exists() {
+            let empty = fs::read_dir(&self.dest)?.next().is_none();
+            if !empty && !self.dest.join(REPORT_MARKER).is_file() {
+                bail!(
+                    "refusing to clear {}, as it doesn't contain a report written by crater",
+                    self.dest.display()
+                );
+            }
+            fs::remove_dir_all(&self.dest)?;
+        }
+        fs::create_dir_all(&self.dest)?;
+        self.marked.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl ReportStream for BufWriter<File> {
+    fn finish(mut self: Box<Self>) -> Fallible<()> {
+        self.flush()?;
         Ok(())
     }
 }
 
 impl Display for FileWriter {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.0.display().fmt(f)
+        self.dest.display().fmt(f)
     }
 }
 
 #[cfg(test)]
 #[derive(Default)]
 pub struct DummyWriter {
-    results: RefCell<HashMap<(PathBuf, Mime), Vec<u8>>>,
+    results: Mutex<HashMap<(PathBuf, Mime), Vec<u8>>>,
 }
 
 #[cfg(test)]
 impl DummyWriter {
     pub fn get<P: AsRef<Path>>(&self, path: P, mime: &Mime) -> Vec<u8> {
         self.results
-            .borrow()
+            .lock()
+            .unwrap()
             .get(&(path.as_ref().to_path_buf(), mime.clone()))
             .unwrap()
             .clone()
@@ -453,13 +744,14 @@ impl ReportWriter for DummyWriter {
         _: EncodingType,
     ) -> Fallible<()> {
         self.results
-            .borrow_mut()
+            .lock()
+            .unwrap()
             .insert((path.as_ref().to_path_buf(), mime.clone()), b);
         Ok(())
     }
 
     fn write_string<P: AsRef<Path>>(&self, path: P, s: Cow<str>, mime: &Mime) -> Fallible<()> {
-        self.results.borrow_mut().insert(
+        self.results.lock().unwrap().insert(
             (path.as_ref().to_path_buf(), mime.clone()),
             s.bytes().collect(),
         );
@@ -471,10 +763,54 @@ impl ReportWriter for DummyWriter {
         r.read_to_end(&mut buffer)?;
 
         self.results
-            .borrow_mut()
+            .lock()
+            .unwrap()
             .insert((path.as_ref().to_path_buf(), mime.clone()), buffer);
         Ok(())
     }
+
+    fn stream<P: AsRef<Path>>(&self, path: P, mime: &Mime) -> Fallible<Box<dyn ReportStream + '_>> {
+        Ok(Box::new(DummyStream {
+            writer: self,
+            path: path.as_ref().to_path_buf(),
+            mime: mime.clone(),
+            buffer: Vec::new(),
+        }))
+    }
+
+    fn clear(&self) -> Fallible<()> {
+        self.results.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+struct DummyStream<'a> {
+    writer: &'a DummyWriter,
+    path: PathBuf,
+    mime: Mime,
+    buffer: Vec<u8>,
+}
+
+#[cfg(test)]
+impl Write for DummyStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl ReportStream for DummyStream<'_> {
+    fn finish(self: Box<Self>) -> Fallible<()> {
+        let stream = *self;
+        stream
+            .writer
+            .write_bytes(stream.path, stream.buffer, &stream.mime, EncodingType::Plain)
+    }
 }
 
 #[cfg(test)]
@@ -487,11 +823,19 @@ impl Display for DummyWriter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
     use crate::config::{Config, CrateConfig};
     use crate::crates::{Crate, GitHubRepo, RegistryCrate};
-    use crate::experiments::{CapLints, Experiment, Mode, Status};
-    use crate::results::{BrokenReason, DummyDB, FailureReason, TestResult};
-    use crate::toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use crate::db::Database;
+    use crate::experiments::{
+        BuildTargets, CapLints, CrateSkipReason, Experiment, Mode, Status, Tags, Targets,
+        TestFlags, DEFAULT_NAMESPACE,
+    };
+    use crate::results::{
+        BrokenReason, DatabaseDB, Diagnostic, DiagnosticCounts, DiagnosticLevel, DummyDB,
+        FailedTarget, FailureReason, Invocation, MemberResult, SkipReason, TargetKind, TestResult,
+    };
+    use crate::toolchain::{ToolchainVersion, MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
     use std::collections::HashMap;
 
     #[test]
@@ -577,6 +921,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_remove() {
+        let writer = DummyWriter::default();
+        writer
+            .write_string("index.html", "report".into(), &mime::TEXT_HTML)
+            .unwrap();
+        writer
+            .write_string(
+                "regressed/foo/log.txt",
+                "log".into(),
+                &mime::TEXT_PLAIN_UTF_8,
+            )
+            .unwrap();
+
+        // The tombstone replaces all the pages of the report
+        remove("foo", ReportRemoval::Tombstone, &writer).unwrap();
+        let index = String::from_utf8(writer.get("index.html", &mime::TEXT_HTML)).unwrap();
+        assert!(index.contains("This experiment was deleted"));
+        assert_eq!(writer.results.lock().unwrap().len(), 3);
+
+        remove("foo", ReportRemoval::Delete, &writer).unwrap();
+        assert!(writer.results.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parallel_map() {
+        let items = (0..1000).collect::<Vec<usize>>();
+        let doubled = parallel_map(&items, |i| Ok(i * 2)).unwrap();
+        assert_eq!(doubled, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+
+        assert!(parallel_map(&items, |&i| if i == 500 {
+            bail!("failed")
+        } else {
+            Ok(i)
+        })
+        .is_err());
+
+        assert!(parallel_map(&[] as &[usize], |&i| Ok(i)).unwrap().is_empty());
+    }
+
     #[test]
     fn test_compare() {
         use crate::results::{FailureReason::*, TestResult::*};
@@ -654,6 +1038,11 @@ mod tests {
                 TestSkipped, BrokenCrate(BrokenReason::Unknown) => Broken;
                 TestFail(Unknown), BrokenCrate(BrokenReason::Unknown) => Broken;
                 BuildFail(Unknown), BrokenCrate(BrokenReason::Unknown) => Broken;
+
+                // Skipped
+                Skipped(SkipReason::IncompatibleTarget), TestSkipped => Skipped;
+                Skipped(SkipReason::Unknown), TestPass => Skipped;
+                BuildFail(Unknown), Skipped(SkipReason::Unknown) => Skipped;
             ]
         );
 
@@ -666,6 +1055,7 @@ mod tests {
                 skip_tests: false,
                 quiet: false,
                 broken: false,
+                known_broken: None,
             },
         );
         assert_eq!(compare(&config, &reg, None, None), Comparison::Skipped);
@@ -683,7 +1073,8 @@ mod tests {
 
         let ex = Experiment {
             name: "foo".to_string(),
-            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            toolchains: vec![MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
             mode: Mode::BuildAndTest,
             cap_lints: CapLints::Forbid,
             priority: 0,
@@ -696,6 +1087,19 @@ mod tests {
             report_url: None,
             ignore_blacklist: false,
             requirement: None,
+            pinned: false,
+            edition: None,
+            max_rust_version: None,
+            build_targets: BuildTargets::Default,
+            test_flags: TestFlags::default(),
+            dist_server: None,
+            rustc_wrapper: None,
+            timings: false,
+            targets: Targets::default(),
+            tags: Tags::default(),
+            index_snapshot: None,
+            crates_seed: None,
+            crate_select: None,
         };
 
         let mut db = DummyDB::default();
@@ -724,9 +1128,140 @@ mod tests {
             TEST_TOOLCHAIN.clone(),
             EncodedLog::Plain(b"beta log".to_vec()),
         );
+        // The build of the workspace failed with beta, interrupting the build of the other
+        // members, including one recorded before the reason of those interruptions was stored
+        for &(tc, results) in &[
+            (
+                &MAIN_TOOLCHAIN,
+                [
+                    TestResult::TestPass,
+                    TestResult::TestPass,
+                    TestResult::TestPass,
+                ],
+            ),
+            (
+                &TEST_TOOLCHAIN,
+                [
+                    TestResult::Skipped(SkipReason::WorkspaceBuildFailed),
+                    TestResult::TestSkipped,
+                    TestResult::BuildFail(FailureReason::Unknown),
+                ],
+            ),
+        ] {
+            db.add_dummy_members(
+                &ex,
+                gh.clone(),
+                tc.clone(),
+                ["hello", "hello-cli", "hello-macros"]
+                    .iter()
+                    .zip(results.iter())
+                    .map(|(name, &result)| MemberResult {
+                        name: name.to_string(),
+                        result,
+                    })
+                    .collect(),
+            );
+        }
+        db.add_dummy_failed_targets(
+            &ex,
+            gh.clone(),
+            TEST_TOOLCHAIN.clone(),
+            vec![FailedTarget {
+                package: "hello-macros".into(),
+                kind: TargetKind::Lib,
+                name: "hello_macros".into(),
+            }],
+        );
+        let diagnostic = |level, code: &str| Diagnostic {
+            package: "hello-macros".into(),
+            target_kind: Some(TargetKind::Lib),
+            target_name: Some("hello_macros".into()),
+            level,
+            code: Some(code.into()),
+            message: "something went wrong".into(),
+            file: None,
+            line: None,
+            column: None,
+        };
+        db.add_dummy_diagnostics(
+            &ex,
+            gh.clone(),
+            TEST_TOOLCHAIN.clone(),
+            vec![
+                diagnostic(DiagnosticLevel::Warning, "unused_imports"),
+                diagnostic(DiagnosticLevel::Error, "E0308"),
+            ],
+        );
+        for &(tc, warnings) in &[(&MAIN_TOOLCHAIN, 3), (&TEST_TOOLCHAIN, 5)] {
+            db.add_dummy_diagnostic_counts(
+                &ex,
+                gh.clone(),
+                tc.clone(),
+                DiagnosticCounts {
+                    errors: 0,
+                    warnings,
+                },
+            );
+        }
+        db.add_dummy_invocations(
+            &ex,
+            gh.clone(),
+            TEST_TOOLCHAIN.clone(),
+            vec![Invocation {
+                toolchain: "beta".into(),
+                cargo_toolchain: None,
+                args: vec!["build".into(), "--frozen".into()],
+                env: vec![("RUSTFLAGS".into(), "--cap-lints=forbid".into())],
+                memory_limit: None,
+                cpu_limit: None,
+                cpuset: None,
+            }],
+        );
+        db.add_dummy_snapshot(
+            &ex,
+            gh.clone(),
+            TEST_TOOLCHAIN.clone(),
+            b"beta snapshot".to_vec(),
+        );
+        db.add_dummy_c_compiler(
+            &ex,
+            gh.clone(),
+            TEST_TOOLCHAIN.clone(),
+            "clang version 10.0.0".to_string(),
+        );
+        db.add_dummy_toolchain_version(
+            &ex,
+            MAIN_TOOLCHAIN.clone(),
+            ToolchainVersion {
+                release: "1.40.0".into(),
+                commit_hash: None,
+                commit_date: None,
+                host: None,
+                llvm_version: None,
+            },
+        );
+
+        let yanked = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "0.1.0".into(),
+        });
+        db.add_dummy_skip_reason(&ex, yanked.clone(), CrateSkipReason::Yanked);
 
         let writer = DummyWriter::default();
-        gen(&db, &ex, &[gh], &writer, &config).unwrap();
+        let summary = gen(&db, &ex, &[gh, yanked], &writer, &config).unwrap();
+
+        // Only the crates shown in the summary are kept in memory
+        assert_eq!(summary.total, 2);
+        assert_eq!(summary.results.crates.len(), 1);
+        assert_eq!(summary.results.crates[0].res, Comparison::Regressed);
+
+        // All the crates are listed in the full report
+        let index = String::from_utf8(writer.get("index.html", &mime::TEXT_HTML)).unwrap();
+        let full = String::from_utf8(writer.get("full.html", &mime::TEXT_HTML)).unwrap();
+        assert!(index.contains("brson.hello-rs.f00"));
+        assert!(!index.contains("lazy_static-0.1.0"));
+        assert!(full.contains("brson.hello-rs.f00"));
+        assert!(full.contains("lazy_static-0.1.0"));
 
         assert_eq!(
             writer.get("config.json", &mime::APPLICATION_JSON),
@@ -745,7 +1280,21 @@ mod tests {
         let result: TestResults =
             serde_json::from_slice(&writer.get("results.json", &mime::APPLICATION_JSON)).unwrap();
 
-        assert_eq!(result.crates.len(), 1);
+        assert_eq!(
+            result.toolchain_versions[0].as_ref().unwrap().release,
+            "1.40.0"
+        );
+        assert!(result.toolchain_versions[1].is_none());
+        assert!(result.c_compilers[0].is_empty());
+        assert_eq!(result.c_compilers[1], vec!["clang version 10.0.0"]);
+
+        // Crates skipped when the experiment was created are counted by reason
+        assert_eq!(result.crates.len(), 2);
+        assert_eq!(result.crates[1].name.as_str(), "lazy_static-0.1.0");
+        assert_eq!(result.crates[1].res, Comparison::Skipped);
+        assert_eq!(result.skipped_crates.len(), 1);
+        assert_eq!(result.skipped_crates["yanked"], 1);
+
         let crate_result = &result.crates[0];
 
         assert_eq!(crate_result.name.as_str(), "brson.hello-rs.f00");
@@ -770,5 +1319,242 @@ mod tests {
             (&crate_result.runs[1]).as_ref().unwrap().log.as_str(),
             "beta/gh/brson.hello-rs"
         );
+        assert!(!(&crate_result.runs[0]).as_ref().unwrap().reproduce);
+        assert!((&crate_result.runs[1]).as_ref().unwrap().reproduce);
+        let script = writer.get("beta/gh/brson.hello-rs/reproduce.sh", &mime::TEXT_PLAIN_UTF_8);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("git checkout f00"));
+        assert!(script.contains("RUSTFLAGS=--cap-lints=forbid cargo +beta build --frozen"));
+        assert!(!(&crate_result.runs[0]).as_ref().unwrap().snapshot);
+        assert!((&crate_result.runs[1]).as_ref().unwrap().snapshot);
+        let gzip: Mime = "application/gzip".parse().unwrap();
+        assert_eq!(
+            &writer.get("beta/gh/brson.hello-rs/snapshot.tar.gz", &gzip),
+            b"beta snapshot"
+        );
+        assert!((&crate_result.runs[0])
+            .as_ref()
+            .unwrap()
+            .failed_targets
+            .is_empty());
+        assert_eq!(
+            (&crate_result.runs[1]).as_ref().unwrap().failed_targets,
+            vec![FailedTarget {
+                package: "hello-macros".into(),
+                kind: TargetKind::Lib,
+                name: "hello_macros".into(),
+            }]
+        );
+        assert!((&crate_result.runs[0])
+            .as_ref()
+            .unwrap()
+            .primary_error
+            .is_none());
+        let primary_error = (&crate_result.runs[1])
+            .as_ref()
+            .unwrap()
+            .primary_error
+            .clone()
+            .unwrap();
+        assert_eq!(primary_error.package, "hello-macros");
+        assert_eq!(
+            primary_error.code.as_ref().map(|s| s.as_str()),
+            Some("E0308")
+        );
+
+        assert_eq!(
+            crate_result.diagnostics_delta,
+            Some(DiagnosticsDelta {
+                errors: 0,
+                warnings: 2,
+            })
+        );
+
+        // The regression is attributed to the right member of the workspace
+        let members = crate_result
+            .members
+            .iter()
+            .map(|m| (m.name.as_str(), m.res))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            members,
+            vec![
+                ("hello", Comparison::Skipped),
+                ("hello-cli", Comparison::Skipped),
+                ("hello-macros", Comparison::Regressed),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_logs_changed() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let krate = ex.get_crates(&db).unwrap().remove(0);
+        let results = DatabaseDB::new(&db);
+        let store = |tc: &Toolchain, log: &str| {
+            results
+                .store_result(
+                    &ex,
+                    &krate,
+                    tc,
+                    TestResult::BuildFail(FailureReason::Unknown),
+                    log.as_bytes(),
+                    EncodingType::Plain,
+                )
+                .unwrap();
+        };
+        let changed = || logs_changed(&results, &config, &ex, &ex.toolchains, &krate).unwrap();
+
+        // Only the parts changing between runs of the same build are different
+        store(
+            &MAIN_TOOLCHAIN,
+            "[2019-01-01T10:00:00Z] cargo +stable build\nerror[E0308]: mismatched types\n\
+             error: could not compile `foo` in 1.23s",
+        );
+        store(
+            &TEST_TOOLCHAIN,
+            "[2019-01-02T11:00:00Z] cargo +beta build\nerror[E0308]: mismatched types\n\
+             error: could not compile `foo` in 4.56s",
+        );
+        assert!(!changed());
+
+        store(
+            &TEST_TOOLCHAIN,
+            "[2019-01-02T11:00:00Z] cargo +beta build\nerror[E0599]: no method named `bar` found\n\
+             error: could not compile `foo` in 4.56s",
+        );
+        assert!(changed());
+    }
+
+    #[test]
+    fn test_first_bad() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment {
+            toolchains: vec![
+                MAIN_TOOLCHAIN.clone(),
+                "nightly-2019-01-01".parse().unwrap(),
+                "nightly-2019-01-02".parse().unwrap(),
+                TEST_TOOLCHAIN.clone(),
+            ],
+            ..CreateExperiment::dummy("dummy")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let krate = ex.get_crates(&db).unwrap().remove(0);
+        let results = DatabaseDB::new(&db);
+        let ordered = ex.ordered_toolchains().remove(0).1;
+        assert_eq!(ordered, ex.toolchains);
+        assert_eq!(
+            ex.target_toolchains()[0].1,
+            [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()]
+        );
+
+        let store = |tc: &Toolchain, res: TestResult| {
+            results
+                .store_result(&ex, &krate, tc, res, b"", EncodingType::Plain)
+                .unwrap();
+        };
+        let fail = TestResult::BuildFail(FailureReason::Unknown);
+        store(&ordered[0], TestResult::TestPass);
+        store(&ordered[1], TestResult::TestPass);
+        store(&ordered[2], fail);
+        store(&ordered[3], fail);
+        assert_eq!(
+            first_bad(&results, &config, &ex, &ordered, &krate).unwrap(),
+            Some("nightly-2019-01-02".to_string())
+        );
+
+        // Crates already broken with the start toolchain never regressed
+        store(&ordered[0], fail);
+        assert!(first_bad(&results, &config, &ex, &ordered, &krate)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_docs_delta() {
+        use crate::results::{DocItem, WriteResults};
+
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment {
+            mode: Mode::RustdocJson,
+            ..CreateExperiment::dummy("dummy")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        let krate = ex.get_crates(&db).unwrap().remove(0);
+        let results = DatabaseDB::new(&db);
+        let item = |path: &str, hash: &str| DocItem {
+            path: path.into(),
+            kind: "function".into(),
+            hash: hash.into(),
+        };
+        let store = |tc: &Toolchain, items: &[DocItem]| {
+            results
+                .store_result(
+                    &ex,
+                    &krate,
+                    tc,
+                    TestResult::TestPass,
+                    b"",
+                    EncodingType::Plain,
+                )
+                .unwrap();
+            results.record_doc_items(&ex, tc, &krate, items).unwrap();
+        };
+        store(&MAIN_TOOLCHAIN, &[item("foo::a", "1"), item("foo::b", "2")]);
+        store(&TEST_TOOLCHAIN, &[item("foo::a", "1"), item("foo::b", "3")]);
+
+        let mut crates = Vec::new();
+        generate_report(&results, &config, &ex, &[krate.clone()], |page| {
+            crates.extend(page);
+            Ok(())
+        })
+        .unwrap();
+        let docs_delta = crates[0].docs_delta.as_ref().unwrap();
+        assert!(docs_delta.added.is_empty());
+        assert!(docs_delta.removed.is_empty());
+        assert_eq!(docs_delta.changed, vec!["foo::b".to_string()]);
+    }
+
+    #[test]
+    fn test_file_writer_clear() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Directories not containing a report are left alone
+        let user_file = dir.path().join("notes.txt");
+        fs::write(&user_file, "important").unwrap();
+        let writer = FileWriter::create(dir.path().into()).unwrap();
+        assert!(writer.clear().is_err());
+        assert!(user_file.is_file());
+
+        // Reports are removed along with everything else in their directory
+        let dest = dir.path().join("report");
+        let writer = FileWriter::create(dest.clone()).unwrap();
+        writer.clear().unwrap();
+        writer
+            .write_string("logs/foo/log.txt", "log".into(), &mime::TEXT_PLAIN_UTF_8)
+            .unwrap();
+        fs::write(dest.join("extra.txt"), "extra").unwrap();
+        let writer = FileWriter::create(dest.clone()).unwrap();
+        writer.clear().unwrap();
+        assert!(fs::read_dir(&dest).unwrap().next().is_none());
+        assert!(user_file.is_file());
     }
 }
@@ -0,0 +1,302 @@
+use crate::crates::{Crate, GitHubRepo};
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::report::{crate_to_path_fragment, parallel_map, ReportWriter, SanitizationContext};
+use crate::results::{Invocation, ReadResults};
+use crate::toolchain::Toolchain;
+use crate::utils::string::shell_quote;
+use crater_results::report::Comparison;
+use mime;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Image the crates are built in, unless the agent is configured to use a different one.
+const SANDBOX_IMAGE: &str = "rustops/crates-build-env";
+
+lazy_static! {
+    /// Names rustup uses for the toolchains built by rust-lang CI, installed by Crater with
+    /// `rustup-toolchain-install-master`.
+    static ref CI_TOOLCHAIN: Regex = Regex::new(r"^([0-9a-f]{40})(-alt)?$").unwrap();
+}
+
+/// Only the builds that regressed get a reproduction script, using the invocations recorded by
+/// the runner with the toolchain being tested.
+pub(super) fn is_reproducible(comparison: Comparison, invocations: &[Invocation]) -> bool {
+    comparison == Comparison::Regressed && !invocations.is_empty()
+}
+
+fn install_commands(invocation: &Invocation) -> Vec<String> {
+    let target = invocation
+        .env
+        .iter()
+        .find(|(name, _)| name == "CARGO_BUILD_TARGET")
+        .map(|(_, target)| format!(" --target {}", shell_quote(target)))
+        .unwrap_or_default();
+
+    let mut commands = Vec::new();
+    if let Some(captures) = CI_TOOLCHAIN.captures(&invocation.toolchain) {
+        let alt = if captures.get(2).is_some() {
+            " --alt"
+        } else {
+            ""
+        };
+        commands.push("rustup toolchain install stable --profile minimal".to_string());
+        commands.push("cargo +stable install rustup-toolchain-install-master".to_string());
+        commands.push(format!(
+            "rustup-toolchain-install-master{} {}",
+            alt, &captures[1]
+        ));
+        if !target.is_empty() {
+            commands.push(format!(
+                "rustup target add --toolchain {}{}",
+                shell_quote(&invocation.toolchain),
+                target
+            ));
+        }
+    } else {
+        commands.push(format!(
+            "rustup toolchain install {} --profile minimal{}",
+            shell_quote(&invocation.toolchain),
+            target
+        ));
+    }
+    if let Some(ref cargo) = invocation.cargo_toolchain {
+        commands.push(format!(
+            "rustup toolchain install {} --profile minimal",
+            shell_quote(cargo)
+        ));
+    }
+    commands
+}
+
+fn cargo_command(invocation: &Invocation) -> String {
+    let mut command = String::new();
+    for (name, value) in &invocation.env {
+        command.push_str(&format!("{}={} ", name, shell_quote(value)));
+    }
+    // Cargo from another toolchain has to be pointed to the compiler being tested
+    let cargo = if let Some(ref cargo) = invocation.cargo_toolchain {
+        for tool in &["rustc", "rustdoc"] {
+            command.push_str(&format!(
+                "{}=\"$(rustup which --toolchain {} {})\" ",
+                tool.to_uppercase(),
+                shell_quote(&invocation.toolchain),
+                tool
+            ));
+        }
+        cargo
+    } else {
+        &invocation.toolchain
+    };
+    command.push_str(&format!("cargo +{}", shell_quote(cargo)));
+    for arg in &invocation.args {
+        command.push(' ');
+        command.push_str(&shell_quote(arg));
+    }
+    command
+}
+
+fn docker_run_flags(invocation: &Invocation) -> String {
+    let mut flags = String::new();
+    if let Some(memory) = invocation.memory_limit {
+        flags.push_str(&format!(" --memory {}", memory));
+    }
+    if let Some(cpus) = invocation.cpu_limit {
+        flags.push_str(&format!(" --cpus {}", cpus));
+    }
+    flags
+}
+
+fn render_script(
+    ex: &Experiment,
+    krate: &Crate,
+    shas: &HashMap<GitHubRepo, String>,
+    invocations: &[Invocation],
+) -> Fallible<String> {
+    let first = &invocations[0];
+    let mut script = String::new();
+    writeln!(script, "#!/usr/bin/env bash")?;
+    writeln!(
+        script,
+        "# Reproduce the build of {} in the Crater experiment {}.",
+        krate, ex.name
+    )?;
+    writeln!(script, "#")?;
+    writeln!(
+        script,
+        "# Run this script directly, or in the sandbox Crater uses with the Dockerfile next to it:"
+    )?;
+    writeln!(script, "#")?;
+    writeln!(script, "#     docker build -t crater-reproduce .")?;
+    writeln!(
+        script,
+        "#     docker run --rm{} crater-reproduce",
+        docker_run_flags(first)
+    )?;
+    writeln!(script, "#")?;
+    writeln!(
+        script,
+        "# Crater builds the crate without network access, after fetching its dependencies."
+    )?;
+    writeln!(script, "set -euo pipefail")?;
+    writeln!(script)?;
+
+    writeln!(script, "if ! command -v rustup >/dev/null; then")?;
+    writeln!(
+        script,
+        "    curl -sSf https://sh.rustup.rs | sh -s -- -y --profile minimal --default-toolchain none"
+    )?;
+    writeln!(script, "    source \"$HOME/.cargo/env\"")?;
+    writeln!(script, "fi")?;
+    for command in install_commands(first) {
+        writeln!(script, "{}", command)?;
+    }
+    writeln!(script)?;
+
+    writeln!(script, "cd \"$(mktemp -d)\"")?;
+    for command in krate.source().fetch_commands(krate.commit(shas)) {
+        writeln!(script, "{}", command)?;
+    }
+    let cargo = shell_quote(first.cargo_toolchain.as_ref().unwrap_or(&first.toolchain));
+    writeln!(
+        script,
+        "[ -f Cargo.lock ] || cargo +{} generate-lockfile",
+        cargo
+    )?;
+    writeln!(script, "cargo +{} fetch", cargo)?;
+    writeln!(script)?;
+
+    for invocation in invocations {
+        writeln!(script, "{}", cargo_command(invocation))?;
+    }
+    Ok(script)
+}
+
+fn render_dockerfile(ex: &Experiment, krate: &Crate) -> Fallible<String> {
+    let mut dockerfile = String::new();
+    writeln!(
+        dockerfile,
+        "# Reproduce the build of {} in the Crater experiment {}, in the default image of the",
+        krate, ex.name
+    )?;
+    writeln!(dockerfile, "# build sandbox.")?;
+    writeln!(dockerfile, "FROM {}", SANDBOX_IMAGE)?;
+    writeln!(dockerfile, "COPY reproduce.sh /reproduce.sh")?;
+    writeln!(dockerfile, "CMD [\"bash\", \"/reproduce.sh\"]")?;
+    Ok(dockerfile)
+}
+
+fn write_reproduction<W: ReportWriter>(
+    ex: &Experiment,
+    toolchain: &Toolchain,
+    krate: &Crate,
+    shas: &HashMap<GitHubRepo, String>,
+    invocations: &[Invocation],
+    dest: &W,
+) -> Fallible<()> {
+    let dir = crate_to_path_fragment(toolchain, krate, SanitizationContext::Path);
+    dest.write_string(
+        dir.join("reproduce.sh"),
+        render_script(ex, krate, shas, invocations)?.into(),
+        &mime::TEXT_PLAIN_UTF_8,
+    )?;
+    dest.write_string(
+        dir.join("Dockerfile"),
+        render_dockerfile(ex, krate)?.into(),
+        &mime::TEXT_PLAIN_UTF_8,
+    )?;
+    Ok(())
+}
+
+pub(super) fn write_reproductions<DB: ReadResults + Sync, W: ReportWriter + Sync>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+    dest: &W,
+) -> Fallible<()> {
+    let shas = db.load_all_shas(ex)?;
+    let target_toolchains = ex.target_toolchains();
+    parallel_map(crates, |krate| -> Fallible<()> {
+        for (_, toolchains) in &target_toolchains {
+            let res1 = db.load_test_result(ex, &toolchains[0], krate)?;
+            let res2 = db.load_test_result(ex, &toolchains[1], krate)?;
+            let comparison = match (res1, res2) {
+                (Some(res1), Some(res2)) => crater_results::report::compare(res1, res2),
+                _ => continue,
+            };
+
+            let invocations = db.load_invocations(ex, &toolchains[1], krate)?;
+            if is_reproducible(comparison, &invocations) {
+                write_reproduction(ex, &toolchains[1], krate, &shas, &invocations, dest)?;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cargo_command, install_commands};
+    use crate::results::Invocation;
+
+    fn invocation(toolchain: &str, cargo_toolchain: Option<&str>) -> Invocation {
+        Invocation {
+            toolchain: toolchain.into(),
+            cargo_toolchain: cargo_toolchain.map(|name| name.into()),
+            args: vec!["test".into(), "--frozen".into()],
+            env: vec![
+                (
+                    "RUSTFLAGS".into(),
+                    "--cap-lints=forbid -C opt-level=3".into(),
+                ),
+                ("CARGO_BUILD_TARGET".into(), "wasm32-unknown-unknown".into()),
+            ],
+            memory_limit: Some(1024),
+            cpu_limit: None,
+            cpuset: None,
+        }
+    }
+
+    #[test]
+    fn test_cargo_command() {
+        assert_eq!(
+            cargo_command(&invocation("beta", None)),
+            "RUSTFLAGS='--cap-lints=forbid -C opt-level=3' \
+             CARGO_BUILD_TARGET=wasm32-unknown-unknown cargo +beta test --frozen"
+        );
+        assert_eq!(
+            cargo_command(&invocation("beta", Some("stable"))),
+            "RUSTFLAGS='--cap-lints=forbid -C opt-level=3' \
+             CARGO_BUILD_TARGET=wasm32-unknown-unknown \
+             RUSTC=\"$(rustup which --toolchain beta rustc)\" \
+             RUSTDOC=\"$(rustup which --toolchain beta rustdoc)\" cargo +stable test --frozen"
+        );
+    }
+
+    #[test]
+    fn test_install_commands() {
+        assert_eq!(
+            install_commands(&invocation("nightly-2020-01-01", None)),
+            vec![
+                "rustup toolchain install nightly-2020-01-01 --profile minimal \
+                  --target wasm32-unknown-unknown"
+            ]
+        );
+
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        let commands = install_commands(&invocation(&format!("{}-alt", sha), None));
+        assert_eq!(
+            commands[2],
+            format!("rustup-toolchain-install-master --alt {}", sha)
+        );
+        assert_eq!(
+            commands[3],
+            format!(
+                "rustup target add --toolchain {}-alt --target wasm32-unknown-unknown",
+                sha
+            )
+        );
+    }
+}
@@ -1,10 +1,15 @@
 use crate::prelude::*;
-use crate::report::ReportWriter;
+use crate::report::{ReportStream, ReportWriter};
 use crate::results::EncodingType;
 use mime::Mime;
 use rusoto_core::request::HttpClient;
 use rusoto_core::{DefaultCredentialsProvider, Region};
-use rusoto_s3::{GetBucketLocationRequest, PutObjectRequest, S3Client, S3};
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, Delete, DeleteObjectsRequest,
+    GetBucketLocationRequest, ListObjectsV2Request, ObjectIdentifier, PutObjectRequest, S3Client,
+    UploadPartRequest, S3,
+};
 use std::borrow::Cow;
 use std::fmt::{self, Display};
 use std::io;
@@ -60,10 +65,10 @@ impl FromStr for S3Prefix {
 
 pub struct S3Writer {
     prefix: S3Prefix,
-    client: Box<dyn S3>,
+    client: Box<dyn S3 + Send + Sync>,
 }
 
-pub fn get_client_for_bucket(bucket: &str) -> Fallible<Box<dyn S3>> {
+pub fn get_client_for_bucket(bucket: &str) -> Fallible<Box<dyn S3 + Send + Sync>> {
     let make_client = |region| -> Fallible<S3Client> {
         let credentials = DefaultCredentialsProvider::new().unwrap();
         Ok(S3Client::new_with(HttpClient::new()?, credentials, region))
@@ -87,7 +92,7 @@ pub fn get_client_for_bucket(bucket: &str) -> Fallible<Box<dyn S3>> {
 const S3RETRIES: u64 = 4;
 
 impl S3Writer {
-    pub fn create(client: Box<dyn S3>, prefix: S3Prefix) -> Fallible<S3Writer> {
+    pub fn create(client: Box<dyn S3 + Send + Sync>, prefix: S3Prefix) -> Fallible<S3Writer> {
         Ok(S3Writer { prefix, client })
     }
 }
@@ -153,6 +158,223 @@ impl ReportWriter for S3Writer {
         io::copy(r, &mut bytes)?;
         self.write_bytes(path, bytes, mime, EncodingType::Plain)
     }
+
+    fn stream<P: AsRef<Path>>(&self, path: P, mime: &Mime) -> Fallible<Box<dyn ReportStream + '_>> {
+        let key: String = self
+            .prefix
+            .prefix
+            .join(path.as_ref())
+            .to_string_lossy()
+            .into();
+        let upload = self
+            .client
+            .create_multipart_upload(CreateMultipartUploadRequest {
+                acl: Some("public-read".into()),
+                bucket: self.prefix.bucket.clone(),
+                key: key.clone(),
+                content_type: Some(mime.to_string()),
+                ..Default::default()
+            })
+            .sync()
+            .with_context(|_| format!("S3 failure to start uploading {:?}", path.as_ref()))?;
+
+        Ok(Box::new(S3Stream {
+            writer: self,
+            key,
+            upload_id: upload
+                .upload_id
+                .ok_or_else(|| err_msg("S3 didn't return the multipart upload id"))?,
+            buffer: Vec::with_capacity(MULTIPART_PART_SIZE),
+            parts: Vec::new(),
+            finished: false,
+        }))
+    }
+
+    fn clear(&self) -> Fallible<()> {
+        let deleted = delete_prefix(&*self.client, &self.prefix)?;
+        info!("deleted {} files from {}", deleted, self.prefix);
+        Ok(())
+    }
+}
+
+/// Size of each part of multipart uploads. S3 requires all the parts except the last one to be
+/// at least 5MB.
+const MULTIPART_PART_SIZE: usize = 16 * 1024 * 1024;
+
+struct S3Stream<'a> {
+    writer: &'a S3Writer,
+    key: String,
+    upload_id: String,
+    buffer: Vec<u8>,
+    parts: Vec<CompletedPart>,
+    finished: bool,
+}
+
+impl S3Stream<'_> {
+    fn upload_part(&mut self) -> Fallible<()> {
+        let part_number = self.parts.len() as i64 + 1;
+        let body = std::mem::replace(&mut self.buffer, Vec::with_capacity(MULTIPART_PART_SIZE));
+
+        let mut retry = 0;
+        let output = loop {
+            let req = UploadPartRequest {
+                body: Some(body.clone().into()),
+                bucket: self.writer.prefix.bucket.clone(),
+                key: self.key.clone(),
+                part_number,
+                upload_id: self.upload_id.clone(),
+                ..Default::default()
+            };
+            match self.writer.client.upload_part(req).sync() {
+                Err(_) if retry < S3RETRIES => {
+                    retry += 1;
+                    thread::sleep(Duration::from_secs(2 * retry));
+                    warn!(
+                        "retry ({}/{}) S3 upload of part {} of {}",
+                        retry, S3RETRIES, part_number, self.key
+                    );
+                }
+                r => {
+                    break r.with_context(|_| {
+                        format!("S3 failure to upload part {} of {}", part_number, self.key)
+                    })?
+                }
+            }
+        };
+
+        self.parts.push(CompletedPart {
+            e_tag: output.e_tag,
+            part_number: Some(part_number),
+        });
+        Ok(())
+    }
+}
+
+impl io::Write for S3Stream<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= MULTIPART_PART_SIZE {
+            self.upload_part()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.compat()))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Parts can't be smaller than the minimum size, so the data is only uploaded when enough
+        // of it is buffered or when the stream is finished
+        Ok(())
+    }
+}
+
+impl ReportStream for S3Stream<'_> {
+    fn finish(mut self: Box<Self>) -> Fallible<()> {
+        if !self.buffer.is_empty() || self.parts.is_empty() {
+            self.upload_part()?;
+        }
+
+        self.writer
+            .client
+            .complete_multipart_upload(CompleteMultipartUploadRequest {
+                bucket: self.writer.prefix.bucket.clone(),
+                key: self.key.clone(),
+                upload_id: self.upload_id.clone(),
+                multipart_upload: Some(CompletedMultipartUpload {
+                    parts: Some(std::mem::replace(&mut self.parts, Vec::new())),
+                }),
+                ..Default::default()
+            })
+            .sync()
+            .with_context(|_| format!("S3 failure to complete the upload of {}", self.key))?;
+        self.finished = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for S3Stream<'_> {
+    fn drop(&mut self) {
+        // Avoid leaving incomplete uploads around, as S3 charges for their storage
+        if !self.finished {
+            let res = self
+                .writer
+                .client
+                .abort_multipart_upload(AbortMultipartUploadRequest {
+                    bucket: self.writer.prefix.bucket.clone(),
+                    key: self.key.clone(),
+                    upload_id: self.upload_id.clone(),
+                    ..Default::default()
+                })
+                .sync();
+            if let Err(err) = res {
+                warn!("failed to abort the S3 upload of {}: {}", self.key, err);
+            }
+        }
+    }
+}
+
+/// Delete all the objects stored under the prefix, returning how many of them were removed.
+fn delete_prefix(client: &dyn S3, prefix: &S3Prefix) -> Fallible<usize> {
+    // An empty prefix would match the whole bucket
+    let key_prefix = prefix.prefix.to_string_lossy();
+    if key_prefix.is_empty() {
+        return Err(S3Error::BadUrl(prefix.to_string()).into());
+    }
+    // Without the trailing slash the prefix `foo` would also match the objects of `foo-2`
+    let key_prefix = format!("{}/", key_prefix.trim_end_matches('/'));
+
+    let mut deleted = 0;
+    let mut continuation_token = None;
+    loop {
+        let list = client
+            .list_objects_v2(ListObjectsV2Request {
+                bucket: prefix.bucket.clone(),
+                prefix: Some(key_prefix.clone()),
+                continuation_token: continuation_token.take(),
+                ..Default::default()
+            })
+            .sync()
+            .with_context(|_| format!("S3 failure to list the objects in {}", prefix))?;
+
+        // Every page contains at most 1000 keys, the maximum accepted by a single deletion
+        let objects = list
+            .contents
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|object| object.key)
+            .map(|key| ObjectIdentifier {
+                key,
+                version_id: None,
+            })
+            .collect::<Vec<_>>();
+        if !objects.is_empty() {
+            let count = objects.len();
+            let output = client
+                .delete_objects(DeleteObjectsRequest {
+                    bucket: prefix.bucket.clone(),
+                    delete: Delete {
+                        objects,
+                        quiet: Some(true),
+                    },
+                    ..Default::default()
+                })
+                .sync()
+                .with_context(|_| format!("S3 failure to delete the objects in {}", prefix))?;
+            if let Some(err) = output.errors.unwrap_or_default().into_iter().next() {
+                bail!(
+                    "S3 failure to delete {}: {}",
+                    err.key.unwrap_or_default(),
+                    err.message.unwrap_or_default()
+                );
+            }
+            deleted += count;
+        }
+
+        match list.next_continuation_token {
+            Some(token) if list.is_truncated == Some(true) => continuation_token = Some(token),
+            _ => return Ok(deleted),
+        }
+    }
 }
 
 impl Display for S3Prefix {
@@ -0,0 +1,148 @@
+use crate::crates::Crate;
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::report::ReportWriter;
+use crate::results::ReadResults;
+use mime;
+use serde_json;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Time spent compiling a package across all the crates of an experiment, with each toolchain.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageTimings {
+    package: String,
+    version: String,
+    /// Number of units of the package compiled with each toolchain.
+    units: [u64; 2],
+    /// Seconds spent compiling the units of the package with each toolchain.
+    duration: [f64; 2],
+}
+
+fn aggregate_timings<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+) -> Fallible<Vec<PackageTimings>> {
+    let mut packages: HashMap<(String, String), PackageTimings> = HashMap::new();
+    let target_toolchains = ex.target_toolchains();
+    for krate in crates {
+        for (_, toolchains) in &target_toolchains {
+            for (idx, toolchain) in toolchains.iter().enumerate() {
+                for timing in db.load_unit_timings(ex, toolchain, krate)? {
+                    let package = packages
+                        .entry((timing.package.clone(), timing.version.clone()))
+                        .or_insert_with(|| PackageTimings {
+                            package: timing.package,
+                            version: timing.version,
+                            units: [0, 0],
+                            duration: [0.0, 0.0],
+                        });
+                    package.units[idx] += 1;
+                    package.duration[idx] += timing.duration;
+                }
+            }
+        }
+    }
+
+    // The packages slowest to compile with the toolchain being tested come first
+    let mut packages = packages.into_iter().map(|(_, p)| p).collect::<Vec<_>>();
+    packages.sort_by(|a, b| {
+        b.duration[1]
+            .partial_cmp(&a.duration[1])
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.package.cmp(&b.package))
+            .then_with(|| a.version.cmp(&b.version))
+    });
+    Ok(packages)
+}
+
+pub fn write_timings<DB: ReadResults, W: ReportWriter>(
+    db: &DB,
+    ex: &Experiment,
+    crates: &[Crate],
+    dest: &W,
+) -> Fallible<()> {
+    let packages = aggregate_timings(db, ex, crates)?;
+    dest.write_string(
+        "timings.json",
+        serde_json::to_string(&packages)?.into(),
+        &mime::APPLICATION_JSON,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_timings, PackageTimings};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::crates::Crate;
+    use crate::db::Database;
+    use crate::experiments::Experiment;
+    use crate::report::DummyWriter;
+    use crate::results::{DummyDB, TargetKind, UnitTiming};
+
+    #[test]
+    fn test_write_timings() {
+        let config = Config::default();
+        let db = Database::temp().unwrap();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        let timing = |package: &str, mode: &str, duration| UnitTiming {
+            package: package.into(),
+            version: "1.0.0".into(),
+            target_kind: Some(TargetKind::Lib),
+            target_name: package.into(),
+            mode: mode.into(),
+            duration,
+        };
+        let crates = vec![Crate::Local("foo".into()), Crate::Local("bar".into())];
+        let mut results = DummyDB::default();
+        for krate in &crates {
+            results.add_dummy_unit_timings(
+                &ex,
+                krate.clone(),
+                ex.toolchains[0].clone(),
+                vec![timing("syn", "build", 4.0), timing("libc", "build", 1.0)],
+            );
+            results.add_dummy_unit_timings(
+                &ex,
+                krate.clone(),
+                ex.toolchains[1].clone(),
+                vec![
+                    timing("syn", "build", 2.0),
+                    timing("libc", "build", 3.0),
+                    timing("libc", "run-custom-build", 0.5),
+                ],
+            );
+        }
+
+        let writer = DummyWriter::default();
+        write_timings(&results, &ex, &crates, &writer).unwrap();
+        let packages: Vec<PackageTimings> =
+            serde_json::from_slice(&writer.get("timings.json", &mime::APPLICATION_JSON)).unwrap();
+
+        assert_eq!(
+            packages,
+            vec![
+                PackageTimings {
+                    package: "libc".into(),
+                    version: "1.0.0".into(),
+                    units: [2, 4],
+                    duration: [2.0, 7.0],
+                },
+                PackageTimings {
+                    package: "syn".into(),
+                    version: "1.0.0".into(),
+                    units: [2, 2],
+                    duration: [8.0, 4.0],
+                },
+            ]
+        );
+    }
+}
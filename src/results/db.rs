@@ -1,30 +1,62 @@
+use crate::audit::{self, Actor, AuditAction};
 use crate::config::Config;
-use crate::crates::{Crate, GitHubRepo};
+use crate::crates::{Crate, GitHubRepo, RustVersion};
 use crate::db::{Database, QueryUtils};
-use crate::experiments::Experiment;
+use crate::experiments::{CrateSkipReason, Experiment, Status};
 use crate::prelude::*;
 use crate::results::{
-    DeleteResults, EncodedLog, EncodingType, ReadResults, TestResult, WriteResults,
+    lockfile_hash, DeleteResults, Diagnostic, DiagnosticCounts, DocItem, EncodedLog, EncodingType,
+    FailedTarget, Invocation, MemberResult, ReadResults, ResourceUsage, TestResult, UnitTiming,
+    WriteResults,
 };
-use crate::toolchain::Toolchain;
+use crate::toolchain::{Toolchain, ToolchainVersion};
 use base64;
 use rustwide::logging::{self, LogStorage};
-use serde_json;
+use serde_json::{self, json};
 use std::collections::HashMap;
 
 #[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct TaskResult {
     #[serde(rename = "crate")]
     pub krate: Crate,
     pub toolchain: Toolchain,
     pub result: TestResult,
     pub log: String,
+    #[serde(default)]
+    pub members: Vec<MemberResult>,
+    #[serde(default)]
+    pub failed_targets: Vec<FailedTarget>,
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+    #[serde(default)]
+    pub diagnostic_counts: Option<DiagnosticCounts>,
+    #[serde(default)]
+    pub unit_timings: Vec<UnitTiming>,
+    #[serde(default)]
+    pub doc_items: Vec<DocItem>,
+    #[serde(default)]
+    pub invocations: Vec<Invocation>,
+    #[serde(default)]
+    pub resource_usage: Option<ResourceUsage>,
+    /// Base64-encoded snapshot of the build directory.
+    #[serde(default)]
+    pub snapshot: Option<String>,
+    /// Version of the C compiler the crate was built with.
+    #[serde(default)]
+    pub c_compiler: Option<String>,
+    /// `Cargo.lock` the crate was built with, sent only for the baseline toolchain.
+    #[serde(default)]
+    pub lockfile: Option<String>,
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct ProgressData {
     pub results: Vec<TaskResult>,
     pub shas: Vec<(GitHubRepo, String)>,
+    #[serde(default)]
+    pub toolchain_versions: Vec<(Toolchain, ToolchainVersion)>,
 }
 
 pub struct DatabaseDB<'a> {
@@ -51,16 +83,65 @@ impl<'a> DatabaseDB<'a> {
                 &base64::decode(&result.log).with_context(|_| "invalid base64 log provided")?,
                 encoding_type,
             )?;
+            if !result.members.is_empty() {
+                self.record_member_results(ex, &result.toolchain, &result.krate, &result.members)?;
+            }
+            if !result.failed_targets.is_empty() {
+                self.record_failed_targets(
+                    ex,
+                    &result.toolchain,
+                    &result.krate,
+                    &result.failed_targets,
+                )?;
+            }
+            if !result.diagnostics.is_empty() {
+                self.record_diagnostics(ex, &result.toolchain, &result.krate, &result.diagnostics)?;
+            }
+            if let Some(counts) = result.diagnostic_counts {
+                self.record_diagnostic_counts(ex, &result.toolchain, &result.krate, counts)?;
+            }
+            if !result.unit_timings.is_empty() {
+                self.record_unit_timings(
+                    ex,
+                    &result.toolchain,
+                    &result.krate,
+                    &result.unit_timings,
+                )?;
+            }
+            if !result.doc_items.is_empty() {
+                self.record_doc_items(ex, &result.toolchain, &result.krate, &result.doc_items)?;
+            }
+            if !result.invocations.is_empty() {
+                self.record_invocations(ex, &result.toolchain, &result.krate, &result.invocations)?;
+            }
+            if let Some(usage) = result.resource_usage {
+                self.record_resource_usage(ex, &result.toolchain, &result.krate, usage)?;
+            }
+            if let Some(ref snapshot) = result.snapshot {
+                let snapshot = base64::decode(snapshot)
+                    .with_context(|_| "invalid base64 snapshot provided")?;
+                self.record_snapshot(ex, &result.toolchain, &result.krate, &snapshot)?;
+            }
+            if let Some(ref version) = result.c_compiler {
+                self.record_c_compiler(ex, &result.toolchain, &result.krate, version)?;
+            }
+            if let Some(ref lockfile) = result.lockfile {
+                self.record_lockfile(ex, &result.toolchain, &result.krate, lockfile)?;
+            }
         }
 
         for &(ref repo, ref sha) in &data.shas {
             self.record_sha(ex, repo, sha)?;
         }
 
+        for &(ref toolchain, ref version) in &data.toolchain_versions {
+            self.record_toolchain_version(ex, toolchain, version)?;
+        }
+
         Ok(())
     }
 
-    fn store_result(
+    pub(crate) fn store_result(
         &self,
         ex: &Experiment,
         krate: &Crate,
@@ -82,18 +163,32 @@ impl<'a> DatabaseDB<'a> {
         res: TestResult,
         log: EncodedLog,
     ) -> Fallible<usize> {
-        self.db.execute(
-            "INSERT INTO results (experiment, crate, toolchain, result, log, encoding) \
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
-            &[
-                &ex.name,
-                &serde_json::to_string(krate)?,
-                &toolchain.to_string(),
-                &res.to_string(),
-                &log.as_slice(),
-                &log.get_encoding_type().to_str(),
-            ],
-        )
+        // Logs are content-addressed, so identical logs are stored only once: the number of
+        // results pointing to each log is tracked by triggers on the results table, which also
+        // remove the log when it's not referenced anymore.
+        let hash = log.content_hash();
+        let krate = serde_json::to_string(krate)?;
+        let toolchain = toolchain.to_string();
+
+        self.db.transaction(|t| {
+            // Remove the previous result explicitly instead of replacing it, otherwise the
+            // triggers wouldn't be fired for the old row. It's removed before storing the log, as
+            // the trigger deletes the old log if it was only referenced by this result, even when
+            // it's the same as the new one.
+            t.execute(
+                "DELETE FROM results WHERE experiment = ?1 AND crate = ?2 AND toolchain = ?3;",
+                &[&ex.name, &krate, &toolchain],
+            )?;
+            t.execute(
+                "INSERT OR IGNORE INTO logs (hash, encoding, content) VALUES (?1, ?2, ?3);",
+                &[&hash, &log.get_encoding_type().to_str(), &log.as_slice()],
+            )?;
+            t.execute(
+                "INSERT INTO results (experiment, crate, toolchain, result, log) \
+                 VALUES (?1, ?2, ?3, ?4, ?5);",
+                &[&ex.name, &krate, &toolchain, &res.to_string(), &hash],
+            )
+        })
     }
 }
 
@@ -125,7 +220,8 @@ impl<'a> ReadResults for DatabaseDB<'a> {
         krate: &Crate,
     ) -> Fallible<Option<EncodedLog>> {
         self.db.get_row(
-            "SELECT log, encoding FROM results \
+            "SELECT logs.content, logs.encoding FROM results \
+             INNER JOIN logs ON logs.hash = results.log \
              WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
              LIMIT 1;",
             &[
@@ -134,7 +230,7 @@ impl<'a> ReadResults for DatabaseDB<'a> {
                 &serde_json::to_string(krate)?,
             ],
             |row| {
-                let log: Vec<u8> = row.get("log");
+                let log: Vec<u8> = row.get("content");
                 let encoding: String = row.get("encoding");
                 let encoding = encoding.parse().unwrap();
 
@@ -173,52 +269,752 @@ impl<'a> ReadResults for DatabaseDB<'a> {
             Ok(None)
         }
     }
-}
 
-impl<'a> WriteResults for DatabaseDB<'a> {
-    fn get_result(
+    fn load_rust_version(&self, krate: &Crate) -> Fallible<Option<RustVersion>> {
+        crate::crates::metadata::rust_version(self.db, krate)
+    }
+
+    fn load_member_results(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<MemberResult>> {
+        self.db
+            .query(
+                "SELECT member, result FROM member_results \
+                 WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+                 ORDER BY rowid;",
+                &[
+                    &ex.name,
+                    &toolchain.to_string(),
+                    &serde_json::to_string(krate)?,
+                ],
+                |row| -> (String, String) { (row.get("member"), row.get("result")) },
+            )?
+            .into_iter()
+            .map(|(name, result)| {
+                Ok(MemberResult {
+                    name,
+                    result: result.parse()?,
+                })
+            })
+            .collect()
+    }
+
+    fn load_failed_targets(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<FailedTarget>> {
+        self.db
+            .query(
+                "SELECT package, kind, name FROM failed_targets \
+                 WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+                 ORDER BY rowid;",
+                &[
+                    &ex.name,
+                    &toolchain.to_string(),
+                    &serde_json::to_string(krate)?,
+                ],
+                |row| -> (String, String, String) {
+                    (row.get("package"), row.get("kind"), row.get("name"))
+                },
+            )?
+            .into_iter()
+            .map(|(package, kind, name)| {
+                Ok(FailedTarget {
+                    package,
+                    kind: kind.parse()?,
+                    name,
+                })
+            })
+            .collect()
+    }
+
+    fn load_diagnostics(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<Diagnostic>> {
+        self.db
+            .query(
+                "SELECT * FROM diagnostics \
+                 WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+                 ORDER BY rowid;",
+                &[
+                    &ex.name,
+                    &toolchain.to_string(),
+                    &serde_json::to_string(krate)?,
+                ],
+                |row| {
+                    (
+                        row.get::<_, String>("package"),
+                        row.get::<_, Option<String>>("target_kind"),
+                        row.get::<_, Option<String>>("target_name"),
+                        row.get::<_, String>("level"),
+                        row.get::<_, Option<String>>("code"),
+                        row.get::<_, String>("message"),
+                        row.get::<_, Option<String>>("file"),
+                        row.get::<_, Option<i64>>("line"),
+                        row.get::<_, Option<i64>>("column"),
+                    )
+                },
+            )?
+            .into_iter()
+            .map(
+                |(package, target_kind, target_name, level, code, message, file, line, column)| {
+                    Ok(Diagnostic {
+                        package,
+                        target_kind: target_kind.map(|kind| kind.parse()).transpose()?,
+                        target_name,
+                        level: level.parse()?,
+                        code,
+                        message,
+                        file,
+                        line: line.map(|line| line as u32),
+                        column: column.map(|column| column as u32),
+                    })
+                },
+            )
+            .collect()
+    }
+
+    fn load_diagnostic_counts(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<DiagnosticCounts>> {
+        self.db.get_row(
+            "SELECT errors, warnings FROM diagnostic_counts \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[
+                &ex.name,
+                &toolchain.to_string(),
+                &serde_json::to_string(krate)?,
+            ],
+            |row| DiagnosticCounts {
+                errors: row.get::<_, i64>("errors") as u32,
+                warnings: row.get::<_, i64>("warnings") as u32,
+            },
+        )
+    }
+
+    fn load_unit_timings(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<UnitTiming>> {
+        self.db
+            .query(
+                "SELECT * FROM unit_timings \
+                 WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+                 ORDER BY rowid;",
+                &[
+                    &ex.name,
+                    &toolchain.to_string(),
+                    &serde_json::to_string(krate)?,
+                ],
+                |row| {
+                    (
+                        row.get::<_, String>("package"),
+                        row.get::<_, String>("version"),
+                        row.get::<_, Option<String>>("target_kind"),
+                        row.get::<_, String>("target_name"),
+                        row.get::<_, String>("mode"),
+                        row.get::<_, f64>("duration"),
+                    )
+                },
+            )?
+            .into_iter()
+            .map(
+                |(package, version, target_kind, target_name, mode, duration)| {
+                    Ok(UnitTiming {
+                        package,
+                        version,
+                        target_kind: target_kind.map(|kind| kind.parse()).transpose()?,
+                        target_name,
+                        mode,
+                        duration,
+                    })
+                },
+            )
+            .collect()
+    }
+
+    fn load_doc_items(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<DocItem>> {
+        self.db.query(
+            "SELECT path, kind, hash FROM doc_items \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+             ORDER BY rowid;",
+            &[
+                &ex.name,
+                &toolchain.to_string(),
+                &serde_json::to_string(krate)?,
+            ],
+            |row| DocItem {
+                path: row.get("path"),
+                kind: row.get("kind"),
+                hash: row.get("hash"),
+            },
+        )
+    }
+
+    fn load_invocations(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<Invocation>> {
+        self.db
+            .query(
+                "SELECT * FROM invocations \
+                 WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+                 ORDER BY rowid;",
+                &[
+                    &ex.name,
+                    &toolchain.to_string(),
+                    &serde_json::to_string(krate)?,
+                ],
+                |row| {
+                    (
+                        row.get::<_, String>("rustup_toolchain"),
+                        row.get::<_, Option<String>>("cargo_toolchain"),
+                        row.get::<_, String>("args"),
+                        row.get::<_, String>("env"),
+                        row.get::<_, Option<i64>>("memory_limit"),
+                        row.get::<_, Option<f64>>("cpu_limit"),
+                        row.get::<_, Option<String>>("cpuset"),
+                    )
+                },
+            )?
+            .into_iter()
+            .map(
+                |(toolchain, cargo_toolchain, args, env, memory_limit, cpu_limit, cpuset)| {
+                    Ok(Invocation {
+                        toolchain,
+                        cargo_toolchain,
+                        args: serde_json::from_str(&args)?,
+                        env: serde_json::from_str(&env)?,
+                        memory_limit: memory_limit.map(|limit| limit as u64),
+                        cpu_limit: cpu_limit.map(|limit| limit as f32),
+                        cpuset,
+                    })
+                },
+            )
+            .collect()
+    }
+
+    fn load_snapshot(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<Vec<u8>>> {
+        self.db.get_row(
+            "SELECT content FROM snapshots \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[
+                &ex.name,
+                &toolchain.to_string(),
+                &serde_json::to_string(krate)?,
+            ],
+            |row| row.get("content"),
+        )
+    }
+
+    fn load_c_compiler(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<String>> {
+        self.db.get_row(
+            "SELECT version FROM c_compilers \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[
+                &ex.name,
+                &toolchain.to_string(),
+                &serde_json::to_string(krate)?,
+            ],
+            |row| row.get("version"),
+        )
+    }
+
+    fn load_c_compilers(&self, ex: &Experiment, toolchain: &Toolchain) -> Fallible<Vec<String>> {
+        self.db.query(
+            "SELECT DISTINCT version FROM c_compilers \
+             WHERE experiment = ?1 AND toolchain = ?2 ORDER BY version;",
+            &[&ex.name, &toolchain.to_string()],
+            |row| row.get("version"),
+        )
+    }
+
+    fn load_lockfile(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<String>> {
+        self.db.get_row(
+            "SELECT lockfile_contents.content FROM lockfiles \
+             INNER JOIN lockfile_contents ON lockfile_contents.hash = lockfiles.lockfile \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[
+                &ex.name,
+                &toolchain.to_string(),
+                &serde_json::to_string(krate)?,
+            ],
+            |row| row.get("content"),
+        )
+    }
+
+    fn load_toolchain_version(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+    ) -> Fallible<Option<ToolchainVersion>> {
+        self.db.get_row(
+            "SELECT release, commit_hash, commit_date, host, llvm_version \
+             FROM toolchain_versions WHERE experiment = ?1 AND toolchain = ?2;",
+            &[&ex.name, &toolchain.to_string()],
+            |row| ToolchainVersion {
+                release: row.get("release"),
+                commit_hash: row.get("commit_hash"),
+                commit_date: row.get("commit_date"),
+                host: row.get("host"),
+                llvm_version: row.get("llvm_version"),
+            },
+        )
+    }
+
+    fn load_skip_reasons(&self, ex: &Experiment) -> Fallible<HashMap<Crate, CrateSkipReason>> {
+        ex.get_skip_reasons(self.db)
+    }
+}
+
+impl<'a> WriteResults for DatabaseDB<'a> {
+    fn get_result(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<TestResult>> {
+        self.load_test_result(ex, toolchain, krate)
+    }
+
+    fn record_sha(&self, ex: &Experiment, repo: &GitHubRepo, sha: &str) -> Fallible<()> {
+        self.db.execute(
+            "INSERT INTO shas (experiment, org, name, sha) VALUES (?1, ?2, ?3, ?4)",
+            &[&ex.name, &repo.org, &repo.name, &sha],
+        )?;
+
+        Ok(())
+    }
+
+    fn record_result<F>(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        existing_logs: Option<LogStorage>,
+        config: &Config,
+        encoding_type: EncodingType,
+        f: F,
+    ) -> Fallible<TestResult>
+    where
+        F: FnOnce() -> Fallible<TestResult>,
+    {
+        let storage = existing_logs.unwrap_or_else(|| LogStorage::from(config));
+        let result = logging::capture(&storage, f)?;
+        let output = storage.to_string();
+        self.store_result(
+            ex,
+            krate,
+            toolchain,
+            result,
+            output.as_bytes(),
+            encoding_type,
+        )?;
+        Ok(result)
+    }
+
+    fn record_member_results(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        members: &[MemberResult],
+    ) -> Fallible<()> {
+        let krate = serde_json::to_string(krate)?;
+        let toolchain = toolchain.to_string();
+
+        self.db.transaction(|t| {
+            t.execute(
+                "DELETE FROM member_results \
+                 WHERE experiment = ?1 AND crate = ?2 AND toolchain = ?3;",
+                &[&ex.name, &krate, &toolchain],
+            )?;
+            for member in members {
+                t.execute(
+                    "INSERT INTO member_results (experiment, crate, toolchain, member, result) \
+                     VALUES (?1, ?2, ?3, ?4, ?5);",
+                    &[
+                        &ex.name,
+                        &krate,
+                        &toolchain,
+                        &member.name,
+                        &member.result.to_string(),
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    fn record_failed_targets(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        targets: &[FailedTarget],
+    ) -> Fallible<()> {
+        let krate = serde_json::to_string(krate)?;
+        let toolchain = toolchain.to_string();
+
+        self.db.transaction(|t| {
+            t.execute(
+                "DELETE FROM failed_targets \
+                 WHERE experiment = ?1 AND crate = ?2 AND toolchain = ?3;",
+                &[&ex.name, &krate, &toolchain],
+            )?;
+            for target in targets {
+                t.execute(
+                    "INSERT INTO failed_targets \
+                     (experiment, crate, toolchain, package, kind, name) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+                    &[
+                        &ex.name,
+                        &krate,
+                        &toolchain,
+                        &target.package,
+                        &target.kind.to_str(),
+                        &target.name,
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    fn record_diagnostics(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        diagnostics: &[Diagnostic],
+    ) -> Fallible<()> {
+        let krate = serde_json::to_string(krate)?;
+        let toolchain = toolchain.to_string();
+
+        self.db.transaction(|t| {
+            t.execute(
+                "DELETE FROM diagnostics \
+                 WHERE experiment = ?1 AND crate = ?2 AND toolchain = ?3;",
+                &[&ex.name, &krate, &toolchain],
+            )?;
+            for diagnostic in diagnostics {
+                t.execute(
+                    "INSERT INTO diagnostics \
+                     (experiment, crate, toolchain, package, target_kind, target_name, level, \
+                      code, message, file, line, column) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12);",
+                    &[
+                        &ex.name,
+                        &krate,
+                        &toolchain,
+                        &diagnostic.package,
+                        &diagnostic.target_kind.map(|kind| kind.to_str()),
+                        &diagnostic.target_name,
+                        &diagnostic.level.to_str(),
+                        &diagnostic.code,
+                        &diagnostic.message,
+                        &diagnostic.file,
+                        &diagnostic.line.map(i64::from),
+                        &diagnostic.column.map(i64::from),
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    fn record_diagnostic_counts(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        counts: DiagnosticCounts,
+    ) -> Fallible<()> {
+        self.db.execute(
+            "INSERT INTO diagnostic_counts (experiment, crate, toolchain, errors, warnings) \
+             VALUES (?1, ?2, ?3, ?4, ?5);",
+            &[
+                &ex.name,
+                &serde_json::to_string(krate)?,
+                &toolchain.to_string(),
+                &i64::from(counts.errors),
+                &i64::from(counts.warnings),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn record_unit_timings(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        timings: &[UnitTiming],
+    ) -> Fallible<()> {
+        let krate = serde_json::to_string(krate)?;
+        let toolchain = toolchain.to_string();
+
+        self.db.transaction(|t| {
+            t.execute(
+                "DELETE FROM unit_timings \
+                 WHERE experiment = ?1 AND crate = ?2 AND toolchain = ?3;",
+                &[&ex.name, &krate, &toolchain],
+            )?;
+            for timing in timings {
+                t.execute(
+                    "INSERT INTO unit_timings \
+                     (experiment, crate, toolchain, package, version, target_kind, target_name, \
+                      mode, duration) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9);",
+                    &[
+                        &ex.name,
+                        &krate,
+                        &toolchain,
+                        &timing.package,
+                        &timing.version,
+                        &timing.target_kind.map(|kind| kind.to_str()),
+                        &timing.target_name,
+                        &timing.mode,
+                        &timing.duration,
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    fn record_doc_items(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        items: &[DocItem],
+    ) -> Fallible<()> {
+        let krate = serde_json::to_string(krate)?;
+        let toolchain = toolchain.to_string();
+
+        self.db.transaction(|t| {
+            t.execute(
+                "DELETE FROM doc_items \
+                 WHERE experiment = ?1 AND crate = ?2 AND toolchain = ?3;",
+                &[&ex.name, &krate, &toolchain],
+            )?;
+            for item in items {
+                t.execute(
+                    "INSERT INTO doc_items (experiment, crate, toolchain, path, kind, hash) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+                    &[
+                        &ex.name, &krate, &toolchain, &item.path, &item.kind, &item.hash,
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    fn record_invocations(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        invocations: &[Invocation],
+    ) -> Fallible<()> {
+        let krate = serde_json::to_string(krate)?;
+        let toolchain = toolchain.to_string();
+
+        self.db.transaction(|t| {
+            t.execute(
+                "DELETE FROM invocations \
+                 WHERE experiment = ?1 AND crate = ?2 AND toolchain = ?3;",
+                &[&ex.name, &krate, &toolchain],
+            )?;
+            for invocation in invocations {
+                t.execute(
+                    "INSERT INTO invocations \
+                     (experiment, crate, toolchain, rustup_toolchain, cargo_toolchain, args, env, \
+                      memory_limit, cpu_limit, cpuset) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10);",
+                    &[
+                        &ex.name,
+                        &krate,
+                        &toolchain,
+                        &invocation.toolchain,
+                        &invocation.cargo_toolchain,
+                        &serde_json::to_string(&invocation.args)?,
+                        &serde_json::to_string(&invocation.env)?,
+                        &invocation.memory_limit.map(|limit| limit as i64),
+                        &invocation.cpu_limit.map(f64::from),
+                        &invocation.cpuset,
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    fn record_resource_usage(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        usage: ResourceUsage,
+    ) -> Fallible<()> {
+        self.db.execute(
+            "INSERT INTO resource_usage (experiment, crate, toolchain, duration, cpus) \
+             VALUES (?1, ?2, ?3, ?4, ?5);",
+            &[
+                &ex.name,
+                &serde_json::to_string(krate)?,
+                &toolchain.to_string(),
+                &usage.duration,
+                &usage.cpus,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn record_snapshot(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        snapshot: &[u8],
+    ) -> Fallible<()> {
+        self.db.execute(
+            "INSERT INTO snapshots (experiment, crate, toolchain, content) \
+             VALUES (?1, ?2, ?3, ?4);",
+            &[
+                &ex.name,
+                &serde_json::to_string(krate)?,
+                &toolchain.to_string(),
+                &snapshot,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn record_c_compiler(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        version: &str,
+    ) -> Fallible<()> {
+        self.db.execute(
+            "INSERT INTO c_compilers (experiment, crate, toolchain, version) \
+             VALUES (?1, ?2, ?3, ?4);",
+            &[
+                &ex.name,
+                &serde_json::to_string(krate)?,
+                &toolchain.to_string(),
+                &version,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn record_lockfile(
         &self,
         ex: &Experiment,
         toolchain: &Toolchain,
         krate: &Crate,
-    ) -> Fallible<Option<TestResult>> {
-        self.load_test_result(ex, toolchain, krate)
-    }
-
-    fn record_sha(&self, ex: &Experiment, repo: &GitHubRepo, sha: &str) -> Fallible<()> {
-        self.db.execute(
-            "INSERT INTO shas (experiment, org, name, sha) VALUES (?1, ?2, ?3, ?4)",
-            &[&ex.name, &repo.org, &repo.name, &sha],
-        )?;
+        lockfile: &str,
+    ) -> Fallible<()> {
+        // Lockfiles are content-addressed like the logs, as most crates resolve the same
+        // dependencies with every toolchain: the references are counted by triggers.
+        let hash = lockfile_hash(lockfile);
+        let krate = serde_json::to_string(krate)?;
+        let toolchain = toolchain.to_string();
 
+        self.db.transaction(|t| {
+            t.execute(
+                "DELETE FROM lockfiles WHERE experiment = ?1 AND crate = ?2 AND toolchain = ?3;",
+                &[&ex.name, &krate, &toolchain],
+            )?;
+            t.execute(
+                "INSERT OR IGNORE INTO lockfile_contents (hash, content) VALUES (?1, ?2);",
+                &[&hash, &lockfile],
+            )?;
+            t.execute(
+                "INSERT INTO lockfiles (experiment, crate, toolchain, lockfile) \
+                 VALUES (?1, ?2, ?3, ?4);",
+                &[&ex.name, &krate, &toolchain, &hash],
+            )
+        })?;
         Ok(())
     }
 
-    fn record_result<F>(
+    fn record_toolchain_version(
         &self,
         ex: &Experiment,
         toolchain: &Toolchain,
-        krate: &Crate,
-        existing_logs: Option<LogStorage>,
-        config: &Config,
-        encoding_type: EncodingType,
-        f: F,
-    ) -> Fallible<TestResult>
-    where
-        F: FnOnce() -> Fallible<TestResult>,
-    {
-        let storage = existing_logs.unwrap_or_else(|| LogStorage::from(config));
-        let result = logging::capture(&storage, f)?;
-        let output = storage.to_string();
-        self.store_result(
-            ex,
-            krate,
-            toolchain,
-            result,
-            output.as_bytes(),
-            encoding_type,
+        version: &ToolchainVersion,
+    ) -> Fallible<()> {
+        self.db.execute(
+            "INSERT INTO toolchain_versions \
+             (experiment, toolchain, release, commit_hash, commit_date, host, llvm_version) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+            &[
+                &ex.name,
+                &toolchain.to_string(),
+                &version.release,
+                &version.commit_hash,
+                &version.commit_date,
+                &version.host,
+                &version.llvm_version,
+            ],
         )?;
-        Ok(result)
+        Ok(())
+    }
+
+    fn record_worker_crash(&self, ex: &Experiment, worker: &str, error: &str) -> Fallible<()> {
+        // Experiments are only run directly on the database by the CLI
+        audit::record(
+            self.db,
+            &Actor::CLI,
+            AuditAction::WorkerCrashed,
+            Some(&ex.name),
+            &json!({ "worker": worker, "error": error }),
+        )
+    }
+
+    fn is_cancelled(&self, ex: &Experiment) -> Fallible<bool> {
+        Ok(Experiment::get(self.db, &ex.name)?.map_or(true, |ex| ex.status == Status::Cancelled))
     }
 }
 
@@ -226,17 +1022,102 @@ impl<'a> DeleteResults for DatabaseDB<'a> {
     fn delete_all_results(&self, ex: &Experiment) -> Fallible<()> {
         self.db
             .execute("DELETE FROM results WHERE experiment = ?1;", &[&ex.name])?;
+        self.db.execute(
+            "DELETE FROM toolchain_versions WHERE experiment = ?1;",
+            &[&ex.name],
+        )?;
+        self.db.execute(
+            "DELETE FROM member_results WHERE experiment = ?1;",
+            &[&ex.name],
+        )?;
+        self.db.execute(
+            "DELETE FROM failed_targets WHERE experiment = ?1;",
+            &[&ex.name],
+        )?;
+        self.db.execute(
+            "DELETE FROM diagnostics WHERE experiment = ?1;",
+            &[&ex.name],
+        )?;
+        self.db.execute(
+            "DELETE FROM diagnostic_counts WHERE experiment = ?1;",
+            &[&ex.name],
+        )?;
+        self.db.execute(
+            "DELETE FROM unit_timings WHERE experiment = ?1;",
+            &[&ex.name],
+        )?;
+        self.db
+            .execute("DELETE FROM doc_items WHERE experiment = ?1;", &[&ex.name])?;
+        self.db.execute(
+            "DELETE FROM invocations WHERE experiment = ?1;",
+            &[&ex.name],
+        )?;
+        self.db.execute(
+            "DELETE FROM resource_usage WHERE experiment = ?1;",
+            &[&ex.name],
+        )?;
+        self.db
+            .execute("DELETE FROM snapshots WHERE experiment = ?1;", &[&ex.name])?;
+        self.db.execute(
+            "DELETE FROM c_compilers WHERE experiment = ?1;",
+            &[&ex.name],
+        )?;
+        self.db
+            .execute("DELETE FROM lockfiles WHERE experiment = ?1;", &[&ex.name])?;
         Ok(())
     }
 
     fn delete_result(&self, ex: &Experiment, tc: &Toolchain, krate: &Crate) -> Fallible<()> {
+        let krate = serde_json::to_string(krate)?;
+        let tc = tc.to_string();
         self.db.execute(
             "DELETE FROM results WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
-            &[
-                &ex.name,
-                &tc.to_string(),
-                &serde_json::to_string(krate).unwrap(),
-            ],
+            &[&ex.name, &tc, &krate],
+        )?;
+        self.db.execute(
+            "DELETE FROM member_results WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[&ex.name, &tc, &krate],
+        )?;
+        self.db.execute(
+            "DELETE FROM failed_targets WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[&ex.name, &tc, &krate],
+        )?;
+        self.db.execute(
+            "DELETE FROM diagnostics WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[&ex.name, &tc, &krate],
+        )?;
+        self.db.execute(
+            "DELETE FROM diagnostic_counts \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[&ex.name, &tc, &krate],
+        )?;
+        self.db.execute(
+            "DELETE FROM unit_timings WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[&ex.name, &tc, &krate],
+        )?;
+        self.db.execute(
+            "DELETE FROM doc_items WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[&ex.name, &tc, &krate],
+        )?;
+        self.db.execute(
+            "DELETE FROM invocations WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[&ex.name, &tc, &krate],
+        )?;
+        self.db.execute(
+            "DELETE FROM resource_usage WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[&ex.name, &tc, &krate],
+        )?;
+        self.db.execute(
+            "DELETE FROM snapshots WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[&ex.name, &tc, &krate],
+        )?;
+        self.db.execute(
+            "DELETE FROM c_compilers WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[&ex.name, &tc, &krate],
+        )?;
+        self.db.execute(
+            "DELETE FROM lockfiles WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[&ex.name, &tc, &krate],
         )?;
         Ok(())
     }
@@ -248,16 +1129,42 @@ mod tests {
     use crate::actions::{Action, ActionsCtx, CreateExperiment};
     use crate::config::Config;
     use crate::crates::{Crate, GitHubRepo, RegistryCrate};
-    use crate::db::Database;
+    use crate::db::{Database, QueryUtils};
     use crate::experiments::Experiment;
     use crate::prelude::*;
     use crate::results::{
-        DeleteResults, EncodedLog, EncodingType, FailureReason, ReadResults, TestResult,
-        WriteResults,
+        DeleteResults, Diagnostic, DiagnosticCounts, DiagnosticLevel, DocItem, EncodedLog,
+        EncodingType, FailedTarget, FailureReason, Invocation, MemberResult, ReadResults,
+        ResourceUsage, TargetKind, TestResult, UnitTiming, WriteResults,
     };
-    use crate::toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use crate::toolchain::{ToolchainVersion, MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
     use base64;
 
+    #[test]
+    fn test_task_result_from_agent() {
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        let target = FailedTarget {
+            package: "lazy_static".into(),
+            kind: TargetKind::Example,
+            name: "demo".into(),
+        };
+
+        // Same format as the results sent by the agents
+        let result: TaskResult = serde_json::from_value(serde_json::json!({
+            "crate": krate,
+            "toolchain": MAIN_TOOLCHAIN,
+            "result": TestResult::TestPass,
+            "log": base64::encode("foo"),
+            "members": [],
+            "failed-targets": [target],
+        }))
+        .unwrap();
+        assert_eq!(result.failed_targets, vec![target]);
+    }
+
     #[test]
     fn test_shas() {
         let db = Database::temp().unwrap();
@@ -426,6 +1333,162 @@ mod tests {
             .is_none());
     }
 
+    #[test]
+    fn test_logs_deduplication() {
+        let db = Database::temp().unwrap();
+        let results = DatabaseDB::new(&db);
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        let krate1 = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        let krate2 = Crate::Registry(RegistryCrate {
+            name: "regex".into(),
+            version: "1".into(),
+        });
+        let count_logs = || -> i64 {
+            db.query("SELECT COUNT(*) AS count FROM logs;", &[], |r| r.get("count"))
+                .unwrap()[0]
+        };
+
+        // Identical logs are stored only once
+        for krate in &[&krate1, &krate2] {
+            for tc in &[&MAIN_TOOLCHAIN, &TEST_TOOLCHAIN] {
+                results
+                    .store_result(
+                        &ex,
+                        krate,
+                        tc,
+                        TestResult::BuildFail(FailureReason::Unknown),
+                        b"error: failed to build",
+                        EncodingType::Gzip,
+                    )
+                    .unwrap();
+            }
+        }
+        assert_eq!(count_logs(), 1);
+        assert_eq!(
+            results
+                .load_log(&ex, &TEST_TOOLCHAIN, &krate2)
+                .unwrap()
+                .unwrap()
+                .to_plain()
+                .unwrap(),
+            b"error: failed to build".to_vec()
+        );
+
+        // Overriding a result doesn't leave the old log around if nothing else references it
+        results
+            .store_result(
+                &ex,
+                &krate1,
+                &MAIN_TOOLCHAIN,
+                TestResult::TestPass,
+                b"ok",
+                EncodingType::Gzip,
+            )
+            .unwrap();
+        assert_eq!(count_logs(), 2);
+        results
+            .store_result(
+                &ex,
+                &krate1,
+                &MAIN_TOOLCHAIN,
+                TestResult::TestPass,
+                b"ok again",
+                EncodingType::Gzip,
+            )
+            .unwrap();
+        assert_eq!(count_logs(), 2);
+
+        // Storing the same result and log again, like when an agent retries an upload, keeps the
+        // log even if only the replaced result referenced it
+        for _ in 0..2 {
+            results
+                .store_result(
+                    &ex,
+                    &krate1,
+                    &MAIN_TOOLCHAIN,
+                    TestResult::TestPass,
+                    b"ok again",
+                    EncodingType::Gzip,
+                )
+                .unwrap();
+        }
+        assert_eq!(count_logs(), 2);
+        assert_eq!(
+            results
+                .load_log(&ex, &MAIN_TOOLCHAIN, &krate1)
+                .unwrap()
+                .unwrap()
+                .to_plain()
+                .unwrap(),
+            b"ok again".to_vec()
+        );
+
+        // Logs are removed once all the results referencing them are deleted
+        results.delete_all_results(&ex).unwrap();
+        assert_eq!(count_logs(), 0);
+    }
+
+    #[test]
+    fn test_lockfiles_deduplication() {
+        let db = Database::temp().unwrap();
+        let results = DatabaseDB::new(&db);
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        let count_lockfiles = || -> i64 {
+            db.query(
+                "SELECT COUNT(*) AS count FROM lockfile_contents;",
+                &[],
+                |r| r.get("count"),
+            )
+            .unwrap()[0]
+        };
+
+        // The lockfile reused by the second toolchain is stored only once
+        for tc in &[&MAIN_TOOLCHAIN, &TEST_TOOLCHAIN] {
+            results
+                .record_lockfile(&ex, tc, &krate, "version = 3\n")
+                .unwrap();
+        }
+        assert_eq!(count_lockfiles(), 1);
+
+        // Replacing a lockfile keeps the content still referenced by the other toolchain
+        results
+            .record_lockfile(&ex, &TEST_TOOLCHAIN, &krate, "version = 4\n")
+            .unwrap();
+        assert_eq!(count_lockfiles(), 2);
+        assert_eq!(
+            results.load_lockfile(&ex, &MAIN_TOOLCHAIN, &krate).unwrap(),
+            Some("version = 3\n".into())
+        );
+        assert_eq!(
+            results.load_lockfile(&ex, &TEST_TOOLCHAIN, &krate).unwrap(),
+            Some("version = 4\n".into())
+        );
+
+        results.delete_all_results(&ex).unwrap();
+        assert_eq!(count_lockfiles(), 0);
+    }
+
     #[test]
     fn test_store() {
         let db = Database::temp().unwrap();
@@ -454,6 +1517,65 @@ mod tests {
                         toolchain: MAIN_TOOLCHAIN.clone(),
                         result: TestResult::TestPass,
                         log: base64::encode("foo"),
+                        members: vec![
+                            MemberResult {
+                                name: "lazy_static".into(),
+                                result: TestResult::TestPass,
+                            },
+                            MemberResult {
+                                name: "lazy_static_derive".into(),
+                                result: TestResult::TestSkipped,
+                            },
+                        ],
+                        failed_targets: vec![FailedTarget {
+                            package: "lazy_static".into(),
+                            kind: TargetKind::Example,
+                            name: "demo".into(),
+                        }],
+                        diagnostics: vec![Diagnostic {
+                            package: "lazy_static".into(),
+                            target_kind: Some(TargetKind::Example),
+                            target_name: Some("demo".into()),
+                            level: DiagnosticLevel::Error,
+                            code: Some("E0425".into()),
+                            message: "cannot find value `x` in this scope".into(),
+                            file: Some("examples/demo.rs".into()),
+                            line: Some(2),
+                            column: Some(5),
+                        }],
+                        diagnostic_counts: Some(DiagnosticCounts {
+                            errors: 1,
+                            warnings: 0,
+                        }),
+                        unit_timings: vec![UnitTiming {
+                            package: "lazy_static".into(),
+                            version: "1.0.0".into(),
+                            target_kind: Some(TargetKind::Lib),
+                            target_name: "lazy_static".into(),
+                            mode: "build".into(),
+                            duration: 1.5,
+                        }],
+                        doc_items: vec![DocItem {
+                            path: "lazy_static::LazyStatic".into(),
+                            kind: "trait".into(),
+                            hash: "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12".into(),
+                        }],
+                        invocations: vec![Invocation {
+                            toolchain: "stable".into(),
+                            cargo_toolchain: None,
+                            args: vec!["test".into(), "--frozen".into()],
+                            env: vec![("CARGO_INCREMENTAL".into(), "0".into())],
+                            memory_limit: Some(1536 * 1024 * 1024),
+                            cpu_limit: None,
+                            cpuset: Some("0-1".into()),
+                        }],
+                        resource_usage: Some(ResourceUsage {
+                            duration: 12.5,
+                            cpus: 2.0,
+                        }),
+                        snapshot: Some(base64::encode("snapshot")),
+                        c_compiler: Some("clang version 10.0.0-4ubuntu1".into()),
+                        lockfile: Some("version = 3\n".into()),
                     }],
                     shas: vec![
                         (
@@ -471,6 +1593,16 @@ mod tests {
                             "beef".into(),
                         ),
                     ],
+                    toolchain_versions: vec![(
+                        MAIN_TOOLCHAIN.clone(),
+                        ToolchainVersion {
+                            release: "1.40.0".into(),
+                            commit_hash: Some("73528e339aae0f17a15ffa49a8ac608f50c6cf14".into()),
+                            commit_date: Some("2019-12-16".into()),
+                            host: Some("x86_64-unknown-linux-gnu".into()),
+                            llvm_version: None,
+                        },
+                    )],
                 },
                 EncodingType::Plain,
             )
@@ -486,5 +1618,164 @@ mod tests {
                 .unwrap(),
             Some(TestResult::TestPass)
         );
+        assert_eq!(
+            results
+                .load_member_results(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            vec![
+                MemberResult {
+                    name: "lazy_static".into(),
+                    result: TestResult::TestPass,
+                },
+                MemberResult {
+                    name: "lazy_static_derive".into(),
+                    result: TestResult::TestSkipped,
+                },
+            ]
+        );
+
+        assert_eq!(
+            results
+                .load_failed_targets(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            vec![FailedTarget {
+                package: "lazy_static".into(),
+                kind: TargetKind::Example,
+                name: "demo".into(),
+            }]
+        );
+
+        let version = results
+            .load_toolchain_version(&ex, &MAIN_TOOLCHAIN)
+            .unwrap()
+            .unwrap();
+        assert_eq!(version.release, "1.40.0");
+        assert_eq!(
+            version.commit_date.as_ref().map(|s| s.as_str()),
+            Some("2019-12-16")
+        );
+        assert!(version.llvm_version.is_none());
+        assert!(results
+            .load_toolchain_version(&ex, &TEST_TOOLCHAIN)
+            .unwrap()
+            .is_none());
+
+        let diagnostics = results
+            .load_diagnostics(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code.as_ref().unwrap(), "E0425");
+        assert_eq!(diagnostics[0].line, Some(2));
+        assert_eq!(
+            results
+                .load_diagnostic_counts(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(DiagnosticCounts {
+                errors: 1,
+                warnings: 0,
+            })
+        );
+        let timings = results
+            .load_unit_timings(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap();
+        assert_eq!(timings.len(), 1);
+        assert_eq!(timings[0].target_kind, Some(TargetKind::Lib));
+        assert!((timings[0].duration - 1.5).abs() < std::f64::EPSILON);
+        let doc_items = results
+            .load_doc_items(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap();
+        assert_eq!(doc_items.len(), 1);
+        assert_eq!(doc_items[0].path, "lazy_static::LazyStatic");
+        let invocations = results
+            .load_invocations(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].args, vec!["test", "--frozen"]);
+        assert_eq!(invocations[0].memory_limit, Some(1536 * 1024 * 1024));
+        assert_eq!(invocations[0].cpuset, Some("0-1".into()));
+        let cpu_time = db
+            .get_row(
+                "SELECT duration * cpus FROM resource_usage \
+                 WHERE experiment = ?1 AND crate = ?2 AND toolchain = ?3;",
+                &[
+                    &ex.name,
+                    &serde_json::to_string(&krate).unwrap(),
+                    &MAIN_TOOLCHAIN.to_string(),
+                ],
+                |row| -> f64 { row.get(0) },
+            )
+            .unwrap();
+        assert_eq!(cpu_time, Some(25.0));
+        assert_eq!(
+            results.load_snapshot(&ex, &MAIN_TOOLCHAIN, &krate).unwrap(),
+            Some(b"snapshot".to_vec())
+        );
+        assert_eq!(
+            results
+                .load_c_compiler(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some("clang version 10.0.0-4ubuntu1".into())
+        );
+        assert_eq!(
+            results.load_c_compilers(&ex, &MAIN_TOOLCHAIN).unwrap(),
+            vec!["clang version 10.0.0-4ubuntu1".to_string()]
+        );
+        assert_eq!(
+            results.load_lockfile(&ex, &MAIN_TOOLCHAIN, &krate).unwrap(),
+            Some("version = 3\n".into())
+        );
+
+        // Member results, failed targets and diagnostics are removed along with the result of
+        // the crate
+        results.delete_result(&ex, &MAIN_TOOLCHAIN, &krate).unwrap();
+        assert!(results
+            .load_member_results(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .is_empty());
+        assert!(results
+            .load_failed_targets(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .is_empty());
+        assert!(results
+            .load_diagnostics(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .is_empty());
+        assert!(results
+            .load_diagnostic_counts(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .is_none());
+        assert!(results
+            .load_unit_timings(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .is_empty());
+        assert!(results
+            .load_doc_items(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .is_empty());
+        assert!(results
+            .load_invocations(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .is_empty());
+        assert!(results
+            .load_snapshot(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .is_none());
+        assert!(results
+            .load_c_compiler(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .is_none());
+        assert!(results
+            .load_lockfile(&ex, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            db.get_row(
+                "SELECT COUNT(*) FROM resource_usage WHERE experiment = ?1;",
+                &[&ex.name],
+                |row| -> i64 { row.get(0) },
+            )
+            .unwrap(),
+            Some(0)
+        );
     }
 }
@@ -1,8 +1,11 @@
-use crate::crates::{Crate, GitHubRepo};
-use crate::experiments::Experiment;
+use crate::crates::{Crate, GitHubRepo, RustVersion};
+use crate::experiments::{CrateSkipReason, Experiment};
 use crate::prelude::*;
-use crate::results::{EncodedLog, ReadResults, TestResult};
-use crate::toolchain::Toolchain;
+use crate::results::{
+    Diagnostic, DiagnosticCounts, DocItem, EncodedLog, FailedTarget, Invocation, MemberResult,
+    ReadResults, TestResult, UnitTiming,
+};
+use crate::toolchain::{Toolchain, ToolchainVersion};
 use std::collections::HashMap;
 
 #[derive(Default)]
@@ -10,6 +13,18 @@ struct DummyData {
     shas: HashMap<GitHubRepo, String>,
     logs: HashMap<(Crate, Toolchain), EncodedLog>,
     results: HashMap<(Crate, Toolchain), TestResult>,
+    members: HashMap<(Crate, Toolchain), Vec<MemberResult>>,
+    failed_targets: HashMap<(Crate, Toolchain), Vec<FailedTarget>>,
+    diagnostics: HashMap<(Crate, Toolchain), Vec<Diagnostic>>,
+    diagnostic_counts: HashMap<(Crate, Toolchain), DiagnosticCounts>,
+    unit_timings: HashMap<(Crate, Toolchain), Vec<UnitTiming>>,
+    doc_items: HashMap<(Crate, Toolchain), Vec<DocItem>>,
+    invocations: HashMap<(Crate, Toolchain), Vec<Invocation>>,
+    snapshots: HashMap<(Crate, Toolchain), Vec<u8>>,
+    c_compilers: HashMap<(Crate, Toolchain), String>,
+    lockfiles: HashMap<(Crate, Toolchain), String>,
+    toolchain_versions: HashMap<Toolchain, ToolchainVersion>,
+    skip_reasons: HashMap<Crate, CrateSkipReason>,
 }
 
 #[derive(Default)]
@@ -54,6 +69,158 @@ impl DummyDB {
             .results
             .insert((krate, tc), res);
     }
+
+    pub fn add_dummy_members(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        members: Vec<MemberResult>,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_insert_with(DummyData::default)
+            .members
+            .insert((krate, tc), members);
+    }
+
+    pub fn add_dummy_failed_targets(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        targets: Vec<FailedTarget>,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_insert_with(DummyData::default)
+            .failed_targets
+            .insert((krate, tc), targets);
+    }
+
+    pub fn add_dummy_diagnostics(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        diagnostics: Vec<Diagnostic>,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_insert_with(DummyData::default)
+            .diagnostics
+            .insert((krate, tc), diagnostics);
+    }
+
+    pub fn add_dummy_diagnostic_counts(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        counts: DiagnosticCounts,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_insert_with(DummyData::default)
+            .diagnostic_counts
+            .insert((krate, tc), counts);
+    }
+
+    pub fn add_dummy_unit_timings(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        timings: Vec<UnitTiming>,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_insert_with(DummyData::default)
+            .unit_timings
+            .insert((krate, tc), timings);
+    }
+
+    pub fn add_dummy_doc_items(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        items: Vec<DocItem>,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_insert_with(DummyData::default)
+            .doc_items
+            .insert((krate, tc), items);
+    }
+
+    pub fn add_dummy_invocations(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        invocations: Vec<Invocation>,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_insert_with(DummyData::default)
+            .invocations
+            .insert((krate, tc), invocations);
+    }
+
+    pub fn add_dummy_snapshot(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        snapshot: Vec<u8>,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_insert_with(DummyData::default)
+            .snapshots
+            .insert((krate, tc), snapshot);
+    }
+
+    pub fn add_dummy_c_compiler(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        version: String,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_insert_with(DummyData::default)
+            .c_compilers
+            .insert((krate, tc), version);
+    }
+
+    pub fn add_dummy_toolchain_version(
+        &mut self,
+        ex: &Experiment,
+        tc: Toolchain,
+        version: ToolchainVersion,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_insert_with(DummyData::default)
+            .toolchain_versions
+            .insert(tc, version);
+    }
+
+    pub fn add_dummy_skip_reason(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        reason: CrateSkipReason,
+    ) {
+        self.experiments
+            .entry(ex.name.to_string())
+            .or_insert_with(DummyData::default)
+            .skip_reasons
+            .insert(krate, reason);
+    }
 }
 
 impl ReadResults for DummyDB {
@@ -86,4 +253,173 @@ impl ReadResults for DummyDB {
             .get(&(krate.clone(), toolchain.clone()))
             .cloned())
     }
+
+    fn load_rust_version(&self, _krate: &Crate) -> Fallible<Option<RustVersion>> {
+        Ok(None)
+    }
+
+    fn load_member_results(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<MemberResult>> {
+        Ok(self
+            .get_data(ex)?
+            .members
+            .get(&(krate.clone(), toolchain.clone()))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn load_failed_targets(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<FailedTarget>> {
+        Ok(self
+            .get_data(ex)?
+            .failed_targets
+            .get(&(krate.clone(), toolchain.clone()))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn load_diagnostics(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<Diagnostic>> {
+        Ok(self
+            .get_data(ex)?
+            .diagnostics
+            .get(&(krate.clone(), toolchain.clone()))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn load_diagnostic_counts(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<DiagnosticCounts>> {
+        Ok(self
+            .get_data(ex)?
+            .diagnostic_counts
+            .get(&(krate.clone(), toolchain.clone()))
+            .cloned())
+    }
+
+    fn load_unit_timings(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<UnitTiming>> {
+        Ok(self
+            .get_data(ex)?
+            .unit_timings
+            .get(&(krate.clone(), toolchain.clone()))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn load_doc_items(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<DocItem>> {
+        Ok(self
+            .get_data(ex)?
+            .doc_items
+            .get(&(krate.clone(), toolchain.clone()))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn load_invocations(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<Invocation>> {
+        Ok(self
+            .get_data(ex)?
+            .invocations
+            .get(&(krate.clone(), toolchain.clone()))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn load_snapshot(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<Vec<u8>>> {
+        Ok(self
+            .get_data(ex)?
+            .snapshots
+            .get(&(krate.clone(), toolchain.clone()))
+            .cloned())
+    }
+
+    fn load_c_compiler(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<String>> {
+        Ok(self
+            .get_data(ex)?
+            .c_compilers
+            .get(&(krate.clone(), toolchain.clone()))
+            .cloned())
+    }
+
+    fn load_c_compilers(&self, ex: &Experiment, toolchain: &Toolchain) -> Fallible<Vec<String>> {
+        let mut versions = self
+            .get_data(ex)?
+            .c_compilers
+            .iter()
+            .filter(|((_, tc), _)| tc == toolchain)
+            .map(|(_, version)| version.clone())
+            .collect::<Vec<_>>();
+        versions.sort();
+        versions.dedup();
+        Ok(versions)
+    }
+
+    fn load_lockfile(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<String>> {
+        Ok(self
+            .get_data(ex)?
+            .lockfiles
+            .get(&(krate.clone(), toolchain.clone()))
+            .cloned())
+    }
+
+    fn load_toolchain_version(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+    ) -> Fallible<Option<ToolchainVersion>> {
+        Ok(self
+            .get_data(ex)?
+            .toolchain_versions
+            .get(toolchain)
+            .cloned())
+    }
+
+    fn load_skip_reasons(&self, ex: &Experiment) -> Fallible<HashMap<Crate, CrateSkipReason>> {
+        Ok(self.get_data(ex)?.skip_reasons.clone())
+    }
 }
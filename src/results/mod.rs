@@ -1,20 +1,28 @@
 mod db;
 #[cfg(test)]
 mod dummy;
+pub(crate) mod normalize;
 use crate::config::Config;
-use crate::crates::{Crate, GitHubRepo};
-use crate::experiments::Experiment;
+use crate::crates::{Crate, GitHubRepo, RustVersion};
+use crate::experiments::{CrateSkipReason, Experiment};
 use crate::prelude::*;
 pub use crate::results::db::{DatabaseDB, ProgressData};
 #[cfg(test)]
 pub use crate::results::dummy::DummyDB;
-use crate::toolchain::Toolchain;
+pub use crate::results::normalize::NormalizationRule;
+use crate::toolchain::{Toolchain, ToolchainVersion};
+pub use crater_results::results::{
+    BrokenReason, Diagnostic, DiagnosticCounts, DiagnosticLevel, DocItem, FailedTarget,
+    FailureReason, Invocation, MemberResult, ResourceUsage, SkipReason, TargetKind, TestResult,
+    TestResultParseError, UnitTiming,
+};
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use rustwide::logging::LogStorage;
+use sha1::{Digest, Sha1};
 use std::collections::HashMap;
-use std::{fmt, io::Read, io::Write, str::FromStr};
+use std::io::{Read, Write};
 
 pub trait ReadResults {
     fn load_all_shas(&self, ex: &Experiment) -> Fallible<HashMap<GitHubRepo, String>>;
@@ -30,6 +38,79 @@ pub trait ReadResults {
         toolchain: &Toolchain,
         krate: &Crate,
     ) -> Fallible<Option<TestResult>>;
+    fn load_rust_version(&self, krate: &Crate) -> Fallible<Option<RustVersion>>;
+    fn load_member_results(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<MemberResult>>;
+    fn load_failed_targets(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<FailedTarget>>;
+    fn load_diagnostics(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<Diagnostic>>;
+    fn load_diagnostic_counts(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<DiagnosticCounts>>;
+    fn load_unit_timings(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<UnitTiming>>;
+    fn load_doc_items(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<DocItem>>;
+    fn load_invocations(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Vec<Invocation>>;
+    /// Load the compressed tarball of the build directory, captured if the build failed.
+    fn load_snapshot(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<Vec<u8>>>;
+    /// Load the version of the C compiler the crate was built with, if the toolchain chose one.
+    fn load_c_compiler(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<String>>;
+    /// Load the distinct versions of the C compiler reported by the builds of a toolchain.
+    fn load_c_compilers(&self, ex: &Experiment, toolchain: &Toolchain) -> Fallible<Vec<String>>;
+    /// Load the `Cargo.lock` captured when building the crate, which is only stored for the
+    /// baseline toolchain.
+    fn load_lockfile(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Fallible<Option<String>>;
+    fn load_toolchain_version(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+    ) -> Fallible<Option<ToolchainVersion>>;
+    fn load_skip_reasons(&self, ex: &Experiment) -> Fallible<HashMap<Crate, CrateSkipReason>>;
 }
 
 pub trait WriteResults {
@@ -52,6 +133,94 @@ pub trait WriteResults {
     ) -> Fallible<TestResult>
     where
         F: FnOnce() -> Fallible<TestResult>;
+    fn record_member_results(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        members: &[MemberResult],
+    ) -> Fallible<()>;
+    fn record_failed_targets(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        targets: &[FailedTarget],
+    ) -> Fallible<()>;
+    fn record_diagnostics(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        diagnostics: &[Diagnostic],
+    ) -> Fallible<()>;
+    fn record_diagnostic_counts(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        counts: DiagnosticCounts,
+    ) -> Fallible<()>;
+    fn record_unit_timings(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        timings: &[UnitTiming],
+    ) -> Fallible<()>;
+    fn record_doc_items(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        items: &[DocItem],
+    ) -> Fallible<()>;
+    fn record_invocations(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        invocations: &[Invocation],
+    ) -> Fallible<()>;
+    fn record_resource_usage(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        usage: ResourceUsage,
+    ) -> Fallible<()>;
+    fn record_snapshot(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        snapshot: &[u8],
+    ) -> Fallible<()>;
+    fn record_c_compiler(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        version: &str,
+    ) -> Fallible<()>;
+    fn record_lockfile(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        lockfile: &str,
+    ) -> Fallible<()>;
+    fn record_toolchain_version(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        version: &ToolchainVersion,
+    ) -> Fallible<()>;
+    /// Report that a worker crashed while running the experiment, before it's restarted.
+    fn record_worker_crash(&self, ex: &Experiment, worker: &str, error: &str) -> Fallible<()>;
+    /// Check whether the experiment was cancelled (or deleted), in which case no new task should
+    /// be started.
+    fn is_cancelled(&self, ex: &Experiment) -> Fallible<bool>;
 }
 
 pub trait DeleteResults {
@@ -97,6 +266,15 @@ impl EncodedLog {
         }
     }
 
+    /// Hash identifying the stored content of the log, used to deduplicate identical logs.
+    pub fn content_hash(&self) -> String {
+        let mut hasher = Sha1::new();
+        hasher.input(self.get_encoding_type().to_str().as_bytes());
+        hasher.input(b"\0");
+        hasher.input(self.as_slice());
+        format!("{:x}", hasher.result())
+    }
+
     pub fn from_plain_slice(data: &[u8], desired_encoding: EncodingType) -> Fallible<EncodedLog> {
         match desired_encoding {
             EncodingType::Gzip => {
@@ -110,140 +288,9 @@ impl EncodedLog {
     }
 }
 
-macro_rules! test_result_enum {
-    (pub enum $name:ident {
-        with_reason { $($with_reason_name:ident($reason:ident) => $with_reason_repr:expr,)* }
-        without_reason { $($reasonless_name:ident => $reasonless_repr:expr,)* }
-    }) => {
-        #[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
-        pub enum $name {
-            $($with_reason_name($reason),)*
-            $($reasonless_name,)*
-        }
-
-        impl FromStr for $name {
-            type Err = ::failure::Error;
-
-            fn from_str(input: &str) -> Fallible<Self> {
-                let parts: Vec<&str> = input.split(':').collect();
-
-                if parts.len() == 1 {
-                    match parts[0] {
-                        $($with_reason_repr => Ok($name::$with_reason_name($reason::Unknown)),)*
-                        $($reasonless_repr => Ok($name::$reasonless_name),)*
-                        other => Err(TestResultParseError::UnknownResult(other.into()).into()),
-                    }
-                } else if parts.len() == 2 {
-                    match parts[0] {
-                        $($reasonless_repr => Err(TestResultParseError::UnexpectedFailureReason.into()),)*
-                        $($with_reason_repr => Ok($name::$with_reason_name(parts[1].parse()?)),)*
-                        other => Err(TestResultParseError::UnknownResult(other.into()).into()),
-                    }
-                } else {
-                    Err(TestResultParseError::TooManySegments.into())
-                }
-            }
-        }
-
-        impl fmt::Display for $name {
-            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                match self {
-                    $($name::$with_reason_name(reason) => write!(f, "{}:{}", $with_reason_repr, reason),)*
-                    $($name::$reasonless_name => write!(f, "{}", $reasonless_repr),)*
-                }
-            }
-        }
-    }
-}
-
-#[derive(Debug, Fail)]
-pub enum TestResultParseError {
-    #[fail(display = "unknown test result: {}", _0)]
-    UnknownResult(String),
-    #[fail(display = "unexpected failure reason")]
-    UnexpectedFailureReason,
-    #[fail(display = "too many segments")]
-    TooManySegments,
-}
-
-string_enum!(pub enum FailureReason {
-    Unknown => "unknown",
-    OOM => "oom",
-    Timeout => "timeout",
-});
-
-impl FailureReason {
-    pub(crate) fn is_spurious(self) -> bool {
-        match self {
-            FailureReason::Unknown => false,
-            FailureReason::OOM | FailureReason::Timeout => true,
-        }
-    }
-}
-
-string_enum!(pub enum BrokenReason {
-    Unknown => "unknown",
-    CargoToml => "cargo-toml",
-    Yanked => "yanked",
-    MissingGitRepository => "missing-git-repository",
-});
-
-test_result_enum!(pub enum TestResult {
-    with_reason {
-        BrokenCrate(BrokenReason) => "broken",
-        BuildFail(FailureReason) => "build-fail",
-        TestFail(FailureReason) => "test-fail",
-    }
-    without_reason {
-        TestSkipped => "test-skipped",
-        TestPass => "test-pass",
-        Error => "error",
-    }
-});
-
-impl_serde_from_parse!(TestResult, expecting = "a test result");
-
-#[cfg(test)]
-mod tests {
-    use std::str::FromStr;
-
-    #[test]
-    fn test_test_result_parsing() {
-        use super::{
-            FailureReason::*,
-            TestResult::{self, *},
-        };
-
-        macro_rules! test_from_str {
-            ($($str:expr => $rust:expr,)*) => {
-                $(
-                    // Test parsing from string to rust
-                    assert_eq!(TestResult::from_str($str).unwrap(), $rust);
-
-                    // Test dumping from rust to string
-                    assert_eq!(&$rust.to_string(), $str);
-
-                    // Test dumping from rust to string to rust
-                    assert_eq!(TestResult::from_str($rust.to_string().as_ref()).unwrap(), $rust);
-                )*
-            };
-        }
-
-        test_from_str! {
-            "build-fail:unknown" => BuildFail(Unknown),
-            "build-fail:oom" => BuildFail(OOM),
-            "test-fail:timeout" => TestFail(Timeout),
-            "test-pass" => TestPass,
-            "error" => Error,
-        }
-
-        // Backward compatibility
-        assert_eq!(
-            TestResult::from_str("build-fail").unwrap(),
-            BuildFail(Unknown)
-        );
-
-        assert!(TestResult::from_str("error:oom").is_err());
-        assert!(TestResult::from_str("build-fail:pleasedonotaddthis").is_err());
-    }
+/// Hash identifying the content of a `Cargo.lock`, used to store identical lockfiles only once.
+pub(crate) fn lockfile_hash(lockfile: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input(lockfile.as_bytes());
+    format!("{:x}", hasher.result())
 }
@@ -0,0 +1,161 @@
+use crate::prelude::*;
+use regex::Regex;
+use sha1::{Digest, Sha1};
+
+// Parts of the build logs that change between runs of the same build, and are removed before
+// comparing the logs.
+string_enum!(pub enum NormalizationRule {
+    Timestamps => "timestamps",
+    Durations => "durations",
+    TempPaths => "temp-paths",
+    Hashes => "hashes",
+    Addresses => "addresses",
+    Ordering => "ordering",
+});
+
+impl NormalizationRule {
+    /// Rules applied to the experiments whose mode isn't configured in `config.toml`.
+    pub const DEFAULT: &'static [NormalizationRule] = &[
+        NormalizationRule::Timestamps,
+        NormalizationRule::Durations,
+        NormalizationRule::TempPaths,
+        NormalizationRule::Hashes,
+        NormalizationRule::Addresses,
+        NormalizationRule::Ordering,
+    ];
+}
+
+lazy_static! {
+    static ref TIMESTAMP: Regex = Regex::new(
+        r"\b\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:?\d{2})?|\b\d{2}:\d{2}:\d{2}(\.\d+)?\b"
+    )
+    .unwrap();
+    static ref DURATION: Regex = Regex::new(r"\b\d+(\.\d+)?(s|ms|us|µs|ns)\b").unwrap();
+    static ref TEMP_PATH: Regex =
+        Regex::new(r#"(/tmp|/var/folders|(?i:[a-z]:\\[^\s]*\\temp))[/\\][^\s:'"`)]*"#).unwrap();
+    static ref ADDRESS: Regex = Regex::new(r"\b0x[0-9a-fA-F]{6,16}\b").unwrap();
+    static ref HASH: Regex = Regex::new(r"\b[0-9a-f]{16,64}\b").unwrap();
+}
+
+/// Normalize the build log, to compare it with the logs of other runs.
+pub(crate) fn normalize(log: &[u8], rules: &[NormalizationRule]) -> String {
+    let mut log = String::from_utf8_lossy(log).into_owned();
+
+    // The rules are always applied in the same order, as the timestamps would otherwise be
+    // partially replaced by the durations, and the addresses by the hashes
+    let replacements: &[(NormalizationRule, &Regex, &str)] = &[
+        (NormalizationRule::TempPaths, &TEMP_PATH, "[tmp]"),
+        (NormalizationRule::Timestamps, &TIMESTAMP, "[timestamp]"),
+        (NormalizationRule::Durations, &DURATION, "[duration]"),
+        (NormalizationRule::Addresses, &ADDRESS, "[address]"),
+        (NormalizationRule::Hashes, &HASH, "[hash]"),
+    ];
+    for (rule, regex, replacement) in replacements {
+        if rules.contains(rule) {
+            log = regex.replace_all(&log, *replacement).into_owned();
+        }
+    }
+
+    if rules.contains(&NormalizationRule::Ordering) {
+        log = sort_interleaved_lines(&log);
+    }
+    log
+}
+
+/// Hash of the normalized log, which is the same for the logs only differing in the parts removed
+/// by the rules.
+pub(crate) fn fingerprint(log: &[u8], rules: &[NormalizationRule]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input(normalize(log, rules).as_bytes());
+    format!("{:x}", hasher.result())
+}
+
+/// Sort the consecutive lines starting with the same word, like the `Compiling` lines of Cargo or
+/// the `test` lines of libtest, as their order depends on which job finishes first.
+fn sort_interleaved_lines(log: &str) -> String {
+    fn first_word(line: &str) -> &str {
+        line.trim_start().split(' ').next().unwrap_or("")
+    }
+
+    let mut result = String::with_capacity(log.len());
+    let mut lines = log.lines().peekable();
+    while let Some(line) = lines.next() {
+        let word = first_word(line);
+        let mut group = vec![line];
+        while let Some(next) = lines.peek() {
+            if word.is_empty() || first_word(next) != word {
+                break;
+            }
+            group.push(lines.next().unwrap());
+        }
+
+        group.sort();
+        for line in group {
+            result.push_str(line);
+            result.push('\n');
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fingerprint, normalize, NormalizationRule};
+
+    #[test]
+    fn test_normalize() {
+        let all = NormalizationRule::DEFAULT;
+        let check = |log: &str, rules: &[NormalizationRule], expected: &str| {
+            assert_eq!(normalize(log.as_bytes(), rules), expected);
+        };
+
+        check(
+            "[2019-01-01T12:34:56Z INFO] started at 12:34:56.789",
+            all,
+            "[[timestamp] INFO] started at [timestamp]\n",
+        );
+        check(
+            "    Finished dev [unoptimized + debuginfo] target(s) in 3.45s",
+            all,
+            "    Finished dev [unoptimized + debuginfo] target(s) in [duration]\n",
+        );
+        check(
+            "error: couldn't read /tmp/.tmpAb12Cd/src/lib.rs: not found",
+            all,
+            "error: couldn't read [tmp]: not found\n",
+        );
+        check(
+            "target/debug/deps/foo-1a2b3c4d5e6f7a8b.rlib",
+            all,
+            "target/debug/deps/foo-[hash].rlib\n",
+        );
+        check("segfault at 0x7ffd5e8c1a40", all, "segfault at [address]\n");
+        check(
+            "running 2 tests\ntest b ... ok\ntest a ... ok\n\nresult: ok",
+            all,
+            "running 2 tests\ntest a ... ok\ntest b ... ok\n\nresult: ok\n",
+        );
+
+        // Only the requested rules are applied
+        check(
+            "test b ... ok in 1.2s\ntest a ... ok in 3.4s",
+            &[NormalizationRule::Durations],
+            "test b ... ok in [duration]\ntest a ... ok in [duration]",
+        );
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let rules = NormalizationRule::DEFAULT;
+        let before = b"   Compiling foo v0.1.0 (/tmp/.tmp123456)\n   Compiling bar v0.1.0\n\
+                       error[E0308]: mismatched types\n    Finished in 1.23s";
+        let after = b"   Compiling bar v0.1.0\n   Compiling foo v0.1.0 (/tmp/.tmpabcdef)\n\
+                      error[E0308]: mismatched types\n    Finished in 4.56s";
+        let different = b"   Compiling bar v0.1.0\n   Compiling foo v0.1.0 (/tmp/.tmpabcdef)\n\
+                          error[E0599]: no method found\n    Finished in 4.56s";
+
+        assert_eq!(fingerprint(before, rules), fingerprint(after, rules));
+        assert_ne!(fingerprint(before, rules), fingerprint(different, rules));
+        assert_ne!(fingerprint(before, &[]), fingerprint(after, &[]));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::actions::{Action, ActionsCtx, DeleteExperiment};
+use crate::audit::{self, Actor, AuditAction};
+use crate::config::{Config, RetentionConfig};
+use crate::db::{Database, QueryUtils};
+use crate::experiments::{Experiment, Status};
+use crate::prelude::*;
+use crate::results::EncodedLog;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+
+/// Content replacing the build logs removed by the retention policy.
+const PRUNED_LOG: &[u8] = b"This log was removed by Crater's data retention policy.\n";
+
+fn pruned_log() -> EncodedLog {
+    EncodedLog::Plain(PRUNED_LOG.to_vec())
+}
+
+/// Data that should be removed according to the retention policy.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RetentionPlan {
+    /// Experiments whose build logs should be removed.
+    pub prune_logs: Vec<String>,
+    /// Experiments that should be deleted altogether.
+    pub delete: Vec<String>,
+}
+
+impl RetentionPlan {
+    pub fn is_empty(&self) -> bool {
+        self.prune_logs.is_empty() && self.delete.is_empty()
+    }
+}
+
+fn finished_at(ex: &Experiment) -> Option<DateTime<Utc>> {
+    match ex.status {
+        Status::Completed
+        | Status::Cancelled
+        | Status::Failed
+        | Status::ToolchainInstallFailed
+        | Status::Archived => Some(ex.completed_at.unwrap_or(ex.created_at)),
+        _ => None,
+    }
+}
+
+/// Find out which data should be removed at the time `now`, without removing anything.
+pub fn plan(
+    db: &Database,
+    config: &RetentionConfig,
+    now: DateTime<Utc>,
+) -> Fallible<RetentionPlan> {
+    let pruned_hash = pruned_log().content_hash();
+    let mut plan = RetentionPlan::default();
+
+    for ex in Experiment::all(db)? {
+        let finished_at = match finished_at(&ex) {
+            Some(finished_at) => finished_at,
+            None => continue,
+        };
+        let expired = |days: Option<u32>| {
+            days.map(|days| finished_at + Duration::days(days.into()) < now)
+                .unwrap_or(false)
+        };
+
+        if !ex.pinned && expired(config.experiments_days) {
+            plan.delete.push(ex.name);
+        } else if expired(config.logs_days)
+            && db.exists(
+                "SELECT rowid FROM results WHERE experiment = ?1 AND log != ?2 LIMIT 1;",
+                &[&ex.name, &pruned_hash],
+            )?
+        {
+            plan.prune_logs.push(ex.name);
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Remove the data listed in the plan, recording every change in the audit log.
+pub fn enforce(
+    db: &Database,
+    config: &Config,
+    actor: &Actor,
+    plan: &RetentionPlan,
+) -> Fallible<()> {
+    let pruned = pruned_log();
+    let pruned_hash = pruned.content_hash();
+
+    for name in &plan.prune_logs {
+        db.transaction(|t| {
+            t.execute(
+                "INSERT OR IGNORE INTO logs (hash, encoding, content) VALUES (?1, ?2, ?3);",
+                &[
+                    &pruned_hash,
+                    &pruned.get_encoding_type().to_str(),
+                    &pruned.as_slice(),
+                ],
+            )?;
+            // The triggers on the results table take care of removing the old logs
+            t.execute(
+                "UPDATE results SET log = ?1 WHERE experiment = ?2 AND log != ?1;",
+                &[&pruned_hash, name],
+            )?;
+            audit::record(t, actor, AuditAction::PruneLogs, Some(name), &json!({}))
+        })?;
+    }
+
+    let ctx = ActionsCtx::new(db, config).with_actor(actor.clone());
+    for name in &plan.delete {
+        DeleteExperiment { name: name.clone() }.apply(&ctx)?;
+    }
+
+    Ok(())
+}
+
+/// Apply the retention policy configured in `config.toml`, only reporting what would be removed
+/// if the policy is in dry-run mode.
+pub fn run(db: &Database, config: &Config, actor: &Actor) -> Fallible<RetentionPlan> {
+    let retention = &config.server.retention;
+    let plan = plan(db, retention, Utc::now())?;
+
+    for name in &plan.prune_logs {
+        info!("retention: removing the logs of experiment {}", name);
+    }
+    for name in &plan.delete {
+        info!("retention: deleting experiment {}", name);
+    }
+
+    if retention.dry_run {
+        if !plan.is_empty() {
+            info!("retention: dry-run mode enabled, nothing was removed");
+        }
+    } else {
+        enforce(db, config, actor, &plan)?;
+    }
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{enforce, plan, RetentionPlan, PRUNED_LOG};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::audit::Actor;
+    use crate::config::{Config, RetentionConfig};
+    use crate::crates::{Crate, RegistryCrate};
+    use crate::db::Database;
+    use crate::experiments::{Experiment, Status};
+    use crate::results::{DatabaseDB, EncodingType, ReadResults, TestResult, WriteResults};
+    use crate::toolchain::MAIN_TOOLCHAIN;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn test_retention() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        let results = DatabaseDB::new(&db);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        for name in &["old", "pinned", "running"] {
+            CreateExperiment::dummy(name).apply(&ctx).unwrap();
+            let ex = Experiment::get(&db, name).unwrap().unwrap();
+            results
+                .store_result(
+                    &ex,
+                    &krate,
+                    &MAIN_TOOLCHAIN,
+                    TestResult::TestPass,
+                    b"test result: ok",
+                    EncodingType::Plain,
+                )
+                .unwrap();
+        }
+        let mut old = Experiment::get(&db, "old").unwrap().unwrap();
+        old.set_status(&db, Status::Completed).unwrap();
+        let mut pinned = Experiment::get(&db, "pinned").unwrap().unwrap();
+        pinned.set_status(&db, Status::Failed).unwrap();
+        pinned.set_pinned(&db, true).unwrap();
+
+        let retention = RetentionConfig {
+            logs_days: Some(90),
+            experiments_days: Some(365),
+            dry_run: false,
+        };
+
+        // Nothing expired yet
+        assert!(plan(&db, &retention, Utc::now()).unwrap().is_empty());
+
+        // Only the logs expired
+        let in_100_days = Utc::now() + Duration::days(100);
+        assert_eq!(
+            plan(&db, &retention, in_100_days).unwrap(),
+            RetentionPlan {
+                prune_logs: vec!["old".into(), "pinned".into()],
+                delete: vec![],
+            }
+        );
+
+        // Pinned experiments are never deleted
+        let in_400_days = Utc::now() + Duration::days(400);
+        let expired = plan(&db, &retention, in_400_days).unwrap();
+        assert_eq!(
+            expired,
+            RetentionPlan {
+                prune_logs: vec!["pinned".into()],
+                delete: vec!["old".into()],
+            }
+        );
+
+        enforce(&db, &config, &Actor::Server, &expired).unwrap();
+        assert!(!Experiment::exists(&db, "old").unwrap());
+        assert!(plan(&db, &retention, in_400_days).unwrap().is_empty());
+
+        // The results are kept, only the logs are replaced
+        let log = results
+            .load_log(&pinned, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .unwrap();
+        assert_eq!(log.to_plain().unwrap(), PRUNED_LOG.to_vec());
+        let running = Experiment::get(&db, "running").unwrap().unwrap();
+        let log = results
+            .load_log(&running, &MAIN_TOOLCHAIN, &krate)
+            .unwrap()
+            .unwrap();
+        assert_eq!(log.to_plain().unwrap(), b"test result: ok".to_vec());
+    }
+}
@@ -2,18 +2,20 @@
 // executed in order to complete the Crater run. Once the DAG is created, a number of worker
 // threads are spawned, and each thread picks the first task without dependencies from the DAG and
 // marks it as running, removing it when the task is done. The next task then is picked using a
-// depth-first search.
+// depth-first search. The builds with the other toolchains also wait for the one with the
+// baseline toolchain, as they reuse the `Cargo.lock` generated by it, but they still run if the
+// baseline build fails: they then use the `Cargo.lock` they generated themselves.
 //
 //                                   +---+ tc1 <---+
-//                                   |             |
-//          +---+ crate-complete <---+             +---+ prepare
-//          |                        |             |
+//                                   |      .      |
+//          +---+ crate-complete <---+      .      +---+ prepare
+//          |                        |      .      |
 //          |                        +---+ tc2 <---+
 // root <---+
 //          |                        +---+ tc1 <---+
-//          |                        |             |
-//          +---+ crate-complete <---+             +---+ prepare
-//                                   |             |
+//          |                        |      .      |
+//          +---+ crate-complete <---+      .      +---+ prepare
+//                                   |      .      |
 //                                   +---+ tc2 <---+
 
 use crate::config::Config;
@@ -22,10 +24,13 @@ use crate::experiments::{Experiment, Mode};
 use crate::prelude::*;
 use crate::results::{TestResult, WriteResults};
 use crate::runner::{
+    lockfile,
     tasks::{Task, TaskStep},
     RunnerState,
 };
+use petgraph::visit::EdgeRef;
 use petgraph::{dot::Dot, graph::NodeIndex, stable_graph::StableDiGraph, Direction};
+use std::collections::HashSet;
 use std::fmt::{self, Debug};
 use std::sync::Arc;
 
@@ -52,6 +57,15 @@ impl Debug for Node {
     }
 }
 
+/// Edge between a node and one of the nodes it waits for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dependency {
+    /// The node can't be executed if the dependency failed.
+    Required,
+    /// The node is only executed after the dependency, even if it failed.
+    Ordering,
+}
+
 #[derive(Debug)]
 pub(super) enum WalkResult {
     Task(NodeIndex, Arc<Task>),
@@ -72,7 +86,7 @@ impl WalkResult {
 
 #[derive(Default)]
 pub(super) struct TasksGraph {
-    graph: StableDiGraph<Node, ()>,
+    graph: StableDiGraph<Node, Dependency>,
     root: NodeIndex,
 }
 
@@ -104,12 +118,25 @@ impl TasksGraph {
         let id = self.graph.add_node(node);
 
         for dep in deps {
-            self.graph.add_edge(id, *dep, ());
+            self.graph.add_edge(id, *dep, Dependency::Required);
         }
 
         id
     }
 
+    /// Execute `node` only after `dep` is completed or failed.
+    fn add_ordering(&mut self, node: NodeIndex, dep: NodeIndex) {
+        self.graph.add_edge(node, dep, Dependency::Ordering);
+    }
+
+    /// Number of nodes that must be executed successfully before `node`.
+    fn required_parents(&self, node: NodeIndex) -> usize {
+        self.graph
+            .edges_directed(node, Direction::Outgoing)
+            .filter(|edge| *edge.weight() == Dependency::Required)
+            .count()
+    }
+
     pub(super) fn next_task<DB: WriteResults>(&mut self, ex: &Experiment, db: &DB) -> WalkResult {
         let root = self.root;
         self.walk_graph(root, ex, db)
@@ -200,16 +227,16 @@ impl TasksGraph {
         error: &failure::Error,
         result: TestResult,
     ) -> Fallible<()> {
+        // The children only ordered after this node are executed anyway
         let mut children = self
             .graph
-            .neighbors_directed(node, Direction::Incoming)
+            .edges_directed(node, Direction::Incoming)
+            .filter(|edge| *edge.weight() == Dependency::Required)
+            .map(|edge| edge.source())
             .collect::<Vec<_>>();
         for child in children.drain(..) {
             // Don't recursively mark a child as failed if this is not the only parent of the child
-            let parents = self
-                .graph
-                .neighbors_directed(child, Direction::Outgoing)
-                .count();
+            let parents = self.required_parents(child);
             if parents > 1 {
                 log::trace!(
                     "{:?}: prevented recursive mark_as_failed as it has other parents",
@@ -236,18 +263,25 @@ impl TasksGraph {
         self.graph.neighbors(self.root).count()
     }
 
-    pub(super) fn generate_dot<'a>(&'a self) -> Dot<&'a StableDiGraph<impl Debug, ()>> {
+    pub(super) fn generate_dot<'a>(&'a self) -> Dot<&'a StableDiGraph<impl Debug, impl Debug>> {
         Dot::new(&self.graph)
     }
 }
 
 pub(super) fn build_graph(ex: &Experiment, crates: &[Crate], config: &Config) -> TasksGraph {
     let mut graph = TasksGraph::new();
+    let mut seen = HashSet::new();
+    let toolchains = ex.run_toolchains();
 
     for krate in crates {
         if !ex.ignore_blacklist && config.should_skip(krate) {
             continue;
         }
+        // Scheduling the same crate twice would build it concurrently in two workers
+        if !seen.insert(krate) {
+            debug!("skipping duplicate crate {}", krate);
+            continue;
+        }
 
         let prepare_id = graph.add_task(
             Task {
@@ -259,7 +293,14 @@ pub(super) fn build_graph(ex: &Experiment, crates: &[Crate], config: &Config) ->
 
         let quiet = config.is_quiet(krate);
         let mut builds = Vec::new();
-        for tc in &ex.toolchains {
+        let mut baseline_id = None;
+        for tc in &toolchains {
+            // The toolchains of each target are listed after their baseline
+            let baseline = if lockfile::is_baseline(ex, tc) {
+                None
+            } else {
+                baseline_id
+            };
             let build_id = graph.add_task(
                 Task {
                     krate: krate.clone(),
@@ -268,7 +309,7 @@ pub(super) fn build_graph(ex: &Experiment, crates: &[Crate], config: &Config) ->
                             tc: tc.clone(),
                             quiet,
                         },
-                        Mode::BuildAndTest
+                        Mode::BuildAndTest | Mode::Miri | Mode::DocTest
                             if !ex.ignore_blacklist && config.should_skip_tests(krate) =>
                         {
                             TaskStep::BuildOnly {
@@ -292,11 +333,35 @@ pub(super) fn build_graph(ex: &Experiment, crates: &[Crate], config: &Config) ->
                             tc: tc.clone(),
                             quiet,
                         },
+                        Mode::RustdocJson => TaskStep::RustdocJson {
+                            tc: tc.clone(),
+                            quiet,
+                        },
                         Mode::UnstableFeatures => TaskStep::UnstableFeatures { tc: tc.clone() },
+                        Mode::BuildBenches => TaskStep::BuildBenches {
+                            tc: tc.clone(),
+                            quiet,
+                        },
+                        Mode::Miri => TaskStep::Miri {
+                            tc: tc.clone(),
+                            quiet,
+                        },
+                        Mode::Fix => TaskStep::Fix {
+                            tc: tc.clone(),
+                            quiet,
+                        },
+                        Mode::DocTest => TaskStep::DocTest {
+                            tc: tc.clone(),
+                            quiet,
+                        },
                     },
                 },
                 &[prepare_id],
             );
+            match baseline {
+                Some(baseline) => graph.add_ordering(build_id, baseline),
+                None => baseline_id = Some(build_id),
+            }
 
             builds.push(build_id);
         }
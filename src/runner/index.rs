@@ -0,0 +1,125 @@
+use crate::crates::IndexSnapshot;
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use rustwide::cmd::Command;
+use rustwide::Workspace;
+use std::fs;
+use std::path::Path;
+
+/// Name of the source replacing crates.io when the index is pinned to a snapshot.
+const SNAPSHOT_SOURCE: &str = "crater-index-snapshot";
+
+/// Manifest of the crate used to fetch the snapshot of the index in cargo's cache.
+const FETCH_MANIFEST: &str = "[package]\n\
+                              name = \"crater-index-snapshot\"\n\
+                              version = \"0.0.0\"\n\
+                              \n\
+                              [dependencies]\n\
+                              lazy_static = \"*\"\n";
+
+/// Cargo configuration replacing crates.io with the local clone of the index at `snapshot`.
+fn cargo_config(snapshot: &IndexSnapshot) -> String {
+    format!(
+        "[source.crates-io]\n\
+         replace-with = \"{name}\"\n\
+         \n\
+         [source.{name}]\n\
+         registry = \"file://{path}\"\n",
+        name = SNAPSHOT_SOURCE,
+        path = snapshot.registry_path().display(),
+    )
+}
+
+/// Make cargo resolve the dependencies of the crates in `dir` against the snapshot of the index
+/// the experiment is pinned to, if any. The configuration applies to the subdirectories too.
+pub(super) fn write_cargo_config(ex: &Experiment, dir: &Path) -> Fallible<()> {
+    // Older versions of cargo don't load `config.toml`
+    let path = dir.join(".cargo").join("config");
+    if let Some(snapshot) = &ex.index_snapshot {
+        fs::create_dir_all(dir.join(".cargo"))?;
+        fs::write(&path, cargo_config(snapshot))?;
+    } else if path.exists() {
+        // Left over by an experiment interrupted before its build directories were purged
+        fs::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+/// Clone the index at the snapshot the experiment is pinned to, if any, and fetch it in cargo's
+/// cache: the builds don't update the registries by themselves.
+pub(super) fn prepare(ex: &Experiment, workspace: &Workspace) -> Fallible<()> {
+    let snapshot = match &ex.index_snapshot {
+        Some(snapshot) => snapshot,
+        None => return Ok(()),
+    };
+    info!(
+        "pinning the crates.io index to commit {} ({})",
+        snapshot.commit, snapshot.date
+    );
+    snapshot.prepare_registry()?;
+
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("Cargo.toml"), FETCH_MANIFEST)?;
+    fs::create_dir(dir.path().join("src"))?;
+    fs::write(dir.path().join("src").join("lib.rs"), "")?;
+    write_cargo_config(ex, dir.path())?;
+
+    let toolchain = &ex.toolchains[0];
+    let cargo_toolchain = toolchain.cargo_toolchain();
+    Command::new(
+        workspace,
+        cargo_toolchain
+            .as_ref()
+            .unwrap_or(&toolchain.source)
+            .cargo(),
+    )
+    .args(&["generate-lockfile"])
+    .args(&toolchain.cargo_flags)
+    .cd(dir.path())
+    .run()
+    .with_context(|_| "failed to fetch the snapshot of the crates.io index")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_cargo_config;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::crates::IndexSnapshot;
+    use crate::db::Database;
+    use crate::experiments::Experiment;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_write_cargo_config() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo").apply(&ctx).unwrap();
+        let mut ex = Experiment::get(&db, "foo").unwrap().unwrap();
+
+        // Nothing is written for the experiments not pinned to a snapshot
+        let dir = tempfile::tempdir().unwrap();
+        write_cargo_config(&ex, dir.path()).unwrap();
+        assert!(!dir.path().join(".cargo").exists());
+
+        let snapshot = IndexSnapshot {
+            commit: "0123456789abcdef0123456789abcdef01234567".into(),
+            date: Utc.timestamp(1_546_300_800, 0),
+        };
+        ex.index_snapshot = Some(snapshot.clone());
+        write_cargo_config(&ex, dir.path()).unwrap();
+        let config = std::fs::read_to_string(dir.path().join(".cargo").join("config")).unwrap();
+        assert!(config.contains("replace-with = \"crater-index-snapshot\""));
+        assert!(config.contains(&format!(
+            "registry = \"file://{}\"",
+            snapshot.registry_path().display()
+        )));
+
+        ex.index_snapshot = None;
+        write_cargo_config(&ex, dir.path()).unwrap();
+        assert!(!dir.path().join(".cargo").join("config").exists());
+    }
+}
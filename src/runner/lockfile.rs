@@ -0,0 +1,68 @@
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::results::WriteResults;
+use crate::runner::tasks::TaskCtx;
+use crate::toolchain::Toolchain;
+use rustwide::cmd::Command;
+use rustwide::Build;
+use std::fs;
+
+/// Check whether the toolchain is the baseline of the experiment, on any of its targets.
+pub(super) fn is_baseline(ex: &Experiment, toolchain: &Toolchain) -> bool {
+    ex.target_toolchains()
+        .iter()
+        .any(|(_, toolchains)| toolchains[0] == *toolchain)
+}
+
+/// Build the crate with the same dependencies for every toolchain: the `Cargo.lock` generated for
+/// the baseline toolchain is stored, and replaces the one generated for the other toolchain, which
+/// could resolve different versions if the index or the resolver of cargo changed in between.
+pub(super) fn capture_or_reuse<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+) -> Fallible<()> {
+    let path = build_env.host_source_dir().join("Cargo.lock");
+    let current =
+        fs::read_to_string(&path).with_context(|_| "failed to read the Cargo.lock of the crate")?;
+
+    if is_baseline(ctx.experiment, ctx.toolchain) {
+        ctx.db
+            .record_lockfile(ctx.experiment, ctx.toolchain, ctx.krate, &current)?;
+        ctx.state
+            .lock()
+            .lockfiles
+            .insert(ctx.krate.clone(), current);
+        return Ok(());
+    }
+
+    // Missing when the baseline build failed before generating it, or wasn't run by this agent
+    let baseline = match ctx.state.lock().lockfiles.get(ctx.krate) {
+        Some(baseline) => baseline.clone(),
+        None => return Ok(()),
+    };
+    if baseline == current {
+        return Ok(());
+    }
+
+    info!("reusing the Cargo.lock generated with the baseline toolchain");
+    fs::write(&path, &baseline)?;
+
+    // The dependencies are fetched outside the sandbox, which doesn't have network access
+    let cargo_toolchain = ctx.toolchain.cargo_toolchain();
+    let toolchain = cargo_toolchain.as_ref().unwrap_or(&ctx.toolchain.source);
+    let res = Command::new(ctx.workspace, toolchain.cargo())
+        .args(&["fetch", "--locked"])
+        .args(&ctx.toolchain.cargo_flags)
+        .cd(build_env.host_source_dir())
+        .run();
+    if let Err(err) = res {
+        // Older versions of cargo can't read the lockfiles written by newer ones
+        warn!(
+            "failed to fetch the dependencies locked with the baseline toolchain, \
+             using the ones resolved with {}: {}",
+            ctx.toolchain, err
+        );
+        fs::write(&path, &current)?;
+    }
+    Ok(())
+}
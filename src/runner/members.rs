@@ -0,0 +1,700 @@
+use crate::prelude::*;
+use crate::results::{
+    Diagnostic, DiagnosticCounts, DiagnosticLevel, FailedTarget, FailureReason, MemberResult,
+    TargetKind, TestResult, UnitTiming,
+};
+use std::collections::HashSet;
+
+/// Maximum number of warnings recorded for a single build, as some crates emit thousands of them.
+/// Errors are always recorded, as they're needed to categorize the failures.
+const MAX_WARNINGS: usize = 1000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct CargoMessage {
+    reason: String,
+    package_id: Option<String>,
+    target: Option<Target>,
+    message: Option<CompilerMessage>,
+    /// Only present in the messages emitted by `--timings=json`.
+    mode: Option<String>,
+    duration: Option<f64>,
+}
+
+/// Output of `cargo metadata --no-deps`.
+#[derive(Deserialize)]
+struct CargoMetadata {
+    workspace_members: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    kind: Vec<String>,
+    name: String,
+}
+
+impl Target {
+    fn kind(&self) -> Option<TargetKind> {
+        // Libraries are reported with their crate types instead of "lib"
+        self.kind.iter().find_map(|kind| match kind.as_str() {
+            "lib" | "rlib" | "dylib" | "cdylib" | "staticlib" | "proc-macro" => {
+                Some(TargetKind::Lib)
+            }
+            other => other.parse().ok(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+    level: String,
+    #[serde(default)]
+    message: String,
+    code: Option<CompilerMessageCode>,
+    #[serde(default)]
+    spans: Vec<CompilerMessageSpan>,
+    rendered: Option<String>,
+}
+
+impl CompilerMessage {
+    fn to_diagnostic(&self, package: &str, target: Option<&Target>) -> Option<Diagnostic> {
+        let level = match self.level.as_str() {
+            "error" => DiagnosticLevel::Error,
+            "warning" => DiagnosticLevel::Warning,
+            "error: internal compiler error" => DiagnosticLevel::InternalCompilerError,
+            // Notes and help messages are only interesting as part of another diagnostic
+            _ => return None,
+        };
+        let span = self.spans.iter().find(|span| span.is_primary);
+
+        Some(Diagnostic {
+            package: package.to_string(),
+            target_kind: target.and_then(|target| target.kind()),
+            target_name: target.map(|target| target.name.clone()),
+            level,
+            code: self.code.as_ref().map(|code| code.code.clone()),
+            message: self.message.clone(),
+            file: span.map(|span| span.file_name.clone()),
+            line: span.map(|span| span.line_start),
+            column: span.map(|span| span.column_start),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct CompilerMessageCode {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessageSpan {
+    file_name: String,
+    line_start: u32,
+    column_start: u32,
+    is_primary: bool,
+}
+
+/// Get the name of a package from its ID, wherever the package comes from.
+fn package_name(package_id: &str) -> Option<&str> {
+    if package_id.contains(" (") {
+        // Older Cargo versions: foo 0.1.0 (path+file:///path/to/foo)
+        package_id.split(' ').next()
+    } else {
+        // Newer Cargo versions: path+file:///path/to/foo#foo@0.1.0, shortened to
+        // path+file:///path/to/foo#0.1.0 when the package is named like its directory
+        let mut parts = package_id.splitn(2, '#');
+        let path = parts.next()?;
+        let fragment = parts.next()?;
+        match fragment.find('@') {
+            Some(idx) => Some(&fragment[..idx]),
+            None => path.rsplit('/').next(),
+        }
+    }
+}
+
+/// Get the version of a package from its ID, wherever the package comes from.
+fn package_version(package_id: &str) -> Option<&str> {
+    if package_id.contains(" (") {
+        package_id.split(' ').nth(1)
+    } else {
+        let fragment = package_id.splitn(2, '#').nth(1)?;
+        match fragment.find('@') {
+            Some(idx) => Some(&fragment[idx + 1..]),
+            None => Some(fragment),
+        }
+    }
+}
+
+/// Get the name of a package built from the crate being tested, or `None` if the package comes
+/// from somewhere else (like a registry).
+fn local_package_name(package_id: &str) -> Option<&str> {
+    if package_id.starts_with("path+file://") || package_id.contains(" (path+file://") {
+        package_name(package_id)
+    } else {
+        None
+    }
+}
+
+/// Packages of the crate being tested and their targets that failed to build, tracked from the
+/// JSON messages emitted by Cargo.
+///
+/// Crates that are not workspaces only have one package, while workspaces can have as many
+/// packages as their members.
+#[derive(Default)]
+pub(super) struct Members {
+    /// Names of the packages of the workspace, if Cargo reported them. Otherwise every package
+    /// built from the crate's directory is considered part of it.
+    workspace: Option<HashSet<String>>,
+    /// Names of the packages, in the order Cargo started building them.
+    names: Vec<String>,
+    failed: HashSet<String>,
+    failed_targets: Vec<FailedTarget>,
+    diagnostics: Vec<Diagnostic>,
+    /// Every diagnostic reported so far, including the ones past the limit of warnings.
+    seen_diagnostics: HashSet<Diagnostic>,
+    warnings: usize,
+    counts: DiagnosticCounts,
+    timings: Vec<UnitTiming>,
+    /// Cargo doesn't emit JSON messages when build scripts fail to run.
+    build_script_failed: bool,
+    /// Cargo doesn't emit JSON messages when the doctests fail.
+    doctests_failed: bool,
+    /// Miri reports the undefined behavior as a text error of the test binary.
+    undefined_behavior: bool,
+    /// Only libraries have doctests, and Cargo refuses to run them for other packages.
+    no_library: bool,
+}
+
+impl Members {
+    /// Track the packages of a workspace, as listed in the output of `cargo metadata`. The local
+    /// packages outside of it, like path dependencies, are treated like the other dependencies.
+    pub(super) fn from_metadata(metadata: &str) -> Fallible<Self> {
+        let metadata: CargoMetadata = ::serde_json::from_str(metadata)?;
+        Ok(Members {
+            workspace: Some(
+                metadata
+                    .workspace_members
+                    .iter()
+                    .filter_map(|id| package_name(id))
+                    .map(|name| name.to_string())
+                    .collect(),
+            ),
+            ..Members::default()
+        })
+    }
+
+    fn is_member(&self, package: &str) -> bool {
+        self.workspace
+            .as_ref()
+            .map_or(true, |workspace| workspace.contains(package))
+    }
+
+    /// Process a line of Cargo's output, returning what should be included in the build log.
+    pub(super) fn process_line(&mut self, line: &str) -> Option<String> {
+        if !line.starts_with('{') {
+            self.process_text_line(line);
+            return Some(line.to_string());
+        }
+        let message: CargoMessage = match ::serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(_) => return Some(line.to_string()),
+        };
+        if message.reason == "timing-info" {
+            self.record_timing(&message);
+            return None;
+        }
+
+        let package = message
+            .package_id
+            .as_ref()
+            .and_then(|id| local_package_name(id))
+            .filter(|package| self.is_member(package));
+        if let Some(package) = package {
+            if !self.names.iter().any(|name| name == package) {
+                self.names.push(package.to_string());
+            }
+        }
+
+        match (message.reason.as_str(), message.message) {
+            ("compiler-message", Some(compiler_message)) => {
+                if compiler_message.level.starts_with("error") {
+                    if let Some(package) = package {
+                        self.failed.insert(package.to_string());
+                        if let Some(target) = &message.target {
+                            self.record_failed_target(package, target);
+                        }
+                    }
+                }
+                // Diagnostics of dependencies are recorded too, as they're often the ones failing
+                let any_package = message.package_id.as_ref().and_then(|id| package_name(id));
+                if let Some(any_package) = any_package {
+                    if let Some(diagnostic) =
+                        compiler_message.to_diagnostic(any_package, message.target.as_ref())
+                    {
+                        self.record_diagnostic(diagnostic);
+                    }
+                }
+                compiler_message
+                    .rendered
+                    .map(|rendered| rendered.trim_end().to_string())
+            }
+            // Artifacts and build script outputs aren't interesting in the log
+            _ => None,
+        }
+    }
+
+    fn process_text_line(&mut self, line: &str) {
+        if line.starts_with("error: failed to run custom build command for") {
+            self.build_script_failed = true;
+        } else if line.starts_with("error: doctest failed")
+            || (line.starts_with("error: test failed") && line.contains("--doc"))
+        {
+            self.doctests_failed = true;
+        } else if line.starts_with("error: Undefined Behavior:") {
+            self.undefined_behavior = true;
+        } else if line.starts_with("error: no library targets found") {
+            self.no_library = true;
+        }
+    }
+
+    fn record_timing(&mut self, message: &CargoMessage) {
+        let package_id = match &message.package_id {
+            Some(package_id) => package_id,
+            None => return,
+        };
+        if let (Some(name), Some(version), Some(target), Some(mode), Some(duration)) = (
+            package_name(package_id),
+            package_version(package_id),
+            &message.target,
+            &message.mode,
+            message.duration,
+        ) {
+            self.timings.push(UnitTiming {
+                package: name.to_string(),
+                version: version.to_string(),
+                target_kind: target.kind(),
+                target_name: target.name.clone(),
+                mode: mode.clone(),
+                duration,
+            });
+        }
+    }
+
+    fn record_failed_target(&mut self, package: &str, target: &Target) {
+        let kind = match target.kind() {
+            Some(kind) => kind,
+            None => return,
+        };
+        let failed = FailedTarget {
+            package: package.to_string(),
+            kind,
+            name: target.name.clone(),
+        };
+        if !self.failed_targets.contains(&failed) {
+            self.failed_targets.push(failed);
+        }
+    }
+
+    fn record_diagnostic(&mut self, diagnostic: Diagnostic) {
+        // Multiple Cargo invocations on the same crate report the same diagnostics again
+        if !self.seen_diagnostics.insert(diagnostic.clone()) {
+            return;
+        }
+        if self.names.contains(&diagnostic.package) {
+            match diagnostic.level {
+                DiagnosticLevel::Warning => self.counts.warnings += 1,
+                DiagnosticLevel::Error | DiagnosticLevel::InternalCompilerError => {
+                    self.counts.errors += 1
+                }
+            }
+        }
+        if diagnostic.level == DiagnosticLevel::Warning {
+            if self.warnings >= MAX_WARNINGS {
+                return;
+            }
+            self.warnings += 1;
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Whether the crate is a workspace with multiple packages.
+    pub(super) fn is_workspace(&self) -> bool {
+        self.names.len() > 1
+    }
+
+    pub(super) fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Targets that failed to build, in the order Cargo reported them.
+    pub(super) fn failed_targets(&self) -> &[FailedTarget] {
+        &self.failed_targets
+    }
+
+    /// Diagnostics emitted by the compiler, in the order Cargo reported them.
+    pub(super) fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Time spent compiling every unit, if Cargo was asked to report it.
+    pub(super) fn timings(&self) -> &[UnitTiming] {
+        &self.timings
+    }
+
+    /// Number of errors and warnings emitted in the packages of the crate.
+    pub(super) fn diagnostic_counts(&self) -> DiagnosticCounts {
+        self.counts
+    }
+
+    /// Categorize a failed build from the diagnostics and the errors reported by Cargo.
+    pub(super) fn build_failure_reason(&self) -> FailureReason {
+        let mut errors = self
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.level != DiagnosticLevel::Warning);
+        if errors
+            .clone()
+            .any(|diagnostic| diagnostic.level == DiagnosticLevel::InternalCompilerError)
+        {
+            FailureReason::ICE
+        } else if errors
+            .clone()
+            .any(|diagnostic| self.names.contains(&diagnostic.package))
+        {
+            FailureReason::CompilerError
+        } else if self.build_script_failed {
+            FailureReason::BuildScript
+        } else if errors.next().is_some() {
+            FailureReason::DependsOn
+        } else {
+            FailureReason::Unknown
+        }
+    }
+
+    /// Whether the doctests couldn't be run because the package has no library.
+    pub(super) fn has_no_library(&self) -> bool {
+        self.no_library
+    }
+
+    /// Categorize failed tests from the errors reported by Cargo.
+    pub(super) fn test_failure_reason(&self) -> FailureReason {
+        if self.undefined_behavior {
+            FailureReason::UndefinedBehavior
+        } else if self.doctests_failed {
+            FailureReason::DocTest
+        } else {
+            FailureReason::Unknown
+        }
+    }
+
+    /// Get the result of every package after the build, marking the packages that failed to
+    /// build with `reason` and the other ones with `passed`.
+    pub(super) fn build_results(
+        &self,
+        reason: FailureReason,
+        passed: TestResult,
+    ) -> Vec<MemberResult> {
+        self.names
+            .iter()
+            .map(|name| MemberResult {
+                name: name.clone(),
+                result: if self.failed.contains(name) {
+                    TestResult::BuildFail(reason)
+                } else {
+                    passed
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{local_package_name, package_name, package_version, Members};
+    use crate::results::{
+        Diagnostic, DiagnosticCounts, DiagnosticLevel, FailedTarget, FailureReason, MemberResult,
+        TargetKind, TestResult, UnitTiming,
+    };
+
+    #[test]
+    fn test_local_package_name() {
+        assert_eq!(
+            local_package_name("foo 0.1.0 (path+file:///opt/rustwide/workdir/foo)"),
+            Some("foo")
+        );
+        assert_eq!(
+            local_package_name("path+file:///opt/rustwide/workdir#foo-derive@0.1.0"),
+            Some("foo-derive")
+        );
+        assert_eq!(
+            local_package_name("path+file:///opt/rustwide/workdir/foo#0.1.0"),
+            Some("foo")
+        );
+        assert_eq!(
+            local_package_name(
+                "lazy_static 1.4.0 (registry+https://github.com/rust-lang/crates.io-index)"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_package_name() {
+        assert_eq!(
+            package_name(
+                "lazy_static 1.4.0 (registry+https://github.com/rust-lang/crates.io-index)"
+            ),
+            Some("lazy_static")
+        );
+        assert_eq!(
+            package_name("registry+https://github.com/rust-lang/crates.io-index#lazy_static@1.4.0"),
+            Some("lazy_static")
+        );
+        assert_eq!(
+            package_name("path+file:///opt/rustwide/workdir/foo#0.1.0"),
+            Some("foo")
+        );
+        assert_eq!(package_name("foo"), None);
+    }
+
+    #[test]
+    fn test_package_version() {
+        assert_eq!(
+            package_version(
+                "lazy_static 1.4.0 (registry+https://github.com/rust-lang/crates.io-index)"
+            ),
+            Some("1.4.0")
+        );
+        assert_eq!(
+            package_version(
+                "registry+https://github.com/rust-lang/crates.io-index#lazy_static@1.4.0"
+            ),
+            Some("1.4.0")
+        );
+        assert_eq!(
+            package_version("path+file:///opt/rustwide/workdir/foo#0.1.0"),
+            Some("0.1.0")
+        );
+        assert_eq!(package_version("foo"), None);
+    }
+
+    #[test]
+    fn test_timings() {
+        let mut members = Members::default();
+        let lines = [
+            r#"{"reason":"timing-info","package_id":"syn 1.0.0 (registry+https://github.com/rust-lang/crates.io-index)","target":{"kind":["lib"],"name":"syn"},"mode":"build","duration":4.25,"rmeta_time":2.5}"#,
+            r#"{"reason":"timing-info","package_id":"foo 0.1.0 (path+file:///workdir/foo)","target":{"kind":["custom-build"],"name":"build-script-build"},"mode":"run-custom-build","duration":0.5}"#,
+        ];
+        for line in &lines {
+            assert!(members.process_line(line).is_none());
+        }
+
+        assert_eq!(
+            members.timings(),
+            &[
+                UnitTiming {
+                    package: "syn".into(),
+                    version: "1.0.0".into(),
+                    target_kind: Some(TargetKind::Lib),
+                    target_name: "syn".into(),
+                    mode: "build".into(),
+                    duration: 4.25,
+                },
+                UnitTiming {
+                    package: "foo".into(),
+                    version: "0.1.0".into(),
+                    target_kind: Some(TargetKind::BuildScript),
+                    target_name: "build-script-build".into(),
+                    mode: "run-custom-build".into(),
+                    duration: 0.5,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut members = Members::default();
+        let lines = [
+            r#"{"reason":"compiler-message","package_id":"registry+https://github.com/rust-lang/crates.io-index#dep@1.0.0","target":{"kind":["lib"],"name":"dep"},"message":{"level":"error","message":"mismatched types","code":{"code":"E0308","explanation":null},"spans":[{"file_name":"src/lib.rs","line_start":3,"column_start":5,"is_primary":false},{"file_name":"src/lib.rs","line_start":4,"column_start":9,"is_primary":true}],"rendered":"error[E0308]: mismatched types\n"}}"#,
+            r#"{"reason":"compiler-message","package_id":"foo 0.1.0 (path+file:///workdir/foo)","target":{"kind":["bin"],"name":"foo"},"message":{"level":"warning","message":"unused import","code":{"code":"unused_imports","explanation":null},"spans":[],"rendered":"warning: unused import\n"}}"#,
+            r#"{"reason":"compiler-message","package_id":"foo 0.1.0 (path+file:///workdir/foo)","target":{"kind":["bin"],"name":"foo"},"message":{"level":"warning","message":"unused import","code":{"code":"unused_imports","explanation":null},"spans":[],"rendered":"warning: unused import\n"}}"#,
+            r#"{"reason":"compiler-message","package_id":"foo 0.1.0 (path+file:///workdir/foo)","target":{"kind":["bin"],"name":"foo"},"message":{"level":"note","message":"see the docs","spans":[],"rendered":null}}"#,
+        ];
+        for line in &lines {
+            members.process_line(line);
+        }
+
+        assert_eq!(
+            members.diagnostics(),
+            &[
+                Diagnostic {
+                    package: "dep".into(),
+                    target_kind: Some(TargetKind::Lib),
+                    target_name: Some("dep".into()),
+                    level: DiagnosticLevel::Error,
+                    code: Some("E0308".into()),
+                    message: "mismatched types".into(),
+                    file: Some("src/lib.rs".into()),
+                    line: Some(4),
+                    column: Some(9),
+                },
+                Diagnostic {
+                    package: "foo".into(),
+                    target_kind: Some(TargetKind::Bin),
+                    target_name: Some("foo".into()),
+                    level: DiagnosticLevel::Warning,
+                    code: Some("unused_imports".into()),
+                    message: "unused import".into(),
+                    file: None,
+                    line: None,
+                    column: None,
+                },
+            ]
+        );
+        // Errors in dependencies don't mark the packages of the crate as failed
+        assert!(members.failed_targets().is_empty());
+        // Only the diagnostics of the crate are counted, once
+        assert_eq!(
+            members.diagnostic_counts(),
+            DiagnosticCounts {
+                errors: 0,
+                warnings: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_failure_reason() {
+        let dep_error = r#"{"reason":"compiler-message","package_id":"dep 1.0.0 (registry+https://github.com/rust-lang/crates.io-index)","target":{"kind":["lib"],"name":"dep"},"message":{"level":"error","message":"mismatched types","rendered":null}}"#;
+        let local_error = r#"{"reason":"compiler-message","package_id":"foo 0.1.0 (path+file:///workdir/foo)","target":{"kind":["lib"],"name":"foo"},"message":{"level":"error","message":"mismatched types","rendered":null}}"#;
+        let local_ice = r#"{"reason":"compiler-message","package_id":"foo 0.1.0 (path+file:///workdir/foo)","target":{"kind":["lib"],"name":"foo"},"message":{"level":"error: internal compiler error","message":"unexpected panic","rendered":null}}"#;
+        let build_script =
+            "error: failed to run custom build command for `foo v0.1.0 (/workdir/foo)`";
+
+        let reason = |lines: &[&str]| {
+            let mut members = Members::default();
+            for line in lines {
+                members.process_line(line);
+            }
+            members.build_failure_reason()
+        };
+        assert_eq!(reason(&[]), FailureReason::Unknown);
+        assert_eq!(reason(&[dep_error]), FailureReason::DependsOn);
+        assert_eq!(
+            reason(&[dep_error, build_script]),
+            FailureReason::BuildScript
+        );
+        assert_eq!(
+            reason(&[dep_error, local_error]),
+            FailureReason::CompilerError
+        );
+        assert_eq!(reason(&[local_error, local_ice]), FailureReason::ICE);
+
+        let mut members = Members::default();
+        members.process_line("error: test failed, to rerun pass `--lib`");
+        assert_eq!(members.test_failure_reason(), FailureReason::Unknown);
+        members.process_line("error: test failed, to rerun pass '--doc'");
+        assert_eq!(members.test_failure_reason(), FailureReason::DocTest);
+
+        let mut members = Members::default();
+        members.process_line(
+            "error: Undefined Behavior: dereferencing pointer failed: null pointer is a dangling pointer",
+        );
+        members.process_line("error: test failed, to rerun pass '--lib'");
+        assert_eq!(
+            members.test_failure_reason(),
+            FailureReason::UndefinedBehavior
+        );
+
+        let mut members = Members::default();
+        assert!(!members.has_no_library());
+        members.process_line("error: no library targets found in package `foo`");
+        assert!(members.has_no_library());
+    }
+
+    #[test]
+    fn test_members() {
+        let mut members = Members::default();
+        let lines = [
+            "   Compiling lazy_static v1.4.0",
+            r#"{"reason":"compiler-artifact","package_id":"lazy_static 1.4.0 (registry+https://github.com/rust-lang/crates.io-index)"}"#,
+            r#"{"reason":"compiler-artifact","package_id":"foo 0.1.0 (path+file:///workdir/foo)"}"#,
+            r#"{"reason":"compiler-message","package_id":"bar 0.1.0 (path+file:///workdir/bar)","target":{"kind":["example"],"name":"demo"},"message":{"level":"error","rendered":"error: oops\n"}}"#,
+        ];
+        let logged = lines
+            .iter()
+            .filter_map(|line| members.process_line(line))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            logged,
+            vec!["   Compiling lazy_static v1.4.0", "error: oops"]
+        );
+        assert!(members.is_workspace());
+        assert_eq!(
+            members.build_results(FailureReason::Unknown, TestResult::TestSkipped),
+            vec![
+                MemberResult {
+                    name: "foo".into(),
+                    result: TestResult::TestSkipped,
+                },
+                MemberResult {
+                    name: "bar".into(),
+                    result: TestResult::BuildFail(FailureReason::Unknown),
+                },
+            ]
+        );
+        assert_eq!(
+            members.failed_targets(),
+            &[FailedTarget {
+                package: "bar".into(),
+                kind: TargetKind::Example,
+                name: "demo".into(),
+            }]
+        );
+    }
+    #[test]
+    fn test_workspace_members() {
+        let metadata = r#"{"packages":[],"workspace_members":["foo 0.1.0 (path+file:///workdir)","path+file:///workdir/bar#0.1.0"],"version":1}"#;
+        let mut members = Members::from_metadata(metadata).unwrap();
+        let lines = [
+            r#"{"reason":"compiler-artifact","package_id":"foo 0.1.0 (path+file:///workdir)"}"#,
+            r#"{"reason":"compiler-artifact","package_id":"path+file:///workdir/bar#0.1.0"}"#,
+            r#"{"reason":"compiler-message","package_id":"baz 0.1.0 (path+file:///baz)","target":{"kind":["lib"],"name":"baz"},"message":{"level":"error","rendered":null}}"#,
+        ];
+        for line in &lines {
+            members.process_line(line);
+        }
+
+        // Path dependencies outside of the workspace are not members
+        assert_eq!(members.names(), &["foo".to_string(), "bar".to_string()]);
+        assert!(members.failed_targets().is_empty());
+        assert_eq!(members.build_failure_reason(), FailureReason::DependsOn);
+
+        assert!(Members::from_metadata("error: no Cargo.toml").is_err());
+    }
+
+    #[test]
+    fn test_target_kind() {
+        let mut members = Members::default();
+        let lines = [
+            r#"{"reason":"compiler-message","package_id":"foo 0.1.0 (path+file:///workdir/foo)","target":{"kind":["proc-macro"],"name":"foo"},"message":{"level":"error","rendered":null}}"#,
+            r#"{"reason":"compiler-message","package_id":"foo 0.1.0 (path+file:///workdir/foo)","target":{"kind":["bin"],"name":"foo-cli"},"message":{"level":"error: internal compiler error","rendered":null}}"#,
+            r#"{"reason":"compiler-message","package_id":"foo 0.1.0 (path+file:///workdir/foo)","target":{"kind":["test"],"name":"smoke"},"message":{"level":"warning","rendered":null}}"#,
+        ];
+        for line in &lines {
+            assert!(members.process_line(line).is_none());
+        }
+
+        assert!(!members.is_workspace());
+        assert_eq!(
+            members
+                .failed_targets()
+                .iter()
+                .map(|t| (t.kind, t.name.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(TargetKind::Lib, "foo"), (TargetKind::Bin, "foo-cli")]
+        );
+    }
+}
@@ -1,7 +1,14 @@
 mod graph;
+mod index;
+mod lockfile;
+mod members;
+mod rustdoc_json;
+mod sandbox;
+mod snapshot;
 mod tasks;
 mod test;
 mod unstable_features;
+mod watchdog;
 mod worker;
 
 use crate::config::Config;
@@ -10,18 +17,58 @@ use crate::experiments::{Experiment, Mode};
 use crate::prelude::*;
 use crate::results::{TestResult, WriteResults};
 use crate::runner::graph::build_graph;
-use crate::runner::worker::{DiskSpaceWatcher, Worker};
+use crate::runner::watchdog::{BuildWatchdog, RunningBuilds};
+use crate::runner::worker::{CancellationWatcher, DiskSpaceWatcher, Worker};
 use crossbeam_utils::thread::{scope, ScopedJoinHandle};
 use rustwide::logging::LogStorage;
 use rustwide::Workspace;
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 
 const DISK_SPACE_WATCHER_INTERVAL: Duration = Duration::from_secs(600);
 const DISK_SPACE_WATCHER_THRESHOLD: f32 = 0.9;
+const BUILD_WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+const CANCELLATION_WATCHER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Workers of the agent an experiment is run with.
+#[derive(Debug, Clone)]
+pub struct Workers {
+    /// Indexes of the workers, identifying their build directories and the CPUs they're pinned to.
+    indexes: Vec<usize>,
+    /// Number of workers of the whole agent.
+    total: usize,
+}
+
+impl Workers {
+    /// Run the experiment with all the `count` workers.
+    pub fn all(count: usize) -> Self {
+        Workers {
+            indexes: (0..count).collect(),
+            total: count,
+        }
+    }
+
+    /// Run the experiment with part of the `total` workers, as the other ones are running other
+    /// experiments.
+    pub fn partition(indexes: Vec<usize>, total: usize) -> Fallible<Self> {
+        if indexes.is_empty() {
+            bail!("experiments can't be run without any worker");
+        }
+        Ok(Workers { indexes, total })
+    }
+
+    fn is_partial(&self) -> bool {
+        self.indexes.len() < self.total
+    }
+}
+
+fn worker_name(index: usize) -> String {
+    format!("worker-{}", index)
+}
 
 #[derive(Debug, Fail)]
 #[fail(display = "overridden task result to {}", _0)]
@@ -29,17 +76,25 @@ pub struct OverrideResult(TestResult);
 
 struct RunnerStateInner {
     prepare_logs: HashMap<Crate, LogStorage>,
+    /// `Cargo.lock` generated with the baseline toolchain, reused by the other toolchain.
+    lockfiles: HashMap<Crate, String>,
 }
 
 struct RunnerState {
     inner: Mutex<RunnerStateInner>,
+    builds: RunningBuilds,
+    /// Set once the experiment is cancelled, stopping the workers after their current task.
+    cancelled: AtomicBool,
 }
 
 impl RunnerState {
     fn new() -> Self {
         RunnerState {
+            builds: RunningBuilds::default(),
+            cancelled: AtomicBool::new(false),
             inner: Mutex::new(RunnerStateInner {
                 prepare_logs: HashMap::new(),
+                lockfiles: HashMap::new(),
             }),
         }
     }
@@ -47,6 +102,14 @@ impl RunnerState {
     fn lock(&self) -> std::sync::MutexGuard<RunnerStateInner> {
         self.inner.lock().unwrap()
     }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
 }
 
 pub fn run_ex<DB: WriteResults + Sync>(
@@ -54,15 +117,23 @@ pub fn run_ex<DB: WriteResults + Sync>(
     workspace: &Workspace,
     crates: &[Crate],
     db: &DB,
-    threads_count: usize,
+    workers: &Workers,
     config: &Config,
 ) -> Fallible<()> {
     if !rustwide::cmd::docker_running(workspace) {
         return Err(err_msg("docker is not running"));
     }
 
-    let res = run_ex_inner(ex, workspace, crates, db, threads_count, config);
-    workspace.purge_all_build_dirs()?;
+    let res = run_ex_inner(ex, workspace, crates, db, workers, config);
+    crate::toolchain::unpin(&ex.name);
+    if workers.is_partial() {
+        // The build directories of the other workers are used by other experiments
+        for &index in &workers.indexes {
+            workspace.build_dir(&worker_name(index)).purge()?;
+        }
+    } else {
+        workspace.purge_all_build_dirs()?;
+    }
     res
 }
 
@@ -71,31 +142,85 @@ fn run_ex_inner<DB: WriteResults + Sync>(
     workspace: &Workspace,
     crates: &[Crate],
     db: &DB,
-    threads_count: usize,
+    workers: &Workers,
     config: &Config,
 ) -> Fallible<()> {
-    info!("computing the tasks graph...");
-    let graph = Mutex::new(build_graph(ex, crates, config));
-
     info!("preparing the execution...");
+    if let Some(ref wrapper) = ex.rustc_wrapper {
+        if !wrapper.host_path().is_file() {
+            bail!(
+                "rustc wrapper {} is missing from {}",
+                wrapper,
+                crate::dirs::RUSTC_WRAPPERS_DIR.display()
+            );
+        }
+    }
+    let dist_server = ex
+        .dist_server
+        .as_ref()
+        .or_else(|| config.toolchains.dist_server.as_ref())
+        .map(|server| server.as_str());
+    let components: &[&str] = match ex.mode {
+        Mode::Clippy => &["clippy"],
+        // Miri builds its own standard library from the sources
+        Mode::Miri => &["miri", "rust-src"],
+        _ => &[],
+    };
+
+    // Installing the toolchains mostly waits on the network, so compute the graph in the meantime
+    info!("installing the toolchains and computing the tasks graph...");
+    let toolchains = ex.run_toolchains();
+    let graph = scope(|scope| -> Fallible<_> {
+        let install = scope.spawn(|| -> Fallible<()> {
+            crate::toolchain::install_all(
+                &ex.name,
+                &toolchains,
+                workspace,
+                dist_server,
+                components,
+            )?;
+            if let Err(err) = crate::toolchain::update_cache(config.toolchains.cache_size) {
+                warn!("failed to update the toolchains cache: {}", err);
+            }
+            Ok(())
+        });
+        let graph = build_graph(ex, crates, config);
+        match install.join() {
+            Ok(res) => res?,
+            Err(panic) => {
+                crate::utils::report_panic(&panic);
+                bail!("the toolchains installation thread panicked");
+            }
+        }
+        Ok(Mutex::new(graph))
+    })?;
+
+    index::prepare(ex, workspace)?;
+
     for tc in &ex.toolchains {
-        tc.install(workspace)?;
-        if ex.mode == Mode::Clippy {
-            tc.add_component(workspace, "clippy")?;
+        // Channels like nightly move during long experiments, so record what was installed
+        match tc.version() {
+            Ok(version) => {
+                info!("toolchain {} is rustc {}", tc, version.release);
+                db.record_toolchain_version(ex, tc, &version)?;
+            }
+            Err(err) => warn!("failed to get the version of toolchain {}: {}", tc, err),
         }
     }
 
-    info!("running tasks in {} threads...", threads_count);
+    info!("running tasks in {} threads...", workers.indexes.len());
 
     // An HashMap is used instead of an HashSet because Thread is not Eq+Hash
     let parked_threads: Mutex<HashMap<thread::ThreadId, thread::Thread>> =
         Mutex::new(HashMap::new());
     let state = RunnerState::new();
 
-    let workers = (0..threads_count)
-        .map(|i| {
+    let workers = workers
+        .indexes
+        .iter()
+        .map(|&i| {
             Worker::new(
-                format!("worker-{}", i),
+                worker_name(i),
                 workspace,
                 ex,
                 config,
@@ -103,6 +228,7 @@ fn run_ex_inner<DB: WriteResults + Sync>(
                 &state,
                 db,
                 &parked_threads,
+                sandbox::cpu_limits(config, i, workers.total),
             )
         })
         .collect::<Vec<_>>();
@@ -112,6 +238,14 @@ fn run_ex_inner<DB: WriteResults + Sync>(
         DISK_SPACE_WATCHER_THRESHOLD,
         &workers,
     );
+    let build_watchdog = BuildWatchdog::new(BUILD_WATCHDOG_INTERVAL, &state.builds);
+    let cancellation_watcher = CancellationWatcher::new(
+        CANCELLATION_WATCHER_INTERVAL,
+        ex,
+        db,
+        &state,
+        &parked_threads,
+    );
 
     scope(|scope| -> Fallible<()> {
         let mut threads = Vec::new();
@@ -120,25 +254,48 @@ fn run_ex_inner<DB: WriteResults + Sync>(
             let join = scope
                 .builder()
                 .name(worker.name().into())
-                .spawn(move || worker.run())?;
+                .spawn(move || worker.supervise())?;
             threads.push(join);
         }
         let disk_watcher_thread = scope
             .builder()
             .name("disk-space-watcher".into())
             .spawn(|| disk_watcher.run())?;
+        let build_watchdog_thread = scope
+            .builder()
+            .name("build-watchdog".into())
+            .spawn(|| build_watchdog.run())?;
+        let cancellation_watcher_thread = scope
+            .builder()
+            .name("cancellation-watcher".into())
+            .spawn(|| cancellation_watcher.run())?;
 
         let clean_exit = join_threads(threads.drain(..));
         disk_watcher.stop();
-        let disk_watcher_clean_exit = join_threads(std::iter::once(disk_watcher_thread));
+        build_watchdog.stop();
+        cancellation_watcher.stop();
+        let watchers_clean_exit = join_threads(
+            vec![
+                disk_watcher_thread,
+                build_watchdog_thread,
+                cancellation_watcher_thread,
+            ]
+            .into_iter(),
+        );
 
-        if clean_exit && disk_watcher_clean_exit {
+        if clean_exit && watchers_clean_exit {
             Ok(())
         } else {
             bail!("some threads returned an error");
         }
     })?;
 
+    // The tasks not started before the cancellation are left in the graph
+    if state.is_cancelled() {
+        info!("experiment {} was cancelled, stopped running it", ex.name);
+        return Ok(());
+    }
+
     // Only the root node must be present
     let mut g = graph.lock().unwrap();
     assert!(g.next_task(ex, db).is_finished());
@@ -0,0 +1,199 @@
+use crate::prelude::*;
+use crate::results::DocItem;
+use serde_json::{Map, Value};
+use sha1::{Digest, Sha1};
+
+/// Fields of the items changing between two runs of rustdoc on the same code.
+const IGNORED_FIELDS: &[&str] = &["crate_id", "span", "links"];
+
+/// Fields listing the ids of other items.
+const ID_LIST_FIELDS: &[&str] = &[
+    "items",
+    "impls",
+    "implementations",
+    "implementors",
+    "variants",
+    "fields",
+];
+
+/// Maximum depth of the nested items included in the hash of an item, in case they're cyclic.
+const MAX_DEPTH: usize = 8;
+
+fn id_key(id: &Value) -> Option<String> {
+    match id {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+struct Docs<'a> {
+    index: &'a Map<String, Value>,
+    paths: &'a Map<String, Value>,
+}
+
+impl<'a> Docs<'a> {
+    /// Path of an item documented on its own page, which is hashed separately.
+    fn path(&self, id: &Value) -> Option<String> {
+        let summary = self.paths.get(&id_key(id)?)?;
+        let segments = summary["path"]
+            .as_array()?
+            .iter()
+            .filter_map(|segment| segment.as_str())
+            .collect::<Vec<_>>();
+        Some(segments.join("::"))
+    }
+
+    fn normalize(&self, value: &Value, depth: usize) -> Value {
+        match value {
+            Value::Object(object) => {
+                let mut normalized = Map::new();
+                for (key, value) in object {
+                    if key == "id" {
+                        // The ids are assigned in a different order by every run of rustdoc, so
+                        // the references to other items are replaced by their path
+                        if let Some(path) = self.path(value) {
+                            normalized.insert(key.clone(), Value::String(path));
+                        }
+                    } else if IGNORED_FIELDS.contains(&key.as_str()) {
+                        continue;
+                    } else if let (true, Some(ids)) =
+                        (ID_LIST_FIELDS.contains(&key.as_str()), value.as_array())
+                    {
+                        normalized.insert(key.clone(), self.expand(ids, depth));
+                    } else {
+                        normalized.insert(key.clone(), self.normalize(value, depth));
+                    }
+                }
+                Value::Object(normalized)
+            }
+            Value::Array(values) => Value::Array(
+                values
+                    .iter()
+                    .map(|value| self.normalize(value, depth))
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+
+    /// Replace the ids of the nested items (like the methods of a type) with their content, or
+    /// with their path if they're documented on their own.
+    fn expand(&self, ids: &[Value], depth: usize) -> Value {
+        let mut items = ids
+            .iter()
+            .filter_map(|id| {
+                let key = match id_key(id) {
+                    Some(key) => key,
+                    None => return Some(self.normalize(id, depth)),
+                };
+                if let Some(path) = self.path(id) {
+                    Some(Value::String(path))
+                } else if depth < MAX_DEPTH {
+                    let item = self.index.get(&key)?;
+                    Some(self.normalize(item, depth + 1))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        // The nested items are listed in the order of their ids
+        items.sort_by_cached_key(|item| item.to_string());
+        Value::Array(items)
+    }
+}
+
+/// Fingerprint the public items of the crate documented by rustdoc's JSON output, to compare
+/// them with the output of another toolchain.
+pub(super) fn doc_items(doc: &Value) -> Fallible<Vec<DocItem>> {
+    let docs = match (doc["index"].as_object(), doc["paths"].as_object()) {
+        (Some(index), Some(paths)) => Docs { index, paths },
+        _ => bail!("invalid rustdoc JSON output"),
+    };
+
+    let mut items = Vec::new();
+    for (id, summary) in docs.paths {
+        // The items of the dependencies are only included when they're referenced
+        if summary["crate_id"].as_u64() != Some(0) {
+            continue;
+        }
+        let (item, path) = match (docs.index.get(id), docs.path(&Value::String(id.clone()))) {
+            (Some(item), Some(path)) => (item, path),
+            _ => continue,
+        };
+
+        let mut hasher = Sha1::new();
+        hasher.input(docs.normalize(item, 0).to_string().as_bytes());
+        items.push(DocItem {
+            path,
+            kind: summary["kind"].as_str().unwrap_or("unknown").to_string(),
+            hash: format!("{:x}", hasher.result()),
+        });
+    }
+    items.sort();
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::doc_items;
+    use serde_json::{json, Value};
+
+    fn docs(struct_id: u64, method_id: u64, method_name: &str) -> Value {
+        json!({
+            "root": 0,
+            "format_version": 30,
+            "index": {
+                "0": {
+                    "id": 0,
+                    "crate_id": 0,
+                    "name": "foo",
+                    "inner": {"module": {"is_crate": true, "items": [struct_id]}},
+                },
+                struct_id.to_string(): {
+                    "id": struct_id,
+                    "crate_id": 0,
+                    "name": "Bar",
+                    "span": {"filename": "src/lib.rs", "begin": [1, 0], "end": [1, 10]},
+                    "inner": {"struct": {"impls": [100]}},
+                },
+                "100": {
+                    "id": 100,
+                    "crate_id": 0,
+                    "inner": {"impl": {"items": [method_id]}},
+                },
+                method_id.to_string(): {
+                    "id": method_id,
+                    "crate_id": 0,
+                    "name": method_name,
+                    "inner": {"function": {"sig": {"inputs": [], "output": null}}},
+                },
+            },
+            "paths": {
+                "0": {"crate_id": 0, "path": ["foo"], "kind": "module"},
+                struct_id.to_string(): {"crate_id": 0, "path": ["foo", "Bar"], "kind": "struct"},
+                "200": {"crate_id": 1, "path": ["std", "string", "String"], "kind": "struct"},
+            },
+        })
+    }
+
+    #[test]
+    fn test_doc_items() {
+        let items = doc_items(&docs(1, 2, "baz")).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].path, "foo");
+        assert_eq!(items[0].kind, "module");
+        assert_eq!(items[1].path, "foo::Bar");
+        assert_eq!(items[1].kind, "struct");
+
+        // The ids are different in every run of rustdoc
+        assert_eq!(items, doc_items(&docs(3, 4, "baz")).unwrap());
+
+        // Changes to the methods are included in the hash of the type
+        let renamed = doc_items(&docs(1, 2, "qux")).unwrap();
+        assert_eq!(items[0], renamed[0]);
+        assert_ne!(items[1].hash, renamed[1].hash);
+
+        assert!(doc_items(&json!({})).is_err());
+    }
+}
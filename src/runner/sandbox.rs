@@ -0,0 +1,123 @@
+use crate::config::Config;
+use crate::dirs::RUSTC_WRAPPERS_DIR;
+use crate::experiments::SANDBOX_RUSTC_WRAPPERS_DIR;
+use crate::prelude::*;
+use crate::results::WriteResults;
+use crate::runner::tasks::TaskCtx;
+use rustwide::cmd::{MountKind, SandboxBuilder};
+use std::fmt;
+use std::path::Path;
+
+/// CPU resources the builds of a single worker are allowed to use.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct CpuLimits {
+    pub(super) quota: Option<f32>,
+    pub(super) cpuset: Option<String>,
+}
+
+impl fmt::Display for CpuLimits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.quota {
+            Some(quota) => write!(f, "cpu quota: {}", quota)?,
+            None => write!(f, "cpu quota: unlimited")?,
+        }
+        match self.cpuset {
+            Some(ref cpuset) => write!(f, ", cpuset: {}", cpuset),
+            None => write!(f, ", cpuset: all"),
+        }
+    }
+}
+
+impl CpuLimits {
+    /// Number of CPUs the builds can use, used to estimate the CPU time spent on them. Without a
+    /// cpuset the builds can use all the CPUs of the agent.
+    pub(super) fn cpus(&self) -> f64 {
+        let available = match self.cpuset {
+            Some(ref cpuset) => match cpuset.find('-') {
+                Some(pos) => {
+                    let first: usize = cpuset[..pos].parse().unwrap_or(0);
+                    let last: usize = cpuset[pos + 1..].parse().unwrap_or(first);
+                    (last.saturating_sub(first) + 1) as f64
+                }
+                None => 1.0,
+            },
+            None => num_cpus::get() as f64,
+        };
+        match self.quota {
+            Some(quota) => available.min(f64::from(quota)),
+            None => available,
+        }
+    }
+}
+
+/// Calculate the CPU limits of the worker with the provided index.
+pub(super) fn cpu_limits(config: &Config, worker: usize, workers_count: usize) -> CpuLimits {
+    CpuLimits {
+        quota: config.sandbox.cpu_limit,
+        cpuset: if config.sandbox.pin_cpus {
+            Some(cpuset_for(worker, workers_count, num_cpus::get()))
+        } else {
+            None
+        },
+    }
+}
+
+fn cpuset_for(worker: usize, workers_count: usize, cpus: usize) -> String {
+    // Every worker gets the same number of CPUs: if there are more workers than CPUs, some of
+    // the workers will share the same CPU
+    let per_worker = std::cmp::max(1, cpus / std::cmp::max(1, workers_count));
+    let first = (worker * per_worker) % cpus;
+    let last = std::cmp::min(first + per_worker, cpus) - 1;
+    if first == last {
+        first.to_string()
+    } else {
+        format!("{}-{}", first, last)
+    }
+}
+
+/// Create the sandbox every build of a task is executed in.
+pub(super) fn builder<DB: WriteResults>(ctx: &TaskCtx<DB>) -> SandboxBuilder {
+    // rustwide doesn't allow passing `--cpuset-cpus` to `docker create`, so the cpuset is applied
+    // by the build watchdog with `docker update` as soon as the container is started.
+    // The limits are recorded in every build log to allow fair comparisons of build times
+    info!("{}", ctx.cpu_limits);
+
+    let mut builder = SandboxBuilder::new()
+        .memory_limit(Some(ctx.config.sandbox.memory_limit.to_bytes()))
+        .cpu_limit(ctx.cpu_limits.quota)
+        .enable_networking(false);
+    if ctx.experiment.rustc_wrapper.is_some() {
+        builder = builder.mount(
+            &RUSTC_WRAPPERS_DIR,
+            Path::new(SANDBOX_RUSTC_WRAPPERS_DIR),
+            MountKind::ReadOnly,
+        );
+    }
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cpuset_for, CpuLimits};
+
+    #[test]
+    fn test_cpuset_for() {
+        assert_eq!(cpuset_for(0, 4, 8), "0-1");
+        assert_eq!(cpuset_for(3, 4, 8), "6-7");
+        assert_eq!(cpuset_for(1, 3, 8), "2-3");
+        assert_eq!(cpuset_for(0, 8, 8), "0");
+        assert_eq!(cpuset_for(5, 4, 2), "1");
+    }
+
+    #[test]
+    fn test_cpus() {
+        let limits = |quota, cpuset: &str| CpuLimits {
+            quota,
+            cpuset: Some(cpuset.into()),
+        };
+        assert_eq!(limits(None, "2-5").cpus(), 4.0);
+        assert_eq!(limits(None, "3").cpus(), 1.0);
+        assert_eq!(limits(Some(1.5), "2-5").cpus(), 1.5);
+        assert_eq!(limits(Some(8.0), "0-1").cpus(), 2.0);
+    }
+}
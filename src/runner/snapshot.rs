@@ -0,0 +1,143 @@
+use crate::prelude::*;
+use crate::results::WriteResults;
+use crate::runner::tasks::TaskCtx;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rustwide::Build;
+use std::path::{Path, PathBuf};
+use tar::{Builder as TarBuilder, Header as TarHeader};
+use walkdir::WalkDir;
+
+/// File of the snapshot listing the files left out because of the size limit.
+const SKIPPED_FILE: &str = "SKIPPED.txt";
+
+/// Directories included in the snapshot of a build, along with their path inside of it.
+fn snapshot_dirs<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+    fix_dir: &str,
+) -> Vec<(PathBuf, PathBuf)> {
+    let target_dir = build_env.host_target_dir();
+    let mut dirs = vec![
+        (build_env.host_source_dir(), PathBuf::from("source")),
+        (target_dir.join(fix_dir), PathBuf::from("fixed")),
+        (
+            target_dir.join("debug").join("build"),
+            PathBuf::from("build"),
+        ),
+    ];
+    // The build scripts of the crates built for another target still run on the host, but their
+    // output is stored in the directory of the target
+    if let Some(ref target) = ctx.toolchain.target {
+        dirs.push((
+            target_dir.join(target).join("debug").join("build"),
+            Path::new("build").join(target),
+        ));
+    }
+    dirs
+}
+
+/// Create a compressed tarball of the directories, leaving out the files that would make the
+/// total size of the files in it exceed `max_size`.
+fn capture(dirs: &[(PathBuf, PathBuf)], max_size: u64) -> Fallible<Vec<u8>> {
+    let mut archive = TarBuilder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let mut size = 0;
+    let mut skipped = Vec::new();
+
+    for (dir, name) in dirs {
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in WalkDir::new(dir).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+            let entry = entry?;
+            // Symlinks are not followed, as they could point outside of the build directory
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let path = name.join(entry.path().strip_prefix(dir)?);
+            let len = entry.metadata()?.len();
+            if size + len > max_size {
+                skipped.push(path);
+                continue;
+            }
+            size += len;
+            archive.append_path_with_name(entry.path(), &path)?;
+        }
+    }
+
+    if !skipped.is_empty() {
+        let list = skipped
+            .iter()
+            .map(|path| format!("{}\n", path.display()))
+            .collect::<String>();
+        let mut header = TarHeader::new_gnu();
+        header.set_size(list.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive.append_data(&mut header, SKIPPED_FILE, list.as_bytes())?;
+    }
+
+    Ok(archive.into_inner()?.finish()?)
+}
+
+/// Record a snapshot of the build directory of a failed build, if they're enabled. Failing to
+/// capture it doesn't change the result of the build.
+pub(super) fn record<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+    fix_dir: &str,
+) -> Fallible<()> {
+    let max_size = match ctx.config.snapshots.max_size {
+        Some(ref max_size) => max_size.to_bytes() as u64,
+        None => return Ok(()),
+    };
+
+    match capture(&snapshot_dirs(ctx, build_env, fix_dir), max_size) {
+        Ok(snapshot) => {
+            ctx.db
+                .record_snapshot(ctx.experiment, ctx.toolchain, ctx.krate, &snapshot)?;
+        }
+        Err(err) => warn!("failed to capture the snapshot of {}: {}", ctx.krate, err),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{capture, SKIPPED_FILE};
+    use flate2::read::GzDecoder;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::Read;
+    use std::path::PathBuf;
+    use tar::Archive;
+
+    #[test]
+    fn test_capture() {
+        let source = tempfile::tempdir().unwrap();
+        fs::create_dir(source.path().join("src")).unwrap();
+        fs::write(source.path().join("Cargo.toml"), "[package]\n").unwrap();
+        fs::write(source.path().join("src").join("lib.rs"), vec![b'/'; 100]).unwrap();
+
+        let dirs = vec![
+            (source.path().to_path_buf(), PathBuf::from("source")),
+            (source.path().join("missing"), PathBuf::from("build")),
+        ];
+        let snapshot = capture(&dirs, 50).unwrap();
+
+        let mut files = HashMap::new();
+        let mut archive = Archive::new(GzDecoder::new(snapshot.as_slice()));
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            files.insert(entry.path().unwrap().to_path_buf(), content);
+        }
+
+        // The files exceeding the size limit are listed instead of being included
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[&PathBuf::from("source/Cargo.toml")], "[package]\n");
+        assert_eq!(files[&PathBuf::from(SKIPPED_FILE)], "source/src/lib.rs\n");
+    }
+}
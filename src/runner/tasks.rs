@@ -2,7 +2,8 @@ use crate::config::Config;
 use crate::crates::Crate;
 use crate::experiments::Experiment;
 use crate::prelude::*;
-use crate::results::{EncodingType, TestResult, WriteResults};
+use crate::results::{EncodingType, Invocation, TestResult, WriteResults};
+use crate::runner::sandbox::CpuLimits;
 use crate::runner::test::detect_broken;
 use crate::runner::{test, RunnerState};
 use crate::toolchain::Toolchain;
@@ -14,6 +15,7 @@ use rustwide::logging::{self, LogStorage};
 use std::fmt;
 
 pub(super) struct TaskCtx<'ctx, DB: WriteResults + 'ctx> {
+    pub(super) workspace: &'ctx Workspace,
     pub(super) build_dir: &'ctx Mutex<BuildDirectory>,
     pub(super) config: &'ctx Config,
     pub(super) db: &'ctx DB,
@@ -21,11 +23,15 @@ pub(super) struct TaskCtx<'ctx, DB: WriteResults + 'ctx> {
     pub(super) toolchain: &'ctx Toolchain,
     pub(super) krate: &'ctx Crate,
     pub(super) state: &'ctx RunnerState,
+    pub(super) cpu_limits: &'ctx CpuLimits,
     pub(super) quiet: bool,
+    /// Cargo commands executed so far while testing the crate.
+    pub(super) invocations: Mutex<Vec<Invocation>>,
 }
 
 impl<'ctx, DB: WriteResults + 'ctx> TaskCtx<'ctx, DB> {
     fn new(
+        workspace: &'ctx Workspace,
         build_dir: &'ctx Mutex<BuildDirectory>,
         config: &'ctx Config,
         db: &'ctx DB,
@@ -33,9 +39,11 @@ impl<'ctx, DB: WriteResults + 'ctx> TaskCtx<'ctx, DB> {
         toolchain: &'ctx Toolchain,
         krate: &'ctx Crate,
         state: &'ctx RunnerState,
+        cpu_limits: &'ctx CpuLimits,
         quiet: bool,
     ) -> Self {
         TaskCtx {
+            workspace,
             build_dir,
             config,
             db,
@@ -43,7 +51,9 @@ impl<'ctx, DB: WriteResults + 'ctx> TaskCtx<'ctx, DB> {
             toolchain,
             krate,
             state,
+            cpu_limits,
             quiet,
+            invocations: Mutex::new(Vec::new()),
         }
     }
 }
@@ -56,7 +66,12 @@ pub(super) enum TaskStep {
     CheckOnly { tc: Toolchain, quiet: bool },
     Clippy { tc: Toolchain, quiet: bool },
     Rustdoc { tc: Toolchain, quiet: bool },
+    RustdocJson { tc: Toolchain, quiet: bool },
     UnstableFeatures { tc: Toolchain },
+    BuildBenches { tc: Toolchain, quiet: bool },
+    Miri { tc: Toolchain, quiet: bool },
+    Fix { tc: Toolchain, quiet: bool },
+    DocTest { tc: Toolchain, quiet: bool },
 }
 
 impl fmt::Debug for TaskStep {
@@ -69,7 +84,12 @@ impl fmt::Debug for TaskStep {
             TaskStep::CheckOnly { ref tc, quiet } => ("check", quiet, Some(tc)),
             TaskStep::Clippy { ref tc, quiet } => ("clippy", quiet, Some(tc)),
             TaskStep::Rustdoc { ref tc, quiet } => ("doc", quiet, Some(tc)),
+            TaskStep::RustdocJson { ref tc, quiet } => ("doc (JSON)", quiet, Some(tc)),
             TaskStep::UnstableFeatures { ref tc } => ("find unstable features on", false, Some(tc)),
+            TaskStep::BuildBenches { ref tc, quiet } => ("build benchmarks", quiet, Some(tc)),
+            TaskStep::Miri { ref tc, quiet } => ("test under miri", quiet, Some(tc)),
+            TaskStep::Fix { ref tc, quiet } => ("fix", quiet, Some(tc)),
+            TaskStep::DocTest { ref tc, quiet } => ("build and run doctests", quiet, Some(tc)),
         };
 
         write!(f, "{}", name)?;
@@ -110,7 +130,10 @@ impl Task {
             | TaskStep::CheckOnly { ref tc, .. }
             | TaskStep::Clippy { ref tc, .. }
             | TaskStep::Rustdoc { ref tc, .. }
-            | TaskStep::UnstableFeatures { ref tc } => {
+            | TaskStep::UnstableFeatures { ref tc }
+            | TaskStep::BuildBenches { ref tc, .. }
+            | TaskStep::Miri { ref tc, .. }
+            | TaskStep::Fix { ref tc, .. } => {
                 db.get_result(ex, tc, &self.krate).unwrap_or(None).is_none()
             }
         }
@@ -132,7 +155,10 @@ impl Task {
             | TaskStep::CheckOnly { ref tc, .. }
             | TaskStep::Clippy { ref tc, .. }
             | TaskStep::Rustdoc { ref tc, .. }
-            | TaskStep::UnstableFeatures { ref tc } => {
+            | TaskStep::UnstableFeatures { ref tc }
+            | TaskStep::BuildBenches { ref tc, .. }
+            | TaskStep::Miri { ref tc, .. }
+            | TaskStep::Fix { ref tc, .. } => {
                 let log_storage = state
                     .lock()
                     .prepare_logs
@@ -160,16 +186,19 @@ impl Task {
     pub(super) fn run<'ctx, 's: 'ctx, DB: WriteResults>(
         &'s self,
         config: &'ctx Config,
-        workspace: &Workspace,
+        workspace: &'ctx Workspace,
         build_dir: &'ctx Mutex<BuildDirectory>,
         ex: &'ctx Experiment,
         db: &'ctx DB,
         state: &'ctx RunnerState,
+        cpu_limits: &'ctx CpuLimits,
     ) -> Fallible<()> {
         match self.step {
             TaskStep::Cleanup => {
-                // Remove stored logs
-                state.lock().prepare_logs.remove(&self.krate);
+                // Remove stored logs and lockfiles
+                let mut state = state.lock();
+                state.prepare_logs.remove(&self.krate);
+                state.lockfiles.remove(&self.krate);
             }
             TaskStep::Prepare => {
                 let storage = LogStorage::from(config);
@@ -178,6 +207,7 @@ impl Task {
                     .prepare_logs
                     .insert(self.krate.clone(), storage.clone());
                 logging::capture(&storage, || {
+                    self.krate.source().fetch()?;
                     let rustwide_crate = self.krate.to_rustwide();
                     detect_broken(rustwide_crate.fetch(workspace))?;
 
@@ -194,33 +224,174 @@ impl Task {
                 })?;
             }
             TaskStep::BuildAndTest { ref tc, quiet } => {
-                let ctx = TaskCtx::new(build_dir, config, db, ex, tc, &self.krate, state, quiet);
+                let ctx = TaskCtx::new(
+                    workspace,
+                    build_dir,
+                    config,
+                    db,
+                    ex,
+                    tc,
+                    &self.krate,
+                    state,
+                    cpu_limits,
+                    quiet,
+                );
                 test::run_test("testing", &ctx, test::test_build_and_test)?;
             }
             TaskStep::BuildOnly { ref tc, quiet } => {
-                let ctx = TaskCtx::new(build_dir, config, db, ex, tc, &self.krate, state, quiet);
+                let ctx = TaskCtx::new(
+                    workspace,
+                    build_dir,
+                    config,
+                    db,
+                    ex,
+                    tc,
+                    &self.krate,
+                    state,
+                    cpu_limits,
+                    quiet,
+                );
                 test::run_test("building", &ctx, test::test_build_only)?;
             }
             TaskStep::CheckOnly { ref tc, quiet } => {
-                let ctx = TaskCtx::new(build_dir, config, db, ex, tc, &self.krate, state, quiet);
+                let ctx = TaskCtx::new(
+                    workspace,
+                    build_dir,
+                    config,
+                    db,
+                    ex,
+                    tc,
+                    &self.krate,
+                    state,
+                    cpu_limits,
+                    quiet,
+                );
                 test::run_test("checking", &ctx, test::test_check_only)?;
             }
             TaskStep::Clippy { ref tc, quiet } => {
-                let ctx = TaskCtx::new(build_dir, config, db, ex, tc, &self.krate, state, quiet);
+                let ctx = TaskCtx::new(
+                    workspace,
+                    build_dir,
+                    config,
+                    db,
+                    ex,
+                    tc,
+                    &self.krate,
+                    state,
+                    cpu_limits,
+                    quiet,
+                );
                 test::run_test("linting", &ctx, test::test_clippy_only)?;
             }
             TaskStep::Rustdoc { ref tc, quiet } => {
-                let ctx = TaskCtx::new(build_dir, config, db, ex, tc, &self.krate, state, quiet);
+                let ctx = TaskCtx::new(
+                    workspace,
+                    build_dir,
+                    config,
+                    db,
+                    ex,
+                    tc,
+                    &self.krate,
+                    state,
+                    cpu_limits,
+                    quiet,
+                );
                 test::run_test("documenting", &ctx, test::test_rustdoc)?;
             }
+            TaskStep::RustdocJson { ref tc, quiet } => {
+                let ctx = TaskCtx::new(
+                    workspace,
+                    build_dir,
+                    config,
+                    db,
+                    ex,
+                    tc,
+                    &self.krate,
+                    state,
+                    cpu_limits,
+                    quiet,
+                );
+                test::run_test("documenting as JSON", &ctx, test::test_rustdoc_json)?;
+            }
             TaskStep::UnstableFeatures { ref tc } => {
-                let ctx = TaskCtx::new(build_dir, config, db, ex, tc, &self.krate, state, false);
+                let ctx = TaskCtx::new(
+                    workspace,
+                    build_dir,
+                    config,
+                    db,
+                    ex,
+                    tc,
+                    &self.krate,
+                    state,
+                    cpu_limits,
+                    false,
+                );
                 test::run_test(
                     "checking unstable",
                     &ctx,
                     crate::runner::unstable_features::find_unstable_features,
                 )?;
             }
+            TaskStep::BuildBenches { ref tc, quiet } => {
+                let ctx = TaskCtx::new(
+                    workspace,
+                    build_dir,
+                    config,
+                    db,
+                    ex,
+                    tc,
+                    &self.krate,
+                    state,
+                    cpu_limits,
+                    quiet,
+                );
+                test::run_test("building benchmarks", &ctx, test::test_build_benches)?;
+            }
+            TaskStep::Miri { ref tc, quiet } => {
+                let ctx = TaskCtx::new(
+                    workspace,
+                    build_dir,
+                    config,
+                    db,
+                    ex,
+                    tc,
+                    &self.krate,
+                    state,
+                    cpu_limits,
+                    quiet,
+                );
+                test::run_test("testing under miri", &ctx, test::test_miri)?;
+            }
+            TaskStep::Fix { ref tc, quiet } => {
+                let ctx = TaskCtx::new(
+                    workspace,
+                    build_dir,
+                    config,
+                    db,
+                    ex,
+                    tc,
+                    &self.krate,
+                    state,
+                    cpu_limits,
+                    quiet,
+                );
+                test::run_test("fixing", &ctx, test::test_fix)?;
+            }
+            TaskStep::DocTest { ref tc, quiet } => {
+                let ctx = TaskCtx::new(
+                    workspace,
+                    build_dir,
+                    config,
+                    db,
+                    ex,
+                    tc,
+                    &self.krate,
+                    state,
+                    cpu_limits,
+                    quiet,
+                );
+                test::run_test("running doctests", &ctx, test::test_doctests)?;
+            }
         }
 
         Ok(())
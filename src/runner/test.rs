@@ -1,11 +1,28 @@
+use crate::experiments::Mode;
 use crate::prelude::*;
-use crate::results::{BrokenReason, EncodingType, FailureReason, TestResult, WriteResults};
+use crate::results::{
+    BrokenReason, EncodingType, FailureReason, Invocation, MemberResult, ResourceUsage, SkipReason,
+    TestResult, WriteResults,
+};
+use crate::runner::members::Members;
+use crate::runner::rustdoc_json;
+use crate::runner::snapshot;
 use crate::runner::tasks::TaskCtx;
-use crate::runner::OverrideResult;
+use crate::runner::watchdog::BuildKilled;
+use crate::runner::{index, lockfile, sandbox, OverrideResult};
 use failure::Error;
 use remove_dir_all::remove_dir_all;
-use rustwide::cmd::{CommandError, SandboxBuilder};
+use rustwide::cmd::CommandError;
 use rustwide::{Build, PrepareError};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+/// Directory the sources are fixed in, inside the target directory as the sources are mounted
+/// read-only in the sandbox.
+const FIX_DIR: &str = "crater-fix";
+const SANDBOX_FIX_MANIFEST: &str = "/opt/rustwide/target/crater-fix/Cargo.toml";
 
 fn failure_reason(err: &Error) -> FailureReason {
     for cause in err.iter_chain() {
@@ -15,12 +32,22 @@ fn failure_reason(err: &Error) -> FailureReason {
             return FailureReason::Timeout;
         } else if let Some(&CommandError::Timeout(_)) = cause.downcast_ctx() {
             return FailureReason::Timeout;
+        } else if let Some(&BuildKilled(_)) = cause.downcast_ctx() {
+            return FailureReason::Timeout;
         }
     }
 
     FailureReason::Unknown
 }
 
+/// Get the reason of a failure, refining unknown reasons with what Cargo reported.
+fn refine_failure_reason(reason: FailureReason, members_reason: FailureReason) -> FailureReason {
+    match reason {
+        FailureReason::Unknown => members_reason,
+        other => other,
+    }
+}
+
 pub(super) fn detect_broken<T>(res: Result<T, Error>) -> Result<T, Error> {
     match res {
         Ok(ok) => Ok(ok),
@@ -57,33 +84,255 @@ fn run_cargo<DB: WriteResults>(
     ctx: &TaskCtx<DB>,
     build_env: &Build,
     args: &[&str],
+    members: Option<&mut Members>,
 ) -> Fallible<()> {
     let mut rustflags = format!("--cap-lints={}", ctx.experiment.cap_lints.to_str());
     if let Some(ref tc_rustflags) = ctx.toolchain.rustflags {
         rustflags.push(' ');
         rustflags.push_str(tc_rustflags);
     }
+    if let Some(linker) = ctx.toolchain.linker {
+        rustflags.push(' ');
+        rustflags.push_str(linker.rustflags());
+    }
+
+    let rustdoc_json = args.get(0) == Some(&"doc") && ctx.experiment.mode == Mode::RustdocJson;
+    if rustdoc_json {
+        rustflags.push_str(" -Zunstable-options --output-format=json");
+    }
 
     let rustflags_env = if let Some(&"doc") = args.get(0) {
+        if let Some(ref tc_rustdocflags) = ctx.toolchain.rustdocflags {
+            rustflags.push(' ');
+            rustflags.push_str(tc_rustdocflags);
+        }
         "RUSTDOCFLAGS"
     } else {
         "RUSTFLAGS"
     };
 
-    let mut command = build_env
-        .cargo()
-        .args(args)
-        .env("CARGO_INCREMENTAL", "0")
-        .env("RUST_BACKTRACE", "full")
-        .env(rustflags_env, rustflags);
+    let mut args = args.to_vec();
+    // Placed right after the subcommand, as the other arguments might be passed to rustc
+    for (idx, flag) in ctx.toolchain.cargo_flags.iter().enumerate() {
+        args.insert(1 + idx, flag.as_str());
+    }
+    if members.is_some() {
+        // Everything after `--` is passed to the test harness instead of Cargo
+        let idx = args
+            .iter()
+            .position(|&arg| arg == "--")
+            .unwrap_or(args.len());
+        args.insert(idx, "--message-format=json");
+        if ctx.experiment.timings {
+            args.insert(idx, "--timings=json");
+            args.insert(idx, "-Zunstable-options");
+        }
+    }
+
+    let mut env = vec![
+        ("CARGO_INCREMENTAL", "0".to_string()),
+        ("RUST_BACKTRACE", "full".to_string()),
+        (rustflags_env, rustflags),
+    ];
+    // Doctests are compiled by rustdoc when running the tests
+    if rustflags_env == "RUSTFLAGS" {
+        if let Some(ref tc_rustdocflags) = ctx.toolchain.rustdocflags {
+            env.push(("RUSTDOCFLAGS", tc_rustdocflags.clone()));
+        }
+    }
+    if let Some(ref target) = ctx.toolchain.target {
+        env.push(("CARGO_BUILD_TARGET", target.clone()));
+    }
+    // Build scripts compiling C code with the `cc` crate pick the compiler from these variables
+    if let Some(ref cc) = ctx.toolchain.cc {
+        env.push(("CC", cc.cc()));
+        env.push(("CXX", cc.cxx()));
+    }
+    // `#[bench]` requires the unstable `test` crate, which would otherwise prevent building the
+    // benchmarks of most crates with stable and beta toolchains. The timings and the JSON output
+    // of rustdoc are unstable too, and have to be generated with every toolchain.
+    if args.get(0) == Some(&"bench")
+        || (members.is_some() && ctx.experiment.timings)
+        || rustdoc_json
+    {
+        env.push(("RUSTC_BOOTSTRAP", "1".to_string()));
+    }
+    // Set last, to allow overriding the variables set by Crater
+    for (name, value) in &ctx.toolchain.env {
+        env.push((name.as_str(), value.clone()));
+    }
+
+    // The variables set after recording the invocation point inside the sandbox, and would be
+    // meaningless when reproducing the build elsewhere
+    ctx.invocations.lock().unwrap().push(Invocation {
+        toolchain: ctx.toolchain.rustup_name(),
+        cargo_toolchain: ctx.toolchain.cargo.clone(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        env: env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect(),
+        memory_limit: Some(ctx.config.sandbox.memory_limit.to_bytes() as u64),
+        cpu_limit: ctx.cpu_limits.quota,
+        cpuset: ctx.cpu_limits.cpuset.clone(),
+    });
+
+    let mut command = build_env.cargo().args(&args);
+    for (name, value) in &env {
+        command = command.env(name, value);
+    }
     if ctx.quiet {
         command = command.no_output_timeout(None);
     }
-    command.run()?;
+    if let Some(ref wrapper) = ctx.experiment.rustc_wrapper {
+        command = command.env("RUSTC_WRAPPER", wrapper.sandbox_path());
+    }
+    // When cargo comes from another toolchain it would otherwise use that toolchain's rustc
+    if ctx.toolchain.cargo.is_some() {
+        let bin_dir = ctx.toolchain.sandbox_bin_dir()?;
+        command = command
+            .env("RUSTC", bin_dir.join("rustc"))
+            .env("RUSTDOC", bin_dir.join("rustdoc"));
+    }
+
+    let timeout = Duration::from_secs(ctx.config.sandbox.build_timeout);
+    command = command.timeout(Some(timeout));
+
+    ctx.state.builds.watch(
+        &build_env.host_source_dir(),
+        timeout,
+        ctx.cpu_limits.cpuset.as_ref().map(|cpuset| cpuset.as_str()),
+        || {
+            if let Some(members) = members {
+                // Cargo's JSON messages tell which package each diagnostic belongs to, but only
+                // the rendered diagnostics are useful in the build log
+                let mut process_line = |line: &str| {
+                    if let Some(line) = members.process_line(line) {
+                        info!("{}", line);
+                    }
+                };
+                command
+                    .log_output(false)
+                    .process_lines(&mut process_line)
+                    .run()?;
+            } else {
+                command.run()?;
+            }
+            Ok(())
+        },
+    )
+}
+
+fn record_member_results<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    members: &Members,
+    results: &[MemberResult],
+) -> Fallible<()> {
+    // Crates with a single package already have their result recorded as a whole
+    if members.is_workspace() {
+        ctx.db
+            .record_member_results(ctx.experiment, ctx.toolchain, ctx.krate, results)?;
+    }
+    Ok(())
+}
+
+fn record_failed_targets<DB: WriteResults>(ctx: &TaskCtx<DB>, members: &Members) -> Fallible<()> {
+    if !members.failed_targets().is_empty() {
+        ctx.db.record_failed_targets(
+            ctx.experiment,
+            ctx.toolchain,
+            ctx.krate,
+            members.failed_targets(),
+        )?;
+    }
+    Ok(())
+}
+
+fn record_diagnostics<DB: WriteResults>(ctx: &TaskCtx<DB>, members: &Members) -> Fallible<()> {
+    if !members.diagnostics().is_empty() {
+        ctx.db.record_diagnostics(
+            ctx.experiment,
+            ctx.toolchain,
+            ctx.krate,
+            members.diagnostics(),
+        )?;
+    }
+    if !members.timings().is_empty() {
+        ctx.db
+            .record_unit_timings(ctx.experiment, ctx.toolchain, ctx.krate, members.timings())?;
+    }
+    // The counts are recorded even without diagnostics, to compare them with the other toolchain
+    ctx.db.record_diagnostic_counts(
+        ctx.experiment,
+        ctx.toolchain,
+        ctx.krate,
+        members.diagnostic_counts(),
+    )?;
+    Ok(())
+}
 
+/// Record the version of the C compiler chosen by the toolchain, as reported by the compiler
+/// installed in the sandbox.
+fn record_c_compiler<DB: WriteResults>(ctx: &TaskCtx<DB>, build_env: &Build) -> Fallible<()> {
+    let cc = match ctx.toolchain.cc {
+        Some(ref cc) => cc.cc(),
+        None => return Ok(()),
+    };
+
+    let mut version = None;
+    let mut process_line = |line: &str| {
+        if version.is_none() {
+            version = Some(line.trim().to_string());
+        }
+    };
+    let res = build_env
+        .cmd(cc.as_str())
+        .args(&["--version"])
+        .log_output(false)
+        .process_lines(&mut process_line)
+        .run();
+
+    // A missing compiler makes the builds compiling C code fail, which is visible in the results
+    match (res, version) {
+        (Ok(()), Some(version)) => {
+            info!("C compiler: {}", version);
+            ctx.db
+                .record_c_compiler(ctx.experiment, ctx.toolchain, ctx.krate, &version)?;
+        }
+        (Ok(()), None) => warn!("{} --version didn't output anything", cc),
+        (Err(err), _) => warn!("failed to get the version of {}: {}", cc, err),
+    }
     Ok(())
 }
 
+/// Test the crate in its build environment, capturing a snapshot of it if the crate failed.
+fn run_in_build<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+    test_fn: fn(&TaskCtx<DB>, &Build) -> Fallible<TestResult>,
+) -> Fallible<TestResult> {
+    // The configuration of the build directory isn't available inside the sandbox
+    index::write_cargo_config(ctx.experiment, &build_env.host_source_dir())?;
+    lockfile::capture_or_reuse(ctx, build_env)?;
+    record_c_compiler(ctx, build_env)?;
+
+    let res = test_fn(ctx, build_env);
+    match res {
+        Ok(TestResult::BuildFail(_)) | Ok(TestResult::TestFail(_)) => {
+            snapshot::record(ctx, build_env, FIX_DIR)?
+        }
+        _ => {}
+    }
+
+    // The fixed sources are only needed to check and snapshot them, while the target directory
+    // is kept between builds
+    let fix_dir = build_env.host_target_dir().join(FIX_DIR);
+    if fix_dir.exists() {
+        remove_dir_all(&fix_dir)?;
+    }
+    res
+}
+
 pub(super) fn run_test<DB: WriteResults>(
     action: &str,
     ctx: &TaskCtx<DB>,
@@ -116,90 +365,276 @@ pub(super) fn run_test<DB: WriteResults>(
                     ctx.toolchain.to_string(),
                     ctx.experiment.name
                 );
-                let sandbox = SandboxBuilder::new()
-                    .memory_limit(Some(ctx.config.sandbox.memory_limit.to_bytes()))
-                    .enable_networking(false);
-                detect_broken(
+                let sandbox = sandbox::builder(ctx);
+                // Cargo is invoked through the toolchain it's pinned to, if any
+                let cargo_toolchain = ctx.toolchain.cargo_toolchain();
+                let build_toolchain = cargo_toolchain.as_ref().unwrap_or(&ctx.toolchain.source);
+                let start = Instant::now();
+                let res = detect_broken(
                     ctx.build_dir
                         .lock()
                         .unwrap()
-                        .build(&ctx.toolchain, &ctx.krate.to_rustwide(), sandbox)
-                        .run(|build| test_fn(ctx, build)),
-                )
+                        .build(build_toolchain, &ctx.krate.to_rustwide(), sandbox)
+                        .run(|build| run_in_build(ctx, build, test_fn)),
+                );
+                // Recorded along with the result, to allow reproducing the build from the report
+                ctx.db.record_invocations(
+                    ctx.experiment,
+                    ctx.toolchain,
+                    ctx.krate,
+                    &ctx.invocations.lock().unwrap(),
+                )?;
+                ctx.db.record_resource_usage(
+                    ctx.experiment,
+                    ctx.toolchain,
+                    ctx.krate,
+                    ResourceUsage {
+                        duration: start.elapsed().as_secs_f64(),
+                        cpus: ctx.cpu_limits.cpus(),
+                    },
+                )?;
+                res
             },
         )?;
     }
     Ok(())
 }
 
-fn build<DB: WriteResults>(ctx: &TaskCtx<DB>, build_env: &Build) -> Fallible<()> {
-    run_cargo(ctx, build_env, &["build", "--frozen"])?;
-    run_cargo(ctx, build_env, &["test", "--frozen", "--no-run"])?;
+fn build<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+    members: &mut Members,
+) -> Fallible<()> {
+    let mut build_args = vec!["build", "--frozen"];
+    build_args.extend_from_slice(ctx.experiment.build_targets.cargo_args());
+    run_cargo(ctx, build_env, &build_args, Some(&mut *members))?;
+    run_cargo(
+        ctx,
+        build_env,
+        &["test", "--frozen", "--no-run"],
+        Some(members),
+    )?;
     Ok(())
 }
 
-fn test<DB: WriteResults>(ctx: &TaskCtx<DB>, build_env: &Build) -> Fallible<()> {
-    run_cargo(ctx, build_env, &["test", "--frozen"])
+fn test<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+    subcommand: &[&str],
+    args: &[&str],
+) -> TestResult {
+    let mut test_args = subcommand.to_vec();
+    test_args.push("--frozen");
+    test_args.extend_from_slice(args);
+    for flag in ctx.experiment.test_flags.args() {
+        test_args.push(flag);
+    }
+    let mut members = Members::default();
+    match run_cargo(ctx, build_env, &test_args, Some(&mut members)) {
+        Ok(()) => TestResult::TestPass,
+        // Packages without a library have no doctests to run
+        Err(_) if members.has_no_library() => TestResult::TestSkipped,
+        Err(err) => TestResult::TestFail(refine_failure_reason(
+            failure_reason(&err),
+            members.test_failure_reason(),
+        )),
+    }
 }
 
-pub(super) fn test_build_and_test<DB: WriteResults>(
+/// Get the packages of the workspace with `cargo metadata`, to tell them apart from the path
+/// dependencies outside of it. Every local package is considered a member if that fails.
+fn workspace_members(build_env: &Build) -> Members {
+    let mut metadata = String::new();
+    let mut process_line = |line: &str| {
+        if line.starts_with('{') {
+            metadata = line.to_string();
+        }
+    };
+    let res = build_env
+        .cargo()
+        .args(&["metadata", "--no-deps", "--format-version=1", "--frozen"])
+        .log_output(false)
+        .process_lines(&mut process_line)
+        .run();
+
+    match res
+        .map_err(Error::from)
+        .and_then(|()| Members::from_metadata(&metadata))
+    {
+        Ok(members) => members,
+        Err(err) => {
+            warn!("failed to get the members of the workspace: {}", err);
+            Members::default()
+        }
+    }
+}
+
+/// Build the crate with `build_fn`, recording the result of every package of workspaces.
+fn build_members<DB: WriteResults>(
     ctx: &TaskCtx<DB>,
     build_env: &Build,
+    result: TestResult,
+    build_fn: fn(&TaskCtx<DB>, &Build, &mut Members) -> Fallible<()>,
 ) -> Fallible<TestResult> {
-    let build_r = build(ctx, build_env);
-    let test_r = if build_r.is_ok() {
-        Some(test(ctx, build_env))
-    } else {
-        None
+    let mut members = workspace_members(build_env);
+    let (result, reason) = match build_fn(ctx, build_env, &mut members) {
+        Ok(()) => (result, FailureReason::Unknown),
+        Err(err) => {
+            let reason =
+                refine_failure_reason(failure_reason(&err), members.build_failure_reason());
+            (TestResult::BuildFail(reason), reason)
+        }
     };
+    // The other toolchain could build those packages, and their results must be comparable
+    let passed = match result {
+        TestResult::BuildFail(_) => TestResult::Skipped(SkipReason::WorkspaceBuildFailed),
+        other => other,
+    };
+    record_member_results(ctx, &members, &members.build_results(reason, passed))?;
+    record_failed_targets(ctx, &members)?;
+    record_diagnostics(ctx, &members)?;
+    Ok(result)
+}
 
-    Ok(match (build_r, test_r) {
-        (Err(err), None) => TestResult::BuildFail(failure_reason(&err)),
-        (Ok(_), Some(Err(err))) => TestResult::TestFail(failure_reason(&err)),
-        (Ok(_), Some(Ok(_))) => TestResult::TestPass,
-        (_, _) => unreachable!(),
-    })
+/// Build the crate with `build_fn` and run its tests with the `subcommand` of Cargo, testing the
+/// members of workspaces one at a time.
+fn build_and_run_tests<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+    build_fn: fn(&TaskCtx<DB>, &Build, &mut Members) -> Fallible<()>,
+    subcommand: &[&str],
+) -> Fallible<TestResult> {
+    let mut members = workspace_members(build_env);
+    let build_res = build_fn(ctx, build_env, &mut members);
+    record_diagnostics(ctx, &members)?;
+    if let Err(err) = build_res {
+        let reason = refine_failure_reason(failure_reason(&err), members.build_failure_reason());
+        // The tests of the other packages weren't run, while they could be with the other
+        // toolchain
+        let results = members.build_results(
+            reason,
+            TestResult::Skipped(SkipReason::WorkspaceBuildFailed),
+        );
+        record_member_results(ctx, &members, &results)?;
+        record_failed_targets(ctx, &members)?;
+        return Ok(TestResult::BuildFail(reason));
+    }
+
+    if !members.is_workspace() {
+        return Ok(test(ctx, build_env, subcommand, &[]));
+    }
+
+    // Test the members of the workspace one at a time, to know which ones are failing
+    let mut result = None;
+    let mut results = Vec::new();
+    for name in members.names() {
+        let member_result = test(ctx, build_env, subcommand, &["-p", name]);
+        // The members without tests to run don't change the result of the whole crate
+        if member_result != TestResult::TestSkipped
+            && result.unwrap_or(TestResult::TestPass) == TestResult::TestPass
+        {
+            result = Some(member_result);
+        }
+        results.push(MemberResult {
+            name: name.clone(),
+            result: member_result,
+        });
+    }
+    record_member_results(ctx, &members, &results)?;
+
+    Ok(result.unwrap_or(TestResult::TestSkipped))
+}
+
+pub(super) fn test_build_and_test<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+) -> Fallible<TestResult> {
+    build_and_run_tests(ctx, build_env, build, &["test"])
+}
+
+pub(super) fn test_miri<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+) -> Fallible<TestResult> {
+    build_and_run_tests(ctx, build_env, build, &["miri", "test"])
+}
+
+pub(super) fn test_doctests<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+) -> Fallible<TestResult> {
+    // The doctests are compiled by rustdoc while running them, so only the crate itself has to
+    // be built beforehand
+    build_and_run_tests(
+        ctx,
+        build_env,
+        |ctx, build_env, members| run_cargo(ctx, build_env, &["build", "--frozen"], Some(members)),
+        &["test", "--doc"],
+    )
 }
 
 pub(super) fn test_build_only<DB: WriteResults>(
     ctx: &TaskCtx<DB>,
     build_env: &Build,
 ) -> Fallible<TestResult> {
-    if let Err(err) = build(ctx, build_env) {
-        Ok(TestResult::BuildFail(failure_reason(&err)))
-    } else {
-        Ok(TestResult::TestSkipped)
-    }
+    build_members(ctx, build_env, TestResult::TestSkipped, build)
 }
 
 pub(super) fn test_check_only<DB: WriteResults>(
     ctx: &TaskCtx<DB>,
     build_env: &Build,
 ) -> Fallible<TestResult> {
-    if let Err(err) = run_cargo(
+    build_members(
         ctx,
         build_env,
-        &["check", "--frozen", "--all", "--all-targets"],
-    ) {
-        Ok(TestResult::BuildFail(failure_reason(&err)))
-    } else {
-        Ok(TestResult::TestPass)
-    }
+        TestResult::TestPass,
+        |ctx, build_env, members| {
+            run_cargo(
+                ctx,
+                build_env,
+                &["check", "--frozen", "--all", "--all-targets"],
+                Some(members),
+            )
+        },
+    )
 }
 
 pub(super) fn test_clippy_only<DB: WriteResults>(
     ctx: &TaskCtx<DB>,
     build_env: &Build,
 ) -> Fallible<TestResult> {
-    if let Err(err) = run_cargo(
+    build_members(
         ctx,
         build_env,
-        &["clippy", "--frozen", "--all", "--all-targets"],
-    ) {
-        Ok(TestResult::BuildFail(failure_reason(&err)))
-    } else {
-        Ok(TestResult::TestPass)
-    }
+        TestResult::TestPass,
+        |ctx, build_env, members| {
+            run_cargo(
+                ctx,
+                build_env,
+                &["clippy", "--frozen", "--all", "--all-targets"],
+                Some(members),
+            )
+        },
+    )
+}
+
+pub(super) fn test_build_benches<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+) -> Fallible<TestResult> {
+    build_members(
+        ctx,
+        build_env,
+        TestResult::TestPass,
+        |ctx, build_env, members| {
+            run_cargo(
+                ctx,
+                build_env,
+                &["bench", "--frozen", "--no-run", "--all"],
+                Some(members),
+            )
+        },
+    )
 }
 
 pub(super) fn test_rustdoc<DB: WriteResults>(
@@ -210,6 +645,7 @@ pub(super) fn test_rustdoc<DB: WriteResults>(
         ctx,
         build_env,
         &["doc", "--frozen", "--no-deps", "--document-private-items"],
+        None,
     );
 
     // Make sure to remove the built documentation
@@ -222,3 +658,122 @@ pub(super) fn test_rustdoc<DB: WriteResults>(
         Ok(TestResult::TestPass)
     }
 }
+
+pub(super) fn test_rustdoc_json<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+) -> Fallible<TestResult> {
+    let res = run_cargo(ctx, build_env, &["doc", "--frozen", "--no-deps"], None);
+
+    // Only the fingerprints of the items are kept, as the JSON files are large
+    let doc_dir = build_env.host_target_dir().join("doc");
+    let mut items = Vec::new();
+    if res.is_ok() {
+        for entry in fs::read_dir(&doc_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+                let doc = serde_json::from_slice(&fs::read(&path)?)
+                    .with_context(|_| format!("invalid rustdoc JSON output {}", path.display()))?;
+                items.extend(rustdoc_json::doc_items(&doc)?);
+            }
+        }
+    }
+    remove_dir_all(&doc_dir)?;
+
+    if let Err(err) = res {
+        Ok(TestResult::BuildFail(failure_reason(&err)))
+    } else {
+        ctx.db
+            .record_doc_items(ctx.experiment, ctx.toolchain, ctx.krate, &items)?;
+        Ok(TestResult::TestPass)
+    }
+}
+
+fn copy_dir(src: &Path, dest: &Path) -> Fallible<()> {
+    for entry in WalkDir::new(src) {
+        let entry = entry?;
+        let path = dest.join(entry.path().strip_prefix(src)?);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&path)?;
+        } else {
+            fs::copy(entry.path(), &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fix the copy of the crate in `FIX_DIR` and check it afterwards. As the crate was building
+/// before, the fixed code failing to compile means the fixes broke it, while a failure of `cargo
+/// fix` itself with the code still compiling means the fixes couldn't be applied.
+fn fix<DB: WriteResults>(ctx: &TaskCtx<DB>, build_env: &Build) -> Fallible<TestResult> {
+    let mut fix_args = vec![
+        "fix",
+        "--frozen",
+        "--all",
+        "--all-targets",
+        "--allow-no-vcs",
+        "--allow-dirty",
+        // The fixes breaking the build are kept instead of being reverted, to check them
+        "--broken-code",
+        "--manifest-path",
+        SANDBOX_FIX_MANIFEST,
+    ];
+    fix_args.extend(ctx.config.fix.args.iter().map(|arg| arg.as_str()));
+    let fix_res = run_cargo(ctx, build_env, &fix_args, None);
+    if let Err(ref err) = fix_res {
+        let reason = failure_reason(err);
+        if reason != FailureReason::Unknown {
+            return Ok(TestResult::TestFail(reason));
+        }
+    }
+
+    let mut members = Members::default();
+    let check_res = run_cargo(
+        ctx,
+        build_env,
+        &[
+            "check",
+            "--frozen",
+            "--all",
+            "--all-targets",
+            "--manifest-path",
+            SANDBOX_FIX_MANIFEST,
+        ],
+        Some(&mut members),
+    );
+    record_diagnostics(ctx, &members)?;
+    Ok(match (check_res, fix_res) {
+        (Err(err), _) => TestResult::TestFail(refine_failure_reason(
+            failure_reason(&err),
+            members.build_failure_reason(),
+        )),
+        (Ok(()), Err(_)) => TestResult::TestFail(FailureReason::FixFailed),
+        (Ok(()), Ok(())) => TestResult::TestPass,
+    })
+}
+
+pub(super) fn test_fix<DB: WriteResults>(
+    ctx: &TaskCtx<DB>,
+    build_env: &Build,
+) -> Fallible<TestResult> {
+    // Crates already failing to build are not fixed, as the fixes couldn't be checked
+    let mut members = Members::default();
+    let res = run_cargo(
+        ctx,
+        build_env,
+        &["check", "--frozen", "--all", "--all-targets"],
+        Some(&mut members),
+    );
+    if let Err(err) = res {
+        record_diagnostics(ctx, &members)?;
+        let reason = refine_failure_reason(failure_reason(&err), members.build_failure_reason());
+        return Ok(TestResult::BuildFail(reason));
+    }
+
+    let fix_dir = build_env.host_target_dir().join(FIX_DIR);
+    if fix_dir.exists() {
+        remove_dir_all(&fix_dir)?;
+    }
+    copy_dir(&build_env.host_source_dir(), &fix_dir)?;
+    fix(ctx, build_env)
+}
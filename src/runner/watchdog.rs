@@ -0,0 +1,332 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{
+    mpsc::{self, RecvTimeoutError},
+    Mutex,
+};
+use std::time::{Duration, Instant};
+
+/// Time rustwide has to stop a build by itself after its timeout, before the watchdog steps in.
+const GRACE_PERIOD: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "build killed by the watchdog after running for {} seconds",
+    _0
+)]
+pub(super) struct BuildKilled(pub(super) u64);
+
+struct RunningBuild {
+    started_at: Instant,
+    timeout: Duration,
+    cpuset: Option<String>,
+    pinned: bool,
+    killed: bool,
+}
+
+impl RunningBuild {
+    fn is_stuck(&self, now: Instant) -> bool {
+        !self.killed && now.duration_since(self.started_at) > self.timeout + GRACE_PERIOD
+    }
+}
+
+/// Builds being executed by the workers, keyed by the source directory mounted in their
+/// container.
+#[derive(Default)]
+pub(super) struct RunningBuilds {
+    builds: Mutex<HashMap<PathBuf, RunningBuild>>,
+}
+
+impl RunningBuilds {
+    /// Run a build command, allowing the watchdog to pin its container to the provided cpuset and
+    /// to kill it if it's still running long after its timeout. The build fails with
+    /// `BuildKilled` if that happens.
+    pub(super) fn watch<T, F>(
+        &self,
+        source_dir: &Path,
+        timeout: Duration,
+        cpuset: Option<&str>,
+        f: F,
+    ) -> Fallible<T>
+    where
+        F: FnOnce() -> Fallible<T>,
+    {
+        self.builds.lock().unwrap().insert(
+            source_dir.to_path_buf(),
+            RunningBuild {
+                started_at: Instant::now(),
+                timeout,
+                cpuset: cpuset.map(|cpuset| cpuset.to_string()),
+                pinned: false,
+                killed: false,
+            },
+        );
+        let res = f();
+        let build = self.builds.lock().unwrap().remove(source_dir);
+
+        match (res, build) {
+            (Err(err), Some(ref build)) if build.killed => {
+                let elapsed = build.started_at.elapsed().as_secs();
+                Err(err.context(BuildKilled(elapsed)).into())
+            }
+            (res, _) => res,
+        }
+    }
+
+    /// Mark the builds running past their timeout and grace period as killed, returning their
+    /// source directories.
+    fn take_stuck(&self, now: Instant) -> Vec<PathBuf> {
+        let mut builds = self.builds.lock().unwrap();
+        let mut stuck = Vec::new();
+        for (source_dir, build) in builds.iter_mut() {
+            if build.is_stuck(now) {
+                build.killed = true;
+                stuck.push(source_dir.clone());
+            }
+        }
+        stuck
+    }
+
+    /// Return the builds whose container wasn't pinned to their cpuset yet, along with the time
+    /// they started at to identify them in `mark_pinned`.
+    fn unpinned(&self) -> Vec<(PathBuf, Instant, String)> {
+        self.builds
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, build)| !build.pinned && !build.killed)
+            .filter_map(|(source_dir, build)| {
+                let cpuset = build.cpuset.clone()?;
+                Some((source_dir.clone(), build.started_at, cpuset))
+            })
+            .collect()
+    }
+
+    fn mark_pinned(&self, source_dir: &Path, started_at: Instant) {
+        if let Some(build) = self.builds.lock().unwrap().get_mut(source_dir) {
+            // The build might have been replaced by the next one in the meantime
+            if build.started_at == started_at {
+                build.pinned = true;
+            }
+        }
+    }
+}
+
+/// Thread forcibly removing the containers of the builds that didn't stop after their timeout,
+/// for example because the processes are stuck in uninterruptible I/O. The worker running the
+/// build is then able to record the result and move on to the next task.
+///
+/// rustwide doesn't allow passing `--cpuset-cpus` to `docker create`, so the watchdog also pins
+/// the containers of the builds to the cpuset of their worker as soon as they're started.
+pub(super) struct BuildWatchdog<'a> {
+    interval: Duration,
+    builds: &'a RunningBuilds,
+    stop_send: Mutex<mpsc::Sender<()>>,
+    stop_recv: Mutex<mpsc::Receiver<()>>,
+}
+
+impl<'a> BuildWatchdog<'a> {
+    pub(super) fn new(interval: Duration, builds: &'a RunningBuilds) -> Self {
+        let (stop_send, stop_recv) = mpsc::channel();
+        BuildWatchdog {
+            interval,
+            builds,
+            stop_send: Mutex::new(stop_send),
+            stop_recv: Mutex::new(stop_recv),
+        }
+    }
+
+    pub(super) fn stop(&self) {
+        self.stop_send.lock().unwrap().send(()).unwrap();
+    }
+
+    pub(super) fn run(&self) -> Fallible<()> {
+        loop {
+            for source_dir in self.builds.take_stuck(Instant::now()) {
+                warn!(
+                    "build in {} is stuck past its timeout, removing its container",
+                    source_dir.display()
+                );
+                if let Err(err) = kill_containers(&source_dir) {
+                    crate::utils::report_failure(&err);
+                }
+            }
+
+            for (source_dir, started_at, cpuset) in self.builds.unpinned() {
+                match pin_containers(&source_dir, &cpuset) {
+                    Ok(true) => self.builds.mark_pinned(&source_dir, started_at),
+                    // The container wasn't created yet
+                    Ok(false) => {}
+                    Err(err) => crate::utils::report_failure(&err),
+                }
+            }
+
+            match self.stop_recv.lock().unwrap().recv_timeout(self.interval) {
+                Ok(()) => return Ok(()),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => panic!("disconnected stop channel"),
+            }
+        }
+    }
+}
+
+fn docker(args: &[&str]) -> Fallible<String> {
+    let output = Command::new("docker").args(args).output()?;
+    if !output.status.success() {
+        bail!(
+            "docker {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// rustwide doesn't expose the containers it creates, so they're found through the source
+/// directory of the build, which is mounted only in the containers of a single worker.
+fn containers_of(source_dir: &Path) -> Fallible<Vec<String>> {
+    let running = docker(&["ps", "-q", "--no-trunc"])?;
+    let mut args = vec![
+        "inspect",
+        "--format",
+        "{{.Id}}{{range .Mounts}}|{{.Source}}{{end}}",
+    ];
+    args.extend(running.lines().filter(|id| !id.is_empty()));
+    if args.len() == 3 {
+        return Ok(Vec::new());
+    }
+
+    Ok(containers_mounting(&docker(&args)?, source_dir))
+}
+
+fn kill_containers(source_dir: &Path) -> Fallible<()> {
+    for id in containers_of(source_dir)? {
+        info!("forcibly removing container {}", id);
+        docker(&["rm", "--force", &id])?;
+    }
+    Ok(())
+}
+
+/// Pin the containers of the build to the cpuset, returning whether any container was found.
+fn pin_containers(source_dir: &Path, cpuset: &str) -> Fallible<bool> {
+    let containers = containers_of(source_dir)?;
+    for id in &containers {
+        debug!("pinning container {} to cpuset {}", id, cpuset);
+        docker(&pin_args(id, cpuset))?;
+    }
+    Ok(!containers.is_empty())
+}
+
+fn pin_args<'a>(id: &'a str, cpuset: &'a str) -> [&'a str; 4] {
+    ["update", "--cpuset-cpus", cpuset, id]
+}
+
+fn containers_mounting(inspect_output: &str, source_dir: &Path) -> Vec<String> {
+    inspect_output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split('|');
+            let id = parts.next()?;
+            if parts.any(|source| Path::new(source) == source_dir) {
+                Some(id.to_string())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{containers_mounting, pin_args, BuildKilled, RunningBuilds, GRACE_PERIOD};
+    use crate::prelude::*;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_containers_mounting() {
+        let output = "aaa|/work/builds/worker-0/source|/work/cargo-home\n\
+                      bbb|/work/builds/worker-1/source|/work/cargo-home\n\
+                      ccc\n";
+        assert_eq!(
+            containers_mounting(output, Path::new("/work/builds/worker-1/source")),
+            vec!["bbb".to_string()]
+        );
+        assert!(containers_mounting(output, Path::new("/work/builds")).is_empty());
+    }
+
+    #[test]
+    fn test_stuck_builds() {
+        let builds = RunningBuilds::default();
+        let source_dir = Path::new("/work/builds/worker-0/source");
+        let timeout = Duration::from_secs(60);
+
+        let err = builds
+            .watch(source_dir, timeout, None, || -> Fallible<()> {
+                // Builds are only stuck after the grace period
+                let now = Instant::now();
+                assert!(builds.take_stuck(now + timeout).is_empty());
+                let later = now + timeout + GRACE_PERIOD + Duration::from_secs(1);
+                assert_eq!(builds.take_stuck(later), vec![source_dir.to_path_buf()]);
+                assert!(builds.take_stuck(later).is_empty());
+
+                bail!("container removed");
+            })
+            .unwrap_err();
+        assert!(err
+            .iter_chain()
+            .any(|cause| cause.downcast_ctx::<BuildKilled>().is_some()));
+
+        // Failures of builds that weren't killed are left alone
+        let err = builds
+            .watch(source_dir, timeout, None, || -> Fallible<()> {
+                bail!("build failed")
+            })
+            .unwrap_err();
+        assert!(!err
+            .iter_chain()
+            .any(|cause| cause.downcast_ctx::<BuildKilled>().is_some()));
+    }
+
+    #[test]
+    fn test_pinned_builds() {
+        let builds = RunningBuilds::default();
+        let source_dir = Path::new("/work/builds/worker-1/source");
+        let timeout = Duration::from_secs(60);
+
+        builds
+            .watch(source_dir, timeout, Some("2-3"), || -> Fallible<()> {
+                let unpinned = builds.unpinned();
+                assert_eq!(unpinned.len(), 1);
+                let (ref dir, started_at, ref cpuset) = unpinned[0];
+                assert_eq!(dir, source_dir);
+                assert_eq!(cpuset, "2-3");
+
+                // Builds started after the pinned one still have to be pinned
+                builds.mark_pinned(source_dir, started_at + Duration::from_secs(1));
+                assert_eq!(builds.unpinned().len(), 1);
+                builds.mark_pinned(source_dir, started_at);
+                assert!(builds.unpinned().is_empty());
+                Ok(())
+            })
+            .unwrap();
+
+        // Builds without a cpuset are never pinned
+        builds
+            .watch(source_dir, timeout, None, || -> Fallible<()> {
+                assert!(builds.unpinned().is_empty());
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_pin_args() {
+        assert_eq!(
+            pin_args("aaa", "0-1"),
+            ["update", "--cpuset-cpus", "0-1", "aaa"]
+        );
+    }
+}
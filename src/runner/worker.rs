@@ -1,12 +1,17 @@
 use crate::config::Config;
+use crate::dirs::WORK_DIR;
 use crate::experiments::Experiment;
 use crate::prelude::*;
 use crate::results::{BrokenReason, TestResult, WriteResults};
 use crate::runner::graph::{TasksGraph, WalkResult};
+use crate::runner::index;
+use crate::runner::sandbox::CpuLimits;
 use crate::runner::{OverrideResult, RunnerState};
 use crate::utils;
+use petgraph::graph::NodeIndex;
 use rustwide::{BuildDirectory, Workspace};
 use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
@@ -17,6 +22,9 @@ use std::thread;
 use std::time::Duration;
 use systemstat::{Filesystem, Platform, System};
 
+/// How many times a worker is restarted after crashing before giving up on the experiment.
+const MAX_WORKER_RESTARTS: usize = 10;
+
 pub(super) struct Worker<'a, DB: WriteResults + Sync> {
     name: String,
     workspace: &'a Workspace,
@@ -27,7 +35,10 @@ pub(super) struct Worker<'a, DB: WriteResults + Sync> {
     state: &'a RunnerState,
     db: &'a DB,
     parked_threads: &'a Mutex<HashMap<thread::ThreadId, thread::Thread>>,
+    cpu_limits: CpuLimits,
     target_dir_cleanup: AtomicBool,
+    /// Task being run, marked as failed if the worker crashes in the middle of it.
+    current_task: Mutex<Option<NodeIndex>>,
 }
 
 impl<'a, DB: WriteResults + Sync> Worker<'a, DB> {
@@ -40,6 +51,7 @@ impl<'a, DB: WriteResults + Sync> Worker<'a, DB> {
         state: &'a RunnerState,
         db: &'a DB,
         parked_threads: &'a Mutex<HashMap<thread::ThreadId, thread::Thread>>,
+        cpu_limits: CpuLimits,
     ) -> Self {
         Worker {
             build_dir: Mutex::new(workspace.build_dir(&name)),
@@ -51,7 +63,9 @@ impl<'a, DB: WriteResults + Sync> Worker<'a, DB> {
             state,
             db,
             parked_threads,
+            cpu_limits,
             target_dir_cleanup: AtomicBool::new(false),
+            current_task: Mutex::new(None),
         }
     }
 
@@ -59,14 +73,90 @@ impl<'a, DB: WriteResults + Sync> Worker<'a, DB> {
         &self.name
     }
 
-    pub(super) fn run(&self) -> Fallible<()> {
+    /// Run the worker, restarting it if it panics or returns an error: a single crash would
+    /// otherwise reduce the parallelism of the agent until the end of the experiment.
+    pub(super) fn supervise(&self) -> Fallible<()> {
+        let mut restarts = 0;
+        loop {
+            let error = match panic::catch_unwind(AssertUnwindSafe(|| self.run())) {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(err)) => {
+                    utils::report_failure(&err);
+                    err
+                }
+                Err(panic) => {
+                    utils::report_panic(&*panic);
+                    err_msg(format!(
+                        "worker panicked: {}",
+                        utils::panic_message(&*panic).unwrap_or("unknown payload")
+                    ))
+                }
+            };
+
+            // Panicking while the graph is locked could leave it in an inconsistent state
+            if restarts == MAX_WORKER_RESTARTS || self.graph.is_poisoned() {
+                return Err(error);
+            }
+            restarts += 1;
+            warn!(
+                "{} crashed, restarting it ({}/{})",
+                self.name, restarts, MAX_WORKER_RESTARTS
+            );
+
+            if let Err(e) = self
+                .db
+                .record_worker_crash(self.ex, &self.name, &error.to_string())
+                .with_context(|_| "failed to report the worker crash")
+            {
+                utils::report_failure(&e);
+            }
+            self.recover(&error)?;
+        }
+    }
+
+    fn recover(&self, error: &failure::Error) -> Fallible<()> {
+        // Nothing else would complete the task, blocking the crate's other tasks forever
+        if let Some(id) = self.current_task.lock().unwrap().take() {
+            self.graph.lock().unwrap().mark_as_failed(
+                id,
+                self.ex,
+                self.db,
+                self.state,
+                self.config,
+                error,
+                TestResult::Error,
+            )?;
+            self.unpark_threads();
+        }
+
+        // The build directory could have been left in any state by the crash
+        self.build_dir.clear_poison();
+        self.schedule_target_dir_cleanup();
+        Ok(())
+    }
+
+    fn unpark_threads(&self) {
+        let mut parked = self.parked_threads.lock().unwrap();
+        for (_id, thread) in parked.drain() {
+            thread.unpark();
+        }
+    }
+
+    fn run(&self) -> Fallible<()> {
         // This uses a `loop` instead of a `while let` to avoid locking the graph too much
         loop {
+            if self.state.is_cancelled() {
+                info!("{} stopped, as the experiment was cancelled", self.name);
+                break;
+            }
             self.maybe_cleanup_target_dir()?;
+            // Written again before every task, as cleaning up the build directory removes it
+            index::write_cargo_config(self.ex, &WORK_DIR.join("builds").join(&self.name))?;
             let walk_result = self.graph.lock().unwrap().next_task(self.ex, self.db);
             match walk_result {
                 WalkResult::Task(id, task) => {
                     info!("running task: {:?}", task);
+                    *self.current_task.lock().unwrap() = Some(id);
                     let res = task.run(
                         self.config,
                         self.workspace,
@@ -74,6 +164,7 @@ impl<'a, DB: WriteResults + Sync> Worker<'a, DB> {
                         self.ex,
                         self.db,
                         self.state,
+                        &self.cpu_limits,
                     );
                     if let Err(e) = res {
                         error!("task failed, marking childs as failed too: {:?}", task);
@@ -104,12 +195,9 @@ impl<'a, DB: WriteResults + Sync> Worker<'a, DB> {
                     } else {
                         self.graph.lock().unwrap().mark_as_completed(id);
                     }
+                    *self.current_task.lock().unwrap() = None;
 
-                    // Unpark all the threads
-                    let mut parked = self.parked_threads.lock().unwrap();
-                    for (_id, thread) in parked.drain() {
-                        thread.unpark();
-                    }
+                    self.unpark_threads();
                 }
                 WalkResult::Blocked => {
                     // Wait until another thread finished before looking for tasks again
@@ -120,6 +208,10 @@ impl<'a, DB: WriteResults + Sync> Worker<'a, DB> {
                         let current = thread::current();
                         parked_threads.insert(current.id(), current);
                     }
+                    // Checked after registering the thread, so a cancellation can't be missed
+                    if self.state.is_cancelled() {
+                        continue;
+                    }
                     thread::park();
                 }
                 WalkResult::NotBlocked => unreachable!("NotBlocked leaked from the run"),
@@ -144,6 +236,71 @@ impl<'a, DB: WriteResults + Sync> Worker<'a, DB> {
     }
 }
 
+/// Thread periodically checking whether the experiment was cancelled, stopping the workers once
+/// they finish their current task.
+pub(super) struct CancellationWatcher<'a, DB: WriteResults + Sync> {
+    interval: Duration,
+    ex: &'a Experiment,
+    db: &'a DB,
+    state: &'a RunnerState,
+    parked_threads: &'a Mutex<HashMap<thread::ThreadId, thread::Thread>>,
+    stop_send: Mutex<mpsc::Sender<()>>,
+    stop_recv: Mutex<mpsc::Receiver<()>>,
+}
+
+impl<'a, DB: WriteResults + Sync> CancellationWatcher<'a, DB> {
+    pub(super) fn new(
+        interval: Duration,
+        ex: &'a Experiment,
+        db: &'a DB,
+        state: &'a RunnerState,
+        parked_threads: &'a Mutex<HashMap<thread::ThreadId, thread::Thread>>,
+    ) -> Self {
+        let (stop_send, stop_recv) = mpsc::channel();
+        CancellationWatcher {
+            interval,
+            ex,
+            db,
+            state,
+            parked_threads,
+            stop_send: Mutex::new(stop_send),
+            stop_recv: Mutex::new(stop_recv),
+        }
+    }
+
+    pub(super) fn stop(&self) {
+        self.stop_send.lock().unwrap().send(()).unwrap();
+    }
+
+    pub(super) fn run(&self) -> Fallible<()> {
+        loop {
+            match self.db.is_cancelled(self.ex) {
+                Ok(true) => {
+                    warn!(
+                        "experiment {} was cancelled or taken away, stopping the workers",
+                        self.ex.name
+                    );
+                    self.state.cancel();
+                    // The workers waiting for other tasks would otherwise never notice
+                    for (_id, thread) in self.parked_threads.lock().unwrap().drain() {
+                        thread.unpark();
+                    }
+                    return Ok(());
+                }
+                Ok(false) => {}
+                // Failing to check isn't a reason to stop the experiment
+                Err(err) => utils::report_failure(&err),
+            }
+
+            match self.stop_recv.lock().unwrap().recv_timeout(self.interval) {
+                Ok(()) => return Ok(()),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => panic!("disconnected stop channel"),
+            }
+        }
+    }
+}
+
 pub(super) struct DiskSpaceWatcher<'a, DB: WriteResults + Sync> {
     interval: Duration,
     threshold: f32,
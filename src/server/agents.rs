@@ -1,33 +1,63 @@
-use crate::agent::Capabilities;
+use crate::agent::{Capabilities, HEARTBEAT_INTERVAL};
+use crate::audit::{self, Actor, AuditAction};
 use crate::db::{Database, QueryUtils};
 use crate::experiments::{Assignee, Experiment};
 use crate::prelude::*;
 use crate::server::tokens::Tokens;
 use chrono::Duration;
 use chrono::{DateTime, Utc};
-use std::collections::HashSet;
+use serde_json::json;
+use std::collections::HashMap;
 
 /// Number of seconds without an heartbeat after an agent should be considered unreachable.
 const INACTIVE_AFTER: i64 = 300;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum AgentStatus {
-    Working,
-    Idle,
-    Unreachable,
+/// Name of the job checking whether the agents running experiments are still reachable, and
+/// queueing again the experiments of the unreachable ones if configured.
+pub const HEARTBEATS_JOB: &str = "heartbeats";
+
+/// Name of the job releasing the crates claimed by the agents whose lease expired.
+pub const RECLAIM_JOB: &str = "reclaim-crates";
+
+/// Name of the job queueing again the experiments whose agents stopped sending results.
+pub const STALLED_JOB: &str = "requeue-stalled";
+
+/// Parse the numeric components of a version, ignoring pre-release and build metadata.
+pub(crate) fn parse_version(version: &str) -> Option<Vec<u64>> {
+    let version = version.split(|c| c == '-' || c == '+').next()?;
+    version.split('.').map(|part| part.parse().ok()).collect()
 }
 
+/// Check whether an agent version is at least the minimum one. Agents not reporting their version
+/// are considered outdated.
+pub fn is_version_supported(version: Option<&str>, minimum: &str) -> bool {
+    match (version.and_then(parse_version), parse_version(minimum)) {
+        (Some(version), Some(minimum)) => version >= minimum,
+        (None, _) => false,
+        // An invalid minimum version is rejected when the server starts
+        (Some(_), None) => true,
+    }
+}
+
+string_enum!(pub enum AgentStatus {
+    Working => "working",
+    Idle => "idle",
+    Unreachable => "unreachable",
+});
+
 pub struct Agent {
     name: String,
-    experiment: Option<Experiment>,
+    namespace: String,
+    experiments: Vec<Experiment>,
     last_heartbeat: Option<DateTime<Utc>>,
     git_revision: Option<String>,
+    version: Option<String>,
     capabilities: Option<Capabilities>,
 }
 
 impl Agent {
-    fn with_experiment(mut self, db: &Database) -> Fallible<Self> {
-        self.experiment = Experiment::run_by(db, &Assignee::Agent(self.name.clone()))?;
+    fn with_experiments(mut self, db: &Database) -> Fallible<Self> {
+        self.experiments = Experiment::all_run_by(db, &Assignee::Agent(self.name.clone()))?;
         Ok(self)
     }
 
@@ -40,12 +70,21 @@ impl Agent {
         self.git_revision.as_ref()
     }
 
+    pub fn version(&self) -> Option<&String> {
+        self.version.as_ref()
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
 
-    pub fn assigned_experiment(&self) -> Option<&Experiment> {
-        self.experiment.as_ref()
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Experiments the agent is running, which are more than one if its workers are partitioned.
+    pub fn assigned_experiments(&self) -> &[Experiment] {
+        &self.experiments
     }
 
     pub fn last_heartbeat(&self) -> Option<&DateTime<Utc>> {
@@ -55,7 +94,7 @@ impl Agent {
     pub fn status(&self) -> AgentStatus {
         if let Some(ref heartbeat) = self.last_heartbeat {
             if Utc::now() - Duration::seconds(INACTIVE_AFTER) < *heartbeat {
-                if self.experiment.is_some() {
+                if !self.experiments.is_empty() {
                     return AgentStatus::Working;
                 } else {
                     return AgentStatus::Idle;
@@ -85,17 +124,38 @@ impl Agents {
 
     fn synchronize(&self, tokens: &Tokens) -> Fallible<()> {
         self.db.transaction(|trans| {
-            let mut real = tokens.agents.values().collect::<HashSet<&String>>();
+            let mut real = tokens.all_agents().collect::<HashMap<&str, &str>>();
             for agent in &self.all()? {
-                if !real.remove(&agent.name) {
+                if let Some(namespace) = real.remove(agent.name.as_str()) {
+                    if agent.namespace != namespace {
+                        trans.execute(
+                            "UPDATE agents SET namespace = ?1 WHERE name = ?2;",
+                            &[&namespace, &agent.name.as_str()],
+                        )?;
+                    }
+                } else {
                     trans.execute("DELETE FROM agents WHERE name = ?1;", &[&agent.name])?;
+                    audit::record(
+                        trans,
+                        &Actor::Server,
+                        AuditAction::AgentRemoved,
+                        None,
+                        &json!({ "agent": agent.name }),
+                    )?;
                 }
             }
 
-            for missing in &real {
+            for (missing, namespace) in &real {
                 trans.execute(
-                    "INSERT INTO agents (name) VALUES (?1);",
-                    &[&missing.as_str()],
+                    "INSERT INTO agents (name, namespace) VALUES (?1, ?2);",
+                    &[missing, namespace],
+                )?;
+                audit::record(
+                    trans,
+                    &Actor::Server,
+                    AuditAction::AgentAdded,
+                    None,
+                    &json!({ "agent": missing, "namespace": namespace }),
                 )?;
             }
 
@@ -108,18 +168,20 @@ impl Agents {
             .query("SELECT * FROM agents ORDER BY name;", &[], |row| {
                 Agent {
                     name: row.get("name"),
+                    namespace: row.get("namespace"),
                     last_heartbeat: row.get("last_heartbeat"),
                     git_revision: row.get("git_revision"),
+                    version: row.get("version"),
 
                     // Lazy loaded after this
-                    experiment: None,
+                    experiments: Vec::new(),
                     capabilities: None,
                 }
             })?
             .into_iter()
             .map(|agent| {
                 agent
-                    .with_experiment(&self.db)
+                    .with_experiments(&self.db)
                     .and_then(|agent| agent.with_capabilities(&self.db))
             })
             .collect()
@@ -131,15 +193,17 @@ impl Agents {
             .get_row("SELECT * FROM agents WHERE name = ?1;", &[&name], |row| {
                 Agent {
                     name: row.get("name"),
+                    namespace: row.get("namespace"),
                     last_heartbeat: row.get("last_heartbeat"),
                     git_revision: row.get("git_revision"),
+                    version: row.get("version"),
 
                     // Lazy loaded after this
-                    experiment: None,
+                    experiments: Vec::new(),
                     capabilities: None,
                 }
             })?
-            .map(|agent| agent.with_experiment(&self.db))
+            .map(|agent| agent.with_experiments(&self.db))
             .transpose()?
             .map(|agent| agent.with_capabilities(&self.db))
             .transpose()
@@ -166,6 +230,51 @@ impl Agents {
         Ok(())
     }
 
+    pub fn set_version(&self, agent: &str, version: &str) -> Fallible<()> {
+        let changes = self.db.execute(
+            "UPDATE agents SET version = ?1 WHERE name = ?2;",
+            &[&version, &agent],
+        )?;
+        assert_eq!(changes, 1);
+
+        Ok(())
+    }
+
+    /// Fail if any agent running experiments stopped sending heartbeats, as its experiments won't
+    /// progress until it comes back.
+    pub fn check_heartbeats(&self) -> Fallible<()> {
+        let unreachable = self
+            .all()?
+            .into_iter()
+            .filter(|agent| {
+                agent.status() == AgentStatus::Unreachable && !agent.experiments.is_empty()
+            })
+            .map(|agent| agent.name)
+            .collect::<Vec<_>>();
+        if !unreachable.is_empty() {
+            bail!(
+                "agents running experiments are unreachable: {}",
+                unreachable.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Queue again the experiments of the agents which missed `missed` heartbeats in a row, so
+    /// they don't stay running forever if the agents never come back.
+    pub fn requeue_unreachable(&self, missed: u32) -> Fallible<Vec<String>> {
+        let cutoff = Utc::now() - Duration::seconds(HEARTBEAT_INTERVAL as i64 * i64::from(missed));
+
+        let mut requeued = Vec::new();
+        for agent in self.all()? {
+            let unreachable = agent.last_heartbeat.map_or(true, |time| time < cutoff);
+            if unreachable {
+                requeued.extend(Experiment::requeue_abandoned(&self.db, &agent.name)?);
+            }
+        }
+        Ok(requeued)
+    }
+
     pub fn add_capabilities(&self, agent: &str, caps: &Capabilities) -> Fallible<()> {
         const SQL: &str = "INSERT INTO agent_capabilities (agent_name, capability) VALUES (?, ?)";
 
@@ -181,13 +290,14 @@ impl Agents {
 
 #[cfg(test)]
 mod tests {
-    use super::{AgentStatus, Agents};
+    use super::{is_version_supported, AgentStatus, Agents};
     use crate::actions::{Action, ActionsCtx, CreateExperiment};
     use crate::agent::Capabilities;
     use crate::config::Config;
-    use crate::db::Database;
-    use crate::experiments::{Assignee, Experiment};
+    use crate::db::{Database, QueryUtils};
+    use crate::experiments::{Assignee, Experiment, Status};
     use crate::server::tokens::Tokens;
+    use chrono::{Duration, Utc};
 
     #[test]
     fn test_agents_synchronize() {
@@ -268,11 +378,63 @@ mod tests {
 
         // Create a new experiment and assign it to the agent
         CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
-        Experiment::next(&db, &Assignee::Agent("agent".to_string())).unwrap();
+        Experiment::next(&db, &config, &Assignee::Agent("agent".to_string())).unwrap();
 
         // After an experiment is assigned to the agent, the agent is working
         let agent = agents.get("agent").unwrap().unwrap();
         assert_eq!(agent.status(), AgentStatus::Working);
+        agents.check_heartbeats().unwrap();
+
+        // Agents stopping to send heartbeats while working are reported
+        db.execute("UPDATE agents SET last_heartbeat = NULL;", &[])
+            .unwrap();
+        let agent = agents.get("agent").unwrap().unwrap();
+        assert_eq!(agent.status(), AgentStatus::Unreachable);
+        assert!(agents.check_heartbeats().is_err());
+    }
+
+    #[test]
+    fn test_requeue_unreachable() {
+        let db = Database::temp().unwrap();
+        let mut config = Config::default();
+        config.server.chunks.crates = Some(1);
+        let ctx = ActionsCtx::new(&db, &config);
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+        let agents = Agents::new(db.clone(), &tokens).unwrap();
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+
+        agents.record_heartbeat("agent-1").unwrap();
+        agents.record_heartbeat("agent-2").unwrap();
+        CreateExperiment::dummy("dummy").apply(&ctx).unwrap();
+        Experiment::next(&db, &config, &Assignee::Agent("agent-1".into())).unwrap();
+        Experiment::next(&db, &config, &Assignee::Agent("agent-2".into())).unwrap();
+
+        // Experiments of reachable agents are left alone
+        assert!(agents.requeue_unreachable(5).unwrap().is_empty());
+
+        // Experiments other agents are still working on keep running
+        db.execute(
+            "UPDATE agents SET last_heartbeat = ?1 WHERE name = 'agent-1';",
+            &[&(Utc::now() - Duration::minutes(10))],
+        )
+        .unwrap();
+        assert!(agents.requeue_unreachable(5).unwrap().is_empty());
+
+        db.execute(
+            "UPDATE experiment_crates SET claimed_by = NULL, claimed_at = NULL \
+             WHERE claimed_by = 'agent-2';",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(agents.requeue_unreachable(5).unwrap(), vec!["dummy"]);
+        let ex = Experiment::get(&db, "dummy").unwrap().unwrap();
+        assert_eq!(ex.status, Status::Queued);
+        assert!(ex.assigned_to.is_none());
+        assert!(ex.get_claimed_crates(&db, "agent-1").unwrap().is_empty());
     }
 
     #[test]
@@ -291,4 +453,15 @@ mod tests {
         let caps_from_db = Capabilities::for_agent(&db, "agent").unwrap();
         assert!(caps.iter().eq(caps_from_db.iter()));
     }
+
+    #[test]
+    fn test_is_version_supported() {
+        assert!(is_version_supported(Some("0.2.0"), "0.2.0"));
+        assert!(is_version_supported(Some("0.10.0"), "0.2.0"));
+        assert!(is_version_supported(Some("1.0.0"), "0.9.9"));
+        assert!(is_version_supported(Some("0.2.1-alpha.1"), "0.2.1"));
+        assert!(!is_version_supported(Some("0.1.9"), "0.2.0"));
+        assert!(!is_version_supported(Some("garbage"), "0.2.0"));
+        assert!(!is_version_supported(None, "0.2.0"));
+    }
 }
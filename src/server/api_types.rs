@@ -1,5 +1,11 @@
 use crate::config::Config;
+use crate::crates::{Crate, IndexSnapshot};
+use crate::experiments::{Experiment, ExperimentCost, Mode, Status};
 use crate::prelude::*;
+use crate::report::Comparison;
+use crate::results::TestResult;
+use crate::server::agents::{Agent, AgentStatus};
+use chrono::{DateTime, Utc};
 use http::header::{HeaderValue, CONTENT_TYPE};
 use http::Response;
 use http::StatusCode;
@@ -9,6 +15,9 @@ use std::fmt;
 use std::fmt::Display;
 use std::str::FromStr;
 
+/// Header used by the agents to report the version of Crater they're running.
+pub const AGENT_VERSION_HEADER: &str = "X-Crater-Agent-Version";
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct AgentConfig {
@@ -16,13 +25,25 @@ pub struct AgentConfig {
     pub crater_config: Config,
 }
 
+/// Experiment assigned to an agent, along with the number of its workers it should be run with.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NextExperiment {
+    pub experiment: Experiment,
+    pub crates: Vec<Crate>,
+    pub workers: usize,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "status", rename_all = "kebab-case")]
 pub enum ApiResponse<T> {
     Success { result: T },
     InternalError { error: String },
+    BadRequest { error: String },
     Unauthorized,
     NotFound,
+    #[serde(rename_all = "kebab-case")]
+    UpgradeRequired { minimum_version: String },
 }
 
 impl ApiResponse<()> {
@@ -30,6 +51,10 @@ impl ApiResponse<()> {
         ApiResponse::InternalError { error }
     }
 
+    pub(in crate::server) fn bad_request(error: String) -> ApiResponse<()> {
+        ApiResponse::BadRequest { error }
+    }
+
     pub(in crate::server) fn unauthorized() -> ApiResponse<()> {
         ApiResponse::Unauthorized
     }
@@ -37,6 +62,10 @@ impl ApiResponse<()> {
     pub(in crate::server) fn not_found() -> ApiResponse<()> {
         ApiResponse::NotFound
     }
+
+    pub(in crate::server) fn upgrade_required(minimum_version: String) -> ApiResponse<()> {
+        ApiResponse::UpgradeRequired { minimum_version }
+    }
 }
 
 impl<T> ApiResponse<T> {
@@ -44,8 +73,10 @@ impl<T> ApiResponse<T> {
         match *self {
             ApiResponse::Success { .. } => StatusCode::OK,
             ApiResponse::InternalError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiResponse::BadRequest { .. } => StatusCode::BAD_REQUEST,
             ApiResponse::Unauthorized => StatusCode::UNAUTHORIZED,
             ApiResponse::NotFound => StatusCode::NOT_FOUND,
+            ApiResponse::UpgradeRequired { .. } => StatusCode::UPGRADE_REQUIRED,
         }
     }
 }
@@ -62,6 +93,188 @@ impl<T: Serialize> ApiResponse<T> {
     }
 }
 
+/// Filters accepted by the `experiments/<name>/results` endpoint of the public API.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResultsQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+    pub classification: Option<Comparison>,
+    #[serde(rename = "crate")]
+    pub krate: Option<String>,
+    pub toolchain: Option<String>,
+}
+
+/// Filters accepted by the `experiments` endpoint of the public API and by the queue page.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExperimentsQuery {
+    pub status: Option<Status>,
+    pub mode: Option<Mode>,
+    /// Who created the experiment, either the full actor in the audit log (`github:ghost`) or
+    /// only the user name (`ghost`).
+    pub requester: Option<String>,
+    /// Part of the name of one of the toolchains.
+    pub toolchain: Option<String>,
+    /// One of the tags attached to the experiment.
+    pub tag: Option<String>,
+    /// Part of the name of the experiment, ignoring the case.
+    pub search: Option<String>,
+}
+
+impl ExperimentsQuery {
+    /// Check whether an experiment matches all the filters, given the actor who created it.
+    pub fn matches(&self, ex: &Experiment, requester: Option<&str>) -> bool {
+        if self.status.map_or(false, |status| status != ex.status) {
+            return false;
+        }
+        if self.mode.map_or(false, |mode| mode != ex.mode) {
+            return false;
+        }
+        if let Some(ref wanted) = self.requester {
+            let matches = requester.map_or(false, |actor| {
+                let user = actor.splitn(2, ':').last().unwrap_or(actor);
+                actor.eq_ignore_ascii_case(wanted) || user.eq_ignore_ascii_case(wanted)
+            });
+            if !matches {
+                return false;
+            }
+        }
+        if let Some(ref toolchain) = self.toolchain {
+            if !ex
+                .toolchains
+                .iter()
+                .any(|tc| tc.to_string().contains(toolchain.as_str()))
+            {
+                return false;
+            }
+        }
+        if let Some(ref tag) = self.tag {
+            if !ex.tags.contains(tag) {
+                return false;
+            }
+        }
+        if let Some(ref search) = self.search {
+            if !ex.name.to_lowercase().contains(&search.to_lowercase()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditLogQuery {
+    pub before: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+/// Experiment as listed by the `experiments` endpoint of the public API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExperimentSummary {
+    pub name: String,
+    pub mode: Mode,
+    pub status: Status,
+    pub priority: i32,
+    pub toolchains: Vec<String>,
+    pub report_url: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub index_snapshot: Option<IndexSnapshot>,
+    #[serde(default)]
+    pub cost: ExperimentCost,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResultsPage {
+    pub results: Vec<CrateResults>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct CrateResults {
+    #[serde(rename = "crate")]
+    pub krate: Crate,
+    pub name: String,
+    pub classification: Comparison,
+    pub runs: Vec<ToolchainResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ToolchainResult {
+    pub toolchain: String,
+    pub result: Option<TestResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ToolchainDetails {
+    pub toolchain: String,
+    pub release: Option<String>,
+    pub commit_hash: Option<String>,
+    pub commit_date: Option<String>,
+    pub host: Option<String>,
+    pub llvm_version: Option<String>,
+}
+
+/// Agent as listed by the `agents` endpoint of the public API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct AgentSummary {
+    pub name: String,
+    pub status: AgentStatus,
+    pub last_heartbeat: Option<DateTime<Utc>>,
+    pub assigned_experiments: Vec<String>,
+    pub git_revision: Option<String>,
+    pub version: Option<String>,
+    pub capabilities: Vec<String>,
+}
+
+impl AgentSummary {
+    pub fn from_agent(agent: &Agent) -> Self {
+        AgentSummary {
+            name: agent.name().to_string(),
+            status: agent.status(),
+            last_heartbeat: agent.last_heartbeat().cloned(),
+            assigned_experiments: agent
+                .assigned_experiments()
+                .iter()
+                .map(|ex| ex.name.clone())
+                .collect(),
+            git_revision: agent.git_revision().cloned(),
+            version: agent.version().cloned(),
+            capabilities: agent
+                .capabilities()
+                .map(|caps| caps.iter().cloned().collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Experiment queued by the `POST /experiments` endpoint of the public API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct QueueExperiment {
+    pub name: String,
+    /// Arguments of the experiment, with the same syntax as the `run` command of the bot.
+    pub args: String,
+}
+
+/// List of crates stored by the `crate-lists` endpoint of the public API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct CrateListSummary {
+    /// Crate selection of the experiments testing the list.
+    pub select: String,
+    pub crates: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct CraterToken {
     pub token: String,
@@ -82,3 +295,81 @@ impl FromStr for CraterToken {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ExperimentsQuery;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::{Experiment, ExperimentCost, Mode, Status};
+
+    #[test]
+    fn test_experiments_query() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment {
+            tags: "release:1.78".parse().unwrap(),
+            ..CreateExperiment::dummy("pr-12345")
+        }
+        .apply(&ctx)
+        .unwrap();
+        let ex = Experiment::get(&db, "pr-12345").unwrap().unwrap();
+        let requester = Some("github:ghost");
+
+        assert!(ExperimentsQuery::default().matches(&ex, None));
+        assert!(ExperimentsQuery {
+            status: Some(Status::Queued),
+            mode: Some(ex.mode),
+            requester: Some("Ghost".into()),
+            toolchain: Some(ex.toolchains[1].to_string()),
+            tag: Some("release:1.78".into()),
+            search: Some("PR-123".into()),
+        }
+        .matches(&ex, requester));
+
+        let mismatches = vec![
+            ExperimentsQuery {
+                status: Some(Status::Running),
+                ..ExperimentsQuery::default()
+            },
+            ExperimentsQuery {
+                mode: Some(if ex.mode == Mode::Clippy {
+                    Mode::Rustdoc
+                } else {
+                    Mode::Clippy
+                }),
+                ..ExperimentsQuery::default()
+            },
+            ExperimentsQuery {
+                requester: Some("github:other".into()),
+                ..ExperimentsQuery::default()
+            },
+            ExperimentsQuery {
+                toolchain: Some("not-a-toolchain".into()),
+                ..ExperimentsQuery::default()
+            },
+            ExperimentsQuery {
+                tag: Some("release".into()),
+                ..ExperimentsQuery::default()
+            },
+            ExperimentsQuery {
+                search: Some("pr-54321".into()),
+                ..ExperimentsQuery::default()
+            },
+        ];
+        for query in &mismatches {
+            assert!(!query.matches(&ex, requester), "{:?}", query);
+        }
+
+        // Experiments without a known requester never match a requester filter
+        assert!(!ExperimentsQuery {
+            requester: Some("ghost".into()),
+            ..ExperimentsQuery::default()
+        }
+        .matches(&ex, None));
+    }
+}
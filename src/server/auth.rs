@@ -1,5 +1,6 @@
 use crate::config::Config;
 use crate::prelude::*;
+use crate::server::api_types::AGENT_VERSION_HEADER;
 use crate::server::github::{GitHub, GitHubApi};
 use crate::server::{Data, HttpError};
 use http::header::{HeaderMap, AUTHORIZATION, USER_AGENT};
@@ -17,11 +18,19 @@ lazy_static! {
 #[derive(Copy, Clone)]
 pub enum TokenType {
     Agent,
+    /// Tokens that can only read experiments and their results, without changing anything.
+    ReadOnly,
+    /// Tokens that can upload the lists of crates selected by experiments.
+    CrateList,
+    /// Tokens that can queue new experiments.
+    Queue,
 }
 
 pub struct AuthDetails {
     pub name: String,
+    pub namespace: String,
     pub git_revision: Option<String>,
+    pub version: Option<String>,
 }
 
 fn parse_token(authorization: &str) -> Option<&str> {
@@ -57,23 +66,57 @@ fn check_auth(data: &Data, headers: &HeaderMap, token_type: TokenType) -> Option
         None
     };
 
+    let version = headers
+        .get(AGENT_VERSION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
     if let Some(authorization_value) = headers.get(AUTHORIZATION) {
         if let Ok(authorization) = authorization_value.to_str() {
             if let Some(token) = parse_token(authorization) {
-                let tokens = match token_type {
-                    TokenType::Agent => &data.tokens.agents,
+                let found = match token_type {
+                    TokenType::Agent => data.tokens.agent(token),
+                    TokenType::ReadOnly => data.tokens.api(token),
+                    TokenType::CrateList => data.tokens.crate_list(token),
+                    TokenType::Queue => data.tokens.queue(token),
                 };
 
-                if let Some(name) = tokens.get(token) {
+                if let Some((name, namespace)) = found {
                     return Some(AuthDetails {
-                        name: name.clone(),
+                        name: name.to_string(),
+                        namespace: namespace.to_string(),
                         git_revision,
+                        version,
                     });
                 }
             }
         }
     }
 
+    // Agents can also authenticate with a client certificate, verified by the reverse proxy. The
+    // header is only trusted when explicitly enabled, as anyone could set it otherwise.
+    let certs = data
+        .tokens
+        .client_certs
+        .as_ref()
+        .filter(|_| data.config.server.trust_client_cert_header);
+    if let (TokenType::Agent, Some(certs)) = (token_type, certs) {
+        if let Some(cn) = headers.get(certs.header.as_str()) {
+            if let Some((name, namespace)) = cn
+                .to_str()
+                .ok()
+                .and_then(|cn| data.tokens.agent_by_cert(cn.trim()))
+            {
+                return Some(AuthDetails {
+                    name: name.to_string(),
+                    namespace: namespace.to_string(),
+                    git_revision,
+                    version,
+                });
+            }
+        }
+    }
+
     None
 }
 
@@ -189,7 +232,12 @@ impl ACL {
 
 #[cfg(test)]
 mod tests {
-    use super::{git_revision, parse_token};
+    use super::{check_auth, git_revision, parse_token, TokenType};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::server::tokens::{ClientCerts, Tokens};
+    use crate::server::Data;
+    use http::header::HeaderMap;
 
     #[test]
     fn test_parse_token() {
@@ -223,4 +271,26 @@ mod tests {
         assert!(git_revision("crater/0000000 (foo bar!) ").is_none());
         assert!(git_revision("crate/0000000").is_none());
     }
+
+    #[test]
+    fn test_client_cert_header() {
+        let mut tokens = Tokens::default();
+        tokens.client_certs = Some(ClientCerts {
+            header: "X-Client-Cert-CN".into(),
+            agents: vec!["agent-1".into()],
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Client-Cert-CN", "agent-1".parse().unwrap());
+
+        // The header is ignored unless it's explicitly trusted
+        let mut config = Config::default();
+        let data = Data::dummy(Database::temp().unwrap(), config.clone(), tokens.clone()).unwrap();
+        assert!(check_auth(&data, &headers, TokenType::Agent).is_none());
+
+        config.server.trust_client_cert_header = true;
+        let data = Data::dummy(Database::temp().unwrap(), config, tokens).unwrap();
+        let auth = check_auth(&data, &headers, TokenType::Agent).unwrap();
+        assert_eq!(auth.name, "agent-1");
+        assert!(check_auth(&data, &headers, TokenType::ReadOnly).is_none());
+    }
 }
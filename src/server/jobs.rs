@@ -0,0 +1,377 @@
+use crate::db::{Database, QueryUtils};
+use crate::prelude::*;
+use crate::server::Data;
+use crate::utils;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// Number of times a failed job is retried before waiting for its next scheduled run.
+const MAX_RETRIES: i32 = 3;
+/// Seconds to wait before retrying a failed job, doubled after each failed retry.
+const RETRY_DELAY: i64 = 60;
+/// Seconds to wait before restarting the thread of a job that crashed.
+const RESPAWN_DELAY: u64 = 60;
+
+string_enum!(pub enum JobStatus {
+    Scheduled => "scheduled",
+    Running => "running",
+    Retrying => "retrying",
+    Failed => "failed",
+    Completed => "completed",
+});
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// Run the job once every time the server starts.
+    Once,
+    /// Run the job periodically, and every time it's triggered.
+    Every(Duration),
+}
+
+/// State of a background job, stored in the database so it can be shown in the dashboard and
+/// restored after the server restarts.
+pub struct JobState {
+    pub name: String,
+    pub status: JobStatus,
+    pub runs: i32,
+    pub failures: i32,
+    pub last_started_at: Option<DateTime<Utc>>,
+    pub last_finished_at: Option<DateTime<Utc>>,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+pub fn all(db: &Database) -> Fallible<Vec<JobState>> {
+    db.query(
+        "SELECT * FROM jobs ORDER BY name;",
+        &[],
+        |row| -> Fallible<JobState> {
+            let status: String = row.get("status");
+            Ok(JobState {
+                name: row.get("name"),
+                status: status.parse()?,
+                runs: row.get("runs"),
+                failures: row.get("failures"),
+                last_started_at: row.get("last_started_at"),
+                last_finished_at: row.get("last_finished_at"),
+                next_run_at: row.get("next_run_at"),
+                last_error: row.get("last_error"),
+            })
+        },
+    )?
+    .into_iter()
+    .collect()
+}
+
+fn get(db: &Database, name: &str) -> Fallible<JobState> {
+    all(db)?
+        .into_iter()
+        .find(|job| job.name == name)
+        .ok_or_else(|| err_msg(format!("missing job {}", name)))
+}
+
+/// Store the job if it's new, scheduling it to run right away unless it's periodic and its next
+/// run was already scheduled before the server restarted.
+fn register(db: &Database, name: &str, schedule: Schedule) -> Fallible<()> {
+    let now = Utc::now();
+    db.execute(
+        "INSERT OR IGNORE INTO jobs (name, status, runs, failures) VALUES (?1, ?2, 0, 0);",
+        &[&name, &JobStatus::Scheduled.to_str()],
+    )?;
+    if schedule == Schedule::Once {
+        db.execute(
+            "UPDATE jobs SET status = ?1, failures = 0, next_run_at = ?2 WHERE name = ?3;",
+            &[&JobStatus::Scheduled.to_str(), &now, &name],
+        )?;
+    } else {
+        // Jobs interrupted by the restart are run again
+        db.execute(
+            "UPDATE jobs SET next_run_at = ?1 WHERE name = ?2 \
+             AND (next_run_at IS NULL OR status = ?3);",
+            &[&now, &name, &JobStatus::Running.to_str()],
+        )?;
+    }
+    Ok(())
+}
+
+fn record_start(db: &Database, name: &str) -> Fallible<()> {
+    db.execute(
+        "UPDATE jobs SET status = ?1, last_started_at = ?2 WHERE name = ?3;",
+        &[&JobStatus::Running.to_str(), &Utc::now(), &name],
+    )?;
+    Ok(())
+}
+
+/// Record the outcome of a run of the job, returning when the job should run next.
+fn record_finish(
+    db: &Database,
+    name: &str,
+    schedule: Schedule,
+    error: Option<&str>,
+) -> Fallible<Option<DateTime<Utc>>> {
+    let now = Utc::now();
+    let failures = match error {
+        Some(_) => get(db, name)?.failures + 1,
+        None => 0,
+    };
+    let (status, next_run_at) = next_run(schedule, failures, now);
+    db.execute(
+        "UPDATE jobs SET status = ?1, runs = runs + 1, failures = ?2, last_finished_at = ?3, \
+         next_run_at = ?4, last_error = COALESCE(?5, last_error) WHERE name = ?6;",
+        &[
+            &status.to_str(),
+            &failures,
+            &now,
+            &next_run_at,
+            &error,
+            &name,
+        ],
+    )?;
+    Ok(next_run_at)
+}
+
+/// Decide when to run a job again after it failed `failures` times in a row.
+fn next_run(
+    schedule: Schedule,
+    failures: i32,
+    now: DateTime<Utc>,
+) -> (JobStatus, Option<DateTime<Utc>>) {
+    if failures > 0 && failures <= MAX_RETRIES {
+        let delay = Duration::seconds(RETRY_DELAY * (1 << (failures - 1)));
+        return (JobStatus::Retrying, Some(now + delay));
+    }
+
+    let status = if failures > 0 {
+        JobStatus::Failed
+    } else if schedule == Schedule::Once {
+        JobStatus::Completed
+    } else {
+        JobStatus::Scheduled
+    };
+    match schedule {
+        Schedule::Once => (status, None),
+        Schedule::Every(interval) => (status, Some(now + interval)),
+    }
+}
+
+struct Job {
+    name: &'static str,
+    schedule: Schedule,
+    run: fn(&Data) -> Fallible<()>,
+}
+
+impl Job {
+    fn run_once(&self, data: &Data) -> Fallible<()> {
+        match panic::catch_unwind(AssertUnwindSafe(|| (self.run)(data))) {
+            Ok(result) => result,
+            Err(panic) => {
+                utils::report_panic(&*panic);
+                bail!(
+                    "the job panicked: {}",
+                    utils::panic_message(&*panic).unwrap_or("unknown payload")
+                );
+            }
+        }
+    }
+
+    fn thread(&self, data: &Data, triggers: &mpsc::Receiver<()>) -> Fallible<()> {
+        let mut next_run_at = get(&data.db, self.name)?.next_run_at;
+        while let Some(next) = next_run_at {
+            let now = Utc::now();
+            if next > now {
+                // This will sleep until the next run *or* until the job is triggered
+                let timeout = (next - now).to_std()?;
+                match triggers.recv_timeout(timeout) {
+                    Ok(()) => next_run_at = Some(Utc::now()),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => thread::sleep(timeout),
+                }
+                continue;
+            }
+
+            info!("running job {}...", self.name);
+            record_start(&data.db, self.name)?;
+            let error = match self.run_once(data) {
+                Ok(()) => None,
+                Err(e) => {
+                    let message = e.to_string();
+                    utils::report_failure(&e.context(format!("job {} failed", self.name)));
+                    Some(message)
+                }
+            };
+            next_run_at = record_finish(
+                &data.db,
+                self.name,
+                self.schedule,
+                error.as_ref().map(|e| e.as_str()),
+            )?;
+
+            // Don't lose the triggers received while the job was running
+            if next_run_at.is_some() && triggers.try_recv().is_ok() {
+                while triggers.try_recv().is_ok() {}
+                next_run_at = Some(Utc::now());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runner of the background jobs of the server, with a thread for each job.
+#[derive(Clone)]
+pub struct Jobs {
+    db: Database,
+    triggers: Arc<Mutex<HashMap<&'static str, mpsc::Sender<()>>>>,
+}
+
+impl Jobs {
+    pub fn new(db: Database) -> Self {
+        Jobs {
+            db,
+            triggers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn spawn(
+        &self,
+        data: &Data,
+        name: &'static str,
+        schedule: Schedule,
+        run: fn(&Data) -> Fallible<()>,
+    ) -> Fallible<()> {
+        register(&self.db, name, schedule)?;
+
+        let (trigger_send, trigger_recv) = mpsc::channel();
+        self.triggers.lock().unwrap().insert(name, trigger_send);
+
+        let job = Job {
+            name,
+            schedule,
+            run,
+        };
+        let data = data.clone();
+        thread::spawn(move || loop {
+            let result = job
+                .thread(&data, &trigger_recv)
+                .with_context(|_| format!("the thread of job {} crashed", job.name));
+            match result {
+                Ok(()) => return,
+                Err(e) => utils::report_failure(&e),
+            }
+
+            warn!(
+                "the thread of job {} will be respawned in one minute",
+                job.name
+            );
+            thread::sleep(std::time::Duration::from_secs(RESPAWN_DELAY));
+        });
+
+        Ok(())
+    }
+
+    /// Run a periodic job as soon as possible, without waiting for its next scheduled run. The
+    /// trigger is stored, so it's not lost if the server restarts in the meantime.
+    pub fn trigger(&self, name: &str) {
+        let result = self.db.execute(
+            "UPDATE jobs SET next_run_at = ?1 WHERE name = ?2 AND next_run_at IS NOT NULL;",
+            &[&Utc::now(), &name],
+        );
+        if let Err(e) = result {
+            utils::report_failure(&e);
+        }
+
+        // We don't really care if the trigger fails: the job will run on its own schedule anyway,
+        // so this just speeds up the process
+        let triggers = self.triggers.lock().unwrap();
+        match triggers.get(name) {
+            Some(trigger) => {
+                if trigger.send(()).is_err() {
+                    warn!("can't trigger the job {}, will have to wait", name);
+                }
+            }
+            None => warn!("no job {} to trigger!", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{all, next_run, record_finish, record_start, register, JobStatus, Schedule};
+    use crate::db::Database;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn test_next_run() {
+        let now = Utc::now();
+        let every = Schedule::Every(Duration::hours(1));
+
+        assert_eq!(
+            next_run(every, 0, now),
+            (JobStatus::Scheduled, Some(now + Duration::hours(1)))
+        );
+        assert_eq!(
+            next_run(Schedule::Once, 0, now),
+            (JobStatus::Completed, None)
+        );
+
+        // Failed jobs are retried with an increasing delay
+        assert_eq!(
+            next_run(every, 1, now),
+            (JobStatus::Retrying, Some(now + Duration::minutes(1)))
+        );
+        assert_eq!(
+            next_run(Schedule::Once, 3, now),
+            (JobStatus::Retrying, Some(now + Duration::minutes(4)))
+        );
+
+        // After too many retries the jobs wait for their next run
+        assert_eq!(
+            next_run(every, 4, now),
+            (JobStatus::Failed, Some(now + Duration::hours(1)))
+        );
+        assert_eq!(next_run(Schedule::Once, 4, now), (JobStatus::Failed, None));
+    }
+
+    #[test]
+    fn test_job_state() {
+        let db = Database::temp().unwrap();
+        let every = Schedule::Every(Duration::hours(1));
+
+        register(&db, "foo", every).unwrap();
+        let job = &all(&db).unwrap()[0];
+        assert_eq!(job.name, "foo");
+        assert_eq!(job.status, JobStatus::Scheduled);
+        assert!(job.next_run_at.unwrap() <= Utc::now());
+
+        record_start(&db, "foo").unwrap();
+        assert_eq!(all(&db).unwrap()[0].status, JobStatus::Running);
+        record_finish(&db, "foo", every, Some("failure")).unwrap();
+        record_start(&db, "foo").unwrap();
+        record_finish(&db, "foo", every, Some("another failure")).unwrap();
+
+        let job = &all(&db).unwrap()[0];
+        assert_eq!(job.status, JobStatus::Retrying);
+        assert_eq!(job.runs, 2);
+        assert_eq!(job.failures, 2);
+        assert_eq!(job.last_error.as_ref().unwrap(), "another failure");
+
+        // The last error is kept after a successful run, but the failures are reset
+        record_start(&db, "foo").unwrap();
+        let next = record_finish(&db, "foo", every, None).unwrap().unwrap();
+        let job = &all(&db).unwrap()[0];
+        assert_eq!(job.status, JobStatus::Scheduled);
+        assert_eq!(job.failures, 0);
+        assert_eq!(job.last_error.as_ref().unwrap(), "another failure");
+        assert_eq!(job.next_run_at, Some(next));
+
+        // The schedule of periodic jobs survives restarts, unless they were interrupted
+        register(&db, "foo", every).unwrap();
+        assert_eq!(all(&db).unwrap()[0].next_run_at, Some(next));
+        record_start(&db, "foo").unwrap();
+        register(&db, "foo", every).unwrap();
+        assert!(all(&db).unwrap()[0].next_run_at.unwrap() < next);
+    }
+}
@@ -2,19 +2,26 @@ pub mod agents;
 pub mod api_types;
 mod auth;
 mod github;
+pub mod jobs;
 mod messages;
 mod reports;
+mod retention;
 mod routes;
+mod schedules;
 pub mod tokens;
 mod try_builds;
+pub mod webhook_events;
 
 use crate::config::Config;
 use crate::db::Database;
+use crate::experiments::Experiment;
 use crate::prelude::*;
 use crate::server::agents::Agents;
 use crate::server::auth::ACL;
 use crate::server::github::{GitHub, GitHubApi};
+use crate::server::jobs::{Jobs, Schedule};
 use crate::server::tokens::Tokens;
+use chrono::Duration;
 use http::{self, header::HeaderValue, Response};
 use hyper::Body;
 use std::sync::Arc;
@@ -41,11 +48,36 @@ pub struct Data {
     pub tokens: Tokens,
     pub agents: Agents,
     pub db: Database,
-    pub reports_worker: reports::ReportsWorker,
+    pub jobs: Jobs,
     pub acl: ACL,
 }
 
+#[cfg(test)]
+impl Data {
+    /// Data of a server that doesn't talk to GitHub, for the tests of the routes.
+    pub(crate) fn dummy(db: Database, config: Config, tokens: Tokens) -> Fallible<Self> {
+        let github = GitHubApi::new(&tokens);
+        Ok(Data {
+            bot_username: "crater".into(),
+            acl: ACL::new(&config, &github)?,
+            agents: Agents::new(db.clone(), &tokens)?,
+            jobs: Jobs::new(db.clone()),
+            config,
+            github,
+            tokens,
+            db,
+        })
+    }
+}
+
 pub fn run(config: Config) -> Fallible<()> {
+    if let Some(ref min) = config.server.agent_min_version {
+        if agents::parse_version(min).is_none() {
+            bail!("invalid minimum agent version: {}", min);
+        }
+    }
+    schedules::check(&config)?;
+
     let db = Database::open()?;
     let tokens = tokens::Tokens::load()?;
     let github = GitHubApi::new(&tokens);
@@ -62,11 +94,92 @@ pub fn run(config: Config) -> Fallible<()> {
         tokens,
         agents,
         db: db.clone(),
-        reports_worker: reports::ReportsWorker::new(),
+        jobs: Jobs::new(db.clone()),
         acl,
     };
 
-    data.reports_worker.spawn(data.clone());
+    let interrupted = webhook_events::requeue_interrupted(&data.db)?;
+    if interrupted > 0 {
+        info!(
+            "{} webhook events were interrupted, retrying them",
+            interrupted
+        );
+    }
+
+    let jobs = &data.jobs;
+    jobs.spawn(
+        &data,
+        reports::JOB,
+        Schedule::Every(Duration::minutes(10)),
+        reports::generate_reports,
+    )?;
+    jobs.spawn(
+        &data,
+        reports::REQUEUE_JOB,
+        Schedule::Once,
+        reports::requeue_interrupted,
+    )?;
+    jobs.spawn(
+        &data,
+        agents::HEARTBEATS_JOB,
+        Schedule::Every(Duration::minutes(5)),
+        |data| {
+            if let Some(missed) = data.config.server.requeue_after_missed_heartbeats {
+                data.agents.requeue_unreachable(missed)?;
+            }
+            data.agents.check_heartbeats()
+        },
+    )?;
+    jobs.spawn(
+        &data,
+        agents::RECLAIM_JOB,
+        Schedule::Every(Duration::minutes(5)),
+        |data| {
+            Experiment::release_expired_claims(&data.db, &data.config.server.chunks)?;
+            Ok(())
+        },
+    )?;
+    if data.config.server.requeue_stalled_after_minutes.is_some() {
+        jobs.spawn(
+            &data,
+            agents::STALLED_JOB,
+            Schedule::Every(Duration::minutes(5)),
+            |data| {
+                if let Some(minutes) = data.config.server.requeue_stalled_after_minutes {
+                    Experiment::requeue_stalled(&data.db, Duration::minutes(i64::from(minutes)))?;
+                }
+                Ok(())
+            },
+        )?;
+    }
+    jobs.spawn(
+        &data,
+        routes::webhooks::RETRY_JOB,
+        Schedule::Every(Duration::minutes(5)),
+        routes::webhooks::retry_events,
+    )?;
+    jobs.spawn(
+        &data,
+        routes::webhooks::GC_JOB,
+        Schedule::Every(Duration::days(1)),
+        routes::webhooks::prune_events,
+    )?;
+    if schedules::enabled(&data) {
+        jobs.spawn(
+            &data,
+            schedules::JOB,
+            Schedule::Every(Duration::minutes(1)),
+            schedules::create_scheduled,
+        )?;
+    }
+    if retention::enabled(&data) {
+        jobs.spawn(
+            &data,
+            retention::JOB,
+            Schedule::Every(Duration::hours(6)),
+            retention::apply,
+        )?;
+    }
 
     info!("running server...");
 
@@ -78,6 +191,8 @@ pub fn run(config: Config) -> Fallible<()> {
                 .and(warp::path("webhooks").and(routes::webhooks::routes(data.clone())))
                 .or(warp::path("agent-api").and(routes::agent::routes(data.clone())))
                 .unify()
+                .or(warp::path("api").and(routes::api::routes(data.clone())))
+                .unify()
                 .or(routes::ui::routes(data.clone()))
                 .unify(),
         )
@@ -1,25 +1,20 @@
+use crate::actions::{self, ActionsCtx, BisectionStep};
+use crate::audit::Actor;
 use crate::experiments::{Experiment, Status};
 use crate::prelude::*;
-use crate::report::{self, Comparison, TestResults};
+use crate::report::{self, Comparison, ReportSummary};
 use crate::results::DatabaseDB;
 use crate::server::messages::{Label, Message};
 use crate::server::Data;
 use crate::utils;
-use rusoto_core::request::HttpClient;
-use rusoto_s3::S3Client;
-use std::sync::{mpsc, Arc, Mutex};
-use std::thread;
-use std::time::Duration;
-
-// Automatically wake up the reports generator thread every 10 minutes to check for new jobs
-const AUTOMATIC_THREAD_WAKEUP: u64 = 600;
-
-fn generate_report(data: &Data, ex: &Experiment, results: &DatabaseDB) -> Fallible<TestResults> {
-    let client = S3Client::new_with(
-        HttpClient::new()?,
-        data.tokens.reports_bucket.to_aws_credentials(),
-        data.tokens.reports_bucket.region.to_region()?,
-    );
+
+/// Name of the job generating the reports, which is triggered when experiments complete.
+pub const JOB: &str = "reports";
+/// Name of the job queueing again the reports interrupted by a restart of the server.
+pub const REQUEUE_JOB: &str = "requeue-reports";
+
+fn generate_report(data: &Data, ex: &Experiment, results: &DatabaseDB) -> Fallible<ReportSummary> {
+    let client = data.tokens.reports_bucket.s3_client()?;
     let dest = format!("s3://{}/{}", data.tokens.reports_bucket.bucket, &ex.name);
     let writer = report::S3Writer::create(Box::new(client), dest.parse()?)?;
 
@@ -29,22 +24,11 @@ fn generate_report(data: &Data, ex: &Experiment, results: &DatabaseDB) -> Fallib
     Ok(res)
 }
 
-fn reports_thread(data: &Data, wakes: &mpsc::Receiver<()>) -> Fallible<()> {
-    let timeout = Duration::from_secs(AUTOMATIC_THREAD_WAKEUP);
+/// Generate the reports of all the experiments waiting for one.
+pub fn generate_reports(data: &Data) -> Fallible<()> {
     let results = DatabaseDB::new(&data.db);
 
-    loop {
-        let mut ex = match Experiment::first_by_status(&data.db, Status::NeedsReport)? {
-            Some(ex) => ex,
-            None => {
-                // This will sleep AUTOMATIC_THREAD_WAKEUP seconds *or* until a wake is received
-                if let Err(mpsc::RecvTimeoutError::Disconnected) = wakes.recv_timeout(timeout) {
-                    thread::sleep(timeout);
-                }
-
-                continue;
-            }
-        };
+    while let Some(mut ex) = Experiment::first_by_status(&data.db, Status::NeedsReport)? {
         let name = ex.name.clone();
 
         info!("generating report for experiment {}...", name);
@@ -87,8 +71,24 @@ fn reports_thread(data: &Data, wakes: &mpsc::Receiver<()>) -> Fallible<()> {
                 ex.set_report_url(&data.db, &report_url)?;
                 info!("report for the experiment {} generated successfully!", name);
 
+                let ctx = ActionsCtx::new(&data.db, &data.config).with_actor(Actor::Server);
+                let bisection = match actions::advance_bisection(&ctx, &ex, &res.results) {
+                    Ok(step) => step,
+                    Err(err) => {
+                        error!("failed to advance the bisection of {}", name);
+                        utils::report_failure(&err);
+                        None
+                    }
+                };
+
                 let (mut regressed, mut fixed) = (0, 0);
-                res.crates.iter().for_each(|krate| {
+                // Crates known to be broken are still listed in the report, but aren't news
+                let relevant = res
+                    .results
+                    .crates
+                    .iter()
+                    .filter(|krate| krate.known_issue.is_none());
+                relevant.for_each(|krate| {
                     match krate.res {
                         Comparison::Regressed => regressed += 1,
                         Comparison::Fixed => fixed += 1,
@@ -97,17 +97,54 @@ fn reports_thread(data: &Data, wakes: &mpsc::Receiver<()>) -> Fallible<()> {
                 });
 
                 if let Some(ref github_issue) = ex.github_issue {
-                    Message::new()
+                    let mut message = Message::new()
                         .line("tada", format!("Experiment **`{}`** is completed!", name))
                         .line(
                             "bar_chart",
                             format!(
                                 " {} regressed and {} fixed ({} total)",
-                                regressed,
-                                fixed,
-                                res.crates.len(),
+                                regressed, fixed, res.total,
                             ),
-                        )
+                        );
+                    if res.targets.len() > 1 {
+                        let by_target = res
+                            .targets
+                            .iter()
+                            .map(|s| {
+                                format!(
+                                    "`{}`: {} regressed, {} fixed",
+                                    s.target, s.regressed, s.fixed
+                                )
+                            })
+                            .collect::<Vec<_>>();
+                        message = message.line("dart", by_target.join("; "));
+                    }
+                    match bisection {
+                        Some(BisectionStep::NotReproduced) => {
+                            message = message.line(
+                                "mag",
+                                "The regression wasn't reproduced, so the bisection stopped.",
+                            );
+                        }
+                        Some(BisectionStep::Found { good, bad, commits }) => {
+                            let mut found = format!(
+                                "The regression was introduced between `{}` and `{}`",
+                                good, bad
+                            );
+                            if let Some(commits) = commits {
+                                found.push_str(&format!(" ([commits]({}))", commits));
+                            }
+                            message = message.line("mag", format!("{}.", found));
+                        }
+                        Some(BisectionStep::Continued(next)) => {
+                            message = message.line(
+                                "mag",
+                                format!("The bisection continues with **`{}`**.", next),
+                            );
+                        }
+                        None => {}
+                    }
+                    message
                         .line(
                             "newspaper",
                             format!("[Open the full report]({}).", report_url),
@@ -126,48 +163,26 @@ fn reports_thread(data: &Data, wakes: &mpsc::Receiver<()>) -> Fallible<()> {
             }
         }
     }
-}
-
-#[derive(Clone, Default)]
-pub struct ReportsWorker(Arc<Mutex<Option<mpsc::Sender<()>>>>);
 
-impl ReportsWorker {
-    pub fn new() -> Self {
-        ReportsWorker(Arc::new(Mutex::new(None)))
-    }
-
-    pub fn spawn(&self, data: Data) {
-        let waker = self.0.clone();
-        thread::spawn(move || {
-            // Set up a new waker channel
-            let (wake_send, wake_recv) = mpsc::channel();
-            {
-                let mut waker = waker.lock().unwrap();
-                *waker = Some(wake_send);
-            }
-
-            loop {
-                let result = reports_thread(&data.clone(), &wake_recv)
-                    .with_context(|_| "the reports generator thread crashed");
-                if let Err(e) = result {
-                    utils::report_failure(&e);
-                }
+    Ok(())
+}
 
-                warn!("the reports generator thread will be respawned in one minute");
-                thread::sleep(Duration::from_secs(60));
-            }
-        });
+/// Queue again the reports whose generation was interrupted by a restart of the server.
+pub fn requeue_interrupted(data: &Data) -> Fallible<()> {
+    let mut requeued = 0;
+    for mut ex in Experiment::all(&data.db)? {
+        if ex.status == Status::GeneratingReport {
+            ex.set_status(&data.db, Status::NeedsReport)?;
+            requeued += 1;
+        }
     }
 
-    pub fn wake(&self) {
-        // We don't really care if the wake fails: the reports generator thread wakes up on its own
-        // every few minutes, so this just speeds up the process
-        if let Some(waker) = self.0.lock().ok().as_ref().and_then(|opt| opt.as_ref()) {
-            if waker.send(()).is_err() {
-                warn!("can't wake the reports generator, will have to wait");
-            }
-        } else {
-            warn!("no report generator to wake up!");
-        }
+    if requeued > 0 {
+        info!(
+            "{} reports were interrupted, generating them again",
+            requeued
+        );
+        data.jobs.trigger(JOB);
     }
+    Ok(())
 }
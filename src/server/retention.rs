@@ -0,0 +1,17 @@
+use crate::audit::Actor;
+use crate::prelude::*;
+use crate::server::Data;
+
+/// Name of the job removing the expired data.
+pub const JOB: &str = "retention";
+
+pub fn enabled(data: &Data) -> bool {
+    let retention = &data.config.server.retention;
+    retention.logs_days.is_some() || retention.experiments_days.is_some()
+}
+
+pub fn apply(data: &Data) -> Fallible<()> {
+    crate::retention::run(&data.db, &data.config, &Actor::Server)
+        .with_context(|_| "failed to apply the retention policy")?;
+    Ok(())
+}
@@ -1,10 +1,17 @@
-use crate::agent::Capabilities;
-use crate::experiments::{Assignee, Experiment, Status};
+use crate::agent::{AgentWorkers, Capabilities};
+use crate::audit::{self, Actor, AuditAction};
+use crate::crates::{metadata, Crate};
+use crate::db::QueryUtils;
+use crate::experiments::{Assignee, CrateSkipReason, Experiment, Status};
 use crate::prelude::*;
-use crate::results::{DatabaseDB, EncodingType, ProgressData};
-use crate::server::api_types::{AgentConfig, ApiResponse};
+use crate::results::{
+    DatabaseDB, EncodingType, ProgressData, SkipReason, TestResult, WriteResults,
+};
+use crate::server::agents::is_version_supported;
+use crate::server::api_types::{AgentConfig, ApiResponse, NextExperiment};
 use crate::server::auth::{auth_filter, AuthDetails, TokenType};
 use crate::server::messages::Message;
+use crate::server::reports;
 use crate::server::{Data, HttpError};
 use failure::Compat;
 use http::{Response, StatusCode};
@@ -21,6 +28,12 @@ pub struct ExperimentData<T> {
     data: T,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ExperimentName {
+    experiment_name: String,
+}
+
 pub fn routes(
     data: Arc<Data>,
 ) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
@@ -50,6 +63,14 @@ pub fn routes(
         .and(auth_filter(data.clone(), TokenType::Agent))
         .map(endpoint_next_experiment);
 
+    let next_experiment_partitioned = warp::post2()
+        .and(warp::path("next-experiment"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .map(endpoint_next_experiment_partitioned);
+
     let record_progress = warp::post2()
         .and(warp::path("record-progress"))
         .and(warp::path::end())
@@ -58,6 +79,14 @@ pub fn routes(
         .and(auth_filter(data.clone(), TokenType::Agent))
         .map(endpoint_record_progress);
 
+    let experiment_cancelled = warp::post2()
+        .and(warp::path("experiment-cancelled"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .map(endpoint_experiment_cancelled);
+
     let heartbeat = warp::post2()
         .and(warp::path("heartbeat"))
         .and(warp::path::end())
@@ -73,6 +102,14 @@ pub fn routes(
         .and(auth_filter(data.clone(), TokenType::Agent))
         .map(endpoint_error);
 
+    let worker_crash = warp::post2()
+        .and(warp::path("worker-crash"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .map(endpoint_worker_crash);
+
     warp::any()
         .and(
             config
@@ -80,11 +117,17 @@ pub fn routes(
                 .unify()
                 .or(next_experiment)
                 .unify()
+                .or(next_experiment_partitioned)
+                .unify()
                 .or(record_progress)
                 .unify()
+                .or(experiment_cancelled)
+                .unify()
                 .or(heartbeat)
                 .unify()
                 .or(error)
+                .unify()
+                .or(worker_crash)
                 .unify(),
         )
         .map(handle_results)
@@ -97,6 +140,10 @@ fn endpoint_config(
     data: Arc<Data>,
     auth: AuthDetails,
 ) -> Fallible<Response<Body>> {
+    if let Some(resp) = check_agent_version(&data, &auth)? {
+        return Ok(resp);
+    }
+
     data.agents.add_capabilities(&auth.name, &caps)?;
 
     Ok(ApiResponse::Success {
@@ -109,24 +156,57 @@ fn endpoint_config(
 }
 
 fn endpoint_next_experiment(data: Arc<Data>, auth: AuthDetails) -> Fallible<Response<Body>> {
-    let next = Experiment::next(&data.db, &Assignee::Agent(auth.name.clone()))?;
+    if let Some(resp) = check_agent_version(&data, &auth)? {
+        return Ok(resp);
+    }
+
+    let assignee = Assignee::Agent(auth.name.clone());
+    let next = if crate::maintenance::status(&data.db)?.is_some() {
+        // During maintenance agents are allowed to finish the experiment they're running, but
+        // they don't get any new one.
+        Experiment::run_by(&data.db, &assignee)?.map(|ex| (false, ex))
+    } else {
+        Experiment::next(&data.db, &data.config, &assignee)?
+    };
 
     let result = if let Some((new, ex)) = next {
-        if new {
-            if let Some(ref github_issue) = ex.github_issue {
-                Message::new()
-                    .line(
-                        "construction",
-                        format!(
-                            "Experiment **`{}`** is now **running** on agent `{}`.",
-                            ex.name, auth.name,
-                        ),
-                    )
-                    .send(&github_issue.api_url, &data)?;
-            }
-        }
+        Some(start_experiment(&data, &auth, new, ex)?)
+    } else {
+        None
+    };
 
-        Some((ex.clone(), ex.get_uncompleted_crates(&data.db)?))
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+/// Assign an experiment to an agent running other experiments with part of its workers, along
+/// with how many workers it should be run with.
+fn endpoint_next_experiment_partitioned(
+    workers: AgentWorkers,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    if let Some(resp) = check_agent_version(&data, &auth)? {
+        return Ok(resp);
+    }
+
+    let maintenance = crate::maintenance::status(&data.db)?.is_some();
+    let next = match Experiment::interrupted(&data.db, &workers, &auth.name)? {
+        // The experiments the agent was running before restarting are resumed first, even during
+        // maintenance
+        Some(ex) => ex
+            .partition_workers(&data.db, &data.config, &workers)?
+            .map(|count| (false, ex, count)),
+        None if maintenance => None,
+        None => Experiment::next_partitioned(&data.db, &data.config, &workers, &auth.name)?,
+    };
+
+    let result = if let Some((new, ex, workers)) = next {
+        let (experiment, crates) = start_experiment(&data, &auth, new, ex)?;
+        Some(NextExperiment {
+            experiment,
+            crates,
+            workers,
+        })
     } else {
         None
     };
@@ -134,12 +214,104 @@ fn endpoint_next_experiment(data: Arc<Data>, auth: AuthDetails) -> Fallible<Resp
     Ok(ApiResponse::Success { result }.into_response()?)
 }
 
+/// Notify the start of a new experiment, and get the crates the agent claimed and should run.
+fn start_experiment(
+    data: &Data,
+    auth: &AuthDetails,
+    new: bool,
+    mut ex: Experiment,
+) -> Fallible<(Experiment, Vec<Crate>)> {
+    if new {
+        if let Some(ref github_issue) = ex.github_issue {
+            Message::new()
+                .line(
+                    "construction",
+                    format!(
+                        "Experiment **`{}`** is now **running** on agent `{}`.",
+                        ex.name, auth.name,
+                    ),
+                )
+                .send(&github_issue.api_url, data)?;
+        }
+    }
+
+    let crates = skip_incompatible_crates(data, &mut ex, &auth.name)?;
+    Ok((ex, crates))
+}
+
+/// Record the crates that can't be built on the operating system of the agent as skipped,
+/// returning the crates the agent should still run.
+fn skip_incompatible_crates(data: &Data, ex: &mut Experiment, agent: &str) -> Fallible<Vec<Crate>> {
+    let crates = ex.get_claimed_crates(&data.db, agent)?;
+
+    let caps = Capabilities::for_agent(&data.db, agent)?;
+    let os = match caps.target_os() {
+        Some(os) => os,
+        None => return Ok(crates),
+    };
+    let incompatible = metadata::incompatible_crates(&data.db, &crates, os)?;
+    if incompatible.is_empty() {
+        return Ok(crates);
+    }
+
+    info!(
+        "skipping {} crates of experiment {} incompatible with {}",
+        incompatible.len(),
+        ex.name,
+        os
+    );
+    let db = DatabaseDB::new(&data.db);
+    let log = format!("crate skipped: it doesn't support the {} target\n", os);
+    let toolchains = ex.run_toolchains();
+    for krate in &incompatible {
+        // The crate still gets its skipped results, so it's not excluded from the experiment
+        data.db.execute(
+            "UPDATE experiment_crates SET skip_reason = ?1 WHERE experiment = ?2 AND crate = ?3;",
+            &[
+                &CrateSkipReason::IncompatibleTarget.to_str(),
+                &ex.name,
+                &serde_json::to_string(krate)?,
+            ],
+        )?;
+        for toolchain in &toolchains {
+            if db.get_result(ex, toolchain, krate)?.is_none() {
+                db.store_result(
+                    ex,
+                    krate,
+                    toolchain,
+                    TestResult::Skipped(SkipReason::IncompatibleTarget),
+                    log.as_bytes(),
+                    EncodingType::Gzip,
+                )?;
+            }
+        }
+    }
+
+    let (completed, all) = ex.raw_progress(&data.db)?;
+    if completed == all {
+        ex.set_status(&data.db, Status::NeedsReport)?;
+        info!("experiment {} completed, marked as needs-report", ex.name);
+        data.jobs.trigger(reports::JOB);
+    }
+
+    Ok(crates
+        .into_iter()
+        .filter(|krate| !incompatible.contains(krate))
+        .collect())
+}
+
 fn endpoint_record_progress(
     result: ExperimentData<ProgressData>,
     data: Arc<Data>,
     auth: AuthDetails,
 ) -> Fallible<Response<Body>> {
+    if let Some(resp) = check_agent_version(&data, &auth)? {
+        return Ok(resp);
+    }
+
+    // Agents are only allowed to act on experiments of their own namespace
     let mut ex = Experiment::get(&data.db, &result.experiment_name)?
+        .filter(|ex| ex.namespace == auth.namespace)
         .ok_or_else(|| err_msg("no experiment run by this agent"))?;
 
     info!(
@@ -149,37 +321,101 @@ fn endpoint_record_progress(
 
     let db = DatabaseDB::new(&data.db);
     db.store(&ex, &result.data, EncodingType::Gzip)?;
+    ex.record_progress_time(&data.db)?;
 
+    // Results sent after the experiment was cancelled are kept, but there is no report to generate
     let (completed, all) = ex.raw_progress(&data.db)?;
-    if completed == all {
+    if completed == all && ex.status != Status::Cancelled {
         ex.set_status(&data.db, Status::NeedsReport)?;
         info!("experiment {} completed, marked as needs-report", ex.name);
-        data.reports_worker.wake(); // Ensure the reports worker is awake
+        data.jobs.trigger(reports::JOB); // Ensure the report is generated right away
     }
 
     Ok(ApiResponse::Success { result: true }.into_response()?)
 }
 
+/// Tell the agent whether it should stop running the experiment, as it was cancelled, deleted or
+/// taken away from the agent (for example because the agent stalled and it was queued again).
+fn endpoint_experiment_cancelled(
+    query: ExperimentName,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let cancelled = match Experiment::get(&data.db, &query.experiment_name)?
+        .filter(|ex| ex.namespace == auth.namespace)
+    {
+        Some(ex) => !ex.is_run_by(&data.db, &auth.name)?,
+        None => true,
+    };
+
+    Ok(ApiResponse::Success { result: cancelled }.into_response()?)
+}
+
 fn endpoint_heartbeat(data: Arc<Data>, auth: AuthDetails) -> Fallible<Response<Body>> {
     if let Some(rev) = auth.git_revision {
         data.agents.set_git_revision(&auth.name, &rev)?;
     }
+    if let Some(version) = auth.version {
+        data.agents.set_version(&auth.name, &version)?;
+    }
 
     data.agents.record_heartbeat(&auth.name)?;
+    // The crates claimed by the agent can't be claimed by other agents while it's alive
+    Experiment::renew_claims(&data.db, &auth.name)?;
     Ok(ApiResponse::Success { result: true }.into_response()?)
 }
 
 fn endpoint_error(
     error: ExperimentData<HashMap<String, String>>,
     data: Arc<Data>,
-    _auth: AuthDetails,
+    auth: AuthDetails,
 ) -> Fallible<Response<Body>> {
     let mut ex = Experiment::get(&data.db, &error.experiment_name)?
+        .filter(|ex| ex.namespace == auth.namespace)
         .ok_or_else(|| err_msg("no experiment run by this agent"))?;
 
-    ex.set_status(&data.db, Status::Failed)?;
+    // Agents send the toolchain along with the error when it couldn't be installed
+    let toolchain = error.data.get("toolchain");
+    ex.set_status(
+        &data.db,
+        if toolchain.is_some() {
+            Status::ToolchainInstallFailed
+        } else {
+            Status::Failed
+        },
+    )?;
+    audit::record(
+        &data.db,
+        &Actor::Agent(auth.name.clone()),
+        AuditAction::ExperimentFailed,
+        Some(&ex.name),
+        &error.data,
+    )?;
 
-    if let Some(ref github_issue) = ex.github_issue {
+    if let (Some(github_issue), Some(toolchain)) = (&ex.github_issue, toolchain) {
+        Message::new()
+            .line(
+                "rotating_light",
+                format!(
+                    "Experiment **`{}`** couldn't install toolchain `{}`: {}",
+                    ex.name,
+                    toolchain,
+                    error.data.get("error").unwrap_or(&String::from("no error")),
+                ),
+            )
+            .line(
+                "page_facing_up",
+                format!(
+                    "Last lines of the installation log:\n\n```\n{}\n```",
+                    error.data.get("log").unwrap_or(&String::new()),
+                ),
+            )
+            .line(
+                "hammer_and_wrench",
+                "If the toolchain is fixed use the `retry` command.",
+            )
+            .send(&github_issue.api_url, &data)?;
+    } else if let Some(ref github_issue) = ex.github_issue {
         Message::new()
             .line(
                 "rotating_light",
@@ -202,6 +438,57 @@ fn endpoint_error(
     Ok(ApiResponse::Success { result: true }.into_response()?)
 }
 
+#[derive(Deserialize, Serialize)]
+struct WorkerCrash {
+    worker: String,
+    error: String,
+}
+
+fn endpoint_worker_crash(
+    crash: ExperimentData<WorkerCrash>,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let ex = Experiment::get(&data.db, &crash.experiment_name)?
+        .filter(|ex| ex.namespace == auth.namespace)
+        .ok_or_else(|| err_msg("no experiment run by this agent"))?;
+
+    // The agent restarts the worker by itself, the crash is only recorded to be investigated
+    warn!(
+        "worker {} of agent {} crashed while running {}: {}",
+        crash.data.worker, auth.name, ex.name, crash.data.error
+    );
+    audit::record(
+        &data.db,
+        &Actor::Agent(auth.name.clone()),
+        AuditAction::WorkerCrashed,
+        Some(&ex.name),
+        &crash.data,
+    )?;
+
+    Ok(ApiResponse::Success { result: true }.into_response()?)
+}
+
+/// Refuse to give work to agents older than the minimum version configured, as they might produce
+/// results incompatible with the other agents.
+fn check_agent_version(data: &Data, auth: &AuthDetails) -> Fallible<Option<Response<Body>>> {
+    if let Some(ref minimum) = data.config.server.agent_min_version {
+        let version = auth.version.as_ref().map(|v| v.as_str());
+        if !is_version_supported(version, minimum) {
+            warn!(
+                "refusing work to agent {} running the outdated version {}",
+                auth.name,
+                version.unwrap_or("unknown"),
+            );
+            return Ok(Some(
+                ApiResponse::upgrade_required(minimum.clone()).into_response()?,
+            ));
+        }
+    }
+
+    Ok(None)
+}
+
 fn handle_results(resp: Fallible<Response<Body>>) -> Response<Body> {
     match resp {
         Ok(resp) => resp,
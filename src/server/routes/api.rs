@@ -0,0 +1,785 @@
+use crate::actions::{Action, ActionsCtx, ExperimentError};
+use crate::audit::{self, Actor};
+use crate::crates::lists::{self, CrateListError};
+use crate::crates::Crate;
+use crate::db::QueryUtils;
+use crate::experiments::{qualified_name, CrateSelect, Experiment};
+use crate::prelude::*;
+use crate::report::{self, Comparison};
+use crate::results::{DatabaseDB, ReadResults, TestResult};
+use crate::server::api_types::{
+    AgentSummary, ApiResponse, AuditLogQuery, CrateListSummary, CrateResults, ExperimentSummary,
+    ExperimentsQuery, QueueExperiment, ResultsPage, ResultsQuery, ToolchainDetails,
+    ToolchainResult,
+};
+use crate::server::auth::{auth_filter, AuthDetails, TokenType};
+use crate::server::routes::webhooks::experiment_from_template;
+use crate::server::{Data, HttpError};
+use failure::Compat;
+use futures::{sync::mpsc, Future, Sink, Stream};
+use http::header::{HeaderValue, CONTENT_TYPE};
+use http::{Response, StatusCode};
+use hyper::{Body, Chunk};
+use percent_encoding::percent_decode;
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+use std::thread;
+use warp::{self, filters::body::FullBody, Filter, Rejection};
+
+const DEFAULT_RESULTS_LIMIT: usize = 100;
+const MAX_RESULTS_LIMIT: usize = 1000;
+// Number of chunks of the exported results buffered while the client is reading them
+const EXPORT_BUFFERED_CHUNKS: usize = 16;
+const MAX_CRATE_LIST_SIZE: u64 = 16 * 1024 * 1024;
+const MAX_QUEUE_EXPERIMENT_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, Fail)]
+enum ApiError {
+    #[fail(display = "invalid cursor")]
+    InvalidCursor,
+    #[fail(display = "limit must be between 1 and {}", _0)]
+    InvalidLimit(usize),
+    #[fail(display = "toolchain {} is not part of the experiment", _0)]
+    UnknownToolchain(String),
+    #[fail(display = "the list of crates is not valid UTF-8")]
+    InvalidCrateList,
+}
+
+pub fn routes(
+    data: Arc<Data>,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    let data_cloned = data.clone();
+    let data_filter = warp::any().map(move || data_cloned.clone());
+
+    let experiments = warp::get2()
+        .and(warp::path("experiments"))
+        .and(warp::path::end())
+        .and(warp::query::<ExperimentsQuery>())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::ReadOnly))
+        .map(endpoint_experiments);
+
+    let results = warp::get2()
+        .and(warp::path("experiments"))
+        .and(experiment_name())
+        .and(warp::path("results"))
+        .and(warp::path::end())
+        .and(warp::query::<ResultsQuery>())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::ReadOnly))
+        .map(endpoint_results);
+
+    let export = warp::get2()
+        .and(warp::path("experiments"))
+        .and(experiment_name())
+        .and(warp::path("results.ndjson"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::ReadOnly))
+        .map(endpoint_export);
+
+    let toolchains = warp::get2()
+        .and(warp::path("experiments"))
+        .and(experiment_name())
+        .and(warp::path("toolchains"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::ReadOnly))
+        .map(endpoint_toolchains);
+
+    let progress = warp::get2()
+        .and(warp::path("experiments"))
+        .and(experiment_name())
+        .and(warp::path("progress"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::ReadOnly))
+        .map(endpoint_progress);
+
+    let audit_log = warp::get2()
+        .and(warp::path("audit-log"))
+        .and(warp::path::end())
+        .and(warp::query::<AuditLogQuery>())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::ReadOnly))
+        .map(endpoint_audit_log);
+
+    let agents = warp::get2()
+        .and(warp::path("agents"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::ReadOnly))
+        .map(endpoint_agents);
+
+    let queue_experiment = warp::post2()
+        .and(warp::path("experiments"))
+        .and(warp::path::end())
+        .and(warp::body::content_length_limit(MAX_QUEUE_EXPERIMENT_SIZE))
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::Queue))
+        .map(endpoint_queue_experiment);
+
+    let upload_crate_list = warp::put2()
+        .and(warp::path("crate-lists"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::body::content_length_limit(MAX_CRATE_LIST_SIZE))
+        .and(warp::body::concat())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::CrateList))
+        .map(endpoint_upload_crate_list);
+
+    let results = experiments
+        .or(results)
+        .unify()
+        .or(export)
+        .unify()
+        .or(toolchains)
+        .unify()
+        .or(progress)
+        .unify()
+        .or(audit_log)
+        .unify()
+        .or(agents)
+        .unify()
+        .or(upload_crate_list)
+        .unify()
+        .or(queue_experiment)
+        .unify();
+
+    // The GraphQL schema isn't aware of namespaces, so only the tokens of the default namespace
+    // are allowed to use it
+    #[cfg(feature = "graphql")]
+    let results = results
+        .or(warp::path("graphql")
+            .and(auth_filter(data.clone(), TokenType::ReadOnly))
+            .and_then(|auth: AuthDetails| {
+                if auth.namespace == crate::experiments::DEFAULT_NAMESPACE {
+                    Ok(auth)
+                } else {
+                    Err(warp::reject::custom(HttpError::Forbidden.compat()))
+                }
+            })
+            .and(super::graphql::routes(data.clone()))
+            .map(|_auth: AuthDetails, resp| resp))
+        .unify();
+
+    warp::any()
+        .and(results)
+        .map(handle_results)
+        .recover(handle_errors)
+        .unify()
+}
+
+/// Name of the experiment in the path of the request. Names are percent-encoded by the clients,
+/// as they can contain characters not allowed in paths.
+fn experiment_name() -> impl Filter<Extract = (String,), Error = Rejection> + Copy {
+    warp::path::param().and_then(|name: String| {
+        percent_decode(name.as_bytes())
+            .decode_utf8()
+            .map(|name| name.into_owned())
+            .map_err(|_| warp::reject::not_found())
+    })
+}
+
+/// Load an experiment, only if it belongs to the namespace of the token.
+fn get_experiment(data: &Data, name: &str, auth: &AuthDetails) -> Fallible<Option<Experiment>> {
+    Ok(Experiment::get(&data.db, name)?.filter(|ex| ex.namespace == auth.namespace))
+}
+
+fn endpoint_experiments(
+    query: ExperimentsQuery,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let requesters = audit::requesters(&data.db)?;
+    let experiments = Experiment::all(&data.db)?
+        .into_iter()
+        .filter(|ex| ex.namespace == auth.namespace)
+        .filter(|ex| query.matches(ex, requesters.get(&ex.name).map(|r| r.as_str())))
+        .map(|ex| {
+            Ok(ExperimentSummary {
+                cost: ex.cost(&data.db)?,
+                toolchains: ex.toolchains.iter().map(|tc| tc.to_string()).collect(),
+                name: ex.name,
+                mode: ex.mode,
+                status: ex.status,
+                priority: ex.priority,
+                report_url: ex.report_url,
+                tags: ex.tags.names().to_vec(),
+                index_snapshot: ex.index_snapshot,
+            })
+        })
+        .collect::<Fallible<Vec<_>>>()?;
+
+    Ok(ApiResponse::Success {
+        result: experiments,
+    }
+    .into_response()?)
+}
+
+fn endpoint_results(
+    name: String,
+    query: ResultsQuery,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let ex = match get_experiment(&data, &name, &auth)? {
+        Some(ex) => ex,
+        None => return Ok(ApiResponse::not_found().into_response()?),
+    };
+
+    match results_page(&data, &ex, &query) {
+        Ok(page) => Ok(ApiResponse::Success { result: page }.into_response()?),
+        Err(err) => {
+            if let Some(err) = err.downcast_ref::<ApiError>() {
+                Ok(ApiResponse::bad_request(err.to_string()).into_response()?)
+            } else {
+                Err(err)
+            }
+        }
+    }
+}
+
+type ChunkSender = mpsc::Sender<Result<Chunk, io::Error>>;
+
+/// Writer sending what's written to it as chunks of the body of a response.
+struct ChunkWriter(Option<ChunkSender>);
+
+impl ChunkWriter {
+    /// Send the error instead of the rest of the body, aborting the response.
+    fn abort(&mut self, err: &failure::Error) {
+        if let Some(sender) = self.0.take() {
+            let err = io::Error::new(io::ErrorKind::Other, err.to_string());
+            // The client might have disconnected already
+            let _ = sender.send(Err(err)).wait();
+        }
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let disconnected = || io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected");
+        let sender = self.0.take().ok_or_else(disconnected)?;
+        // Block until the client read enough of the previous chunks
+        let sender = sender
+            .send(Ok(Chunk::from(buf.to_vec())))
+            .wait()
+            .map_err(|_| disconnected())?;
+        self.0 = Some(sender);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn endpoint_export(name: String, data: Arc<Data>, auth: AuthDetails) -> Fallible<Response<Body>> {
+    let ex = match get_experiment(&data, &name, &auth)? {
+        Some(ex) => ex,
+        None => return Ok(ApiResponse::not_found().into_response()?),
+    };
+
+    // The results are written by another thread while they're sent to the client, as loading all
+    // of them in memory first wouldn't be feasible for big experiments
+    let (sender, receiver) = mpsc::channel(EXPORT_BUFFERED_CHUNKS);
+    thread::spawn(move || {
+        let mut writer = BufWriter::new(ChunkWriter(Some(sender)));
+        let result = ex.get_crates(&data.db).and_then(|crates| {
+            let results_db = DatabaseDB::new(&data.db);
+            report::export_ndjson(&results_db, &data.config, &ex, &crates, &mut writer)
+        });
+        if let Err(err) = result {
+            error!("failed to export the results of {}", ex.name);
+            crate::utils::report_failure(&err);
+            writer.get_mut().abort(&err);
+        }
+    });
+
+    let body = receiver
+        .map_err(|()| io::Error::new(io::ErrorKind::Other, "export interrupted"))
+        .and_then(|chunk| chunk);
+    let mut resp = Response::new(Body::wrap_stream(body));
+    resp.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+    Ok(resp)
+}
+
+fn endpoint_toolchains(
+    name: String,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let ex = match get_experiment(&data, &name, &auth)? {
+        Some(ex) => ex,
+        None => return Ok(ApiResponse::not_found().into_response()?),
+    };
+
+    let results_db = DatabaseDB::new(&data.db);
+    let mut toolchains = Vec::new();
+    for tc in &ex.toolchains {
+        // The version is only known after an agent installed the toolchain
+        let version = results_db.load_toolchain_version(&ex, tc)?;
+        toolchains.push(ToolchainDetails {
+            toolchain: tc.to_string(),
+            release: version.as_ref().map(|v| v.release.clone()),
+            commit_hash: version.as_ref().and_then(|v| v.commit_hash.clone()),
+            commit_date: version.as_ref().and_then(|v| v.commit_date.clone()),
+            host: version.as_ref().and_then(|v| v.host.clone()),
+            llvm_version: version.and_then(|v| v.llvm_version),
+        });
+    }
+
+    Ok(ApiResponse::Success { result: toolchains }.into_response()?)
+}
+
+fn endpoint_progress(name: String, data: Arc<Data>, auth: AuthDetails) -> Fallible<Response<Body>> {
+    let ex = match get_experiment(&data, &name, &auth)? {
+        Some(ex) => ex,
+        None => return Ok(ApiResponse::not_found().into_response()?),
+    };
+
+    let progress = ex.detailed_progress(&data.db)?;
+    Ok(ApiResponse::Success { result: progress }.into_response()?)
+}
+
+fn endpoint_audit_log(
+    query: AuditLogQuery,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let limit = query.limit.unwrap_or(DEFAULT_RESULTS_LIMIT);
+    if limit == 0 || limit > MAX_RESULTS_LIMIT {
+        return Ok(
+            ApiResponse::bad_request(ApiError::InvalidLimit(MAX_RESULTS_LIMIT).to_string())
+                .into_response()?,
+        );
+    }
+
+    let entries = audit::entries(&data.db, Some(&auth.namespace), query.before, limit)?;
+    Ok(ApiResponse::Success { result: entries }.into_response()?)
+}
+
+fn endpoint_agents(data: Arc<Data>, auth: AuthDetails) -> Fallible<Response<Body>> {
+    let agents = data
+        .agents
+        .all()?
+        .iter()
+        .filter(|agent| agent.namespace() == auth.namespace)
+        .map(AgentSummary::from_agent)
+        .collect::<Vec<_>>();
+    Ok(ApiResponse::Success { result: agents }.into_response()?)
+}
+
+fn endpoint_upload_crate_list(
+    name: String,
+    body: FullBody,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    // Lists uploaded by the tokens of a namespace can't replace the lists of other namespaces
+    let stored: Fallible<usize> = std::str::from_utf8(body.bytes())
+        .map_err(|_| ApiError::InvalidCrateList.into())
+        .and_then(|list| {
+            let crates = lists::parse_crate_list(&data.db, list)?;
+            lists::store_crate_list(&data.db, &auth.namespace, &name, &crates)?;
+            Ok(crates.len())
+        });
+
+    match stored {
+        Ok(crates) => {
+            let name = qualified_name(&auth.namespace, &name);
+            info!("{} uploaded the list of crates {}", auth.name, name);
+            Ok(ApiResponse::Success {
+                result: CrateListSummary {
+                    select: CrateSelect::List(name).to_string(),
+                    crates,
+                },
+            }
+            .into_response()?)
+        }
+        Err(err) => {
+            if err.downcast_ref::<CrateListError>().is_some()
+                || err.downcast_ref::<ApiError>().is_some()
+            {
+                Ok(ApiResponse::bad_request(err.to_string()).into_response()?)
+            } else {
+                Err(err)
+            }
+        }
+    }
+}
+
+fn endpoint_queue_experiment(
+    queue: QueueExperiment,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Fallible<Response<Body>> {
+    let mut experiment = match experiment_from_template(queue.name.clone(), &queue.args) {
+        Ok(experiment) => experiment,
+        Err(err) => return Ok(ApiResponse::bad_request(err.to_string()).into_response()?),
+    };
+    experiment.namespace = auth.namespace.clone();
+
+    let ctx = ActionsCtx::new(&data.db, &data.config).with_actor(Actor::Api(auth.name.clone()));
+    match experiment.apply(&ctx) {
+        Ok(()) => {
+            info!("{} queued the experiment {}", auth.name, queue.name);
+            Ok(ApiResponse::Success { result: () }.into_response()?)
+        }
+        Err(err) => {
+            if err.downcast_ref::<ExperimentError>().is_some()
+                || err.downcast_ref::<CrateListError>().is_some()
+            {
+                Ok(ApiResponse::bad_request(err.to_string()).into_response()?)
+            } else {
+                Err(err)
+            }
+        }
+    }
+}
+
+fn results_page(data: &Data, ex: &Experiment, query: &ResultsQuery) -> Fallible<ResultsPage> {
+    let limit = query.limit.unwrap_or(DEFAULT_RESULTS_LIMIT);
+    if limit == 0 || limit > MAX_RESULTS_LIMIT {
+        return Err(ApiError::InvalidLimit(MAX_RESULTS_LIMIT).into());
+    }
+
+    // Only the compared toolchains are classified
+    let compared = [ex.start_toolchain(), ex.end_toolchain()];
+    let toolchains = if let Some(ref name) = query.toolchain {
+        let tc = compared
+            .iter()
+            .find(|tc| tc.to_string() == *name)
+            .ok_or_else(|| ApiError::UnknownToolchain(name.clone()))?;
+        vec![*tc]
+    } else {
+        compared.to_vec()
+    };
+
+    // The cursor is the serialized representation of the last crate returned in the previous
+    // page, as the crates are returned in the order they're stored in the database
+    let mut cursor = match query.cursor {
+        Some(ref cursor) => {
+            let decoded = base64::decode(cursor).map_err(|_| ApiError::InvalidCursor)?;
+            String::from_utf8(decoded).map_err(|_| ApiError::InvalidCursor)?
+        }
+        None => String::new(),
+    };
+
+    let results_db = DatabaseDB::new(&data.db);
+    let mut results = Vec::new();
+    loop {
+        // Fetch the crates in batches, as the filters are applied after the crates are loaded
+        let batch = data.db.query(
+            "SELECT crate FROM experiment_crates WHERE experiment = ?1 AND crate > ?2 \
+             ORDER BY crate LIMIT ?3;",
+            &[&ex.name, &cursor, &(limit as i64)],
+            |row| row.get::<_, String>("crate"),
+        )?;
+        let exhausted = batch.len() < limit;
+
+        for raw in batch {
+            let krate: Crate = serde_json::from_str(&raw)?;
+            cursor = raw;
+
+            if let Some(ref prefix) = query.krate {
+                if !krate.source().name().starts_with(prefix.as_str()) {
+                    continue;
+                }
+            }
+
+            let (classification, res1, res2) = classify(data, ex, &results_db, &krate)?;
+            if let Some(filter) = query.classification {
+                if filter != classification {
+                    continue;
+                }
+            }
+
+            let runs = toolchains
+                .iter()
+                .map(|&tc| ToolchainResult {
+                    toolchain: tc.to_string(),
+                    result: if tc == ex.start_toolchain() {
+                        res1
+                    } else {
+                        res2
+                    },
+                })
+                .collect();
+
+            results.push(CrateResults {
+                name: krate.to_string(),
+                krate,
+                classification,
+                runs,
+            });
+
+            if results.len() == limit {
+                return Ok(ResultsPage {
+                    results,
+                    next_cursor: Some(base64::encode(&cursor)),
+                });
+            }
+        }
+
+        if exhausted {
+            return Ok(ResultsPage {
+                results,
+                next_cursor: None,
+            });
+        }
+    }
+}
+
+/// Load the results of a crate in the experiment, comparing them.
+pub(super) fn classify(
+    data: &Data,
+    ex: &Experiment,
+    results_db: &DatabaseDB,
+    krate: &Crate,
+) -> Fallible<(Comparison, Option<TestResult>, Option<TestResult>)> {
+    let res1 = results_db.load_test_result(ex, ex.start_toolchain(), krate)?;
+    let res2 = results_db.load_test_result(ex, ex.end_toolchain(), krate)?;
+    Ok((
+        report::compare(&data.config, krate, res1, res2),
+        res1,
+        res2,
+    ))
+}
+
+fn handle_results(resp: Fallible<Response<Body>>) -> Response<Body> {
+    match resp {
+        Ok(resp) => resp,
+        Err(err) => ApiResponse::internal_error(err.to_string())
+            .into_response()
+            .unwrap(),
+    }
+}
+
+fn handle_errors(err: Rejection) -> Result<Response<Body>, Rejection> {
+    let error = if let Some(compat) = err.find_cause::<Compat<HttpError>>() {
+        Some(*compat.get_ref())
+    } else if let StatusCode::NOT_FOUND = err.status() {
+        Some(HttpError::NotFound)
+    } else if let StatusCode::METHOD_NOT_ALLOWED = err.status() {
+        Some(HttpError::NotFound)
+    } else {
+        None
+    };
+
+    match error {
+        Some(HttpError::NotFound) => Ok(ApiResponse::not_found().into_response().unwrap()),
+        Some(HttpError::Forbidden) => Ok(ApiResponse::unauthorized().into_response().unwrap()),
+        None => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::routes;
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::audit;
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::experiments::{Experiment, Mode, Status};
+    use crate::server::tokens::{NamespaceTokens, Tokens};
+    use crate::server::Data;
+    use http::StatusCode;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_tokens_required() {
+        let mut tokens = Tokens::default();
+        tokens.api.insert("read-only".into(), "reader".into());
+        tokens.agents.insert("agent".into(), "agent-1".into());
+        tokens
+            .crate_lists
+            .insert("crate-list".into(), "uploader".into());
+        let data = Data::dummy(Database::temp().unwrap(), Config::default(), tokens).unwrap();
+        let api = routes(Arc::new(data));
+
+        let status = |method: &str, path: &str, token: Option<&str>| {
+            // Uploads are only accepted with a known length
+            let mut req = warp::test::request().method(method).path(path).body("foo");
+            if let Some(token) = token {
+                req = req.header("Authorization", format!("CraterToken {}", token).as_str());
+            }
+            req.reply(&api).status()
+        };
+
+        // Requests without a valid token are rejected
+        assert_eq!(
+            status("GET", "/experiments", None),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("GET", "/experiments", Some("invalid")),
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Tokens of the other types can't read the experiments
+        assert_eq!(
+            status("GET", "/experiments", Some("agent")),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("GET", "/experiments", Some("crate-list")),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("GET", "/experiments", Some("read-only")),
+            StatusCode::OK
+        );
+
+        // Read-only tokens can't upload lists of crates
+        assert_eq!(
+            status("PUT", "/crate-lists/foo", None),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("PUT", "/crate-lists/foo", Some("read-only")),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[test]
+    fn test_queue_experiment() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        let mut tokens = Tokens::default();
+        tokens.api.insert("read-only".into(), "reader".into());
+        tokens.queue.insert("queue".into(), "automation".into());
+        let api = routes(Arc::new(Data::dummy(db.clone(), config, tokens).unwrap()));
+
+        let queue = |token: &str, body: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/experiments")
+                .header("Authorization", format!("CraterToken {}", token).as_str())
+                .body(body)
+                .reply(&api)
+                .status()
+        };
+        let body =
+            r#"{"name": "foo", "args": "start=stable end=beta mode=check-only crates=local"}"#;
+
+        // Read-only tokens can't queue experiments
+        assert_eq!(queue("read-only", body), StatusCode::UNAUTHORIZED);
+        assert!(Experiment::get(&db, "foo").unwrap().is_none());
+
+        assert_eq!(queue("queue", body), StatusCode::OK);
+        let ex = Experiment::get(&db, "foo").unwrap().unwrap();
+        assert_eq!(ex.status, Status::Queued);
+        assert_eq!(ex.mode, Mode::CheckOnly);
+        assert_eq!(audit::requesters(&db).unwrap()["foo"], "api:automation");
+
+        // Invalid arguments and existing names are rejected
+        assert_eq!(queue("queue", body), StatusCode::BAD_REQUEST);
+        let invalid = r#"{"name": "bar", "args": "start=stable mode=unknown"}"#;
+        assert_eq!(queue("queue", invalid), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_namespaced_tokens() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo")
+            .apply(&ActionsCtx::new(&db, &config))
+            .unwrap();
+        let mut tokens = Tokens::default();
+        tokens.api.insert("default-reader".into(), "reader".into());
+        let mut infra = NamespaceTokens::default();
+        infra.api.insert("infra-reader".into(), "reader".into());
+        infra
+            .queue
+            .insert("infra-queue".into(), "automation".into());
+        infra
+            .crate_lists
+            .insert("infra-uploader".into(), "uploader".into());
+        tokens.namespaces.insert("infra".into(), infra);
+        let api = routes(Arc::new(Data::dummy(db.clone(), config, tokens).unwrap()));
+
+        let request = |method: &str, path: &str, token: &str, body: &str| {
+            warp::test::request()
+                .method(method)
+                .path(path)
+                .header("Authorization", format!("CraterToken {}", token).as_str())
+                .body(body)
+                .reply(&api)
+        };
+
+        // Experiments are queued in the namespace of the token
+        let body =
+            r#"{"name": "foo", "args": "start=stable end=beta mode=check-only crates=local"}"#;
+        assert_eq!(
+            request("POST", "/experiments", "infra-queue", body).status(),
+            StatusCode::OK
+        );
+        let ex = Experiment::get(&db, "infra:foo").unwrap().unwrap();
+        assert_eq!(ex.namespace, "infra");
+
+        // Tokens can only read the experiments of their own namespace
+        let list = |token| {
+            let resp = request("GET", "/experiments", token, "");
+            let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            body["result"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|ex| ex["name"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(list("default-reader"), vec!["foo".to_string()]);
+        assert_eq!(list("infra-reader"), vec!["infra:foo".to_string()]);
+        let status = |path, token| request("GET", path, token, "").status();
+        assert_eq!(
+            status("/experiments/foo/progress", "infra-reader"),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status("/experiments/infra%3Afoo/progress", "default-reader"),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status("/experiments/infra%3Afoo/progress", "infra-reader"),
+            StatusCode::OK
+        );
+
+        // Lists of crates are stored inside the namespace of the token
+        let list = "https://github.com/brson/hello-rs";
+        let resp = request("PUT", "/crate-lists/regressed", "infra-uploader", list);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["result"]["select"], "list:infra:regressed");
+    }
+
+    #[test]
+    fn test_encoded_experiment_name() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo bar")
+            .apply(&ActionsCtx::new(&db, &config))
+            .unwrap();
+        let mut tokens = Tokens::default();
+        tokens.api.insert("read-only".into(), "reader".into());
+        let api = routes(Arc::new(Data::dummy(db, config, tokens).unwrap()));
+
+        let status = |path: &str| {
+            warp::test::request()
+                .path(path)
+                .header("Authorization", "CraterToken read-only")
+                .reply(&api)
+                .status()
+        };
+        assert_eq!(status("/experiments/foo%20bar/progress"), StatusCode::OK);
+        assert_eq!(status("/experiments/foo/progress"), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::crates::Crate;
+use crate::experiments::{Experiment, Status};
+use crate::prelude::*;
+use crate::report::{self, Comparison, SanitizationContext};
+use crate::results::{DatabaseDB, TestResult};
+use crate::server::routes::api::classify;
+use crate::server::Data;
+use crate::toolchain::Toolchain;
+use http::Response;
+use hyper::Body;
+use juniper::{EmptyMutation, FieldResult, RootNode};
+use std::sync::Arc;
+use warp::{self, Filter, Rejection};
+
+pub(super) struct Context {
+    data: Arc<Data>,
+}
+
+impl juniper::Context for Context {}
+
+type Schema = RootNode<'static, Query, EmptyMutation<Context>>;
+
+pub(super) fn routes(
+    data: Arc<Data>,
+) -> impl Filter<Extract = (Fallible<Response<Body>>,), Error = Rejection> + Clone {
+    let context = warp::any()
+        .map(move || Context { data: data.clone() })
+        .boxed();
+
+    warp::path::end()
+        .and(juniper_warp::make_graphql_filter(
+            Schema::new(Query, EmptyMutation::new()),
+            context,
+        ))
+        .map(|resp: Response<Vec<u8>>| -> Fallible<Response<Body>> { Ok(resp.map(Body::from)) })
+}
+
+pub(super) struct Query;
+
+#[juniper::object(Context = Context)]
+impl Query {
+    /// Get a single experiment by its name.
+    fn experiment(context: &Context, name: String) -> FieldResult<Option<ExperimentObject>> {
+        Ok(Experiment::get(&context.data.db, &name)?.map(ExperimentObject))
+    }
+
+    /// List all the experiments, optionally only the ones with the provided status. Archived
+    /// experiments are only returned when explicitly requested with the `archived` status.
+    fn experiments(
+        context: &Context,
+        status: Option<String>,
+    ) -> FieldResult<Vec<ExperimentObject>> {
+        let status = status.map(|s| s.parse::<Status>()).transpose()?;
+        Ok(Experiment::all(&context.data.db)?
+            .into_iter()
+            .filter(|ex| match status {
+                Some(status) => status == ex.status,
+                None => ex.status != Status::Archived,
+            })
+            .map(ExperimentObject)
+            .collect())
+    }
+}
+
+pub(super) struct ExperimentObject(Experiment);
+
+#[juniper::object(Context = Context, name = "Experiment")]
+impl ExperimentObject {
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    fn namespace(&self) -> &str {
+        &self.0.namespace
+    }
+
+    fn status(&self) -> &str {
+        self.0.status.to_str()
+    }
+
+    fn mode(&self) -> &str {
+        self.0.mode.to_str()
+    }
+
+    fn toolchains(&self) -> Vec<String> {
+        self.0.toolchains.iter().map(|tc| tc.to_string()).collect()
+    }
+
+    fn priority(&self) -> i32 {
+        self.0.priority
+    }
+
+    fn created_at(&self) -> String {
+        self.0.created_at.to_rfc3339()
+    }
+
+    fn report_url(&self) -> Option<&str> {
+        self.0.report_url.as_ref().map(|url| url.as_str())
+    }
+
+    /// Percentage of the experiment completed so far.
+    fn progress(&self, context: &Context) -> FieldResult<i32> {
+        Ok(i32::from(self.0.progress(&context.data.db)?))
+    }
+
+    /// Results of all the crates in the experiment, optionally only the ones with the provided
+    /// classification.
+    fn results(
+        &self,
+        context: &Context,
+        classification: Option<String>,
+    ) -> FieldResult<Vec<CrateResultObject>> {
+        let classification = classification
+            .map(|c| c.parse::<Comparison>())
+            .transpose()?;
+        load_results(&context.data, &self.0, classification)
+    }
+
+    /// Results of the crates that regressed in the experiment.
+    fn regressions(&self, context: &Context) -> FieldResult<Vec<CrateResultObject>> {
+        load_results(&context.data, &self.0, Some(Comparison::Regressed))
+    }
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(name = "CrateResult")]
+pub(super) struct CrateResultObject {
+    name: String,
+    classification: String,
+    runs: Vec<RunObject>,
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(name = "Run")]
+pub(super) struct RunObject {
+    toolchain: String,
+    result: Option<String>,
+    /// URL of the build log, only available after the report is generated.
+    log_url: Option<String>,
+}
+
+fn load_results(
+    data: &Data,
+    ex: &Experiment,
+    classification: Option<Comparison>,
+) -> FieldResult<Vec<CrateResultObject>> {
+    let results_db = DatabaseDB::new(&data.db);
+    let mut results = Vec::new();
+
+    for krate in ex.get_crates(&data.db)? {
+        let (comparison, res1, res2) = classify(data, ex, &results_db, &krate)?;
+        if classification.map(|c| c != comparison).unwrap_or(false) {
+            continue;
+        }
+
+        results.push(CrateResultObject {
+            name: krate.to_string(),
+            classification: comparison.to_string(),
+            runs: vec![
+                run(ex, &krate, ex.start_toolchain(), res1),
+                run(ex, &krate, ex.end_toolchain(), res2),
+            ],
+        });
+    }
+
+    Ok(results)
+}
+
+fn run(ex: &Experiment, krate: &Crate, tc: &Toolchain, result: Option<TestResult>) -> RunObject {
+    RunObject {
+        toolchain: tc.to_string(),
+        result: result.map(|r| r.to_string()),
+        log_url: ex.report_url.as_ref().map(|url| {
+            let path = report::crate_to_path_fragment(tc, krate, SanitizationContext::Url);
+            format!(
+                "{}/{}/log.txt",
+                url.trim_end_matches("index.html").trim_end_matches('/'),
+                path.to_string_lossy().replace(r"\", "/"),
+            )
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Context, Query, Schema};
+    use crate::actions::{Action, ActionsCtx, CreateExperiment};
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::server::tokens::Tokens;
+    use crate::server::Data;
+    use juniper::{graphql_value, EmptyMutation, Variables};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_experiment_query() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        CreateExperiment::dummy("foo")
+            .apply(&ActionsCtx::new(&db, &config))
+            .unwrap();
+
+        let context = Context {
+            data: Arc::new(Data::dummy(db, config, Tokens::default()).unwrap()),
+        };
+        let schema = Schema::new(Query, EmptyMutation::new());
+        let (res, errors) = juniper::execute(
+            r#"{
+                experiment(name: "foo") { name status mode toolchains }
+                missing: experiment(name: "bar") { name }
+            }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &context,
+        )
+        .unwrap();
+
+        assert!(errors.is_empty());
+        assert_eq!(
+            res,
+            graphql_value!({
+                "experiment": {
+                    "name": "foo",
+                    "status": "queued",
+                    "mode": "build-and-test",
+                    "toolchains": ["stable", "beta"],
+                },
+                "missing": None,
+            })
+        );
+    }
+}
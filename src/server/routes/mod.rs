@@ -1,3 +1,6 @@
 pub mod agent;
+pub mod api;
+#[cfg(feature = "graphql")]
+mod graphql;
 pub mod ui;
 pub mod webhooks;
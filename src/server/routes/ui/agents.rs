@@ -13,8 +13,9 @@ struct AgentData {
     status_class: &'static str,
     status_pretty: &'static str,
     last_heartbeat: Option<String>,
-    assigned_experiment: Option<String>,
+    assigned_experiments: Vec<String>,
     git_revision: Option<String>,
+    version: Option<String>,
     capabilities: Vec<String>,
 }
 
@@ -47,12 +48,17 @@ pub fn endpoint_list(data: Arc<Data>) -> Fallible<Response<Body>> {
             last_heartbeat: agent
                 .last_heartbeat()
                 .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true)),
-            assigned_experiment: if show_assigned {
-                agent.assigned_experiment().map(|ex| ex.name.clone())
+            assigned_experiments: if show_assigned {
+                agent
+                    .assigned_experiments()
+                    .iter()
+                    .map(|ex| ex.name.clone())
+                    .collect()
             } else {
-                None
+                Vec::new()
             },
             git_revision: agent.git_revision().cloned(),
+            version: agent.version().cloned(),
             capabilities,
         });
     }
@@ -60,7 +66,7 @@ pub fn endpoint_list(data: Arc<Data>) -> Fallible<Response<Body>> {
     render_template(
         "ui/agents.html",
         &ListContext {
-            layout: LayoutContext::new(),
+            layout: LayoutContext::load(&data)?,
             agents,
         },
     )
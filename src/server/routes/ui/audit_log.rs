@@ -0,0 +1,47 @@
+use crate::audit;
+use crate::prelude::*;
+use crate::server::routes::ui::{render_template, LayoutContext};
+use crate::server::Data;
+use chrono::SecondsFormat;
+use http::Response;
+use hyper::Body;
+use std::sync::Arc;
+
+/// Number of entries displayed in the page.
+const ENTRIES_COUNT: usize = 200;
+
+#[derive(Serialize)]
+struct EntryData {
+    time: String,
+    actor: String,
+    action: &'static str,
+    experiment: Option<String>,
+    payload: String,
+}
+
+#[derive(Serialize)]
+struct ListContext {
+    layout: LayoutContext,
+    entries: Vec<EntryData>,
+}
+
+pub fn endpoint_list(data: Arc<Data>) -> Fallible<Response<Body>> {
+    let entries = audit::entries(&data.db, None, None, ENTRIES_COUNT)?
+        .into_iter()
+        .map(|entry| EntryData {
+            time: entry.created_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            actor: entry.actor,
+            action: entry.action.to_str(),
+            experiment: entry.experiment,
+            payload: entry.payload.to_string(),
+        })
+        .collect();
+
+    render_template(
+        "ui/audit-log.html",
+        &ListContext {
+            layout: LayoutContext::load(&data)?,
+            entries,
+        },
+    )
+}
@@ -1,11 +1,15 @@
-use crate::experiments::{Experiment, Mode, Status};
+use crate::audit;
+use crate::experiments::{Experiment, Mode, Status, ToolchainProgress};
 use crate::prelude::*;
+use crate::report::Comparison;
+use crate::server::api_types::ExperimentsQuery;
 use crate::server::routes::ui::{render_template, LayoutContext};
 use crate::server::{Data, HttpError};
 use chrono::{Duration, SecondsFormat, Utc};
 use chrono_humanize::{Accuracy, HumanTime, Tense};
 use http::Response;
 use hyper::Body;
+use std::cmp::Reverse;
 use std::sync::Arc;
 
 #[derive(Serialize)]
@@ -18,18 +22,25 @@ struct ExperimentData {
     requirement: Option<String>,
     progress: u8,
     priority: i32,
+    /// Priority after applying the aging policy, which differs from `priority` while queued.
+    effective_priority: i32,
+    tags: Vec<String>,
 }
 
 impl ExperimentData {
     fn new(data: &Data, experiment: &Experiment) -> Fallible<Self> {
         let (status_class, status_pretty, show_progress) = match experiment.status {
+            Status::Creating => ("", "Being created", false),
             Status::Queued => ("", "Queued", true),
             Status::Running => ("orange", "Running", true),
             Status::NeedsReport => ("orange", "Needs report", false),
             Status::Failed => ("red", "Failed", false),
+            Status::ToolchainInstallFailed => ("red", "Toolchain install failed", false),
             Status::GeneratingReport => ("orange", "Generating report", false),
             Status::ReportFailed => ("red", "Report failed", false),
             Status::Completed => ("green", "Completed", false),
+            Status::Cancelled => ("", "Cancelled", false),
+            Status::Archived => ("", "Archived", false),
         };
 
         Ok(ExperimentData {
@@ -42,10 +53,17 @@ impl ExperimentData {
                 Mode::CheckOnly => "cargo check",
                 Mode::Clippy => "cargo clippy",
                 Mode::Rustdoc => "cargo doc",
+                Mode::RustdocJson => "cargo doc (JSON)",
                 Mode::UnstableFeatures => "unstable features",
+                Mode::BuildBenches => "cargo bench --no-run",
+                Mode::Miri => "cargo miri test",
+                Mode::Fix => "cargo fix",
+                Mode::DocTest => "cargo test --doc",
             },
             assigned_to: experiment.assigned_to.as_ref().map(|a| a.to_string()),
             priority: experiment.priority,
+            effective_priority: experiment.effective_priority(&data.config.server.priority_aging),
+            tags: experiment.tags.names().to_vec(),
             requirement: experiment.requirement.clone(),
             progress: if show_progress {
                 experiment.progress(&data.db)?
@@ -56,13 +74,62 @@ impl ExperimentData {
     }
 }
 
+/// Statuses of the experiments shown in the queue.
+const QUEUE_STATUSES: &[Status] = &[
+    Status::Creating,
+    Status::Queued,
+    Status::Running,
+    Status::NeedsReport,
+    Status::Failed,
+    Status::ToolchainInstallFailed,
+    Status::GeneratingReport,
+    Status::ReportFailed,
+];
+
+/// Filters of the queue page. The form submits every field, leaving the unused ones empty.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct QueueQuery {
+    status: Option<String>,
+    mode: Option<String>,
+    requester: Option<String>,
+    toolchain: Option<String>,
+    tag: Option<String>,
+    search: Option<String>,
+}
+
+impl QueueQuery {
+    fn to_experiments_query(&self) -> Fallible<ExperimentsQuery> {
+        fn non_empty(value: &Option<String>) -> Option<&str> {
+            value.as_ref().map(|v| v.trim()).filter(|v| !v.is_empty())
+        }
+
+        Ok(ExperimentsQuery {
+            status: non_empty(&self.status).map(str::parse).transpose()?,
+            mode: non_empty(&self.mode).map(str::parse).transpose()?,
+            requester: non_empty(&self.requester).map(String::from),
+            toolchain: non_empty(&self.toolchain).map(String::from),
+            tag: non_empty(&self.tag).map(String::from),
+            search: non_empty(&self.search).map(String::from),
+        })
+    }
+}
+
 #[derive(Serialize)]
 struct ListContext {
     layout: LayoutContext,
     experiments: Vec<ExperimentData>,
+    filters: QueueQuery,
+    filtered: bool,
+    statuses: Vec<&'static str>,
+    modes: &'static [&'static str],
 }
 
-pub fn endpoint_queue(data: Arc<Data>) -> Fallible<Response<Body>> {
+pub fn endpoint_queue(query: QueueQuery, data: Arc<Data>) -> Fallible<Response<Body>> {
+    let filter = query.to_experiments_query()?;
+    let filtered = filter != ExperimentsQuery::default();
+    let requesters = audit::requesters(&data.db)?;
+
     let mut queued = Vec::new();
     let mut running = Vec::new();
     let mut needs_report = Vec::new();
@@ -70,22 +137,31 @@ pub fn endpoint_queue(data: Arc<Data>) -> Fallible<Response<Body>> {
     let mut generating_report = Vec::new();
     let mut report_failed = Vec::new();
 
-    for experiment in &Experiment::unfinished(&data.db)? {
+    // Show the queued experiments in the order they're going to be run
+    let aging = &data.config.server.priority_aging;
+    let mut unfinished = Experiment::unfinished(&data.db)?;
+    unfinished.sort_by_key(|ex| (Reverse(ex.effective_priority(aging)), ex.created_at));
+
+    for experiment in &unfinished {
         // Don't include completed experiments in the queue
         if experiment.status == Status::Completed {
             continue;
         }
+        let requester = requesters.get(&experiment.name).map(|r| r.as_str());
+        if !filter.matches(experiment, requester) {
+            continue;
+        }
 
         let ex = ExperimentData::new(&data, &experiment)?;
 
         match experiment.status {
-            Status::Queued => queued.push(ex),
+            Status::Creating | Status::Queued => queued.push(ex),
             Status::Running => running.push(ex),
             Status::NeedsReport => needs_report.push(ex),
-            Status::Failed => failed.push(ex),
+            Status::Failed | Status::ToolchainInstallFailed => failed.push(ex),
             Status::GeneratingReport => generating_report.push(ex),
             Status::ReportFailed => report_failed.push(ex),
-            Status::Completed => unreachable!(),
+            Status::Completed | Status::Cancelled | Status::Archived => unreachable!(),
         };
     }
 
@@ -100,8 +176,12 @@ pub fn endpoint_queue(data: Arc<Data>) -> Fallible<Response<Body>> {
     render_template(
         "ui/queue.html",
         &ListContext {
-            layout: LayoutContext::new(),
+            layout: LayoutContext::load(&data)?,
             experiments,
+            filtered,
+            filters: query,
+            statuses: QUEUE_STATUSES.iter().map(|s| s.to_str()).collect(),
+            modes: Mode::possible_values(),
         },
     )
 }
@@ -117,12 +197,19 @@ struct ExperimentExt {
     created_at: String,
     started_at: Option<String>,
     completed_at: Option<String>,
+    index_commit: Option<String>,
+    index_date: Option<String>,
 
     total_jobs: u32,
     completed_jobs: u32,
+    toolchains_progress: Vec<ToolchainProgress>,
+    results_so_far: Option<String>,
     duration: Option<String>,
     estimated_end: Option<String>,
     average_job_duration: Option<String>,
+    agent_time: Option<String>,
+    cpu_time: Option<String>,
+    stored_logs: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -133,7 +220,24 @@ struct ExperimentContext {
 
 pub fn endpoint_experiment(name: String, data: Arc<Data>) -> Fallible<Response<Body>> {
     if let Some(ex) = Experiment::get(&data.db, &name)? {
-        let (completed_jobs, total_jobs) = ex.raw_progress(&data.db)?;
+        let progress = ex.detailed_progress(&data.db)?;
+        let (completed_jobs, total_jobs) = (progress.completed_jobs, progress.total_jobs);
+
+        // Only the most interesting categories are shown while the experiment runs, the report
+        // contains all of them
+        let results_so_far = if progress.comparisons.is_empty() {
+            None
+        } else {
+            let count = |comparison: Comparison| {
+                progress.comparisons.get(&comparison).cloned().unwrap_or(0)
+            };
+            Some(format!(
+                "{} regressed, {} fixed, {} errors",
+                count(Comparison::Regressed),
+                count(Comparison::Fixed),
+                count(Comparison::Error),
+            ))
+        };
 
         let (duration, estimated_end, average_job_duration) = if completed_jobs > 0
             && total_jobs > 0
@@ -151,7 +255,7 @@ pub fn endpoint_experiment(name: String, data: Arc<Data>) -> Fallible<Response<B
                     let job_duration = total / completed_jobs as i32;
                     (
                         None,
-                        Some(job_duration * (total_jobs as i32 - completed_jobs as i32)),
+                        ex.estimated_remaining(completed_jobs, total_jobs),
                         Some(job_duration.num_seconds()),
                     )
                 };
@@ -173,6 +277,25 @@ pub fn endpoint_experiment(name: String, data: Arc<Data>) -> Fallible<Response<B
             (None, None, None)
         };
 
+        // Agents that don't report the resources used by the jobs aren't accounted for
+        let cost = ex.cost(&data.db)?;
+        let (agent_time, cpu_time) = if cost.jobs > 0 {
+            (
+                Some(format!("{:.1} hours", cost.agent_hours)),
+                Some(format!("{:.1} hours", cost.cpu_hours)),
+            )
+        } else {
+            (None, None)
+        };
+        let stored_logs = if cost.stored_bytes > 0 {
+            Some(format!(
+                "{:.1} MiB",
+                cost.stored_bytes as f64 / (1024.0 * 1024.0)
+            ))
+        } else {
+            None
+        };
+
         let experiment = ExperimentExt {
             common: ExperimentData::new(&data, &ex)?,
 
@@ -186,18 +309,28 @@ pub fn endpoint_experiment(name: String, data: Arc<Data>) -> Fallible<Response<B
             completed_at: ex
                 .completed_at
                 .map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            index_commit: ex.index_snapshot.as_ref().map(|s| s.commit.clone()),
+            index_date: ex
+                .index_snapshot
+                .as_ref()
+                .map(|s| s.date.to_rfc3339_opts(SecondsFormat::Secs, true)),
 
             total_jobs,
             completed_jobs,
+            toolchains_progress: progress.toolchains,
+            results_so_far,
             duration,
             estimated_end,
             average_job_duration,
+            agent_time,
+            cpu_time,
+            stored_logs,
         };
 
         render_template(
             "ui/experiment.html",
             &ExperimentContext {
-                layout: LayoutContext::new(),
+                layout: LayoutContext::load(&data)?,
                 experiment,
             },
         )
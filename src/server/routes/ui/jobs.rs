@@ -0,0 +1,64 @@
+use crate::prelude::*;
+use crate::server::jobs::{self, JobStatus};
+use crate::server::routes::ui::{render_template, LayoutContext};
+use crate::server::Data;
+use chrono::{DateTime, SecondsFormat, Utc};
+use http::Response;
+use hyper::Body;
+use std::sync::Arc;
+
+#[derive(Serialize)]
+struct JobData {
+    name: String,
+    status_class: &'static str,
+    status_pretty: &'static str,
+    runs: i32,
+    failures: i32,
+    last_started_at: Option<String>,
+    last_finished_at: Option<String>,
+    next_run_at: Option<String>,
+    last_error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ListContext {
+    layout: LayoutContext,
+    jobs: Vec<JobData>,
+}
+
+pub fn endpoint_list(data: Arc<Data>) -> Fallible<Response<Body>> {
+    let format_time = |time: DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    let jobs = jobs::all(&data.db)?
+        .into_iter()
+        .map(|job| {
+            let (status_class, status_pretty) = match job.status {
+                JobStatus::Scheduled => ("green", "Scheduled"),
+                JobStatus::Completed => ("green", "Completed"),
+                JobStatus::Running => ("orange", "Running"),
+                JobStatus::Retrying => ("orange", "Retrying"),
+                JobStatus::Failed => ("red", "Failed"),
+            };
+
+            JobData {
+                name: job.name,
+                status_class,
+                status_pretty,
+                runs: job.runs,
+                failures: job.failures,
+                last_started_at: job.last_started_at.map(format_time),
+                last_finished_at: job.last_finished_at.map(format_time),
+                next_run_at: job.next_run_at.map(format_time),
+                last_error: job.last_error,
+            }
+        })
+        .collect();
+
+    render_template(
+        "ui/jobs.html",
+        &ListContext {
+            layout: LayoutContext::load(&data)?,
+            jobs,
+        },
+    )
+}
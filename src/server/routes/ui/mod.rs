@@ -1,4 +1,5 @@
 use crate::assets;
+use crate::maintenance::{self, Maintenance};
 use crate::prelude::*;
 use crate::server::{Data, HttpError};
 use http::header::{HeaderValue, CONTENT_TYPE};
@@ -9,19 +10,31 @@ use std::sync::Arc;
 use warp::{self, Filter, Rejection};
 
 mod agents;
+mod audit_log;
 mod experiments;
+mod jobs;
 
 #[derive(Serialize)]
 struct LayoutContext {
     git_revision: Option<&'static str>,
+    maintenance: Option<Maintenance>,
 }
 
 impl LayoutContext {
     fn new() -> Self {
         LayoutContext {
             git_revision: crate::GIT_REVISION,
+            maintenance: None,
         }
     }
+
+    /// Create the layout context, including the banner shown during maintenance.
+    fn load(data: &Data) -> Fallible<Self> {
+        Ok(LayoutContext {
+            maintenance: maintenance::status(&data.db)?,
+            ..LayoutContext::new()
+        })
+    }
 }
 
 pub fn routes(
@@ -31,6 +44,7 @@ pub fn routes(
 
     let queue = warp::get2()
         .and(warp::path::end())
+        .and(warp::query::<experiments::QueueQuery>())
         .and(data_filter.clone())
         .map(experiments::endpoint_queue);
 
@@ -47,6 +61,18 @@ pub fn routes(
         .and(data_filter.clone())
         .map(agents::endpoint_list);
 
+    let jobs = warp::get2()
+        .and(warp::path("jobs"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .map(jobs::endpoint_list);
+
+    let audit_log = warp::get2()
+        .and(warp::path("audit-log"))
+        .and(warp::path::end())
+        .and(data_filter.clone())
+        .map(audit_log::endpoint_list);
+
     let assets = warp::get2()
         .and(warp::path("assets"))
         .and(warp::path::param())
@@ -60,6 +86,10 @@ pub fn routes(
                 .unify()
                 .or(agents)
                 .unify()
+                .or(jobs)
+                .unify()
+                .or(audit_log)
+                .unify()
                 .or(assets)
                 .unify(),
         )
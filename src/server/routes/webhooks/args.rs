@@ -1,4 +1,7 @@
-use crate::experiments::{Assignee, CapLints, CrateSelect, Mode};
+use crate::crates::{Edition, RustVersion};
+use crate::experiments::{
+    Assignee, BuildTargets, CapLints, CrateSelect, Mode, RustcWrapper, Tags, Targets, TestFlags,
+};
 use crate::toolchain::Toolchain;
 
 #[derive(Debug, Fail)]
@@ -12,6 +15,13 @@ pub enum CommandParseError {
     DuplicateKey(String),
     #[fail(display = "unknown key: {}", _0)]
     UnknownKey(String),
+    #[fail(display = "invalid TOML configuration: {}", _0)]
+    InvalidToml(String),
+    #[fail(
+        display = "unsupported value for {}: only strings, integers and booleans are allowed",
+        _0
+    )]
+    UnsupportedValue(String),
 }
 
 macro_rules! generate_parser {
@@ -44,12 +54,22 @@ macro_rules! generate_parser {
             $($variant($var_struct),)*
         }
 
-        #[allow(unused_variables, unused_mut)]
         impl FromStr for $enum {
             type Err = ::failure::Error;
 
             fn from_str(input: &str) -> Fallible<$enum> {
-                let mut parts = split_quoted(input)?.into_iter().peekable();
+                $enum::parse_with_args(input, Vec::new())
+            }
+        }
+
+        #[allow(unused_variables, unused_mut)]
+        impl $enum {
+            /// Parse a command, adding the `key=value` arguments provided outside of the command
+            /// line, which are not unquoted.
+            pub fn parse_with_args(input: &str, extra: Vec<String>) -> Fallible<$enum> {
+                let mut parts = split_quoted(input)?;
+                parts.extend(extra);
+                let mut parts = parts.into_iter().peekable();
                 Ok(match parts.peek().map(|s| s.as_str()) {
                     $(
                         Some($command) => generate_parser!(@parser
@@ -113,6 +133,45 @@ generate_parser!(pub enum Command {
         ignore_blacklist: Option<bool> = "ignore-blacklist",
         assign: Option<Assignee> = "assign",
         requirement: Option<String> = "requirement",
+        edition: Option<Edition> = "edition",
+        max_rust_version: Option<RustVersion> = "max-rust-version",
+        build_targets: Option<BuildTargets> = "build-targets",
+        test_flags: Option<TestFlags> = "test-flags",
+        dist_server: Option<String> = "dist-server",
+        rustc_wrapper: Option<RustcWrapper> = "rustc-wrapper",
+        timings: Option<bool> = "timings",
+        targets: Option<Targets> = "targets",
+        tags: Option<Tags> = "tags",
+    })
+
+    "check-regressed" => CheckRegressed(CheckRegressedArgs {
+        parent: Option<String> = "parent",
+        name: Option<String> = "name",
+        start: Option<Toolchain> = "start",
+        end: Option<Toolchain> = "end",
+        priority: Option<i32> = "p",
+    })
+
+    "retry-as" => RetryAs(RetryAsArgs {
+        name: Option<String> = "name",
+        new_name: Option<String> = "new-name",
+        start: Option<Toolchain> = "start",
+        end: Option<Toolchain> = "end",
+        mode: Option<Mode> = "mode",
+        crates: Option<CrateSelect> = "crates",
+        cap_lints: Option<CapLints> = "cap-lints",
+        priority: Option<i32> = "p",
+        ignore_blacklist: Option<bool> = "ignore-blacklist",
+        assign: Option<Assignee> = "assign",
+        requirement: Option<String> = "requirement",
+        edition: Option<Edition> = "edition",
+        max_rust_version: Option<RustVersion> = "max-rust-version",
+        build_targets: Option<BuildTargets> = "build-targets",
+        test_flags: Option<TestFlags> = "test-flags",
+        dist_server: Option<String> = "dist-server",
+        rustc_wrapper: Option<RustcWrapper> = "rustc-wrapper",
+        timings: Option<bool> = "timings",
+        targets: Option<Targets> = "targets",
     })
 
     "abort" => Abort(AbortArgs {
@@ -121,16 +180,37 @@ generate_parser!(pub enum Command {
 
     "ping" => Ping(PingArgs {})
 
+    "queue" => Queue(QueueArgs {})
+
+    "rename" => Rename(RenameArgs {
+        name: Option<String> = "name",
+        new_name: Option<String> = "new-name",
+    })
+
+    "tag" => Tag(TagArgs {
+        name: Option<String> = "name",
+        tags: Option<Tags> = "tags",
+    })
+
     "retry-report" => RetryReport(RetryReportArgs {
         name: Option<String> = "name",
     })
 
+    "regenerate-report" => RegenerateReport(RegenerateReportArgs {
+        name: Option<String> = "name",
+    })
+
     "retry" => Retry(RetryArgs {
         name: Option<String> = "name",
     })
 
     "reload-acl" => ReloadACL(ReloadACLArgs {})
 
+    "maintenance" => Maintenance(MaintenanceArgs {
+        enabled: Option<bool> = "enabled",
+        message: Option<String> = "message",
+    })
+
     _ => Edit(EditArgs {
         name: Option<String> = "name",
         start: Option<Toolchain> = "start",
@@ -142,12 +222,79 @@ generate_parser!(pub enum Command {
         ignore_blacklist: Option<bool> = "ignore-blacklist",
         assign: Option<Assignee> = "assign",
         requirement: Option<String> = "requirement",
+        edition: Option<Edition> = "edition",
+        max_rust_version: Option<RustVersion> = "max-rust-version",
+        build_targets: Option<BuildTargets> = "build-targets",
+        test_flags: Option<TestFlags> = "test-flags",
+        dist_server: Option<String> = "dist-server",
+        rustc_wrapper: Option<RustcWrapper> = "rustc-wrapper",
+        timings: Option<bool> = "timings",
+        targets: Option<Targets> = "targets",
     })
 });
 
+/// Parse the `run` command proposed to `check`, whose own name can be omitted. `None` is returned
+/// if the command isn't `check`.
+pub fn parse_check(input: &str, extra: Vec<String>) -> Fallible<Option<RunArgs>> {
+    let mut parts = input.trim().splitn(2, char::is_whitespace);
+    if parts.next() != Some("check") {
+        return Ok(None);
+    }
+    let proposed = parts.next().unwrap_or("").trim_start();
+    let proposed = if proposed == "run" || proposed.starts_with("run ") {
+        proposed.to_string()
+    } else {
+        format!("run {}", proposed)
+    };
+
+    match Command::parse_with_args(&proposed, extra)? {
+        Command::Run(args) => Ok(Some(args)),
+        _ => unreachable!("the proposed command is always `run`"),
+    }
+}
+
+/// Content of the fenced TOML block following a command, if any. Blank lines are allowed between
+/// the command and the block.
+pub fn config_block<'a>(lines: impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut lines = lines.skip_while(|line| line.trim().is_empty());
+    if lines.next()?.trim() != "```toml" {
+        return None;
+    }
+
+    // GitHub renders unterminated blocks up to the end of the comment
+    let mut block = String::new();
+    for line in lines {
+        if line.trim() == "```" {
+            break;
+        }
+        block.push_str(line);
+        block.push('\n');
+    }
+    Some(block)
+}
+
+/// Convert a TOML configuration block into `key=value` arguments of the command, using the same
+/// syntax for the values. Options with long values or spaces don't need any quoting this way.
+pub fn toml_arguments(block: &str) -> Fallible<Vec<String>> {
+    let table: ::toml::value::Table =
+        ::toml::from_str(block).map_err(|e| CommandParseError::InvalidToml(e.to_string()))?;
+
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let value = match value {
+            ::toml::Value::String(string) => string,
+            ::toml::Value::Integer(integer) => integer.to_string(),
+            ::toml::Value::Boolean(boolean) => boolean.to_string(),
+            _ => return Err(CommandParseError::UnsupportedValue(key).into()),
+        };
+        args.push(format!("{}={}", key, value));
+    }
+    Ok(args)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::CommandParseError;
+    use super::{config_block, parse_check, toml_arguments, CommandParseError};
 
     // Use a simpler parser for tests
     generate_parser!(pub enum TestCommand {
@@ -225,4 +372,77 @@ mod tests {
         test!(fail "foo arg4=42", CommandParseError::UnknownKey("arg4".into()));
         test!(fail "foo bar", CommandParseError::InvalidArgument("bar".into()));
     }
+
+    #[test]
+    fn test_config_block() {
+        let comment = "\n```toml\nname = \"foo\"\r\np = 2\n```\ntrailing text";
+        assert_eq!(
+            config_block(comment.lines()).unwrap(),
+            "name = \"foo\"\r\np = 2\n"
+        );
+
+        // Only a block right after the command is part of it
+        assert!(config_block("some text\n```toml\np = 2\n```".lines()).is_none());
+        assert!(config_block("```rust\nfn main() {}\n```".lines()).is_none());
+        assert!(config_block("".lines()).is_none());
+    }
+
+    #[test]
+    fn test_toml_arguments() {
+        let args = toml_arguments(
+            "arg2 = 'quoted \"value\" with spaces'\n\
+             arg1 = 98\n",
+        )
+        .unwrap();
+        assert_eq!(
+            TestCommand::parse_with_args("foo", args).unwrap(),
+            TestCommand::Foo(FooArgs {
+                arg1: Some(98),
+                arg2: Some("quoted \"value\" with spaces".into()),
+            })
+        );
+
+        // The keys are validated by the command parser
+        let args = toml_arguments("arg1 = 42").unwrap();
+        assert_eq!(
+            TestCommand::parse_with_args("foo arg1=98", args)
+                .unwrap_err()
+                .downcast_ref(),
+            Some(&CommandParseError::DuplicateKey("arg1".into()))
+        );
+
+        assert_eq!(
+            toml_arguments("arg1 = [1, 2]").unwrap_err().downcast_ref(),
+            Some(&CommandParseError::UnsupportedValue("arg1".into()))
+        );
+        assert!(toml_arguments("arg1 = ").is_err());
+    }
+
+    #[test]
+    fn test_parse_check() {
+        let args = parse_check("check run name=foo p=2", Vec::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.name, Some("foo".into()));
+        assert_eq!(args.priority, Some(2));
+
+        // The name of the `run` command is optional
+        let args = parse_check("check name=foo", vec!["p=2".into()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.name, Some("foo".into()));
+        assert_eq!(args.priority, Some(2));
+        assert!(parse_check("check", Vec::new()).unwrap().is_some());
+
+        assert!(parse_check("check-regressed", Vec::new())
+            .unwrap()
+            .is_none());
+        assert!(parse_check("run name=foo", Vec::new()).unwrap().is_none());
+        assert_eq!(
+            parse_check("check run foo=bar", Vec::new())
+                .unwrap_err()
+                .downcast_ref(),
+            Some(&CommandParseError::UnknownKey("foo".into()))
+        );
+    }
 }
@@ -1,15 +1,24 @@
 use crate::actions::{self, Action, ActionsCtx};
+use crate::audit::{self, Actor, AuditAction};
 use crate::db::{Database, QueryUtils};
-use crate::experiments::{CapLints, CrateSelect, Experiment, GitHubIssue, Mode, Status};
+use crate::experiments::{
+    BuildTargets, CapLints, CrateSelect, Experiment, GitHubIssue, Mode, Status, DEFAULT_NAMESPACE,
+};
+use crate::maintenance;
 use crate::prelude::*;
 use crate::server::github::{Issue, Repository};
 use crate::server::messages::{Label, Message};
+use crate::server::reports;
 use crate::server::routes::webhooks::args::{
-    AbortArgs, EditArgs, RetryArgs, RetryReportArgs, RunArgs,
+    AbortArgs, CheckRegressedArgs, Command, EditArgs, MaintenanceArgs, RegenerateReportArgs,
+    RenameArgs, RetryArgs, RetryAsArgs, RetryReportArgs, RunArgs, TagArgs,
 };
 use crate::server::Data;
 use crate::toolchain::Toolchain;
+use chrono_humanize::{Accuracy, HumanTime, Tense};
 use rustwide::Toolchain as RustwideToolchain;
+use serde_json::json;
+use std::str::FromStr;
 
 pub fn ping(data: &Data, issue: &Issue) -> Fallible<()> {
     Message::new()
@@ -19,68 +28,130 @@ pub fn ping(data: &Data, issue: &Issue) -> Fallible<()> {
     Ok(())
 }
 
-pub fn run(
-    host: &str,
+/// Local sources are on the disk of whoever defines the experiment, so they're only available
+/// from the CLI.
+fn check_crate_select(crates: &Option<CrateSelect>) -> Fallible<()> {
+    if let Some(CrateSelect::LocalSource(_)) = crates {
+        bail!("local paths and git URLs can only be tested from the CLI");
+    }
+    Ok(())
+}
+
+/// Configure the experiment requested by a `run` command, named `name`. The try build the
+/// toolchains were detected from is also returned, if any.
+fn run_experiment(
     data: &Data,
     repo: &Repository,
     issue: &Issue,
-    args: RunArgs,
-) -> Fallible<()> {
-    let name = setup_run_name(&data.db, issue, args.name)?;
-
+    name: String,
+    mut args: RunArgs,
+) -> Fallible<(actions::CreateExperiment, Option<String>)> {
     // Autodetect toolchains only if none of them was specified
-    let (mut detected_start, mut detected_end, mut try_build) = (None, None, None);
+    let mut try_build = None;
     if args.start.is_none() && args.end.is_none() {
         if let Some(build) =
             crate::server::try_builds::get_sha(&data.db, &repo.full_name, issue.number)?
         {
             try_build = Some(build.merge_sha.clone());
-            detected_start = Some(Toolchain {
+            args.start = Some(Toolchain {
                 source: RustwideToolchain::CI {
                     sha: build.base_sha.into(),
                     alt: true,
                 },
                 rustflags: None,
-                ci_try: false,
-            });
-            detected_end = Some(Toolchain {
-                source: RustwideToolchain::CI {
-                    sha: build.merge_sha.into(),
-                    alt: true,
-                },
-                rustflags: None,
-                ci_try: true,
+                rustdocflags: None,
+                env: Vec::new(),
+                cargo: None,
+                cargo_flags: Vec::new(),
+                linker: None,
+                cc: None,
+                target: None,
+                custom: None,
             });
+            args.end = Some(Toolchain::try_build(&build.merge_sha));
+        }
+    }
+
+    let github_issue = GitHubIssue {
+        api_url: issue.url.clone(),
+        html_url: issue.html_url.clone(),
+        number: issue.number,
+    };
+    let experiment = create_experiment(name, args, Some(github_issue))?;
+    Ok((experiment, try_build))
+}
+
+/// Configure an experiment from the arguments of the `run` command in `template`, not tied to any
+/// GitHub issue.
+pub fn experiment_from_template(
+    name: String,
+    template: &str,
+) -> Fallible<actions::CreateExperiment> {
+    match Command::from_str(&format!("run {}", template))? {
+        Command::Run(args) => {
+            check_crate_select(&args.crates)?;
+            create_experiment(name, args, None)
         }
+        _ => bail!("the template must contain the arguments of `run`"),
     }
+}
 
-    // Make crater runs created via webhook require linux by default.
-    let requirement = args.requirement.unwrap_or_else(|| "linux".to_string());
+/// Configure the experiment described by the arguments of a `run` command.
+fn create_experiment(
+    name: String,
+    args: RunArgs,
+    github_issue: Option<GitHubIssue>,
+) -> Fallible<actions::CreateExperiment> {
+    // Make crater runs created by the server require linux by default, unless the targets need
+    // agents running a specific OS.
+    let targets = args.targets.unwrap_or_default();
+    let requirement = if args.requirement.is_none() && targets.required_os().is_empty() {
+        Some("linux".to_string())
+    } else {
+        args.requirement
+    };
 
-    actions::CreateExperiment {
-        name: name.clone(),
-        toolchains: [
+    Ok(actions::CreateExperiment {
+        name,
+        namespace: DEFAULT_NAMESPACE.to_string(),
+        toolchains: vec![
             args.start
-                .or(detected_start)
                 .ok_or_else(|| err_msg("missing start toolchain"))?,
-            args.end
-                .or(detected_end)
-                .ok_or_else(|| err_msg("missing end toolchain"))?,
+            args.end.ok_or_else(|| err_msg("missing end toolchain"))?,
         ],
         mode: args.mode.unwrap_or(Mode::BuildAndTest),
         crates: args.crates.unwrap_or(CrateSelect::Full),
         cap_lints: args.cap_lints.unwrap_or(CapLints::Forbid),
         priority: args.priority.unwrap_or(0),
-        github_issue: Some(GitHubIssue {
-            api_url: issue.url.clone(),
-            html_url: issue.html_url.clone(),
-            number: issue.number,
-        }),
+        github_issue,
         ignore_blacklist: args.ignore_blacklist.unwrap_or(false),
         assign: args.assign,
-        requirement: Some(requirement),
-    }
-    .apply(&ActionsCtx::new(&data.db, &data.config))?;
+        requirement,
+        edition: args.edition,
+        max_rust_version: args.max_rust_version,
+        build_targets: args.build_targets.unwrap_or(BuildTargets::Default),
+        test_flags: args.test_flags.unwrap_or_default(),
+        dist_server: args.dist_server,
+        rustc_wrapper: args.rustc_wrapper,
+        timings: args.timings.unwrap_or(false),
+        targets,
+        tags: args.tags.unwrap_or_default(),
+    })
+}
+
+pub fn run(
+    host: &str,
+    data: &Data,
+    actor: &Actor,
+    repo: &Repository,
+    issue: &Issue,
+    mut args: RunArgs,
+) -> Fallible<()> {
+    check_crate_select(&args.crates)?;
+    let name = setup_run_name(&data.db, issue, args.name.take())?;
+
+    let (experiment, try_build) = run_experiment(data, repo, issue, name.clone(), args)?;
+    experiment.apply(&ActionsCtx::new(&data.db, &data.config).with_actor(actor.clone()))?;
 
     let mut message = Message::new().line(
         "ok_hand",
@@ -89,6 +160,12 @@ pub fn run(
     if let Some(sha) = try_build {
         message = message.line("robot", format!("Automatically detected try build {}", sha));
     }
+    if let Some(position) = queue_position(&data.db, &name)? {
+        message = message.line(
+            "hourglass",
+            format!("The experiment is #{} in the queue.", position),
+        );
+    }
     message
         .line(
             "mag",
@@ -101,7 +178,306 @@ pub fn run(
     Ok(())
 }
 
-pub fn edit(data: &Data, issue: &Issue, args: EditArgs) -> Fallible<()> {
+/// Dry run of the `run` command: validate it and describe the experiment it would queue, without
+/// creating anything.
+pub fn check(data: &Data, repo: &Repository, issue: &Issue, mut args: RunArgs) -> Fallible<()> {
+    check_crate_select(&args.crates)?;
+    // The name isn't stored, as no experiment is created
+    let name = match args.name.take() {
+        Some(name) => name,
+        None => generate_new_experiment_name(&data.db, issue)?,
+    };
+    let (experiment, try_build) = run_experiment(data, repo, issue, name.clone(), args)?;
+    let preview = experiment.preview(&ActionsCtx::new(&data.db, &data.config))?;
+
+    let mut message = Message::new().line(
+        "white_check_mark",
+        format!(
+            "The command is valid: it would queue the experiment **`{}`**.",
+            name
+        ),
+    );
+    if let Some(sha) = try_build {
+        message = message.line("robot", format!("Automatically detected try build {}", sha));
+    }
+    message = message
+        .line(
+            "wrench",
+            format!(
+                "Toolchains `{}` and `{}`, in `{}` mode.",
+                experiment.toolchains[0],
+                experiment.toolchains[experiment.toolchains.len() - 1],
+                experiment.mode
+            ),
+        )
+        .line(
+            "package",
+            format!(
+                "{} crates selected by `{}`, {} of which would be skipped.",
+                preview.crates, experiment.crates, preview.skipped
+            ),
+        );
+    if let Some(requirement) = preview.requirement {
+        message = message.line(
+            "computer",
+            format!("Only agents matching `{}` would run it.", requirement),
+        );
+    }
+    message
+        .note(
+            "information_source",
+            "Nothing was created: send the same command with `run` instead of `check` to queue \
+             the experiment.",
+        )
+        .send(&issue.url, data)?;
+
+    Ok(())
+}
+
+/// Queue a follow-up experiment testing again only the crates that regressed in another one, with
+/// the same configuration and, unless they're provided, the same toolchains.
+pub fn check_regressed(
+    host: &str,
+    data: &Data,
+    actor: &Actor,
+    issue: &Issue,
+    args: CheckRegressedArgs,
+) -> Fallible<()> {
+    let parent_name = get_name(&data.db, issue, args.parent)?;
+    let parent = if let Some(parent) = Experiment::get(&data.db, &parent_name)? {
+        parent
+    } else {
+        bail!("an experiment named **`{}`** doesn't exist!", parent_name);
+    };
+    let name = setup_run_name(&data.db, issue, args.name)?;
+
+    // The intermediate toolchains are only meaningful between the toolchains of the parent
+    let toolchains = match (args.start, args.end) {
+        (None, None) => parent.toolchains,
+        (start, end) => vec![
+            start.unwrap_or_else(|| parent.start_toolchain().clone()),
+            end.unwrap_or_else(|| parent.end_toolchain().clone()),
+        ],
+    };
+    actions::CreateExperiment {
+        name: name.clone(),
+        namespace: DEFAULT_NAMESPACE.to_string(),
+        toolchains,
+        mode: parent.mode,
+        crates: CrateSelect::Regressed(parent.name),
+        cap_lints: parent.cap_lints,
+        priority: args.priority.unwrap_or(parent.priority),
+        github_issue: Some(GitHubIssue {
+            api_url: issue.url.clone(),
+            html_url: issue.html_url.clone(),
+            number: issue.number,
+        }),
+        ignore_blacklist: parent.ignore_blacklist,
+        assign: None,
+        requirement: parent.requirement,
+        // The crates were already filtered when creating the parent experiment
+        edition: None,
+        max_rust_version: None,
+        build_targets: parent.build_targets,
+        test_flags: parent.test_flags,
+        dist_server: parent.dist_server,
+        rustc_wrapper: parent.rustc_wrapper,
+        timings: parent.timings,
+        targets: parent.targets,
+        tags: parent.tags,
+    }
+    .apply(&ActionsCtx::new(&data.db, &data.config).with_actor(actor.clone()))?;
+
+    let crates = Experiment::get(&data.db, &name)?
+        .ok_or_else(|| err_msg("missing experiment"))?
+        .get_crates(&data.db)?;
+    Message::new()
+        .line(
+            "ok_hand",
+            format!(
+                "Experiment **`{}`** created and queued, testing again the {} crates that \
+                 regressed in **`{}`**.",
+                name,
+                crates.len(),
+                parent_name
+            ),
+        )
+        .line(
+            "mag",
+            format!(
+                "You can check out [the queue](https://{}) and [this experiment's details](https://{0}/ex/{1}).",
+                host, name
+            ),
+        )
+        .set_label(Label::ExperimentQueued)
+        .send(&issue.url, data)?;
+
+    Ok(())
+}
+
+/// Queue a new experiment with the configuration and the crates of another one, changing only the
+/// provided arguments.
+pub fn retry_as(
+    host: &str,
+    data: &Data,
+    actor: &Actor,
+    issue: &Issue,
+    args: RetryAsArgs,
+) -> Fallible<()> {
+    check_crate_select(&args.crates)?;
+    let source = get_name(&data.db, issue, args.name)?;
+    if !Experiment::exists(&data.db, &source)? {
+        bail!("an experiment named **`{}`** doesn't exist!", source);
+    }
+    let name = setup_run_name(&data.db, issue, args.new_name)?;
+
+    actions::CloneExperiment {
+        name: source.clone(),
+        new_name: name.clone(),
+        github_issue: Some(GitHubIssue {
+            api_url: issue.url.clone(),
+            html_url: issue.html_url.clone(),
+            number: issue.number,
+        }),
+        toolchains: [args.start, args.end],
+        crates: args.crates,
+        mode: args.mode,
+        cap_lints: args.cap_lints,
+        priority: args.priority,
+        ignore_blacklist: args.ignore_blacklist,
+        assign: args.assign,
+        requirement: args.requirement,
+        edition: args.edition,
+        max_rust_version: args.max_rust_version,
+        build_targets: args.build_targets,
+        test_flags: args.test_flags,
+        dist_server: args.dist_server,
+        rustc_wrapper: args.rustc_wrapper,
+        timings: args.timings,
+        targets: args.targets,
+    }
+    .apply(&ActionsCtx::new(&data.db, &data.config).with_actor(actor.clone()))?;
+
+    Message::new()
+        .line(
+            "ok_hand",
+            format!(
+                "Experiment **`{}`** created and queued, with the configuration of **`{}`**.",
+                name, source
+            ),
+        )
+        .line(
+            "mag",
+            format!(
+                "You can check out [the queue](https://{}) and [this experiment's details](https://{0}/ex/{1}).",
+                host, name
+            ),
+        )
+        .set_label(Label::ExperimentQueued)
+        .send(&issue.url, data)?;
+
+    Ok(())
+}
+
+/// Queue the experiment configured in `server.try-experiments` for a pull request whose try
+/// build just completed, as if the `run` command was sent with the template as its arguments.
+pub fn try_experiment(
+    host: &str,
+    data: &Data,
+    actor: &Actor,
+    repo: &Repository,
+    issue: &Issue,
+    template: &str,
+) -> Fallible<()> {
+    // A new try build of the same PR shouldn't queue another experiment while one is pending
+    let pending = Experiment::unfinished(&data.db)?.into_iter().any(|ex| {
+        ex.github_issue
+            .map(|gh| gh.api_url == issue.url)
+            .unwrap_or(false)
+    });
+    if pending {
+        info!(
+            "not queueing an experiment for {}#{}, one is already pending",
+            repo.full_name, issue.number
+        );
+        return Ok(());
+    }
+
+    match Command::from_str(&format!("run {}", template))
+        .with_context(|_| "failed to parse the template of the try experiments")?
+    {
+        Command::Run(args) => run(host, data, actor, repo, issue, args),
+        _ => bail!("the template of the try experiments must contain the arguments of `run`"),
+    }
+}
+
+/// Position of a queued experiment among the ones waiting for an agent, starting from 1.
+fn queue_position(db: &Database, name: &str) -> Fallible<Option<usize>> {
+    Ok(Experiment::unfinished(db)?
+        .into_iter()
+        .filter(|ex| ex.status == Status::Queued)
+        .position(|ex| ex.name == name)
+        .map(|idx| idx + 1))
+}
+
+pub fn queue(host: &str, data: &Data, issue: &Issue) -> Fallible<()> {
+    let message = if let Some(table) = queue_table(host, &data.db)? {
+        Message::new().line("hourglass", format!("Current queue:\n\n{}", table))
+    } else {
+        Message::new().line("zzz", "No experiments are running or queued.")
+    };
+    message
+        .line(
+            "mag",
+            format!("You can check out [the full queue](https://{}).", host),
+        )
+        .send(&issue.url, data)?;
+
+    Ok(())
+}
+
+/// Render the running and queued experiments as a Markdown table, in the order the agents pick
+/// them up.
+fn queue_table(host: &str, db: &Database) -> Fallible<Option<String>> {
+    let mut rows = Vec::new();
+    let mut position = 0;
+    for ex in Experiment::unfinished(db)? {
+        let (status, progress, eta) = match ex.status {
+            Status::Running => {
+                let (completed_jobs, total_jobs) = ex.raw_progress(db)?;
+                let eta = ex
+                    .estimated_remaining(completed_jobs, total_jobs)
+                    .map(|eta| HumanTime::from(eta).to_text_en(Accuracy::Rough, Tense::Present))
+                    .unwrap_or_else(|| "-".into());
+                ("running".into(), format!("{}%", ex.progress(db)?), eta)
+            }
+            Status::Queued => {
+                position += 1;
+                (format!("queued #{}", position), "-".into(), "-".into())
+            }
+            _ => continue,
+        };
+        rows.push(format!(
+            "| [`{name}`](https://{host}/ex/{name}) | {} | {} | {} |",
+            status,
+            progress,
+            eta,
+            name = ex.name,
+            host = host,
+        ));
+    }
+
+    if rows.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "| Experiment | Status | Progress | ETA |\n|---|---|---|---|\n{}",
+        rows.join("\n")
+    )))
+}
+
+pub fn edit(data: &Data, actor: &Actor, issue: &Issue, args: EditArgs) -> Fallible<()> {
+    check_crate_select(&args.crates)?;
     let name = get_name(&data.db, issue, args.name)?;
 
     actions::EditExperiment {
@@ -114,8 +490,16 @@ pub fn edit(data: &Data, issue: &Issue, args: EditArgs) -> Fallible<()> {
         ignore_blacklist: args.ignore_blacklist,
         assign: args.assign,
         requirement: args.requirement,
+        edition: args.edition,
+        max_rust_version: args.max_rust_version,
+        build_targets: args.build_targets,
+        test_flags: args.test_flags,
+        dist_server: args.dist_server,
+        rustc_wrapper: args.rustc_wrapper,
+        timings: args.timings,
+        targets: args.targets,
     }
-    .apply(&ActionsCtx::new(&data.db, &data.config))?;
+    .apply(&ActionsCtx::new(&data.db, &data.config).with_actor(actor.clone()))?;
 
     Message::new()
         .line(
@@ -127,7 +511,59 @@ pub fn edit(data: &Data, issue: &Issue, args: EditArgs) -> Fallible<()> {
     Ok(())
 }
 
-pub fn retry_report(data: &Data, issue: &Issue, args: RetryReportArgs) -> Fallible<()> {
+pub fn rename(data: &Data, actor: &Actor, issue: &Issue, args: RenameArgs) -> Fallible<()> {
+    let name = get_name(&data.db, issue, args.name)?;
+    let new_name = match args.new_name {
+        Some(new_name) => new_name,
+        None => bail!("missing the new name of the experiment (`new-name`)"),
+    };
+
+    actions::RenameExperiment {
+        name: name.clone(),
+        new_name: new_name.clone(),
+    }
+    .apply(&ActionsCtx::new(&data.db, &data.config).with_actor(actor.clone()))?;
+    // Renaming a completed experiment queues its report again, to publish it at the new path
+    data.jobs.trigger(reports::JOB);
+
+    Message::new()
+        .line(
+            "memo",
+            format!("Experiment **`{}`** renamed to **`{}`**.", name, new_name),
+        )
+        .send(&issue.url, data)?;
+
+    Ok(())
+}
+
+pub fn tag(data: &Data, actor: &Actor, issue: &Issue, args: TagArgs) -> Fallible<()> {
+    let name = get_name(&data.db, issue, args.name)?;
+    let tags = match args.tags {
+        Some(tags) => tags,
+        None => bail!("missing the tags of the experiment (`tags`)"),
+    };
+
+    let message = if tags.is_empty() {
+        format!("Tags of experiment **`{}`** removed.", name)
+    } else {
+        format!("Experiment **`{}`** tagged with `{}`.", name, tags)
+    };
+    actions::TagExperiment { name, tags }
+        .apply(&ActionsCtx::new(&data.db, &data.config).with_actor(actor.clone()))?;
+
+    Message::new()
+        .line("label", message)
+        .send(&issue.url, data)?;
+
+    Ok(())
+}
+
+pub fn retry_report(
+    data: &Data,
+    actor: &Actor,
+    issue: &Issue,
+    args: RetryReportArgs,
+) -> Fallible<()> {
     let name = get_name(&data.db, issue, args.name)?;
 
     if let Some(mut experiment) = Experiment::get(&data.db, &name)? {
@@ -139,7 +575,14 @@ pub fn retry_report(data: &Data, issue: &Issue, args: RetryReportArgs) -> Fallib
         }
 
         experiment.set_status(&data.db, Status::NeedsReport)?;
-        data.reports_worker.wake();
+        audit::record(
+            &data.db,
+            actor,
+            AuditAction::RetryReport,
+            Some(&name),
+            &json!({}),
+        )?;
+        data.jobs.trigger(reports::JOB);
 
         Message::new()
             .line(
@@ -155,16 +598,50 @@ pub fn retry_report(data: &Data, issue: &Issue, args: RetryReportArgs) -> Fallib
     }
 }
 
-pub fn retry(data: &Data, issue: &Issue, args: RetryArgs) -> Fallible<()> {
+pub fn regenerate_report(
+    data: &Data,
+    actor: &Actor,
+    issue: &Issue,
+    args: RegenerateReportArgs,
+) -> Fallible<()> {
+    let name = get_name(&data.db, issue, args.name)?;
+
+    actions::RegenerateReport { name: name.clone() }
+        .apply(&ActionsCtx::new(&data.db, &data.config).with_actor(actor.clone()))?;
+    data.jobs.trigger(reports::JOB);
+
+    Message::new()
+        .line(
+            "hammer_and_wrench",
+            format!(
+                "The report of **`{}`** will be generated again from the stored results.",
+                name
+            ),
+        )
+        .set_label(Label::ExperimentQueued)
+        .send(&issue.url, data)?;
+
+    Ok(())
+}
+
+pub fn retry(data: &Data, actor: &Actor, issue: &Issue, args: RetryArgs) -> Fallible<()> {
     let name = get_name(&data.db, issue, args.name)?;
 
     if let Some(mut experiment) = Experiment::get(&data.db, &name)? {
-        if experiment.status != Status::Failed {
-            bail!("Experiment **`{}`** didn't fail!", name);
+        match experiment.status {
+            Status::Failed | Status::ToolchainInstallFailed => {}
+            _ => bail!("Experiment **`{}`** didn't fail!", name),
         }
 
         experiment.set_status(&data.db, Status::Queued)?;
-        data.reports_worker.wake();
+        audit::record(
+            &data.db,
+            actor,
+            AuditAction::RetryExperiment,
+            Some(&name),
+            &json!({}),
+        )?;
+        data.jobs.trigger(reports::JOB);
 
         Message::new()
             .line(
@@ -180,14 +657,26 @@ pub fn retry(data: &Data, issue: &Issue, args: RetryArgs) -> Fallible<()> {
     }
 }
 
-pub fn abort(data: &Data, issue: &Issue, args: AbortArgs) -> Fallible<()> {
+pub fn abort(data: &Data, actor: &Actor, issue: &Issue, args: AbortArgs) -> Fallible<()> {
     let name = get_name(&data.db, issue, args.name)?;
+    let ctx = ActionsCtx::new(&data.db, &data.config).with_actor(actor.clone());
 
-    actions::DeleteExperiment { name: name.clone() }
-        .apply(&ActionsCtx::new(&data.db, &data.config))?;
+    // Running experiments are only cancelled, keeping the results recorded so far
+    let running = Experiment::get(&data.db, &name)?.map(|ex| ex.status) == Some(Status::Running);
+    let message = if running {
+        actions::CancelExperiment { name: name.clone() }.apply(&ctx)?;
+        Message::new()
+            .line("stop_sign", format!("Experiment **`{}`** cancelled!", name))
+            .line(
+                "hammer_and_wrench",
+                "The agents will stop running it, but its partial results are kept.",
+            )
+    } else {
+        actions::DeleteExperiment { name: name.clone() }.apply(&ctx)?;
+        Message::new().line("wastebasket", format!("Experiment **`{}`** deleted!", name))
+    };
 
-    Message::new()
-        .line("wastebasket", format!("Experiment **`{}`** deleted!", name))
+    message
         .set_label(Label::ExperimentCompleted)
         .send(&issue.url, data)?;
 
@@ -204,8 +693,42 @@ pub fn reload_acl(data: &Data, issue: &Issue) -> Fallible<()> {
     Ok(())
 }
 
+pub fn maintenance(
+    data: &Data,
+    actor: &Actor,
+    issue: &Issue,
+    args: MaintenanceArgs,
+) -> Fallible<()> {
+    if args.enabled.unwrap_or(true) {
+        maintenance::enable(&data.db, actor, args.message.as_ref().map(|m| m.as_str()))?;
+
+        Message::new()
+            .line(
+                "construction",
+                "Maintenance mode **enabled**: agents won't start new experiments and new \
+                 experiments can't be created.",
+            )
+            .send(&issue.url, data)?;
+    } else {
+        maintenance::disable(&data.db, actor)?;
+
+        Message::new()
+            .line("white_check_mark", "Maintenance mode **disabled**.")
+            .send(&issue.url, data)?;
+    }
+
+    Ok(())
+}
+
 fn get_name(db: &Database, issue: &Issue, name: Option<String>) -> Fallible<String> {
     if let Some(name) = name {
+        // Experiments outside the default namespace are stored as `namespace:name`
+        if name.contains(':') {
+            bail!(
+                "the bot can only manage experiments in the `{}` namespace",
+                DEFAULT_NAMESPACE
+            );
+        }
         store_experiment_name(db, issue, &name)?;
         Ok(name)
     } else if let Some(default) = default_experiment_name(db, issue)? {
@@ -272,12 +795,13 @@ fn generate_new_experiment_name(db: &Database, issue: &Issue) -> Fallible<String
 #[cfg(test)]
 mod tests {
     use super::{
-        default_experiment_name, generate_new_experiment_name, get_name, setup_run_name,
-        store_experiment_name,
+        default_experiment_name, generate_new_experiment_name, get_name, queue_position,
+        queue_table, setup_run_name, store_experiment_name,
     };
     use crate::actions::{self, Action, ActionsCtx};
     use crate::config::Config;
     use crate::db::Database;
+    use crate::experiments::{Experiment, Status};
     use crate::prelude::*;
     use crate::server::github;
 
@@ -479,4 +1003,53 @@ mod tests {
             "pr-12345-2"
         );
     }
+
+    #[test]
+    fn test_queue_position() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        // Experiments with a higher priority are run first
+        for &(name, priority) in &[("first", 3), ("second", 2), ("third", 1)] {
+            let mut create = actions::CreateExperiment::dummy(name);
+            create.priority = priority;
+            create.apply(&ctx).unwrap();
+        }
+        assert_eq!(queue_position(&db, "second").unwrap(), Some(2));
+
+        // Running experiments are not part of the queue anymore
+        let mut first = Experiment::get(&db, "first").unwrap().unwrap();
+        first.set_status(&db, Status::Running).unwrap();
+        assert_eq!(queue_position(&db, "second").unwrap(), Some(1));
+        assert_eq!(queue_position(&db, "first").unwrap(), None);
+    }
+
+    #[test]
+    fn test_queue_table() {
+        let db = Database::temp().unwrap();
+        let config = Config::default();
+        let ctx = ActionsCtx::new(&db, &config);
+
+        assert_eq!(queue_table("crater.example", &db).unwrap(), None);
+
+        for &(name, priority) in &[("first", 3), ("second", 2), ("third", 1)] {
+            let mut create = actions::CreateExperiment::dummy(name);
+            create.priority = priority;
+            create.apply(&ctx).unwrap();
+        }
+        let mut first = Experiment::get(&db, "first").unwrap().unwrap();
+        first.set_status(&db, Status::Running).unwrap();
+        let mut third = Experiment::get(&db, "third").unwrap().unwrap();
+        third.set_status(&db, Status::NeedsReport).unwrap();
+
+        // Nothing was completed yet, so there's no estimate for the running experiment
+        assert_eq!(
+            queue_table("crater.example", &db).unwrap().unwrap(),
+            "| Experiment | Status | Progress | ETA |\n\
+             |---|---|---|---|\n\
+             | [`first`](https://crater.example/ex/first) | running | 0% | - |\n\
+             | [`second`](https://crater.example/ex/second) | queued #1 | - | - |"
+        );
+    }
 }
@@ -1,31 +1,33 @@
 mod args;
 mod commands;
 
+pub use self::commands::experiment_from_template;
+
+use crate::audit::Actor;
 use crate::prelude::*;
 use crate::server::github::{EventIssueComment, Issue, Repository};
 use crate::server::messages::Message;
 use crate::server::routes::webhooks::args::Command;
+use crate::server::webhook_events::{self, EventStatus};
 use crate::server::Data;
+use crate::utils;
 use bytes::buf::Buf;
 use hmac::{Hmac, Mac};
 use http::{HeaderMap, Response, StatusCode};
 use hyper::Body;
 use serde_json;
-use std::str::FromStr;
 use std::sync::Arc;
 use warp::{self, filters::body::FullBody, Filter, Rejection};
 
-fn process_webhook(
-    payload: &[u8],
-    host: &str,
-    signature: &str,
-    event: &str,
-    data: &Data,
-) -> Fallible<()> {
-    if !verify_signature(&data.tokens.bot.webhooks_secret, payload, signature) {
-        bail!("invalid signature for the webhook!");
-    }
+/// Name of the job retrying the events that failed to be processed.
+pub const RETRY_JOB: &str = "webhook-retries";
+/// Name of the job removing the old processed events.
+pub const GC_JOB: &str = "gc";
 
+// Keep the processed events for a week, to be able to replay them
+const PROCESSED_EVENTS_DAYS: i64 = 7;
+
+fn process_webhook(payload: &[u8], host: &str, event: &str, data: &Data) -> Fallible<()> {
     match event {
         "ping" => info!("the webhook is configured correctly!"),
         "issue_comment" => {
@@ -36,7 +38,7 @@ fn process_webhook(
                 return Ok(());
             }
 
-            crate::server::try_builds::detect(
+            let try_build = crate::server::try_builds::detect(
                 &data.db,
                 &data.github,
                 &p.repository.full_name,
@@ -44,17 +46,27 @@ fn process_webhook(
                 &p.comment.body,
             )?;
 
-            if let Err(e) = process_command(
-                host,
-                &p.sender.login,
-                p.sender.id,
-                &p.comment.body,
-                &p.repository,
-                &p.issue,
-                data,
-            ) {
+            let res = if try_build.is_some() {
+                process_try_build(host, &p.sender.login, &p.repository, &p.issue, data)
+            } else {
+                process_command(
+                    host,
+                    &p.sender.login,
+                    p.sender.id,
+                    &p.comment.body,
+                    &p.repository,
+                    &p.issue,
+                    data,
+                )
+            };
+            if let Err(e) = res {
+                // Include the causes, to explain what's wrong with the command
+                let mut error = e.to_string();
+                for cause in e.iter_causes() {
+                    error.push_str(&format!(": {}", cause));
+                }
                 Message::new()
-                    .line("rotating_light", format!("**Error:** {}", e))
+                    .line("rotating_light", format!("**Error:** {}", error))
                     .note(
                         "sos",
                         "If you have any trouble with Crater please ping **`@rust-lang/infra`**!",
@@ -68,6 +80,30 @@ fn process_webhook(
     Ok(())
 }
 
+fn process_try_build(
+    host: &str,
+    sender: &str,
+    repo: &Repository,
+    issue: &Issue,
+    data: &Data,
+) -> Fallible<()> {
+    let config = match data.config.server.try_experiments {
+        Some(ref config) => config,
+        None => return Ok(()),
+    };
+    // Only the pull requests explicitly opted in get an experiment
+    if issue.pull_request.is_none() || !issue.labels.iter().any(|l| l.name == config.label) {
+        return Ok(());
+    }
+
+    info!(
+        "try build of {}#{} completed, queueing an experiment",
+        repo.full_name, issue.number
+    );
+    let actor = Actor::GitHub(sender.to_string());
+    commands::try_experiment(host, data, &actor, repo, issue, &config.template)
+}
+
 fn process_command(
     host: &str,
     sender: &str,
@@ -78,7 +114,8 @@ fn process_command(
     data: &Data,
 ) -> Fallible<()> {
     let start = format!("@{} ", data.bot_username);
-    for line in body.lines() {
+    let mut lines = body.lines();
+    while let Some(line) = lines.next() {
         if !line.starts_with(&start) {
             continue;
         }
@@ -107,38 +144,81 @@ fn process_command(
         }
 
         info!("user @{} sent command: {}", sender, command);
+        let actor = Actor::GitHub(sender.to_string());
+
+        // Options can also be provided in a TOML block following the command
+        let extra_args = match args::config_block(lines.clone()) {
+            Some(block) => args::toml_arguments(&block)?,
+            None => Vec::new(),
+        };
+
+        // `check` is a dry run of the `run` command it's followed by
+        if let Some(args) = args::parse_check(command, extra_args.clone())
+            .with_context(|_| "failed to parse the command")?
+        {
+            commands::check(data, repo, issue, args)?;
+            break;
+        }
 
-        let args: Command =
-            Command::from_str(command).with_context(|_| "failed to parse the command")?;
+        let args = Command::parse_with_args(command, extra_args)
+            .with_context(|_| "failed to parse the command")?;
 
         match args {
             Command::Ping(_) => {
                 commands::ping(data, issue)?;
             }
 
+            Command::Queue(_) => {
+                commands::queue(host, data, issue)?;
+            }
+
             Command::Run(args) => {
-                commands::run(host, data, repo, issue, args)?;
+                commands::run(host, data, &actor, repo, issue, args)?;
+            }
+
+            Command::CheckRegressed(args) => {
+                commands::check_regressed(host, data, &actor, issue, args)?;
+            }
+
+            Command::RetryAs(args) => {
+                commands::retry_as(host, data, &actor, issue, args)?;
             }
 
             Command::Edit(args) => {
-                commands::edit(data, issue, args)?;
+                commands::edit(data, &actor, issue, args)?;
+            }
+
+            Command::Rename(args) => {
+                commands::rename(data, &actor, issue, args)?;
+            }
+
+            Command::Tag(args) => {
+                commands::tag(data, &actor, issue, args)?;
             }
 
             Command::RetryReport(args) => {
-                commands::retry_report(data, issue, args)?;
+                commands::retry_report(data, &actor, issue, args)?;
+            }
+
+            Command::RegenerateReport(args) => {
+                commands::regenerate_report(data, &actor, issue, args)?;
             }
 
             Command::Retry(args) => {
-                commands::retry(data, issue, args)?;
+                commands::retry(data, &actor, issue, args)?;
             }
 
             Command::Abort(args) => {
-                commands::abort(data, issue, args)?;
+                commands::abort(data, &actor, issue, args)?;
             }
 
             Command::ReloadACL(_) => {
                 commands::reload_acl(data, issue)?;
             }
+
+            Command::Maintenance(args) => {
+                commands::maintenance(data, &actor, issue, args)?;
+            }
         }
 
         break;
@@ -184,6 +264,48 @@ fn verify_signature(secret: &str, payload: &[u8], raw_signature: &str) -> bool {
     mac.verify(&signature).is_ok()
 }
 
+/// Process a stored event, recording whether it needs to be retried.
+fn process_event(id: i64, payload: &[u8], host: &str, event: &str, data: &Data) -> Fallible<()> {
+    let res = process_webhook(payload, host, event, data);
+    match res {
+        Ok(()) => webhook_events::mark_processed(&data.db, id)?,
+        Err(ref err) => {
+            let status = webhook_events::mark_failed(&data.db, id, &err.to_string())?;
+            if status == EventStatus::Failed {
+                error!(
+                    "giving up on webhook event {} after {} attempts",
+                    id,
+                    webhook_events::MAX_ATTEMPTS
+                );
+            }
+        }
+    }
+    res
+}
+
+/// Retry the events that failed to be processed, along with the ones interrupted by the last
+/// restart of the server and the ones replayed from the CLI.
+pub fn retry_events(data: &Data) -> Fallible<()> {
+    for event in webhook_events::claim_pending(&data.db)? {
+        info!("retrying webhook event {} ({})", event.id, event.event);
+        let result = process_event(event.id, &event.payload, &event.host, &event.event, data)
+            .with_context(|_| format!("failed to retry webhook event {}", event.id));
+        if let Err(e) = result {
+            utils::report_failure(&e);
+        }
+    }
+    Ok(())
+}
+
+/// Remove the processed events old enough not to be worth replaying anymore.
+pub fn prune_events(data: &Data) -> Fallible<()> {
+    let pruned = webhook_events::prune_processed(&data.db, PROCESSED_EVENTS_DAYS)?;
+    if pruned > 0 {
+        info!("removed {} old webhook events", pruned);
+    }
+    Ok(())
+}
+
 fn receive_endpoint(data: Arc<Data>, headers: HeaderMap, body: FullBody) -> Fallible<()> {
     let signature = headers
         .get("X-Hub-Signature")
@@ -198,7 +320,15 @@ fn receive_endpoint(data: Arc<Data>, headers: HeaderMap, body: FullBody) -> Fall
         .and_then(|h| h.to_str().ok())
         .ok_or_else(|| err_msg("missing header Host\n"))?;
 
-    process_webhook(body.bytes(), host, signature, event, &data)
+    let payload = body.bytes();
+    if !verify_signature(&data.tokens.bot.webhooks_secret, payload, signature) {
+        bail!("invalid signature for the webhook!");
+    }
+
+    // Store the event before processing it, so it can be retried if the processing fails or is
+    // interrupted
+    let id = webhook_events::record(&data.db, event, host, payload)?;
+    process_event(id, payload, host, event, &data)
 }
 
 pub fn routes(
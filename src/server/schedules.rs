@@ -0,0 +1,299 @@
+use crate::actions::{Action, ActionsCtx, CreateExperiment};
+use crate::audit::Actor;
+use crate::config::{Config, ScheduleConfig};
+use crate::db::{Database, QueryUtils};
+use crate::experiments::Experiment;
+use crate::prelude::*;
+use crate::server::routes::webhooks::experiment_from_template;
+use crate::server::Data;
+use crate::toolchain;
+use crate::utils;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use std::collections::HashSet;
+
+/// Name of the job creating the experiments of the schedules.
+pub const JOB: &str = "schedules";
+
+/// Number of days searched for the nightly published before the latest one.
+const MAX_NIGHTLIES_GAP: i64 = 14;
+
+/// Dates of the nightlies replacing the `{nightly}` and `{previous-nightly}` placeholders.
+#[derive(Clone, Copy)]
+struct Nightlies {
+    latest: NaiveDate,
+    previous: NaiveDate,
+}
+
+impl Nightlies {
+    /// Find the latest nightly published on the dist server, and the last one published before
+    /// it. Nightlies are not published on the days their build failed.
+    fn fetch(config: &Config) -> Fallible<Self> {
+        let server = toolchain::dist_server(config);
+
+        let url = format!("{}/dist/channel-rust-nightly.toml", server);
+        let latest = manifest_date(&utils::http::get_sync(&url)?.text()?)
+            .with_context(|_| format!("invalid nightly manifest at {}", url))?;
+
+        for days in 1..=MAX_NIGHTLIES_GAP {
+            let date = latest - Duration::days(days);
+            if toolchain::nightly_published(server, date)? {
+                return Ok(Nightlies {
+                    latest,
+                    previous: date,
+                });
+            }
+        }
+        bail!(
+            "no nightly published in the {} days before nightly-{}",
+            MAX_NIGHTLIES_GAP,
+            latest
+        );
+    }
+}
+
+/// Get the date of the toolchain described by a dist manifest.
+fn manifest_date(manifest: &str) -> Fallible<NaiveDate> {
+    let manifest: ::toml::Value = ::toml::from_str(manifest)?;
+    let date = manifest
+        .get("date")
+        .and_then(|date| date.as_str())
+        .ok_or_else(|| err_msg("missing date"))?;
+    Ok(date.parse()?)
+}
+
+/// Whether the template of a schedule needs the dates of the nightlies.
+fn uses_nightlies(template: &str) -> bool {
+    template.contains("{nightly}") || template.contains("{previous-nightly}")
+}
+
+pub fn enabled(data: &Data) -> bool {
+    !data.config.server.schedules.is_empty()
+}
+
+/// Reject the schedules with the same name or an invalid template when the server starts, instead
+/// of the first time they're due.
+pub fn check(config: &Config) -> Fallible<()> {
+    let mut names = HashSet::new();
+    for schedule in &config.server.schedules {
+        if !names.insert(schedule.name.as_str()) {
+            bail!("duplicate schedule: {}", schedule.name);
+        }
+        // The nightlies aren't fetched when starting the server, any date checks the template
+        let today = Utc::now().date().naive_utc();
+        let nightlies = Nightlies {
+            latest: today,
+            previous: today - Duration::days(1),
+        };
+        experiment(schedule, Utc::now(), schedule.name.clone(), Some(nightlies))
+            .with_context(|_| format!("invalid template of the schedule {}", schedule.name))?;
+    }
+    Ok(())
+}
+
+pub fn create_scheduled(data: &Data) -> Fallible<()> {
+    create_due(&data.db, &data.config, Utc::now(), || {
+        Nightlies::fetch(&data.config)
+    })?;
+    Ok(())
+}
+
+/// Create the experiments of the schedules which were due since they last ran, returning their
+/// names. Schedules only start running once the server knows about them, and they create a
+/// single experiment even if they missed multiple runs (for example while the server was down).
+fn create_due<F: Fn() -> Fallible<Nightlies>>(
+    db: &Database,
+    config: &Config,
+    now: DateTime<Utc>,
+    fetch_nightlies: F,
+) -> Fallible<Vec<String>> {
+    let mut created = Vec::new();
+    for schedule in &config.server.schedules {
+        let last_run: Option<DateTime<Utc>> = db.get_row(
+            "SELECT last_run FROM schedules WHERE name = ?1;",
+            &[&schedule.name],
+            |row| row.get("last_run"),
+        )?;
+        let due = match last_run {
+            Some(last_run) => schedule
+                .cron
+                .next_after(last_run)
+                .map_or(false, |t| t <= now),
+            None => {
+                db.execute(
+                    "INSERT INTO schedules (name, last_run) VALUES (?1, ?2);",
+                    &[&schedule.name, &now],
+                )?;
+                false
+            }
+        };
+        if !due {
+            continue;
+        }
+
+        db.execute(
+            "UPDATE schedules SET last_run = ?1 WHERE name = ?2;",
+            &[&now, &schedule.name],
+        )?;
+        // A broken schedule shouldn't prevent the other ones from running
+        match create(db, config, schedule, now, &fetch_nightlies) {
+            Ok(name) => created.push(name),
+            Err(err) => utils::report_failure(
+                &err.context(format!("failed to run the schedule {}", schedule.name)),
+            ),
+        }
+    }
+    Ok(created)
+}
+
+fn create<F: Fn() -> Fallible<Nightlies>>(
+    db: &Database,
+    config: &Config,
+    schedule: &ScheduleConfig,
+    now: DateTime<Utc>,
+    fetch_nightlies: F,
+) -> Fallible<String> {
+    // Schedules running more than once a day get a numbered suffix after the first experiment
+    let base = format!("{}-{}", schedule.name, now.date().naive_utc());
+    let mut name = base.clone();
+    let mut suffix = 0;
+    while Experiment::exists(db, &name)? {
+        suffix += 1;
+        name = format!("{}-{}", base, suffix);
+    }
+
+    let nightlies = if uses_nightlies(&schedule.template) {
+        Some(fetch_nightlies()?)
+    } else {
+        None
+    };
+    experiment(schedule, now, name.clone(), nightlies)?
+        .apply(&ActionsCtx::new(db, config).with_actor(Actor::Server))?;
+    info!(
+        "created the experiment {} scheduled by {}",
+        name, schedule.name
+    );
+    Ok(name)
+}
+
+fn experiment(
+    schedule: &ScheduleConfig,
+    now: DateTime<Utc>,
+    name: String,
+    nightlies: Option<Nightlies>,
+) -> Fallible<CreateExperiment> {
+    let mut template = schedule
+        .template
+        .replace("{date}", &now.date().naive_utc().to_string());
+    if let Some(nightlies) = nightlies {
+        template = template
+            .replace("{nightly}", &format!("nightly-{}", nightlies.latest))
+            .replace(
+                "{previous-nightly}",
+                &format!("nightly-{}", nightlies.previous),
+            );
+    }
+    experiment_from_template(name, &template)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, create_due, manifest_date, Nightlies};
+    use crate::config::{Config, ScheduleConfig};
+    use crate::db::Database;
+    use crate::experiments::Experiment;
+    use crate::prelude::*;
+    use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+    fn time(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.ymd(2024, 1, day).and_hms(hour, 0, 0)
+    }
+
+    fn nightlies(latest: u32, previous: u32) -> impl Fn() -> Fallible<Nightlies> {
+        move || {
+            Ok(Nightlies {
+                latest: NaiveDate::from_ymd(2024, 1, latest),
+                previous: NaiveDate::from_ymd(2024, 1, previous),
+            })
+        }
+    }
+
+    #[test]
+    fn test_create_due() {
+        let db = Database::temp().unwrap();
+        let mut config = Config::default();
+        crate::crates::lists::setup_test_lists(&db, &config).unwrap();
+        config.server.schedules.push(ScheduleConfig {
+            name: "nightly-monitor".into(),
+            cron: "0 3 * * *".parse().unwrap(),
+            template: "start={previous-nightly} end={nightly} crates=local".into(),
+        });
+        check(&config).unwrap();
+
+        // Schedules only start running after the server first sees them
+        assert!(create_due(&db, &config, time(1, 12), nightlies(1, 0))
+            .unwrap()
+            .is_empty());
+        assert!(create_due(&db, &config, time(2, 2), nightlies(1, 0))
+            .unwrap()
+            .is_empty());
+
+        let created = create_due(&db, &config, time(2, 3), nightlies(2, 1)).unwrap();
+        assert_eq!(created, vec!["nightly-monitor-2024-01-02"]);
+        let ex = Experiment::get(&db, &created[0]).unwrap().unwrap();
+        assert_eq!(ex.toolchains[0].to_string(), "nightly-2024-01-01");
+        assert_eq!(ex.toolchains[1].to_string(), "nightly-2024-01-02");
+        assert!(ex.github_issue.is_none());
+
+        // Missed runs create a single experiment
+        assert!(create_due(&db, &config, time(2, 4), nightlies(2, 1))
+            .unwrap()
+            .is_empty());
+        let created = create_due(&db, &config, time(5, 12), nightlies(5, 4)).unwrap();
+        assert_eq!(created, vec!["nightly-monitor-2024-01-05"]);
+
+        // The latest published nightlies are used, even if they're not from the last days
+        config.server.schedules[0].cron = "0 * * * *".parse().unwrap();
+        let created = create_due(&db, &config, time(5, 13), nightlies(4, 2)).unwrap();
+        assert_eq!(created, vec!["nightly-monitor-2024-01-05-1"]);
+        let ex = Experiment::get(&db, &created[0]).unwrap().unwrap();
+        assert_eq!(ex.toolchains[0].to_string(), "nightly-2024-01-02");
+        assert_eq!(ex.toolchains[1].to_string(), "nightly-2024-01-04");
+
+        // The nightlies are only fetched when the template needs them
+        config.server.schedules[0].template = "start=stable end=beta crates=local".into();
+        let created = create_due(&db, &config, time(5, 14), || {
+            Err(err_msg("the nightlies shouldn't be fetched"))
+        })
+        .unwrap();
+        assert_eq!(created, vec!["nightly-monitor-2024-01-05-2"]);
+    }
+
+    #[test]
+    fn test_manifest_date() {
+        let manifest = "manifest-version = \"2\"\ndate = \"2024-01-04\"\n\n[pkg.rustc]\n";
+        assert_eq!(
+            manifest_date(manifest).unwrap(),
+            NaiveDate::from_ymd(2024, 1, 4)
+        );
+        assert!(manifest_date("manifest-version = \"2\"").is_err());
+        assert!(manifest_date("date = \"yesterday\"").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let mut config = Config::default();
+        let schedule = ScheduleConfig {
+            name: "broken".into(),
+            cron: "0 3 * * *".parse().unwrap(),
+            template: "mode=check-only".into(),
+        };
+        config.server.schedules.push(schedule.clone());
+        assert!(check(&config).is_err());
+
+        config.server.schedules[0].template = "start=stable end=beta".into();
+        check(&config).unwrap();
+        config.server.schedules.push(schedule);
+        config.server.schedules[1].template = "start=stable end=beta".into();
+        assert!(check(&config).is_err());
+    }
+}
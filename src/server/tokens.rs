@@ -1,6 +1,9 @@
+use crate::experiments::{is_valid_namespace, DEFAULT_NAMESPACE};
 use crate::prelude::*;
+use rusoto_core::request::HttpClient;
 use rusoto_core::Region;
 use rusoto_credential::StaticProvider;
+use rusoto_s3::S3Client;
 use serde_derive::Deserialize;
 use std::collections::HashMap;
 use std::path::Path;
@@ -47,6 +50,46 @@ impl ReportsBucket {
     pub(crate) fn to_aws_credentials(&self) -> StaticProvider {
         StaticProvider::new_minimal(self.access_key.clone(), self.secret_key.clone())
     }
+
+    /// Create a client of the bucket, authenticated with the configured credentials.
+    pub fn s3_client(&self) -> Fallible<S3Client> {
+        Ok(S3Client::new_with(
+            HttpClient::new()?,
+            self.to_aws_credentials(),
+            self.region.to_region()?,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NamespaceTokens {
+    #[serde(default)]
+    pub agents: HashMap<String, String>,
+    #[serde(default)]
+    pub cert_agents: Vec<String>,
+    /// Read-only tokens for the public API, limited to the experiments of the namespace.
+    #[serde(default)]
+    pub api: HashMap<String, String>,
+    /// Tokens allowed to upload lists of crates, stored inside the namespace.
+    #[serde(default)]
+    pub crate_lists: HashMap<String, String>,
+    /// Tokens allowed to queue experiments in the namespace.
+    #[serde(default)]
+    pub queue: HashMap<String, String>,
+}
+
+/// Authentication of the agents through TLS client certificates.
+///
+/// The TLS connection is terminated by a reverse proxy, which verifies the client certificate
+/// and forwards its CN to Crater in the configured header. The proxy must strip that header from
+/// incoming requests.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ClientCerts {
+    pub header: String,
+    #[serde(default)]
+    pub agents: Vec<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -55,6 +98,19 @@ pub struct Tokens {
     pub bot: BotTokens,
     pub reports_bucket: ReportsBucket,
     pub agents: HashMap<String, String>,
+    #[serde(default)]
+    pub namespaces: HashMap<String, NamespaceTokens>,
+    /// Read-only tokens for the public API, mapped to the name of their owner.
+    #[serde(default)]
+    pub api: HashMap<String, String>,
+    /// Tokens allowed to upload lists of crates, mapped to the name of their owner.
+    #[serde(default)]
+    pub crate_lists: HashMap<String, String>,
+    /// Tokens allowed to queue experiments, mapped to the name of their owner.
+    #[serde(default)]
+    pub queue: HashMap<String, String>,
+    #[serde(default)]
+    pub client_certs: Option<ClientCerts>,
 }
 
 #[cfg(test)]
@@ -75,6 +131,11 @@ impl Default for Tokens {
                 secret_key: String::new(),
             },
             agents: HashMap::new(),
+            namespaces: HashMap::new(),
+            api: HashMap::new(),
+            crate_lists: HashMap::new(),
+            queue: HashMap::new(),
+            client_certs: None,
         }
     }
 }
@@ -83,7 +144,184 @@ impl Tokens {
     pub fn load() -> Fallible<Tokens> {
         let content = ::std::fs::read_to_string(Path::new(TOKENS_PATH))
             .with_context(|_| format!("could not find {}", TOKENS_PATH))?;
-        let res = ::toml::from_str(&content)?;
+        let res: Tokens = ::toml::from_str(&content)?;
+        for namespace in res.namespaces.keys() {
+            if !is_valid_namespace(namespace) {
+                bail!("invalid namespace name: {}", namespace);
+            }
+        }
         Ok(res)
     }
+
+    /// Find the agent (and the namespace it belongs to) authenticated by a token.
+    pub fn agent(&self, token: &str) -> Option<(&str, &str)> {
+        self.find(token, &self.agents, |tokens| &tokens.agents)
+    }
+
+    /// Find the owner (and the namespace it belongs to) of a read-only token.
+    pub fn api(&self, token: &str) -> Option<(&str, &str)> {
+        self.find(token, &self.api, |tokens| &tokens.api)
+    }
+
+    /// Find the owner (and the namespace it belongs to) of a token uploading lists of crates.
+    pub fn crate_list(&self, token: &str) -> Option<(&str, &str)> {
+        self.find(token, &self.crate_lists, |tokens| &tokens.crate_lists)
+    }
+
+    /// Find the owner (and the namespace it belongs to) of a token queueing experiments.
+    pub fn queue(&self, token: &str) -> Option<(&str, &str)> {
+        self.find(token, &self.queue, |tokens| &tokens.queue)
+    }
+
+    /// Look up a token in the tokens of the default namespace and then in the ones of every
+    /// other namespace.
+    fn find<'a, F>(
+        &'a self,
+        token: &str,
+        default: &'a HashMap<String, String>,
+        namespaced: F,
+    ) -> Option<(&'a str, &'a str)>
+    where
+        F: Fn(&'a NamespaceTokens) -> &'a HashMap<String, String>,
+    {
+        if let Some(name) = default.get(token) {
+            return Some((name.as_str(), DEFAULT_NAMESPACE));
+        }
+
+        self.namespaces.iter().find_map(|(namespace, tokens)| {
+            namespaced(tokens)
+                .get(token)
+                .map(|name| (name.as_str(), namespace.as_str()))
+        })
+    }
+
+    /// Find the agent (and the namespace it belongs to) with the CN of a client certificate.
+    pub fn agent_by_cert(&self, cn: &str) -> Option<(&str, &str)> {
+        let certs = self.client_certs.as_ref()?;
+        if let Some(name) = certs.agents.iter().find(|name| *name == cn) {
+            return Some((name.as_str(), DEFAULT_NAMESPACE));
+        }
+
+        self.namespaces.iter().find_map(|(namespace, tokens)| {
+            tokens
+                .cert_agents
+                .iter()
+                .find(|name| *name == cn)
+                .map(|name| (name.as_str(), namespace.as_str()))
+        })
+    }
+
+    /// Iterate over the names of all the agents, along with the namespace they belong to.
+    pub fn all_agents(&self) -> impl Iterator<Item = (&str, &str)> {
+        let default = self
+            .agents
+            .values()
+            .chain(self.client_certs.iter().flat_map(|certs| certs.agents.iter()))
+            .map(|name| (name.as_str(), DEFAULT_NAMESPACE));
+        let namespaced = self.namespaces.iter().flat_map(|(namespace, tokens)| {
+            tokens
+                .agents
+                .values()
+                .chain(tokens.cert_agents.iter())
+                .map(move |name| (name.as_str(), namespace.as_str()))
+        });
+        default.chain(namespaced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tokens;
+
+    #[test]
+    fn test_agents_lookup() {
+        let tokens: Tokens = ::toml::from_str(
+            r#"
+            [bot]
+            webhooks-secret = ""
+            api-token = ""
+
+            [reports-bucket]
+            region = { type = "s3", region = "us-west-1" }
+            bucket = "crater-reports"
+            public-url = ""
+            access-key = ""
+            secret-key = ""
+
+            [agents]
+            "token1" = "agent-1"
+
+            [client-certs]
+            header = "X-Client-Cert-CN"
+            agents = ["agent-2"]
+
+            [namespaces.infra]
+            agents = { "token3" = "agent-3" }
+            cert-agents = ["agent-4"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(tokens.agent("token1"), Some(("agent-1", "default")));
+        assert_eq!(tokens.agent("token3"), Some(("agent-3", "infra")));
+        assert_eq!(tokens.agent("agent-2"), None);
+
+        assert_eq!(tokens.agent_by_cert("agent-2"), Some(("agent-2", "default")));
+        assert_eq!(tokens.agent_by_cert("agent-4"), Some(("agent-4", "infra")));
+        assert_eq!(tokens.agent_by_cert("token1"), None);
+
+        let mut all = tokens.all_agents().collect::<Vec<_>>();
+        all.sort();
+        assert_eq!(
+            all,
+            vec![
+                ("agent-1", "default"),
+                ("agent-2", "default"),
+                ("agent-3", "infra"),
+                ("agent-4", "infra"),
+            ]
+        );
+    }
+    #[test]
+    fn test_namespaced_tokens() {
+        let tokens: Tokens = ::toml::from_str(
+            r#"
+            agents = {}
+
+            [bot]
+            webhooks-secret = ""
+            api-token = ""
+
+            [reports-bucket]
+            region = { type = "s3", region = "us-west-1" }
+            bucket = "crater-reports"
+            public-url = ""
+            access-key = ""
+            secret-key = ""
+
+            [api]
+            "token1" = "reader-1"
+
+            [queue]
+            "token2" = "queuer-1"
+
+            [namespaces.infra]
+            api = { "token3" = "reader-2" }
+            crate-lists = { "token4" = "uploader" }
+            queue = { "token5" = "queuer-2" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(tokens.api("token1"), Some(("reader-1", "default")));
+        assert_eq!(tokens.api("token3"), Some(("reader-2", "infra")));
+        assert_eq!(tokens.crate_list("token4"), Some(("uploader", "infra")));
+        assert_eq!(tokens.queue("token2"), Some(("queuer-1", "default")));
+        assert_eq!(tokens.queue("token5"), Some(("queuer-2", "infra")));
+
+        // Tokens are only valid for their own type
+        assert_eq!(tokens.api("token5"), None);
+        assert_eq!(tokens.queue("token3"), None);
+        assert_eq!(tokens.agent("token1"), None);
+    }
 }
@@ -26,13 +26,14 @@ fn base_commit(gh: &dyn GitHub, repo: &str, merge_sha: &str) -> Fallible<Option<
     Ok(Some(commit.parents.remove(0).sha))
 }
 
+/// Record the try build whose completion is announced by the comment, if any.
 pub(crate) fn detect(
     db: &Database,
     gh: &dyn GitHub,
     repo: &str,
     pr: i32,
     comment: &str,
-) -> Fallible<()> {
+) -> Fallible<Option<TryBuild>> {
     if let Some(HomuComment::TryBuildCompleted { merge_sha }) = HOMU_COMMENT_RE
         .captures(comment)
         .and_then(|captures| serde_json::from_str(&captures[1]).ok())
@@ -43,9 +44,13 @@ pub(crate) fn detect(
                  VALUES (?1, ?2, ?3, ?4);",
                 &[&repo, &pr, &base_sha, &merge_sha],
             )?;
+            return Ok(Some(TryBuild {
+                base_sha,
+                merge_sha,
+            }));
         }
     }
-    Ok(())
+    Ok(None)
 }
 
 pub(crate) fn get_sha(db: &Database, repo: &str, pr: i32) -> Fallible<Option<TryBuild>> {
@@ -77,7 +82,9 @@ mod tests {
         let db = Database::temp().unwrap();
         let gh = DummyGitHub::default();
 
-        detect(&db, &gh, "rust-lang/rust", 1, "Test message").unwrap();
+        assert!(detect(&db, &gh, "rust-lang/rust", 1, "Test message")
+            .unwrap()
+            .is_none());
         assert!(get_sha(&db, "rust-lang/rust", 1).unwrap().is_none());
 
         gh.set_commit(
@@ -95,7 +102,7 @@ mod tests {
                 ],
             },
         );
-        detect(
+        let build = detect(
             &db,
             &gh,
             "rust-lang/rust",
@@ -108,7 +115,9 @@ mod tests {
                 COMMIT_A
             ),
         )
+        .unwrap()
         .unwrap();
+        assert_eq!(build.merge_sha.as_str(), COMMIT_A);
         let commit = get_sha(&db, "rust-lang/rust", 1).unwrap().unwrap();
         assert_eq!(commit.merge_sha.as_str(), COMMIT_A);
         assert_eq!(commit.base_sha.as_str(), COMMIT_B);
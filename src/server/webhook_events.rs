@@ -0,0 +1,246 @@
+use crate::db::{Database, QueryUtils};
+use crate::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+
+/// Number of times an event is processed before giving up on it.
+pub const MAX_ATTEMPTS: i32 = 5;
+
+string_enum!(pub enum EventStatus {
+    Processing => "processing",
+    Pending => "pending",
+    Processed => "processed",
+    Failed => "failed",
+});
+
+/// A webhook event received from GitHub, stored before processing it so it's not lost if the
+/// server is restarted or GitHub can't be reached.
+pub struct WebhookEvent {
+    pub id: i64,
+    pub event: String,
+    pub host: String,
+    pub payload: Vec<u8>,
+    pub received_at: DateTime<Utc>,
+    pub status: EventStatus,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+}
+
+/// Store a newly received event, which is processed right away by the caller.
+pub fn record(db: &Database, event: &str, host: &str, payload: &[u8]) -> Fallible<i64> {
+    db.with_conn(|conn| {
+        conn.execute(
+            "INSERT INTO webhook_events (event, host, payload, received_at, status, attempts) \
+             VALUES (?1, ?2, ?3, ?4, ?5, 0);",
+            &[
+                &event,
+                &host,
+                &payload,
+                &Utc::now(),
+                &EventStatus::Processing.to_str(),
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    })
+}
+
+fn load(db: &Database, status: EventStatus) -> Fallible<Vec<WebhookEvent>> {
+    db.query(
+        "SELECT * FROM webhook_events WHERE status = ?1 ORDER BY id;",
+        &[&status.to_str()],
+        |row| -> Fallible<WebhookEvent> {
+            let status: String = row.get("status");
+            Ok(WebhookEvent {
+                id: row.get("id"),
+                event: row.get("event"),
+                host: row.get("host"),
+                payload: row.get("payload"),
+                received_at: row.get("received_at"),
+                status: status.parse()?,
+                attempts: row.get("attempts"),
+                last_error: row.get("last_error"),
+            })
+        },
+    )?
+    .into_iter()
+    .collect()
+}
+
+/// Claim the events waiting to be processed again. Events claimed by another thread in the
+/// meantime are left out.
+pub fn claim_pending(db: &Database) -> Fallible<Vec<WebhookEvent>> {
+    let mut claimed = Vec::new();
+    for mut event in load(db, EventStatus::Pending)? {
+        let updated = db.execute(
+            "UPDATE webhook_events SET status = ?1 WHERE id = ?2 AND status = ?3;",
+            &[
+                &EventStatus::Processing.to_str(),
+                &event.id,
+                &EventStatus::Pending.to_str(),
+            ],
+        )?;
+        if updated == 1 {
+            event.status = EventStatus::Processing;
+            claimed.push(event);
+        }
+    }
+    Ok(claimed)
+}
+
+/// Queue again the events whose processing was interrupted by a restart of the server. This must
+/// only be called before the server starts receiving events.
+pub fn requeue_interrupted(db: &Database) -> Fallible<usize> {
+    db.execute(
+        "UPDATE webhook_events SET status = ?1 WHERE status = ?2;",
+        &[
+            &EventStatus::Pending.to_str(),
+            &EventStatus::Processing.to_str(),
+        ],
+    )
+}
+
+pub fn mark_processed(db: &Database, id: i64) -> Fallible<()> {
+    db.execute(
+        "UPDATE webhook_events SET status = ?1, attempts = attempts + 1, last_error = NULL \
+         WHERE id = ?2;",
+        &[&EventStatus::Processed.to_str(), &id],
+    )?;
+    Ok(())
+}
+
+/// Record a failed attempt at processing the event, queueing it to be retried until it failed
+/// `MAX_ATTEMPTS` times.
+pub fn mark_failed(db: &Database, id: i64, error: &str) -> Fallible<EventStatus> {
+    db.transaction(|t| {
+        let attempts = t
+            .get_row(
+                "SELECT attempts FROM webhook_events WHERE id = ?1;",
+                &[&id],
+                |row| -> i32 { row.get("attempts") },
+            )?
+            .ok_or_else(|| err_msg(format!("missing webhook event {}", id)))?
+            + 1;
+        let status = if attempts >= MAX_ATTEMPTS {
+            EventStatus::Failed
+        } else {
+            EventStatus::Pending
+        };
+        t.execute(
+            "UPDATE webhook_events SET status = ?1, attempts = ?2, last_error = ?3 WHERE id = ?4;",
+            &[&status.to_str(), &attempts, &error, &id],
+        )?;
+        Ok(status)
+    })
+}
+
+/// Queue the events with the provided IDs to be processed again by the server, or all the events
+/// that failed too many times if no ID is provided. Events already processed can be replayed too.
+pub fn replay(db: &Database, ids: &[i64]) -> Fallible<Vec<WebhookEvent>> {
+    let events = if ids.is_empty() {
+        load(db, EventStatus::Failed)?
+    } else {
+        let mut events = Vec::new();
+        for status in &[EventStatus::Failed, EventStatus::Processed] {
+            events.extend(
+                load(db, *status)?
+                    .into_iter()
+                    .filter(|event| ids.contains(&event.id)),
+            );
+        }
+        for id in ids {
+            if !events.iter().any(|event| event.id == *id) {
+                bail!("webhook event {} doesn't exist or is still queued", id);
+            }
+        }
+        events
+    };
+
+    db.transaction(|t| {
+        for event in &events {
+            t.execute(
+                "UPDATE webhook_events SET status = ?1, attempts = 0 WHERE id = ?2;",
+                &[&EventStatus::Pending.to_str(), &event.id],
+            )?;
+        }
+        Ok(())
+    })?;
+    Ok(events)
+}
+
+/// Remove the events processed more than `days` days ago.
+pub fn prune_processed(db: &Database, days: i64) -> Fallible<usize> {
+    db.execute(
+        "DELETE FROM webhook_events WHERE status = ?1 AND received_at < ?2;",
+        &[
+            &EventStatus::Processed.to_str(),
+            &(Utc::now() - Duration::days(days)),
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        claim_pending, mark_failed, mark_processed, record, replay, requeue_interrupted,
+        EventStatus, MAX_ATTEMPTS,
+    };
+    use crate::db::Database;
+
+    #[test]
+    fn test_retries_and_replay() {
+        let db = Database::temp().unwrap();
+
+        let processed = record(&db, "issue_comment", "crater.rust-lang.org", b"{}").unwrap();
+        let failing = record(&db, "issue_comment", "crater.rust-lang.org", b"[]").unwrap();
+        let interrupted = record(&db, "ping", "crater.rust-lang.org", b"").unwrap();
+        mark_processed(&db, processed).unwrap();
+
+        // Events being processed are not retried, unless the server was restarted
+        assert_eq!(
+            mark_failed(&db, failing, "GitHub is down").unwrap(),
+            EventStatus::Pending
+        );
+        let pending = claim_pending(&db).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, failing);
+        assert_eq!(pending[0].payload, b"[]");
+        assert_eq!(pending[0].last_error.as_ref().unwrap(), "GitHub is down");
+        assert!(claim_pending(&db).unwrap().is_empty());
+
+        assert_eq!(requeue_interrupted(&db).unwrap(), 2);
+        let pending = claim_pending(&db).unwrap();
+        assert_eq!(
+            pending.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![failing, interrupted]
+        );
+        mark_processed(&db, interrupted).unwrap();
+
+        // Events are given up on after too many failures
+        for attempt in 2..=MAX_ATTEMPTS {
+            let expected = if attempt == MAX_ATTEMPTS {
+                EventStatus::Failed
+            } else {
+                EventStatus::Pending
+            };
+            assert_eq!(
+                mark_failed(&db, failing, "GitHub is down").unwrap(),
+                expected
+            );
+        }
+        assert!(claim_pending(&db).unwrap().is_empty());
+
+        // Failed events are replayed by default, and processed ones only when requested
+        let replayed = replay(&db, &[]).unwrap();
+        assert_eq!(replayed.len(), 1);
+        assert_eq!(replayed[0].id, failing);
+        assert_eq!(replayed[0].attempts, MAX_ATTEMPTS);
+        assert!(replay(&db, &[failing]).is_err());
+        assert_eq!(replay(&db, &[processed]).unwrap().len(), 1);
+
+        let pending = claim_pending(&db).unwrap();
+        assert_eq!(
+            pending.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![processed, failing]
+        );
+        assert!(pending.iter().all(|e| e.attempts == 0));
+    }
+}
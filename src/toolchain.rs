@@ -1,9 +1,31 @@
+use crate::config::Config;
 use crate::prelude::*;
 use crate::utils;
-use rustwide::Toolchain as RustwideToolchain;
+use crate::utils::size::Size;
+use chrono::{DateTime, NaiveDate, Utc};
+pub use crater_results::version::ToolchainVersion;
+use flate2::read::GzDecoder;
+use http::{Method, StatusCode};
+use log::LevelFilter;
+use regex::Regex;
+use remove_dir_all::remove_dir_all;
+use rustwide::logging::{self, LogStorage};
+use rustwide::{Toolchain as RustwideToolchain, Workspace};
+use serde_json;
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use tar::Archive;
+use walkdir::WalkDir;
 
 /// This toolchain is used during internal tests, and must be different than TEST_TOOLCHAIN
 #[cfg(test)]
@@ -12,7 +34,14 @@ pub(crate) static MAIN_TOOLCHAIN: Toolchain = Toolchain {
         name: Cow::Borrowed("stable"),
     },
     rustflags: None,
-    ci_try: false,
+    rustdocflags: None,
+    env: Vec::new(),
+    cargo: None,
+    cargo_flags: Vec::new(),
+    linker: None,
+    cc: None,
+    target: None,
+    custom: None,
 };
 
 /// This toolchain is used during internal tests, and must be different than MAIN_TOOLCHAIN
@@ -22,22 +51,906 @@ pub(crate) static TEST_TOOLCHAIN: Toolchain = Toolchain {
         name: Cow::Borrowed("beta"),
     },
     rustflags: None,
-    ci_try: false,
+    rustdocflags: None,
+    env: Vec::new(),
+    cargo: None,
+    cargo_flags: Vec::new(),
+    linker: None,
+    cc: None,
+    target: None,
+    custom: None,
 };
 
+/// Environment variable used by rustup to pick the server toolchains are downloaded from.
+const RUSTUP_DIST_SERVER: &str = "RUSTUP_DIST_SERVER";
+
+/// Path of the rustup home inside the build sandbox.
+const SANDBOX_RUSTUP_HOME: &str = "/opt/rustwide/rustup-home";
+
+string_enum!(pub enum Linker {
+    Cc => "cc",
+    Lld => "lld",
+    Mold => "mold",
+});
+
+impl Linker {
+    /// Flags passed to rustc to link with this linker. The linkers are invoked through the system
+    /// C compiler, and must be installed in the sandbox image.
+    pub fn rustflags(self) -> &'static str {
+        match self {
+            Linker::Cc => "-C linker=cc",
+            Linker::Lld => "-C linker=cc -C link-arg=-fuse-ld=lld",
+            Linker::Mold => "-C linker=cc -C link-arg=-fuse-ld=mold",
+        }
+    }
+}
+
+string_enum!(pub enum CCompilerFamily {
+    Gcc => "gcc",
+    Clang => "clang",
+});
+
+/// C and C++ compiler of the sandbox used by the build scripts, like `gcc-9` or `clang-10`.
+/// Without a version the default one of the sandbox image is used.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CCompiler {
+    pub family: CCompilerFamily,
+    pub version: Option<String>,
+}
+
+impl CCompiler {
+    /// Name of the C compiler binary, set as `CC`.
+    pub fn cc(&self) -> String {
+        self.binary(self.family.to_str())
+    }
+
+    /// Name of the C++ compiler binary, set as `CXX`.
+    pub fn cxx(&self) -> String {
+        self.binary(match self.family {
+            CCompilerFamily::Gcc => "g++",
+            CCompilerFamily::Clang => "clang++",
+        })
+    }
+
+    fn binary(&self, name: &str) -> String {
+        match self.version {
+            Some(ref version) => format!("{}-{}", name, version),
+            None => name.to_string(),
+        }
+    }
+}
+
+impl FromStr for CCompiler {
+    type Err = ::failure::Error;
+
+    fn from_str(input: &str) -> Fallible<Self> {
+        let (family, version) = match input.find('-') {
+            Some(idx) => (&input[..idx], Some(&input[idx + 1..])),
+            None => (input, None),
+        };
+        if let Some(version) = version {
+            // The version ends up in the name of the binaries invoked in the sandbox
+            if version.is_empty() || !version.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                bail!("invalid C compiler version: {}", version);
+            }
+        }
+        Ok(CCompiler {
+            family: family.parse()?,
+            version: version.map(|version| version.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for CCompiler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.cc())
+    }
+}
+
+impl_serde_from_parse!(CCompiler, expecting = "a C compiler like gcc-9 or clang-10");
+
+/// Server the nightlies are published on, unless another one is configured.
+const DEFAULT_DIST_SERVER: &str = "https://static.rust-lang.org";
+/// Base URL of the bucket containing the dist tarballs built by rust-lang CI, by commit.
+const CI_ARTIFACTS_URL: &str = "https://ci-artifacts.rust-lang.org/rustc-builds";
+/// Target triple of the machine running Crater, which try builds are downloaded for.
+const HOST_TARGET: &str = env!("CRATER_HOST_TARGET");
+
+/// Name of the directory inside the work directory caching the toolchains built from artifacts.
+const ARTIFACTS_DIR: &str = "toolchain-artifacts";
+/// Name of the file inside the work directory tracking when the cached toolchains were last used.
+const CACHE_INDEX: &str = "toolchain-cache.json";
+/// Number of times the installation of a toolchain is attempted before giving up.
+const INSTALL_ATTEMPTS: u32 = 3;
+const INSTALL_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Number of lines at the end of the installation log included in installation errors.
+const INSTALL_LOG_LINES: usize = 50;
+
+lazy_static! {
+    static ref PINNED_DIST_NAME: Regex =
+        Regex::new(r"^((nightly|beta|stable)-\d{4}-\d{2}-\d{2}|\d+\.\d+\.\d+)(-.+)?$").unwrap();
+
+    /// Toolchains of the experiments running in this process, which are never removed from the
+    /// cache. The lock is held around every rustup invocation, including the installation of the
+    /// toolchains of an experiment, as rustup can't be invoked by multiple threads at the same
+    /// time.
+    static ref RUNNING_TOOLCHAINS: Mutex<HashMap<String, Vec<Toolchain>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Toolchain downloaded and installed by Crater itself instead of rustup or rustwide.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
+pub enum CustomToolchain {
+    /// Combined dist tarball provided with the toolchain.
+    Artifacts(ToolchainArtifacts),
+    /// Try build of rust-lang/rust, downloaded from the CI bucket.
+    TryBuild { sha: String },
+}
+
+impl CustomToolchain {
+    /// Name the toolchain is installed as in rustup.
+    fn toolchain_name(&self) -> String {
+        match self {
+            CustomToolchain::Artifacts(artifacts) => artifacts.toolchain_name(),
+            CustomToolchain::TryBuild { sha } => format!("crater-try-{}", sha),
+        }
+    }
+
+    /// Install the toolchain and the requested components in rustup's toolchains directory.
+    /// Components can't be added to artifacts toolchains, they must already be in the tarball.
+    fn install(&self, components: &[&str]) -> Fallible<()> {
+        match self {
+            CustomToolchain::Artifacts(artifacts) => artifacts.install(),
+            CustomToolchain::TryBuild { sha } => {
+                let mut tarballs = vec![
+                    format!("rustc-nightly-{}", HOST_TARGET),
+                    format!("rust-std-nightly-{}", HOST_TARGET),
+                    format!("cargo-nightly-{}", HOST_TARGET),
+                ];
+                tarballs.extend(components.iter().map(|component| match *component {
+                    "rust-src" => "rust-src-nightly".to_string(),
+                    component => format!("{}-nightly-{}", component, HOST_TARGET),
+                }));
+                install_ci_tarballs(&self.toolchain_name(), sha, &tarballs)
+            }
+        }
+    }
+}
+
+/// Download the dist tarballs of the CI build of `sha` and install them in rustup's toolchains
+/// directory as `name`, like rustup-toolchain-install-master does.
+///
+/// The tarballs installed by previous experiments are recorded in the cache directory of the build
+/// and aren't downloaded again, unless the toolchain was removed in the meantime.
+fn install_ci_tarballs(name: &str, sha: &str, tarballs: &[String]) -> Fallible<()> {
+    let dir = crate::dirs::WORK_DIR.join(ARTIFACTS_DIR).join(name);
+    let prefix = rustup_home().join("toolchains").join(name);
+    let installed_file = dir.join("installed");
+
+    let installed: HashSet<String> = if prefix.join("bin").join("rustc").exists() {
+        match fs::read_to_string(&installed_file) {
+            Ok(content) => content.lines().map(|line| line.to_string()).collect(),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => return Err(err.into()),
+        }
+    } else {
+        HashSet::new()
+    };
+    if installed.is_empty() {
+        for path in &[&dir, &prefix] {
+            if path.exists() {
+                remove_dir_all(path)?;
+            }
+        }
+    }
+    fs::create_dir_all(&dir)?;
+
+    let extracted = dir.join("extracted");
+    for tarball in tarballs {
+        if installed.contains(tarball) {
+            continue;
+        }
+        if extracted.exists() {
+            remove_dir_all(&extracted)?;
+        }
+
+        let url = format!("{}/{}/{}.tar.gz", CI_ARTIFACTS_URL, sha, tarball);
+        info!("downloading toolchain artifacts from {}", url);
+        let resp = utils::http::get_sync(&url)?;
+        Archive::new(GzDecoder::new(resp)).unpack(&extracted)?;
+        run_installer(&extracted, &prefix)?;
+        remove_dir_all(&extracted)?;
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&installed_file)?;
+        writeln!(file, "{}", tarball)?;
+    }
+
+    Ok(())
+}
+
+/// Dist tarball of a toolchain that never went through rust-lang CI, like a one-off local build.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
+pub struct ToolchainArtifacts {
+    /// URL of a combined `.tar.gz` installer, containing at least rustc and the standard library.
+    pub url: String,
+    /// Expected SHA-256 checksum of the tarball, as lowercase hex.
+    pub sha256: String,
+}
+
+impl ToolchainArtifacts {
+    /// Hash of the URL, identifying the cached copy of the toolchain.
+    fn url_hash(&self) -> String {
+        let mut hasher = Sha256::default();
+        hasher.input(self.url.as_bytes());
+        utils::hex::to_hex(hasher.result().as_slice())
+    }
+
+    /// Name the toolchain is installed as in rustup.
+    fn toolchain_name(&self) -> String {
+        format!("crater-artifacts-{}", &self.url_hash()[..16])
+    }
+
+    /// Download, verify and install the toolchain in rustup's toolchains directory.
+    ///
+    /// The toolchain is installed there directly instead of being added with `rustup toolchain
+    /// link`, as the symlink created by rustup would point outside of the directories mounted in
+    /// the build sandbox. Toolchains are cached by the hash of their URL, and they're downloaded
+    /// again only if the expected checksum changed since the last installation.
+    fn install(&self) -> Fallible<()> {
+        let dir = crate::dirs::WORK_DIR
+            .join(ARTIFACTS_DIR)
+            .join(self.url_hash());
+        let prefix = rustup_home().join("toolchains").join(self.toolchain_name());
+        let checksum_file = dir.join("sha256");
+
+        let cached = fs::read_to_string(&checksum_file)
+            .map(|checksum| checksum.trim() == self.sha256)
+            .unwrap_or(false);
+        if cached && prefix.join("bin").join("rustc").exists() {
+            info!("using the cached toolchain from {}", self.url);
+            return Ok(());
+        }
+
+        for path in &[&dir, &prefix] {
+            if path.exists() {
+                remove_dir_all(path)?;
+            }
+        }
+        fs::create_dir_all(&dir)?;
+
+        info!("downloading toolchain artifacts from {}", self.url);
+        let archive = dir.join("archive.tar.gz");
+        self.download(&archive)?;
+
+        let extracted = dir.join("extracted");
+        Archive::new(GzDecoder::new(File::open(&archive)?)).unpack(&extracted)?;
+        run_installer(&extracted, &prefix)?;
+
+        remove_dir_all(&extracted)?;
+        fs::remove_file(&archive)?;
+        fs::write(&checksum_file, &self.sha256)?;
+
+        Ok(())
+    }
+
+    /// Download the tarball to `dest`, failing if its checksum doesn't match the expected one.
+    fn download(&self, dest: &Path) -> Fallible<()> {
+        let mut resp = utils::http::get_sync(&self.url)?;
+        let mut file = File::create(dest)?;
+        let mut hasher = Sha256::default();
+
+        let mut buffer = [0; 64 * 1024];
+        loop {
+            let len = resp.read(&mut buffer)?;
+            if len == 0 {
+                break;
+            }
+            hasher.input(&buffer[..len]);
+            file.write_all(&buffer[..len])?;
+        }
+
+        let actual = utils::hex::to_hex(hasher.result().as_slice());
+        if actual != self.sha256 {
+            return Err(ToolchainArtifactsError::ChecksumMismatch {
+                url: self.url.clone(),
+                expected: self.sha256.clone(),
+                actual,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Error returned when a toolchain can't be installed, even after retrying.
+#[derive(Debug, Fail)]
+#[fail(display = "failed to install toolchain {}: {}", toolchain, error)]
+pub struct ToolchainInstallError {
+    pub toolchain: String,
+    pub error: String,
+    /// Last lines of the log of the last installation attempt.
+    pub log: String,
+}
+
+#[derive(Debug, Fail)]
+pub enum ToolchainArtifactsError {
+    #[fail(
+        display = "checksum mismatch for {}: expected {}, got {}",
+        url, expected, actual
+    )]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    #[fail(display = "no installer found in the toolchain artifacts")]
+    MissingInstaller,
+}
+
+/// Run the `install.sh` script of the extracted dist tarball, installing the toolchain in `prefix`.
+fn run_installer(extracted: &Path, prefix: &Path) -> Fallible<()> {
+    // Dist tarballs contain a single top-level directory with the installer inside it
+    let mut installer = None;
+    for entry in fs::read_dir(extracted)? {
+        let path = entry?.path().join("install.sh");
+        if path.is_file() {
+            installer = Some(path);
+            break;
+        }
+    }
+    let installer = installer.ok_or(ToolchainArtifactsError::MissingInstaller)?;
+
+    let mut prefix_arg = std::ffi::OsString::from("--prefix=");
+    prefix_arg.push(prefix);
+    let status = Command::new("sh")
+        .arg(&installer)
+        .arg(prefix_arg)
+        .arg("--disable-ldconfig")
+        .status()?;
+    if !status.success() {
+        bail!("the toolchain installer failed with {}", status);
+    }
+
+    Ok(())
+}
+
+/// Install the toolchains of an experiment one after the other, along with the requested
+/// components and the toolchains providing cargo, downloading them from `dist_server` instead of
+/// static.rust-lang.org if one is provided.
+///
+/// The server is passed to the rustup processes through their `RUSTUP_DIST_SERVER` environment
+/// variable. The artifacts of `try#` and `master#` toolchains are not downloaded by rustup and
+/// always come from the CI bucket, while the `artifacts#` toolchains are downloaded from their own
+/// URL.
+///
+/// The toolchains are then pinned to the experiment until `unpin` is called, so that
+/// `update_cache` never removes them while the experiment is running.
+pub fn install_all(
+    ex: &str,
+    toolchains: &[Toolchain],
+    workspace: &Workspace,
+    dist_server: Option<&str>,
+    components: &[&str],
+) -> Fallible<()> {
+    let mut running = RUNNING_TOOLCHAINS.lock().unwrap();
+    if let Some(server) = dist_server {
+        info!("downloading the toolchains from {}", server);
+    }
+
+    // Toolchains differing only in their flags share the same installation. They're installed
+    // sequentially, as concurrent rustup processes would step on each other's files.
+    let mut installed = HashSet::new();
+    for tc in toolchains {
+        if installed.insert(tc.rustup_name()) {
+            tc.install_with_retries(workspace, components, dist_server)?;
+        }
+    }
+
+    // The toolchains providing cargo are installed afterwards, as they could also be one of the
+    // toolchains of the experiment
+    for tc in toolchains {
+        if let (Some(name), Some(cargo)) = (&tc.cargo, tc.cargo_toolchain()) {
+            if installed.insert(name.clone()) {
+                install_source(&cargo, workspace, dist_server)?;
+            }
+        }
+    }
+
+    // Targets are added once the toolchains they belong to are installed, as multiple targets
+    // can share the same installation
+    let mut added = HashSet::new();
+    for tc in toolchains {
+        if let Some(ref target) = tc.target {
+            if added.insert((tc.rustup_name(), target)) {
+                tc.add_target(target, dist_server)?;
+            }
+        }
+    }
+
+    running.insert(ex.to_string(), toolchains.to_vec());
+    Ok(())
+}
+
+/// Allow the cache to remove the toolchains of an experiment once it's not running anymore.
+pub fn unpin(ex: &str) {
+    RUNNING_TOOLCHAINS.lock().unwrap().remove(ex);
+}
+
+/// Install a toolchain with rustwide, or with rustup directly for the dist toolchains downloaded
+/// from another server, as rustwide doesn't allow setting the environment of its commands.
+fn install_source(
+    source: &RustwideToolchain,
+    workspace: &Workspace,
+    dist_server: Option<&str>,
+) -> Fallible<()> {
+    match (source, dist_server) {
+        (RustwideToolchain::Dist { name }, Some(_)) => {
+            info!("installing toolchain {}", name);
+            run_rustup(dist_server, &["toolchain", "install", &**name])
+        }
+        _ => Ok(source.install(workspace)?),
+    }
+}
+
+/// Get the dist server the nightlies are published on, without a trailing slash.
+pub(crate) fn dist_server(config: &Config) -> &str {
+    config
+        .toolchains
+        .dist_server
+        .as_ref()
+        .map(|server| server.trim_end_matches('/'))
+        .unwrap_or(DEFAULT_DIST_SERVER)
+}
+
+/// Check whether the nightly of a day was published on the dist server. Nightlies are not
+/// published on the days their build failed.
+pub(crate) fn nightly_published(server: &str, date: NaiveDate) -> Fallible<bool> {
+    let url = format!("{}/dist/{}/channel-rust-nightly.toml", server, date);
+    let resp = utils::http::prepare_sync(Method::HEAD, &url).send()?;
+    match resp.status() {
+        StatusCode::OK => Ok(true),
+        StatusCode::NOT_FOUND => Ok(false),
+        status => bail!("request to {} returned status code {}", url, status),
+    }
+}
+
+/// Mark the toolchains of the running experiments as used, and uninstall the least recently used
+/// toolchains installed by previous experiments until the installed toolchains fit in `budget`.
+///
+/// The toolchains pinned by the experiments running in this process are never removed, even if
+/// they alone exceed the budget.
+pub fn update_cache(budget: Option<Size>) -> Fallible<()> {
+    let running = RUNNING_TOOLCHAINS.lock().unwrap();
+    let toolchains_dir = rustup_home().join("toolchains");
+    let index_path = crate::dirs::WORK_DIR.join(CACHE_INDEX);
+    let mut index: HashMap<String, DateTime<Utc>> = match fs::read(&index_path) {
+        Ok(content) => serde_json::from_slice(&content)?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let now = Utc::now();
+    let mut in_use = HashSet::new();
+    for tc in running.values().flatten() {
+        for name in tc.installed_dirs()? {
+            index.insert(name.clone(), now);
+            in_use.insert(name);
+        }
+    }
+
+    // Toolchains installed before the index existed are treated as last used when installed
+    let mut sizes = HashMap::new();
+    for entry in fs::read_dir(&toolchains_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !index.contains_key(&name) {
+            index.insert(name.clone(), entry.metadata()?.modified()?.into());
+        }
+        sizes.insert(name, dir_size(&entry.path()));
+    }
+    index.retain(|name, _| sizes.contains_key(name));
+
+    if let Some(budget) = budget {
+        let mut total: u64 = sizes.values().sum();
+        let mut by_last_use = index
+            .iter()
+            .filter(|&(name, _)| !in_use.contains(name))
+            .map(|(name, last_use)| (*last_use, name.clone()))
+            .collect::<Vec<_>>();
+        by_last_use.sort();
+
+        for (_, name) in by_last_use {
+            if total <= budget.to_bytes() as u64 {
+                break;
+            }
+
+            info!("removing the cached toolchain {}", name);
+            let status = rustup()
+                .args(&["toolchain", "uninstall", name.as_str()])
+                .status()?;
+            if !status.success() {
+                // Toolchains not managed by rustup can't be uninstalled by it
+                warn!("rustup failed to uninstall {}, removing it manually", name);
+                remove_dir_all(&toolchains_dir.join(&name))?;
+            }
+            // Try builds also record which of their tarballs were installed
+            let cached = crate::dirs::WORK_DIR.join(ARTIFACTS_DIR).join(&name);
+            if cached.exists() {
+                remove_dir_all(&cached)?;
+            }
+
+            total -= sizes[&name];
+            index.remove(&name);
+        }
+    }
+
+    fs::write(&index_path, serde_json::to_vec(&index)?)?;
+    Ok(())
+}
+
+/// Total size of the files inside a directory, ignoring the ones that can't be read.
+fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Rustup home of the workspace, where rustwide installs the toolchains.
+fn rustup_home() -> PathBuf {
+    crate::dirs::WORK_DIR.join("rustup-home")
+}
+
+/// Prepare a command invoking the rustup installed by rustwide in the workspace.
+fn rustup() -> Command {
+    let cargo_home = crate::dirs::WORK_DIR.join("cargo-home");
+    let mut command = Command::new(cargo_home.join("bin").join("rustup"));
+    command
+        .env("CARGO_HOME", &cargo_home)
+        .env("RUSTUP_HOME", rustup_home());
+    command
+}
+
+/// Run a rustup command downloading from `dist_server` if one is provided, recording its output in
+/// the log.
+fn run_rustup(dist_server: Option<&str>, args: &[&str]) -> Fallible<()> {
+    let mut command = rustup();
+    if let Some(server) = dist_server {
+        command.env(RUSTUP_DIST_SERVER, server);
+    }
+
+    let output = command.args(args).output()?;
+    for output in &[&output.stdout, &output.stderr] {
+        for line in String::from_utf8_lossy(output).lines() {
+            info!("{}", line);
+        }
+    }
+    if !output.status.success() {
+        bail!("rustup {} failed with {}", args.join(" "), output.status);
+    }
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
 pub struct Toolchain {
     pub source: RustwideToolchain,
     pub rustflags: Option<String>,
-    pub ci_try: bool,
+    /// Extra flags passed to rustdoc, when documenting the crates or running their doctests.
+    #[serde(default)]
+    pub rustdocflags: Option<String>,
+    /// Environment variables set in the build, after the ones set by Crater.
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    /// Dist toolchain providing cargo, instead of the cargo bundled with this toolchain.
+    #[serde(default)]
+    pub cargo: Option<String>,
+    /// Extra flags passed to the cargo commands of the build, like unstable `-Z` flags.
+    #[serde(default)]
+    pub cargo_flags: Vec<String>,
+    /// Linker used instead of the default one of the target.
+    #[serde(default)]
+    pub linker: Option<Linker>,
+    /// C and C++ compiler used instead of the default `cc` of the sandbox.
+    #[serde(default)]
+    pub cc: Option<CCompiler>,
+    /// Target triple the crates are built for, instead of the host.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Set for the toolchains installed by Crater itself, known to rustup with the name stored in
+    /// `source`.
+    #[serde(default)]
+    pub custom: Option<CustomToolchain>,
 }
 
 impl Toolchain {
+    /// Toolchain of a rust-lang/rust try build, without any flag.
+    pub fn try_build(sha: &str) -> Toolchain {
+        let custom = CustomToolchain::TryBuild {
+            sha: sha.to_string(),
+        };
+        Toolchain {
+            source: RustwideToolchain::Dist {
+                name: Cow::Owned(custom.toolchain_name()),
+            },
+            rustflags: None,
+            rustdocflags: None,
+            env: Vec::new(),
+            cargo: None,
+            cargo_flags: Vec::new(),
+            linker: None,
+            cc: None,
+            target: None,
+            custom: Some(custom),
+        }
+    }
+
+    /// Check the parts of a new toolchain that aren't validated when it's parsed, as the
+    /// toolchains stored by previous versions of Crater must keep loading.
+    pub fn validate_new(&self) -> Result<(), ToolchainParseError> {
+        let sha: &str = match (&self.custom, &self.source) {
+            (Some(CustomToolchain::TryBuild { sha }), _) => sha.as_str(),
+            (None, RustwideToolchain::CI { sha, .. }) => &**sha,
+            _ => return Ok(()),
+        };
+
+        // The CI artifacts are stored by full commit hash, so anything else can't be downloaded
+        if !is_commit_hash(sha) {
+            return Err(ToolchainParseError::InvalidCommit(sha.to_string()));
+        }
+        Ok(())
+    }
+
     pub fn to_path_component(&self) -> String {
         use percent_encoding::utf8_percent_encode as encode;
 
         encode(&self.to_string(), &utils::FILENAME_ENCODE_SET).to_string()
     }
+
+    /// Install the toolchain and the requested components.
+    fn install_with_components(
+        &self,
+        workspace: &Workspace,
+        components: &[&str],
+        dist_server: Option<&str>,
+    ) -> Fallible<()> {
+        if let Some(custom) = &self.custom {
+            custom.install(components)
+        } else {
+            self.install_cached(workspace, dist_server)?;
+            for component in components {
+                match (&self.source, dist_server) {
+                    (RustwideToolchain::Dist { name }, Some(_)) => run_rustup(
+                        dist_server,
+                        &["component", "add", "--toolchain", &**name, *component],
+                    )?,
+                    _ => self.add_component(workspace, component)?,
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Install the standard library of another target in the toolchain.
+    fn add_target(&self, target: &str, dist_server: Option<&str>) -> Fallible<()> {
+        info!("installing target {} for toolchain {}", target, self);
+        // Rustup can't add targets to the toolchains it didn't install
+        if let Some(CustomToolchain::TryBuild { sha }) = &self.custom {
+            let tarball = format!("rust-std-nightly-{}", target);
+            return install_ci_tarballs(&self.rustup_name(), sha, &[tarball]);
+        }
+
+        let name = self.rustup_name();
+        run_rustup(
+            dist_server,
+            &["target", "add", "--toolchain", name.as_str(), target],
+        )
+        .with_context(|_| format!("failed to install target {} for toolchain {}", target, self))?;
+        Ok(())
+    }
+
+    /// Copy of this toolchain building the crates for `target`.
+    pub fn with_target(&self, target: &str) -> Toolchain {
+        Toolchain {
+            target: Some(target.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// Install the toolchain and the requested components, retrying a few times to survive
+    /// network issues before failing with a `ToolchainInstallError`.
+    fn install_with_retries(
+        &self,
+        workspace: &Workspace,
+        components: &[&str],
+        dist_server: Option<&str>,
+    ) -> Fallible<()> {
+        let mut attempt = 1;
+        loop {
+            let storage = LogStorage::new(LevelFilter::Info);
+            match logging::capture(&storage, || {
+                self.install_with_components(workspace, components, dist_server)
+            }) {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < INSTALL_ATTEMPTS => {
+                    warn!(
+                        "failed to install toolchain {} (attempt {} of {}): {}",
+                        self, attempt, INSTALL_ATTEMPTS, err
+                    );
+                    thread::sleep(INSTALL_RETRY_DELAY);
+                    attempt += 1;
+                }
+                Err(err) => {
+                    let log = storage.to_string();
+                    let lines = log.lines().collect::<Vec<_>>();
+                    let tail = &lines[lines.len().saturating_sub(INSTALL_LOG_LINES)..];
+                    return Err(ToolchainInstallError {
+                        toolchain: self.to_string(),
+                        error: err.to_string(),
+                        log: tail.join("\n"),
+                    }
+                    .into());
+                }
+            }
+        }
+    }
+
+    /// Install the toolchain, reusing the copy installed by a previous experiment if the toolchain
+    /// is pinned to a single build and the copy is still working.
+    fn install_cached(&self, workspace: &Workspace, dist_server: Option<&str>) -> Fallible<()> {
+        if self.is_pinned() && self.is_installed()? {
+            if self.validate_installed() {
+                info!("reusing the cached toolchain {}", self);
+                return Ok(());
+            }
+
+            warn!("the cached toolchain {} is broken, reinstalling it", self);
+            let status = rustup()
+                .args(&["toolchain", "uninstall", self.rustup_name().as_str()])
+                .status()?;
+            if !status.success() {
+                bail!("failed to uninstall the cached toolchain {}", self);
+            }
+        }
+
+        install_source(&self.source, workspace, dist_server)
+    }
+
+    /// Whether the toolchain always refers to the same build, unlike channels such as `nightly`
+    /// which need to be updated before every experiment.
+    fn is_pinned(&self) -> bool {
+        match &self.source {
+            RustwideToolchain::CI { .. } => true,
+            RustwideToolchain::Dist { name } => {
+                self.custom.is_some() || PINNED_DIST_NAME.is_match(name)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether rustup knows about the toolchain, without installing it.
+    fn is_installed(&self) -> Fallible<bool> {
+        let output = rustup().args(&["toolchain", "list"]).output()?;
+        if !output.status.success() {
+            bail!("failed to list the installed toolchains");
+        }
+
+        // Dist toolchains are listed with the host triple appended to their name
+        let name = self.rustup_name();
+        let host_prefix = format!("{}-", name);
+        Ok(String::from_utf8(output.stdout)?.lines().any(|line| {
+            let installed = line.split_whitespace().next().unwrap_or("");
+            installed == name || installed.starts_with(&host_prefix)
+        }))
+    }
+
+    /// Names of the directories inside rustup's toolchains directory used by this toolchain,
+    /// including the one providing cargo. Dist toolchains have the host triple in their name.
+    fn installed_dirs(&self) -> Fallible<Vec<String>> {
+        let mut names = vec![self.rustup_name()];
+        if let Some(cargo) = &self.cargo {
+            names.push(cargo.clone());
+        }
+
+        let mut dirs = Vec::new();
+        for name in &names {
+            let output = rustup()
+                .args(&["which", "--toolchain", name.as_str(), "rustc"])
+                .output()?;
+            if !output.status.success() {
+                bail!("failed to locate the rustc binary of {}", name);
+            }
+
+            let rustc = PathBuf::from(String::from_utf8(output.stdout)?.trim());
+            match rustc
+                .strip_prefix(rustup_home().join("toolchains"))
+                .ok()
+                .and_then(|relative| relative.components().next())
+            {
+                Some(dir) => dirs.push(dir.as_os_str().to_string_lossy().into_owned()),
+                None => bail!("rustc of {} is outside of the rustup home", name),
+            }
+        }
+        Ok(dirs)
+    }
+
+    /// Check that an installed copy of the toolchain works and is the expected build.
+    fn validate_installed(&self) -> bool {
+        match (self.installed_version(), &self.source) {
+            (Ok(version), RustwideToolchain::CI { sha, .. }) => {
+                version.commit_hash.as_ref() == Some(sha.as_ref())
+            }
+            (Ok(_), _) => true,
+            (Err(_), _) => false,
+        }
+    }
+
+    /// The toolchain providing cargo, if it's not the one bundled with this toolchain.
+    pub(crate) fn cargo_toolchain(&self) -> Option<RustwideToolchain> {
+        self.cargo.as_ref().map(|name| RustwideToolchain::Dist {
+            name: Cow::Owned(name.clone()),
+        })
+    }
+
+    /// Name of the toolchain in rustup.
+    pub(crate) fn rustup_name(&self) -> String {
+        match &self.source {
+            RustwideToolchain::Dist { name } => name.to_string(),
+            RustwideToolchain::CI { sha, alt: true } => format!("{}-alt", sha),
+            RustwideToolchain::CI { sha, alt: false } => sha.to_string(),
+            _ => panic!("unsupported rustwide toolchain"),
+        }
+    }
+
+    /// Directory containing the binaries of the toolchain (like rustc and rustdoc), as seen from
+    /// inside the build sandbox.
+    ///
+    /// Builds using a cargo from another toolchain need it, as the `+toolchain` override of cargo
+    /// also applies to the rustc invoked by it.
+    pub(crate) fn sandbox_bin_dir(&self) -> Fallible<PathBuf> {
+        let output = {
+            let _rustup = RUNNING_TOOLCHAINS.lock().unwrap();
+            rustup()
+                .args(&["which", "--toolchain", self.rustup_name().as_str(), "rustc"])
+                .output()?
+        };
+        if !output.status.success() {
+            bail!("failed to locate the rustc binary of {}", self);
+        }
+
+        let rustc = PathBuf::from(String::from_utf8(output.stdout)?.trim());
+        let relative = match rustc.strip_prefix(rustup_home()) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => bail!("rustc of {} is outside of the rustup home", self),
+        };
+        let bin_dir = relative
+            .parent()
+            .ok_or_else(|| err_msg("invalid rustc path"))?;
+        Ok(Path::new(SANDBOX_RUSTUP_HOME).join(bin_dir))
+    }
+
+    /// Get the exact version of the installed toolchain from `rustc -vV`.
+    pub fn version(&self) -> Fallible<ToolchainVersion> {
+        let _rustup = RUNNING_TOOLCHAINS.lock().unwrap();
+        self.installed_version()
+    }
+
+    /// Get the version of the toolchain while the rustup lock is already held.
+    fn installed_version(&self) -> Fallible<ToolchainVersion> {
+        let output = rustup()
+            .args(&["run", self.rustup_name().as_str(), "rustc", "-vV"])
+            .output()?;
+        if !output.status.success() {
+            bail!("failed to get the version of {}", self);
+        }
+        ToolchainVersion::parse(&String::from_utf8(output.stdout)?)
+    }
 }
 
 impl std::ops::Deref for Toolchain {
@@ -50,44 +963,110 @@ impl std::ops::Deref for Toolchain {
 
 impl fmt::Display for Toolchain {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self.source {
-            RustwideToolchain::Dist { name } => write!(f, "{}", name)?,
-            RustwideToolchain::CI { sha, .. } => {
-                if self.ci_try {
-                    write!(f, "try#{}", sha)?;
-                } else {
-                    write!(f, "master#{}", sha)?;
-                }
-            }
+        match (&self.custom, &self.source) {
+            (Some(CustomToolchain::Artifacts(artifacts)), _) => write!(
+                f,
+                "artifacts#{}@{}",
+                artifacts.sha256,
+                escape_flag(&artifacts.url)
+            )?,
+            (Some(CustomToolchain::TryBuild { sha }), _) => write!(f, "try#{}", sha)?,
+            (None, RustwideToolchain::Dist { name }) => write!(f, "{}", name)?,
+            (None, RustwideToolchain::CI { sha, .. }) => write!(f, "master#{}", sha)?,
             _ => panic!("unsupported rustwide toolchain"),
-        };
+        }
 
         if let Some(ref flag) = self.rustflags {
-            write!(f, "+rustflags={}", flag)?;
+            write!(f, "+rustflags={}", escape_flag(flag))?;
+        }
+        if let Some(ref flag) = self.rustdocflags {
+            write!(f, "+rustdocflags={}", escape_flag(flag))?;
+        }
+        for (name, value) in &self.env {
+            write!(f, "+env={}={}", name, escape_flag(value))?;
+        }
+        if let Some(ref cargo) = self.cargo {
+            write!(f, "+cargo={}", escape_flag(cargo))?;
+        }
+        if !self.cargo_flags.is_empty() {
+            write!(
+                f,
+                "+cargoflags={}",
+                escape_flag(&self.cargo_flags.join(" "))
+            )?;
+        }
+        if let Some(linker) = self.linker {
+            write!(f, "+linker={}", linker)?;
+        }
+        if let Some(ref cc) = self.cc {
+            write!(f, "+cc={}", cc)?;
+        }
+        if let Some(ref target) = self.target {
+            write!(f, "+target={}", escape_flag(target))?;
         }
 
         Ok(())
     }
 }
 
+/// Escape the characters of a flag value that would otherwise be parsed as a separator, a quote
+/// or an escape.
+fn escape_flag(value: &str) -> Cow<str> {
+    if !value.contains(|chr| chr == '+' || chr == '"' || chr == '\\') {
+        return Cow::Borrowed(value);
+    }
+
+    let mut escaped = String::with_capacity(value.len());
+    for chr in value.chars() {
+        if chr == '+' || chr == '"' || chr == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(chr);
+    }
+    Cow::Owned(escaped)
+}
+
 #[derive(Debug, Fail)]
 pub enum ToolchainParseError {
     #[fail(display = "empty toolchain name")]
     EmptyName,
     #[fail(display = "invalid toolchain source name: {}", _0)]
     InvalidSourceName(String),
+    #[fail(display = "invalid commit (expected the full 40-chars hash): {}", _0)]
+    InvalidCommit(String),
     #[fail(display = "invalid toolchain flag: {}", _0)]
     InvalidFlag(String),
+    #[fail(display = "invalid cargo flag: {}", _0)]
+    InvalidCargoFlag(String),
+    #[fail(display = "invalid linker: {}", _0)]
+    InvalidLinker(String),
+    #[fail(display = "invalid C compiler: {}", _0)]
+    InvalidCCompiler(String),
+    #[fail(
+        display = "invalid environment variable (expected `NAME=value`): {}",
+        _0
+    )]
+    InvalidEnv(String),
+    #[fail(
+        display = "invalid toolchain artifacts (expected `sha256@url`): {}",
+        _0
+    )]
+    InvalidArtifacts(String),
+    #[fail(display = "unbalanced quotes in toolchain: {}", _0)]
+    UnbalancedQuotes(String),
 }
 
 impl FromStr for Toolchain {
     type Err = ToolchainParseError;
 
     fn from_str(input: &str) -> Result<Self, ToolchainParseError> {
-        let mut parts = input.split('+');
+        // Flag values containing a `+` must be quoted or escaped
+        let parts = utils::string::split_quoted_by(input, |chr| chr == '+')
+            .map_err(|_| ToolchainParseError::UnbalancedQuotes(input.to_string()))?;
+        let mut parts = parts.iter().map(|part| part.as_str());
 
         let raw_source = parts.next().ok_or(ToolchainParseError::EmptyName)?;
-        let mut ci_try = false;
+        let mut custom = None;
         let source = if let Some(hash_idx) = raw_source.find('#') {
             let (source_name, sha_with_hash) = raw_source.split_at(hash_idx);
 
@@ -98,16 +1077,26 @@ impl FromStr for Toolchain {
 
             match source_name {
                 "try" => {
-                    ci_try = true;
-                    RustwideToolchain::CI {
-                        sha: Cow::Owned(sha),
-                        alt: true,
+                    let parsed = CustomToolchain::TryBuild { sha };
+                    let name = parsed.toolchain_name();
+                    custom = Some(parsed);
+                    RustwideToolchain::Dist {
+                        name: Cow::Owned(name),
                     }
                 }
                 "master" => RustwideToolchain::CI {
                     sha: Cow::Owned(sha),
                     alt: true,
                 },
+                "artifacts" => {
+                    let parsed = parse_artifacts(&sha)
+                        .ok_or_else(|| ToolchainParseError::InvalidArtifacts(sha.clone()))?;
+                    let name = parsed.toolchain_name();
+                    custom = Some(CustomToolchain::Artifacts(parsed));
+                    RustwideToolchain::Dist {
+                        name: Cow::Owned(name),
+                    }
+                }
                 name => return Err(ToolchainParseError::InvalidSourceName(name.to_string())),
             }
         } else if raw_source.is_empty() {
@@ -119,6 +1108,13 @@ impl FromStr for Toolchain {
         };
 
         let mut rustflags = None;
+        let mut rustdocflags = None;
+        let mut env = Vec::new();
+        let mut cargo = None;
+        let mut cargo_flags = Vec::new();
+        let mut linker = None;
+        let mut cc = None;
+        let mut target = None;
         for part in parts {
             if let Some(equal_idx) = part.find('=') {
                 let (flag, value_with_equal) = part.split_at(equal_idx);
@@ -130,6 +1126,40 @@ impl FromStr for Toolchain {
 
                 match flag {
                     "rustflags" => rustflags = Some(value),
+                    "rustdocflags" => rustdocflags = Some(value),
+                    "env" => {
+                        let var = parse_env(&env, &value)
+                            .ok_or_else(|| ToolchainParseError::InvalidEnv(value.clone()))?;
+                        env.push(var);
+                    }
+                    "cargo" => cargo = Some(value),
+                    "cargoflags" => {
+                        if value.trim().is_empty() {
+                            return Err(ToolchainParseError::InvalidFlag(flag.to_string()));
+                        }
+                        // Anything else would be passed as an argument of the cargo subcommand
+                        for flag in value.split_whitespace() {
+                            if !flag.starts_with('-') {
+                                return Err(ToolchainParseError::InvalidCargoFlag(flag.into()));
+                            }
+                            cargo_flags.push(flag.to_string());
+                        }
+                    }
+                    "target" => target = Some(value),
+                    "linker" => {
+                        linker = Some(
+                            value
+                                .parse()
+                                .map_err(|_| ToolchainParseError::InvalidLinker(value))?,
+                        )
+                    }
+                    "cc" => {
+                        cc = Some(
+                            value
+                                .parse()
+                                .map_err(|_| ToolchainParseError::InvalidCCompiler(value))?,
+                        )
+                    }
                     unknown => return Err(ToolchainParseError::InvalidFlag(unknown.to_string())),
                 }
             } else {
@@ -140,34 +1170,200 @@ impl FromStr for Toolchain {
         Ok(Toolchain {
             source,
             rustflags,
-            ci_try,
+            rustdocflags,
+            env,
+            cargo,
+            cargo_flags,
+            linker,
+            cc,
+            target,
+            custom,
         })
     }
 }
 
+/// Parse the value of an `+env=NAME=value` flag. The flags Crater passes to rustc and rustdoc can
+/// only be extended with their own toolchain flags, and each variable can only be set once.
+fn parse_env(previous: &[(String, String)], input: &str) -> Option<(String, String)> {
+    let equal_idx = input.find('=')?;
+    let (name, value) = (&input[..equal_idx], &input[equal_idx + 1..]);
+
+    let mut chars = name.chars();
+    let valid_start = chars
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_')
+        .unwrap_or(false);
+    if !valid_start || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    if name == "RUSTFLAGS" || name == "RUSTDOCFLAGS" {
+        return None;
+    }
+    if previous.iter().any(|(previous, _)| previous == name) {
+        return None;
+    }
+    Some((name.to_string(), value.to_string()))
+}
+
+/// Whether the input is a full commit hash, as used by the CI artifacts of `try#` and `master#`.
+fn is_commit_hash(input: &str) -> bool {
+    input.len() == 40 && input.chars().all(|c| c.is_digit(16) && !c.is_uppercase())
+}
+
+/// Parse the `sha256@url` part of the `artifacts#` toolchains.
+fn parse_artifacts(input: &str) -> Option<ToolchainArtifacts> {
+    let at_idx = input.find('@')?;
+    let (sha256, url_with_at) = input.split_at(at_idx);
+    let url = &url_with_at[1..];
+
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_digit(16) && !c.is_uppercase()) {
+        return None;
+    }
+    match url::Url::parse(url) {
+        Ok(ref parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => {}
+        _ => return None,
+    }
+
+    Some(ToolchainArtifacts {
+        url: url.to_string(),
+        sha256: sha256.to_string(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Toolchain;
+    use super::{
+        dir_size, CCompiler, CCompilerFamily, CustomToolchain, Linker, Toolchain,
+        ToolchainArtifacts, ToolchainVersion, MAIN_TOOLCHAIN,
+    };
     use rustwide::Toolchain as RustwideToolchain;
+    use std::fs;
     use std::str::FromStr;
 
     #[test]
     fn test_string_repr() {
         macro_rules! test_from_str {
-            ($($str:expr => { source: $source:expr, ci_try: $ci_try:expr, },)*) => {
+            ($($str:expr => { source: $source:expr, custom: $custom:expr, },)*) => {
                 $(
                     // Test parsing without flags
                     test_from_str!($str => Toolchain {
                         source: $source,
                         rustflags: None,
-                        ci_try: $ci_try,
+                        rustdocflags: None,
+                        env: Vec::new(),
+                        cargo: None,
+                        cargo_flags: Vec::new(),
+                        linker: None,
+                        cc: None,
+                        target: None,
+                        custom: $custom,
                     });
 
                     // Test parsing with flags
                     test_from_str!(concat!($str, "+rustflags=foo bar") => Toolchain {
                         source: $source,
                         rustflags: Some("foo bar".to_string()),
-                        ci_try: $ci_try,
+                        rustdocflags: None,
+                        env: Vec::new(),
+                        cargo: None,
+                        cargo_flags: Vec::new(),
+                        linker: None,
+                        cc: None,
+                        target: None,
+                        custom: $custom,
+                    });
+
+                    // Test parsing with a different cargo
+                    test_from_str!(concat!($str, "+rustflags=foo bar+cargo=beta") => Toolchain {
+                        source: $source,
+                        rustflags: Some("foo bar".to_string()),
+                        rustdocflags: None,
+                        env: Vec::new(),
+                        cargo: Some("beta".to_string()),
+                        cargo_flags: Vec::new(),
+                        linker: None,
+                        cc: None,
+                        target: None,
+                        custom: $custom,
+                    });
+
+                    // Test parsing with rustdoc flags and environment variables
+                    test_from_str!(
+                        concat!($str, "+rustdocflags=-Zfoo+env=FOO=bar baz+env=_BAR=")
+                        => Toolchain {
+                            source: $source,
+                            rustflags: None,
+                            rustdocflags: Some("-Zfoo".to_string()),
+                            env: vec![
+                                ("FOO".to_string(), "bar baz".to_string()),
+                                ("_BAR".to_string(), String::new()),
+                            ],
+                            cargo: None,
+                            cargo_flags: Vec::new(),
+                            linker: None,
+                            cc: None,
+                            target: None,
+                            custom: $custom,
+                        }
+                    );
+
+                    // Test parsing with unstable cargo flags
+                    test_from_str!(concat!($str, "+cargoflags=-Zfoo --bar") => Toolchain {
+                        source: $source,
+                        rustflags: None,
+                        rustdocflags: None,
+                        env: Vec::new(),
+                        cargo: None,
+                        cargo_flags: vec!["-Zfoo".to_string(), "--bar".to_string()],
+                        linker: None,
+                        cc: None,
+                        target: None,
+                        custom: $custom,
+                    });
+
+                    // Test parsing with a different target
+                    test_from_str!(concat!($str, "+target=wasm32-unknown-unknown") => Toolchain {
+                        source: $source,
+                        rustflags: None,
+                        rustdocflags: None,
+                        env: Vec::new(),
+                        cargo: None,
+                        cargo_flags: Vec::new(),
+                        linker: None,
+                        cc: None,
+                        target: Some("wasm32-unknown-unknown".to_string()),
+                        custom: $custom,
+                    });
+
+                    // Test parsing with a different linker
+                    test_from_str!(concat!($str, "+linker=lld") => Toolchain {
+                        source: $source,
+                        rustflags: None,
+                        rustdocflags: None,
+                        env: Vec::new(),
+                        cargo: None,
+                        cargo_flags: Vec::new(),
+                        linker: Some(Linker::Lld),
+                        cc: None,
+                        target: None,
+                        custom: $custom,
+                    });
+
+                    // Test parsing with a different C compiler
+                    test_from_str!(concat!($str, "+cc=clang-10") => Toolchain {
+                        source: $source,
+                        rustflags: None,
+                        rustdocflags: None,
+                        env: Vec::new(),
+                        cargo: None,
+                        cargo_flags: Vec::new(),
+                        linker: None,
+                        cc: Some(CCompiler {
+                            family: CCompilerFamily::Clang,
+                            version: Some("10".to_string()),
+                        }),
+                        target: None,
+                        custom: $custom,
                     });
                 )*
             };
@@ -189,33 +1385,34 @@ mod tests {
                 source:RustwideToolchain::Dist {
                     name: "stable".into(),
                 },
-                ci_try: false,
+                custom: None,
             },
             "beta-1970-01-01" => {
                 source: RustwideToolchain::Dist {
                     name: "beta-1970-01-01".into(),
                 },
-                ci_try: false,
+                custom: None,
             },
             "nightly-1970-01-01" => {
                 source: RustwideToolchain::Dist {
                     name: "nightly-1970-01-01".into(),
                 },
-                ci_try: false,
+                custom: None,
             },
             "master#0000000000000000000000000000000000000000" => {
                 source: RustwideToolchain::CI {
                     sha: "0000000000000000000000000000000000000000".into(),
                     alt: true,
                 },
-                ci_try: false,
+                custom: None,
             },
             "try#0000000000000000000000000000000000000000" => {
-                source: RustwideToolchain::CI {
-                    sha: "0000000000000000000000000000000000000000".into(),
-                    alt: true,
+                source: RustwideToolchain::Dist {
+                    name: "crater-try-0000000000000000000000000000000000000000".into(),
                 },
-                ci_try: true,
+                custom: Some(CustomToolchain::TryBuild {
+                    sha: "0000000000000000000000000000000000000000".into(),
+                }),
             },
         };
 
@@ -225,6 +1422,268 @@ mod tests {
         assert!(Toolchain::from_str("foo#0000000000000000000000000000000000000000").is_err());
         assert!(Toolchain::from_str("stable+rustflags").is_err());
         assert!(Toolchain::from_str("stable+rustflags=").is_err());
+        assert!(Toolchain::from_str("stable+cargo=").is_err());
+        assert!(Toolchain::from_str("stable+cargoflags=").is_err());
+        assert!(Toolchain::from_str("stable+cargoflags=-Zfoo bar").is_err());
+        assert!(Toolchain::from_str("stable+linker=gold").is_err());
+        assert!(Toolchain::from_str("stable+cc=icc").is_err());
+        assert!(Toolchain::from_str("stable+cc=gcc-").is_err());
+        assert!(Toolchain::from_str("stable+cc=gcc-9;ls").is_err());
+        assert!(Toolchain::from_str("stable+target=").is_err());
+        assert!(Toolchain::from_str("stable+rustdocflags=").is_err());
+        assert!(Toolchain::from_str("stable+env=FOO").is_err());
+        assert!(Toolchain::from_str("stable+env==bar").is_err());
+        assert!(Toolchain::from_str("stable+env=1FOO=bar").is_err());
+        assert!(Toolchain::from_str("stable+env=FOO-BAR=baz").is_err());
+        assert!(Toolchain::from_str("stable+env=RUSTFLAGS=-Zfoo").is_err());
+        assert!(Toolchain::from_str("stable+env=FOO=bar+env=FOO=baz").is_err());
+
+        // The cargo toolchain is installed separately
+        assert_eq!(
+            Toolchain::from_str("nightly+cargo=beta")
+                .unwrap()
+                .cargo_toolchain(),
+            Some(RustwideToolchain::Dist {
+                name: "beta".into(),
+            })
+        );
+        assert!(MAIN_TOOLCHAIN.cargo_toolchain().is_none());
         assert!(Toolchain::from_str("stable+donotusethisflag=ever").is_err())
     }
+
+    #[test]
+    fn test_quoted_flags() {
+        macro_rules! test_quoted {
+            ($($input:expr => $repr:expr,)*) => {
+                $(
+                    let tc = Toolchain::from_str($input).unwrap();
+                    assert_eq!(tc.to_string(), $repr);
+                    assert_eq!(Toolchain::from_str(&tc.to_string()).unwrap(), tc);
+                )*
+            };
+        }
+
+        test_quoted! {
+            r#"stable+env=FOO="a+b""# => r"stable+env=FOO=a\+b",
+            r"stable+env=FOO=a\+b+env=BAR=c" => r"stable+env=FOO=a\+b+env=BAR=c",
+            r#"stable+rustflags="-C link-arg=-Wl,--foo" "x\"y""# =>
+                r#"stable+rustflags=-C link-arg=-Wl,--foo x\"y"#,
+            r#"stable+cargoflags="-Zfoo --bar""# => "stable+cargoflags=-Zfoo --bar",
+        };
+
+        let tc = Toolchain::from_str(r"stable+env=FOO=a\+b+env=BAR=c").unwrap();
+        assert_eq!(
+            tc.env,
+            vec![
+                ("FOO".to_string(), "a+b".to_string()),
+                ("BAR".to_string(), "c".to_string()),
+            ]
+        );
+        let tc = Toolchain::from_str(r#"stable+cargoflags="-Zfoo --bar""#).unwrap();
+        assert_eq!(tc.cargo_flags, vec!["-Zfoo", "--bar"]);
+
+        // Artifacts URLs containing a `+`
+        let sha256 = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+        let url = "https://example.com/builds/1.50.0+abc/rust.tar.gz";
+        let tc =
+            Toolchain::from_str(&format!("artifacts#\"{}@{}\"+target=x", sha256, url)).unwrap();
+        match &tc.custom {
+            Some(CustomToolchain::Artifacts(artifacts)) => assert_eq!(artifacts.url, url),
+            _ => panic!("expected an artifacts toolchain"),
+        }
+        assert_eq!(tc.target, Some("x".to_string()));
+        assert_eq!(
+            tc.to_string(),
+            format!("artifacts#{}@{}+target=x", sha256, url.replace('+', r"\+"))
+        );
+        assert_eq!(Toolchain::from_str(&tc.to_string()).unwrap(), tc);
+
+        // Toolchains in the bot commands can be quoted as well
+        let args =
+            crate::utils::string::split_quoted(r#"end=stable+cargoflags="-Zfoo --bar""#).unwrap();
+        assert_eq!(args, vec!["end=stable+cargoflags=-Zfoo --bar"]);
+        let tc = Toolchain::from_str(&args[0]["end=".len()..]).unwrap();
+        assert_eq!(tc.cargo_flags, vec!["-Zfoo", "--bar"]);
+        let args = crate::utils::string::split_quoted(r"end=stable+env=FOO=a\\+b").unwrap();
+        let tc = Toolchain::from_str(&args[0]["end=".len()..]).unwrap();
+        assert_eq!(tc.env, vec![("FOO".to_string(), "a+b".to_string())]);
+
+        assert!(Toolchain::from_str(r#"stable+rustflags="-Zfoo"#).is_err());
+    }
+
+    #[test]
+    fn test_stored_toolchains() {
+        // The results are stored with the string representation of their toolchain, so the
+        // toolchains stored by previous versions must be parsed and printed back unchanged
+        let sha = "0000000000000000000000000000000000000000";
+        let stored = vec![
+            "stable".to_string(),
+            "nightly-2024-01-01+rustflags=-Ccodegen-units=1".to_string(),
+            "beta+rustdocflags=-Zunstable-options+env=RUST_MIN_STACK=8000000".to_string(),
+            "stable+cargo=nightly+cargoflags=-Zbuild-std --locked".to_string(),
+            "nightly+rustflags=-Zfoo+cargoflags=-Zbar+target=x86_64-unknown-linux-gnu".to_string(),
+            format!("master#{}+cargoflags=-Zfoo", sha),
+            format!("try#{}+rustflags=-Zfoo+cargoflags=-Zbar", sha),
+        ];
+        for repr in &stored {
+            let tc = Toolchain::from_str(repr).unwrap();
+            assert_eq!(&tc.to_string(), repr);
+            let json = serde_json::to_string(&tc).unwrap();
+            assert_eq!(serde_json::from_str::<Toolchain>(&json).unwrap(), tc);
+        }
+
+        let tc = Toolchain::from_str(&stored[3]).unwrap();
+        assert_eq!(tc.cargo, Some("nightly".to_string()));
+        assert_eq!(tc.cargo_flags, vec!["-Zbuild-std", "--locked"]);
+    }
+
+    #[test]
+    fn test_c_compiler() {
+        let gcc = CCompiler::from_str("gcc-9").unwrap();
+        assert_eq!(gcc.cc(), "gcc-9");
+        assert_eq!(gcc.cxx(), "g++-9");
+
+        let clang = CCompiler::from_str("clang").unwrap();
+        assert_eq!(clang.cc(), "clang");
+        assert_eq!(clang.cxx(), "clang++");
+        assert_eq!(clang.to_string(), "clang");
+
+        assert_eq!(
+            CCompiler::from_str("clang-3.9").unwrap().cxx(),
+            "clang++-3.9"
+        );
+        assert!(CCompiler::from_str("msvc").is_err());
+        assert!(CCompiler::from_str("gcc-9-x").is_err());
+    }
+
+    #[test]
+    fn test_version_parsing() {
+        let output = "rustc 1.40.0-nightly (1423bec54 2019-11-05)\n\
+                      binary: rustc\n\
+                      commit-hash: 1423bec54cf2db283b614e527cfd602b481485d1\n\
+                      commit-date: 2019-11-05\n\
+                      host: x86_64-unknown-linux-gnu\n\
+                      release: 1.40.0-nightly\n\
+                      LLVM version: 9.0\n";
+        assert_eq!(
+            ToolchainVersion::parse(output).unwrap(),
+            ToolchainVersion {
+                release: "1.40.0-nightly".into(),
+                commit_hash: Some("1423bec54cf2db283b614e527cfd602b481485d1".into()),
+                commit_date: Some("2019-11-05".into()),
+                host: Some("x86_64-unknown-linux-gnu".into()),
+                llvm_version: Some("9.0".into()),
+            }
+        );
+
+        // Local builds don't know their commit
+        let output = "rustc 1.42.0-dev\n\
+                      binary: rustc\n\
+                      commit-hash: unknown\n\
+                      commit-date: unknown\n\
+                      host: x86_64-unknown-linux-gnu\n\
+                      release: 1.42.0-dev\n";
+        let version = ToolchainVersion::parse(output).unwrap();
+        assert_eq!(version.release, "1.42.0-dev");
+        assert!(version.commit_hash.is_none());
+        assert!(version.llvm_version.is_none());
+
+        assert!(ToolchainVersion::parse("error: toolchain not installed").is_err());
+    }
+
+    #[test]
+    fn test_is_pinned() {
+        for (repr, pinned) in &[
+            ("stable", false),
+            ("nightly", false),
+            ("beta-x86_64-unknown-linux-gnu", false),
+            ("1.40", false),
+            ("1.40.0", true),
+            ("1.40.0-x86_64-unknown-linux-gnu", true),
+            ("nightly-2019-12-01", true),
+            ("beta-2019-12-01-x86_64-unknown-linux-gnu", true),
+            ("try#1423bec54cf2db283b614e527cfd602b481485d1", true),
+            ("master#1423bec54cf2db283b614e527cfd602b481485d1", true),
+        ] {
+            assert_eq!(
+                Toolchain::from_str(repr).unwrap().is_pinned(),
+                *pinned,
+                "{}",
+                repr
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_new() {
+        let sha = "1423bec54cf2db283b614e527cfd602b481485d1";
+        assert_eq!(
+            Toolchain::try_build(sha).to_string(),
+            format!("try#{}", sha)
+        );
+        for repr in &["stable", "nightly-2019-12-01+rustflags=-Zfoo"] {
+            assert!(Toolchain::from_str(repr).unwrap().validate_new().is_ok());
+        }
+        for source in &["try", "master"] {
+            let tc = Toolchain::from_str(&format!("{}#{}", source, sha)).unwrap();
+            assert!(tc.validate_new().is_ok());
+
+            // Toolchains stored before the commit hash was validated must keep loading
+            for invalid in &["deadbeef", "000000000000000000000000000000000000000g"] {
+                let repr = format!("{}#{}", source, invalid);
+                let tc = Toolchain::from_str(&repr).unwrap();
+                assert_eq!(tc.to_string(), repr);
+                let json = serde_json::to_string(&tc).unwrap();
+                assert_eq!(serde_json::from_str::<Toolchain>(&json).unwrap(), tc);
+                assert!(tc.validate_new().is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_dir_size() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("bin")).unwrap();
+        fs::write(dir.path().join("bin").join("rustc"), vec![0; 1024]).unwrap();
+        fs::write(dir.path().join("README.md"), "hello").unwrap();
+
+        assert_eq!(dir_size(dir.path()), 1029);
+        assert_eq!(dir_size(&dir.path().join("missing")), 0);
+    }
+
+    #[test]
+    fn test_artifacts_repr() {
+        let sha256 = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+        let url = "https://example.com/builds/rust-nightly-x86_64-unknown-linux-gnu.tar.gz";
+        let repr = format!("artifacts#{}@{}+rustflags=-Zfoo", sha256, url);
+
+        let tc = Toolchain::from_str(&repr).unwrap();
+        assert_eq!(
+            tc.custom,
+            Some(CustomToolchain::Artifacts(ToolchainArtifacts {
+                url: url.to_string(),
+                sha256: sha256.to_string(),
+            }))
+        );
+        assert_eq!(tc.rustflags, Some("-Zfoo".to_string()));
+        assert_eq!(tc.to_string(), repr);
+
+        // The toolchain is installed with a name derived from the URL
+        match &tc.source {
+            RustwideToolchain::Dist { name } => assert!(name.starts_with("crater-artifacts-")),
+            _ => panic!("artifacts toolchains should be used as dist toolchains"),
+        }
+        let other = Toolchain::from_str(&format!("artifacts#{}@{}.old", sha256, url)).unwrap();
+        assert_ne!(tc.source, other.source);
+
+        // Invalid checksums or URLs
+        assert!(Toolchain::from_str(&format!("artifacts#{}", url)).is_err());
+        assert!(Toolchain::from_str(&format!("artifacts#abc@{}", url)).is_err());
+        assert!(
+            Toolchain::from_str(&format!("artifacts#{}@{}", sha256.to_uppercase(), url)).is_err()
+        );
+        assert!(
+            Toolchain::from_str(&format!("artifacts#{}@file:///tmp/rust.tar.gz", sha256)).is_err()
+        );
+    }
 }
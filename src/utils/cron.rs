@@ -0,0 +1,208 @@
+use crate::prelude::*;
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+/// Recurring schedule written with the syntax of cron: the minute, the hour, the day of the
+/// month, the month and the day of the week (0 or 7 being Sunday), separated by spaces. Every
+/// field accepts `*`, numbers, ranges (`1-5`), steps (`*/15` or `0-30/10`) and lists of them
+/// (`1,15`). The times are in UTC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Parse a field of a cron expression into a bitmask of the values it matches.
+fn parse_field(field: &str, min: u32, max: u32) -> Fallible<u64> {
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(idx) => (&part[..idx], part[idx + 1..].parse::<u32>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("invalid step in the cron field: {}", part);
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some(idx) = range.find('-') {
+            (range[..idx].parse()?, range[idx + 1..].parse()?)
+        } else {
+            let value = range.parse()?;
+            // `5/10` is a shorthand for `5-<max>/10`
+            (value, if step > 1 { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            bail!("cron field out of the {}-{} range: {}", min, max, part);
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+impl CronSchedule {
+    fn matches_day(&self, time: &DateTime<Utc>) -> bool {
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        // Like cron, either field matching is enough when both are restricted
+        if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+
+    /// First minute matching the schedule after `time`, or `None` if no date matches it (like
+    /// the 30th of February).
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut next = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = time + Duration::days(366 * 4);
+        while next <= limit {
+            if self.months & (1 << next.month()) == 0 {
+                let (year, month) = match next.month() {
+                    12 => (next.year() + 1, 1),
+                    month => (next.year(), month + 1),
+                };
+                next = Utc.ymd(year, month, 1).and_hms(0, 0, 0);
+            } else if !self.matches_day(&next) {
+                next = (next.date() + Duration::days(1)).and_hms(0, 0, 0);
+            } else if self.hours & (1 << next.hour()) == 0 {
+                next = next.with_minute(0)? + Duration::hours(1);
+            } else if self.minutes & (1 << next.minute()) == 0 {
+                next = next + Duration::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = failure::Error;
+
+    fn from_str(input: &str) -> Fallible<CronSchedule> {
+        let fields = input.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            bail!("cron expressions must have 5 fields: {}", input);
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+
+        Ok(CronSchedule {
+            expression: fields.join(" "),
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+}
+
+impl_serde_from_parse!(CronSchedule, expecting = "a cron expression");
+
+#[cfg(test)]
+mod tests {
+    use super::CronSchedule;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn time(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.ymd(2024, month, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn test_parse() {
+        let invalid = &[
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ];
+        for expression in invalid {
+            assert!(
+                expression.parse::<CronSchedule>().is_err(),
+                "{}",
+                expression
+            );
+        }
+
+        let schedule = "0  3 * * 1-5".parse::<CronSchedule>().unwrap();
+        assert_eq!(schedule.to_string(), "0 3 * * 1-5");
+    }
+
+    #[test]
+    fn test_next_after() {
+        // 2024-01-01 is a Monday
+        let cases = &[
+            ("* * * * *", time(1, 1, 0, 0), time(1, 1, 0, 1)),
+            ("0 3 * * *", time(1, 1, 3, 0), time(1, 2, 3, 0)),
+            ("0 3 * * *", time(1, 1, 2, 59), time(1, 1, 3, 0)),
+            ("*/15 * * * *", time(1, 1, 0, 20), time(1, 1, 0, 30)),
+            ("10/20 * * * *", time(1, 1, 0, 31), time(1, 1, 0, 50)),
+            ("0 0,12 * * *", time(1, 1, 1, 0), time(1, 1, 12, 0)),
+            // Days of the week, with Sunday being both 0 and 7
+            ("0 3 * * 6", time(1, 1, 0, 0), time(1, 6, 3, 0)),
+            ("0 3 * * 0", time(1, 1, 0, 0), time(1, 7, 3, 0)),
+            ("0 3 * * 7", time(1, 1, 0, 0), time(1, 7, 3, 0)),
+            // Either the day of the month or the day of the week match when both are restricted
+            ("0 0 15 * 3", time(1, 1, 0, 0), time(1, 3, 0, 0)),
+            ("0 0 15 * 3", time(1, 11, 0, 0), time(1, 15, 0, 0)),
+            // Months and years roll over
+            ("0 0 1 * *", time(1, 15, 0, 0), time(2, 1, 0, 0)),
+            ("0 0 29 2 *", time(1, 1, 0, 0), time(2, 29, 0, 0)),
+            (
+                "0 0 1 1 *",
+                time(1, 1, 0, 0),
+                Utc.ymd(2025, 1, 1).and_hms(0, 0, 0),
+            ),
+            (
+                "0 0 29 2 *",
+                time(3, 1, 0, 0),
+                Utc.ymd(2028, 2, 29).and_hms(0, 0, 0),
+            ),
+        ];
+        for (expression, after, expected) in cases {
+            let schedule = expression.parse::<CronSchedule>().unwrap();
+            assert_eq!(
+                schedule.next_after(*after),
+                Some(*expected),
+                "{}",
+                expression
+            );
+        }
+
+        // Dates which don't exist never match
+        let schedule = "0 0 30 2 *".parse::<CronSchedule>().unwrap();
+        assert_eq!(schedule.next_after(time(1, 1, 0, 0)), None);
+    }
+}
@@ -42,9 +42,13 @@ pub(crate) fn from_hex(input: &str) -> Result<Vec<u8>, HexError> {
     }
 }
 
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{from_hex, HexError};
+    use super::{from_hex, to_hex, HexError};
 
     #[test]
     fn test_from_hex() {
@@ -60,4 +64,11 @@ mod tests {
         // Invalid length
         assert_eq!(from_hex("000").unwrap_err(), HexError::InvalidLength);
     }
+
+    #[test]
+    fn test_to_hex() {
+        let bytes = vec![0x00, 0x01, 0x02, 0x10, 0xFF];
+        assert_eq!(to_hex(&bytes), "00010210ff");
+        assert_eq!(from_hex(&to_hex(&bytes)).unwrap(), bytes);
+    }
 }
@@ -16,10 +16,12 @@ lazy_static! {
 }
 
 fn setup_sync_client() -> Client {
-    ClientBuilder::new()
-        .redirect(RedirectPolicy::limited(MAX_REDIRECTS))
-        .build()
-        .unwrap()
+    client_builder().build().unwrap()
+}
+
+/// Create a builder for an HTTP client with the same settings as the default one.
+pub(crate) fn client_builder() -> ClientBuilder {
+    ClientBuilder::new().redirect(RedirectPolicy::limited(MAX_REDIRECTS))
 }
 
 pub(crate) fn prepare_sync(method: Method, url: &str) -> RequestBuilder {
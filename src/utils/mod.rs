@@ -9,6 +9,8 @@ pub(crate) mod hex;
 pub(crate) mod http;
 #[macro_use]
 mod macros;
+// Declared after the macros it uses
+pub mod cron;
 pub(crate) mod path;
 pub mod size;
 pub(crate) mod string;
@@ -27,11 +29,20 @@ pub(crate) const FILENAME_ENCODE_SET: AsciiSet = CONTROLS
     .add(b'?')
     .add(b'*');
 
-pub fn report_panic(e: &dyn Any) {
+/// Message a thread panicked with, if the payload is a string.
+pub fn panic_message(e: &dyn Any) -> Option<&str> {
     if let Some(e) = e.downcast_ref::<String>() {
-        error!("panicked: {}", e)
+        Some(e)
     } else if let Some(e) = e.downcast_ref::<&'static str>() {
-        error!("panicked: {}", e)
+        Some(e)
+    } else {
+        None
+    }
+}
+
+pub fn report_panic(e: &dyn Any) {
+    if let Some(message) = panic_message(e) {
+        error!("panicked: {}", message)
     } else {
         error!("panicked")
     }
@@ -7,6 +7,14 @@ pub enum SplitQuotedError {
 }
 
 pub(crate) fn split_quoted(input: &str) -> Result<Vec<String>, SplitQuotedError> {
+    split_quoted_by(input, |chr| chr == ' ' || chr == '\t')
+}
+
+/// Split the input on the separators outside of quotes, removing the quotes and the escapes.
+pub(crate) fn split_quoted_by(
+    input: &str,
+    is_separator: impl Fn(char) -> bool,
+) -> Result<Vec<String>, SplitQuotedError> {
     let mut segments = Vec::new();
     let mut buffer = String::new();
 
@@ -23,8 +31,8 @@ pub(crate) fn split_quoted(input: &str) -> Result<Vec<String>, SplitQuotedError>
             '\\' => is_escaped = true,
             // When a " is encountered, toggle quoting
             '"' => is_quoted = !is_quoted,
-            // Split with spaces only if we're not inside a quote
-            ' ' | '\t' if !is_quoted => {
+            // Split only if we're not inside a quote
+            _ if !is_quoted && is_separator(chr) => {
                 segments.push(buffer);
                 buffer = String::new();
             }
@@ -41,9 +49,23 @@ pub(crate) fn split_quoted(input: &str) -> Result<Vec<String>, SplitQuotedError>
     }
 }
 
+/// Characters that don't need to be quoted in a shell word.
+fn is_shell_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-_=+.,:/@%".contains(c)
+}
+
+/// Quote a word to be used in a POSIX shell command, if needed.
+pub(crate) fn shell_quote(word: &str) -> String {
+    if !word.is_empty() && word.chars().all(is_shell_safe) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::split_quoted;
+    use super::{shell_quote, split_quoted, split_quoted_by};
 
     #[test]
     fn test_split_quoted() {
@@ -68,5 +90,19 @@ mod tests {
 
         // Unbalanced quotes
         assert!(split_quoted("a b \" c").is_err());
+
+        // Other separators
+        assert_eq!(
+            split_quoted_by("a+b c+\"d+e\"+f\\+g", |chr| chr == '+').unwrap(),
+            vec!["a", "b c", "d+e", "f+g"]
+        );
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("--frozen"), "--frozen");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
     }
 }